@@ -0,0 +1,244 @@
+// Micro-benchmarks for the hot paths: RESP decoding and encoding, storage reads and writes under contention,
+// the keyspace hashers and expiry sweeps. #[bench] needs nightly, so the suite is one test run in release mode:
+//
+//     cargo test --release --bench hot_paths
+//
+// setting ZENQL_BENCH to a substring to run only the matching benchmarks. Each benchmark prints the time
+// per operation as [fastest median slowest] sample, and the throughput at the median.
+use std::hash::BuildHasher;
+use std::hint::black_box;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use bytes::{Bytes, BytesMut};
+use redis_starter_rust::clock::{ManualClock, SystemClock};
+use redis_starter_rust::codec::{RespCodec, WriteBuffer};
+use redis_starter_rust::hasher::{HasherKind, KeyHasher};
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::storage::{BackendKind, Storage, DEFAULT_SHARDS};
+
+const WARM_UP: Duration = Duration::from_millis(300);
+const MEASURE: Duration = Duration::from_secs(2);
+const MIN_SAMPLES: usize = 5;
+const MAX_SAMPLES: usize = 50;
+const KEYSPACE: usize = 10_000;
+const START: u64 = 1_700_000_000_000;
+
+struct Bencher {
+    filter: Option<String>,
+}
+
+impl Bencher {
+    fn bench(&self, name: &str, ops: u64, mut routine: impl FnMut()) {
+        self.bench_with_setup(name, ops, || (), |_| routine());
+    }
+
+    // Times `routine`, which performs `ops` operations per call, on fresh input from the untimed `setup`
+    fn bench_with_setup<S>(&self, name: &str, ops: u64, mut setup: impl FnMut() -> S, mut routine: impl FnMut(S)) {
+        if self.filter.as_ref().is_some_and(|filter| !name.contains(filter.as_str())) {
+            return;
+        }
+        let started = Instant::now();
+        while started.elapsed() < WARM_UP {
+            routine(setup());
+        }
+        let mut samples = vec![];
+        let started = Instant::now();
+        while samples.len() < MIN_SAMPLES || (samples.len() < MAX_SAMPLES && started.elapsed() < MEASURE) {
+            let input = setup();
+            let start = Instant::now();
+            routine(input);
+            samples.push(start.elapsed() / ops as u32);
+        }
+        samples.sort_unstable();
+
+        let median = samples[samples.len() / 2];
+        let throughput = 1.0 / median.as_secs_f64().max(f64::MIN_POSITIVE);
+        // Written to stdout directly, the test harness only captures print!
+        let _ = writeln!(
+            std::io::stdout(),
+            "{:<44} time: [{} {} {}]  thrpt: {:.3} Mops/s",
+            name, format_time(samples[0]), format_time(median), format_time(samples[samples.len() - 1]), throughput / 1e6,
+        );
+    }
+}
+
+fn format_time(time: Duration) -> String {
+    match time.as_nanos() {
+        nanos @ 0..1_000 => format!("{} ns", nanos),
+        nanos @ 1_000..1_000_000 => format!("{:.2} µs", nanos as f64 / 1e3),
+        nanos => format!("{:.2} ms", nanos as f64 / 1e6),
+    }
+}
+
+fn keys(prefix: &str) -> Vec<Bytes> {
+    (0..KEYSPACE).map(|i| Bytes::from(format!("{}:{:06}", prefix, i))).collect()
+}
+
+// `count` copies of `frame`, as a pipelining client would send them
+fn repeated(frame: &[u8], count: usize) -> Bytes {
+    Bytes::from(frame.repeat(count))
+}
+
+fn bulk_command(args: &[&[u8]]) -> Vec<u8> {
+    let mut frame = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        frame.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        frame.extend_from_slice(arg);
+        frame.extend_from_slice(b"\r\n");
+    }
+    frame
+}
+
+fn decode_all(input: &Bytes) {
+    let mut codec = RespCodec::default();
+    let mut buf = BytesMut::from(&input[..]);
+    while let Some(value) = codec.decode(&mut buf).unwrap() {
+        black_box(value);
+    }
+}
+
+fn encode_all(values: Vec<Value>) {
+    let mut codec = RespCodec::default();
+    let mut out = WriteBuffer::new();
+    for value in values {
+        codec.encode(value, &mut out);
+    }
+    black_box(out.take_chunks());
+}
+
+fn resp(bencher: &Bencher) {
+    let set = repeated(&bulk_command(&[b"SET", b"key:000001", b"xxx"]), 1_000);
+    bencher.bench("resp/decode/set", 1_000, || decode_all(&set));
+    let inline = repeated(b"SET key:000001 xxx\r\n", 1_000);
+    bencher.bench("resp/decode/inline", 1_000, || decode_all(&inline));
+    let large = repeated(&bulk_command(&[b"SET", b"key:000001", &[b'x'; 16 * 1024]]), 100);
+    bencher.bench("resp/decode/set-16k", 100, || decode_all(&large));
+
+    let value = Bytes::from_static(b"xxx");
+    bencher.bench_with_setup("resp/encode/bulk", 1_000, || vec![Value::BulkString(value.clone()); 1_000], encode_all);
+    let array = Value::Array(vec![Value::BulkString(value.clone()); 100]);
+    bencher.bench_with_setup("resp/encode/array-100", 100, || vec![array.clone(); 100], encode_all);
+    let large = Bytes::from(vec![b'x'; 1024 * 1024]);
+    bencher.bench_with_setup("resp/encode/bulk-1m", 100, || vec![Value::BulkString(large.clone()); 100], encode_all);
+}
+
+// Each of `threads` threads runs `ops` operations, one in `write_every` a SET and the rest GETs, over `keys`
+fn contended(storage: &Storage, keys: &[Bytes], threads: usize, ops: usize, write_every: usize) {
+    std::thread::scope(|scope| {
+        for thread in 0..threads {
+            scope.spawn(move || {
+                let value = Bytes::from_static(b"xxx");
+                for i in 0..ops {
+                    let key = &keys[(i * 7 + thread * 131) % keys.len()];
+                    if i % write_every == 0 {
+                        storage.set(key.clone(), value.clone(), None);
+                    } else {
+                        let _ = black_box(storage.get(key));
+                    }
+                }
+            });
+        }
+    });
+}
+
+fn storage(bencher: &Bencher) {
+    let keys = keys("key");
+    let value = Bytes::from_static(b"xxx");
+    let threads = std::thread::available_parallelism().map_or(4, |n| n.get()).max(2);
+    for (kind, name) in [(BackendKind::Sharded, "sharded"), (BackendKind::Concurrent, "concurrent")] {
+        let storage = Storage::new(kind, DEFAULT_SHARDS);
+        for key in &keys {
+            storage.set(key.clone(), value.clone(), None);
+        }
+        bencher.bench(&format!("storage/{}/get", name), KEYSPACE as u64, || {
+            for key in &keys {
+                let _ = black_box(storage.get(key));
+            }
+        });
+        bencher.bench(&format!("storage/{}/set", name), KEYSPACE as u64, || {
+            for key in &keys {
+                storage.set(key.clone(), value.clone(), None);
+            }
+        });
+        bencher.bench(&format!("storage/{}/set-ex", name), KEYSPACE as u64, || {
+            for (i, key) in keys.iter().enumerate() {
+                storage.set(key.clone(), value.clone(), Some(u64::MAX / 2 + i as u64));
+            }
+        });
+
+        let ops = 20_000;
+        let total = (threads * ops) as u64;
+        bencher.bench(&format!("storage/{}/contended-{}t-get9-set1", name, threads), total, || {
+            contended(&storage, &keys, threads, ops, 10)
+        });
+        bencher.bench(&format!("storage/{}/contended-{}t-set", name, threads), total, || {
+            contended(&storage, &keys, threads, ops, 1)
+        });
+        bencher.bench(&format!("storage/{}/contended-{}t-hot-key", name, threads), total, || {
+            contended(&storage, &keys[..1], threads, ops, 10)
+        });
+    }
+}
+
+// The keyspace hashers side by side: hashing alone, then the storage paths a hash is taken on twice (the
+// shard, then the bucket in its map)
+fn hashing(bencher: &Bencher) {
+    let keys = keys("key");
+    let value = Bytes::from_static(b"xxx");
+    for kind in [HasherKind::Fx, HasherKind::SipHash] {
+        let hasher = KeyHasher::new(kind);
+        bencher.bench(&format!("hashing/{}/hash", kind.as_str()), KEYSPACE as u64, || {
+            for key in &keys {
+                black_box(hasher.hash_one(&key[..]));
+            }
+        });
+        let storage = Storage::with_clock(BackendKind::Sharded, DEFAULT_SHARDS, kind, Arc::new(SystemClock));
+        for key in &keys {
+            storage.set(key.clone(), value.clone(), None);
+        }
+        bencher.bench(&format!("hashing/{}/storage-get", kind.as_str()), KEYSPACE as u64, || {
+            for key in &keys {
+                let _ = black_box(storage.get(key));
+            }
+        });
+        bencher.bench(&format!("hashing/{}/storage-set", kind.as_str()), KEYSPACE as u64, || {
+            for key in &keys {
+                storage.set(key.clone(), value.clone(), None);
+            }
+        });
+    }
+}
+
+fn expiry(bencher: &Bencher) {
+    let keys = keys("volatile");
+    let value = Bytes::from_static(b"xxx");
+    // A fresh storage with every key due, so each sample sweeps the full keyspace
+    let filled = || {
+        let clock = Arc::new(ManualClock::new(START));
+        let storage = Storage::with_clock(BackendKind::Sharded, DEFAULT_SHARDS, HasherKind::default(), clock.clone());
+        for (i, key) in keys.iter().enumerate() {
+            storage.set(key.clone(), value.clone(), Some(START + 1 + i as u64 % 1_000));
+        }
+        clock.advance(Duration::from_secs(10));
+        storage
+    };
+    bencher.bench_with_setup("expiry/remove-expired", KEYSPACE as u64, filled, |storage| {
+        assert_eq!(storage.remove_expired(), KEYSPACE);
+    });
+    bencher.bench_with_setup("expiry/get-expired", KEYSPACE as u64, filled, |storage| {
+        for key in &keys {
+            assert_eq!(storage.get(key), Ok(None));
+        }
+    });
+}
+
+#[test]
+fn hot_paths() {
+    let bencher = Bencher { filter: std::env::var("ZENQL_BENCH").ok() };
+    let _ = writeln!(std::io::stdout()); // Off the harness's "test hot_paths ..." line
+    resp(&bencher);
+    storage(&bencher);
+    hashing(&bencher);
+    expiry(&bencher);
+}
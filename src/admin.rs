@@ -0,0 +1,108 @@
+// Admin endpoints for load balancers and orchestrators such as Kubernetes, served over HTTP on admin-port of
+// every bind address:
+//
+//   GET /healthz   200 while the process is serving requests at all, for liveness probes
+//   GET /readyz    200 when clients should be sent here, 503 with the reason when not, for readiness probes
+//   GET /status    the INFO sections as JSON: {"ready":true,"version":"..","keys":3,"clients":{..},..}
+//
+// The data set is loaded before anything is served, so the server is ready once its listeners accept, and
+// stops being ready when it starts shutting down or has maxclients connected. There's no replication to wait
+// on. Probes get their answer without a password; /status needs one when requirepass is set, and is only
+// served on the loopback interface in protected mode, as the HTTP gateway is.
+use anyhow::Result;
+use bytes::BytesMut;
+use tokio::net::TcpStream;
+use crate::allocator::MemoryStats;
+use crate::connection::PROTECTED_MODE;
+use crate::http::{self, Format, Request, Response};
+use crate::json::Json;
+use crate::server::Server;
+use crate::stats::SERVER_STATS;
+
+pub async fn serve(server: Server, port: u16) -> Result<()> {
+    http::listen(&server.clone(), port, "admin endpoints", move |stream, _, loopback| {
+        let server = server.clone();
+        async move { handle(stream, loopback, &server).await }
+    }).await
+}
+
+async fn handle(mut stream: TcpStream, loopback: bool, server: &Server) -> Result<()> {
+    let mut buf = BytesMut::with_capacity(1024);
+    loop {
+        let request = match http::read_request(&mut stream, &mut buf, server).await {
+            Ok(Some(request)) => request,
+            Ok(None) => return Ok(()),
+            Err(response) => return http::write(&mut stream, response, false).await,
+        };
+        let response = respond(server, loopback, &request);
+        http::write(&mut stream, response, request.keep_alive).await?;
+        if !request.keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+fn respond(server: &Server, loopback: bool, request: &Request) -> Response {
+    if !matches!(request.path.as_str(), "/healthz" | "/readyz" | "/status") {
+        return Response::error(404, "not found", Format::Json);
+    }
+    if request.method != "GET" {
+        return Response::error(405, "only GET is supported", Format::Json).with_header("Allow", "GET");
+    }
+    let status = |text: &str| ("status".to_string(), Json::String(text.to_string()));
+    match request.path.as_str() {
+        "/healthz" => Response::json(200, Json::Object(vec![status("ok")])),
+        "/readyz" => match unready(server) {
+            None => Response::json(200, Json::Object(vec![status("ready")])),
+            Some(reason) => Response::json(503, Json::Object(vec![status("not ready"), ("reason".to_string(), Json::String(reason.to_string()))])),
+        },
+        _ => {
+            let config = server.config();
+            if config.protected() && !loopback {
+                return Response::error(403, PROTECTED_MODE, Format::Json);
+            }
+            if let Some(password) = &config.requirepass {
+                if !http::authorized(request.header("authorization"), password) {
+                    return Response::error(401, "authentication required", Format::Json).with_header("WWW-Authenticate", "Basic realm=\"zenql\"");
+                }
+            }
+            Response::json(200, status_json(server))
+        },
+    }
+}
+
+// Why clients shouldn't be sent here, None when they can be
+fn unready(server: &Server) -> Option<&'static str> {
+    if !server.ready() {
+        return Some("not accepting connections");
+    }
+    if server.clients.len() >= server.config().maxclients {
+        return Some("maxclients reached");
+    }
+    None
+}
+
+fn status_json(server: &Server) -> Json {
+    let mut fields = vec![
+        ("ready".to_string(), Json::Bool(unready(server).is_none())),
+        ("version".to_string(), Json::String(env!("CARGO_PKG_VERSION").to_string())),
+        ("keys".to_string(), Json::Int(server.storage.len() as i64)),
+    ];
+    for (name, info) in [("clients", SERVER_STATS.clients_info()), ("memory", MemoryStats::read().info()), ("stats", SERVER_STATS.info())] {
+        fields.push((name.to_string(), info_json(&info)));
+    }
+    Json::Object(fields)
+}
+
+// An INFO section's name:value lines as an object, numbers as numbers
+fn info_json(info: &str) -> Json {
+    let fields = info.lines().filter_map(|line| line.split_once(':')).map(|(name, value)| {
+        let value = match (value.parse::<i64>(), value.parse::<f64>()) {
+            (Ok(n), _) => Json::Int(n),
+            (_, Ok(n)) if n.is_finite() => Json::Float(n),
+            _ => Json::String(value.to_string()),
+        };
+        (name.to_string(), value)
+    });
+    Json::Object(fields.collect())
+}
@@ -0,0 +1,128 @@
+// Memory accounting for MEMORY STATS and INFO memory. The server allocates through the system allocator
+// wrapped in CountingAllocator, which main.rs installs as the global allocator. The wrapper tracks the bytes handed out, which against the resident set size the
+// kernel reports gives the fragmentation ratio as Redis defines it: resident over allocated. Embedders using
+// the library install it in their own binary, or go without the allocation figures.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fmt::Write;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Reported as mem_allocator, like Redis does when built against the C library's malloc
+pub const ALLOCATOR_NAME: &str = "libc";
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+pub struct CountingAllocator;
+
+impl CountingAllocator {
+    fn allocated(size: usize) {
+        let allocated = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK.fetch_max(allocated, Ordering::Relaxed);
+    }
+
+    fn freed(size: usize) {
+        ALLOCATED.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+// Safety: every call is passed straight to System, which upholds GlobalAlloc's contract; the counting
+// around it doesn't allocate
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            CountingAllocator::allocated(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            CountingAllocator::allocated(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CountingAllocator::freed(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = System.realloc(ptr, layout, new_size);
+        if !new.is_null() {
+            CountingAllocator::freed(layout.size());
+            CountingAllocator::allocated(new_size);
+        }
+        new
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoryStats {
+    pub allocated: Option<usize>, // Bytes allocated now, None without CountingAllocator installed
+    pub peak: Option<usize>, // Most bytes allocated at once
+    pub resident: Option<usize>, // Resident set size, None where the kernel doesn't say
+}
+
+impl MemoryStats {
+    pub fn read() -> MemoryStats {
+        // Anything running has allocated something, so nothing counted means the wrapper isn't in use
+        let allocated = Some(ALLOCATED.load(Ordering::Relaxed)).filter(|&allocated| allocated > 0);
+        MemoryStats { allocated, peak: allocated.map(|_| PEAK.load(Ordering::Relaxed)), resident: resident_set_size() }
+    }
+
+    // Resident over allocated: above 1 is memory the allocator holds but doesn't use, below 1 is swapped out
+    pub fn fragmentation(&self) -> Option<f64> {
+        match (self.resident, self.allocated) {
+            (Some(resident), Some(allocated)) => Some(resident as f64 / allocated as f64),
+            _ => None,
+        }
+    }
+
+    // INFO's memory section
+    pub fn info(&self) -> String {
+        let mut out = String::from("# Memory\r\n");
+        let mut line = |name: &str, value: String| {
+            let _ = write!(out, "{}:{}\r\n", name, value);
+        };
+        if let (Some(allocated), Some(peak)) = (self.allocated, self.peak) {
+            line("used_memory", allocated.to_string());
+            line("used_memory_human", human(allocated));
+            line("used_memory_peak", peak.to_string());
+            line("used_memory_peak_human", human(peak));
+        }
+        if let Some(resident) = self.resident {
+            line("used_memory_rss", resident.to_string());
+            line("used_memory_rss_human", human(resident));
+        }
+        if let (Some(resident), Some(allocated)) = (self.resident, self.allocated) {
+            line("mem_fragmentation_ratio", format!("{:.2}", resident as f64 / allocated as f64));
+            line("mem_fragmentation_bytes", (resident as i64 - allocated as i64).to_string());
+        }
+        line("mem_allocator", ALLOCATOR_NAME.to_string());
+        out
+    }
+}
+
+// Linux's VmRSS, in kilobytes in /proc
+fn resident_set_size() -> Option<usize> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find_map(|line| line.strip_prefix("VmRSS:"))?;
+    let kilobytes: usize = line.trim().strip_suffix("kB")?.trim().parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+// Like Redis's *_human fields: 1.50M
+fn human(bytes: usize) -> String {
+    let units = ["B", "K", "M", "G", "T"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2}{}", value, units[unit])
+}
@@ -0,0 +1,138 @@
+// Audit log: who ran which write or admin command when, for deployments that have to answer for every change.
+// With audit-log set, every command flagged write or admin that a client sends is appended to that file, one
+// JSON object per line, whether it ran or was refused:
+//
+//   {"ts":"2026-01-01T00:00:00.000Z","id":1,"client":"10.0.0.5:51234","name":"worker","user":"default",
+//    "db":0,"command":["set","user:1","ada"],"result":"ok"}
+//
+// user is the tenant the client authenticated as, see tenant.rs, or default; name is CLIENT SETNAME's, null
+// when unset.
+// Arguments past AUDIT_ARG_MAX bytes are cut short, keeping their length, so values don't flood the log.
+// Commands in a transaction are recorded as EXEC runs them. The file rotates as the logfile does, past
+// audit-max-size bytes, keeping audit-max-files old ones. The last AUDIT_RECENT entries are also kept in
+// memory for AUDIT GET.
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::Result;
+use bytes::Bytes;
+use crate::json::Json;
+use crate::locks;
+use crate::log::{self, log_error, FileOptions, LogFile};
+use crate::resp::Value;
+use crate::session::Session;
+
+// Entries kept in memory for AUDIT GET
+const AUDIT_RECENT: usize = 1024;
+// Longest argument recorded whole
+const AUDIT_ARG_MAX: usize = 256;
+
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub id: u64,
+    pub time_ms: u64, // UNIX milliseconds
+    pub client: String,
+    pub name: Option<Bytes>,
+    pub user: String,
+    pub db: usize,
+    pub command: Vec<String>, // Name and arguments
+    pub result: String, // "ok" or the error replied
+}
+
+#[derive(Default)]
+pub struct AuditLog {
+    file: Option<Mutex<LogFile>>, // None when auditing is off
+    recent: Mutex<VecDeque<AuditEntry>>,
+    next_id: AtomicU64,
+}
+
+impl AuditLog {
+    // An audit log that records nothing
+    pub fn disabled() -> Self {
+        AuditLog::default()
+    }
+
+    pub fn open(path: &str, max_size: u64, max_files: usize) -> Result<Self> {
+        let options = FileOptions { path: path.to_string(), max_size, rotate_interval: Duration::ZERO, max_files };
+        Ok(AuditLog { file: Some(Mutex::new(LogFile::open(options)?)), ..AuditLog::default() })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.file.is_some()
+    }
+
+    // Records a command `session` sent and the replies it got
+    pub fn record(&self, session: &Session, name: &str, args: &[Value], replies: &[Value]) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let now = SystemTime::now();
+        let mut command = vec![name.to_string()];
+        command.extend(args.iter().map(|arg| match arg {
+            Value::BulkString(arg) => truncate(arg),
+            _ => String::new(),
+        }));
+        let result = match replies.iter().find_map(|reply| match reply {
+            Value::Error(e) => Some(e),
+            _ => None,
+        }) {
+            Some(e) => e.clone(),
+            None => "ok".to_string(),
+        };
+        let entry = AuditEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed) + 1,
+            time_ms: now.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64,
+            client: session.peer.clone(),
+            name: session.name.clone(),
+            user: session.tenant.clone().unwrap_or_else(|| "default".to_string()),
+            db: session.db,
+            command,
+            result,
+        };
+        let line = format!("{}\n", to_json(&entry, now).to_text());
+        // Written before the reply goes out, so nothing is acknowledged that wasn't recorded
+        if let Err(e) = locks::lock(file).write(&line, now) {
+            log_error!("Failed to write the audit log: {:?}", e);
+        }
+        let mut recent = locks::lock(&self.recent);
+        if recent.len() == AUDIT_RECENT {
+            recent.pop_back();
+        }
+        recent.push_front(entry);
+    }
+
+    // Up to `count` of the latest entries, newest first
+    pub fn recent(&self, count: usize) -> Vec<AuditEntry> {
+        locks::lock(&self.recent).iter().take(count).cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        locks::lock(&self.recent).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn truncate(arg: &Bytes) -> String {
+    if arg.len() <= AUDIT_ARG_MAX {
+        return String::from_utf8_lossy(arg).into_owned();
+    }
+    format!("{}... ({} bytes)", String::from_utf8_lossy(&arg[..AUDIT_ARG_MAX]), arg.len())
+}
+
+fn to_json(entry: &AuditEntry, now: SystemTime) -> Json {
+    let string = |s: &str| Json::String(s.to_string());
+    Json::Object(vec![
+        ("ts".to_string(), Json::String(log::timestamp(now))),
+        ("id".to_string(), Json::Int(entry.id as i64)),
+        ("client".to_string(), string(&entry.client)),
+        ("name".to_string(), entry.name.as_ref().map_or(Json::Null, |name| string(&String::from_utf8_lossy(name)))),
+        ("user".to_string(), string(&entry.user)),
+        ("db".to_string(), Json::Int(entry.db as i64)),
+        ("command".to_string(), Json::Array(entry.command.iter().map(|part| string(part)).collect())),
+        ("result".to_string(), string(&entry.result)),
+    ])
+}
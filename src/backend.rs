@@ -0,0 +1,79 @@
+use std::io;
+use bytes::Bytes;
+use crate::storage::{Item, StoredValue};
+
+// Where the keyspace's values live. Storage keeps every key, its deadline and version in memory and tells the
+// backend about each change with the key's shard locked for writing, so changes to one key reach it in order.
+// The backend decides how the value itself is held: Memory keeps it as given, Disk (see disk.rs) appends it
+// to a log and keeps only its position, so values need not fit in RAM. Wal (see wal.rs) keeps values in memory
+// and logs changes to be made durable by sync.
+pub trait StorageBackend: Send + Sync {
+    // A key was set, returns what the keyspace holds for the value
+    fn write(&self, key: &Bytes, value: StoredValue, expires_at: Option<u64>) -> StoredValue;
+
+    // A live key's deadline was set or cleared
+    fn set_expiry(&self, key: &Bytes, expires_at: Option<u64>);
+
+    // A key was deleted or expired
+    fn remove(&self, key: &Bytes);
+
+    // The bytes of a value `write` returned
+    fn read(&self, value: &StoredValue) -> io::Result<Bytes>;
+
+    // Whether changes were made that sync hasn't made durable yet
+    fn needs_sync(&self) -> bool {
+        false
+    }
+
+    // Makes every change so far durable, before clients are told about them
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    // Whether compacting would reclaim enough to be worth it
+    fn needs_compaction(&self) -> bool {
+        false
+    }
+
+    // Rewrites the backend's state from the keyspace, updating values that moved
+    fn compact<'a>(&self, _items: &mut dyn Iterator<Item = (&'a Bytes, &'a mut Item)>) -> io::Result<()> {
+        Ok(())
+    }
+
+    // Whether compaction rewrites from a point-in-time view of the keyspace read while writes carry on,
+    // through start_rewrite, instead of compact
+    fn rewrites_from_view(&self) -> bool {
+        false
+    }
+
+    // Starts a rewrite, at the view's point in time: called with every shard locked, so no change is in
+    // flight
+    fn start_rewrite(&self) -> io::Result<Box<dyn Rewrite + '_>> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "the backend can't rewrite from a view"))
+    }
+}
+
+// A rewrite in progress, given every live key of the view in turn. Dropped without finish, it leaves the
+// backend as it was.
+pub trait Rewrite {
+    fn write(&mut self, key: &Bytes, value: &StoredValue, expires_at: Option<u64>) -> io::Result<()>;
+
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+// Values stay in memory, nothing survives a restart
+pub struct Memory;
+
+impl StorageBackend for Memory {
+    fn write(&self, _key: &Bytes, value: StoredValue, _expires_at: Option<u64>) -> StoredValue {
+        value
+    }
+
+    fn set_expiry(&self, _key: &Bytes, _expires_at: Option<u64>) {}
+
+    fn remove(&self, _key: &Bytes) {}
+
+    fn read(&self, value: &StoredValue) -> io::Result<Bytes> {
+        Ok(value.to_bytes())
+    }
+}
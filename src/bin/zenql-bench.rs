@@ -0,0 +1,243 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use anyhow::Result;
+use bytes::Bytes;
+use redis_starter_rust::client::{Client, Pipeline};
+use redis_starter_rust::resp::Value;
+
+const USAGE: &str = "\
+Usage: zenql-bench [OPTIONS]
+  -h <hostname>     Server hostname (default: 127.0.0.1)
+  -p <port>         Server port (default: 6379)
+  -c <clients>      Concurrent connections (default: 50)
+  -n <requests>     Requests per test (default: 100000)
+  -P <pipeline>     Requests pipelined per round trip (default: 1)
+  -d <size>         SET/LPUSH value size in bytes (default: 3)
+  -r <keyspace>     Use random keys from 0 to keyspace-1 instead of a single key
+  -t <tests>        Comma separated tests to run one after another (default: ping,set,get)
+  --mix <weights>   Run one test mixing commands by weight instead, e.g. get=9,set=1
+  -q                Quiet: one line per test
+  --help            Show this help
+
+Commands: ping, set, get, del, incr, lpush. Replies are checked for errors only, so commands the server
+doesn't support show up as errors.";
+
+const COMMANDS: [&str; 6] = ["ping", "set", "get", "del", "incr", "lpush"];
+
+struct Options {
+    host: String,
+    port: u16,
+    clients: usize,
+    requests: u64,
+    pipeline: u64,
+    data_size: usize,
+    keyspace: Option<u64>,
+    tests: Vec<Vec<(String, u32)>>, // Each test is a weighted command mix
+    quiet: bool,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Options> {
+        let mut options = Options {
+            host: "127.0.0.1".to_string(),
+            port: 6379,
+            clients: 50,
+            requests: 100_000,
+            pipeline: 1,
+            data_size: 3,
+            keyspace: None,
+            tests: vec![],
+            quiet: false,
+        };
+        let mut tests = "ping,set,get".to_string();
+        let mut mix = None;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| anyhow::anyhow!("Missing value for '{}'", arg));
+            match arg.as_str() {
+                "-h" => options.host = value()?,
+                "-p" => options.port = value()?.parse()?,
+                "-c" => options.clients = value()?.parse::<usize>()?.max(1),
+                "-n" => options.requests = value()?.parse()?,
+                "-P" => options.pipeline = value()?.parse::<u64>()?.max(1),
+                "-d" => options.data_size = value()?.parse()?,
+                "-r" => options.keyspace = Some(value()?.parse::<u64>()?.max(1)),
+                "-t" => tests = value()?,
+                "--mix" => mix = Some(value()?),
+                "-q" => options.quiet = true,
+                "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                },
+                _ => return Err(anyhow::anyhow!("Unrecognized option '{}'", arg)),
+            }
+        }
+        options.tests = match mix {
+            Some(mix) => vec![parse_mix(&mix)?],
+            None => tests.split(',').map(|name| parse_mix(name.trim())).collect::<Result<_>>()?,
+        };
+        Ok(options)
+    }
+}
+
+// "get=9,set=1" into weighted commands; a bare name weighs 1
+fn parse_mix(mix: &str) -> Result<Vec<(String, u32)>> {
+    mix.split(',')
+        .map(|entry| {
+            let (name, weight) = entry.split_once('=').unwrap_or((entry, "1"));
+            let name = name.trim().to_lowercase();
+            if !COMMANDS.contains(&name.as_str()) {
+                return Err(anyhow::anyhow!("Unknown command '{}' in test, expected one of {}", name, COMMANDS.join(", ")));
+            }
+            Ok((name, weight.trim().parse()?))
+        })
+        .collect()
+}
+
+// xorshift64*, plenty for picking keys and commands
+struct Rng(u64);
+
+impl Rng {
+    fn seeded(stream: u64) -> Rng {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+        Rng((nanos ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15)) | 1)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) % bound
+    }
+}
+
+// Builds the requests of one test for one connection
+struct Generator {
+    mix: Vec<(String, u32)>,
+    total_weight: u64,
+    keyspace: Option<u64>,
+    value: Bytes,
+    rng: Rng,
+}
+
+impl Generator {
+    fn command(&mut self) -> Vec<Bytes> {
+        let mut pick = self.rng.below(self.total_weight);
+        let name = self.mix.iter()
+            .find(|(_, weight)| {
+                let hit = pick < *weight as u64;
+                pick = pick.saturating_sub(*weight as u64);
+                hit
+            })
+            .map_or("ping", |(name, _)| name.as_str());
+        let key = |prefix: &str, rng: &mut Rng| {
+            let n = self.keyspace.map_or(0, |keyspace| rng.below(keyspace));
+            Bytes::from(format!("{}:{:012}", prefix, n))
+        };
+        match name {
+            "set" => vec!["SET".into(), key("key", &mut self.rng), self.value.clone()],
+            "get" => vec!["GET".into(), key("key", &mut self.rng)],
+            "del" => vec!["DEL".into(), key("key", &mut self.rng)],
+            "incr" => vec!["INCR".into(), key("counter", &mut self.rng)],
+            "lpush" => vec!["LPUSH".into(), key("list", &mut self.rng), self.value.clone()],
+            _ => vec!["PING".into()],
+        }
+    }
+}
+
+#[derive(Default)]
+struct Report {
+    latencies: Vec<Duration>, // One per request; pipelined requests share their batch's round trip
+    errors: u64,
+}
+
+async fn run_client(options: Arc<Options>, mix: Vec<(String, u32)>, remaining: Arc<AtomicU64>, stream: u64) -> Result<Report> {
+    let mut client = Client::connect((options.host.as_str(), options.port)).await?;
+    let mut generator = Generator {
+        total_weight: mix.iter().map(|(_, weight)| *weight as u64).sum::<u64>().max(1),
+        mix,
+        keyspace: options.keyspace,
+        value: Bytes::from(vec![b'x'; options.data_size]),
+        rng: Rng::seeded(stream),
+    };
+    let mut report = Report::default();
+    loop {
+        // Claim the next batch from the shared budget
+        let claimed = remaining.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| (left > 0).then(|| left - left.min(options.pipeline)));
+        let Ok(left) = claimed else {
+            return Ok(report);
+        };
+        let batch = left.min(options.pipeline);
+        let pipeline = (0..batch).fold(Pipeline::new(), |pipeline, _| pipeline.cmd(generator.command()));
+
+        let start = Instant::now();
+        let replies = client.execute(pipeline).await?;
+        let elapsed = start.elapsed();
+        report.latencies.extend(std::iter::repeat_n(elapsed, replies.len()));
+        report.errors += replies.iter().filter(|reply| matches!(reply, Value::Error(_))).count() as u64;
+    }
+}
+
+async fn run_test(options: &Arc<Options>, mix: &[(String, u32)]) -> Result<()> {
+    let remaining = Arc::new(AtomicU64::new(options.requests));
+    let start = Instant::now();
+    let tasks: Vec<_> = (0..options.clients)
+        .map(|i| tokio::spawn(run_client(Arc::clone(options), mix.to_vec(), Arc::clone(&remaining), i as u64)))
+        .collect();
+    let mut report = Report::default();
+    for task in tasks {
+        let client_report = task.await??;
+        report.latencies.extend(client_report.latencies);
+        report.errors += client_report.errors;
+    }
+    let elapsed = start.elapsed();
+    report.latencies.sort_unstable();
+
+    let name = mix.iter()
+        .map(|(name, weight)| if mix.len() == 1 { name.to_uppercase() } else { format!("{}={}", name.to_uppercase(), weight) })
+        .collect::<Vec<_>>()
+        .join(",");
+    let completed = report.latencies.len();
+    let throughput = completed as f64 / elapsed.as_secs_f64();
+    let percentile = |p: f64| {
+        let index = ((completed as f64 * p / 100.0).ceil() as usize).clamp(1, completed.max(1)) - 1;
+        report.latencies.get(index).map_or(0.0, |latency| latency.as_secs_f64() * 1000.0)
+    };
+    if options.quiet {
+        let errors = if report.errors > 0 { format!(", {} error replies", report.errors) } else { String::new() };
+        println!("{}: {:.2} requests per second, p50={:.3} msec{}", name, throughput, percentile(50.0), errors);
+        return Ok(());
+    }
+    let average = report.latencies.iter().sum::<Duration>().as_secs_f64() * 1000.0 / completed.max(1) as f64;
+    println!("====== {} ======", name);
+    println!("  {} requests completed in {:.2} seconds", completed, elapsed.as_secs_f64());
+    println!("  {} parallel clients, {} bytes payload, pipeline {}", options.clients, options.data_size, options.pipeline);
+    if let Some(keyspace) = options.keyspace {
+        println!("  {} random keys", keyspace);
+    }
+    println!("  {:.2} requests per second", throughput);
+    println!(
+        "  latency (msec): avg {:.3}  p50 {:.3}  p95 {:.3}  p99 {:.3}  p99.9 {:.3}  max {:.3}",
+        average, percentile(50.0), percentile(95.0), percentile(99.0), percentile(99.9), percentile(100.0)
+    );
+    if report.errors > 0 {
+        println!("  {} error replies", report.errors);
+    }
+    println!();
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    let result = async {
+        let options = Arc::new(Options::parse(std::env::args().skip(1))?);
+        for mix in &options.tests {
+            run_test(&options, mix).await?;
+        }
+        anyhow::Ok(())
+    };
+    if let Err(e) = result.await {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
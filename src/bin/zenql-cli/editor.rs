@@ -0,0 +1,219 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+const MAX_HISTORY: usize = 1000;
+
+// Minimal readline: cursor movement, history and the usual Ctrl shortcuts on a terminal, plain line reads
+// otherwise. The terminal is switched to non-canonical mode with stty only while a line is being read.
+pub struct Editor {
+    history: Vec<String>,
+    history_file: Option<PathBuf>,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        let history_file = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".zenqlcli_history"));
+        let history = history_file.as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|contents| contents.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        Editor { history, history_file }
+    }
+
+    // None at end of input (Ctrl-D on an empty line, Ctrl-C)
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let line = match RawMode::enable() {
+            Some(_raw) => self.edit(prompt)?,
+            None => {
+                print!("{}", prompt);
+                io::stdout().flush()?;
+                let mut line = String::new();
+                if io::stdin().lock().read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                Some(line.trim_end_matches(['\r', '\n']).to_string())
+            },
+        };
+        if let Some(line) = line.as_ref().filter(|line| !line.trim().is_empty()) {
+            self.add_history(line);
+        }
+        Ok(line)
+    }
+
+    fn add_history(&mut self, line: &str) {
+        if self.history.last().map(String::as_str) == Some(line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+        if let Some(path) = &self.history_file {
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+    }
+
+    fn edit(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let mut stdin = io::stdin().lock();
+        let mut line: Vec<char> = vec![];
+        let mut cursor = 0;
+        let mut browsing = self.history.len(); // History entry shown, len() is the line being typed
+        let mut draft = vec![];
+        redraw(prompt, &line, cursor)?;
+
+        loop {
+            let Some(byte) = read_byte(&mut stdin)? else {
+                return Ok(None);
+            };
+            match byte {
+                b'\r' | b'\n' => {
+                    println!("\r");
+                    return Ok(Some(line.into_iter().collect()));
+                },
+                3 => {
+                    println!("^C\r");
+                    return Ok(None);
+                },
+                4 if line.is_empty() => {
+                    println!("\r");
+                    return Ok(None);
+                },
+                4 if cursor < line.len() => {
+                    line.remove(cursor);
+                },
+                1 => cursor = 0, // Ctrl-A
+                5 => cursor = line.len(), // Ctrl-E
+                2 => cursor = cursor.saturating_sub(1), // Ctrl-B
+                6 => cursor = (cursor + 1).min(line.len()), // Ctrl-F
+                11 => line.truncate(cursor), // Ctrl-K
+                21 => {
+                    line.drain(..cursor);
+                    cursor = 0;
+                },
+                23 => {
+                    let start = word_start(&line, cursor);
+                    line.drain(start..cursor);
+                    cursor = start;
+                },
+                12 => print!("\x1b[H\x1b[2J"), // Ctrl-L
+                8 | 127 if cursor > 0 => {
+                    cursor -= 1;
+                    line.remove(cursor);
+                },
+                0x1b => match (read_byte(&mut stdin)?, read_byte(&mut stdin)?) {
+                    (Some(b'[' | b'O'), Some(b'A')) if browsing > 0 => {
+                        if browsing == self.history.len() {
+                            draft = line.clone();
+                        }
+                        browsing -= 1;
+                        line = self.history[browsing].chars().collect();
+                        cursor = line.len();
+                    },
+                    (Some(b'[' | b'O'), Some(b'B')) if browsing < self.history.len() => {
+                        browsing += 1;
+                        line = match self.history.get(browsing) {
+                            Some(entry) => entry.chars().collect(),
+                            None => draft.clone(),
+                        };
+                        cursor = line.len();
+                    },
+                    (Some(b'[' | b'O'), Some(b'C')) => cursor = (cursor + 1).min(line.len()),
+                    (Some(b'[' | b'O'), Some(b'D')) => cursor = cursor.saturating_sub(1),
+                    (Some(b'[' | b'O'), Some(b'H')) => cursor = 0,
+                    (Some(b'[' | b'O'), Some(b'F')) => cursor = line.len(),
+                    (Some(b'['), Some(b'3')) if read_byte(&mut stdin)? == Some(b'~') && cursor < line.len() => {
+                        line.remove(cursor);
+                    },
+                    _ => {},
+                },
+                byte if byte >= 0x20 && byte != 127 => {
+                    line.insert(cursor, read_char(byte, &mut stdin)?);
+                    cursor += 1;
+                },
+                _ => {},
+            }
+            redraw(prompt, &line, cursor)?;
+        }
+    }
+}
+
+// Puts the terminal in non-canonical, no-echo mode and restores the previous settings when dropped
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enable() -> Option<RawMode> {
+        use std::io::IsTerminal;
+        if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+            return None;
+        }
+        let saved = stty(&["-g"])?;
+        stty(&["-icanon", "-echo", "-isig", "-ixon", "-icrnl", "min", "1"])?;
+        Some(RawMode { saved: saved.trim().to_string() })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = stty(&[self.saved.as_str()]);
+    }
+}
+
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(File::open("/dev/tty").ok()?)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+// Completes a UTF-8 sequence starting with `first`
+fn read_char(first: u8, input: &mut impl Read) -> io::Result<char> {
+    let len = match first {
+        0xf0.. => 4,
+        0xe0.. => 3,
+        0xc0.. => 2,
+        _ => 1,
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        bytes.extend(read_byte(input)?);
+    }
+    Ok(std::str::from_utf8(&bytes).ok().and_then(|s| s.chars().next()).unwrap_or(char::REPLACEMENT_CHARACTER))
+}
+
+fn word_start(line: &[char], cursor: usize) -> usize {
+    let mut start = cursor;
+    while start > 0 && line[start - 1] == ' ' {
+        start -= 1;
+    }
+    while start > 0 && line[start - 1] != ' ' {
+        start -= 1;
+    }
+    start
+}
+
+fn redraw(prompt: &str, line: &[char], cursor: usize) -> io::Result<()> {
+    let text: String = line.iter().collect();
+    let mut out = io::stdout().lock();
+    write!(out, "\r{}{}\x1b[K", prompt, text)?;
+    if cursor < line.len() {
+        write!(out, "\x1b[{}D", line.len() - cursor)?;
+    }
+    out.flush()
+}
@@ -0,0 +1,217 @@
+use std::io::{self, BufRead, IsTerminal};
+use anyhow::Result;
+use redis_starter_rust::client::Client;
+use redis_starter_rust::codec::split_inline_args;
+use redis_starter_rust::error::Error;
+use redis_starter_rust::resp::Value;
+use crate::editor::Editor;
+
+mod editor;
+mod output;
+
+const USAGE: &str = "\
+Usage: zenql-cli [OPTIONS] [command [arg ...]]
+  -h <hostname>      Server hostname (default: 127.0.0.1)
+  -p <port>          Server port (default: 6379)
+  -a <password>      Password to AUTH with
+  -n <db>            Database number
+  -e, --eval <line>  Run a command line and exit, may be repeated
+  --raw              Print replies unformatted (default when stdout is not a terminal)
+  --no-raw           Print replies formatted (default on a terminal)
+  --help             Show this help
+
+Without a command or --eval, commands are read from stdin: interactively on a terminal, one per line
+otherwise.";
+
+struct Options {
+    host: String,
+    port: u16,
+    password: Option<String>,
+    db: Option<i64>,
+    eval: Vec<String>,
+    raw: bool,
+    command: Vec<Vec<u8>>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Options> {
+        let mut options = Options {
+            host: "127.0.0.1".to_string(),
+            port: 6379,
+            password: None,
+            db: None,
+            eval: vec![],
+            raw: !io::stdout().is_terminal(),
+            command: vec![],
+        };
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| anyhow::anyhow!("Missing value for '{}'", arg));
+            match arg.as_str() {
+                "-h" => options.host = value()?,
+                "-p" => options.port = value()?.parse()?,
+                "-a" => options.password = Some(value()?),
+                "-n" => options.db = Some(value()?.parse()?),
+                "-e" | "--eval" => options.eval.push(value()?),
+                "--raw" => options.raw = true,
+                "--no-raw" => options.raw = false,
+                "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                },
+                _ if arg.starts_with('-') && options.command.is_empty() => return Err(anyhow::anyhow!("Unrecognized option '{}'", arg)),
+                _ => {
+                    options.command.push(arg.into_bytes());
+                    options.command.extend(args.by_ref().map(String::into_bytes));
+                },
+            }
+        }
+        Ok(options)
+    }
+}
+
+struct Cli {
+    options: Options,
+    client: Option<Client>, // Connected lazily, and again after the connection is lost
+    db: i64,
+}
+
+impl Cli {
+    fn prompt(&self) -> String {
+        match self.db {
+            0 => format!("{}:{}> ", self.options.host, self.options.port),
+            db => format!("{}:{}[{}]> ", self.options.host, self.options.port, db),
+        }
+    }
+
+    async fn connect(&mut self) -> Result<Client> {
+        let mut client = Client::connect((self.options.host.as_str(), self.options.port)).await?;
+        if let Some(password) = &self.options.password {
+            client.auth(password).await?;
+        }
+        let db = self.options.db.unwrap_or(self.db);
+        if db != 0 {
+            client.call(["SELECT".to_string(), db.to_string()]).await?;
+            self.db = db;
+        }
+        Ok(client)
+    }
+
+    // Runs one command and prints its reply. Only connection failures are returned as errors.
+    async fn run(&mut self, args: Vec<Vec<u8>>) -> Result<()> {
+        let mut client = match self.client.take() {
+            Some(client) => client,
+            None => self.connect().await.map_err(|e| {
+                anyhow::anyhow!("Could not connect to zenql at {}:{}: {}", self.options.host, self.options.port, e)
+            })?,
+        };
+        let name = String::from_utf8_lossy(&args[0]).to_lowercase();
+        if name == "subscribe" {
+            return self.listen(client, &args[1..]).await;
+        }
+
+        let reply = match client.call(&args).await {
+            Ok(reply) => reply,
+            Err(Error::Reply(message)) => Value::Error(message),
+            Err(e) => return Err(e.into()), // The connection is dropped and reopened by the next command
+        };
+        if name == "select" && matches!(reply, Value::SimpleString(_)) {
+            self.db = String::from_utf8_lossy(&args[1]).parse().unwrap_or(self.db);
+        }
+        self.print(&reply);
+        self.client = Some(client);
+        Ok(())
+    }
+
+    // SUBSCRIBE turns the connection into a message feed for the rest of the session
+    async fn listen(&mut self, client: Client, channels: &[Vec<u8>]) -> Result<()> {
+        let mut subscription = client.subscribe(channels).await?;
+        if !self.options.raw {
+            println!("Reading messages... (press Ctrl-C to quit)");
+        }
+        for (i, channel) in channels.iter().enumerate() {
+            self.print(&Value::Array(vec![
+                Value::BulkString("subscribe".into()),
+                Value::BulkString(channel.clone().into()),
+                Value::Integer(i as i64 + 1),
+            ]));
+        }
+        loop {
+            let message = subscription.next_message().await?;
+            self.print(&Value::Array(vec![
+                Value::BulkString("message".into()),
+                Value::BulkString(message.channel),
+                Value::BulkString(message.payload),
+            ]));
+        }
+    }
+
+    fn print(&self, reply: &Value) {
+        if self.options.raw {
+            println!("{}", output::raw(reply));
+        } else {
+            println!("{}", output::pretty(reply));
+        }
+    }
+
+    // Splits a typed line and runs it; false once the user asked to quit
+    async fn run_line(&mut self, line: &str) -> Result<bool> {
+        let args = match split_inline_args(line.as_bytes()) {
+            Ok(args) => args,
+            Err(_) => {
+                println!("Invalid argument(s)");
+                return Ok(true);
+            },
+        };
+        match args.first().map(|name| String::from_utf8_lossy(name).to_lowercase()).as_deref() {
+            None => Ok(true),
+            Some("quit" | "exit") => Ok(false),
+            Some(_) => self.run(args).await.map(|_| true),
+        }
+    }
+
+    async fn repl(&mut self) -> Result<()> {
+        let mut editor = Editor::new();
+        while let Some(line) = editor.read_line(&self.prompt())? {
+            match self.run_line(&line).await {
+                Ok(true) => {},
+                Ok(false) => break,
+                Err(e) => println!("{}", e),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    if let Err(e) = cli().await {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn cli() -> Result<()> {
+    let mut options = Options::parse(std::env::args().skip(1))?;
+    let command = std::mem::take(&mut options.command);
+    let eval = std::mem::take(&mut options.eval);
+    let mut cli = Cli { options, client: None, db: 0 };
+
+    if !command.is_empty() {
+        return cli.run(command).await;
+    }
+    if !eval.is_empty() {
+        for line in eval {
+            cli.run_line(&line).await?;
+        }
+        return Ok(());
+    }
+    if io::stdin().is_terminal() {
+        return cli.repl().await;
+    }
+    for line in io::stdin().lock().lines() {
+        if !cli.run_line(&line?).await? {
+            break;
+        }
+    }
+    Ok(())
+}
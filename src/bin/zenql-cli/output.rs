@@ -0,0 +1,85 @@
+use redis_starter_rust::codec::format_double;
+use redis_starter_rust::resp::Value;
+
+// Human-readable form used on a terminal, following redis-cli: quoted strings, typed scalars and numbered,
+// indented aggregates
+pub fn pretty(value: &Value) -> String {
+    let mut out = String::new();
+    write_pretty(value, 0, &mut out);
+    out
+}
+
+fn write_pretty(value: &Value, indent: usize, out: &mut String) {
+    match value {
+        Value::SimpleString(s) => out.push_str(s),
+        Value::Error(s) => out.push_str(&format!("(error) {}", s)),
+        Value::BulkString(b) => out.push_str(&quote(b)),
+        Value::Integer(i) => out.push_str(&format!("(integer) {}", i)),
+        Value::Null | Value::NullArray => out.push_str("(nil)"),
+        Value::Double(d) => out.push_str(&format!("(double) {}", format_double(*d))),
+        Value::Boolean(b) => out.push_str(&format!("({})", b)),
+        Value::BigNumber(n) => out.push_str(&format!("(big number) {}", n)),
+        Value::Verbatim(_, payload) => out.push_str(&String::from_utf8_lossy(payload)),
+        Value::Array(items) | Value::Set(items) | Value::Push(items) => {
+            if items.is_empty() {
+                out.push_str(if matches!(value, Value::Set(_)) { "(empty set)" } else { "(empty array)" });
+            }
+            let width = items.len().to_string().len();
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push('\n');
+                    out.push_str(&" ".repeat(indent));
+                }
+                out.push_str(&format!("{:>width$}) ", i + 1));
+                write_pretty(item, indent + width + 2, out);
+            }
+        },
+        Value::Map(pairs) => {
+            if pairs.is_empty() {
+                out.push_str("(empty hash)");
+            }
+            let width = pairs.len().to_string().len();
+            for (i, (key, value)) in pairs.iter().enumerate() {
+                if i > 0 {
+                    out.push('\n');
+                    out.push_str(&" ".repeat(indent));
+                }
+                out.push_str(&format!("{:>width$}# ", i + 1));
+                write_pretty(key, indent + width + 2, out);
+                out.push_str(" => ");
+                write_pretty(value, indent + width + 2, out);
+            }
+        },
+    }
+}
+
+// Plain form used when stdout isn't a terminal, so output can be piped into other tools
+pub fn raw(value: &Value) -> String {
+    match value {
+        Value::SimpleString(s) | Value::Error(s) | Value::BigNumber(s) => s.clone(),
+        Value::BulkString(b) | Value::Verbatim(_, b) => String::from_utf8_lossy(b).into_owned(),
+        Value::Integer(i) => i.to_string(),
+        Value::Null | Value::NullArray => String::new(),
+        Value::Double(d) => format_double(*d),
+        Value::Boolean(b) => (*b as u8).to_string(),
+        Value::Array(items) | Value::Set(items) | Value::Push(items) => items.iter().map(raw).collect::<Vec<_>>().join("\n"),
+        Value::Map(pairs) => pairs.iter().flat_map(|(key, value)| [raw(key), raw(value)]).collect::<Vec<_>>().join("\n"),
+    }
+}
+
+fn quote(bytes: &[u8]) -> String {
+    let mut quoted = String::from("\"");
+    for &b in bytes {
+        match b {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            b'\t' => quoted.push_str("\\t"),
+            0x20..=0x7e => quoted.push(b as char),
+            _ => quoted.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
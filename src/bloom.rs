@@ -0,0 +1,175 @@
+// Scalable Bloom filters (Almeida et al.): a Bloom filter answers whether an item may have been added, never
+// missing one that was, and wrongly saying yes for about `error_rate` of the others. Each filter is sized for
+// a capacity; once that many items are in, a new filter `expansion` times bigger is stacked on with half the
+// error rate, so the stack as a whole keeps close to the rate asked for however many items come. A
+// non-scaling filter refuses items past its capacity instead. Items are hashed with hasher::stable_hash, as
+// filters are persisted, and each filter sets `hashes` bits per item by double hashing (Kirsch and
+// Mitzenmacher).
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use crate::hasher::stable_hash;
+use crate::record::PayloadReader;
+
+pub const DEFAULT_ERROR_RATE: f64 = 0.01;
+pub const DEFAULT_CAPACITY: u64 = 100;
+pub const DEFAULT_EXPANSION: u64 = 2;
+// Bounds a single filter's size, 512MiB, and how many can stack up
+const MAX_BITS: u64 = 1 << 32;
+const MAX_FILTERS: usize = 64;
+const SEEDS: (u64, u64) = (0x243f_6a88_85a3_08d3, 0x1319_8a2e_0370_7344);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Filter {
+    capacity: u64,
+    count: u64,
+    hashes: u32,
+    bits: u64,
+    words: Vec<u64>,
+}
+
+impl Filter {
+    fn new(capacity: u64, error_rate: f64) -> Result<Filter> {
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-(capacity as f64) * error_rate.ln() / (ln2 * ln2)).ceil().max(64.0);
+        if bits > MAX_BITS as f64 {
+            bail!("a filter for {} items at that error rate would be too large", capacity);
+        }
+        let bits = bits as u64;
+        let hashes = ((bits as f64 / capacity as f64) * ln2).ceil().clamp(1.0, 64.0) as u32;
+        Ok(Filter { capacity, count: 0, hashes, bits, words: vec![0; bits.div_ceil(64) as usize] })
+    }
+
+    // The bits an item sets
+    fn positions(hashes: u32, bits: u64, (h1, h2): (u64, u64)) -> impl Iterator<Item = u64> {
+        (0..hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % bits)
+    }
+
+    fn contains(&self, hash: (u64, u64)) -> bool {
+        Filter::positions(self.hashes, self.bits, hash).all(|bit| self.words[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    fn insert(&mut self, hash: (u64, u64)) {
+        for bit in Filter::positions(self.hashes, self.bits, hash) {
+            self.words[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+    pub error_rate: f64, // The first filter's; each after has half the one before's
+    pub expansion: u64, // 0 for a non-scaling filter
+    filters: Vec<Filter>,
+}
+
+// Error rates are never NaN, so equality is total
+impl Eq for BloomFilter {}
+
+fn hash(item: &[u8]) -> (u64, u64) {
+    (stable_hash(item, SEEDS.0), stable_hash(item, SEEDS.1) | 1)
+}
+
+impl BloomFilter {
+    pub fn new(error_rate: f64, capacity: u64, expansion: u64) -> Result<BloomFilter> {
+        if !(error_rate > 0.0 && error_rate < 1.0) {
+            bail!("the error rate must be between 0 and 1");
+        }
+        if capacity == 0 {
+            bail!("the capacity must be positive");
+        }
+        Ok(BloomFilter { error_rate, expansion, filters: vec![Filter::new(capacity, error_rate)?] })
+    }
+
+    pub fn contains(&self, item: &[u8]) -> bool {
+        let hash = hash(item);
+        self.filters.iter().any(|filter| filter.contains(hash))
+    }
+
+    // Adds an item, false if it may have been added already
+    pub fn insert(&mut self, item: &[u8]) -> Result<bool> {
+        let hash = hash(item);
+        if self.filters.iter().any(|filter| filter.contains(hash)) {
+            return Ok(false);
+        }
+        let last = self.filters.last().expect("there's always a filter");
+        if last.count >= last.capacity {
+            if self.expansion == 0 {
+                bail!("non scaling filter is full");
+            }
+            if self.filters.len() >= MAX_FILTERS {
+                bail!("the filter can't grow any further");
+            }
+            let capacity = last.capacity.saturating_mul(self.expansion);
+            let error_rate = self.error_rate * 0.5f64.powi(self.filters.len() as i32);
+            self.filters.push(Filter::new(capacity, error_rate)?);
+        }
+        self.filters.last_mut().expect("there's always a filter").insert(hash);
+        Ok(true)
+    }
+
+    // How many items were added
+    pub fn len(&self) -> u64 {
+        self.filters.iter().map(|filter| filter.count).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.filters.iter().map(|filter| filter.capacity).sum()
+    }
+
+    pub fn filters(&self) -> usize {
+        self.filters.len()
+    }
+
+    // Bytes of bits
+    pub fn size(&self) -> usize {
+        self.filters.iter().map(|filter| filter.words.len() * 8).sum()
+    }
+
+    // The filter as stored in a record: error rate f64 | expansion u64 | filter count u64, then each filter's
+    // capacity u64 | count u64 | hashes u32 | bits u64 | its bits as u64 words, all little-endian
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(24 + self.size() + 28 * self.filters.len());
+        out.extend_from_slice(&self.error_rate.to_le_bytes());
+        out.extend_from_slice(&self.expansion.to_le_bytes());
+        out.extend_from_slice(&(self.filters.len() as u64).to_le_bytes());
+        for filter in &self.filters {
+            out.extend_from_slice(&filter.capacity.to_le_bytes());
+            out.extend_from_slice(&filter.count.to_le_bytes());
+            out.extend_from_slice(&filter.hashes.to_le_bytes());
+            out.extend_from_slice(&filter.bits.to_le_bytes());
+            for word in &filter.words {
+                out.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+        out
+    }
+
+    pub fn decode(payload: &Bytes) -> Option<BloomFilter> {
+        let mut reader = PayloadReader::new(payload);
+        let error_rate = f64::from_bits(reader.u64()?);
+        let expansion = reader.u64()?;
+        let count = reader.u64()?;
+        if !(error_rate > 0.0 && error_rate < 1.0) || count == 0 || count > MAX_FILTERS as u64 {
+            return None;
+        }
+        let mut filters = vec![];
+        for _ in 0..count {
+            let (capacity, count, hashes, bits) = (reader.u64()?, reader.u64()?, reader.u32()?, reader.u64()?);
+            if bits == 0 || bits > MAX_BITS || !(1..=64).contains(&hashes) {
+                return None;
+            }
+            let words = reader.take(bits.div_ceil(64) as usize * 8)?;
+            let words = words.chunks_exact(8).map(|word| u64::from_le_bytes(word.try_into().expect("chunks of 8")));
+            filters.push(Filter { capacity, count, hashes, bits, words: words.collect() });
+        }
+        if !reader.is_empty() {
+            return None;
+        }
+        Some(BloomFilter { error_rate, expansion, filters })
+    }
+}
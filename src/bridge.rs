@@ -0,0 +1,304 @@
+// Bridges: connectors mirroring keyspace events or pub/sub channels into NATS subjects or Kafka topics, and
+// NATS subjects into pub/sub channels, for plugging zenql into existing event pipelines. Each entry of
+// `bridges` is a URL followed by options:
+//
+//   nats://[user:password@]host[:port];subject=zenql.keys[;events=set,del][;match=user:*]
+//   nats://host;from=channel:orders;subject=orders
+//   nats://host;subject=orders.>;to=channel:orders
+//   kafka://host[:port];topic=zenql-keys[;partition=0][;events=...][;match=...]
+//   kafka://host;from=channel:orders;topic=orders
+//
+// from says what's mirrored out. keyspace, the default, sends each keyspace event as JSON, as webhooks do and
+// filtered the same way, with the event's key as the Kafka record's. channel:<name> sends each message
+// published on the channel as it is. to=channel:<name> goes the other way, publishing each message on the
+// NATS subject, wildcards allowed, to the channel. Ingesting from Kafka isn't supported, as it would need
+// consumer offsets kept somewhere.
+//
+// Outgoing messages queue per bridge and go out in order, in batches the server confirms before the next is
+// sent. When that fails the bridge reconnects with exponential backoff and sends the batch again, so a
+// message may arrive twice but is only lost when the queue fills up and drops it. INFO and the metrics
+// endpoint count messages sent, received and dropped, and connection failures.
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::Result;
+use bytes::Bytes;
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver};
+use crate::events::{EventFilter, KeyEvent};
+use crate::kafka::{KafkaProducer, Record};
+use crate::log::{log_info, log_warn};
+use crate::nats::NatsClient;
+use crate::pubsub::PubSub;
+use crate::resp::Value;
+use crate::stats::{self, SERVER_STATS};
+use crate::storage::Storage;
+
+// Most messages sent at once
+const BRIDGE_BATCH: usize = 500;
+// Messages waiting to go out before more are dropped
+const BRIDGE_QUEUE: usize = 100_000;
+// Wait before reconnecting after the first failure, doubling after every one up to MAX_BACKOFF
+const INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+// Longest connecting and sending a batch may take
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum System {
+    Nats,
+    Kafka,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Flow {
+    Keyspace(EventFilter), // Keyspace events out
+    Channel(Bytes), // A channel's messages out
+    Ingest(Bytes), // The subject's messages into a channel
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bridge {
+    spec: String, // As configured
+    system: System,
+    authority: String, // host:port
+    credentials: Option<(String, String)>, // NATS user and password
+    destination: String, // NATS subject or Kafka topic
+    partition: i32, // Of the Kafka topic
+    flow: Flow,
+}
+
+impl Bridge {
+    pub fn parse(spec: &str) -> Result<Bridge> {
+        let mut parts = spec.split(';');
+        let url = parts.next().unwrap_or_default();
+        let (system, rest) = match url.split_once("://") {
+            Some(("nats", rest)) => (System::Nats, rest),
+            Some(("kafka", rest)) => (System::Kafka, rest),
+            _ => return Err(anyhow::anyhow!("Invalid bridge URL '{}', expected nats:// or kafka://", url)),
+        };
+        let (credentials, host) = match rest.rsplit_once('@') {
+            Some((userinfo, host)) => {
+                let (user, password) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+                (Some((user.to_string(), password.to_string())), host)
+            },
+            None => (None, rest),
+        };
+        if host.is_empty() || host.trim_end_matches('/').contains('/') {
+            return Err(anyhow::anyhow!("Invalid bridge URL '{}', expected a host and port only", url));
+        }
+        if system == System::Kafka && credentials.is_some() {
+            return Err(anyhow::anyhow!("Kafka bridge '{}' has credentials, SASL isn't supported", url));
+        }
+        let host = host.trim_end_matches('/');
+        let default_port = if system == System::Nats { 4222 } else { 9092 };
+        let authority = match host.rsplit_once(':') {
+            Some((_, port)) if !port.contains(']') => host.to_string(),
+            _ => format!("{}:{}", host, default_port),
+        };
+        let (mut from, mut to, mut destination, mut partition) = (None, None, None, 0);
+        let mut filter = EventFilter::default();
+        for option in parts {
+            let Some((name, value)) = option.split_once('=') else {
+                return Err(anyhow::anyhow!("Invalid bridge option '{}', expected name=value", option));
+            };
+            match (name, system) {
+                ("from", _) => from = Some(value),
+                ("to", _) => to = Some(value),
+                ("subject", System::Nats) | ("topic", System::Kafka) => destination = Some(value.to_string()),
+                ("partition", System::Kafka) => partition = value.parse()?,
+                _ if filter.set(name, value)? => {},
+                _ => return Err(anyhow::anyhow!("Unknown option '{}' for bridge '{}'", name, url)),
+            }
+        }
+        let destination = destination.ok_or_else(|| match system {
+            System::Nats => anyhow::anyhow!("Bridge '{}' needs a subject=<subject>", url),
+            System::Kafka => anyhow::anyhow!("Bridge '{}' needs a topic=<topic>", url),
+        })?;
+        let channel = |value: &str| match value.strip_prefix("channel:") {
+            Some(channel) if !channel.is_empty() => Ok(Bytes::copy_from_slice(channel.as_bytes())),
+            _ => Err(anyhow::anyhow!("Invalid bridge endpoint '{}', expected channel:<name>", value)),
+        };
+        let flow = match (from, to) {
+            (Some(_), Some(_)) => return Err(anyhow::anyhow!("Bridge '{}' has both from and to, a bridge goes one way", url)),
+            (None | Some("keyspace"), None) => Flow::Keyspace(filter.clone()),
+            (Some(from), None) => Flow::Channel(channel(from)?),
+            (None, Some(_)) if system == System::Kafka => return Err(anyhow::anyhow!("Ingesting from Kafka isn't supported")),
+            (None, Some(to)) => Flow::Ingest(channel(to)?),
+        };
+        if !matches!(flow, Flow::Keyspace(_)) && filter != EventFilter::default() {
+            return Err(anyhow::anyhow!("Bridge '{}' filters keyspace events but doesn't send them", url));
+        }
+        Ok(Bridge { spec: spec.to_string(), system, authority, credentials, destination, partition, flow })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.spec
+    }
+
+    fn credentials(&self) -> Option<(&str, &str)> {
+        self.credentials.as_ref().map(|(user, password)| (user.as_str(), password.as_str()))
+    }
+
+    async fn connect(&self) -> Result<Connection> {
+        Ok(match self.system {
+            System::Nats => Connection::Nats(NatsClient::connect(&self.authority, self.credentials()).await?),
+            System::Kafka => Connection::Kafka(KafkaProducer::connect(&self.authority, &self.destination, self.partition).await?),
+        })
+    }
+
+    // Sends a batch, connecting first when there's no connection
+    async fn send(&self, connection: &mut Option<Connection>, batch: &[Message]) -> Result<()> {
+        let connection = match connection {
+            Some(connection) => connection,
+            None => connection.insert(self.connect().await?),
+        };
+        match connection {
+            Connection::Nats(client) => {
+                let payloads: Vec<Bytes> = batch.iter().map(|message| message.value.clone()).collect();
+                client.publish(&self.destination, &payloads).await
+            },
+            Connection::Kafka(producer) => {
+                let records: Vec<Record> = batch.iter()
+                    .map(|message| Record { key: message.key.clone(), value: message.value.clone(), timestamp_ms: message.time_ms })
+                    .collect();
+                producer.produce(&records).await
+            },
+        }
+    }
+
+    // Logs a failure and waits out the backoff, doubling it for next time
+    async fn failed(&self, error: anyhow::Error, backoff: &mut Duration) {
+        stats::incr(&SERVER_STATS.bridge_connection_failures, 1);
+        log_warn!("Bridge {} failed, retrying in {:?}: {:?}", self.spec, backoff, error);
+        tokio::time::sleep(*backoff).await;
+        *backoff = (*backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+enum Connection {
+    Nats(NatsClient),
+    Kafka(KafkaProducer),
+}
+
+// What a bridge mirrors out, subscribed to before it starts so nothing in between is missed
+enum Source {
+    Keyspace(UnboundedReceiver<KeyEvent>, EventFilter),
+    Channel(UnboundedReceiver<Value>),
+    Ingest(Bytes),
+}
+
+struct Message {
+    key: Option<Bytes>,
+    value: Bytes,
+    time_ms: u64,
+}
+
+// Subscribes the bridge to what it mirrors, returning what runs it
+pub fn start(bridge: Bridge, storage: &Storage, pubsub: &Arc<PubSub>) -> impl Future<Output = ()> + Send + 'static {
+    let source = match &bridge.flow {
+        Flow::Keyspace(filter) => Source::Keyspace(storage.subscribe(), filter.clone()),
+        Flow::Channel(channel) => {
+            let (tx, rx) = mpsc::unbounded_channel();
+            pubsub.subscribe(channel, pubsub.next_subscriber_id(), tx);
+            Source::Channel(rx)
+        },
+        Flow::Ingest(channel) => Source::Ingest(channel.clone()),
+    };
+    run(Arc::new(bridge), source, Arc::clone(pubsub))
+}
+
+async fn run(bridge: Arc<Bridge>, source: Source, pubsub: Arc<PubSub>) {
+    if let Source::Ingest(channel) = source {
+        return ingest(&bridge, &channel, &pubsub).await;
+    }
+    let (tx, rx) = mpsc::channel(BRIDGE_QUEUE);
+    tokio::spawn(export(Arc::clone(&bridge), rx));
+    feed(source, tx).await;
+}
+
+// Queues what the source has for export, until it's gone
+async fn feed(mut source: Source, queue: Sender<Message>) {
+    loop {
+        let message = match &mut source {
+            Source::Keyspace(events, filter) => match events.recv().await {
+                Some(event) if filter.wants(&event) => {
+                    Message { key: Some(event.key.clone()), value: event.to_json().to_text().into(), time_ms: event.time_ms }
+                },
+                Some(_) => continue,
+                None => return,
+            },
+            Source::Channel(frames) => match frames.recv().await {
+                // ["message", channel, message], see PubSub::publish
+                Some(Value::Push(mut frame)) if frame.len() == 3 => match frame.pop() {
+                    Some(Value::BulkString(message)) => Message { key: None, value: message, time_ms: now_ms() },
+                    _ => continue,
+                },
+                Some(_) => continue,
+                None => return,
+            },
+            Source::Ingest(_) => return,
+        };
+        if queue.try_send(message).is_err() {
+            stats::incr(&SERVER_STATS.bridge_messages_dropped, 1);
+        }
+    }
+}
+
+async fn export(bridge: Arc<Bridge>, mut queue: Receiver<Message>) {
+    let mut connection = None;
+    let mut batch = Vec::with_capacity(BRIDGE_BATCH);
+    let mut backoff = INITIAL_BACKOFF;
+    while let Some(message) = queue.recv().await {
+        batch.push(message);
+        while batch.len() < BRIDGE_BATCH {
+            match queue.try_recv() {
+                Ok(message) => batch.push(message),
+                Err(_) => break,
+            }
+        }
+        loop {
+            let result = match tokio::time::timeout(SEND_TIMEOUT, bridge.send(&mut connection, &batch)).await {
+                Ok(result) => result,
+                Err(_) => Err(anyhow::anyhow!("Timed out")),
+            };
+            match result {
+                Ok(()) => break,
+                Err(e) => {
+                    connection = None;
+                    bridge.failed(e, &mut backoff).await;
+                },
+            }
+        }
+        if backoff != INITIAL_BACKOFF {
+            log_info!("Bridge {} is sending again", bridge.spec);
+            backoff = INITIAL_BACKOFF;
+        }
+        stats::incr(&SERVER_STATS.bridge_messages_sent, batch.len() as u64);
+        batch.clear();
+    }
+}
+
+// Publishes the messages on the bridge's NATS subject to `channel`, reconnecting whenever the connection fails
+async fn ingest(bridge: &Bridge, channel: &Bytes, pubsub: &PubSub) {
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let result = async {
+            let mut client = NatsClient::connect(&bridge.authority, bridge.credentials()).await?;
+            client.subscribe(&bridge.destination, 1).await?;
+            backoff = INITIAL_BACKOFF;
+            loop {
+                let (_, payload) = client.next_message().await?;
+                pubsub.publish(channel, &payload);
+                stats::incr(&SERVER_STATS.bridge_messages_received, 1);
+            }
+        };
+        let result: Result<()> = result.await;
+        if let Err(e) = result {
+            bridge.failed(e, &mut backoff).await;
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
@@ -0,0 +1,283 @@
+// Snapshots kept in an S3-compatible bucket, MinIO say, so backups don't depend on the server's disk. With
+// snapshot-bucket set, SNAPSHOT EXPORT uploads every file it writes to the bucket once it's in place, and
+// snapshot-restore names one to download into dir and load at startup, before the listeners open, replacing
+// whatever the data directory held. The bucket is a URL whose first path segment names the bucket and whose
+// rest is a prefix put before file names:
+//
+//   http://minio:9000/backups/zenql
+//
+// so exporting nightly.snap stores the object zenql/nightly.snap in the bucket backups. An incremental
+// snapshot names its base by file name when both were exported to the same directory, and restoring one
+// downloads that chain too; bases exported elsewhere are expected at the path they were written to.
+//
+// Requests are signed with AWS Signature Version 4, using snapshot-bucket-access-key, -secret-key and
+// -region, and carry the SHA-256 of the whole file rather than signing it chunk by chunk. Nothing else needs
+// SHA-256 or HMAC, so they're written out here. Only plain http is spoken, as for webhooks; put a
+// TLS-terminating proxy in front of endpoints that need https, and keep the link private otherwise, as the
+// signature doesn't hide what it signs. Snapshots go up as they are on disk, encrypted when encryption is on.
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::Result;
+use crate::config::Config;
+use crate::snapshot::{self, ImportMode};
+use crate::storage::Storage;
+
+// Longest a request may wait on the endpoint between reads or writes
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// Longest chain of incremental snapshots downloaded before giving up on a cycle
+const MAX_CHAIN: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bucket {
+    spec: String, // As configured
+    authority: String, // host:port
+    path: String, // /bucket, then the prefix if any, without a trailing slash
+}
+
+impl Bucket {
+    pub fn parse(spec: &str) -> Result<Bucket> {
+        let rest = match spec.split_once("://") {
+            Some(("http", rest)) => rest,
+            Some(("https", _)) => return Err(anyhow::anyhow!("Snapshot bucket '{}' is https, only http is supported", spec)),
+            _ => return Err(anyhow::anyhow!("Invalid snapshot bucket URL '{}', expected http://host[:port]/bucket[/prefix]", spec)),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], rest[slash..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        if authority.is_empty() {
+            return Err(anyhow::anyhow!("Snapshot bucket URL '{}' has no host", spec));
+        }
+        if path.is_empty() {
+            return Err(anyhow::anyhow!("Snapshot bucket URL '{}' names no bucket, expected http://host[:port]/bucket[/prefix]", spec));
+        }
+        // A port is whatever follows the last colon, unless that's inside an IPv6 address's brackets
+        let authority = match authority.rsplit_once(':') {
+            Some((_, port)) if !port.contains(']') => authority.to_string(),
+            _ => format!("{}:80", authority),
+        };
+        Ok(Bucket { spec: spec.to_string(), authority, path: path.to_string() })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.spec
+    }
+
+    // Request path of the object for the file `name`
+    fn object(&self, name: &str) -> String {
+        uri_encode(&format!("{}/{}", self.path, name))
+    }
+}
+
+// Signs requests with AWS Signature Version 4 for the S3 service
+pub struct Signer<'a> {
+    pub access_key: &'a str,
+    pub secret_key: &'a str,
+    pub region: &'a str,
+}
+
+impl Signer<'_> {
+    fn from_config(config: &Config) -> Signer<'_> {
+        Signer { access_key: &config.snapshot_bucket_access_key, secret_key: &config.snapshot_bucket_secret_key, region: &config.snapshot_bucket_region }
+    }
+
+    // The Authorization header for a request to the already encoded `path`, without a query string, that
+    // sends `headers`. They're all signed, and must include x-amz-date and x-amz-content-sha256.
+    pub fn authorization(&self, method: &str, path: &str, headers: &[(&str, &str)]) -> String {
+        let mut headers: Vec<_> = headers.iter().map(|(name, value)| (name.to_ascii_lowercase(), value.trim())).collect();
+        headers.sort();
+        let header = |name: &str| headers.iter().find(|(header, _)| header == name).map_or("", |(_, value)| *value);
+        let (time, payload_hash) = (header("x-amz-date"), header("x-amz-content-sha256"));
+        let signed = headers.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(";");
+        let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value)).collect();
+        let request = format!("{}\n{}\n\n{}\n{}\n{}", method, path, canonical_headers, signed, payload_hash);
+        let date = time.get(..8).unwrap_or(time);
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", time, scope, hex(&sha256(request.as_bytes())));
+        let mut key = hmac_sha256(format!("AWS4{}", self.secret_key).as_bytes(), date.as_bytes());
+        for part in [self.region, "s3", "aws4_request"] {
+            key = hmac_sha256(&key, part.as_bytes());
+        }
+        let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+        format!("AWS4-HMAC-SHA256 Credential={}/{},SignedHeaders={},Signature={}", self.access_key, scope, signed, signature)
+    }
+}
+
+// Uploads the snapshot at `path` to snapshot-bucket under its file name
+pub fn upload(config: &Config, path: &Path) -> io::Result<()> {
+    let Some(bucket) = &config.snapshot_bucket else {
+        return Ok(());
+    };
+    let name = path.file_name().and_then(|name| name.to_str()).ok_or_else(|| io::Error::other(format!("{} has no file name to store it under", path.display())))?;
+    let body = fs::read(path)?;
+    request(bucket, &Signer::from_config(config), "PUT", &bucket.object(name), &body)?;
+    Ok(())
+}
+
+// Downloads the snapshot `name` from snapshot-bucket into dir, with the incremental snapshots it follows, and
+// loads it in place of the keyspace. Returns how many keys it set.
+pub fn restore(config: &Config, storage: &Storage, name: &str) -> io::Result<usize> {
+    let Some(bucket) = &config.snapshot_bucket else {
+        return Err(io::Error::other("snapshot-restore needs snapshot-bucket"));
+    };
+    let signer = Signer::from_config(config);
+    let mut next = Some(PathBuf::from(name));
+    let mut downloaded = 0;
+    while let Some(file) = next.take() {
+        if downloaded == MAX_CHAIN {
+            return Err(io::Error::other(format!("more than {} incremental snapshots chained, is there a cycle?", MAX_CHAIN)));
+        }
+        let object = file.to_str().ok_or_else(|| io::Error::other(format!("{} isn't a name the bucket can hold", file.display())))?;
+        let body = request(bucket, &signer, "GET", &bucket.object(object), &[])?;
+        let path = config.dir.join(&file);
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        fs::write(&temp, body).and_then(|_| fs::rename(&temp, &path)).inspect_err(|_| {
+            let _ = fs::remove_file(&temp);
+        })?;
+        downloaded += 1;
+        // A base named by file name alone was exported next to it, and uploaded next to it too
+        next = snapshot::base(&path, storage.keyring())?.filter(|base| base.parent() == Some(Path::new("")));
+    }
+    snapshot::import(storage, &config.dir.join(name), ImportMode::Replace)
+}
+
+// Sends one request and returns the response body, failing on anything but a 2xx status
+fn request(bucket: &Bucket, signer: &Signer, method: &str, path: &str, body: &[u8]) -> io::Result<Vec<u8>> {
+    let payload_hash = hex(&sha256(body));
+    let time = amz_date(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs());
+    let authorization = signer.authorization(method, path, &[("host", &bucket.authority), ("x-amz-content-sha256", &payload_hash), ("x-amz-date", &time)]);
+    let mut stream = TcpStream::connect(&bucket.authority)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: zenql/{}\r\nx-amz-content-sha256: {}\r\nx-amz-date: {}\r\nAuthorization: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        method, path, bucket.authority, env!("CARGO_PKG_VERSION"), payload_hash, time, authorization, body.len()
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+    let mut response = vec![];
+    stream.read_to_end(&mut response)?;
+
+    let Some(end) = response.windows(4).position(|window| window == b"\r\n\r\n") else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} {} got a truncated response", method, path)));
+    };
+    let head = String::from_utf8_lossy(&response[..end]).to_string();
+    let mut body = response.split_off(end + 4);
+    let status_line = head.lines().next().unwrap_or_default();
+    if !status_line.starts_with("HTTP/1.1 2") && !status_line.starts_with("HTTP/1.0 2") {
+        // S3 explains itself in an XML body, short enough to pass on
+        let reason = String::from_utf8_lossy(&body[..body.len().min(512)]).to_string();
+        return Err(io::Error::other(format!("{} {} got {:?} {}", method, path, status_line, reason.trim())));
+    }
+    for line in head.lines().skip(1) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        match name.trim().to_ascii_lowercase().as_str() {
+            "transfer-encoding" if !value.trim().eq_ignore_ascii_case("identity") => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} {} got a {} encoded response, which isn't supported", method, path, value.trim())));
+            },
+            "content-length" => match value.trim().parse::<usize>() {
+                Ok(length) if length <= body.len() => body.truncate(length),
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} {} got a truncated response", method, path))),
+            },
+            _ => {},
+        }
+    }
+    Ok(body)
+}
+
+// Percent-encodes all but unreserved characters and slashes, as S3 expects of paths
+fn uri_encode(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => encoded.push(b as char),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+// `secs` since the epoch as the basic ISO 8601 form signatures use, 20130524T000000Z
+fn amz_date(secs: u64) -> String {
+    // Civil date from days since the epoch, counting in 400-year eras that start on March 1st
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    let time = secs % 86_400;
+    format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z", year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut state: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut words = [0u32; 64];
+        for (word, bytes) in words.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().expect("4 bytes"));
+        }
+        for i in 16..64 {
+            let s0 = words[i - 15].rotate_right(7) ^ words[i - 15].rotate_right(18) ^ (words[i - 15] >> 3);
+            let s1 = words[i - 2].rotate_right(17) ^ words[i - 2].rotate_right(19) ^ (words[i - 2] >> 10);
+            words[i] = words[i - 16].wrapping_add(s0).wrapping_add(words[i - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for (word, k) in words.into_iter().zip(ROUND_CONSTANTS) {
+            let choice = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25)).wrapping_add(choice).wrapping_add(k).wrapping_add(word);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = (a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22)).wrapping_add(majority);
+            (h, g, f, e, d, c, b, a) = (g, f, e, d.wrapping_add(t1), c, b, a, t1.wrapping_add(t2));
+        }
+        for (state, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+    let mut digest = [0; 32];
+    for (bytes, word) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    match key.len() > block.len() {
+        true => block[..32].copy_from_slice(&sha256(key)),
+        false => block[..key.len()].copy_from_slice(key),
+    }
+    let inner: Vec<u8> = block.iter().map(|b| b ^ 0x36).chain(data.iter().copied()).collect();
+    let outer: Vec<u8> = block.iter().map(|b| b ^ 0x5c).chain(sha256(&inner)).collect();
+    sha256(&outer)
+}
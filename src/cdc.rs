@@ -0,0 +1,169 @@
+// Change data capture: CDC turns a client connection into a feed of the keyspace for systems that mirror it,
+// such as search indexes and warehouses, without them having to pretend to be a replica. The client gets a
+// snapshot of every key, then every change made after it, in order, as RESP arrays:
+//
+//   snapshot <time>                                  the snapshot starts
+//   set <time> <key> <type> <value> <deadline>       a key's value, in the snapshot or as written since
+//   expire <time> <key> <deadline>                   a key's deadline was set or cleared, its value kept
+//   del|expired|evicted <time> <key>                 a key went away
+//   synced <time> <keys>                             the snapshot is over, changes follow
+//   ping <time>                                      nothing changed for a while
+//
+// Times and deadlines are UNIX milliseconds, a deadline of -1 meaning none. The type is as TYPE names it; a
+// hash's value is an array of its fields and their values, a JSON document's its text, and the other types
+// that aren't strings are sent in their snapshot encoding. A set or expire carries the key's value or deadline
+// as of when it's sent, which may already include later changes; a key gone by then is skipped, its del
+// follows.
+//
+// The snapshot is taken like SNAPSHOT EXPORT's while writes carry on, and sent as it's read. A consumer falling
+// more than CDC_BACKLOG changes behind is disconnected rather than buffered for without limit; it has to
+// start over with a fresh snapshot.
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::Result;
+use bytes::Bytes;
+use tokio::sync::mpsc;
+use crate::events::KeyEventKind;
+use crate::glob;
+use crate::log::log_warn;
+use crate::resp::{RespHandler, Value};
+use crate::server::Server;
+use crate::stats::{self, SERVER_STATS};
+use crate::storage::StoredValue;
+
+// Changes waiting to be sent before the consumer is given up on
+const CDC_BACKLOG: usize = 1_000_000;
+// Snapshot frames read ahead of the socket
+const SNAPSHOT_AHEAD: usize = 1024;
+// Frames queued before they're flushed
+const FLUSH_AT: usize = 64 * 1024;
+// Quiet time before a ping is sent
+const CDC_PING: Duration = Duration::from_secs(10);
+
+// What a CDC command asked for, left on the session for the connection to act on once the reply is sent
+#[derive(Debug, Clone)]
+pub struct Follow {
+    pub pattern: Option<Bytes>, // Only keys matching this glob
+}
+
+// Feeds the keyspace to the client until it disconnects or falls too far behind
+pub async fn stream(handler: &mut RespHandler, server: &Server, follow: Follow) -> Result<()> {
+    let storage = Arc::clone(&server.storage);
+    let wanted = move |key: &Bytes| follow.pattern.as_ref().is_none_or(|pattern| glob::matches(pattern, key));
+    handler.write_value(frame("snapshot", storage.now_ms(), vec![])).await?;
+
+    let (tx, mut frames) = mpsc::channel(SNAPSHOT_AHEAD);
+    let snapshot = tokio::task::spawn_blocking({
+        let storage = Arc::clone(&storage);
+        let wanted = wanted.clone();
+        move || {
+            let time = storage.now_ms();
+            storage.snapshot_and_subscribe(|key, value, expires_at| {
+                if !wanted(key) {
+                    return Ok(());
+                }
+                tx.blocking_send(set_frame(time, key, value, expires_at)).map_err(|_| std::io::Error::other("consumer went away"))
+            })
+        }
+    });
+    let mut sent = 0;
+    loop {
+        tokio::select! {
+            next = frames.recv() => {
+                let Some(next) = next else {
+                    break;
+                };
+                handler.queue_value(next);
+                sent += 1;
+                while handler.pending_output() < FLUSH_AT {
+                    let Ok(next) = frames.try_recv() else {
+                        break;
+                    };
+                    handler.queue_value(next);
+                    sent += 1;
+                }
+                handler.flush().await?;
+            },
+            read = handler.read_value() => {
+                if read?.is_none() {
+                    return Ok(());
+                }
+            },
+        }
+    }
+    let (mut events, _) = snapshot.await??;
+    stats::incr(&SERVER_STATS.cdc_frames_sent, sent);
+    handler.write_value(frame("synced", storage.now_ms(), vec![Value::Integer(sent as i64)])).await?;
+
+    let mut ping = tokio::time::interval(CDC_PING);
+    ping.reset();
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let Some(event) = event else {
+                    return Ok(());
+                };
+                if events.len() > CDC_BACKLOG {
+                    log_warn!("Disconnecting CDC consumer, it's more than {} changes behind", CDC_BACKLOG);
+                    stats::incr(&SERVER_STATS.cdc_consumers_dropped, 1);
+                    return Ok(());
+                }
+                let mut sent = 0;
+                for event in std::iter::once(event).chain(std::iter::from_fn(|| events.try_recv().ok())) {
+                    if !wanted(&event.key) {
+                        continue;
+                    }
+                    let change = match event.kind {
+                        KeyEventKind::Set => match storage.entry(&event.key)? {
+                            Some((value, expires_at)) => set_frame(event.time_ms, &event.key, &value, expires_at),
+                            None => continue,
+                        },
+                        KeyEventKind::Expire => match storage.expires_at(&event.key) {
+                            Some(expires_at) => frame("expire", event.time_ms, vec![Value::BulkString(event.key), deadline(expires_at)]),
+                            None => continue,
+                        },
+                        kind => frame(kind.as_str(), event.time_ms, vec![Value::BulkString(event.key)]),
+                    };
+                    handler.queue_value(change);
+                    sent += 1;
+                    if handler.pending_output() >= FLUSH_AT {
+                        break;
+                    }
+                }
+                stats::incr(&SERVER_STATS.cdc_frames_sent, sent);
+                handler.flush().await?;
+                ping.reset();
+            },
+            _ = ping.tick() => handler.write_value(frame("ping", storage.now_ms(), vec![])).await?,
+            // Whatever else the client sends is ignored
+            read = handler.read_value() => {
+                if read?.is_none() {
+                    return Ok(());
+                }
+            },
+        }
+    }
+}
+
+fn frame(kind: &'static str, time: u64, rest: Vec<Value>) -> Value {
+    let mut items = vec![Value::BulkString(Bytes::from_static(kind.as_bytes())), Value::Integer(time as i64)];
+    items.extend(rest);
+    Value::Array(items)
+}
+
+fn set_frame(time: u64, key: &Bytes, value: &StoredValue, expires_at: Option<u64>) -> Value {
+    let data = match value {
+        StoredValue::Hash(hash) => Value::Array(hash.iter().flat_map(|(field, value)| [field, value]).map(|part| Value::BulkString(part.clone())).collect()),
+        value => Value::BulkString(value.to_bytes()),
+    };
+    frame("set", time, vec![
+        Value::BulkString(key.clone()),
+        Value::BulkString(Bytes::from_static(value.value_type().as_str().as_bytes())),
+        data,
+        deadline(expires_at),
+    ])
+}
+
+fn deadline(expires_at: Option<u64>) -> Value {
+    Value::Integer(expires_at.map_or(-1, |deadline| deadline as i64))
+}
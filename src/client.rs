@@ -0,0 +1,328 @@
+use std::time::Duration;
+use bytes::{Bytes, BytesMut};
+use futures::Stream;
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpStream, ToSocketAddrs};
+use crate::codec::{RespCodec, WriteBuffer};
+use crate::error::{Error, Result};
+use crate::resp::Value;
+
+// Minimum free space made available for each socket read
+const READ_CHUNK: usize = 16 * 1024;
+
+// An async connection to a zenql (or any RESP2) server. Error replies come back as Error::Reply, so `?`
+// works on both transport and command failures.
+pub struct Client {
+    stream: TcpStream,
+    codec: RespCodec,
+    read_buf: BytesMut,
+    write_buf: WriteBuffer,
+}
+
+impl Client {
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Client> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(Client {
+            stream,
+            codec: RespCodec::default(),
+            read_buf: BytesMut::with_capacity(READ_CHUNK),
+            write_buf: WriteBuffer::new(),
+        })
+    }
+
+    // Sends one command and waits for its reply
+    pub async fn call<A: AsRef<[u8]>>(&mut self, args: impl IntoIterator<Item = A>) -> Result<Value> {
+        self.queue(command(args));
+        self.flush().await?;
+        check(self.read_reply().await?)
+    }
+
+    // Sends every command of the pipeline in one write, then collects their replies in order. Error replies
+    // are returned as Value::Error rather than failing the whole batch.
+    pub async fn execute(&mut self, pipeline: Pipeline) -> Result<Vec<Value>> {
+        let count = pipeline.commands.len();
+        for command in pipeline.commands {
+            self.queue(command);
+        }
+        self.flush().await?;
+        let mut replies = Vec::with_capacity(count);
+        for _ in 0..count {
+            replies.push(self.read_reply().await?);
+        }
+        Ok(replies)
+    }
+
+    pub async fn ping(&mut self) -> Result<()> {
+        self.call(["PING"]).await.map(|_| ())
+    }
+
+    pub async fn auth(&mut self, password: impl AsRef<[u8]>) -> Result<()> {
+        self.call([b"AUTH".as_slice(), password.as_ref()]).await.map(|_| ())
+    }
+
+    pub async fn get(&mut self, key: impl AsRef<[u8]>) -> Result<Option<Bytes>> {
+        match self.call([b"GET".as_slice(), key.as_ref()]).await? {
+            Value::BulkString(value) => Ok(Some(value)),
+            Value::Null => Ok(None),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub async fn set(&mut self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> Result<()> {
+        self.call([b"SET".as_slice(), key.as_ref(), value.as_ref()]).await.map(|_| ())
+    }
+
+    // Sets a key that expires after `ttl`, at millisecond precision
+    pub async fn set_ex(&mut self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>, ttl: Duration) -> Result<()> {
+        let millis = ttl.as_millis().to_string();
+        self.call([b"SET".as_slice(), key.as_ref(), value.as_ref(), b"PX", millis.as_bytes()]).await.map(|_| ())
+    }
+
+    // Returns how many of the keys existed
+    pub async fn del<A: AsRef<[u8]>>(&mut self, keys: impl IntoIterator<Item = A>) -> Result<i64> {
+        let args = std::iter::once(Bytes::from_static(b"DEL")).chain(keys.into_iter().map(|key| Bytes::copy_from_slice(key.as_ref())));
+        integer(self.call(args).await?)
+    }
+
+    // True when the key exists and its TTL was set
+    pub async fn expire(&mut self, key: impl AsRef<[u8]>, ttl: Duration) -> Result<bool> {
+        let millis = ttl.as_millis().to_string();
+        Ok(integer(self.call([b"PEXPIRE".as_slice(), key.as_ref(), millis.as_bytes()]).await?)? == 1)
+    }
+
+    pub async fn persist(&mut self, key: impl AsRef<[u8]>) -> Result<bool> {
+        Ok(integer(self.call([b"PERSIST".as_slice(), key.as_ref()]).await?)? == 1)
+    }
+
+    // None when the key doesn't exist, Some(None) when it never expires
+    pub async fn ttl(&mut self, key: impl AsRef<[u8]>) -> Result<Option<Option<Duration>>> {
+        Ok(match integer(self.call([b"PTTL".as_slice(), key.as_ref()]).await?)? {
+            -2 => None,
+            -1 => Some(None),
+            millis => Some(Some(Duration::from_millis(millis.max(0) as u64))),
+        })
+    }
+
+    // Returns the number of clients that received the message
+    pub async fn publish(&mut self, channel: impl AsRef<[u8]>, message: impl AsRef<[u8]>) -> Result<i64> {
+        integer(self.call([b"PUBLISH".as_slice(), channel.as_ref(), message.as_ref()]).await?)
+    }
+
+    // Turns the connection into a subscription to `channels`; see Subscription
+    pub async fn subscribe<A: AsRef<[u8]>>(self, channels: impl IntoIterator<Item = A>) -> Result<Subscription> {
+        let mut subscription = Subscription { client: self };
+        subscription.subscribe(channels).await?;
+        Ok(subscription)
+    }
+
+    // Turns the connection into a CDC change feed of the keys matching `pattern`, or all of them; see ChangeFeed
+    pub async fn cdc(mut self, pattern: Option<&[u8]>) -> Result<ChangeFeed> {
+        let mut args = vec![b"CDC".as_slice()];
+        if let Some(pattern) = pattern {
+            args.extend([b"MATCH".as_slice(), pattern]);
+        }
+        self.call(args).await?;
+        Ok(ChangeFeed { client: self })
+    }
+
+    fn queue(&mut self, command: Value) {
+        self.codec.encode(command, &mut self.write_buf);
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        self.write_buf.write_to(&mut self.stream).await?;
+        Ok(())
+    }
+
+    async fn read_reply(&mut self) -> Result<Value> {
+        loop {
+            if let Some(value) = self.codec.decode_reply(&mut self.read_buf)? {
+                return Ok(value);
+            }
+            self.read_buf.reserve(READ_CHUNK);
+            if self.stream.read_buf(&mut self.read_buf).await? == 0 {
+                return Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into()));
+            }
+        }
+    }
+}
+
+// Commands sent together by Client::execute
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    commands: Vec<Value>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Pipeline::default()
+    }
+
+    pub fn cmd<A: AsRef<[u8]>>(mut self, args: impl IntoIterator<Item = A>) -> Self {
+        self.commands.push(command(args));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+}
+
+// A message published to a subscribed channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub channel: Bytes,
+    pub payload: Bytes,
+}
+
+// A connection in subscribed mode: only (un)subscribing and receiving messages are possible until
+// into_client turns it back into a Client
+pub struct Subscription {
+    client: Client,
+}
+
+impl Subscription {
+    pub async fn subscribe<A: AsRef<[u8]>>(&mut self, channels: impl IntoIterator<Item = A>) -> Result<()> {
+        self.send("SUBSCRIBE", channels).await
+    }
+
+    // Unsubscribes from `channels`, or from everything when empty
+    pub async fn unsubscribe<A: AsRef<[u8]>>(&mut self, channels: impl IntoIterator<Item = A>) -> Result<()> {
+        self.send("UNSUBSCRIBE", channels).await
+    }
+
+    // Waits for the next published message, skipping (un)subscribe confirmations
+    pub async fn next_message(&mut self) -> Result<Message> {
+        loop {
+            if let Event::Message(message) = self.next_event().await? {
+                return Ok(message);
+            }
+        }
+    }
+
+    // Unsubscribes from every channel and returns the plain connection. Messages still in flight are dropped.
+    pub async fn into_client(mut self) -> Result<Client> {
+        self.unsubscribe(Vec::<Bytes>::new()).await?;
+        loop {
+            if let Event::Unsubscribed(0) = self.next_event().await? {
+                return Ok(self.client);
+            }
+        }
+    }
+
+    // The messages as a stream, ending at the first error
+    pub fn into_stream(self) -> impl Stream<Item = Result<Message>> {
+        futures::stream::unfold(Some(self), |subscription| async move {
+            let mut subscription = subscription?;
+            match subscription.next_message().await {
+                Ok(message) => Some((Ok(message), Some(subscription))),
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+
+    async fn send<A: AsRef<[u8]>>(&mut self, kind: &'static str, channels: impl IntoIterator<Item = A>) -> Result<()> {
+        let channels = channels.into_iter().map(|channel| Bytes::copy_from_slice(channel.as_ref()));
+        self.client.queue(command(std::iter::once(Bytes::from_static(kind.as_bytes())).chain(channels)));
+        self.client.flush().await
+    }
+
+    async fn next_event(&mut self) -> Result<Event> {
+        let reply = check(self.client.read_reply().await?)?;
+        let (Value::Array(items) | Value::Push(items)) = reply else {
+            return Err(unexpected(reply));
+        };
+        match <[Value; 3]>::try_from(items) {
+            Ok([Value::BulkString(kind), Value::BulkString(channel), Value::BulkString(payload)]) if kind == "message" => {
+                Ok(Event::Message(Message { channel, payload }))
+            },
+            Ok([Value::BulkString(kind), _, Value::Integer(_)]) if kind == "subscribe" => Ok(Event::Subscribed),
+            Ok([Value::BulkString(kind), _, Value::Integer(count)]) if kind == "unsubscribe" => Ok(Event::Unsubscribed(count)),
+            Ok(items) => Err(unexpected(Value::Array(items.into()))),
+            Err(items) => Err(unexpected(Value::Array(items))),
+        }
+    }
+}
+
+// A frame of a CDC feed, see cdc.rs. Times and deadlines are UNIX milliseconds.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Snapshot { time: i64 }, // Sets of every key follow
+    Set { time: i64, key: Bytes, kind: String, value: Value, expires_at: Option<i64> },
+    Expire { time: i64, key: Bytes, expires_at: Option<i64> }, // The key's value is as it was
+    Removed { time: i64, key: Bytes, reason: String }, // del, expired or evicted
+    Synced { time: i64, keys: i64 }, // The snapshot is over
+}
+
+// A connection turned into a CDC feed: a snapshot of the keyspace, then its changes as they're made
+pub struct ChangeFeed {
+    client: Client,
+}
+
+impl ChangeFeed {
+    // Waits for the next change, skipping pings
+    pub async fn next_change(&mut self) -> Result<Change> {
+        loop {
+            let reply = check(self.client.read_reply().await?)?;
+            let Value::Array(items) = &reply else {
+                return Err(unexpected(reply));
+            };
+            let change = match &items[..] {
+                [Value::BulkString(kind), Value::Integer(_)] if kind == "ping" => continue,
+                [Value::BulkString(kind), Value::Integer(time)] if kind == "snapshot" => Change::Snapshot { time: *time },
+                [Value::BulkString(kind), Value::Integer(time), Value::Integer(keys)] if kind == "synced" => Change::Synced { time: *time, keys: *keys },
+                [Value::BulkString(kind), Value::Integer(time), Value::BulkString(key), Value::Integer(deadline)] if kind == "expire" => {
+                    Change::Expire { time: *time, key: key.clone(), expires_at: (*deadline >= 0).then_some(*deadline) }
+                },
+                [Value::BulkString(kind), Value::Integer(time), Value::BulkString(key)] => {
+                    Change::Removed { time: *time, key: key.clone(), reason: String::from_utf8_lossy(kind).into_owned() }
+                },
+                [Value::BulkString(kind), Value::Integer(time), Value::BulkString(key), Value::BulkString(type_name), value, Value::Integer(deadline)] if kind == "set" => {
+                    Change::Set {
+                        time: *time,
+                        key: key.clone(),
+                        kind: String::from_utf8_lossy(type_name).into_owned(),
+                        value: value.clone(),
+                        expires_at: (*deadline >= 0).then_some(*deadline),
+                    }
+                },
+                _ => return Err(unexpected(reply)),
+            };
+            return Ok(change);
+        }
+    }
+}
+
+// What a subscribed connection receives
+enum Event {
+    Message(Message),
+    Subscribed,
+    Unsubscribed(i64), // Subscriptions left
+}
+
+fn command<A: AsRef<[u8]>>(args: impl IntoIterator<Item = A>) -> Value {
+    Value::Array(args.into_iter().map(|arg| Value::BulkString(Bytes::copy_from_slice(arg.as_ref()))).collect())
+}
+
+fn check(reply: Value) -> Result<Value> {
+    match reply {
+        Value::Error(message) => Err(Error::Reply(message)),
+        other => Ok(other),
+    }
+}
+
+fn integer(reply: Value) -> Result<i64> {
+    match reply {
+        Value::Integer(n) => Ok(n),
+        other => Err(unexpected(other)),
+    }
+}
+
+fn unexpected(reply: Value) -> Error {
+    Error::Protocol(format!("unexpected reply {:?}", reply))
+}
@@ -0,0 +1,152 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use bytes::Bytes;
+use crate::locks;
+use crate::session::Session;
+
+// Connected clients as CLIENT LIST shows them. Each connection registers itself and keeps its entry up to
+// date around every command; the entries are separate from the sessions so listing never has to lock
+// another connection's state.
+#[derive(Default)]
+pub struct Clients {
+    clients: Mutex<BTreeMap<u64, Arc<ClientInfo>>>,
+}
+
+pub struct ClientInfo {
+    pub id: u64,
+    pub addr: String, // Peer address
+    pub laddr: String, // Local address the client connected to
+    pub created: Instant,
+    state: Mutex<ClientState>,
+}
+
+// What changes while a client is connected
+#[derive(Debug, Clone)]
+pub struct ClientState {
+    pub name: Option<Bytes>,
+    pub db: usize,
+    pub protocol: u8,
+    pub subscriptions: usize,
+    pub multi: Option<usize>, // Commands queued while in MULTI
+    pub last_command: String,
+    pub last_interaction: Instant,
+}
+
+// Removes the client from the registry when its connection ends
+pub struct Registration {
+    clients: Arc<Clients>,
+    pub info: Arc<ClientInfo>,
+}
+
+impl Clients {
+    pub fn new() -> Self {
+        Clients::default()
+    }
+
+    pub fn register(self: &Arc<Self>, id: u64, addr: String, laddr: String) -> Registration {
+        let now = Instant::now();
+        let info = Arc::new(ClientInfo {
+            id,
+            addr,
+            laddr,
+            created: now,
+            state: Mutex::new(ClientState {
+                name: None,
+                db: 0,
+                protocol: 2,
+                subscriptions: 0,
+                multi: None,
+                last_command: "NULL".to_string(),
+                last_interaction: now,
+            }),
+        });
+        locks::lock(&self.clients).insert(id, Arc::clone(&info));
+        Registration { clients: Arc::clone(self), info }
+    }
+
+    pub fn get(&self, id: u64) -> Option<Arc<ClientInfo>> {
+        locks::lock(&self.clients).get(&id).cloned()
+    }
+
+    // Every connected client, ordered by id
+    pub fn list(&self) -> Vec<Arc<ClientInfo>> {
+        locks::lock(&self.clients).values().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        locks::lock(&self.clients).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        locks::lock(&self.clients.clients).remove(&self.info.id);
+    }
+}
+
+impl ClientInfo {
+    pub fn state(&self) -> ClientState {
+        locks::lock(&self.state).clone()
+    }
+
+    // Called as a command starts, so CLIENT LIST shows it while it runs
+    pub fn command_started(&self, name: &str) {
+        let mut state = locks::lock(&self.state);
+        state.last_command.clear();
+        state.last_command.extend(name.chars().map(|c| c.to_ascii_lowercase()));
+        state.last_interaction = Instant::now();
+    }
+
+    // Called after each command with the session it may have changed
+    pub fn sync(&self, session: &Session) {
+        let mut state = locks::lock(&self.state);
+        state.name = session.name.clone();
+        state.db = session.db;
+        state.protocol = session.protocol;
+        state.subscriptions = session.subscriptions.len();
+        state.multi = session.multi.as_ref().map(|transaction| transaction.commands.len());
+    }
+
+    // One CLIENT LIST / CLIENT INFO line, a subset of the fields Redis reports
+    pub fn describe(&self) -> String {
+        let state = self.state();
+        let now = Instant::now();
+        let mut flags = String::new();
+        if state.subscriptions > 0 {
+            flags.push('P');
+        }
+        if state.multi.is_some() {
+            flags.push('x');
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+        format!(
+            "id={} addr={} laddr={} name={} age={} idle={} flags={} db={} sub={} psub=0 multi={} cmd={} user=default resp={}",
+            self.id,
+            self.addr,
+            self.laddr,
+            state.name.as_deref().map(String::from_utf8_lossy).unwrap_or_default(),
+            now.duration_since(self.created).as_secs(),
+            now.duration_since(state.last_interaction).as_secs(),
+            flags,
+            state.db,
+            state.subscriptions,
+            state.multi.map_or(-1, |queued| queued as i64),
+            state.last_command,
+            state.protocol,
+        )
+    }
+}
+
+// Formats an address the way Redis does: IPv6 in brackets, IPv4 clients of a dual-stack IPv6 listener
+// (::ffff:a.b.c.d) as plain IPv4
+pub fn format_addr(addr: SocketAddr) -> String {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port()).to_string()
+}
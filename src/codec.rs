@@ -0,0 +1,483 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::io::{self, IoSlice};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use crate::error::{Error, Result};
+use crate::resp::Value;
+
+const MAX_NESTING: usize = 128;
+const MAX_INLINE_LEN: usize = 64 * 1024;
+pub const DEFAULT_MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+pub const DEFAULT_MAX_MULTIBULK_LEN: usize = 1024 * 1024;
+pub const DEFAULT_MAX_QUERY_BUFFER: usize = 1024 * 1024 * 1024;
+// Bulk payloads at least this big are written straight from their Bytes instead of being copied into the write buffer
+const LARGE_PAYLOAD: usize = 16 * 1024;
+// Chunks handed to one vectored write, well under any IOV_MAX
+const MAX_IOVECS: usize = 64;
+
+// Framed RESP codec over a connection's BytesMut buffers.
+//
+// Decoding is two-pass: `check` walks the buffered bytes to find where the next frame ends (or that it is
+// still partial), then the frame is split off and frozen so `parse` can hand out bulk strings as slices of
+// it instead of copying every argument.
+#[derive(Debug, Clone, Default)]
+pub struct RespCodec {
+    pub protocol: u8, // 2 or 3, selects how RESP3-only types are encoded
+    pub limits: Limits,
+}
+
+// Bounds on what the peer may send, so a single client can't make us buffer unbounded input. Going over one
+// is a protocol error.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_bulk_len: usize, // proto-max-bulk-len
+    pub max_multibulk_len: usize, // proto-max-multibulk-len: elements of one array
+    pub max_query_buffer: usize, // client-query-buffer-limit: the size of one frame, complete or not
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_bulk_len: DEFAULT_MAX_BULK_LEN,
+            max_multibulk_len: DEFAULT_MAX_MULTIBULK_LEN,
+            max_query_buffer: DEFAULT_MAX_QUERY_BUFFER,
+        }
+    }
+}
+
+// Result of scanning the buffered bytes for one frame
+enum Scan {
+    Complete(usize), // Position just past the frame
+    Partial(usize), // Minimum buffer length needed before the frame can complete
+}
+
+// Encoded replies waiting to be written. Small values are copied into `buf`; large bulk payloads are kept
+// as their own chunks so a multi-megabyte GET is written from the stored Bytes without another copy. Chunks
+// go out together in vectored writes, so a reply of many large values takes one syscall rather than one
+// each. `buf` is split rather than replaced as replies are written, its allocation is reused once they're
+// gone.
+#[derive(Debug, Default)]
+pub struct WriteBuffer {
+    buf: BytesMut,
+    chunks: VecDeque<Bytes>, // Written before `buf`
+    len: usize, // Of `chunks`
+}
+
+impl WriteBuffer {
+    pub fn new() -> Self {
+        WriteBuffer::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len() + self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty() && self.chunks.is_empty()
+    }
+
+    // Everything encoded so far, in write order
+    pub fn take_chunks(&mut self) -> Vec<Bytes> {
+        self.seal();
+        self.len = 0;
+        self.chunks.drain(..).collect()
+    }
+
+    // Writes everything encoded so far to `out`, returning how many bytes that was. What's left after an
+    // error is still queued.
+    pub async fn write_to<W: AsyncWrite + Unpin + ?Sized>(&mut self, out: &mut W) -> io::Result<usize> {
+        self.seal();
+        let total = self.len;
+        while !self.chunks.is_empty() {
+            let mut slices = [IoSlice::new(&[]); MAX_IOVECS];
+            let count = self.chunks.len().min(MAX_IOVECS);
+            for (slice, chunk) in slices.iter_mut().zip(&self.chunks) {
+                *slice = IoSlice::new(chunk);
+            }
+            let written = out.write_vectored(&slices[..count]).await?;
+            if written == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.consume(written);
+        }
+        Ok(total)
+    }
+
+    // Drops `written` bytes off the front of the chunks
+    fn consume(&mut self, mut written: usize) {
+        self.len -= written;
+        while let Some(front) = self.chunks.front_mut() {
+            if front.len() > written {
+                front.advance(written);
+                break;
+            }
+            written -= front.len();
+            self.chunks.pop_front();
+        }
+    }
+
+    // Moves what's in `buf` to the chunks
+    fn seal(&mut self) {
+        if !self.buf.is_empty() {
+            let chunk = self.buf.split().freeze();
+            self.push_chunk(chunk);
+        }
+    }
+
+    fn push_chunk(&mut self, chunk: Bytes) {
+        self.len += chunk.len();
+        self.chunks.push_back(chunk);
+    }
+
+    fn put_slice(&mut self, src: &[u8]) {
+        self.buf.put_slice(src);
+    }
+
+    fn put_payload(&mut self, payload: Bytes) {
+        if payload.len() < LARGE_PAYLOAD {
+            return self.buf.put_slice(&payload);
+        }
+        self.seal();
+        self.push_chunk(payload);
+    }
+}
+
+impl Write for WriteBuffer {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.buf.put_slice(s.as_bytes());
+        Ok(())
+    }
+}
+
+impl RespCodec {
+    // Decodes the next client request: a multibulk array, or an inline command for anything else.
+    // Returns Ok(None) when `src` only holds a partial frame.
+    pub fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Value>> {
+        match src.first() {
+            None => Ok(None),
+            Some(b'*') => decode_frame(src, &self.limits),
+            Some(_) => decode_inline(src),
+        }
+    }
+
+    // Decodes the next server reply, of any type. Returns Ok(None) when `src` only holds a partial frame.
+    pub fn decode_reply(&mut self, src: &mut BytesMut) -> Result<Option<Value>> {
+        if src.is_empty() {
+            return Ok(None);
+        }
+        decode_frame(src, &self.limits)
+    }
+
+    pub fn encode(&mut self, value: Value, dst: &mut WriteBuffer) {
+        encode_value(value, dst, self.protocol);
+    }
+}
+
+fn decode_frame(src: &mut BytesMut, limits: &Limits) -> Result<Option<Value>> {
+    let len = match check(src, 0, 0, limits)? {
+        Scan::Complete(len) | Scan::Partial(len) if len > limits.max_query_buffer => {
+            return Err(protocol_error("query buffer limit exceeded"));
+        },
+        Scan::Complete(len) => len,
+        Scan::Partial(needed) => {
+            // Grow once to the announced size so big bulk strings are read into a single allocation
+            src.reserve(needed.saturating_sub(src.len()));
+            return Ok(None);
+        },
+    };
+    let frame = src.split_to(len).freeze();
+    let (value, _) = parse(&frame, 0)?;
+    Ok(Some(value))
+}
+
+fn decode_inline(src: &mut BytesMut) -> Result<Option<Value>> {
+    let Some(newline) = src.iter().position(|&b| b == b'\n') else {
+        if src.len() > MAX_INLINE_LEN {
+            return Err(protocol_error("too big inline request"));
+        }
+        return Ok(None);
+    };
+    let line = src.split_to(newline + 1).freeze();
+    let line = line.strip_suffix(b"\n").unwrap_or(&line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let args = split_inline_args(line)?;
+    Ok(Some(Value::Array(args.into_iter().map(|arg| Value::BulkString(Bytes::from(arg))).collect())))
+}
+
+// First pass: validates the frame starting at `pos` and returns the position just past it
+fn check(buf: &[u8], pos: usize, depth: usize, limits: &Limits) -> Result<Scan> {
+    let Some(&prefix) = buf.get(pos) else {
+        return Ok(Scan::Partial(pos + 1));
+    };
+    let Some((line, mut end)) = read_line(buf, pos)? else {
+        return Ok(Scan::Partial(buf.len() + 1));
+    };
+    match prefix {
+        b'+' | b'-' => Ok(Scan::Complete(end)),
+        b':' => {
+            parse_int(line).ok_or_else(|| protocol_error("invalid integer"))?;
+            Ok(Scan::Complete(end))
+        },
+        b'$' | b'=' => {
+            let len = match parse_int(line) {
+                Some(-1) if prefix == b'$' => return Ok(Scan::Complete(end)),
+                Some(n) if n >= 0 && n as u64 <= limits.max_bulk_len as u64 => n as usize,
+                _ => return Err(protocol_error("invalid bulk length")),
+            };
+            if buf.len() < end + len + 2 {
+                return Ok(Scan::Partial(end + len + 2));
+            }
+            if &buf[end + len..end + len + 2] != b"\r\n" {
+                return Err(protocol_error("bulk string not terminated by CRLF"));
+            }
+            if prefix == b'=' && (len < 4 || buf[end + 3] != b':') {
+                return Err(protocol_error("invalid verbatim string"));
+            }
+            Ok(Scan::Complete(end + len + 2))
+        },
+        b'*' | b'~' | b'>' | b'%' => {
+            let len = match parse_int(line) {
+                Some(-1) if prefix == b'*' => return Ok(Scan::Complete(end)),
+                Some(n) if n >= 0 && n as u64 <= limits.max_multibulk_len as u64 => n as usize,
+                _ => return Err(protocol_error("invalid multibulk length")),
+            };
+            if depth >= MAX_NESTING {
+                return Err(protocol_error("too many nested arrays"));
+            }
+            let elements = if prefix == b'%' { len * 2 } else { len };
+            for _ in 0..elements {
+                match check(buf, end, depth + 1, limits)? {
+                    Scan::Complete(next) => end = next,
+                    partial => return Ok(partial),
+                }
+            }
+            Ok(Scan::Complete(end))
+        },
+        b'_' if line.is_empty() => Ok(Scan::Complete(end)),
+        b',' => {
+            parse_double(line).ok_or_else(|| protocol_error("invalid double"))?;
+            Ok(Scan::Complete(end))
+        },
+        b'#' if line == b"t" || line == b"f" => Ok(Scan::Complete(end)),
+        b'(' => {
+            let digits = line.strip_prefix(b"-").unwrap_or(line);
+            if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+                return Err(protocol_error("invalid big number"));
+            }
+            Ok(Scan::Complete(end))
+        },
+        b'_' => Err(protocol_error("invalid null")),
+        b'#' => Err(protocol_error("invalid boolean")),
+        _ => Err(protocol_error(&format!("unexpected type byte '{}'", prefix as char))),
+    }
+}
+
+// Second pass over a frame that `check` accepted; bulk payloads are zero-copy slices of `frame`
+fn parse(frame: &Bytes, pos: usize) -> Result<(Value, usize)> {
+    let (line, end) = read_line(frame, pos)?.ok_or_else(|| protocol_error("truncated frame"))?;
+    let text = || String::from_utf8_lossy(line).into_owned();
+    let int = || parse_int(line).ok_or_else(|| protocol_error("invalid length"));
+    let value = match frame[pos] {
+        b'+' => Value::SimpleString(text()),
+        b'-' => Value::Error(text()),
+        b':' => Value::Integer(int()?),
+        b'$' => match int()? {
+            -1 => Value::Null,
+            len => return Ok((Value::BulkString(frame.slice(end..end + len as usize)), end + len as usize + 2)),
+        },
+        b'=' => {
+            let len = int()? as usize;
+            let format = String::from_utf8_lossy(&frame[end..end + 3]).into_owned();
+            return Ok((Value::Verbatim(format, frame.slice(end + 4..end + len)), end + len + 2));
+        },
+        b'*' | b'~' | b'>' => {
+            let len = int()?;
+            if len == -1 {
+                return Ok((Value::NullArray, end));
+            }
+            let mut items = Vec::with_capacity(len as usize);
+            let mut next = end;
+            for _ in 0..len {
+                let (item, after) = parse(frame, next)?;
+                items.push(item);
+                next = after;
+            }
+            let value = match frame[pos] {
+                b'*' => Value::Array(items),
+                b'~' => Value::Set(items),
+                _ => Value::Push(items),
+            };
+            return Ok((value, next));
+        },
+        b'%' => {
+            let len = int()? as usize;
+            let mut pairs = Vec::with_capacity(len);
+            let mut next = end;
+            for _ in 0..len {
+                let (key, after_key) = parse(frame, next)?;
+                let (value, after_value) = parse(frame, after_key)?;
+                pairs.push((key, value));
+                next = after_value;
+            }
+            return Ok((Value::Map(pairs), next));
+        },
+        b'_' => Value::Null,
+        b',' => Value::Double(parse_double(line).ok_or_else(|| protocol_error("invalid double"))?),
+        b'#' => Value::Boolean(line == b"t"),
+        b'(' => Value::BigNumber(text()),
+        other => return Err(protocol_error(&format!("unexpected type byte '{}'", other as char))),
+    };
+    Ok((value, end))
+}
+
+fn encode_value(value: Value, dst: &mut WriteBuffer, protocol: u8) {
+    if protocol < 3 {
+        return encode_resp2(value.downgrade(), dst);
+    }
+    match value {
+        Value::Array(items) => encode_aggregate(dst, '*', items, protocol),
+        Value::Set(items) => encode_aggregate(dst, '~', items, protocol),
+        Value::Push(items) => encode_aggregate(dst, '>', items, protocol),
+        Value::Map(pairs) => {
+            let _ = write!(dst, "%{}\r\n", pairs.len());
+            for (key, value) in pairs {
+                encode_value(key, dst, protocol);
+                encode_value(value, dst, protocol);
+            }
+        },
+        Value::Null | Value::NullArray => dst.put_slice(b"_\r\n"),
+        Value::Double(d) => {
+            let _ = write!(dst, ",{}\r\n", format_double(d));
+        },
+        Value::Boolean(b) => dst.put_slice(if b { b"#t\r\n" } else { b"#f\r\n" }),
+        Value::BigNumber(n) => {
+            let _ = write!(dst, "({}\r\n", n);
+        },
+        Value::Verbatim(format, payload) => {
+            let _ = write!(dst, "={}\r\n{}:", payload.len() + 4, format);
+            dst.put_payload(payload);
+            dst.put_slice(b"\r\n");
+        },
+        other => encode_resp2(other, dst),
+    }
+}
+
+fn encode_resp2(value: Value, dst: &mut WriteBuffer) {
+    match value {
+        Value::SimpleString(s) => {
+            let _ = write!(dst, "+{}\r\n", s);
+        },
+        Value::Error(s) => {
+            let _ = write!(dst, "-{}\r\n", s);
+        },
+        Value::BulkString(s) => {
+            let _ = write!(dst, "${}\r\n", s.len());
+            dst.put_payload(s);
+            dst.put_slice(b"\r\n");
+        },
+        Value::Integer(i) => {
+            let _ = write!(dst, ":{}\r\n", i);
+        },
+        Value::Array(items) => encode_aggregate(dst, '*', items, 2),
+        Value::NullArray => dst.put_slice(b"*-1\r\n"),
+        Value::Null => dst.put_slice(b"$-1\r\n"),
+        other => encode_resp2(other.downgrade(), dst),
+    }
+}
+
+fn encode_aggregate(dst: &mut WriteBuffer, prefix: char, items: Vec<Value>, protocol: u8) {
+    let _ = write!(dst, "{}{}\r\n", prefix, items.len());
+    for item in items {
+        encode_value(item, dst, protocol);
+    }
+}
+
+pub fn format_double(d: f64) -> String {
+    if d.is_nan() {
+        "nan".to_string()
+    } else if d.is_infinite() {
+        if d > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        d.to_string()
+    }
+}
+
+// Splits on whitespace, honouring "double" (with backslash escapes) and 'single' quoted arguments
+pub fn split_inline_args(line: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut args = vec![];
+    let mut i = 0;
+    while i < line.len() {
+        if line[i].is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+        let mut arg = vec![];
+        match line[i] {
+            quote @ (b'"' | b'\'') => {
+                i += 1;
+                loop {
+                    match line.get(i) {
+                        None => return Err(protocol_error("unbalanced quotes in request")),
+                        Some(&c) if c == quote => {
+                            i += 1;
+                            break;
+                        },
+                        Some(b'\\') if quote == b'"' && i + 1 < line.len() => {
+                            arg.push(match line[i + 1] {
+                                b'n' => b'\n',
+                                b'r' => b'\r',
+                                b't' => b'\t',
+                                c => c,
+                            });
+                            i += 2;
+                        },
+                        Some(&c) => {
+                            arg.push(c);
+                            i += 1;
+                        },
+                    }
+                }
+                if i < line.len() && !line[i].is_ascii_whitespace() {
+                    return Err(protocol_error("unbalanced quotes in request"));
+                }
+            },
+            _ => {
+                while i < line.len() && !line[i].is_ascii_whitespace() {
+                    arg.push(line[i]);
+                    i += 1;
+                }
+            },
+        }
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+// Returns the line after the type byte at `pos` and the position just past its CRLF
+fn read_line(buf: &[u8], pos: usize) -> Result<Option<(&[u8], usize)>> {
+    let start = pos + 1;
+    let rest = buf.get(start..).unwrap_or_default();
+    match rest.windows(2).position(|w| w == b"\r\n") {
+        Some(len) => Ok(Some((&rest[..len], start + len + 2))),
+        None if rest.len() > MAX_INLINE_LEN => Err(protocol_error("too big line")),
+        None => Ok(None),
+    }
+}
+
+fn parse_int(line: &[u8]) -> Option<i64> {
+    std::str::from_utf8(line).ok()?.parse::<i64>().ok()
+}
+
+fn parse_double(line: &[u8]) -> Option<f64> {
+    match line {
+        b"inf" => Some(f64::INFINITY),
+        b"-inf" => Some(f64::NEG_INFINITY),
+        _ => std::str::from_utf8(line).ok()?.parse::<f64>().ok(),
+    }
+}
+
+fn protocol_error(message: &str) -> Error {
+    Error::Protocol(message.to_string())
+}
+
@@ -0,0 +1,294 @@
+use bytes::Bytes;
+use crate::bloom::{BloomFilter, DEFAULT_CAPACITY, DEFAULT_ERROR_RATE, DEFAULT_EXPANSION};
+use crate::error::{Error, Result};
+use crate::resp::Value;
+use crate::storage::Storage;
+use super::{ok, parse_int, unpack_bulk_str, unpack_bytes, Command, Context, Flags, KeySpec, Registry, Reply};
+
+pub fn register(registry: &mut Registry) {
+    registry.add(BfReserve);
+    registry.add(BfAdd { multi: false });
+    registry.add(BfAdd { multi: true });
+    registry.add(BfInsert);
+    registry.add(BfExists { multi: false });
+    registry.add(BfExists { multi: true });
+    registry.add(BfCard);
+    registry.add(BfInfo);
+}
+
+// How a filter is made: error rate, capacity and expansion, 0 for a non-scaling one
+type Settings = (f64, u64, u64);
+
+const DEFAULT_SETTINGS: Settings = (DEFAULT_ERROR_RATE, DEFAULT_CAPACITY, DEFAULT_EXPANSION);
+
+fn parse_error_rate(arg: &Value) -> Result<f64> {
+    match unpack_bulk_str(arg)?.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate < 1.0 => Ok(rate),
+        _ => Err(Error::reply("ERR the error rate must be between 0 and 1")),
+    }
+}
+
+fn parse_positive(arg: &Value, what: &str) -> Result<u64> {
+    u64::try_from(parse_int(arg)?).ok().filter(|&n| n > 0).ok_or_else(|| Error::Reply(format!("ERR the {} must be positive", what)))
+}
+
+// Adds items to a filter, making it with `create` if it's missing and that's given: 1 for each item added, 0
+// for each that may have been already, or why it couldn't be
+fn add_items(storage: &Storage, key: &Bytes, items: &[Value], create: Option<Settings>) -> Result<Vec<Value>> {
+    let items = items.iter().map(unpack_bytes).collect::<Result<Vec<_>>>()?;
+    storage.update::<BloomFilter, _>(key, |filter| {
+        let created = filter.is_none();
+        if created {
+            let Some((error_rate, capacity, expansion)) = create else {
+                return (Err(Error::reply("ERR not found")), false);
+            };
+            match BloomFilter::new(error_rate, capacity, expansion) {
+                Ok(created) => *filter = Some(created),
+                Err(e) => return (Err(Error::Reply(format!("ERR {}", e))), false),
+            }
+        }
+        let filter = filter.as_mut().expect("created above");
+        let replies: Vec<Value> = items.iter().map(|item| match filter.insert(item) {
+            Ok(added) => Value::Integer(added as i64),
+            Err(e) => Value::Error(format!("ERR {}", e)),
+        }).collect();
+        // A filter made here is written even when nothing could go in it
+        let changed = created || replies.contains(&Value::Integer(1));
+        (Ok(replies), changed)
+    })?
+}
+
+// BF.RESERVE key error_rate capacity [EXPANSION expansion] [NONSCALING]: an empty filter for `capacity` items
+// wrongly found about `error_rate` of the time. It grows by `expansion`, 2 by default, once full, unless it's
+// NONSCALING.
+struct BfReserve;
+
+impl Command for BfReserve {
+    fn name(&self) -> &'static str {
+        "bf.reserve"
+    }
+
+    fn arity(&self) -> i64 {
+        -4
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let (error_rate, capacity) = (parse_error_rate(&args[1])?, parse_positive(&args[2], "capacity")?);
+        let (mut expansion, mut scaling) = (DEFAULT_EXPANSION, true);
+        let mut i = 3;
+        while i < args.len() {
+            match unpack_bulk_str(&args[i])?.to_lowercase().as_str() {
+                "expansion" if i + 1 < args.len() => {
+                    expansion = parse_positive(&args[i + 1], "expansion")?;
+                    i += 1;
+                },
+                "nonscaling" => scaling = false,
+                _ => return Err(Error::Syntax),
+            }
+            i += 1;
+        }
+        let filter = BloomFilter::new(error_rate, capacity, if scaling { expansion } else { 0 }).map_err(|e| Error::Reply(format!("ERR {}", e)))?;
+        let created = ctx.server.storage.update::<BloomFilter, _>(unpack_bytes(&args[0])?, |slot| match slot {
+            Some(_) => (false, false),
+            None => {
+                *slot = Some(filter);
+                (true, true)
+            },
+        })?;
+        match created {
+            true => Ok(ok()),
+            false => Err(Error::reply("ERR item exists")),
+        }
+    }
+}
+
+// BF.ADD key item / BF.MADD key item [item ...]: adds the items, making a filter with the defaults if the key
+// is missing. 1 for each item added, 0 for each that may have been already.
+struct BfAdd {
+    multi: bool,
+}
+
+impl Command for BfAdd {
+    fn name(&self) -> &'static str {
+        if self.multi { "bf.madd" } else { "bf.add" }
+    }
+
+    fn arity(&self) -> i64 {
+        if self.multi { -3 } else { 3 }
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let mut replies = add_items(&ctx.server.storage, unpack_bytes(&args[0])?, &args[1..], Some(DEFAULT_SETTINGS))?;
+        match self.multi {
+            true => Ok(Value::Array(replies).into()),
+            false => match replies.remove(0) {
+                Value::Error(e) => Err(Error::Reply(e)),
+                reply => Ok(reply.into()),
+            },
+        }
+    }
+}
+
+// BF.INSERT key [CAPACITY capacity] [ERROR error_rate] [EXPANSION expansion] [NOCREATE] [NONSCALING] ITEMS item
+// [item ...]: BF.MADD, making a missing filter as the options say, or failing with NOCREATE
+struct BfInsert;
+
+impl Command for BfInsert {
+    fn name(&self) -> &'static str {
+        "bf.insert"
+    }
+
+    fn arity(&self) -> i64 {
+        -4
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let (mut error_rate, mut capacity, mut expansion) = DEFAULT_SETTINGS;
+        let (mut create, mut scaling) = (true, true);
+        let mut i = 1;
+        let items = loop {
+            let Some(option) = args.get(i) else {
+                return Err(Error::Syntax);
+            };
+            match (unpack_bulk_str(option)?.to_lowercase().as_str(), args.get(i + 1)) {
+                ("capacity", Some(arg)) => capacity = parse_positive(arg, "capacity")?,
+                ("error", Some(arg)) => error_rate = parse_error_rate(arg)?,
+                ("expansion", Some(arg)) => expansion = parse_positive(arg, "expansion")?,
+                ("nocreate", _) => {
+                    create = false;
+                    i += 1;
+                    continue;
+                },
+                ("nonscaling", _) => {
+                    scaling = false;
+                    i += 1;
+                    continue;
+                },
+                ("items", _) if i + 1 < args.len() => break &args[i + 1..],
+                _ => return Err(Error::Syntax),
+            }
+            i += 2;
+        };
+        let settings = create.then_some((error_rate, capacity, if scaling { expansion } else { 0 }));
+        let replies = add_items(&ctx.server.storage, unpack_bytes(&args[0])?, items, settings)?;
+        Ok(Value::Array(replies).into())
+    }
+}
+
+// BF.EXISTS key item / BF.MEXISTS key item [item ...]: 1 for each item that may have been added, 0 for those
+// that certainly weren't
+struct BfExists {
+    multi: bool,
+}
+
+impl Command for BfExists {
+    fn name(&self) -> &'static str {
+        if self.multi { "bf.mexists" } else { "bf.exists" }
+    }
+
+    fn arity(&self) -> i64 {
+        if self.multi { -3 } else { 3 }
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let items = args[1..].iter().map(unpack_bytes).collect::<Result<Vec<_>>>()?;
+        let found = ctx.server.storage.read::<BloomFilter, _>(unpack_bytes(&args[0])?, |filter| items.iter().map(|item| filter.contains(item)).collect::<Vec<_>>())?;
+        let found = found.unwrap_or_else(|| vec![false; items.len()]);
+        let mut replies: Vec<Value> = found.into_iter().map(|found| Value::Integer(found as i64)).collect();
+        match self.multi {
+            true => Ok(Value::Array(replies).into()),
+            false => Ok(replies.remove(0).into()),
+        }
+    }
+}
+
+// BF.CARD key: how many items were added, 0 for a missing key
+struct BfCard;
+
+impl Command for BfCard {
+    fn name(&self) -> &'static str {
+        "bf.card"
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let count = ctx.server.storage.read::<BloomFilter, _>(unpack_bytes(&args[0])?, BloomFilter::len)?;
+        Ok(Value::Integer(count.unwrap_or(0) as i64).into())
+    }
+}
+
+// BF.INFO key: the filter's capacity, size in bytes, how many filters it stacks, items added and expansion
+struct BfInfo;
+
+impl Command for BfInfo {
+    fn name(&self) -> &'static str {
+        "bf.info"
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let name = |name: &str| Value::SimpleString(name.to_string());
+        let info = ctx.server.storage.read::<BloomFilter, _>(unpack_bytes(&args[0])?, |filter| vec![
+            (name("Capacity"), Value::Integer(filter.capacity() as i64)),
+            (name("Size"), Value::Integer(filter.size() as i64)),
+            (name("Number of filters"), Value::Integer(filter.filters() as i64)),
+            (name("Number of items inserted"), Value::Integer(filter.len() as i64)),
+            (name("Expansion rate"), if filter.expansion == 0 { Value::Null } else { Value::Integer(filter.expansion as i64) }),
+        ])?;
+        Ok(Value::Map(info.ok_or_else(|| Error::reply("ERR not found"))?).into())
+    }
+}
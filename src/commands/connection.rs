@@ -0,0 +1,254 @@
+use bytes::Bytes;
+use crate::error::{Error, Result};
+use crate::resp::Value;
+use super::{ok, parse_int, unpack_bulk_str, unpack_bytes, Command, Context, Flags, Registry, Reply};
+
+pub fn register(registry: &mut Registry) {
+    registry.add(Ping);
+    registry.add(Echo);
+    registry.add(Hello);
+    registry.add(Auth);
+    registry.add(Client);
+    registry.add(Select);
+}
+
+// PING [message]
+struct Ping;
+
+impl Command for Ping {
+    fn name(&self) -> &'static str {
+        "ping"
+    }
+
+    fn arity(&self) -> i64 {
+        -1
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::FAST | Flags::SUBSCRIBED
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let message = args.first().map(unpack_bytes).transpose()?.cloned();
+        // Subscribed RESP2 clients can't tell a reply from a message, so they get the multi-bulk form
+        if ctx.session.protocol == 2 && ctx.session.subscribed() {
+            let message = Value::BulkString(message.unwrap_or_default());
+            return Ok(Value::Array(vec![Value::BulkString("pong".into()), message]).into());
+        }
+        match message {
+            Some(message) => Ok(Value::BulkString(message).into()),
+            None => Ok(Value::SimpleString("PONG".to_string()).into()),
+        }
+    }
+}
+
+struct Echo;
+
+impl Command for Echo {
+    fn name(&self) -> &'static str {
+        "echo"
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::FAST
+    }
+
+    fn execute(&self, _ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        Ok(Value::BulkString(unpack_bytes(&args[0])?.clone()).into())
+    }
+}
+
+// HELLO [protover]: switches the connection's protocol and describes the server
+struct Hello;
+
+impl Command for Hello {
+    fn name(&self) -> &'static str {
+        "hello"
+    }
+
+    fn arity(&self) -> i64 {
+        -1
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::FAST | Flags::NO_AUTH
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        if let Some(version) = args.first() {
+            match parse_int(version) {
+                Ok(version @ (2 | 3)) => ctx.session.protocol = version as u8,
+                Ok(_) => return Err(Error::reply("NOPROTO unsupported protocol version")),
+                Err(_) => return Err(Error::reply("ERR Protocol version is not an integer or out of range")),
+            }
+        }
+        if let Some(option) = args.get(1) {
+            return Err(Error::reply(format!("ERR Syntax error in HELLO option '{}'", unpack_bulk_str(option)?)));
+        }
+
+        let field = |name: &str| Value::BulkString(Bytes::copy_from_slice(name.as_bytes()));
+        Ok(Value::Map(vec![
+            (field("server"), field("zenql")),
+            (field("version"), field(env!("CARGO_PKG_VERSION"))),
+            (field("proto"), Value::Integer(ctx.session.protocol as i64)),
+            (field("id"), Value::Integer(ctx.session.id as i64)),
+            (field("mode"), field("standalone")),
+            (field("role"), field("master")),
+            (field("modules"), Value::Array(vec![])),
+        ]).into())
+    }
+}
+
+// AUTH [username] password: the default user, with requirepass, or a tenant, see tenant.rs
+struct Auth;
+
+impl Command for Auth {
+    fn name(&self) -> &'static str {
+        "auth"
+    }
+
+    fn arity(&self) -> i64 {
+        -2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::FAST | Flags::NO_AUTH
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let config = ctx.server.config();
+        let Some(requirepass) = &config.requirepass else {
+            return Err(Error::reply(
+                "ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?"
+            ));
+        };
+        let (username, password) = match args {
+            [password] => ("default", unpack_bytes(password)?),
+            [username, password] => (unpack_bulk_str(username)?, unpack_bytes(password)?),
+            _ => return Err(Error::Syntax),
+        };
+        let tenant = match username {
+            "default" if password == requirepass.as_bytes() => None,
+            _ => match config.tenants.iter().find(|tenant| tenant.name == username && tenant.password.as_bytes() == password) {
+                Some(tenant) => Some(tenant.name.clone()),
+                None => return Err(Error::reply("WRONGPASS invalid username-password pair or user is disabled.")),
+            },
+        };
+        ctx.session.authenticated = true;
+        ctx.session.tenant = tenant;
+        Ok(ok())
+    }
+}
+
+// CLIENT ID | SETNAME name | GETNAME
+struct Client;
+
+impl Command for Client {
+    fn name(&self) -> &'static str {
+        "client"
+    }
+
+    fn arity(&self) -> i64 {
+        -2
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let subcommand = unpack_bulk_str(&args[0])?.to_lowercase();
+        match (subcommand.as_str(), args.len()) {
+            ("id", 1) => Ok(Value::Integer(ctx.session.id as i64).into()),
+            ("getname", 1) => Ok(ctx.session.name.clone().map(Value::BulkString).unwrap_or(Value::Null).into()),
+            ("setname", 2) => {
+                let name = unpack_bytes(&args[1])?;
+                if name.iter().any(|&b| !(b'!'..=b'~').contains(&b)) {
+                    return Err(Error::reply("ERR Client names cannot contain spaces, newlines or special characters."));
+                }
+                ctx.session.name = if name.is_empty() { None } else { Some(name.clone()) };
+                Ok(ok())
+            },
+            ("info", 1) => {
+                let info = ctx.server.clients.get(ctx.session.id).map(|client| client.describe()).unwrap_or_default();
+                Ok(Value::BulkString(format!("{}\n", info).into()).into())
+            },
+            ("list", _) => client_list(ctx, &args[1..]),
+            ("id" | "getname" | "setname" | "info", _) => Err(Error::WrongArity(format!("client|{}", subcommand))),
+            _ => Err(Error::UnknownSubcommand("client".to_string(), subcommand)),
+        }
+    }
+}
+
+// CLIENT LIST [TYPE normal|pubsub] [ID id ...]
+fn client_list(ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+    let mut kind = None;
+    let mut ids = None;
+    let mut i = 0;
+    while i < args.len() {
+        match unpack_bulk_str(&args[i])?.to_lowercase().as_str() {
+            "type" if i + 1 < args.len() => {
+                let name = unpack_bulk_str(&args[i + 1])?.to_lowercase();
+                match name.as_str() {
+                    "normal" | "pubsub" | "master" | "replica" => kind = Some(name),
+                    _ => return Err(Error::Reply(format!("ERR Unknown client type '{}'", name))),
+                }
+                i += 2;
+            },
+            "id" if i + 1 < args.len() => {
+                let mut wanted = vec![];
+                for id in &args[i + 1..] {
+                    match parse_int(id) {
+                        Ok(id) if id > 0 => wanted.push(id as u64),
+                        _ => return Err(Error::reply("ERR Invalid client ID")),
+                    }
+                }
+                ids = Some(wanted);
+                i = args.len();
+            },
+            _ => return Err(Error::Syntax),
+        }
+    }
+
+    let mut list = String::new();
+    for client in ctx.server.clients.list() {
+        if ids.as_ref().is_some_and(|ids| !ids.contains(&client.id)) {
+            continue;
+        }
+        let pubsub = client.state().subscriptions > 0;
+        match kind.as_deref() {
+            Some("normal") if pubsub => continue,
+            Some("pubsub") if !pubsub => continue,
+            Some("master" | "replica") => continue, // No replication
+            _ => {},
+        }
+        list.push_str(&client.describe());
+        list.push('\n');
+    }
+    Ok(Value::BulkString(list.into()).into())
+}
+
+// SELECT index, there is only database 0
+struct Select;
+
+impl Command for Select {
+    fn name(&self) -> &'static str {
+        "select"
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::FAST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        if parse_int(&args[0])? != 0 {
+            return Err(Error::reply("ERR DB index is out of range"));
+        }
+        ctx.session.db = 0;
+        Ok(ok())
+    }
+}
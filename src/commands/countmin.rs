@@ -0,0 +1,227 @@
+use crate::countmin::CountMinSketch;
+use crate::error::{Error, Result};
+use crate::resp::Value;
+use super::{ok, parse_int, unpack_bulk_str, unpack_bytes, Command, Context, Flags, KeySpec, Registry, Reply};
+
+pub fn register(registry: &mut Registry) {
+    registry.add(CmsInit { by_prob: false });
+    registry.add(CmsInit { by_prob: true });
+    registry.add(CmsIncrBy);
+    registry.add(CmsQuery);
+    registry.add(CmsMerge);
+    registry.add(CmsInfo);
+}
+
+fn parse_count(arg: &Value, what: &str) -> Result<u64> {
+    u64::try_from(parse_int(arg)?).map_err(|_| Error::Reply(format!("ERR the {} must not be negative", what)))
+}
+
+fn parse_fraction(arg: &Value, what: &str) -> Result<f64> {
+    match unpack_bulk_str(arg)?.parse::<f64>() {
+        Ok(n) if n > 0.0 && n < 1.0 => Ok(n),
+        _ => Err(Error::Reply(format!("ERR the {} must be between 0 and 1", what))),
+    }
+}
+
+fn not_found() -> Error {
+    Error::reply("ERR CMS: key does not exist")
+}
+
+// CMS.INITBYDIM key width depth / CMS.INITBYPROB key error probability: an empty sketch, of `depth` rows of
+// `width` counters, or sized so counts are over by at most `error` of the total with probability
+// 1 - `probability`
+struct CmsInit {
+    by_prob: bool,
+}
+
+impl Command for CmsInit {
+    fn name(&self) -> &'static str {
+        if self.by_prob { "cms.initbyprob" } else { "cms.initbydim" }
+    }
+
+    fn arity(&self) -> i64 {
+        4
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let sketch = match self.by_prob {
+            true => CountMinSketch::with_error(parse_fraction(&args[1], "error")?, parse_fraction(&args[2], "probability")?),
+            false => CountMinSketch::new(parse_count(&args[1], "width")?, parse_count(&args[2], "depth")?),
+        };
+        let sketch = sketch.map_err(|e| Error::Reply(format!("ERR CMS: {}", e)))?;
+        let created = ctx.server.storage.update::<CountMinSketch, _>(unpack_bytes(&args[0])?, |slot| match slot {
+            Some(_) => (false, false),
+            None => {
+                *slot = Some(sketch);
+                (true, true)
+            },
+        })?;
+        match created {
+            true => Ok(ok()),
+            false => Err(Error::reply("ERR CMS: key already exists")),
+        }
+    }
+}
+
+// CMS.INCRBY key item increment [item increment ...]: counts each item `increment` more times, replying with
+// their counts after
+struct CmsIncrBy;
+
+impl Command for CmsIncrBy {
+    fn name(&self) -> &'static str {
+        "cms.incrby"
+    }
+
+    fn arity(&self) -> i64 {
+        -4
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        if !(args.len() - 1).is_multiple_of(2) {
+            return Err(Error::WrongArity(self.name().to_string()));
+        }
+        let increments = args[1..].chunks(2)
+            .map(|pair| Ok((unpack_bytes(&pair[0])?, parse_count(&pair[1], "increment")?)))
+            .collect::<Result<Vec<_>>>()?;
+        let counts = ctx.server.storage.update::<CountMinSketch, _>(unpack_bytes(&args[0])?, |sketch| match sketch {
+            Some(sketch) => {
+                let counts: Vec<Value> = increments.iter().map(|&(item, by)| Value::Integer(sketch.increment(item, by) as i64)).collect();
+                (Ok(counts), true)
+            },
+            None => (Err(not_found()), false),
+        })??;
+        Ok(Value::Array(counts).into())
+    }
+}
+
+// CMS.QUERY key item [item ...]: how many times each item may have been counted
+struct CmsQuery;
+
+impl Command for CmsQuery {
+    fn name(&self) -> &'static str {
+        "cms.query"
+    }
+
+    fn arity(&self) -> i64 {
+        -3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let items = args[1..].iter().map(unpack_bytes).collect::<Result<Vec<_>>>()?;
+        let counts = ctx.server.storage.read::<CountMinSketch, _>(unpack_bytes(&args[0])?, |sketch| {
+            items.iter().map(|item| Value::Integer(sketch.query(item) as i64)).collect()
+        })?;
+        Ok(Value::Array(counts.ok_or_else(not_found)?).into())
+    }
+}
+
+// CMS.MERGE destination numkeys source [source ...] [WEIGHTS weight [weight ...]]: sets the destination, which
+// must exist, to the sum of the sources' counts, each multiplied by its weight, 1 by default. All the sketches
+// must have the same dimensions.
+struct CmsMerge;
+
+impl Command for CmsMerge {
+    fn name(&self) -> &'static str {
+        "cms.merge"
+    }
+
+    fn arity(&self) -> i64 {
+        -4
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let numkeys = usize::try_from(parse_int(&args[1])?).ok().filter(|&n| n > 0 && n <= args.len() - 2)
+            .ok_or_else(|| Error::reply("ERR CMS: invalid numkeys"))?;
+        let sources = &args[2..2 + numkeys];
+        let weights = match &args[2 + numkeys..] {
+            [] => vec![1; numkeys],
+            [option, weights @ ..] if unpack_bulk_str(option)?.eq_ignore_ascii_case("weights") && weights.len() == numkeys => {
+                weights.iter().map(|weight| parse_count(weight, "weight")).collect::<Result<Vec<_>>>()?
+            },
+            _ => return Err(Error::Syntax),
+        };
+        // The sources are read first, each under its own shard's lock, as they may be anywhere
+        let storage = &ctx.server.storage;
+        let mut read = vec![];
+        for source in sources {
+            let source = storage.read::<CountMinSketch, _>(unpack_bytes(source)?, CountMinSketch::clone)?;
+            read.push(source.ok_or_else(not_found)?);
+        }
+        storage.update::<CountMinSketch, _>(unpack_bytes(&args[0])?, |dest| {
+            let Some(dest) = dest else {
+                return (Err(not_found()), false);
+            };
+            let mut merged = CountMinSketch::new(dest.width, dest.depth).expect("the destination's dimensions are valid");
+            for (source, &weight) in read.iter().zip(&weights) {
+                if let Err(e) = merged.merge(source, weight) {
+                    return (Err(Error::Reply(format!("ERR CMS: {}", e))), false);
+                }
+            }
+            *dest = merged;
+            (Ok(ok()), true)
+        })?
+    }
+}
+
+// CMS.INFO key: the sketch's width, depth and total count
+struct CmsInfo;
+
+impl Command for CmsInfo {
+    fn name(&self) -> &'static str {
+        "cms.info"
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let name = |name: &str| Value::SimpleString(name.to_string());
+        let info = ctx.server.storage.read::<CountMinSketch, _>(unpack_bytes(&args[0])?, |sketch| vec![
+            (name("width"), Value::Integer(sketch.width as i64)),
+            (name("depth"), Value::Integer(sketch.depth as i64)),
+            (name("count"), Value::Integer(sketch.count as i64)),
+        ])?;
+        Ok(Value::Map(info.ok_or_else(not_found)?).into())
+    }
+}
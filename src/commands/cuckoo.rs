@@ -0,0 +1,321 @@
+use bytes::Bytes;
+use crate::cuckoo::{CuckooFilter, DEFAULT_BUCKET_SIZE, DEFAULT_CAPACITY, DEFAULT_EXPANSION, DEFAULT_MAX_ITERATIONS};
+use crate::error::{Error, Result};
+use crate::resp::Value;
+use crate::storage::Storage;
+use super::{ok, parse_int, unpack_bulk_str, unpack_bytes, Command, Context, Flags, KeySpec, Registry, Reply};
+
+pub fn register(registry: &mut Registry) {
+    registry.add(CfReserve);
+    registry.add(CfAdd { nx: false });
+    registry.add(CfAdd { nx: true });
+    registry.add(CfInsert { nx: false });
+    registry.add(CfInsert { nx: true });
+    registry.add(CfExists { multi: false });
+    registry.add(CfExists { multi: true });
+    registry.add(CfCount);
+    registry.add(CfDel);
+    registry.add(CfInfo);
+}
+
+fn parse_count(arg: &Value, what: &str) -> Result<u64> {
+    u64::try_from(parse_int(arg)?).map_err(|_| Error::Reply(format!("ERR the {} must not be negative", what)))
+}
+
+fn new_filter(capacity: u64, bucket_size: u64, max_iterations: u64, expansion: u64) -> Result<CuckooFilter> {
+    CuckooFilter::new(capacity, bucket_size, max_iterations, expansion).map_err(|e| Error::Reply(format!("ERR {}", e)))
+}
+
+// Adds items to a filter, making one with `capacity` and the other defaults if it's missing and that's given.
+// With `nx` an item that may be in already isn't added again. 1 for each item added, 0 for each skipped, or
+// why it couldn't be.
+fn add_items(storage: &Storage, key: &Bytes, items: &[Value], capacity: Option<u64>, nx: bool) -> Result<Vec<Value>> {
+    let items = items.iter().map(unpack_bytes).collect::<Result<Vec<_>>>()?;
+    storage.update::<CuckooFilter, _>(key, |filter| {
+        let created = filter.is_none();
+        if created {
+            let Some(capacity) = capacity else {
+                return (Err(Error::reply("ERR not found")), false);
+            };
+            match new_filter(capacity, DEFAULT_BUCKET_SIZE, DEFAULT_MAX_ITERATIONS, DEFAULT_EXPANSION) {
+                Ok(created) => *filter = Some(created),
+                Err(e) => return (Err(e), false),
+            }
+        }
+        let filter = filter.as_mut().expect("created above");
+        let replies: Vec<Value> = items.iter().map(|item| match nx && filter.contains(item) {
+            true => Value::Integer(0),
+            false => match filter.insert(item) {
+                Ok(()) => Value::Integer(1),
+                Err(e) => Value::Error(format!("ERR {}", e)),
+            },
+        }).collect();
+        // A filter made here is written even when nothing could go in it
+        let changed = created || replies.contains(&Value::Integer(1));
+        (Ok(replies), changed)
+    })?
+}
+
+// CF.RESERVE key capacity [BUCKETSIZE size] [MAXITERATIONS iterations] [EXPANSION expansion]: an empty filter
+// for `capacity` items in buckets of `size`, 2 by default, kicking items up to `iterations` times, 20 by
+// default, to make room. Once full a filter `expansion` times bigger, 1 by default, is stacked on; with 0
+// it's full for good.
+struct CfReserve;
+
+impl Command for CfReserve {
+    fn name(&self) -> &'static str {
+        "cf.reserve"
+    }
+
+    fn arity(&self) -> i64 {
+        -3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let capacity = parse_count(&args[1], "capacity")?;
+        let (mut bucket_size, mut max_iterations, mut expansion) = (DEFAULT_BUCKET_SIZE, DEFAULT_MAX_ITERATIONS, DEFAULT_EXPANSION);
+        let mut options = args[2..].chunks(2);
+        for option in &mut options {
+            let [name, arg] = option else {
+                return Err(Error::Syntax);
+            };
+            match unpack_bulk_str(name)?.to_lowercase().as_str() {
+                "bucketsize" => bucket_size = parse_count(arg, "bucket size")?,
+                "maxiterations" => max_iterations = parse_count(arg, "max iterations")?,
+                "expansion" => expansion = parse_count(arg, "expansion")?,
+                _ => return Err(Error::Syntax),
+            }
+        }
+        let filter = new_filter(capacity, bucket_size, max_iterations, expansion)?;
+        let created = ctx.server.storage.update::<CuckooFilter, _>(unpack_bytes(&args[0])?, |slot| match slot {
+            Some(_) => (false, false),
+            None => {
+                *slot = Some(filter);
+                (true, true)
+            },
+        })?;
+        match created {
+            true => Ok(ok()),
+            false => Err(Error::reply("ERR item exists")),
+        }
+    }
+}
+
+// CF.ADD key item / CF.ADDNX key item: adds the item, making a filter with the defaults if the key is missing.
+// CF.ADD always adds another copy and replies 1; CF.ADDNX replies 0 instead when the item may be in already.
+struct CfAdd {
+    nx: bool,
+}
+
+impl Command for CfAdd {
+    fn name(&self) -> &'static str {
+        if self.nx { "cf.addnx" } else { "cf.add" }
+    }
+
+    fn arity(&self) -> i64 {
+        3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let mut replies = add_items(&ctx.server.storage, unpack_bytes(&args[0])?, &args[1..], Some(DEFAULT_CAPACITY), self.nx)?;
+        match replies.remove(0) {
+            Value::Error(e) => Err(Error::Reply(e)),
+            reply => Ok(reply.into()),
+        }
+    }
+}
+
+// CF.INSERT key [CAPACITY capacity] [NOCREATE] ITEMS item [item ...] / CF.INSERTNX likewise: CF.ADD or
+// CF.ADDNX for each item, making a missing filter for `capacity` items, or failing with NOCREATE
+struct CfInsert {
+    nx: bool,
+}
+
+impl Command for CfInsert {
+    fn name(&self) -> &'static str {
+        if self.nx { "cf.insertnx" } else { "cf.insert" }
+    }
+
+    fn arity(&self) -> i64 {
+        -4
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let (mut capacity, mut create) = (DEFAULT_CAPACITY, true);
+        let mut i = 1;
+        let items = loop {
+            let Some(option) = args.get(i) else {
+                return Err(Error::Syntax);
+            };
+            match (unpack_bulk_str(option)?.to_lowercase().as_str(), args.get(i + 1)) {
+                ("capacity", Some(arg)) => {
+                    capacity = parse_count(arg, "capacity")?;
+                    i += 1;
+                },
+                ("nocreate", _) => create = false,
+                ("items", _) if i + 1 < args.len() => break &args[i + 1..],
+                _ => return Err(Error::Syntax),
+            }
+            i += 1;
+        };
+        let replies = add_items(&ctx.server.storage, unpack_bytes(&args[0])?, items, create.then_some(capacity), self.nx)?;
+        Ok(Value::Array(replies).into())
+    }
+}
+
+// CF.EXISTS key item / CF.MEXISTS key item [item ...]: 1 for each item that may be in, 0 for those that
+// certainly aren't
+struct CfExists {
+    multi: bool,
+}
+
+impl Command for CfExists {
+    fn name(&self) -> &'static str {
+        if self.multi { "cf.mexists" } else { "cf.exists" }
+    }
+
+    fn arity(&self) -> i64 {
+        if self.multi { -3 } else { 3 }
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let items = args[1..].iter().map(unpack_bytes).collect::<Result<Vec<_>>>()?;
+        let found = ctx.server.storage.read::<CuckooFilter, _>(unpack_bytes(&args[0])?, |filter| items.iter().map(|item| filter.contains(item)).collect::<Vec<_>>())?;
+        let found = found.unwrap_or_else(|| vec![false; items.len()]);
+        let mut replies: Vec<Value> = found.into_iter().map(|found| Value::Integer(found as i64)).collect();
+        match self.multi {
+            true => Ok(Value::Array(replies).into()),
+            false => Ok(replies.remove(0).into()),
+        }
+    }
+}
+
+// CF.COUNT key item: how many copies of the item there may be, 0 for a missing key
+struct CfCount;
+
+impl Command for CfCount {
+    fn name(&self) -> &'static str {
+        "cf.count"
+    }
+
+    fn arity(&self) -> i64 {
+        3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let item = unpack_bytes(&args[1])?;
+        let count = ctx.server.storage.read::<CuckooFilter, _>(unpack_bytes(&args[0])?, |filter| filter.count(item))?;
+        Ok(Value::Integer(count.unwrap_or(0) as i64).into())
+    }
+}
+
+// CF.DEL key item: removes one copy of the item, 1 if there was one. Deleting an item that was never added
+// may remove another that was.
+struct CfDel;
+
+impl Command for CfDel {
+    fn name(&self) -> &'static str {
+        "cf.del"
+    }
+
+    fn arity(&self) -> i64 {
+        3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let item = unpack_bytes(&args[1])?;
+        let deleted = ctx.server.storage.update::<CuckooFilter, _>(unpack_bytes(&args[0])?, |filter| match filter {
+            Some(filter) => {
+                let deleted = filter.delete(item);
+                (Ok(deleted), deleted)
+            },
+            None => (Err(Error::reply("ERR not found")), false),
+        })??;
+        Ok(Value::Integer(deleted as i64).into())
+    }
+}
+
+// CF.INFO key: the filter's size in bytes, buckets, how many filters it stacks, items in and deleted, and the
+// options it was made with
+struct CfInfo;
+
+impl Command for CfInfo {
+    fn name(&self) -> &'static str {
+        "cf.info"
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let name = |name: &str| Value::SimpleString(name.to_string());
+        let info = ctx.server.storage.read::<CuckooFilter, _>(unpack_bytes(&args[0])?, |filter| vec![
+            (name("Size"), Value::Integer(filter.size() as i64)),
+            (name("Number of buckets"), Value::Integer(filter.buckets() as i64)),
+            (name("Number of filters"), Value::Integer(filter.filters() as i64)),
+            (name("Number of items inserted"), Value::Integer(filter.len() as i64)),
+            (name("Number of items deleted"), Value::Integer(filter.deleted as i64)),
+            (name("Bucket size"), Value::Integer(filter.bucket_size as i64)),
+            (name("Expansion rate"), Value::Integer(filter.expansion as i64)),
+            (name("Max iterations"), Value::Integer(filter.max_iterations as i64)),
+        ])?;
+        Ok(Value::Map(info.ok_or_else(|| Error::reply("ERR not found"))?).into())
+    }
+}
@@ -0,0 +1,282 @@
+use bytes::Bytes;
+use crate::error::{Error, Result};
+use crate::graph::{Direction, Graph, Properties};
+use crate::resp::Value;
+use super::{parse_int, unpack_bulk_str, unpack_bytes, Command, Context, Flags, KeySpec, Registry, Reply};
+
+pub fn register(registry: &mut Registry) {
+    registry.add(GraphAddNode);
+    registry.add(GraphAddEdge);
+    registry.add(GraphDelNode);
+    registry.add(GraphDelEdge);
+    registry.add(GraphGet { edge: false });
+    registry.add(GraphGet { edge: true });
+    registry.add(GraphWalk { traverse: false });
+    registry.add(GraphWalk { traverse: true });
+    registry.add(GraphInfo);
+}
+
+// How deep GRAPH.TRAVERSE goes without DEPTH
+const DEFAULT_DEPTH: usize = 3;
+
+// Name and value pairs
+fn parse_properties(command: &str, args: &[Value]) -> Result<Vec<(Bytes, Bytes)>> {
+    if !args.len().is_multiple_of(2) {
+        return Err(Error::WrongArity(command.to_string()));
+    }
+    args.chunks(2).map(|pair| Ok((unpack_bytes(&pair[0])?.clone(), unpack_bytes(&pair[1])?.clone()))).collect()
+}
+
+fn properties_reply(properties: Option<&Properties>) -> Value {
+    match properties {
+        Some(properties) => Value::Map(properties.iter().map(|(name, value)| (Value::BulkString(name.clone()), Value::BulkString(value.clone()))).collect()),
+        None => Value::Null,
+    }
+}
+
+// GRAPH.ADDNODE key node [property value ...]: adds a node, or sets properties of the one there is. 1 when the
+// node is new, 0 otherwise.
+struct GraphAddNode;
+
+impl Command for GraphAddNode {
+    fn name(&self) -> &'static str {
+        "graph.addnode"
+    }
+
+    fn arity(&self) -> i64 {
+        -3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let node = unpack_bytes(&args[1])?;
+        let properties = parse_properties(self.name(), &args[2..])?;
+        let new = ctx.server.storage.update::<Graph, _>(unpack_bytes(&args[0])?, |graph| {
+            (graph.get_or_insert_default().add_node(node, properties), true)
+        })?;
+        Ok(Value::Integer(new as i64).into())
+    }
+}
+
+// GRAPH.ADDEDGE key from to label [property value ...]: adds an edge from one node to another, adding either
+// that's missing, or sets properties of the edge with that label there is. 1 when the edge is new, 0
+// otherwise.
+struct GraphAddEdge;
+
+impl Command for GraphAddEdge {
+    fn name(&self) -> &'static str {
+        "graph.addedge"
+    }
+
+    fn arity(&self) -> i64 {
+        -5
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let (from, to, label) = (unpack_bytes(&args[1])?, unpack_bytes(&args[2])?, unpack_bytes(&args[3])?);
+        let properties = parse_properties(self.name(), &args[4..])?;
+        let new = ctx.server.storage.update::<Graph, _>(unpack_bytes(&args[0])?, |graph| {
+            (graph.get_or_insert_default().add_edge(from, to, label, properties), true)
+        })?;
+        Ok(Value::Integer(new as i64).into())
+    }
+}
+
+// GRAPH.DELNODE key node: removes a node and every edge to or from it, and the key with its last node. 1 when
+// there was such a node, 0 otherwise.
+struct GraphDelNode;
+
+impl Command for GraphDelNode {
+    fn name(&self) -> &'static str {
+        "graph.delnode"
+    }
+
+    fn arity(&self) -> i64 {
+        3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let node = unpack_bytes(&args[1])?;
+        let removed = ctx.server.storage.update::<Graph, _>(unpack_bytes(&args[0])?, |slot| {
+            let removed = slot.as_mut().and_then(|graph| graph.remove_node(node)).is_some();
+            if slot.as_ref().is_some_and(|graph| graph.is_empty()) {
+                *slot = None;
+            }
+            (removed, removed)
+        })?;
+        Ok(Value::Integer(removed as i64).into())
+    }
+}
+
+// GRAPH.DELEDGE key from to label: removes the edge. 1 when there was one, 0 otherwise.
+struct GraphDelEdge;
+
+impl Command for GraphDelEdge {
+    fn name(&self) -> &'static str {
+        "graph.deledge"
+    }
+
+    fn arity(&self) -> i64 {
+        5
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let (from, to, label) = (unpack_bytes(&args[1])?, unpack_bytes(&args[2])?, unpack_bytes(&args[3])?);
+        let removed = ctx.server.storage.update::<Graph, _>(unpack_bytes(&args[0])?, |graph| {
+            let removed = graph.as_mut().is_some_and(|graph| graph.remove_edge(from, to, label));
+            (removed, removed)
+        })?;
+        Ok(Value::Integer(removed as i64).into())
+    }
+}
+
+// GRAPH.NODE key node / GRAPH.EDGE key from to label: the node's or edge's properties, nil when there's no
+// such node or edge
+struct GraphGet {
+    edge: bool,
+}
+
+impl Command for GraphGet {
+    fn name(&self) -> &'static str {
+        if self.edge { "graph.edge" } else { "graph.node" }
+    }
+
+    fn arity(&self) -> i64 {
+        if self.edge { 5 } else { 3 }
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let ids = args[1..].iter().map(unpack_bytes).collect::<Result<Vec<_>>>()?;
+        let reply = ctx.server.storage.read::<Graph, _>(unpack_bytes(&args[0])?, |graph| match self.edge {
+            true => properties_reply(graph.edge(ids[0], ids[1], ids[2])),
+            false => properties_reply(graph.node(ids[0])),
+        })?;
+        Ok(reply.unwrap_or(Value::Null).into())
+    }
+}
+
+// GRAPH.NEIGHBORS key node [DIRECTION OUT | IN | BOTH] [LABEL label]: the nodes an edge joins to this one, in
+// id order, following edges out of it unless DIRECTION says otherwise, and only those with the label if one
+// is given /
+// GRAPH.TRAVERSE key node [DEPTH depth] [DIRECTION ...] [LABEL label] [LIMIT count]: the nodes up to `depth`
+// edges away, 3 by default, breadth first, each as its id and depth, up to `count` of them. Both give an
+// empty array for a missing node.
+struct GraphWalk {
+    traverse: bool,
+}
+
+impl Command for GraphWalk {
+    fn name(&self) -> &'static str {
+        if self.traverse { "graph.traverse" } else { "graph.neighbors" }
+    }
+
+    fn arity(&self) -> i64 {
+        -3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let node = unpack_bytes(&args[1])?;
+        let (mut direction, mut label, mut depth, mut limit) = (Direction::default(), None, DEFAULT_DEPTH, usize::MAX);
+        for option in args[2..].chunks(2) {
+            let [option, arg] = option else {
+                return Err(Error::Syntax);
+            };
+            match unpack_bulk_str(option)?.to_lowercase().as_str() {
+                "direction" => direction = Direction::parse(unpack_bulk_str(arg)?).ok_or(Error::Syntax)?,
+                "label" => label = Some(unpack_bytes(arg)?),
+                "depth" if self.traverse => {
+                    depth = usize::try_from(parse_int(arg)?).map_err(|_| Error::reply("ERR DEPTH must not be negative"))?;
+                },
+                "limit" if self.traverse => {
+                    limit = usize::try_from(parse_int(arg)?).map_err(|_| Error::reply("ERR LIMIT must not be negative"))?;
+                },
+                _ => return Err(Error::Syntax),
+            }
+        }
+        let label = label.map(|label| &label[..]);
+        let found = ctx.server.storage.read::<Graph, _>(unpack_bytes(&args[0])?, |graph| match self.traverse {
+            true => graph.traverse(node, direction, label, depth, limit, || ctx.check_budget()).map(|found| found.map(|found| {
+                found.into_iter().map(|(id, depth)| Value::Array(vec![Value::BulkString(id), Value::Integer(depth as i64)])).collect()
+            })),
+            false => Ok(graph.neighbors(node, direction, label).map(|found| found.into_iter().map(|id| Value::BulkString(id.clone())).collect())),
+        })?;
+        Ok(Value::Array(found.transpose()?.flatten().unwrap_or_default()).into())
+    }
+}
+
+// GRAPH.INFO key: how many nodes and edges the graph has
+struct GraphInfo;
+
+impl Command for GraphInfo {
+    fn name(&self) -> &'static str {
+        "graph.info"
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let name = |name: &str| Value::SimpleString(name.to_string());
+        let info = ctx.server.storage.read::<Graph, _>(unpack_bytes(&args[0])?, |graph| vec![
+            (name("nodes"), Value::Integer(graph.nodes() as i64)),
+            (name("edges"), Value::Integer(graph.edges() as i64)),
+        ])?;
+        Ok(Value::Map(info.ok_or_else(|| Error::reply("ERR no such key"))?).into())
+    }
+}
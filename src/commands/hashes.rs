@@ -0,0 +1,220 @@
+use crate::error::{Error, Result};
+use crate::resp::Value;
+use crate::storage::Hash;
+use super::{unpack_bytes, Command, Context, Flags, KeySpec, Registry, Reply};
+
+pub fn register(registry: &mut Registry) {
+    registry.add(HSet);
+    registry.add(HGet);
+    registry.add(HMGet);
+    registry.add(HDel);
+    registry.add(HGetAll);
+    registry.add(HLen);
+    registry.add(HExists);
+}
+
+// HSET key field value [field value ...]: sets the fields, replying with how many were added
+struct HSet;
+
+impl Command for HSet {
+    fn name(&self) -> &'static str {
+        "hset"
+    }
+
+    fn arity(&self) -> i64 {
+        -4
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        if args.len().is_multiple_of(2) {
+            return Err(Error::WrongArity(self.name().to_string()));
+        }
+        let pairs = args[1..].chunks(2).map(|pair| Ok((unpack_bytes(&pair[0])?.clone(), unpack_bytes(&pair[1])?.clone())));
+        let pairs = pairs.collect::<Result<Vec<_>>>()?;
+        let added = ctx.server.storage.update::<Hash, _>(unpack_bytes(&args[0])?, |hash| {
+            let hash = hash.get_or_insert_with(Default::default);
+            let added = pairs.into_iter().map(|(field, value)| hash.insert(field, value)).filter(Option::is_none).count();
+            (added, true)
+        })?;
+        Ok(Value::Integer(added as i64).into())
+    }
+}
+
+// HGET key field
+struct HGet;
+
+impl Command for HGet {
+    fn name(&self) -> &'static str {
+        "hget"
+    }
+
+    fn arity(&self) -> i64 {
+        3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let field = unpack_bytes(&args[1])?;
+        let value = ctx.server.storage.read::<Hash, _>(unpack_bytes(&args[0])?, |hash| hash.get(field).cloned())?;
+        Ok(value.flatten().map_or(Value::Null, Value::BulkString).into())
+    }
+}
+
+// HMGET key field [field ...]: each field's value, nil for those not set
+struct HMGet;
+
+impl Command for HMGet {
+    fn name(&self) -> &'static str {
+        "hmget"
+    }
+
+    fn arity(&self) -> i64 {
+        -3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let fields = args[1..].iter().map(unpack_bytes).collect::<Result<Vec<_>>>()?;
+        let values = ctx.server.storage.read::<Hash, _>(unpack_bytes(&args[0])?, |hash| {
+            fields.iter().map(|field| hash.get(*field).cloned().map_or(Value::Null, Value::BulkString)).collect()
+        })?;
+        Ok(Value::Array(values.unwrap_or_else(|| vec![Value::Null; fields.len()])).into())
+    }
+}
+
+// HDEL key field [field ...]: replies with how many of the fields were set. Deleting the last field deletes
+// the key.
+struct HDel;
+
+impl Command for HDel {
+    fn name(&self) -> &'static str {
+        "hdel"
+    }
+
+    fn arity(&self) -> i64 {
+        -3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let fields = args[1..].iter().map(unpack_bytes).collect::<Result<Vec<_>>>()?;
+        let removed = ctx.server.storage.update::<Hash, _>(unpack_bytes(&args[0])?, |hash| {
+            let Some(hash) = hash else {
+                return (0, false);
+            };
+            let removed = fields.iter().filter(|field| hash.remove(**field).is_some()).count();
+            (removed, removed > 0)
+        })?;
+        Ok(Value::Integer(removed as i64).into())
+    }
+}
+
+// HGETALL key: every field and its value, in field order
+struct HGetAll;
+
+impl Command for HGetAll {
+    fn name(&self) -> &'static str {
+        "hgetall"
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let pairs = ctx.server.storage.read::<Hash, _>(unpack_bytes(&args[0])?, |hash| {
+            hash.iter().map(|(field, value)| (Value::BulkString(field.clone()), Value::BulkString(value.clone()))).collect()
+        })?;
+        Ok(Value::Map(pairs.unwrap_or_default()).into())
+    }
+}
+
+// HLEN key: how many fields are set
+struct HLen;
+
+impl Command for HLen {
+    fn name(&self) -> &'static str {
+        "hlen"
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let len = ctx.server.storage.read::<Hash, _>(unpack_bytes(&args[0])?, |hash| hash.len())?;
+        Ok(Value::Integer(len.unwrap_or(0) as i64).into())
+    }
+}
+
+// HEXISTS key field
+struct HExists;
+
+impl Command for HExists {
+    fn name(&self) -> &'static str {
+        "hexists"
+    }
+
+    fn arity(&self) -> i64 {
+        3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let field = unpack_bytes(&args[1])?;
+        let exists = ctx.server.storage.read::<Hash, _>(unpack_bytes(&args[0])?, |hash| hash.contains_key(field))?;
+        Ok(Value::Integer(exists.unwrap_or(false) as i64).into())
+    }
+}
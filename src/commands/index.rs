@@ -0,0 +1,207 @@
+use std::ops::Bound;
+use bytes::Bytes;
+use crate::error::{Error, Result};
+use crate::index::{IndexDef, IndexKind, TermRange};
+use crate::resp::Value;
+use super::{ok, parse_int, unpack_bulk_str, unpack_bytes, Command, Context, Flags, Registry, Reply};
+
+pub fn register(registry: &mut Registry) {
+    registry.add(IdxCreate);
+    registry.add(IdxDrop);
+    registry.add(IdxQuery);
+    registry.add(IdxList);
+    registry.add(IdxInfo);
+}
+
+fn no_such_index(name: &str) -> Error {
+    Error::Reply(format!("ERR no such index '{}'", name))
+}
+
+// IDX.CREATE index [PREFIX prefix] ON field:name [TEXT | NUMERIC]: indexes a field of the hashes whose keys
+// start with the prefix, all hashes without one, by its text or its numeric value, and likewise the member of
+// the JSON documents there it names. The index is built before the reply.
+struct IdxCreate;
+
+impl Command for IdxCreate {
+    fn name(&self) -> &'static str {
+        "idx.create"
+    }
+
+    fn arity(&self) -> i64 {
+        -4
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let name = unpack_bulk_str(&args[0])?.to_string();
+        let (mut prefix, mut field, mut kind) = (Bytes::new(), None, IndexKind::default());
+        let mut i = 1;
+        while i < args.len() {
+            let option = unpack_bulk_str(&args[i])?.to_lowercase();
+            match option.as_str() {
+                "prefix" if i + 1 < args.len() => {
+                    prefix = unpack_bytes(&args[i + 1])?.clone();
+                    i += 1;
+                },
+                "on" if i + 1 < args.len() => {
+                    let spec = unpack_bytes(&args[i + 1])?;
+                    match spec.get(..6) {
+                        Some(tag) if tag.eq_ignore_ascii_case(b"field:") && spec.len() > 6 => field = Some(spec.slice(6..)),
+                        _ => return Err(Error::reply("ERR expected field:<name> after ON")),
+                    }
+                    i += 1;
+                },
+                "text" | "numeric" => kind = IndexKind::parse(&option).map_err(|e| Error::Reply(format!("ERR {}", e)))?,
+                _ => return Err(Error::Syntax),
+            }
+            i += 1;
+        }
+        let Some(field) = field else {
+            return Err(Error::reply("ERR expected field:<name> after ON"));
+        };
+        if !ctx.server.storage.create_index(IndexDef { name, prefix, field, kind }) {
+            return Err(Error::reply("ERR Index already exists"));
+        }
+        Ok(ok())
+    }
+}
+
+// IDX.DROP index: forgets the index, leaving the keys it covered as they are
+struct IdxDrop;
+
+impl Command for IdxDrop {
+    fn name(&self) -> &'static str {
+        "idx.drop"
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let name = unpack_bulk_str(&args[0])?;
+        if !ctx.server.storage.drop_index(name) {
+            return Err(no_such_index(name));
+        }
+        Ok(ok())
+    }
+}
+
+// IDX.QUERY index EQ value | RANGE min max [LIMIT offset count]: the keys whose field has the value, or one
+// between min and max, in value order. A bound is inclusive, exclusive after '(', and '-' or '+' leaves
+// that end open; '[' may start an inclusive bound, for text beginning with one of these.
+struct IdxQuery;
+
+impl Command for IdxQuery {
+    fn name(&self) -> &'static str {
+        "idx.query"
+    }
+
+    fn arity(&self) -> i64 {
+        -4
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let name = unpack_bulk_str(&args[0])?;
+        let storage = &ctx.server.storage;
+        let indexes = storage.indexes();
+        let Some((def, _)) = indexes.iter().find(|(def, _)| def.name == name) else {
+            return Err(no_such_index(name));
+        };
+        let encode = |value: &[u8]| def.encode(value).ok_or_else(|| Error::reply("ERR value is not a number"));
+        let bound = |value: &Bytes| -> Result<Bound<Bytes>> {
+            Ok(match value.first() {
+                Some(b'-' | b'+') if value.len() == 1 => Bound::Unbounded,
+                Some(b'(') => Bound::Excluded(encode(&value[1..])?),
+                Some(b'[') => Bound::Included(encode(&value[1..])?),
+                _ => Bound::Included(encode(value)?),
+            })
+        };
+        let (range, rest) = match unpack_bulk_str(&args[1])?.to_lowercase().as_str() {
+            "eq" => (TermRange::exact(encode(unpack_bytes(&args[2])?)?), &args[3..]),
+            "range" if args.len() >= 4 => {
+                let range = TermRange { min: bound(unpack_bytes(&args[2])?)?, max: bound(unpack_bytes(&args[3])?)? };
+                (range, &args[4..])
+            },
+            _ => return Err(Error::Syntax),
+        };
+        let (offset, count) = match rest {
+            [] => (0, usize::MAX),
+            [limit, offset, count] if unpack_bulk_str(limit)?.eq_ignore_ascii_case("limit") => {
+                match (parse_int(offset)?, parse_int(count)?) {
+                    (offset, count) if offset >= 0 && count >= 0 => (offset as usize, count as usize),
+                    _ => return Err(Error::reply("ERR LIMIT offset and count can't be negative")),
+                }
+            },
+            _ => return Err(Error::Syntax),
+        };
+        let keys = storage.query_index(name, &range, offset, count).ok_or_else(|| no_such_index(name))?;
+        Ok(Value::Array(keys.into_iter().map(Value::BulkString).collect()).into())
+    }
+}
+
+// IDX.LIST: the names of the indexes
+struct IdxList;
+
+impl Command for IdxList {
+    fn name(&self) -> &'static str {
+        "idx.list"
+    }
+
+    fn arity(&self) -> i64 {
+        1
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn execute(&self, ctx: &mut Context, _args: &[Value]) -> Result<Reply> {
+        let names = ctx.server.storage.indexes().into_iter().map(|(def, _)| Value::BulkString(Bytes::from(def.name.clone())));
+        Ok(Value::Array(names.collect()).into())
+    }
+}
+
+// IDX.INFO index: how the index was declared and how many keys it holds
+struct IdxInfo;
+
+impl Command for IdxInfo {
+    fn name(&self) -> &'static str {
+        "idx.info"
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let name = unpack_bulk_str(&args[0])?;
+        let indexes = ctx.server.storage.indexes();
+        let Some((def, keys)) = indexes.into_iter().find(|(def, _)| def.name == name) else {
+            return Err(no_such_index(name));
+        };
+        let field = |name: &str, value: Value| (Value::BulkString(Bytes::from(name.to_string())), value);
+        Ok(Value::Map(vec![
+            field("name", Value::BulkString(Bytes::from(def.name.clone()))),
+            field("prefix", Value::BulkString(def.prefix.clone())),
+            field("field", Value::BulkString(def.field.clone())),
+            field("type", Value::BulkString(Bytes::from(def.kind.as_str()))),
+            field("keys", Value::Integer(keys as i64)),
+        ]).into())
+    }
+}
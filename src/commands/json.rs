@@ -0,0 +1,537 @@
+use std::collections::HashSet;
+use bytes::Bytes;
+use crate::error::{Error, Result};
+use crate::json::{Format, Json, Path, Target, MAX_DEPTH};
+use crate::resp::Value;
+use crate::storage::Storage;
+use super::{ok, parse_int, unpack_bulk_str, unpack_bytes, Command, Context, Flags, KeySpec, Registry, Reply};
+
+pub fn register(registry: &mut Registry) {
+    registry.add(JsonSet);
+    registry.add(JsonGet);
+    registry.add(JsonDel { name: "json.del" });
+    registry.add(JsonDel { name: "json.forget" });
+    registry.add(JsonNumIncrBy);
+    registry.add(JsonArrAppend);
+    registry.add(JsonArrInsert);
+    registry.add(JsonStrAppend);
+    registry.add(JsonObjKeys);
+}
+
+fn parse_path(path: &str) -> Result<Path> {
+    Path::parse(path).map_err(|e| Error::Reply(format!("ERR invalid path '{}': {}", path, e)))
+}
+
+fn parse_json(text: &[u8]) -> Result<Json> {
+    Json::parse(text).map_err(|e| Error::Reply(format!("ERR invalid JSON: {}", e)))
+}
+
+fn missing_path(path: &str) -> Error {
+    Error::Reply(format!("ERR Path '{}' does not exist", path))
+}
+
+fn wrong_type(expected: &str, found: &Json) -> Error {
+    Error::Reply(format!("ERR wrong type of path value - expected {} but found {}", expected, found.type_name()))
+}
+
+// Changes the values the path matches in place, under the key's lock, so concurrent clients never lose an
+// update. `check` looks at each match first, giving what `apply` needs to change it, None for a value of the
+// wrong type or an error; only when every match passes does `apply` run, deepest first. Results follow the
+// order of the matches, None for those skipped: a JSONPath skips values of the wrong type, a legacy path
+// fails on them and on matching nothing.
+fn update_matches<P, V>(
+    storage: &Storage,
+    key: &Bytes,
+    (text, path): (&str, &Path),
+    expected: &str,
+    check: impl Fn(&Json, &[usize]) -> Result<Option<P>>,
+    mut apply: impl FnMut(&mut Json, P) -> V,
+) -> Result<Vec<Option<V>>> {
+    storage.update::<Json, _>(key, |doc| {
+        let Some(existing) = doc else {
+            return (Err(Error::reply("ERR could not perform this operation on a key that doesn't exist")), false);
+        };
+        let mut seen = HashSet::new();
+        let mut locations = path.find(existing);
+        locations.retain(|location| seen.insert(location.clone()));
+        if locations.is_empty() && path.legacy {
+            return (Err(missing_path(text)), false);
+        }
+        let mut checked = vec![];
+        for location in &locations {
+            let value = existing.at(location);
+            match check(value, location) {
+                Ok(None) if path.legacy => return (Err(wrong_type(expected, value)), false),
+                Ok(change) => checked.push(change),
+                Err(e) => return (Err(e), false),
+            }
+        }
+        let mut order: Vec<usize> = (0..locations.len()).collect();
+        order.sort_by(|&a, &b| locations[b].cmp(&locations[a]));
+        let mut results: Vec<Option<V>> = locations.iter().map(|_| None).collect();
+        for i in order {
+            if let Some(change) = checked[i].take() {
+                results[i] = Some(apply(existing.at_mut(&locations[i]), change));
+            }
+        }
+        let changed = results.iter().any(Option::is_some);
+        (Ok(results), changed)
+    })?
+}
+
+// A reply of integers, one per match for a JSONPath and the first for a legacy path
+fn integer_reply(path: &Path, results: Vec<Option<usize>>) -> Reply {
+    let integer = |result: Option<usize>| result.map_or(Value::Null, |n| Value::Integer(n as i64));
+    match path.legacy {
+        true => integer(results.into_iter().next().flatten()).into(),
+        false => Value::Array(results.into_iter().map(integer).collect()).into(),
+    }
+}
+
+// Deepest location first, so changing one never moves another still to come. A member to add sorts right
+// after its object's existing members.
+fn deepest_first(targets: &mut [Target]) {
+    let order = |target: &Target| match target {
+        Target::Existing(location) => location.clone(),
+        Target::NewMember(object, _) => [&object[..], &[usize::MAX]].concat(),
+    };
+    targets.sort_by_key(|target| std::cmp::Reverse(order(target)));
+}
+
+// JSON.SET key path value [NX | XX]: sets the values the path matches, adding the member it ends in to
+// objects that lack it. A new key must be set at the root. NX only adds, XX only replaces; replies nil when
+// that leaves nothing to set.
+struct JsonSet;
+
+impl Command for JsonSet {
+    fn name(&self) -> &'static str {
+        "json.set"
+    }
+
+    fn arity(&self) -> i64 {
+        -4
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let key = unpack_bytes(&args[0])?;
+        let text = unpack_bulk_str(&args[1])?;
+        let path = parse_path(text)?;
+        let value = parse_json(unpack_bytes(&args[2])?)?;
+        let (nx, xx) = match &args[3..] {
+            [] => (false, false),
+            [condition] => match unpack_bulk_str(condition)?.to_lowercase().as_str() {
+                "nx" => (true, false),
+                "xx" => (false, true),
+                _ => return Err(Error::Syntax),
+            },
+            _ => return Err(Error::Syntax),
+        };
+        let set = ctx.server.storage.update::<Json, _>(key, |doc| {
+            let Some(existing) = doc else {
+                if !path.is_root() {
+                    return (Err(Error::reply("ERR new objects must be created at the root")), false);
+                }
+                if xx {
+                    return (Ok(false), false);
+                }
+                *doc = Some(value);
+                return (Ok(true), true);
+            };
+            let mut targets = path.targets(existing);
+            if targets.is_empty() && path.legacy {
+                return (Err(missing_path(text)), false);
+            }
+            targets.retain(|target| match target {
+                Target::Existing(_) => !nx,
+                Target::NewMember(..) => !xx,
+            });
+            if targets.is_empty() {
+                return (Ok(false), false);
+            }
+            let deepest = targets.iter().map(|target| match target {
+                Target::Existing(location) => location.len(),
+                Target::NewMember(object, _) => object.len() + 1,
+            });
+            if deepest.max().unwrap_or(0) + value.depth() > MAX_DEPTH {
+                return (Err(Error::Reply(format!("ERR documents may nest at most {} levels deep", MAX_DEPTH))), false);
+            }
+            deepest_first(&mut targets);
+            for target in targets {
+                match target {
+                    Target::Existing(location) => *existing.at_mut(&location) = value.clone(),
+                    Target::NewMember(object, name) => {
+                        if let Json::Object(members) = existing.at_mut(&object) {
+                            members.push((name, value.clone()));
+                        }
+                    },
+                }
+            }
+            (Ok(true), true)
+        })??;
+        Ok(if set { ok() } else { Value::Null.into() })
+    }
+}
+
+// JSON.GET key [INDENT indent] [NEWLINE newline] [SPACE space] [path ...]: the document as JSON text, or what
+// the path matches: for a JSONPath the array of every match, for a legacy path the one value. More than one
+// path gives an object of each path's result. The options pretty-print, see json::Format.
+struct JsonGet;
+
+impl Command for JsonGet {
+    fn name(&self) -> &'static str {
+        "json.get"
+    }
+
+    fn arity(&self) -> i64 {
+        -2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let mut format = Format::default();
+        let mut i = 1;
+        while i + 1 < args.len() {
+            let option = match unpack_bulk_str(&args[i])?.to_lowercase().as_str() {
+                "indent" => &mut format.indent,
+                "newline" => &mut format.newline,
+                "space" => &mut format.space,
+                _ => break,
+            };
+            *option = unpack_bulk_str(&args[i + 1])?.to_string();
+            i += 2;
+        }
+        let mut paths = vec![];
+        for arg in &args[i..] {
+            let text = unpack_bulk_str(arg)?;
+            paths.push((text, parse_path(text)?));
+        }
+        if paths.is_empty() {
+            paths.push((".", parse_path(".")?));
+        }
+
+        let text = ctx.server.storage.read::<Json, _>(unpack_bytes(&args[0])?, |doc| {
+            let mut results = vec![];
+            for (text, path) in &paths {
+                let mut found = path.find(doc).into_iter().map(|location| doc.at(&location).clone());
+                let result = match path.legacy {
+                    true => found.next().ok_or_else(|| missing_path(text))?,
+                    false => Json::Array(found.collect()),
+                };
+                results.push((text.to_string(), result));
+            }
+            let result = match results.len() {
+                1 => results.pop().expect("one result").1,
+                _ => Json::Object(results),
+            };
+            let mut out = String::new();
+            result.write(&mut out, &format, 0);
+            Ok::<_, Error>(out)
+        })?;
+        match text {
+            Some(text) => Ok(Value::BulkString(Bytes::from(text?)).into()),
+            None => Ok(Value::Null.into()),
+        }
+    }
+}
+
+// JSON.DEL key [path], JSON.FORGET key [path]: deletes the values the path matches, the whole key for the
+// root, replying with how many were deleted
+struct JsonDel {
+    name: &'static str,
+}
+
+impl Command for JsonDel {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn arity(&self) -> i64 {
+        -2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let path = match &args[1..] {
+            [] => parse_path(".")?,
+            [path] => parse_path(unpack_bulk_str(path)?)?,
+            _ => return Err(Error::Syntax),
+        };
+        let deleted = ctx.server.storage.update::<Json, _>(unpack_bytes(&args[0])?, |doc| {
+            let Some(existing) = doc else {
+                return (0, false);
+            };
+            if path.is_root() {
+                *doc = None;
+                return (1, true);
+            }
+            let mut targets: Vec<Target> = path.find(existing).into_iter().map(Target::Existing).collect();
+            deepest_first(&mut targets);
+            targets.dedup();
+            for target in &targets {
+                if let Target::Existing(location) = target {
+                    existing.remove(location);
+                }
+            }
+            (targets.len(), !targets.is_empty())
+        })?;
+        Ok(Value::Integer(deleted as i64).into())
+    }
+}
+
+// JSON.NUMINCRBY key path number: adds to the numbers the path matches, replying with the results as JSON
+// text, an array of them for a JSONPath with null where a match isn't a number. Integers stay integers
+// unless the sum overflows.
+struct JsonNumIncrBy;
+
+impl Command for JsonNumIncrBy {
+    fn name(&self) -> &'static str {
+        "json.numincrby"
+    }
+
+    fn arity(&self) -> i64 {
+        4
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let text = unpack_bulk_str(&args[1])?;
+        let path = parse_path(text)?;
+        let by = parse_json(unpack_bytes(&args[2])?)?;
+        if by.as_f64().is_none() {
+            return Err(Error::reply("ERR expected a number to increment by"));
+        }
+        let results = update_matches(&ctx.server.storage, unpack_bytes(&args[0])?, (text, &path), "number", |value, _| {
+            let sum = match (value, &by) {
+                (Json::Int(a), Json::Int(b)) => a.checked_add(*b).map(Json::Int),
+                _ => None,
+            };
+            let sum = match (sum, value.as_f64(), by.as_f64()) {
+                (Some(sum), _, _) => sum,
+                (None, Some(a), Some(b)) if (a + b).is_finite() => Json::Float(a + b),
+                (None, Some(_), _) => return Err(Error::reply("ERR result is not a finite number")),
+                (None, None, _) => return Ok(None),
+            };
+            Ok(Some(sum))
+        }, |value, sum| {
+            *value = sum.clone();
+            sum
+        })?;
+        let result = match path.legacy {
+            true => results.into_iter().next().flatten().unwrap_or(Json::Null),
+            false => Json::Array(results.into_iter().map(|sum| sum.unwrap_or(Json::Null)).collect()),
+        };
+        Ok(Value::BulkString(Bytes::from(result.to_text())).into())
+    }
+}
+
+// JSON.ARRAPPEND key path value [value ...]: appends the values to the arrays the path matches, replying
+// with their new lengths
+struct JsonArrAppend;
+
+impl Command for JsonArrAppend {
+    fn name(&self) -> &'static str {
+        "json.arrappend"
+    }
+
+    fn arity(&self) -> i64 {
+        -4
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let text = unpack_bulk_str(&args[1])?;
+        let path = parse_path(text)?;
+        let values = args[2..].iter().map(|arg| parse_json(unpack_bytes(arg)?)).collect::<Result<Vec<_>>>()?;
+        let results = update_matches(&ctx.server.storage, unpack_bytes(&args[0])?, (text, &path), "array", |value, location| {
+            check_insert(value, location, &values).map(|array| array.map(|items| items.len()))
+        }, |value, at| insert(value, at, &values))?;
+        Ok(integer_reply(&path, results))
+    }
+}
+
+// JSON.ARRINSERT key path index value [value ...]: inserts the values before the index in the arrays the
+// path matches, a negative index counting from the end, replying with their new lengths. The index may be
+// the length, to append.
+struct JsonArrInsert;
+
+impl Command for JsonArrInsert {
+    fn name(&self) -> &'static str {
+        "json.arrinsert"
+    }
+
+    fn arity(&self) -> i64 {
+        -5
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let text = unpack_bulk_str(&args[1])?;
+        let path = parse_path(text)?;
+        let index = parse_int(&args[2])?;
+        let values = args[3..].iter().map(|arg| parse_json(unpack_bytes(arg)?)).collect::<Result<Vec<_>>>()?;
+        let results = update_matches(&ctx.server.storage, unpack_bytes(&args[0])?, (text, &path), "array", |value, location| {
+            let Some(items) = check_insert(value, location, &values)? else {
+                return Ok(None);
+            };
+            let len = items.len() as i64;
+            let at = if index < 0 { len + index } else { index };
+            if !(0..=len).contains(&at) {
+                return Err(Error::reply("ERR index out of bounds"));
+            }
+            Ok(Some(at as usize))
+        }, |value, at| insert(value, at, &values))?;
+        Ok(integer_reply(&path, results))
+    }
+}
+
+// The items of an array that can take `values` without nesting too deeply
+fn check_insert<'a>(value: &'a Json, location: &[usize], values: &[Json]) -> Result<Option<&'a [Json]>> {
+    let Json::Array(items) = value else {
+        return Ok(None);
+    };
+    if location.len() + 1 + values.iter().map(Json::depth).max().unwrap_or(0) > MAX_DEPTH {
+        return Err(Error::Reply(format!("ERR documents may nest at most {} levels deep", MAX_DEPTH)));
+    }
+    Ok(Some(items))
+}
+
+fn insert(value: &mut Json, at: usize, values: &[Json]) -> usize {
+    let Json::Array(items) = value else {
+        unreachable!("checked to be an array");
+    };
+    items.splice(at..at, values.iter().cloned());
+    items.len()
+}
+
+// JSON.STRAPPEND key [path] string: appends to the strings the path matches, replying with their new
+// lengths in bytes. The string is given as JSON, quotes and all.
+struct JsonStrAppend;
+
+impl Command for JsonStrAppend {
+    fn name(&self) -> &'static str {
+        "json.strappend"
+    }
+
+    fn arity(&self) -> i64 {
+        -3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let (text, suffix) = match &args[1..] {
+            [suffix] => (".", suffix),
+            [path, suffix] => (unpack_bulk_str(path)?, suffix),
+            _ => return Err(Error::Syntax),
+        };
+        let path = parse_path(text)?;
+        let Json::String(suffix) = parse_json(unpack_bytes(suffix)?)? else {
+            return Err(Error::reply("ERR expected a JSON string to append"));
+        };
+        let results = update_matches(&ctx.server.storage, unpack_bytes(&args[0])?, (text, &path), "string", |value, _| {
+            Ok(matches!(value, Json::String(_)).then_some(()))
+        }, |value, ()| {
+            let Json::String(string) = value else {
+                unreachable!("checked to be a string");
+            };
+            string.push_str(&suffix);
+            string.len()
+        })?;
+        Ok(integer_reply(&path, results))
+    }
+}
+
+// JSON.OBJKEYS key [path]: the member names of the objects the path matches, an array of them per match
+// for a JSONPath with nil where a match isn't an object. Nil for a missing key.
+struct JsonObjKeys;
+
+impl Command for JsonObjKeys {
+    fn name(&self) -> &'static str {
+        "json.objkeys"
+    }
+
+    fn arity(&self) -> i64 {
+        -2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let text = match &args[1..] {
+            [] => ".",
+            [path] => unpack_bulk_str(path)?,
+            _ => return Err(Error::Syntax),
+        };
+        let path = parse_path(text)?;
+        let reply = ctx.server.storage.read::<Json, _>(unpack_bytes(&args[0])?, |doc| {
+            let keys = |value: &Json| match value {
+                Json::Object(members) => Some(Value::Array(members.iter().map(|(name, _)| Value::BulkString(Bytes::from(name.clone()))).collect())),
+                _ => None,
+            };
+            let mut found = path.find(doc).into_iter().map(|location| doc.at(&location));
+            match path.legacy {
+                true => {
+                    let value = found.next().ok_or_else(|| missing_path(text))?;
+                    keys(value).ok_or_else(|| wrong_type("object", value))
+                },
+                false => Ok(Value::Array(found.map(|value| keys(value).unwrap_or(Value::Null)).collect())),
+            }
+        })?;
+        Ok(reply.transpose()?.unwrap_or(Value::Null).into())
+    }
+}
@@ -0,0 +1,334 @@
+use crate::error::{Error, Result};
+use crate::glob;
+use crate::resp::Value;
+use crate::storage::{ExpireFlags, ValueType};
+use crate::tenant;
+use super::{ok, parse_int, unpack_bulk_str, unpack_bytes, Command, Context, Flags, KeySpec, Registry, Reply};
+
+pub fn register(registry: &mut Registry) {
+    registry.add(Del);
+    registry.add(Expire { name: "expire", millis: false, absolute: false });
+    registry.add(Expire { name: "pexpire", millis: true, absolute: false });
+    registry.add(Expire { name: "expireat", millis: false, absolute: true });
+    registry.add(Expire { name: "pexpireat", millis: true, absolute: true });
+    registry.add(Persist);
+    registry.add(ExpireTime { name: "expiretime", millis: false });
+    registry.add(ExpireTime { name: "pexpiretime", millis: true });
+    registry.add(Ttl { name: "ttl", millis: false });
+    registry.add(Ttl { name: "pttl", millis: true });
+    registry.add(Scan);
+    registry.add(DbSize);
+    registry.add(Flush { name: "flushdb" });
+    registry.add(Flush { name: "flushall" });
+}
+
+struct Del;
+
+impl Command for Del {
+    fn name(&self) -> &'static str {
+        "del"
+    }
+
+    fn arity(&self) -> i64 {
+        -2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::ALL
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let mut deleted = 0;
+        for arg in args {
+            if ctx.server.storage.del(unpack_bytes(arg)?) {
+                deleted += 1;
+            }
+        }
+        Ok(Value::Integer(deleted).into())
+    }
+}
+
+// (P)EXPIRE key amount [NX | XX | GT | LT], (P)EXPIREAT key deadline [...]
+struct Expire {
+    name: &'static str,
+    millis: bool,
+    absolute: bool,
+}
+
+impl Command for Expire {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn arity(&self) -> i64 {
+        -3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let key = unpack_bytes(&args[0])?;
+        let amount = parse_int(&args[1])?;
+        let mut flags = ExpireFlags::default();
+        for arg in &args[2..] {
+            let option = unpack_bulk_str(arg)?;
+            match option.to_lowercase().as_str() {
+                "nx" => flags.nx = true,
+                "xx" => flags.xx = true,
+                "gt" => flags.gt = true,
+                "lt" => flags.lt = true,
+                _ => return Err(Error::reply(format!("ERR Unsupported option {}", option))),
+            }
+        }
+        if flags.nx && (flags.xx || flags.gt || flags.lt) {
+            return Err(Error::reply("ERR NX and XX, GT or LT options at the same time are not compatible"));
+        }
+        if flags.gt && flags.lt {
+            return Err(Error::reply("ERR GT and LT options at the same time are not compatible"));
+        }
+        let amount = if self.millis { Some(amount) } else { amount.checked_mul(1000) };
+        let deadline = if self.absolute { amount } else { amount.and_then(|ms| ms.checked_add(ctx.server.storage.now_ms() as i64)) };
+        let deadline = deadline.ok_or_else(|| Error::InvalidExpireTime(self.name.to_string()))?;
+        Ok(Value::Integer(ctx.server.storage.expire(key, Some(deadline.max(0) as u64), flags) as i64).into())
+    }
+}
+
+struct Persist;
+
+impl Command for Persist {
+    fn name(&self) -> &'static str {
+        "persist"
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let key = unpack_bytes(&args[0])?;
+        Ok(Value::Integer(ctx.server.storage.expire(key, None, ExpireFlags::default()) as i64).into())
+    }
+}
+
+// (P)EXPIRETIME key: the absolute deadline, -1 without one, -2 for a missing key
+struct ExpireTime {
+    name: &'static str,
+    millis: bool,
+}
+
+impl Command for ExpireTime {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let key = unpack_bytes(&args[0])?;
+        Ok(Value::Integer(match ctx.server.storage.expires_at(key) {
+            None => -2,
+            Some(None) => -1,
+            Some(Some(deadline)) if self.millis => deadline as i64,
+            Some(Some(deadline)) => (deadline / 1000) as i64,
+        }).into())
+    }
+}
+
+// (P)TTL key: the remaining time to live, -1 without a deadline, -2 for a missing key
+struct Ttl {
+    name: &'static str,
+    millis: bool,
+}
+
+impl Command for Ttl {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let key = unpack_bytes(&args[0])?;
+        Ok(Value::Integer(match ctx.server.storage.ttl(key) {
+            None => -2,
+            Some(None) => -1,
+            Some(Some(remaining)) if self.millis => remaining.as_millis() as i64,
+            Some(Some(remaining)) => ((remaining.as_millis() + 500) / 1000) as i64,
+        }).into())
+    }
+}
+
+// Keys looked at by each SCAN call unless COUNT says otherwise, as in Redis
+const SCAN_COUNT: usize = 10;
+
+// Redis types no key here holds, which SCAN's TYPE filter accepts along with every ValueType name
+const REDIS_ONLY_TYPES: [&str; 4] = ["list", "set", "zset", "stream"];
+
+// SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]: a batch of keys and the cursor for the next call, 0
+// when the iteration is done. COUNT is how many keys to look at, MATCH and TYPE filter those afterwards, so
+// a batch may well come back empty before the iteration ends. A tenant's clients only get its keys, without
+// its prefix.
+struct Scan;
+
+impl Command for Scan {
+    fn name(&self) -> &'static str {
+        "scan"
+    }
+
+    fn arity(&self) -> i64 {
+        -2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let cursor = std::str::from_utf8(unpack_bytes(&args[0])?).ok().and_then(|s| s.parse().ok()).ok_or_else(|| Error::reply("ERR invalid cursor"))?;
+        let mut pattern = None;
+        let mut count = SCAN_COUNT;
+        let mut kind = None;
+        let mut i = 1;
+        while i < args.len() {
+            match unpack_bulk_str(&args[i])?.to_lowercase().as_str() {
+                "match" if i + 1 < args.len() => pattern = Some(unpack_bytes(&args[i + 1])?),
+                "count" if i + 1 < args.len() => {
+                    count = match parse_int(&args[i + 1])? {
+                        count if count >= 1 => count as usize,
+                        _ => return Err(Error::Syntax),
+                    };
+                },
+                "type" if i + 1 < args.len() => {
+                    let name = unpack_bulk_str(&args[i + 1])?;
+                    let mut known = ValueType::ALL.iter().map(|kind| kind.as_str()).chain(REDIS_ONLY_TYPES);
+                    if !known.any(|known| known.eq_ignore_ascii_case(name)) {
+                        return Err(Error::Reply(format!("ERR unknown type name '{}'", name)));
+                    }
+                    kind = Some(name.to_string());
+                },
+                _ => return Err(Error::Syntax),
+            }
+            i += 2;
+        }
+
+        let storage = &ctx.server.storage;
+        let (next, mut keys) = storage.scan(cursor, count);
+        let prefix = ctx.session.tenant.as_deref().map(tenant::prefix);
+        if let Some(prefix) = &prefix {
+            keys.retain(|key| key.starts_with(prefix));
+        }
+        if let Some(kind) = kind {
+            keys.retain(|key| storage.value_type(key).is_some_and(|found| found.as_str().eq_ignore_ascii_case(&kind)));
+        }
+        if let Some(prefix) = &prefix {
+            for key in &mut keys {
+                *key = key.slice(prefix.len()..);
+            }
+        }
+        if let Some(pattern) = pattern {
+            keys.retain(|key| glob::matches(pattern, key));
+        }
+        Ok(Value::Array(vec![
+            Value::BulkString(next.to_string().into()),
+            Value::Array(keys.into_iter().map(Value::BulkString).collect()),
+        ]).into())
+    }
+}
+
+// DBSIZE: how many keys there are, or a tenant's clients how many it has
+struct DbSize;
+
+impl Command for DbSize {
+    fn name(&self) -> &'static str {
+        "dbsize"
+    }
+
+    fn arity(&self) -> i64 {
+        1
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn execute(&self, ctx: &mut Context, _args: &[Value]) -> Result<Reply> {
+        let storage = &ctx.server.storage;
+        let size = match &ctx.session.tenant {
+            Some(name) => storage.len_prefix(&tenant::prefix(name)),
+            None => storage.len(),
+        };
+        Ok(Value::Integer(size as i64).into())
+    }
+}
+
+// FLUSHDB | FLUSHALL [ASYNC | SYNC]: deletes every key, or a tenant's clients every key it has. There's only
+// database 0, so the two are the same, and both always delete before replying.
+struct Flush {
+    name: &'static str,
+}
+
+impl Command for Flush {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn arity(&self) -> i64 {
+        -1
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        match args {
+            [] => {},
+            [mode] if matches!(unpack_bulk_str(mode)?.to_lowercase().as_str(), "async" | "sync") => {},
+            _ => return Err(Error::Syntax),
+        }
+        let storage = &ctx.server.storage;
+        match &ctx.session.tenant {
+            Some(name) => storage.clear_prefix(&tenant::prefix(name)),
+            None => storage.clear(),
+        };
+        Ok(ok())
+    }
+}
@@ -0,0 +1,457 @@
+use std::collections::HashMap;
+use std::ops::BitOr;
+use std::time::{Duration, Instant};
+use bytes::Bytes;
+use crate::error::{Error, Result};
+use crate::locks;
+use crate::resp::Value;
+use crate::server::Server;
+use crate::session::Session;
+use crate::stats::{self, SERVER_STATS};
+use crate::tenant;
+use crate::trace::CommandSpan;
+
+mod bloom;
+mod connection;
+mod countmin;
+mod cuckoo;
+mod graph;
+mod hashes;
+mod index;
+mod json;
+mod keys;
+mod pubsub;
+mod search;
+mod server;
+mod strings;
+mod tdigest;
+mod timeseries;
+mod topk;
+mod transactions;
+mod zql;
+
+// Longer than any command's name, names past it aren't looked up
+const MAX_NAME_LEN: usize = 32;
+
+// Command flags, combined with |
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Flags(u32);
+
+impl Flags {
+    pub const NONE: Flags = Flags(0);
+    pub const WRITE: Flags = Flags(1); // May modify the keyspace
+    pub const READONLY: Flags = Flags(1 << 1); // Only reads the keyspace
+    pub const ADMIN: Flags = Flags(1 << 2);
+    pub const PUBSUB: Flags = Flags(1 << 3);
+    pub const FAST: Flags = Flags(1 << 4); // O(1) or O(log n)
+    pub const NO_AUTH: Flags = Flags(1 << 5); // Allowed before the client authenticated
+    pub const NO_MULTI: Flags = Flags(1 << 6); // Runs straight away inside MULTI instead of being queued
+    pub const EXCLUSIVE: Flags = Flags(1 << 7); // Takes the storage gate exclusively, see Storage
+    pub const SUBSCRIBED: Flags = Flags(1 << 8); // Allowed while a RESP2 client is subscribed
+
+    pub fn contains(self, other: Flags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    // Names as reported by COMMAND
+    fn names(self) -> Vec<&'static str> {
+        [
+            (Flags::WRITE, "write"),
+            (Flags::READONLY, "readonly"),
+            (Flags::ADMIN, "admin"),
+            (Flags::PUBSUB, "pubsub"),
+            (Flags::FAST, "fast"),
+            (Flags::NO_AUTH, "no_auth"),
+            (Flags::NO_MULTI, "no_multi"),
+            (Flags::SUBSCRIBED, "allow_subscribed"),
+        ]
+        .into_iter()
+        .filter(|(flag, _)| self.contains(*flag))
+        .map(|(_, name)| name)
+        .collect()
+    }
+}
+
+impl BitOr for Flags {
+    type Output = Flags;
+
+    fn bitor(self, other: Flags) -> Flags {
+        Flags(self.0 | other.0)
+    }
+}
+
+// Where the key arguments are, Redis-style: argv positions of the first and last key (negative counts from
+// the end) and the step between keys. Position 0 is the command name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeySpec {
+    pub first: usize,
+    pub last: i64,
+    pub step: usize,
+}
+
+impl KeySpec {
+    pub const NONE: KeySpec = KeySpec { first: 0, last: 0, step: 0 };
+    pub const FIRST: KeySpec = KeySpec { first: 1, last: 1, step: 1 };
+    pub const ALL: KeySpec = KeySpec { first: 1, last: -1, step: 1 };
+}
+
+// A command's replies; only subscription commands send more than one
+#[derive(Debug)]
+pub enum Reply {
+    Single(Value),
+    Multiple(Vec<Value>),
+}
+
+impl From<Value> for Reply {
+    fn from(value: Value) -> Self {
+        Reply::Single(value)
+    }
+}
+
+impl Reply {
+    fn into_values(self) -> Vec<Value> {
+        match self {
+            Reply::Single(value) => vec![value],
+            Reply::Multiple(values) => values,
+        }
+    }
+}
+
+// What a command gets to work with
+pub struct Context<'a> {
+    pub session: &'a mut Session,
+    pub server: &'a Server,
+    pub deadline: Option<Instant>, // When the command runs out of command-time-budget
+}
+
+impl Context<'_> {
+    // Long-running commands call this every so often and give up with the error once past the deadline.
+    // Only commands that can stop without leaving a half-done write behind check it.
+    pub fn check_budget(&self) -> Result<()> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                stats::incr(&SERVER_STATS.aborted_commands, 1);
+                Err(Error::TimeBudget(self.server.config().command_time_budget))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    // Starts the clock for the next command to run
+    fn start_budget(&mut self) {
+        self.deadline = match self.server.config().command_time_budget {
+            0 => None,
+            ms => Some(Instant::now() + Duration::from_millis(ms)),
+        };
+    }
+}
+
+pub trait Command: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Positive means exact argc (including the command name), negative means at least -arity
+    fn arity(&self) -> i64;
+
+    fn flags(&self) -> Flags {
+        Flags::NONE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::NONE
+    }
+
+    // `args` excludes the command name; arity has already been checked. Errors are replied to the client.
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply>;
+}
+
+// Checks run on every command after lookup and arity, before it is queued or executed. An error rejects
+// the command with that reply. This is where ACLs, cluster redirects and the like plug in.
+pub trait Hook: Send + Sync {
+    fn before(&self, command: &dyn Command, ctx: &Context, args: &[Value]) -> Result<()>;
+}
+
+// Clients must AUTH first when requirepass is set
+struct RequireAuth;
+
+impl Hook for RequireAuth {
+    fn before(&self, command: &dyn Command, ctx: &Context, _args: &[Value]) -> Result<()> {
+        // The password may have been removed by a config reload since the client connected
+        if ctx.session.authenticated || command.flags().contains(Flags::NO_AUTH) || ctx.server.config().requirepass.is_none() {
+            return Ok(());
+        }
+        Err(Error::NoAuth)
+    }
+}
+
+// Commands a tenant's clients may run besides those whose key spec names every key they touch. These don't
+// touch the keyspace, or keep to the tenant's keys themselves.
+const TENANT_KEYLESS: [&str; 14] = [
+    "ping", "echo", "hello", "auth", "select", "command", "multi", "exec", "discard", "unwatch", "scan", "dbsize",
+    "flushdb", "flushall",
+];
+// Name keys past their key spec, or add them to an index every tenant shares
+const TENANT_REFUSED: [&str; 3] = ["cms.merge", "tdigest.merge", "ft.add"];
+
+// Confines a tenant's clients to commands their keys can be put under the tenant's prefix for, see tenant.rs
+struct TenantScope;
+
+impl Hook for TenantScope {
+    fn before(&self, command: &dyn Command, ctx: &Context, args: &[Value]) -> Result<()> {
+        let Some(tenant) = &ctx.session.tenant else {
+            return Ok(());
+        };
+        let name = command.name();
+        let allowed = match name {
+            // Other clients are no business of a tenant's
+            "client" => match args.first() {
+                Some(Value::BulkString(sub)) => ["id", "getname", "setname", "info"].iter().any(|allowed| sub.eq_ignore_ascii_case(allowed.as_bytes())),
+                _ => false,
+            },
+            _ if TENANT_KEYLESS.contains(&name) => true,
+            _ => command.keys().step != 0 && !command.flags().contains(Flags::ADMIN) && !TENANT_REFUSED.contains(&name),
+        };
+        if allowed {
+            return Ok(());
+        }
+        Err(Error::Reply(format!("NOPERM User {} has no permissions to run the '{}' command", tenant, name)))
+    }
+}
+
+// Puts the keys a tenant's command names under the tenant's prefix, once the hooks let it through
+fn scope_keys(command: &dyn Command, prefix: &[u8], args: &mut [Value]) {
+    let keys = command.keys();
+    if keys.step == 0 {
+        return;
+    }
+    // Positions count the command's name, as COMMAND gives them
+    let argc = args.len() as i64 + 1;
+    let last = if keys.last < 0 { argc + keys.last } else { keys.last.min(argc - 1) };
+    for position in (keys.first..=last.max(0) as usize).step_by(keys.step) {
+        if let Some(Value::BulkString(key)) = args.get_mut(position - 1) {
+            *key = tenant::scoped(prefix, key);
+        }
+    }
+}
+
+// Once subscribed, RESP2 clients may only manage their subscriptions. RESP3 clients get push frames and
+// keep the full command set.
+struct SubscribedContext;
+
+impl Hook for SubscribedContext {
+    fn before(&self, command: &dyn Command, ctx: &Context, _args: &[Value]) -> Result<()> {
+        if ctx.session.protocol != 2 || !ctx.session.subscribed() || command.flags().contains(Flags::SUBSCRIBED) {
+            return Ok(());
+        }
+        Err(Error::Reply(format!(
+            "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+            command.name()
+        )))
+    }
+}
+
+// Every command the server knows, by lowercase name
+pub struct Registry {
+    commands: HashMap<&'static str, Box<dyn Command>>,
+    hooks: Vec<Box<dyn Hook>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        let mut registry = Registry { commands: HashMap::new(), hooks: vec![] };
+        bloom::register(&mut registry);
+        connection::register(&mut registry);
+        countmin::register(&mut registry);
+        cuckoo::register(&mut registry);
+        graph::register(&mut registry);
+        hashes::register(&mut registry);
+        index::register(&mut registry);
+        json::register(&mut registry);
+        keys::register(&mut registry);
+        pubsub::register(&mut registry);
+        search::register(&mut registry);
+        server::register(&mut registry);
+        strings::register(&mut registry);
+        tdigest::register(&mut registry);
+        timeseries::register(&mut registry);
+        topk::register(&mut registry);
+        transactions::register(&mut registry);
+        zql::register(&mut registry);
+        registry.add_hook(RequireAuth);
+        registry.add_hook(TenantScope);
+        registry.add_hook(SubscribedContext);
+        registry
+    }
+
+    pub fn add(&mut self, command: impl Command + 'static) {
+        self.commands.insert(command.name(), Box::new(command));
+    }
+
+    pub fn add_hook(&mut self, hook: impl Hook + 'static) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Command> {
+        self.commands.get(name).map(|command| command.as_ref())
+    }
+
+    // Looks a command up by its name in any case, lowercased on the stack
+    fn lookup(&self, name: &[u8]) -> Option<&dyn Command> {
+        let mut lowercase = [0; MAX_NAME_LEN];
+        let lowercase = lowercase.get_mut(..name.len())?;
+        lowercase.copy_from_slice(name);
+        lowercase.make_ascii_lowercase();
+        self.get(std::str::from_utf8(lowercase).ok()?)
+    }
+
+    // Every command, ordered by name
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Command> {
+        let mut commands: Vec<&dyn Command> = self.commands.values().map(|command| command.as_ref()).collect();
+        commands.sort_by_key(|command| command.name());
+        commands.into_iter()
+    }
+
+    // Runs one command to completion and records its stats. Deliberately not async, see locks.rs
+    pub fn process(&self, server: &Server, session: &mut Session, name: Bytes, mut args: Vec<Value>, span: &mut CommandSpan) -> Vec<Value> {
+        stats::incr(&SERVER_STATS.total_commands_processed, 1);
+        let Some(command) = self.lookup(&name) else {
+            let name = String::from_utf8_lossy(&name);
+            span.set_name(name.to_lowercase());
+            session.flag_multi_error();
+            return self.record_errors(server, span, vec![unknown_command(&name, &args).into_value()]);
+        };
+        let name = command.name();
+        span.set_name(name);
+        if !arity_ok(command.arity(), args.len()) {
+            session.flag_multi_error();
+            locks::lock(&server.stats).record_rejected(name);
+            return self.record_errors(server, span, vec![Error::WrongArity(command.name().to_string()).into_value()]);
+        }
+
+        let mut ctx = Context { session, server, deadline: None };
+        if let Err(rejection) = self.hooks.iter().try_for_each(|hook| hook.before(command, &ctx, &args)) {
+            ctx.session.flag_multi_error();
+            locks::lock(&server.stats).record_rejected(name);
+            let responses = vec![rejection.into_value()];
+            if audited(command) {
+                server.audit.record(ctx.session, name, &args, &responses);
+            }
+            return self.record_errors(server, span, responses);
+        }
+        if let Some(tenant) = &ctx.session.tenant {
+            scope_keys(command, &tenant::prefix(tenant), &mut args);
+        }
+        if ctx.session.multi.is_some() && !command.flags().contains(Flags::NO_MULTI) {
+            if let Some(transaction) = ctx.session.multi.as_mut() {
+                transaction.commands.push((name, args));
+            }
+            return vec![Value::SimpleString("QUEUED".to_string())];
+        }
+
+        let start = Instant::now();
+        span.phase("lock_wait");
+        let responses = {
+            // Held for the whole command, see Storage
+            let (_shared, _exclusive);
+            if command.flags().contains(Flags::EXCLUSIVE) {
+                _exclusive = server.storage.exclusive();
+            } else {
+                _shared = server.storage.shared();
+            }
+            span.phase("execute");
+            ctx.start_budget();
+            reply_values(command.execute(&mut ctx, &args))
+        };
+        let failed = responses.iter().any(|response| error_prefix(response).is_some());
+        locks::lock(&server.stats).record_call(name, start.elapsed(), failed);
+        if audited(command) {
+            server.audit.record(ctx.session, name, &args, &responses);
+        }
+        self.record_errors(server, span, responses)
+    }
+
+    // Runs a command queued by MULTI; lookup, arity and hooks were dealt with when it was queued. Each gets
+    // its own time budget.
+    pub fn execute_queued(&self, ctx: &mut Context, name: &str, args: &[Value]) -> Vec<Value> {
+        ctx.start_budget();
+        match self.get(name) {
+            Some(command) => {
+                let responses = reply_values(command.execute(ctx, args));
+                if audited(command) {
+                    ctx.server.audit.record(ctx.session, name, args, &responses);
+                }
+                responses
+            },
+            None => vec![unknown_command(name, args).into_value()],
+        }
+    }
+
+    fn record_errors(&self, server: &Server, span: &mut CommandSpan, responses: Vec<Value>) -> Vec<Value> {
+        for prefix in responses.iter().filter_map(error_prefix) {
+            locks::lock(&server.stats).record_error(prefix);
+            span.set_error(true);
+        }
+        responses
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry::new()
+    }
+}
+
+fn arity_ok(arity: i64, nargs: usize) -> bool {
+    let argc = nargs as i64 + 1;
+    (arity > 0 && argc == arity) || (arity < 0 && argc >= -arity)
+}
+
+// Whether the audit log records the command, see audit.rs
+fn audited(command: &dyn Command) -> bool {
+    command.flags().contains(Flags::WRITE) || command.flags().contains(Flags::ADMIN)
+}
+
+// The error code is the first word of the error reply, e.g. ERR or WRONGTYPE
+fn error_prefix(value: &Value) -> Option<&str> {
+    match value {
+        Value::Error(s) => s.split_whitespace().next(),
+        _ => None,
+    }
+}
+
+fn unknown_command(command: &str, args: &[Value]) -> Error {
+    let preview: String = args.iter()
+        .filter_map(|arg| unpack_bytes(arg).ok())
+        .map(|arg| format!("'{}' ", String::from_utf8_lossy(arg)))
+        .collect();
+    Error::UnknownCommand(command.to_string(), preview)
+}
+
+// A failed command replies with its error
+fn reply_values(result: Result<Reply>) -> Vec<Value> {
+    match result {
+        Ok(reply) => reply.into_values(),
+        Err(e) => vec![e.into_value()],
+    }
+}
+
+fn ok() -> Reply {
+    Value::SimpleString("OK".to_string()).into()
+}
+
+// Arguments are borrowed from the command; clone the Bytes, which only counts a reference, to keep one
+pub fn unpack_bulk_str(value: &Value) -> Result<&str> {
+    std::str::from_utf8(unpack_bytes(value)?).map_err(|_| Error::Syntax)
+}
+
+pub fn unpack_bytes(value: &Value) -> Result<&Bytes> {
+    static EMPTY: Bytes = Bytes::new();
+    match value {
+        Value::BulkString(b) => Ok(b),
+        Value::Null => Ok(&EMPTY),
+        _ => Err(Error::Protocol("expected bulk string arguments".to_string())),
+    }
+}
+
+pub fn parse_int(value: &Value) -> Result<i64> {
+    std::str::from_utf8(unpack_bytes(value)?).ok().and_then(|s| s.parse().ok()).ok_or(Error::NotInteger)
+}
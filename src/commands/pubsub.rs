@@ -0,0 +1,103 @@
+use bytes::Bytes;
+use crate::error::Result;
+use crate::resp::Value;
+use super::{unpack_bytes, Command, Context, Flags, Registry, Reply};
+
+pub fn register(registry: &mut Registry) {
+    registry.add(Subscribe);
+    registry.add(Unsubscribe);
+    registry.add(Publish);
+}
+
+// Confirmation sent for every channel (un)subscribed, with the connection's remaining subscription count
+fn confirmation(kind: &str, channel: Value, count: usize) -> Value {
+    Value::Push(vec![Value::BulkString(Bytes::copy_from_slice(kind.as_bytes())), channel, Value::Integer(count as i64)])
+}
+
+struct Subscribe;
+
+impl Command for Subscribe {
+    fn name(&self) -> &'static str {
+        "subscribe"
+    }
+
+    fn arity(&self) -> i64 {
+        -2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::PUBSUB | Flags::SUBSCRIBED
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let session = &mut *ctx.session;
+        let mut responses = vec![];
+        for arg in args {
+            let channel = unpack_bytes(arg)?.clone();
+            if session.subscriptions.insert(channel.clone()) {
+                ctx.server.pubsub.subscribe(&channel, session.id, session.sender.clone());
+            }
+            responses.push(confirmation("subscribe", Value::BulkString(channel), session.subscriptions.len()));
+        }
+        Ok(Reply::Multiple(responses))
+    }
+}
+
+// UNSUBSCRIBE [channel ...], all channels when none are given
+struct Unsubscribe;
+
+impl Command for Unsubscribe {
+    fn name(&self) -> &'static str {
+        "unsubscribe"
+    }
+
+    fn arity(&self) -> i64 {
+        -1
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::PUBSUB | Flags::SUBSCRIBED
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let session = &mut *ctx.session;
+        let channels: Vec<Bytes> = if args.is_empty() {
+            session.subscriptions.iter().cloned().collect()
+        } else {
+            args.iter().map(|arg| unpack_bytes(arg).cloned()).collect::<Result<_>>()?
+        };
+        if channels.is_empty() {
+            return Ok(confirmation("unsubscribe", Value::Null, 0).into());
+        }
+        let mut responses = vec![];
+        for channel in channels {
+            if session.subscriptions.remove(&channel) {
+                ctx.server.pubsub.unsubscribe(&channel, session.id);
+            }
+            responses.push(confirmation("unsubscribe", Value::BulkString(channel), session.subscriptions.len()));
+        }
+        Ok(Reply::Multiple(responses))
+    }
+}
+
+struct Publish;
+
+impl Command for Publish {
+    fn name(&self) -> &'static str {
+        "publish"
+    }
+
+    fn arity(&self) -> i64 {
+        3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::PUBSUB | Flags::FAST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let channel = unpack_bytes(&args[0])?;
+        let message = unpack_bytes(&args[1])?;
+        Ok(Value::Integer(ctx.server.pubsub.publish(channel, message) as i64).into())
+    }
+}
@@ -0,0 +1,325 @@
+use bytes::Bytes;
+use crate::error::{Error, Result};
+use crate::resp::Value;
+use crate::search::{DocumentKind, Knn, Query, SearchDef};
+use crate::storage::{Hash, StoredValue, ValueType};
+use crate::vector::VectorParams;
+use super::{ok, parse_int, unpack_bulk_str, unpack_bytes, Command, Context, Flags, KeySpec, Registry, Reply};
+
+pub fn register(registry: &mut Registry) {
+    registry.add(FtCreate);
+    registry.add(FtAdd);
+    registry.add(FtSearch);
+    registry.add(FtDropIndex);
+    registry.add(FtList);
+}
+
+fn unknown_index(name: &str) -> Error {
+    Error::Reply(format!("ERR {}: no such index", name))
+}
+
+// FT.CREATE index [ON HASH | JSON] [PREFIX count prefix ...] SCHEMA field [TEXT] [field [TEXT] ...]: indexes
+// the text in the fields of the hashes or documents whose keys start with one of the prefixes, every key's
+// without any. Document fields are JSONPaths. A field may instead be `field VECTOR FLAT | HNSW nargs name value
+// ...`, with the attributes of vector.rs. The index is built before the reply.
+struct FtCreate;
+
+impl Command for FtCreate {
+    fn name(&self) -> &'static str {
+        "ft.create"
+    }
+
+    fn arity(&self) -> i64 {
+        -4
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let name = unpack_bulk_str(&args[0])?.to_string();
+        let (mut on, mut prefixes) = (DocumentKind::default(), vec![]);
+        let mut i = 1;
+        loop {
+            let Some(option) = args.get(i) else {
+                return Err(Error::reply("ERR expected SCHEMA"));
+            };
+            match unpack_bulk_str(option)?.to_lowercase().as_str() {
+                "on" if i + 1 < args.len() => {
+                    on = DocumentKind::parse(unpack_bulk_str(&args[i + 1])?).map_err(|e| Error::Reply(format!("ERR {}", e)))?;
+                    i += 2;
+                },
+                "prefix" if i + 1 < args.len() => {
+                    let count = parse_int(&args[i + 1])?;
+                    let end = usize::try_from(count).ok().and_then(|count| (i + 2).checked_add(count)).filter(|&end| end <= args.len());
+                    let Some(end) = end else {
+                        return Err(Error::Syntax);
+                    };
+                    prefixes = args[i + 2..end].iter().map(|prefix| unpack_bytes(prefix).cloned()).collect::<Result<_>>()?;
+                    i = end;
+                },
+                "schema" => break,
+                _ => return Err(Error::Syntax),
+            }
+        }
+        let (mut fields, mut vectors) = (vec![], vec![]);
+        let mut schema = &args[i + 1..];
+        while let Some((field, rest)) = schema.split_first() {
+            let field = unpack_bulk_str(field)?.to_string();
+            let kind = match rest.first() {
+                Some(kind) => unpack_bulk_str(kind)?.to_lowercase(),
+                None => String::new(),
+            };
+            schema = match kind.as_str() {
+                "text" => {
+                    fields.push(field);
+                    &rest[1..]
+                },
+                "vector" => {
+                    let (params, rest) = parse_vector(&rest[1..])?;
+                    vectors.push((field, params));
+                    rest
+                },
+                "numeric" | "tag" | "geo" => return Err(Error::reply("ERR only TEXT and VECTOR fields can be searched")),
+                _ => {
+                    fields.push(field);
+                    rest
+                },
+            };
+        }
+        if fields.is_empty() && vectors.is_empty() {
+            return Err(Error::reply("ERR expected at least one field after SCHEMA"));
+        }
+        let def = SearchDef::new(name, on, prefixes, fields, vectors).map_err(|e| Error::Reply(format!("ERR {}", e)))?;
+        if !ctx.server.storage.create_search(def) {
+            return Err(Error::reply("ERR Index already exists"));
+        }
+        Ok(ok())
+    }
+}
+
+// A vector field's `algorithm nargs name value ...`, and the arguments after it
+fn parse_vector(args: &[Value]) -> Result<(VectorParams, &[Value])> {
+    let [algorithm, nargs, rest @ ..] = args else {
+        return Err(Error::Syntax);
+    };
+    let nargs = parse_int(nargs)?;
+    let Some(nargs) = usize::try_from(nargs).ok().filter(|&n| n <= rest.len() && n.is_multiple_of(2)) else {
+        return Err(Error::Syntax);
+    };
+    let attributes = rest[..nargs].chunks(2).map(|pair| Ok((unpack_bulk_str(&pair[0])?.to_string(), unpack_bulk_str(&pair[1])?.to_string())));
+    let attributes = attributes.collect::<Result<Vec<_>>>()?;
+    let params = VectorParams::parse(unpack_bulk_str(algorithm)?, &attributes).map_err(|e| Error::Reply(format!("ERR {}", e)))?;
+    Ok((params, &rest[nargs..]))
+}
+
+// FT.ADD index key [REPLACE] FIELDS field value [field value ...]: stores a hash for a hash index to pick up,
+// replacing the key's value with REPLACE and failing if it's set otherwise
+struct FtAdd;
+
+impl Command for FtAdd {
+    fn name(&self) -> &'static str {
+        "ft.add"
+    }
+
+    fn arity(&self) -> i64 {
+        -6
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec { first: 2, last: 2, step: 1 }
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let name = unpack_bulk_str(&args[0])?;
+        let key = unpack_bytes(&args[1])?;
+        let (replace, rest) = match unpack_bulk_str(&args[2])?.to_lowercase().as_str() {
+            "replace" => (true, &args[3..]),
+            _ => (false, &args[2..]),
+        };
+        let pairs = match rest.split_first() {
+            Some((fields, pairs)) if unpack_bulk_str(fields)?.eq_ignore_ascii_case("fields") && !pairs.is_empty() && pairs.len().is_multiple_of(2) => pairs,
+            _ => return Err(Error::Syntax),
+        };
+        let storage = &ctx.server.storage;
+        let searches = storage.searches();
+        let Some((def, _)) = searches.iter().find(|(def, _)| def.name == name) else {
+            return Err(unknown_index(name));
+        };
+        if def.on != DocumentKind::Hash {
+            return Err(Error::reply("ERR FT.ADD adds hashes, documents are set with JSON.SET"));
+        }
+        if !def.covers(key) {
+            return Err(Error::reply("ERR the key doesn't start with any of the index's prefixes"));
+        }
+        let fields = pairs.chunks(2).map(|pair| Ok((unpack_bytes(&pair[0])?.clone(), unpack_bytes(&pair[1])?.clone())));
+        let fields = fields.collect::<Result<Hash>>()?;
+        match storage.value_type(key) {
+            Some(ValueType::Hash) | None => {},
+            Some(_) if replace => {
+                storage.del(key);
+            },
+            Some(_) => return Err(Error::reply("ERR Document already exists")),
+        }
+        let added = storage.update::<Hash, _>(key, |hash| {
+            if hash.is_some() && !replace {
+                return (false, false);
+            }
+            *hash = Some(fields);
+            (true, true)
+        })?;
+        match added {
+            true => Ok(ok()),
+            false => Err(Error::reply("ERR Document already exists")),
+        }
+    }
+}
+
+// FT.SEARCH index query [NOCONTENT] [WITHSCORES] [LIMIT offset count] [PARAMS nargs name value ...]: how many
+// keys match, then a page of them, 10 unless LIMIT says otherwise, best match first. Each key is followed by
+// its score with WITHSCORES and, without NOCONTENT, its fields and values: a document's text as field `$`. See
+// search.rs for the query syntax. A KNN query's matches come nearest first, scored by their distance, which
+// their content also gives as field `__<field>_score`. Its vector is a parameter, packed as in a hash.
+struct FtSearch;
+
+impl Command for FtSearch {
+    fn name(&self) -> &'static str {
+        "ft.search"
+    }
+
+    fn arity(&self) -> i64 {
+        -3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let name = unpack_bulk_str(&args[0])?;
+        let invalid = |e| Error::Reply(format!("ERR invalid query: {}", e));
+        let (filter, knn) = Knn::split(unpack_bulk_str(&args[1])?).map_err(invalid)?;
+        let query = Query::parse(filter).map_err(invalid)?;
+        let (mut content, mut scores, mut offset, mut count) = (true, false, 0, 10);
+        let mut params = vec![];
+        let mut i = 2;
+        while i < args.len() {
+            match unpack_bulk_str(&args[i])?.to_lowercase().as_str() {
+                "nocontent" => content = false,
+                "withscores" => scores = true,
+                "limit" if i + 2 < args.len() => {
+                    match (parse_int(&args[i + 1])?, parse_int(&args[i + 2])?) {
+                        (from, n) if from >= 0 && n >= 0 => (offset, count) = (from as usize, n as usize),
+                        _ => return Err(Error::reply("ERR LIMIT offset and count can't be negative")),
+                    }
+                    i += 2;
+                },
+                "params" if i + 1 < args.len() => {
+                    let nargs = parse_int(&args[i + 1])?;
+                    let end = usize::try_from(nargs).ok().filter(|n| n.is_multiple_of(2)).and_then(|n| (i + 2).checked_add(n)).filter(|&end| end <= args.len());
+                    let Some(end) = end else {
+                        return Err(Error::Syntax);
+                    };
+                    params = args[i + 2..end].chunks(2).map(|pair| Ok((unpack_bulk_str(&pair[0])?, unpack_bytes(&pair[1])?))).collect::<Result<_>>()?;
+                    i = end - 1;
+                },
+                _ => return Err(Error::Syntax),
+            }
+            i += 1;
+        }
+        let storage = &ctx.server.storage;
+        let (found, score_field) = match knn {
+            None => {
+                let found = storage.search(name, &query, || ctx.check_budget())?.ok_or_else(|| unknown_index(name))?;
+                (found.into_iter().map(|(key, score, value)| (key, score.to_string(), value)).collect::<Vec<_>>(), None)
+            },
+            Some(knn) => {
+                let Some((_, blob)) = params.iter().find(|(name, _)| *name == knn.param) else {
+                    return Err(Error::Reply(format!("ERR no value given for parameter '{}'", knn.param)));
+                };
+                let searches = storage.searches();
+                let def = searches.iter().map(|(def, _)| def).find(|def| def.name == name).ok_or_else(|| unknown_index(name))?;
+                let Some(field) = def.vector_field(&knn.field) else {
+                    return Err(Error::Reply(format!("ERR {} isn't a vector field of {}", knn.field, name)));
+                };
+                let Some(vector) = def.vectors[field].1.from_blob(blob) else {
+                    return Err(Error::Reply(format!("ERR the query vector must be {} FLOAT32s", def.vectors[field].1.dim)));
+                };
+                let found = storage.search_nearest(name, &query, &knn.field, &vector, &knn, || ctx.check_budget())?.ok_or_else(|| unknown_index(name))?;
+                let found = found.into_iter().map(|(key, distance, value)| (key, distance.to_string(), value)).collect();
+                (found, Some(format!("__{}_score", knn.field)))
+            },
+        };
+        let mut reply = vec![Value::Integer(found.len() as i64)];
+        for (key, score, value) in found.into_iter().skip(offset).take(count) {
+            reply.push(Value::BulkString(key));
+            if scores {
+                reply.push(Value::BulkString(Bytes::from(score.clone())));
+            }
+            if content {
+                let mut fields: Vec<Value> = match value {
+                    StoredValue::Hash(hash) => hash.iter().flat_map(|(field, value)| [field.clone(), value.clone()]).map(Value::BulkString).collect(),
+                    value => vec![Value::BulkString(Bytes::from_static(b"$")), Value::BulkString(value.to_bytes())],
+                };
+                if let Some(field) = &score_field {
+                    fields.push(Value::BulkString(Bytes::from(field.clone())));
+                    fields.push(Value::BulkString(Bytes::from(score)));
+                }
+                reply.push(Value::Array(fields));
+            }
+        }
+        Ok(Value::Array(reply).into())
+    }
+}
+
+// FT.DROPINDEX index: forgets the index, leaving the keys it covered as they are
+struct FtDropIndex;
+
+impl Command for FtDropIndex {
+    fn name(&self) -> &'static str {
+        "ft.dropindex"
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let name = unpack_bulk_str(&args[0])?;
+        if !ctx.server.storage.drop_search(name) {
+            return Err(unknown_index(name));
+        }
+        Ok(ok())
+    }
+}
+
+// FT._LIST: the names of the search indexes
+struct FtList;
+
+impl Command for FtList {
+    fn name(&self) -> &'static str {
+        "ft._list"
+    }
+
+    fn arity(&self) -> i64 {
+        1
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn execute(&self, ctx: &mut Context, _args: &[Value]) -> Result<Reply> {
+        let names = ctx.server.storage.searches().into_iter().map(|(def, _)| Value::BulkString(Bytes::from(def.name.clone())));
+        Ok(Value::Array(names.collect()).into())
+    }
+}
@@ -0,0 +1,467 @@
+use bytes::Bytes;
+use crate::allocator::MemoryStats;
+use crate::audit::AuditEntry;
+use crate::bucket;
+use crate::cdc::Follow;
+use crate::error::{Error, Result};
+use crate::locks;
+use crate::resp::Value;
+use crate::shared::SHARED_INTEGERS;
+use crate::snapshot::{self, ImportMode};
+use crate::stats::SERVER_STATS;
+use crate::storage::ValueType;
+use super::{ok, parse_int, unpack_bulk_str, unpack_bytes, Command, Context, Flags, Registry, Reply};
+
+pub fn register(registry: &mut Registry) {
+    registry.add(Info);
+    registry.add(CommandInfo);
+    registry.add(Memory);
+    registry.add(Snapshot);
+    registry.add(Cdc);
+    registry.add(Audit);
+    registry.add(Zenql);
+    registry.add(TtlStats);
+}
+
+// INFO [section]
+struct Info;
+
+impl Command for Info {
+    fn name(&self) -> &'static str {
+        "info"
+    }
+
+    fn arity(&self) -> i64 {
+        -1
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let section = match args.first() {
+            Some(arg) => unpack_bulk_str(arg)?.to_lowercase(),
+            None => "all".to_string(),
+        };
+        let stats_lock = locks::lock(&ctx.server.stats);
+        let info = match section.as_str() {
+            "clients" => SERVER_STATS.clients_info(),
+            "stats" => SERVER_STATS.info(),
+            "replication" => SERVER_STATS.replication_info(),
+            "memory" => MemoryStats::read().info(),
+            "commandstats" => stats_lock.commandstats(),
+            "errorstats" => stats_lock.errorstats(),
+            "all" | "everything" | "default" => format!(
+                "{}\r\n{}\r\n{}\r\n{}\r\n{}\r\n{}",
+                SERVER_STATS.clients_info(),
+                MemoryStats::read().info(),
+                SERVER_STATS.info(),
+                SERVER_STATS.replication_info(),
+                stats_lock.commandstats(),
+                stats_lock.errorstats(),
+            ),
+            _ => String::new(),
+        };
+        Ok(Value::BulkString(info.into()).into())
+    }
+}
+
+// COMMAND [COUNT | INFO [name ...]]: describes the registered commands the way Redis does
+struct CommandInfo;
+
+impl Command for CommandInfo {
+    fn name(&self) -> &'static str {
+        "command"
+    }
+
+    fn arity(&self) -> i64 {
+        -1
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let registry = &ctx.server.commands;
+        let Some(subcommand) = args.first() else {
+            return Ok(Value::Array(registry.iter().map(describe).collect()).into());
+        };
+        match unpack_bulk_str(subcommand)?.to_lowercase().as_str() {
+            "count" if args.len() == 1 => Ok(Value::Integer(registry.iter().count() as i64).into()),
+            "info" => {
+                let mut commands = vec![];
+                for name in &args[1..] {
+                    let name = unpack_bulk_str(name)?.to_lowercase();
+                    commands.push(registry.get(&name).map_or(Value::NullArray, describe));
+                }
+                Ok(Value::Array(commands).into())
+            },
+            "count" => Err(Error::WrongArity("command|count".to_string())),
+            other => Err(Error::UnknownSubcommand("command".to_string(), other.to_string())),
+        }
+    }
+}
+
+// MEMORY STATS: a subset of Redis's fields, those on allocation only with the counting allocator installed, plus how many values are kept as integers, compressed, on disk or in the cold tier
+struct Memory;
+
+impl Command for Memory {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn arity(&self) -> i64 {
+        -2
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        match unpack_bulk_str(&args[0])?.to_lowercase().as_str() {
+            "stats" if args.len() == 1 => {
+                let storage = &ctx.server.storage;
+                let counts = storage.encoding_counts();
+                let memory = MemoryStats::read();
+                let name = |name: &str| Value::BulkString(Bytes::copy_from_slice(name.as_bytes()));
+                let field = |field: &str, value: usize| (name(field), Value::Integer(value as i64));
+                let mut fields = vec![];
+                // Only what the allocator and the kernel can tell, see allocator.rs
+                if let (Some(allocated), Some(peak)) = (memory.allocated, memory.peak) {
+                    fields.extend([field("peak.allocated", peak), field("total.allocated", allocated)]);
+                }
+                if let Some(resident) = memory.resident {
+                    fields.push(field("allocator.resident", resident));
+                }
+                if let Some(ratio) = memory.fragmentation() {
+                    fields.push((name("fragmentation"), Value::Double(ratio)));
+                }
+                fields.extend([
+                    field("keys.count", storage.len()),
+                    field("keys.int-values", counts.int_values),
+                    field("keys.compressed", counts.compressed_values),
+                    field("keys.on-disk", counts.disk_values),
+                    field("keys.spilled", counts.spilled_values),
+                    field("tier.bytes", counts.spilled_bytes),
+                    field("compression.bytes-saved", counts.compression_saved_bytes),
+                    field("shared.integers", SHARED_INTEGERS),
+                ]);
+                Ok(Value::Map(fields).into())
+            },
+            "stats" => Err(Error::WrongArity("memory|stats".to_string())),
+            other => Err(Error::UnknownSubcommand("memory".to_string(), other.to_string())),
+        }
+    }
+}
+
+// SNAPSHOT EXPORT <path> [INCREMENTAL] | IMPORT <path> [MERGE|REPLACE]: writes the keyspace at one point in
+// time to a file, or only what changed since the last export, or loads one, replying with the number of
+// keys; see snapshot.rs. Relative paths are in dir. With snapshot-bucket set, exports are uploaded there
+// too before the reply, see bucket.rs. Other clients carry on meanwhile, apart from writers waiting while an
+// export copies the keyspace, and may see an import half done.
+struct Snapshot;
+
+impl Command for Snapshot {
+    fn name(&self) -> &'static str {
+        "snapshot"
+    }
+
+    fn arity(&self) -> i64 {
+        -3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::ADMIN | Flags::WRITE
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let subcommand = unpack_bulk_str(&args[0])?.to_lowercase();
+        let path = ctx.server.config().dir.join(unpack_bulk_str(&args[1])?);
+        let storage = &ctx.server.storage;
+        let keys = match (subcommand.as_str(), args.len()) {
+            ("export", 2 | 3) => {
+                let incremental = match args.get(2).map(unpack_bulk_str).transpose()?.map(|kind| kind.to_lowercase()).as_deref() {
+                    None => false,
+                    Some("incremental") => true,
+                    Some(_) => return Err(Error::Syntax),
+                };
+                let keys = ctx.server.snapshots.export(storage, &path, incremental)
+                    .map_err(|e| Error::reply(format!("ERR snapshot export to '{}' failed: {}", path.display(), e)))?;
+                bucket::upload(&ctx.server.config(), &path)
+                    .map_err(|e| Error::reply(format!("ERR snapshot written to '{}', but uploading it failed: {}", path.display(), e)))?;
+                keys
+            },
+            ("import", 2 | 3) => {
+                let mode = match args.get(2).map(unpack_bulk_str).transpose()?.map(|mode| mode.to_lowercase()).as_deref() {
+                    None | Some("merge") => ImportMode::Merge,
+                    Some("replace") => ImportMode::Replace,
+                    Some(_) => return Err(Error::Syntax),
+                };
+                snapshot::import(storage, &path, mode)
+                    .map_err(|e| Error::reply(format!("ERR snapshot import from '{}' failed: {}", path.display(), e)))?
+            },
+            ("export" | "import", _) => return Err(Error::WrongArity(format!("snapshot|{}", subcommand))),
+            (other, _) => return Err(Error::UnknownSubcommand("snapshot".to_string(), other.to_string())),
+        };
+        Ok(Value::Integer(keys as i64).into())
+    }
+}
+
+// CDC [MATCH pattern]: turns the connection into a change feed, a snapshot of the keys matching the
+// pattern followed by their changes, see cdc.rs. Nothing else can be sent on it afterwards.
+struct Cdc;
+
+impl Command for Cdc {
+    fn name(&self) -> &'static str {
+        "cdc"
+    }
+
+    fn arity(&self) -> i64 {
+        -1
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::ADMIN | Flags::READONLY | Flags::NO_MULTI
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let pattern = match args {
+            [] => None,
+            [option, pattern] if unpack_bulk_str(option)?.eq_ignore_ascii_case("match") => Some(unpack_bytes(pattern)?.clone()),
+            _ => return Err(Error::Syntax),
+        };
+        if ctx.session.multi.is_some() {
+            return Err(Error::reply("ERR CDC is not allowed in transactions"));
+        }
+        if ctx.session.subscribed() {
+            return Err(Error::reply("ERR CDC is not allowed while subscribed"));
+        }
+        ctx.session.follow = Some(Follow { pattern });
+        Ok(ok())
+    }
+}
+
+// AUDIT GET [count] | LEN: the latest audit log entries, newest first, 10 unless given a count, or how many
+// are kept in memory; see audit.rs. Each entry is [id, time in ms, client, name, user, db, command, result].
+struct Audit;
+
+impl Command for Audit {
+    fn name(&self) -> &'static str {
+        "audit"
+    }
+
+    fn arity(&self) -> i64 {
+        -2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::ADMIN | Flags::READONLY
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let audit = &ctx.server.audit;
+        let subcommand = unpack_bulk_str(&args[0])?.to_lowercase();
+        if !matches!(subcommand.as_str(), "get" | "len") {
+            return Err(Error::UnknownSubcommand("audit".to_string(), subcommand));
+        }
+        if !audit.enabled() {
+            return Err(Error::reply("ERR the audit log is disabled, set audit-log to enable it"));
+        }
+        match (subcommand.as_str(), args.len()) {
+            ("get", 1 | 2) => {
+                let count = match args.get(1) {
+                    Some(count) => usize::try_from(parse_int(count)?).map_err(|_| Error::reply("ERR count must be positive"))?,
+                    None => 10,
+                };
+                Ok(Value::Array(audit.recent(count).iter().map(audit_entry).collect()).into())
+            },
+            ("len", 1) => Ok(Value::Integer(audit.len() as i64).into()),
+            _ => Err(Error::WrongArity(format!("audit|{}", subcommand))),
+        }
+    }
+}
+
+fn audit_entry(entry: &AuditEntry) -> Value {
+    let bulk = |s: &str| Value::BulkString(Bytes::copy_from_slice(s.as_bytes()));
+    Value::Array(vec![
+        Value::Integer(entry.id as i64),
+        Value::Integer(entry.time_ms as i64),
+        bulk(&entry.client),
+        entry.name.clone().map_or(Value::Null, Value::BulkString),
+        bulk(&entry.user),
+        Value::Integer(entry.db as i64),
+        Value::Array(entry.command.iter().map(|part| bulk(part)).collect()),
+        bulk(&entry.result),
+    ])
+}
+
+// ZENQL BIGKEYS [SAMPLES count]: zenql's own analytics, by subcommand. BIGKEYS reports the biggest key of
+// each type and how many keys and how much of the type there is in all, as redis-cli --bigkeys does but
+// without the keys leaving the server. It walks the keyspace a SCAN batch at a time, or only the first
+// `count` keys in hash order, which are as good as a random sample. Sizes come from what's kept of each
+// value without reading it in, in the type's unit, see StoredValue::size. Being a walk of the keyspace it's
+// an admin command, and gives up past command-time-budget.
+struct Zenql;
+
+// Keys looked at per batch, a shard lock taken for each
+const BIGKEYS_BATCH: usize = 1000;
+
+#[derive(Default)]
+struct TypeSizes {
+    keys: u64,
+    total: u64,
+    biggest: Option<(Bytes, u64)>,
+}
+
+impl Command for Zenql {
+    fn name(&self) -> &'static str {
+        "zenql"
+    }
+
+    fn arity(&self) -> i64 {
+        -2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::ADMIN | Flags::READONLY
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        match unpack_bulk_str(&args[0])?.to_lowercase().as_str() {
+            "bigkeys" => bigkeys(ctx, &args[1..]),
+            other => Err(Error::UnknownSubcommand("zenql".to_string(), other.to_string())),
+        }
+    }
+}
+
+fn bigkeys(ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+    let samples = match args {
+        [] => usize::MAX,
+        [option, count] if unpack_bulk_str(option)?.eq_ignore_ascii_case("samples") => match parse_int(count)? {
+            count if count >= 1 => count as usize,
+            _ => return Err(Error::reply("ERR samples must be positive")),
+        },
+        _ => return Err(Error::Syntax),
+    };
+    let storage = &ctx.server.storage;
+    let mut types: Vec<(ValueType, TypeSizes)> = vec![];
+    let (mut cursor, mut scanned) = (0, 0);
+    loop {
+        ctx.check_budget()?;
+        let (next, keys) = storage.scan(cursor, BIGKEYS_BATCH.min(samples - scanned));
+        for key in keys {
+            // Gone or expired since the batch was taken
+            let Some((kind, size)) = storage.value_size(&key) else {
+                continue;
+            };
+            let index = match types.iter().position(|(found, _)| *found == kind) {
+                Some(index) => index,
+                None => {
+                    types.push((kind, TypeSizes::default()));
+                    types.len() - 1
+                },
+            };
+            let sizes = &mut types[index].1;
+            sizes.keys += 1;
+            sizes.total += size;
+            if sizes.biggest.as_ref().is_none_or(|(_, biggest)| size > *biggest) {
+                sizes.biggest = Some((key, size));
+            }
+            scanned += 1;
+        }
+        cursor = next;
+        if cursor == 0 || scanned >= samples {
+            break;
+        }
+    }
+
+    types.sort_by_key(|(kind, _)| kind.as_str());
+    let bulk = |s: &str| Value::BulkString(Bytes::copy_from_slice(s.as_bytes()));
+    let types = types.into_iter().map(|(kind, sizes)| {
+        let (key, size) = sizes.biggest.unwrap_or_default();
+        Value::Map(vec![
+            (bulk("type"), bulk(kind.as_str())),
+            (bulk("keys"), Value::Integer(sizes.keys as i64)),
+            (bulk("unit"), bulk(kind.size_unit())),
+            (bulk("total"), Value::Integer(sizes.total as i64)),
+            (bulk("biggest"), Value::BulkString(key)),
+            (bulk("biggest.size"), Value::Integer(size as i64)),
+        ])
+    }).collect();
+    Ok(Value::Map(vec![
+        (bulk("scanned"), Value::Integer(scanned as i64)),
+        (bulk("types"), Value::Array(types)),
+    ]).into())
+}
+
+// TTLSTATS [MATCH pattern]: how the live keys' remaining TTLs are spread, or those of the keys matching a
+// glob pattern, to see expiry storms coming: how many keys there are with and without a TTL, the shortest,
+// longest and average TTL in ms, null with none, and how many keys expire within each of TTL_BUCKETS and
+// not in an earlier one. Without a pattern only keys with a TTL are looked at. Gives up past
+// command-time-budget.
+struct TtlStats;
+
+// Upper bounds of the histogram's buckets, in ms, and their names
+const TTL_BUCKETS: [(&str, u64); 10] = [
+    ("1s", 1000),
+    ("1m", 60 * 1000),
+    ("5m", 5 * 60 * 1000),
+    ("15m", 15 * 60 * 1000),
+    ("1h", 60 * 60 * 1000),
+    ("6h", 6 * 60 * 60 * 1000),
+    ("1d", 24 * 60 * 60 * 1000),
+    ("7d", 7 * 24 * 60 * 60 * 1000),
+    ("30d", 30 * 24 * 60 * 60 * 1000),
+    ("inf", u64::MAX),
+];
+
+impl Command for TtlStats {
+    fn name(&self) -> &'static str {
+        "ttlstats"
+    }
+
+    fn arity(&self) -> i64 {
+        -1
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let pattern = match args {
+            [] => None,
+            [option, pattern] if unpack_bulk_str(option)?.eq_ignore_ascii_case("match") => Some(unpack_bytes(pattern)?),
+            _ => return Err(Error::Syntax),
+        };
+        let mut counts = [0; TTL_BUCKETS.len()];
+        let (mut volatile, mut total, mut min, mut max) = (0, 0u128, u64::MAX, 0);
+        let persistent = ctx.server.storage.ttls(pattern.map(|pattern| &pattern[..]), || ctx.check_budget(), |ttl| {
+            counts[TTL_BUCKETS.iter().position(|(_, bound)| ttl <= *bound).unwrap_or(TTL_BUCKETS.len() - 1)] += 1;
+            volatile += 1;
+            total += ttl as u128;
+            (min, max) = (min.min(ttl), max.max(ttl));
+        })?;
+
+        let bulk = |s: &str| Value::BulkString(Bytes::copy_from_slice(s.as_bytes()));
+        let ms = |ms: u64| match volatile {
+            0 => Value::Null,
+            _ => Value::Integer(ms.min(i64::MAX as u64) as i64),
+        };
+        let average = total.checked_div(volatile as u128).unwrap_or_default() as u64;
+        let histogram = TTL_BUCKETS.iter().zip(counts).map(|((name, _), count)| (bulk(name), Value::Integer(count))).collect();
+        Ok(Value::Map(vec![
+            (bulk("keys"), Value::Integer((persistent + volatile) as i64)),
+            (bulk("persistent"), Value::Integer(persistent as i64)),
+            (bulk("volatile"), Value::Integer(volatile as i64)),
+            (bulk("ttl.min"), ms(min)),
+            (bulk("ttl.max"), ms(max)),
+            (bulk("ttl.avg"), ms(average)),
+            (bulk("histogram"), Value::Map(histogram)),
+        ]).into())
+    }
+}
+
+fn describe(command: &dyn Command) -> Value {
+    let keys = command.keys();
+    let bulk = |s: &str| Value::BulkString(Bytes::copy_from_slice(s.as_bytes()));
+    Value::Array(vec![
+        bulk(command.name()),
+        Value::Integer(command.arity()),
+        Value::Array(command.flags().names().into_iter().map(|flag| Value::SimpleString(flag.to_string())).collect()),
+        Value::Integer(keys.first as i64),
+        Value::Integer(keys.last),
+        Value::Integer(keys.step as i64),
+    ])
+}
@@ -0,0 +1,272 @@
+use crate::error::{Error, Result};
+use crate::resp::Value;
+use crate::storage::IncrError;
+use super::{ok, parse_int, unpack_bulk_str, unpack_bytes, Command, Context, Flags, KeySpec, Registry, Reply};
+
+pub fn register(registry: &mut Registry) {
+    registry.add(Set);
+    registry.add(Get);
+    registry.add(Incr { name: "incr", by_argument: false, decrement: false });
+    registry.add(Incr { name: "decr", by_argument: false, decrement: true });
+    registry.add(Incr { name: "incrby", by_argument: true, decrement: false });
+    registry.add(Incr { name: "decrby", by_argument: true, decrement: true });
+    registry.add(Lcs);
+}
+
+// SET key value [EX seconds | PX milliseconds | EXAT unix-seconds | PXAT unix-milliseconds]
+struct Set;
+
+impl Command for Set {
+    fn name(&self) -> &'static str {
+        "set"
+    }
+
+    fn arity(&self) -> i64 {
+        -3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let key = unpack_bytes(&args[0])?.clone();
+        let value = unpack_bytes(&args[1])?.clone();
+        let expires_at = match &args[2..] {
+            [] => None,
+            [unit, amount] => {
+                let unit = unpack_bulk_str(unit)?.to_lowercase();
+                let amount = match parse_int(amount)? {
+                    amount if amount > 0 => amount as u64,
+                    _ => return Err(Error::InvalidExpireTime("set".to_string())),
+                };
+                let deadline = match unit.as_str() {
+                    "ex" => amount.checked_mul(1000).and_then(|ms| ms.checked_add(ctx.server.storage.now_ms())),
+                    "px" => amount.checked_add(ctx.server.storage.now_ms()),
+                    "exat" => amount.checked_mul(1000),
+                    "pxat" => Some(amount),
+                    _ => return Err(Error::Syntax),
+                };
+                match deadline {
+                    Some(deadline) if deadline <= i64::MAX as u64 => Some(deadline),
+                    _ => return Err(Error::InvalidExpireTime("set".to_string())),
+                }
+            },
+            _ => return Err(Error::Syntax),
+        };
+        ctx.server.storage.set(key, value, expires_at);
+        Ok(ok())
+    }
+}
+
+struct Get;
+
+impl Command for Get {
+    fn name(&self) -> &'static str {
+        "get"
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        match ctx.server.storage.get(unpack_bytes(&args[0])?)? {
+            Some(value) => Ok(Value::BulkString(value).into()),
+            None => Ok(Value::Null.into()),
+        }
+    }
+}
+
+// INCR key, DECR key, INCRBY key increment, DECRBY key decrement
+struct Incr {
+    name: &'static str,
+    by_argument: bool, // Steps by the argument rather than 1
+    decrement: bool,
+}
+
+impl Command for Incr {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn arity(&self) -> i64 {
+        if self.by_argument { 3 } else { 2 }
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let step = if self.by_argument { parse_int(&args[1])? } else { 1 };
+        let delta = match self.decrement {
+            true => step.checked_neg().ok_or_else(|| Error::reply("ERR decrement would overflow"))?,
+            false => step,
+        };
+        match ctx.server.storage.incr_by(unpack_bytes(&args[0])?.clone(), delta) {
+            Ok(value) => Ok(Value::Integer(value).into()),
+            Err(IncrError::NotInteger) => Err(Error::NotInteger),
+            Err(IncrError::Overflow) => Err(Error::reply("ERR increment or decrement would overflow")),
+            Err(IncrError::WrongType) => Err(Error::WrongType),
+        }
+    }
+}
+
+// LCS key1 key2 [LEN] [IDX] [MINMATCHLEN len] [WITHMATCHLEN]. Missing keys count as empty strings.
+struct Lcs;
+
+impl Command for Lcs {
+    fn name(&self) -> &'static str {
+        "lcs"
+    }
+
+    fn arity(&self) -> i64 {
+        -3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec { first: 1, last: 2, step: 1 }
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let (mut len, mut idx, mut with_match_len, mut min_match_len) = (false, false, false, 0);
+        let mut options = args[2..].iter();
+        while let Some(option) = options.next() {
+            match unpack_bulk_str(option)?.to_lowercase().as_str() {
+                "len" => len = true,
+                "idx" => idx = true,
+                "withmatchlen" => with_match_len = true,
+                "minmatchlen" => {
+                    let value = options.next().ok_or(Error::Syntax)?;
+                    min_match_len = parse_int(value)?.max(0) as usize;
+                },
+                _ => return Err(Error::Syntax),
+            }
+        }
+        if len && idx {
+            return Err(Error::reply("ERR If you want both the length and indexes, please just use IDX."));
+        }
+
+        let storage = &ctx.server.storage;
+        let a = storage.get(unpack_bytes(&args[0])?)?.unwrap_or_default();
+        let b = storage.get(unpack_bytes(&args[1])?)?.unwrap_or_default();
+        // The table holds one u32 per pair of prefixes
+        let cells = (a.len() as u64 + 1) * (b.len() as u64 + 1);
+        if cells > u32::MAX as u64 {
+            return Err(Error::reply("ERR String too long for LCS"));
+        }
+        if cells * 4 > ctx.server.config().proto_max_bulk_len as u64 {
+            return Err(Error::reply("ERR Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len"));
+        }
+        let table = lcs_table(ctx, &a, &b)?;
+        let width = b.len() + 1;
+        let lcs_len = table[a.len() * width + b.len()] as usize;
+        if len {
+            return Ok(Value::Integer(lcs_len as i64).into());
+        }
+
+        // Walk back from the end of both strings, collecting the LCS and the ranges where they match. Ranges
+        // come out last first, as Redis reports them.
+        let mut lcs = vec![0; lcs_len];
+        let mut matches = vec![];
+        let (mut i, mut j, mut k) = (a.len(), b.len(), lcs_len);
+        let mut range: Option<((usize, usize), (usize, usize))> = None; // Current match in a and in b
+        while i > 0 && j > 0 {
+            let mut emit = false;
+            if a[i - 1] == b[j - 1] {
+                lcs[k - 1] = a[i - 1];
+                match &mut range {
+                    None => range = Some(((i - 1, i - 1), (j - 1, j - 1))),
+                    // Contiguous with the current match, extend it backwards
+                    Some((a_range, b_range)) if a_range.0 == i && b_range.0 == j => {
+                        a_range.0 -= 1;
+                        b_range.0 -= 1;
+                    },
+                    Some(_) => emit = true,
+                }
+                // The start of either string ends the walk
+                if range.is_some_and(|(a_range, b_range)| a_range.0 == 0 || b_range.0 == 0) {
+                    emit = true;
+                }
+                k -= 1;
+                i -= 1;
+                j -= 1;
+            } else {
+                if table[(i - 1) * width + j] > table[i * width + j - 1] {
+                    i -= 1;
+                } else {
+                    j -= 1;
+                }
+                emit = range.is_some();
+            }
+            if emit {
+                if let Some((a_range, b_range)) = range.take() {
+                    let match_len = a_range.1 - a_range.0 + 1;
+                    if match_len >= min_match_len {
+                        matches.push(lcs_match(a_range, b_range, with_match_len.then_some(match_len)));
+                    }
+                }
+            }
+        }
+
+        if !idx {
+            return Ok(Value::BulkString(lcs.into()).into());
+        }
+        Ok(Value::Map(vec![
+            (Value::BulkString("matches".into()), Value::Array(matches)),
+            (Value::BulkString("len".into()), Value::Integer(lcs_len as i64)),
+        ])
+        .into())
+    }
+}
+
+// Row-major (a.len() + 1) x (b.len() + 1) table of LCS lengths: cell (i, j) is the length of the LCS of the
+// first i bytes of `a` and the first j bytes of `b`
+fn lcs_table(ctx: &Context, a: &[u8], b: &[u8]) -> Result<Vec<u32>> {
+    let width = b.len() + 1;
+    let mut table = vec![0u32; (a.len() + 1) * width];
+    // The budget is checked about every 64k cells
+    let rows_per_check = (65_536 / width).max(1);
+    for i in 1..=a.len() {
+        if i % rows_per_check == 0 {
+            ctx.check_budget()?;
+        }
+        for j in 1..=b.len() {
+            table[i * width + j] = if a[i - 1] == b[j - 1] {
+                table[(i - 1) * width + j - 1] + 1
+            } else {
+                table[(i - 1) * width + j].max(table[i * width + j - 1])
+            };
+        }
+    }
+    Ok(table)
+}
+
+// [[a_start, a_end], [b_start, b_end]] with the length appended for WITHMATCHLEN
+fn lcs_match(a_range: (usize, usize), b_range: (usize, usize), len: Option<usize>) -> Value {
+    let range = |(start, end): (usize, usize)| Value::Array(vec![Value::Integer(start as i64), Value::Integer(end as i64)]);
+    let mut entry = vec![range(a_range), range(b_range)];
+    entry.extend(len.map(|len| Value::Integer(len as i64)));
+    Value::Array(entry)
+}
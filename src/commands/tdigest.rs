@@ -0,0 +1,327 @@
+use crate::error::{Error, Result};
+use crate::resp::Value;
+use crate::tdigest::{TDigest, DEFAULT_COMPRESSION};
+use super::{ok, parse_int, unpack_bulk_str, unpack_bytes, Command, Context, Flags, KeySpec, Registry, Reply};
+
+pub fn register(registry: &mut Registry) {
+    registry.add(TdigestCreate);
+    registry.add(TdigestAdd);
+    registry.add(TdigestReset);
+    registry.add(TdigestMerge);
+    registry.add(TdigestEstimate { estimate: Estimate::Quantile });
+    registry.add(TdigestEstimate { estimate: Estimate::Cdf });
+    registry.add(TdigestExtreme { max: false });
+    registry.add(TdigestExtreme { max: true });
+    registry.add(TdigestInfo);
+}
+
+fn parse_float(arg: &Value) -> Result<f64> {
+    unpack_bulk_str(arg)?.parse::<f64>().ok().filter(|value| value.is_finite()).ok_or_else(|| Error::reply("ERR T-Digest: error parsing value"))
+}
+
+fn parse_compression(arg: &Value) -> Result<f64> {
+    let compression = parse_int(arg)?;
+    match compression > 0 {
+        true => Ok(compression as f64),
+        false => Err(Error::reply("ERR T-Digest: compression must be positive")),
+    }
+}
+
+fn new_digest(compression: f64) -> Result<TDigest> {
+    TDigest::new(compression).map_err(|e| Error::Reply(format!("ERR T-Digest: {}", e)))
+}
+
+fn not_found() -> Error {
+    Error::reply("ERR T-Digest: key does not exist")
+}
+
+// TDIGEST.CREATE key [COMPRESSION compression]: an empty digest keeping about `compression` centroids, 100 by
+// default
+struct TdigestCreate;
+
+impl Command for TdigestCreate {
+    fn name(&self) -> &'static str {
+        "tdigest.create"
+    }
+
+    fn arity(&self) -> i64 {
+        -2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let compression = match &args[1..] {
+            [] => DEFAULT_COMPRESSION,
+            [option, arg] if unpack_bulk_str(option)?.eq_ignore_ascii_case("compression") => parse_compression(arg)?,
+            _ => return Err(Error::Syntax),
+        };
+        let digest = new_digest(compression)?;
+        let created = ctx.server.storage.update::<TDigest, _>(unpack_bytes(&args[0])?, |slot| match slot {
+            Some(_) => (false, false),
+            None => {
+                *slot = Some(digest);
+                (true, true)
+            },
+        })?;
+        match created {
+            true => Ok(ok()),
+            false => Err(Error::reply("ERR T-Digest: key already exists")),
+        }
+    }
+}
+
+// TDIGEST.ADD key value [value ...]: adds the values
+struct TdigestAdd;
+
+impl Command for TdigestAdd {
+    fn name(&self) -> &'static str {
+        "tdigest.add"
+    }
+
+    fn arity(&self) -> i64 {
+        -3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let values = args[1..].iter().map(parse_float).collect::<Result<Vec<_>>>()?;
+        ctx.server.storage.update::<TDigest, _>(unpack_bytes(&args[0])?, |digest| match digest {
+            Some(digest) => {
+                digest.add(&values).expect("values are checked to be finite");
+                (Ok(ok()), true)
+            },
+            None => (Err(not_found()), false),
+        })?
+    }
+}
+
+// TDIGEST.RESET key: forgets every value, keeping the compression
+struct TdigestReset;
+
+impl Command for TdigestReset {
+    fn name(&self) -> &'static str {
+        "tdigest.reset"
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        ctx.server.storage.update::<TDigest, _>(unpack_bytes(&args[0])?, |digest| match digest {
+            Some(digest) => {
+                digest.reset();
+                (Ok(ok()), true)
+            },
+            None => (Err(not_found()), false),
+        })?
+    }
+}
+
+// TDIGEST.MERGE destination numkeys source [source ...] [COMPRESSION compression] [OVERRIDE]: adds the sources'
+// values to the destination, making it if it's missing, or replacing what it had with OVERRIDE. A new
+// destination takes the given compression, or the largest of the sources'.
+struct TdigestMerge;
+
+impl Command for TdigestMerge {
+    fn name(&self) -> &'static str {
+        "tdigest.merge"
+    }
+
+    fn arity(&self) -> i64 {
+        -4
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let numkeys = usize::try_from(parse_int(&args[1])?).ok().filter(|&n| n > 0 && n <= args.len() - 2)
+            .ok_or_else(|| Error::reply("ERR T-Digest: invalid numkeys"))?;
+        let (mut compression, mut replace) = (None, false);
+        let mut options = args[2 + numkeys..].iter();
+        while let Some(option) = options.next() {
+            match (unpack_bulk_str(option)?.to_lowercase().as_str(), options.clone().next()) {
+                ("compression", Some(arg)) => {
+                    compression = Some(parse_compression(arg)?);
+                    options.next();
+                },
+                ("override", _) => replace = true,
+                _ => return Err(Error::Syntax),
+            }
+        }
+        // The sources are read first, each under its own shard's lock, as they may be anywhere
+        let storage = &ctx.server.storage;
+        let mut sources = vec![];
+        for source in &args[2..2 + numkeys] {
+            sources.push(storage.read::<TDigest, _>(unpack_bytes(source)?, TDigest::clone)?.ok_or_else(not_found)?);
+        }
+        if let Some(compression) = compression {
+            new_digest(compression)?;
+        }
+        let reply = storage.update::<TDigest, _>(unpack_bytes(&args[0])?, |dest| {
+            let mut merged = match dest.take().filter(|_| !replace) {
+                // Recompressed with what it had when asked for another compression
+                Some(kept) => match compression {
+                    Some(compression) if compression != kept.compression => {
+                        let mut recompressed = TDigest::new(compression).expect("checked above");
+                        recompressed.merge(&kept);
+                        recompressed
+                    },
+                    _ => kept,
+                },
+                None => {
+                    let compression = compression.unwrap_or_else(|| sources.iter().map(|source| source.compression).fold(1.0, f64::max));
+                    TDigest::new(compression).expect("checked above, or a source's")
+                },
+            };
+            for source in &sources {
+                merged.merge(source);
+            }
+            *dest = Some(merged);
+            (ok(), true)
+        })?;
+        Ok(reply)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Estimate {
+    Quantile,
+    Cdf,
+}
+
+// TDIGEST.QUANTILE key quantile [quantile ...]: the value each fraction of the way through those added /
+// TDIGEST.CDF key value [value ...]: the fraction of those added below each value, counting half of those
+// equal. nan for an empty digest.
+struct TdigestEstimate {
+    estimate: Estimate,
+}
+
+impl Command for TdigestEstimate {
+    fn name(&self) -> &'static str {
+        match self.estimate {
+            Estimate::Quantile => "tdigest.quantile",
+            Estimate::Cdf => "tdigest.cdf",
+        }
+    }
+
+    fn arity(&self) -> i64 {
+        -3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let points = args[1..].iter().map(parse_float).collect::<Result<Vec<_>>>()?;
+        if matches!(self.estimate, Estimate::Quantile) && points.iter().any(|q| !(0.0..=1.0).contains(q)) {
+            return Err(Error::reply("ERR T-Digest: quantile should be in [0,1]"));
+        }
+        let estimates = ctx.server.storage.read::<TDigest, _>(unpack_bytes(&args[0])?, |digest| {
+            points.iter().map(|&point| Value::Double(match self.estimate {
+                Estimate::Quantile => digest.quantile(point),
+                Estimate::Cdf => digest.cdf(point),
+            })).collect()
+        })?;
+        Ok(Value::Array(estimates.ok_or_else(not_found)?).into())
+    }
+}
+
+// TDIGEST.MIN key / TDIGEST.MAX key: the smallest or largest value added, nan for an empty digest
+struct TdigestExtreme {
+    max: bool,
+}
+
+impl Command for TdigestExtreme {
+    fn name(&self) -> &'static str {
+        if self.max { "tdigest.max" } else { "tdigest.min" }
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let extreme = ctx.server.storage.read::<TDigest, _>(unpack_bytes(&args[0])?, |digest| match (digest.is_empty(), self.max) {
+            (true, _) => f64::NAN,
+            (false, true) => digest.max,
+            (false, false) => digest.min,
+        })?;
+        Ok(Value::Double(extreme.ok_or_else(not_found)?).into())
+    }
+}
+
+// TDIGEST.INFO key: the digest's compression, centroids, how many values it holds, how many times it was
+// compressed and the bytes its centroids take
+struct TdigestInfo;
+
+impl Command for TdigestInfo {
+    fn name(&self) -> &'static str {
+        "tdigest.info"
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let name = |name: &str| Value::SimpleString(name.to_string());
+        let info = ctx.server.storage.read::<TDigest, _>(unpack_bytes(&args[0])?, |digest| vec![
+            (name("Compression"), Value::Integer(digest.compression as i64)),
+            (name("Merged nodes"), Value::Integer(digest.centroids() as i64)),
+            (name("Merged weight"), Value::Integer(digest.weight() as i64)),
+            (name("Total compressions"), Value::Integer(digest.compressions as i64)),
+            (name("Memory usage"), Value::Integer(digest.size() as i64)),
+        ])?;
+        Ok(Value::Map(info.ok_or_else(not_found)?).into())
+    }
+}
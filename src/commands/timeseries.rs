@@ -0,0 +1,663 @@
+use std::fmt::Display;
+use bytes::Bytes;
+use crate::error::{Error, Result};
+use crate::resp::Value;
+use crate::storage::Storage;
+use crate::timeseries::{aggregate, Aggregation, DuplicatePolicy, Matcher, Rule, Sample, TimeSeries};
+use super::{ok, parse_int, unpack_bulk_str, unpack_bytes, Command, Context, Flags, KeySpec, Registry, Reply};
+
+pub fn register(registry: &mut Registry) {
+    registry.add(TsCreate);
+    registry.add(TsAlter);
+    registry.add(TsAdd);
+    registry.add(TsMAdd);
+    registry.add(TsGet);
+    registry.add(TsDel);
+    registry.add(TsRange { reverse: false });
+    registry.add(TsRange { reverse: true });
+    registry.add(TsMRange { reverse: false });
+    registry.add(TsMRange { reverse: true });
+    registry.add(TsQueryIndex);
+    registry.add(TsInfo);
+    registry.add(TsCreateRule);
+    registry.add(TsDeleteRule);
+}
+
+fn tsdb(e: impl Display) -> Error {
+    Error::Reply(format!("ERR TSDB: {}", e))
+}
+
+fn missing() -> Error {
+    tsdb("the key does not exist")
+}
+
+fn sample_value((timestamp, value): Sample) -> Value {
+    Value::Array(vec![Value::Integer(timestamp), Value::Double(value)])
+}
+
+fn labels_value(series: &TimeSeries) -> Value {
+    let label = |(name, value): &(String, String)| Value::Array(vec![Value::BulkString(Bytes::from(name.clone())), Value::BulkString(Bytes::from(value.clone()))]);
+    Value::Array(series.labels.iter().map(label).collect())
+}
+
+// A range bound: `-` for the oldest sample, `+` for the newest, or a timestamp
+fn parse_bound(arg: &Value) -> Result<i64> {
+    match unpack_bulk_str(arg)? {
+        "-" => Ok(i64::MIN),
+        "+" => Ok(i64::MAX),
+        _ => parse_int(arg),
+    }
+}
+
+fn parse_value(arg: &Value) -> Result<f64> {
+    let value = unpack_bulk_str(arg)?.parse::<f64>().ok().filter(|value| value.is_finite());
+    value.ok_or_else(|| tsdb("invalid value"))
+}
+
+// A sample's timestamp: `*` for now, or milliseconds since the epoch
+fn parse_timestamp(ctx: &Context, arg: &Value) -> Result<i64> {
+    if unpack_bulk_str(arg)? == "*" {
+        return Ok(ctx.server.storage.now_ms() as i64);
+    }
+    match parse_int(arg) {
+        Ok(timestamp) if timestamp >= 0 => Ok(timestamp),
+        _ => Err(tsdb("invalid timestamp")),
+    }
+}
+
+// What TS.CREATE, TS.ALTER and TS.ADD say about a series: [RETENTION ms] [DUPLICATE_POLICY policy]
+// [ON_DUPLICATE policy] [LABELS label value ...], LABELS last
+#[derive(Debug, Default)]
+struct Options {
+    retention: Option<u64>,
+    duplicate_policy: Option<DuplicatePolicy>,
+    on_duplicate: Option<DuplicatePolicy>,
+    labels: Option<Vec<(String, String)>>,
+}
+
+impl Options {
+    fn parse(args: &[Value], on_duplicate: bool) -> Result<Options> {
+        let mut options = Options::default();
+        let policy = |arg: &Value| DuplicatePolicy::parse(unpack_bulk_str(arg)?).map_err(tsdb);
+        let mut i = 0;
+        while i < args.len() {
+            match (unpack_bulk_str(&args[i])?.to_lowercase().as_str(), args.get(i + 1)) {
+                ("retention", Some(arg)) => options.retention = Some(u64::try_from(parse_int(arg)?).map_err(|_| tsdb("invalid retention"))?),
+                ("duplicate_policy", Some(arg)) => options.duplicate_policy = Some(policy(arg)?),
+                ("on_duplicate", Some(arg)) if on_duplicate => options.on_duplicate = Some(policy(arg)?),
+                ("labels", _) => {
+                    let pairs = &args[i + 1..];
+                    if !pairs.len().is_multiple_of(2) {
+                        return Err(Error::Syntax);
+                    }
+                    let pairs = pairs.chunks(2).map(|pair| Ok((unpack_bulk_str(&pair[0])?.to_string(), unpack_bulk_str(&pair[1])?.to_string())));
+                    options.labels = Some(pairs.collect::<Result<_>>()?);
+                    break;
+                },
+                _ => return Err(Error::Syntax),
+            }
+            i += 2;
+        }
+        Ok(options)
+    }
+
+    fn apply(&self, series: &mut TimeSeries) {
+        if let Some(retention) = self.retention {
+            series.retention = retention;
+            series.trim();
+        }
+        if let Some(policy) = self.duplicate_policy {
+            series.duplicate_policy = policy;
+        }
+        if let Some(labels) = &self.labels {
+            series.labels = labels.clone();
+        }
+    }
+}
+
+// Adds a sample to a series, creating it with `create` if it's missing and that's given, then passes the
+// aggregates of the buckets that closed on to the rules' destinations. Gives the sample's timestamp.
+fn add_sample(storage: &Storage, key: &Bytes, sample: Sample, create: Option<&Options>, policy: Option<DuplicatePolicy>) -> Result<i64> {
+    let compactions = storage.update::<TimeSeries, _>(key, |slot| {
+        let created = slot.is_none();
+        if created {
+            let Some(options) = create else {
+                return (Err(missing()), false);
+            };
+            let mut series = TimeSeries::default();
+            options.apply(&mut series);
+            *slot = Some(series);
+        }
+        let series = slot.as_mut().expect("created above");
+        match series.add(sample, policy) {
+            Ok(newest) => {
+                let compactions = series.rules.iter().flat_map(|rule| {
+                    series.compactions(rule, sample.0, newest).into_iter().map(|sample| (rule.dest.clone(), sample))
+                });
+                (Ok(compactions.collect::<Vec<_>>()), true)
+            },
+            Err(e) => {
+                if created {
+                    *slot = None;
+                }
+                (Err(tsdb(e)), false)
+            },
+        }
+    })??;
+    // Each destination is written under its own shard's lock, after the source's was let go
+    for (dest, sample) in compactions {
+        storage.update::<TimeSeries, _>(&dest, |series| match series {
+            Some(series) => {
+                let added = series.add(sample, Some(DuplicatePolicy::Last)).is_ok();
+                (added, added)
+            },
+            None => (false, false),
+        }).ok();
+    }
+    Ok(sample.0)
+}
+
+// TS.CREATE key [RETENTION ms] [DUPLICATE_POLICY policy] [LABELS label value ...]: an empty series
+struct TsCreate;
+
+impl Command for TsCreate {
+    fn name(&self) -> &'static str {
+        "ts.create"
+    }
+
+    fn arity(&self) -> i64 {
+        -2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let options = Options::parse(&args[1..], false)?;
+        let created = ctx.server.storage.update::<TimeSeries, _>(unpack_bytes(&args[0])?, |slot| {
+            if slot.is_some() {
+                return (false, false);
+            }
+            let mut series = TimeSeries::default();
+            options.apply(&mut series);
+            *slot = Some(series);
+            (true, true)
+        })?;
+        match created {
+            true => Ok(ok()),
+            false => Err(tsdb("key already exists")),
+        }
+    }
+}
+
+// TS.ALTER key [RETENTION ms] [DUPLICATE_POLICY policy] [LABELS label value ...]: changes what's given,
+// LABELS replacing all the labels
+struct TsAlter;
+
+impl Command for TsAlter {
+    fn name(&self) -> &'static str {
+        "ts.alter"
+    }
+
+    fn arity(&self) -> i64 {
+        -2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let options = Options::parse(&args[1..], false)?;
+        let found = ctx.server.storage.update::<TimeSeries, _>(unpack_bytes(&args[0])?, |series| match series {
+            Some(series) => {
+                options.apply(series);
+                (true, true)
+            },
+            None => (false, false),
+        })?;
+        match found {
+            true => Ok(ok()),
+            false => Err(missing()),
+        }
+    }
+}
+
+// TS.ADD key timestamp value [RETENTION ms] [DUPLICATE_POLICY policy] [ON_DUPLICATE policy] [LABELS ...]:
+// adds a sample, `*` timestamping it now, creating the series with the options if it's missing.
+// ON_DUPLICATE overrides the series' duplicate policy for this sample. Replies with the timestamp.
+struct TsAdd;
+
+impl Command for TsAdd {
+    fn name(&self) -> &'static str {
+        "ts.add"
+    }
+
+    fn arity(&self) -> i64 {
+        -4
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let sample = (parse_timestamp(ctx, &args[1])?, parse_value(&args[2])?);
+        let options = Options::parse(&args[3..], true)?;
+        let timestamp = add_sample(&ctx.server.storage, unpack_bytes(&args[0])?, sample, Some(&options), options.on_duplicate)?;
+        Ok(Value::Integer(timestamp).into())
+    }
+}
+
+// TS.MADD key timestamp value [key timestamp value ...]: adds samples to existing series, replying with each
+// one's timestamp or why it wasn't added
+struct TsMAdd;
+
+impl Command for TsMAdd {
+    fn name(&self) -> &'static str {
+        "ts.madd"
+    }
+
+    fn arity(&self) -> i64 {
+        -4
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec { first: 1, last: -1, step: 3 }
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        if !args.len().is_multiple_of(3) {
+            return Err(Error::WrongArity(self.name().to_string()));
+        }
+        let mut replies = vec![];
+        for triple in args.chunks(3) {
+            let added = parse_timestamp(ctx, &triple[1]).and_then(|timestamp| {
+                let sample = (timestamp, parse_value(&triple[2])?);
+                add_sample(&ctx.server.storage, unpack_bytes(&triple[0])?, sample, None, None)
+            });
+            replies.push(added.map_or_else(Error::into_value, Value::Integer));
+        }
+        Ok(Value::Array(replies).into())
+    }
+}
+
+// TS.GET key: the newest sample, as [timestamp, value], or an empty array without any
+struct TsGet;
+
+impl Command for TsGet {
+    fn name(&self) -> &'static str {
+        "ts.get"
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let last = ctx.server.storage.read::<TimeSeries, _>(unpack_bytes(&args[0])?, TimeSeries::last)?.ok_or_else(missing)?;
+        Ok(last.map_or(Value::Array(vec![]), sample_value).into())
+    }
+}
+
+// TS.DEL key from to: deletes the samples between the timestamps, both included, replying with how many
+struct TsDel;
+
+impl Command for TsDel {
+    fn name(&self) -> &'static str {
+        "ts.del"
+    }
+
+    fn arity(&self) -> i64 {
+        4
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let (from, to) = (parse_bound(&args[1])?, parse_bound(&args[2])?);
+        let deleted = ctx.server.storage.update::<TimeSeries, _>(unpack_bytes(&args[0])?, |series| match series {
+            Some(series) => {
+                let deleted = series.delete(from, to);
+                (Some(deleted), deleted > 0)
+            },
+            None => (None, false),
+        })?;
+        Ok(Value::Integer(deleted.ok_or_else(missing)? as i64).into())
+    }
+}
+
+// How TS.RANGE and TS.MRANGE pick and shape samples: [WITHLABELS] [COUNT n] [AGGREGATION aggregation bucket]
+// [FILTER matcher ...], the first and last for TS.MRANGE only, FILTER last
+#[derive(Debug)]
+struct RangeOptions {
+    with_labels: bool,
+    count: usize,
+    aggregation: Option<(Aggregation, u64)>,
+    filter: Vec<Matcher>,
+}
+
+impl RangeOptions {
+    fn parse(args: &[Value], multi: bool) -> Result<RangeOptions> {
+        let mut options = RangeOptions { with_labels: false, count: usize::MAX, aggregation: None, filter: vec![] };
+        let mut i = 0;
+        while i < args.len() {
+            match unpack_bulk_str(&args[i])?.to_lowercase().as_str() {
+                "withlabels" if multi => options.with_labels = true,
+                "count" if i + 1 < args.len() => {
+                    options.count = usize::try_from(parse_int(&args[i + 1])?).map_err(|_| tsdb("invalid COUNT"))?;
+                    i += 1;
+                },
+                "aggregation" if i + 2 < args.len() => {
+                    let aggregation = Aggregation::parse(unpack_bulk_str(&args[i + 1])?).map_err(tsdb)?;
+                    options.aggregation = Some((aggregation, parse_bucket(&args[i + 2])?));
+                    i += 2;
+                },
+                "filter" if multi => {
+                    options.filter = args[i + 1..].iter().map(|arg| Matcher::parse(unpack_bulk_str(arg)?).map_err(tsdb)).collect::<Result<_>>()?;
+                    break;
+                },
+                _ => return Err(Error::Syntax),
+            }
+            i += 1;
+        }
+        if multi {
+            check_filter(&options.filter)?;
+        }
+        Ok(options)
+    }
+
+    fn samples(&self, series: &TimeSeries, from: i64, to: i64, reverse: bool) -> Vec<Sample> {
+        match (self.aggregation, reverse) {
+            (None, false) => series.range(from, to).take(self.count).collect(),
+            (None, true) => series.range(from, to).rev().take(self.count).collect(),
+            (Some((aggregation, bucket)), _) => {
+                let mut samples = aggregate(series.range(from, to), aggregation, bucket, 0);
+                if reverse {
+                    samples.reverse();
+                }
+                samples.truncate(self.count);
+                samples
+            },
+        }
+    }
+}
+
+fn parse_bucket(arg: &Value) -> Result<u64> {
+    u64::try_from(parse_int(arg)?).ok().filter(|&bucket| bucket > 0).ok_or_else(|| tsdb("the bucket duration must be positive"))
+}
+
+// A filter must select series by some label value, rather than take every series apart from some
+fn check_filter(filter: &[Matcher]) -> Result<()> {
+    match filter.iter().any(Matcher::selects) {
+        true => Ok(()),
+        false => Err(tsdb("FILTER needs at least one label=value matcher")),
+    }
+}
+
+// TS.RANGE / TS.REVRANGE key from to [COUNT n] [AGGREGATION aggregation bucket]: the samples between the
+// timestamps, both included, `-` and `+` standing for the oldest and the newest, oldest first for TS.RANGE.
+// With AGGREGATION, one sample per bucket of `bucket` milliseconds instead, at the bucket's start.
+struct TsRange {
+    reverse: bool,
+}
+
+impl Command for TsRange {
+    fn name(&self) -> &'static str {
+        if self.reverse { "ts.revrange" } else { "ts.range" }
+    }
+
+    fn arity(&self) -> i64 {
+        -4
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let (from, to) = (parse_bound(&args[1])?, parse_bound(&args[2])?);
+        let options = RangeOptions::parse(&args[3..], false)?;
+        let samples = ctx.server.storage.read::<TimeSeries, _>(unpack_bytes(&args[0])?, |series| options.samples(series, from, to, self.reverse))?;
+        let samples = samples.ok_or_else(missing)?;
+        Ok(Value::Array(samples.into_iter().map(sample_value).collect()).into())
+    }
+}
+
+// TS.MRANGE / TS.MREVRANGE from to [WITHLABELS] [COUNT n] [AGGREGATION aggregation bucket] FILTER matcher ...:
+// TS.RANGE over every series whose labels match, see timeseries.rs, in key order, each as [key, labels,
+// samples]; the labels are left empty without WITHLABELS
+struct TsMRange {
+    reverse: bool,
+}
+
+impl Command for TsMRange {
+    fn name(&self) -> &'static str {
+        if self.reverse { "ts.mrevrange" } else { "ts.mrange" }
+    }
+
+    fn arity(&self) -> i64 {
+        -5
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let (from, to) = (parse_bound(&args[0])?, parse_bound(&args[1])?);
+        let options = RangeOptions::parse(&args[2..], true)?;
+        let found = ctx.server.storage.find_series(|| ctx.check_budget(), |series| options.filter.iter().all(|matcher| matcher.matches(series)))?;
+        let replies = found.into_iter().map(|(key, series)| {
+            let labels = if options.with_labels { labels_value(&series) } else { Value::Array(vec![]) };
+            let samples = options.samples(&series, from, to, self.reverse).into_iter().map(sample_value).collect();
+            Value::Array(vec![Value::BulkString(key), labels, Value::Array(samples)])
+        });
+        Ok(Value::Array(replies.collect()).into())
+    }
+}
+
+// TS.QUERYINDEX matcher ...: the keys of the series whose labels match
+struct TsQueryIndex;
+
+impl Command for TsQueryIndex {
+    fn name(&self) -> &'static str {
+        "ts.queryindex"
+    }
+
+    fn arity(&self) -> i64 {
+        -2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let filter = args.iter().map(|arg| Matcher::parse(unpack_bulk_str(arg)?).map_err(tsdb)).collect::<Result<Vec<_>>>()?;
+        check_filter(&filter)?;
+        let found = ctx.server.storage.find_series(|| ctx.check_budget(), |series| filter.iter().all(|matcher| matcher.matches(series)))?;
+        Ok(Value::Array(found.into_iter().map(|(key, _)| Value::BulkString(key)).collect()).into())
+    }
+}
+
+// TS.INFO key: the series' size, span, settings, labels and rules
+struct TsInfo;
+
+impl Command for TsInfo {
+    fn name(&self) -> &'static str {
+        "ts.info"
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let name = |name: &str| Value::SimpleString(name.to_string());
+        let info = ctx.server.storage.read::<TimeSeries, _>(unpack_bytes(&args[0])?, |series| {
+            let rules = series.rules.iter().map(|rule| Value::Array(vec![
+                Value::BulkString(rule.dest.clone()),
+                Value::Integer(rule.bucket as i64),
+                Value::SimpleString(rule.aggregation.as_str().to_string()),
+                Value::Integer(rule.align),
+            ]));
+            vec![
+                (name("totalSamples"), Value::Integer(series.len() as i64)),
+                (name("firstTimestamp"), Value::Integer(series.first().map_or(0, |(at, _)| at))),
+                (name("lastTimestamp"), Value::Integer(series.last().map_or(0, |(at, _)| at))),
+                (name("retentionTime"), Value::Integer(series.retention as i64)),
+                (name("duplicatePolicy"), Value::SimpleString(series.duplicate_policy.as_str().to_string())),
+                (name("labels"), labels_value(series)),
+                (name("sourceKey"), series.source.clone().map_or(Value::Null, Value::BulkString)),
+                (name("rules"), Value::Array(rules.collect())),
+            ]
+        })?;
+        Ok(Value::Map(info.ok_or_else(missing)?).into())
+    }
+}
+
+// TS.CREATERULE source dest AGGREGATION aggregation bucket [align]: from now on compacts the source into the
+// destination, both existing series, see timeseries.rs. A series takes compactions from one source only.
+struct TsCreateRule;
+
+impl Command for TsCreateRule {
+    fn name(&self) -> &'static str {
+        "ts.createrule"
+    }
+
+    fn arity(&self) -> i64 {
+        -6
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec { first: 1, last: 2, step: 1 }
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let (source, dest) = (unpack_bytes(&args[0])?, unpack_bytes(&args[1])?);
+        if !unpack_bulk_str(&args[2])?.eq_ignore_ascii_case("aggregation") || args.len() > 6 {
+            return Err(Error::Syntax);
+        }
+        let aggregation = Aggregation::parse(unpack_bulk_str(&args[3])?).map_err(tsdb)?;
+        let bucket = parse_bucket(&args[4])?;
+        let align = args.get(5).map(parse_int).transpose()?.unwrap_or(0);
+        if source == dest {
+            return Err(tsdb("the source and destination keys must differ"));
+        }
+        let storage = &ctx.server.storage;
+        match storage.read::<TimeSeries, _>(dest, |series| series.source.is_none())? {
+            None => return Err(missing()),
+            Some(false) => return Err(tsdb("the destination key already has a source rule")),
+            Some(true) => {},
+        }
+        storage.update::<TimeSeries, _>(source, |series| match series {
+            Some(series) => {
+                series.rules.push(Rule { dest: dest.clone(), aggregation, bucket, align });
+                (Ok(()), true)
+            },
+            None => (Err(missing()), false),
+        })??;
+        storage.update::<TimeSeries, _>(dest, |series| match series {
+            Some(series) => {
+                series.source = Some(source.clone());
+                ((), true)
+            },
+            None => ((), false),
+        })?;
+        Ok(ok())
+    }
+}
+
+// TS.DELETERULE source dest: stops compacting the source into the destination
+struct TsDeleteRule;
+
+impl Command for TsDeleteRule {
+    fn name(&self) -> &'static str {
+        "ts.deleterule"
+    }
+
+    fn arity(&self) -> i64 {
+        3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec { first: 1, last: 2, step: 1 }
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let (source, dest) = (unpack_bytes(&args[0])?, unpack_bytes(&args[1])?);
+        let storage = &ctx.server.storage;
+        let removed = storage.update::<TimeSeries, _>(source, |series| {
+            let Some(series) = series else {
+                return (false, false);
+            };
+            let before = series.rules.len();
+            series.rules.retain(|rule| rule.dest != dest);
+            let removed = series.rules.len() < before;
+            (removed, removed)
+        })?;
+        if !removed {
+            return Err(tsdb("compaction rule does not exist"));
+        }
+        storage.update::<TimeSeries, _>(dest, |series| match series {
+            Some(series) if series.source.as_ref() == Some(source) => {
+                series.source = None;
+                ((), true)
+            },
+            _ => ((), false),
+        })?;
+        Ok(ok())
+    }
+}
@@ -0,0 +1,214 @@
+use crate::error::{Error, Result};
+use crate::resp::Value;
+use crate::topk::{TopK, DEFAULT_DECAY, DEFAULT_DEPTH, DEFAULT_WIDTH};
+use super::{ok, parse_int, unpack_bulk_str, unpack_bytes, Command, Context, Flags, KeySpec, Registry, Reply};
+
+pub fn register(registry: &mut Registry) {
+    registry.add(TopkReserve);
+    registry.add(TopkAdd { by: false });
+    registry.add(TopkAdd { by: true });
+    registry.add(TopkQuery { counts: false });
+    registry.add(TopkQuery { counts: true });
+    registry.add(TopkList);
+    registry.add(TopkInfo);
+}
+
+fn parse_count(arg: &Value, what: &str) -> Result<u64> {
+    u64::try_from(parse_int(arg)?).map_err(|_| Error::Reply(format!("ERR the {} must not be negative", what)))
+}
+
+fn not_found() -> Error {
+    Error::reply("ERR TopK: key does not exist")
+}
+
+// TOPK.RESERVE key k [width depth decay]: an empty structure keeping the `k` items seen most often, counted in
+// `depth` rows of `width` buckets, 7 of 8 by default, with counts decaying by `decay`, 0.9 by default
+struct TopkReserve;
+
+impl Command for TopkReserve {
+    fn name(&self) -> &'static str {
+        "topk.reserve"
+    }
+
+    fn arity(&self) -> i64 {
+        -3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let k = parse_count(&args[1], "k")?;
+        let (width, depth, decay) = match &args[2..] {
+            [] => (DEFAULT_WIDTH, DEFAULT_DEPTH, DEFAULT_DECAY),
+            [width, depth, decay] => {
+                let decay = unpack_bulk_str(decay)?.parse::<f64>().map_err(|_| Error::reply("ERR TopK: invalid decay"))?;
+                (parse_count(width, "width")?, parse_count(depth, "depth")?, decay)
+            },
+            _ => return Err(Error::Syntax),
+        };
+        let topk = TopK::new(k, width, depth, decay).map_err(|e| Error::Reply(format!("ERR TopK: {}", e)))?;
+        let created = ctx.server.storage.update::<TopK, _>(unpack_bytes(&args[0])?, |slot| match slot {
+            Some(_) => (false, false),
+            None => {
+                *slot = Some(topk);
+                (true, true)
+            },
+        })?;
+        match created {
+            true => Ok(ok()),
+            false => Err(Error::reply("ERR TopK: key already exists")),
+        }
+    }
+}
+
+// TOPK.ADD key item [item ...] / TOPK.INCRBY key item increment [item increment ...]: counts each item once, or
+// `increment` more times, replying for each with the item it pushed out of the top k, or nil
+struct TopkAdd {
+    by: bool,
+}
+
+impl Command for TopkAdd {
+    fn name(&self) -> &'static str {
+        if self.by { "topk.incrby" } else { "topk.add" }
+    }
+
+    fn arity(&self) -> i64 {
+        if self.by { -4 } else { -3 }
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::WRITE
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let increments = match self.by {
+            true if !(args.len() - 1).is_multiple_of(2) => return Err(Error::WrongArity(self.name().to_string())),
+            true => args[1..].chunks(2).map(|pair| Ok((unpack_bytes(&pair[0])?, parse_count(&pair[1], "increment")?))).collect::<Result<Vec<_>>>()?,
+            false => args[1..].iter().map(|item| Ok((unpack_bytes(item)?, 1))).collect::<Result<Vec<_>>>()?,
+        };
+        let expelled = ctx.server.storage.update::<TopK, _>(unpack_bytes(&args[0])?, |topk| match topk {
+            Some(topk) => {
+                let expelled: Vec<Value> = increments.iter().map(|&(item, by)| match topk.add(item, by) {
+                    Some(expelled) => Value::BulkString(expelled),
+                    None => Value::Null,
+                }).collect();
+                (Ok(expelled), true)
+            },
+            None => (Err(not_found()), false),
+        })??;
+        Ok(Value::Array(expelled).into())
+    }
+}
+
+// TOPK.QUERY key item [item ...] / TOPK.COUNT key item [item ...]: 1 for each item in the top k and 0 for the
+// rest, or each item's count
+struct TopkQuery {
+    counts: bool,
+}
+
+impl Command for TopkQuery {
+    fn name(&self) -> &'static str {
+        if self.counts { "topk.count" } else { "topk.query" }
+    }
+
+    fn arity(&self) -> i64 {
+        -3
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let items = args[1..].iter().map(unpack_bytes).collect::<Result<Vec<_>>>()?;
+        let replies = ctx.server.storage.read::<TopK, _>(unpack_bytes(&args[0])?, |topk| {
+            items.iter().map(|item| match self.counts {
+                true => Value::Integer(topk.count(item) as i64),
+                false => Value::Integer(topk.contains(item) as i64),
+            }).collect()
+        })?;
+        Ok(Value::Array(replies.ok_or_else(not_found)?).into())
+    }
+}
+
+// TOPK.LIST key [WITHCOUNT]: the top k items, most counted first, each followed by its count with WITHCOUNT
+struct TopkList;
+
+impl Command for TopkList {
+    fn name(&self) -> &'static str {
+        "topk.list"
+    }
+
+    fn arity(&self) -> i64 {
+        -2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let with_count = match &args[1..] {
+            [] => false,
+            [option] if unpack_bulk_str(option)?.eq_ignore_ascii_case("withcount") => true,
+            _ => return Err(Error::Syntax),
+        };
+        let list = ctx.server.storage.read::<TopK, _>(unpack_bytes(&args[0])?, |topk| {
+            topk.list().iter().flat_map(|(item, count)| {
+                let count = with_count.then(|| Value::Integer(*count as i64));
+                std::iter::once(Value::BulkString(item.clone())).chain(count)
+            }).collect()
+        })?;
+        Ok(Value::Array(list.ok_or_else(not_found)?).into())
+    }
+}
+
+// TOPK.INFO key: k, and the buckets' width, depth and decay
+struct TopkInfo;
+
+impl Command for TopkInfo {
+    fn name(&self) -> &'static str {
+        "topk.info"
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY | Flags::FAST
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::FIRST
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let name = |name: &str| Value::SimpleString(name.to_string());
+        let info = ctx.server.storage.read::<TopK, _>(unpack_bytes(&args[0])?, |topk| vec![
+            (name("k"), Value::Integer(topk.k as i64)),
+            (name("width"), Value::Integer(topk.width as i64)),
+            (name("depth"), Value::Integer(topk.depth as i64)),
+            (name("decay"), Value::Double(topk.decay)),
+        ])?;
+        Ok(Value::Map(info.ok_or_else(not_found)?).into())
+    }
+}
@@ -0,0 +1,152 @@
+use crate::error::{Error, Result};
+use crate::resp::Value;
+use crate::session::Transaction;
+use super::{ok, unpack_bytes, Command, Context, Flags, KeySpec, Registry, Reply};
+
+pub fn register(registry: &mut Registry) {
+    registry.add(Multi);
+    registry.add(Exec);
+    registry.add(Discard);
+    registry.add(Watch);
+    registry.add(Unwatch);
+}
+
+struct Multi;
+
+impl Command for Multi {
+    fn name(&self) -> &'static str {
+        "multi"
+    }
+
+    fn arity(&self) -> i64 {
+        1
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::FAST | Flags::NO_MULTI
+    }
+
+    fn execute(&self, ctx: &mut Context, _args: &[Value]) -> Result<Reply> {
+        if ctx.session.multi.is_some() {
+            return Err(Error::reply("ERR MULTI calls can not be nested"));
+        }
+        ctx.session.multi = Some(Transaction::default());
+        Ok(ok())
+    }
+}
+
+// EXEC: runs the queued commands back to back under the exclusive storage gate, so no other client can
+// interleave. Replies with a null array when a WATCHed key changed since it was watched.
+struct Exec;
+
+impl Command for Exec {
+    fn name(&self) -> &'static str {
+        "exec"
+    }
+
+    fn arity(&self) -> i64 {
+        1
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::NO_MULTI | Flags::EXCLUSIVE
+    }
+
+    fn execute(&self, ctx: &mut Context, _args: &[Value]) -> Result<Reply> {
+        let Some(transaction) = ctx.session.multi.take() else {
+            return Err(Error::reply("ERR EXEC without MULTI"));
+        };
+        let watched = std::mem::take(&mut ctx.session.watched);
+        if transaction.failed {
+            return Err(Error::reply("EXECABORT Transaction discarded because of previous errors."));
+        }
+        if watched.iter().any(|(key, version)| ctx.server.storage.version(key) != *version) {
+            return Ok(Value::NullArray.into());
+        }
+
+        let server = ctx.server;
+        let mut responses = vec![];
+        for (name, args) in transaction.commands {
+            responses.extend(server.commands.execute_queued(ctx, name, &args));
+        }
+        Ok(Value::Array(responses).into())
+    }
+}
+
+struct Discard;
+
+impl Command for Discard {
+    fn name(&self) -> &'static str {
+        "discard"
+    }
+
+    fn arity(&self) -> i64 {
+        1
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::FAST | Flags::NO_MULTI
+    }
+
+    fn execute(&self, ctx: &mut Context, _args: &[Value]) -> Result<Reply> {
+        if ctx.session.multi.take().is_none() {
+            return Err(Error::reply("ERR DISCARD without MULTI"));
+        }
+        ctx.session.watched.clear();
+        Ok(ok())
+    }
+}
+
+// WATCH key [key ...]: remembers each key's version for EXEC to compare against
+struct Watch;
+
+impl Command for Watch {
+    fn name(&self) -> &'static str {
+        "watch"
+    }
+
+    fn arity(&self) -> i64 {
+        -2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::FAST | Flags::NO_MULTI
+    }
+
+    fn keys(&self) -> KeySpec {
+        KeySpec::ALL
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        if ctx.session.multi.is_some() {
+            return Err(Error::reply("ERR WATCH inside MULTI is not allowed"));
+        }
+        for arg in args {
+            let key = unpack_bytes(arg)?;
+            let version = ctx.server.storage.version(key);
+            ctx.session.watched.entry(key.clone()).or_insert(version);
+        }
+        Ok(ok())
+    }
+}
+
+struct Unwatch;
+
+impl Command for Unwatch {
+    fn name(&self) -> &'static str {
+        "unwatch"
+    }
+
+    fn arity(&self) -> i64 {
+        1
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::FAST
+    }
+
+    fn execute(&self, ctx: &mut Context, _args: &[Value]) -> Result<Reply> {
+        ctx.session.watched.clear();
+        Ok(ok())
+    }
+}
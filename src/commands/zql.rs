@@ -0,0 +1,66 @@
+use bytes::Bytes;
+use crate::error::{Error, Result};
+use crate::resp::Value;
+use crate::storage::Storage;
+use crate::zql::{Select, Statement};
+use super::{unpack_bulk_str, Command, Context, Flags, Registry, Reply};
+
+pub fn register(registry: &mut Registry) {
+    registry.add(Zql);
+}
+
+// ZQL query: runs a ZenQL SELECT, see zql.rs, replying as FT.SEARCH does: how many rows match, then the page
+// LIMIT and OFFSET ask for, every row without them, each as its key and the fields and values selected. With
+// EXPLAIN before the SELECT, the plan it would run with instead: whether it scans every key or an index, the
+// index and the range of it read, and how many keys that would look at against how many a scan would.
+struct Zql;
+
+impl Command for Zql {
+    fn name(&self) -> &'static str {
+        "zql"
+    }
+
+    fn arity(&self) -> i64 {
+        2
+    }
+
+    fn flags(&self) -> Flags {
+        Flags::READONLY
+    }
+
+    fn execute(&self, ctx: &mut Context, args: &[Value]) -> Result<Reply> {
+        let statement = Statement::parse(unpack_bulk_str(&args[0])?).map_err(|e| Error::Reply(format!("ERR invalid query: {}", e)))?;
+        let storage = &ctx.server.storage;
+        let select = match statement {
+            Statement::Explain(select) => return Ok(explain(&select, storage)),
+            Statement::Select(select) => select,
+        };
+        let mut rows = select.run(storage, &select.plan(storage), || ctx.check_budget())?;
+        select.sort(&mut rows);
+        let mut reply = vec![Value::Integer(rows.len() as i64)];
+        for (key, value) in rows.iter().skip(select.offset).take(select.limit.unwrap_or(usize::MAX)) {
+            let fields = select.project(key, value).into_iter().flat_map(|(field, value)| [Value::BulkString(field), Value::BulkString(value)]);
+            reply.push(Value::BulkString(key.clone()));
+            reply.push(Value::Array(fields.collect()));
+        }
+        Ok(Value::Array(reply).into())
+    }
+}
+
+fn explain(select: &Select, storage: &Storage) -> Reply {
+    let plan = select.plan(storage);
+    let name = |name: &str| Value::SimpleString(name.to_string());
+    let text = |text: &str| Value::BulkString(Bytes::copy_from_slice(text.as_bytes()));
+    let mut explain = vec![];
+    match &plan.index {
+        None => explain.push((name("access"), text("scan"))),
+        Some(index) => {
+            explain.push((name("access"), text("index")));
+            explain.push((name("index"), text(&index.name)));
+            explain.push((name("range"), text(&index.describe())));
+        },
+    }
+    explain.push((name("estimated cost"), Value::Integer(plan.cost as i64)));
+    explain.push((name("scan cost"), Value::Integer(plan.scan_cost as i64)));
+    Value::Map(explain).into()
+}
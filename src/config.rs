@@ -0,0 +1,656 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::Result;
+use crate::bridge::Bridge;
+use crate::bucket::Bucket;
+use crate::codec::{split_inline_args, DEFAULT_MAX_BULK_LEN, DEFAULT_MAX_MULTIBULK_LEN, DEFAULT_MAX_QUERY_BUFFER};
+use crate::crypt::{self, KeySource};
+use crate::executor::ExecutionModel;
+use crate::hasher::HasherKind;
+use crate::log::{self, FileOptions, Format, Level};
+use crate::ratelimit::RateLimitAction;
+use crate::runtime::RuntimeKind;
+use crate::storage::{BackendKind, DEFAULT_SHARDS};
+use crate::tenant::Tenant;
+use crate::webhook::Webhook;
+
+const USAGE: &str = "\
+Usage: redis-starter-rust [config-file] [--name value ...]
+  --config <file>                 Read options from a redis.conf style file, flags take precedence
+  --bind <addresses>              Space separated addresses to listen on, '-' prefixed ones are optional
+                                  (default: 127.0.0.1)
+  --port <port>                   Port to listen on, 0 for none (default: 6379)
+  --reuseport-acceptors <n>       Listening sockets per bind address, load-balanced by the kernel with
+                                  SO_REUSEPORT when above 1 (default: 1)
+  --unixsocket <path>             Also listen on a unix socket
+  --unixsocketperm <mode>         Octal permissions of the socket file, e.g. 700
+  --dir <path>                    Working directory for data files (default: .)
+  --daemonize <yes|no>            Run in the background, output goes to the logfile (default: no)
+  --pidfile <path>                Write the process id to this file while running
+  --requirepass <password>        Require clients to AUTH
+  --tenants <tenants>             Space separated name=password pairs of tenants, each confined to its
+                                  own keys once authenticated as it; needs requirepass
+  --maxmemory <bytes>             Memory limit; nothing evicts keys yet, so only 0 is accepted (default:
+                                  0, no limit)
+  --compression-threshold <bytes> Store values at least this long LZ4 compressed, trading CPU on every
+                                  read and write for memory (default: 0, off)
+  --tiering-idle-time <secs>      Move values unread for this long out of memory into a file in dir, reading
+                                  them back in when next used (default: 0, off)
+  --maxclients <n>                Maximum simultaneous clients (default: 10000)
+  --timeout <secs>                Close clients idle this long, subscribers excepted (default: 0, never)
+  --client-rate-limit <n>         Commands per second each client may run, bursts of up to a second's
+                                  worth allowed (default: 0, no limit)
+  --client-rate-limit-action <action>
+                                  throttle delays commands over the limit, reject fails them
+                                  (default: throttle)
+  --command-time-budget <ms>      Abort long-running commands such as LCS, ZQL, FT.SEARCH, TS.MRANGE and
+                                  GRAPH.TRAVERSE after this long, so one query can't hold up everyone
+                                  else (default: 0, no limit)
+  --tcp-keepalive <secs>          Non-zero turns on TCP keepalive for clients; probe timing follows the
+                                  kernel's net.ipv4.tcp_keepalive_* settings (default: 300)
+  --protected-mode <yes|no>       Without requirepass, refuse clients from other hosts (default: yes)
+  --loglevel <level>              debug, info, warning or error (default: info)
+  --log-format <format>           plain or json
+  --logfile <path>                Log to a file instead of stdout
+  --log-max-size <bytes>          Rotate the logfile past this size
+  --log-rotate-interval <secs>    Rotate the logfile periodically
+  --log-max-files <n>             Rotated logfiles to keep (default: 5)
+  --hz <n>                        Background task frequency (default: 10)
+  --storage-backend <backend>     sharded; concurrent, the same layout with at least 4 shards per core;
+                                  or disk to keep values in a log in dir that is loaded again on startup
+                                  (default: sharded)
+  --storage-shards <n>            Keyspace partitions (default: 16)
+  --keyspace-hasher <hasher>      fx hashes keys about twice as fast; siphash resists clients choosing
+                                  key names that collide to slow the server down (default: fx)
+  --wal <yes|no>                  Log every write to dir and fsync it before replying, replaying the log on
+                                  startup; not with the disk backend (default: no)
+  --wal-segment-size <bytes>      Start a new write-ahead log file past this size (default: 64mb)
+  --encryption-key <key>          Encrypt the data file, write-ahead log and snapshots with this key, 64 hex
+                                  digits, or env:NAME to read them from an environment variable; this hides
+                                  what they hold but doesn't stop them being altered, see crypt.rs
+  --encryption-key-id <id>        Name of the key, kept in file headers for rotation (default: default)
+  --encryption-key-command <cmd>  Run with a key id as argument to print the key, e.g. from a KMS; used for
+                                  the current key without --encryption-key and for older ones
+  --encryption-old-keys <keys>    Space separated id=key pairs of earlier keys, to read files encrypted
+                                  with them
+  --snapshot-bucket <url>         Also upload what SNAPSHOT EXPORT writes to this S3 or MinIO bucket,
+                                  http://host[:port]/bucket[/prefix], see bucket.rs
+  --snapshot-bucket-access-key <key>
+                                  Access key id requests to the snapshot bucket are signed with
+  --snapshot-bucket-secret-key <key>
+                                  Secret access key requests to the snapshot bucket are signed with
+  --snapshot-bucket-region <region>
+                                  Region of the snapshot bucket (default: us-east-1)
+  --snapshot-restore <name>       Download the snapshot of this name from the snapshot bucket into dir and
+                                  load it at startup in place of the data there
+  --runtime <model>               multi-thread, or current-thread to run everything on one thread
+                                  (default: multi-thread)
+  --worker-threads <n>            Threads of the multi-thread runtime (default: 0, one per core)
+  --command-execution <model>     inline runs commands on each client's task, executor runs them all on one
+                                  dedicated thread in arrival order (default: inline)
+  --proto-max-bulk-len <bytes>    Largest bulk string a client may send (default: 512mb)
+  --proto-max-multibulk-len <n>   Most arguments a client may send in one command (default: 1048576)
+  --client-query-buffer-limit <bytes>
+                                  Largest command a client may send, arguments included (default: 1gb)
+  --metrics-port <port>           Serve Prometheus metrics on this port
+  --http-port <port>              Also serve commands as HTTP/JSON and over WebSockets on this port of
+                                  the bind addresses
+  --grpc-port <port>              Also serve the gRPC service in proto/zenql.proto on this port of the
+                                  bind addresses
+  --memcached-port <port>         Also speak the memcached text protocol on this port of the bind addresses
+  --admin-port <port>             Serve /healthz, /readyz and /status over HTTP on this port of the bind
+                                  addresses, for load balancers and orchestrators
+  --otlp-endpoint <host:port>     Export command spans to an OTLP/HTTP collector
+  --webhooks <targets>            Space separated http:// URLs to POST keyspace events to as JSON, each
+                                  optionally followed by ;events=set,expire,del,expired,evicted and ;match=<glob>
+  --bridges <bridges>             Space separated nats:// or kafka:// URLs to mirror keyspace events or
+                                  channels to, with ;subject=, ;topic= and other options, see bridge.rs
+  --audit-log <path>              Record every write and admin command clients send to this file
+  --audit-max-size <bytes>        Rotate the audit log past this size
+  --audit-max-files <n>           Rotated audit logs to keep (default: 5)
+  --help                          Show this help
+  --version                       Show the version";
+
+// Every setting `set` and `get` know about
+pub const OPTIONS: [&str; 58] = [
+    "bind", "port", "reuseport-acceptors", "unixsocket", "unixsocketperm", "maxmemory", "maxclients", "timeout",
+    "tcp-keepalive", "protected-mode", "metrics-port", "http-port", "grpc-port", "memcached-port", "admin-port",
+    "otlp-endpoint", "loglevel", "log-format", "logfile", "log-max-size", "log-rotate-interval", "log-max-files",
+    "requirepass", "tenants",
+    "storage-backend", "storage-shards", "dir",
+    "hz", "proto-max-bulk-len", "runtime", "worker-threads",
+    "command-execution", "pidfile", "daemonize", "compression-threshold",
+    "proto-max-multibulk-len", "client-query-buffer-limit", "client-rate-limit", "client-rate-limit-action",
+    "command-time-budget", "tiering-idle-time", "wal", "wal-segment-size", "keyspace-hasher", "webhooks",
+    "bridges", "audit-log", "audit-max-size", "audit-max-files", "encryption-key", "encryption-key-id",
+    "encryption-key-command", "encryption-old-keys", "snapshot-bucket", "snapshot-bucket-access-key",
+    "snapshot-bucket-secret-key", "snapshot-bucket-region", "snapshot-restore",
+];
+
+// Settings holding passwords or keys, whose values are never shown: get_redacted and the changes a reload
+// reports leave them out
+pub const SECRET_OPTIONS: [&str; 5] = ["requirepass", "tenants", "encryption-key", "encryption-old-keys", "snapshot-bucket-secret-key"];
+// Stands in for a secret setting's value
+pub const REDACTED: &str = "(redacted)";
+
+// A setting a config reload found changed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub name: &'static str,
+    pub old: String, // REDACTED for secret settings
+    pub new: String,
+    pub needs_restart: bool, // Left at its old value until the server restarts
+    pub secret: bool, // One of SECRET_OPTIONS
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub bind: Vec<String>, // Addresses to listen on, '-' marks ones that may fail to bind
+    pub port: u16, // 0 disables TCP
+    pub reuseport_acceptors: usize, // Listening sockets per bind address, more than 1 uses SO_REUSEPORT
+    pub unixsocket: Option<PathBuf>, // Also listen on this unix socket
+    pub unixsocketperm: u32, // Mode of the socket file, 0 keeps the umask default
+    pub config_file: Option<PathBuf>, // Where the startup configuration was read from
+    pub overrides: Vec<(String, String)>, // Command-line options, applied over the file again on reload
+    pub maxmemory: u64, // Memory limit in bytes, 0 for none, the only value accepted until eviction exists
+    pub compression_threshold: usize, // LZ4 compress values at least this long, 0 disables
+    pub tiering_idle_time: u64, // Seconds unread before a value is spilled to disk, 0 disables
+    pub maxclients: usize, // Connections past this are refused
+    pub timeout: u64, // Close clients idle for this many seconds, 0 disables
+    pub client_rate_limit: u64, // Commands per second each client may run, 0 for no limit
+    pub client_rate_limit_action: RateLimitAction,
+    pub command_time_budget: u64, // Milliseconds a long-running command may take before it is aborted, 0 for no limit
+    pub tcp_keepalive: u64, // Non-zero enables TCP keepalive on client sockets
+    pub protected_mode: bool, // Without a password, only accept loopback and unix socket clients
+    pub metrics_port: Option<u16>, // Prometheus endpoint, disabled when unset
+    pub http_port: Option<u16>, // HTTP gateway, see http.rs, disabled when unset
+    pub grpc_port: Option<u16>, // gRPC service, see grpc.rs, disabled when unset
+    pub memcached_port: Option<u16>, // memcached listener, see memcached.rs, disabled when unset
+    pub admin_port: Option<u16>, // Health endpoints, see admin.rs, disabled when unset
+    pub otlp_endpoint: Option<String>, // host:port of an OTLP/HTTP collector for command spans
+    pub webhooks: Vec<Webhook>, // Endpoints keyspace events are POSTed to, see webhook.rs
+    pub bridges: Vec<Bridge>, // NATS and Kafka connectors, see bridge.rs
+    pub audit_log: Option<String>, // Write and admin commands are recorded here, see audit.rs
+    pub audit_max_size: u64, // Rotate the audit log past this many bytes, 0 disables
+    pub audit_max_files: usize, // Rotated audit logs to keep around
+    pub loglevel: Level,
+    pub log_format: Format,
+    pub logfile: Option<String>, // stdout when unset
+    pub log_max_size: u64, // Rotate the logfile past this many bytes, 0 disables
+    pub log_rotate_interval: u64, // Rotate the logfile every N seconds, 0 disables
+    pub log_max_files: usize, // Rotated logfiles to keep around
+    pub proto_max_bulk_len: usize, // Largest bulk string a client may send
+    pub proto_max_multibulk_len: usize, // Most elements in a client's request
+    pub client_query_buffer_limit: usize, // Largest request a client may send
+    pub requirepass: Option<String>, // Clients must AUTH with this password when set
+    pub tenants: Vec<Tenant>, // Namespaces clients can AUTH into, see tenant.rs
+    pub storage_backend: BackendKind,
+    pub storage_shards: usize, // Independently locked partitions of the keyspace
+    pub keyspace_hasher: HasherKind, // Hash function for keys, see hasher.rs
+    pub wal: bool, // Log writes and fsync them before acknowledging, see wal.rs
+    pub wal_segment_size: u64, // Bytes per write-ahead log file before starting the next
+    pub encryption_key: Option<KeySource>, // Persistence files are encrypted when set, see crypt.rs
+    pub encryption_key_id: String, // Names the current key in file headers
+    pub encryption_key_command: Option<String>, // Prints the key of the id it's given
+    pub encryption_old_keys: Vec<(String, KeySource)>, // Earlier keys by id, for files written with them
+    pub snapshot_bucket: Option<Bucket>, // SNAPSHOT EXPORT uploads here too when set, see bucket.rs
+    pub snapshot_bucket_access_key: String,
+    pub snapshot_bucket_secret_key: String,
+    pub snapshot_bucket_region: String,
+    pub snapshot_restore: Option<String>, // Snapshot in the bucket loaded at startup
+    pub hz: u32, // Background task frequency (active expiry cycle)
+    pub runtime: RuntimeKind,
+    pub worker_threads: usize, // Threads of the multi-thread runtime, 0 for one per core
+    pub command_execution: ExecutionModel,
+    pub dir: PathBuf, // Working directory for data files
+    pub pidfile: Option<PathBuf>, // Holds the process id while the server runs
+    pub daemonize: bool, // Run in the background, detached from the terminal
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: vec!["127.0.0.1".to_string()],
+            port: 6379,
+            reuseport_acceptors: 1,
+            unixsocket: None,
+            unixsocketperm: 0,
+            config_file: None,
+            overrides: vec![],
+            maxmemory: 0,
+            compression_threshold: 0,
+            tiering_idle_time: 0,
+            maxclients: 10_000,
+            timeout: 0,
+            client_rate_limit: 0,
+            client_rate_limit_action: RateLimitAction::Throttle,
+            command_time_budget: 0,
+            tcp_keepalive: 300,
+            protected_mode: true,
+            metrics_port: None,
+            http_port: None,
+            grpc_port: None,
+            memcached_port: None,
+            admin_port: None,
+            otlp_endpoint: None,
+            webhooks: vec![],
+            bridges: vec![],
+            audit_log: None,
+            audit_max_size: 0,
+            audit_max_files: 5,
+            loglevel: Level::Info,
+            log_format: Format::Plain,
+            logfile: None,
+            log_max_size: 0,
+            log_rotate_interval: 0,
+            log_max_files: 5,
+            proto_max_bulk_len: DEFAULT_MAX_BULK_LEN,
+            proto_max_multibulk_len: DEFAULT_MAX_MULTIBULK_LEN,
+            client_query_buffer_limit: DEFAULT_MAX_QUERY_BUFFER,
+            requirepass: None,
+            tenants: vec![],
+            storage_backend: BackendKind::Sharded,
+            storage_shards: DEFAULT_SHARDS,
+            keyspace_hasher: HasherKind::Fx,
+            wal: false,
+            wal_segment_size: 64 * 1024 * 1024,
+            encryption_key: None,
+            encryption_key_id: "default".to_string(),
+            encryption_key_command: None,
+            encryption_old_keys: vec![],
+            snapshot_bucket: None,
+            snapshot_bucket_access_key: String::new(),
+            snapshot_bucket_secret_key: String::new(),
+            snapshot_bucket_region: "us-east-1".to_string(),
+            snapshot_restore: None,
+            hz: 10,
+            runtime: RuntimeKind::MultiThread,
+            worker_threads: 0,
+            command_execution: ExecutionModel::Inline,
+            dir: PathBuf::from("."),
+            pidfile: None,
+            daemonize: false,
+        }
+    }
+}
+
+impl Config {
+    // Reads the startup configuration from the command line
+    pub fn from_args() -> Result<Config> {
+        Config::parse_args(std::env::args().skip(1))
+    }
+
+    // `[config-file] [--name value ...]`, like redis-server: the file is applied first so flags override it
+    pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Config> {
+        let mut config = Config::default();
+        let mut args = args.into_iter().peekable();
+        let mut file = args.next_if(|arg| !arg.starts_with("--")).map(PathBuf::from);
+        let mut options = vec![];
+        while let Some(arg) = args.next() {
+            let name = arg.strip_prefix("--").ok_or_else(|| anyhow::anyhow!("Unexpected argument '{}'", arg))?;
+            match name {
+                "help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                },
+                "version" => {
+                    println!("zenql v{}", env!("CARGO_PKG_VERSION"));
+                    std::process::exit(0);
+                },
+                _ => {},
+            }
+            let value = args.next().ok_or_else(|| anyhow::anyhow!("Missing value for '--{}'", name))?;
+            match name {
+                "config" => file = Some(PathBuf::from(value)),
+                _ => options.push((name.to_string(), value)),
+            }
+        }
+        if let Some(path) = file {
+            config.load_file(&path)?;
+            config.config_file = Some(path);
+        }
+        for (name, value) in &options {
+            config.set(name, value)?;
+        }
+        config.overrides = options;
+        Ok(config)
+    }
+
+    // Re-reads the config file with the command-line options on top. Settings only read at startup keep
+    // their running values; every setting the file changed is reported, restart-only ones flagged as such.
+    pub fn reload(&self) -> Result<(Config, Vec<Change>)> {
+        let path = self.config_file.as_ref().ok_or_else(|| anyhow::anyhow!("No config file to reload"))?;
+        let mut config = Config::default();
+        config.load_file(path)?;
+        for (name, value) in &self.overrides {
+            config.set(name, value)?;
+        }
+        config.config_file = self.config_file.clone();
+        config.overrides = self.overrides.clone();
+
+        let reread: Vec<_> = OPTIONS.iter().map(|name| config.get(name)).collect();
+        config.keep_startup_settings(self);
+        let changes = OPTIONS.into_iter().zip(reread)
+            .filter_map(|(name, new)| {
+                let old = self.get(name)?;
+                let new = new?;
+                if old == new {
+                    return None;
+                }
+                let needs_restart = config.get(name).as_ref() != Some(&new);
+                let secret = is_secret(name);
+                let (old, new) = match secret {
+                    true => (REDACTED.to_string(), REDACTED.to_string()),
+                    false => (old, new),
+                };
+                Some(Change { name, needs_restart, old, new, secret })
+            })
+            .collect();
+        Ok((config, changes))
+    }
+
+    // Copies the settings only read at startup from `running`
+    fn keep_startup_settings(&mut self, running: &Config) {
+        self.bind = running.bind.clone();
+        self.port = running.port;
+        self.reuseport_acceptors = running.reuseport_acceptors;
+        self.unixsocket = running.unixsocket.clone();
+        self.unixsocketperm = running.unixsocketperm;
+        self.dir = running.dir.clone();
+        self.pidfile = running.pidfile.clone();
+        self.daemonize = running.daemonize;
+        self.storage_backend = running.storage_backend;
+        self.storage_shards = running.storage_shards;
+        self.keyspace_hasher = running.keyspace_hasher;
+        self.wal = running.wal;
+        self.wal_segment_size = running.wal_segment_size;
+        self.encryption_key = running.encryption_key.clone();
+        self.encryption_key_id = running.encryption_key_id.clone();
+        self.encryption_key_command = running.encryption_key_command.clone();
+        self.encryption_old_keys = running.encryption_old_keys.clone();
+        self.snapshot_restore = running.snapshot_restore.clone();
+        self.hz = running.hz;
+        self.runtime = running.runtime;
+        self.worker_threads = running.worker_threads;
+        self.command_execution = running.command_execution;
+        self.metrics_port = running.metrics_port;
+        self.http_port = running.http_port;
+        self.grpc_port = running.grpc_port;
+        self.memcached_port = running.memcached_port;
+        self.admin_port = running.admin_port;
+        self.otlp_endpoint = running.otlp_endpoint.clone();
+        self.webhooks = running.webhooks.clone();
+        self.bridges = running.bridges.clone();
+        self.audit_log = running.audit_log.clone();
+        self.audit_max_size = running.audit_max_size;
+        self.audit_max_files = running.audit_max_files;
+    }
+
+    // Fails on settings that don't work together, checked once they're all read
+    pub fn check(&self) -> Result<()> {
+        if !self.tenants.is_empty() && self.requirepass.is_none() {
+            return Err(anyhow::anyhow!("tenants needs requirepass, or clients that don't AUTH would see every tenant's keys"));
+        }
+        if self.snapshot_restore.is_some() && self.snapshot_bucket.is_none() {
+            return Err(anyhow::anyhow!("snapshot-restore needs snapshot-bucket to download it from"));
+        }
+        Ok(())
+    }
+
+    // Whether clients from other hosts are turned away
+    pub fn protected(&self) -> bool {
+        self.protected_mode && self.requirepass.is_none()
+    }
+
+    // Like get, but secret settings that are set read as REDACTED, for showing to anyone
+    pub fn get_redacted(&self, name: &str) -> Option<String> {
+        let value = self.get(name)?;
+        Some(match is_secret(name) && !value.is_empty() {
+            true => REDACTED.to_string(),
+            false => value,
+        })
+    }
+
+    // Current value of a setting in config file form, None for unknown names
+    pub fn get(&self, name: &str) -> Option<String> {
+        let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+        Some(match name.to_lowercase().as_str() {
+            "bind" => self.bind.join(" "),
+            "port" => self.port.to_string(),
+            "reuseport-acceptors" => self.reuseport_acceptors.to_string(),
+            "unixsocket" => self.unixsocket.as_ref().map(|path| path.display().to_string()).unwrap_or_default(),
+            "unixsocketperm" => format!("{:o}", self.unixsocketperm),
+            "maxmemory" => self.maxmemory.to_string(),
+            "compression-threshold" => self.compression_threshold.to_string(),
+            "tiering-idle-time" => self.tiering_idle_time.to_string(),
+            "maxclients" => self.maxclients.to_string(),
+            "timeout" => self.timeout.to_string(),
+            "client-rate-limit" => self.client_rate_limit.to_string(),
+            "client-rate-limit-action" => self.client_rate_limit_action.as_str().to_string(),
+            "command-time-budget" => self.command_time_budget.to_string(),
+            "tcp-keepalive" => self.tcp_keepalive.to_string(),
+            "protected-mode" => if self.protected_mode { "yes" } else { "no" }.to_string(),
+            "metrics-port" => self.metrics_port.map(|port| port.to_string()).unwrap_or_default(),
+            "http-port" => self.http_port.map(|port| port.to_string()).unwrap_or_default(),
+            "grpc-port" => self.grpc_port.map(|port| port.to_string()).unwrap_or_default(),
+            "memcached-port" => self.memcached_port.map(|port| port.to_string()).unwrap_or_default(),
+            "admin-port" => self.admin_port.map(|port| port.to_string()).unwrap_or_default(),
+            "otlp-endpoint" => optional(&self.otlp_endpoint),
+            "webhooks" => self.webhooks.iter().map(Webhook::as_str).collect::<Vec<_>>().join(" "),
+            "bridges" => self.bridges.iter().map(Bridge::as_str).collect::<Vec<_>>().join(" "),
+            "audit-log" => optional(&self.audit_log),
+            "audit-max-size" => self.audit_max_size.to_string(),
+            "audit-max-files" => self.audit_max_files.to_string(),
+            "loglevel" => self.loglevel.as_str().to_string(),
+            "log-format" => self.log_format.as_str().to_string(),
+            "logfile" => optional(&self.logfile),
+            "log-max-size" => self.log_max_size.to_string(),
+            "log-rotate-interval" => self.log_rotate_interval.to_string(),
+            "log-max-files" => self.log_max_files.to_string(),
+            "requirepass" => optional(&self.requirepass),
+            "tenants" => self.tenants.iter().map(Tenant::as_string).collect::<Vec<_>>().join(" "),
+            "storage-backend" => self.storage_backend.as_str().to_string(),
+            "storage-shards" => self.storage_shards.to_string(),
+            "keyspace-hasher" => self.keyspace_hasher.as_str().to_string(),
+            "wal" => if self.wal { "yes" } else { "no" }.to_string(),
+            "wal-segment-size" => self.wal_segment_size.to_string(),
+            "encryption-key" => self.encryption_key.as_ref().map(|key| key.as_str().to_string()).unwrap_or_default(),
+            "encryption-key-id" => self.encryption_key_id.clone(),
+            "encryption-key-command" => optional(&self.encryption_key_command),
+            "snapshot-bucket" => self.snapshot_bucket.as_ref().map(|bucket| bucket.as_str().to_string()).unwrap_or_default(),
+            "snapshot-bucket-access-key" => self.snapshot_bucket_access_key.clone(),
+            "snapshot-bucket-secret-key" => self.snapshot_bucket_secret_key.clone(),
+            "snapshot-bucket-region" => self.snapshot_bucket_region.clone(),
+            "snapshot-restore" => optional(&self.snapshot_restore),
+            "encryption-old-keys" => self.encryption_old_keys.iter().map(|(id, key)| format!("{}={}", id, key.as_str())).collect::<Vec<_>>().join(" "),
+            "dir" => self.dir.display().to_string(),
+            "daemonize" => if self.daemonize { "yes" } else { "no" }.to_string(),
+            "pidfile" => self.pidfile.as_ref().map(|path| path.display().to_string()).unwrap_or_default(),
+            "hz" => self.hz.to_string(),
+            "proto-max-bulk-len" => self.proto_max_bulk_len.to_string(),
+            "proto-max-multibulk-len" => self.proto_max_multibulk_len.to_string(),
+            "client-query-buffer-limit" => self.client_query_buffer_limit.to_string(),
+            "runtime" => self.runtime.as_str().to_string(),
+            "worker-threads" => self.worker_threads.to_string(),
+            "command-execution" => self.command_execution.as_str().to_string(),
+            _ => return None,
+        })
+    }
+
+    // Applies a redis.conf style file: one `name value` directive per line, # comments, quoted values
+    pub fn load_file(&mut self, path: &Path) -> Result<()> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Can't read config file '{}': {}", path.display(), e))?;
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |reason: String| anyhow::anyhow!("{} at line {} of '{}': {}", reason, number + 1, path.display(), line);
+            let args = split_inline_args(line.as_bytes()).map_err(|_| error("Unbalanced quotes".to_string()))?;
+            // Directives taking several values, like bind, get them space separated
+            let Some((name, values)) = args.split_first() else {
+                continue;
+            };
+            if values.is_empty() {
+                return Err(error("Wrong number of arguments".to_string()));
+            }
+            let name = String::from_utf8_lossy(name);
+            let value = values.iter().map(|value| String::from_utf8_lossy(value)).collect::<Vec<_>>().join(" ");
+            self.set(&name, &value).map_err(|e| error(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        match name.to_lowercase().as_str() {
+            "bind" => match value.split_whitespace().map(str::to_string).collect::<Vec<_>>() {
+                addresses if addresses.is_empty() => return Err(anyhow::anyhow!("bind needs at least one address")),
+                addresses => self.bind = addresses,
+            },
+            "port" => self.port = value.parse()?,
+            "reuseport-acceptors" => match value.parse()? {
+                0 => return Err(anyhow::anyhow!("reuseport-acceptors must be at least 1")),
+                acceptors => self.reuseport_acceptors = acceptors,
+            },
+            "unixsocket" => self.unixsocket = if value.is_empty() { None } else { Some(PathBuf::from(value)) },
+            "unixsocketperm" => match u32::from_str_radix(value, 8) {
+                Ok(perm) if perm <= 0o777 => self.unixsocketperm = perm,
+                _ => return Err(anyhow::anyhow!("Invalid socket file permissions '{}', expected octal like 700", value)),
+            },
+            // Nothing evicts keys yet, so a limit would only be a promise the server doesn't keep
+            "maxmemory" => match parse_bytes(value)? {
+                0 => self.maxmemory = 0,
+                _ => return Err(anyhow::anyhow!("maxmemory isn't enforced, nothing evicts keys yet, so only 0 (no limit) is accepted")),
+            },
+            "compression-threshold" => self.compression_threshold = parse_bytes(value)? as usize,
+            "tiering-idle-time" => self.tiering_idle_time = value.parse()?,
+            "protected-mode" => self.protected_mode = parse_bool(value)?,
+            "timeout" => self.timeout = value.parse()?,
+            "client-rate-limit" => self.client_rate_limit = value.parse()?,
+            "client-rate-limit-action" => self.client_rate_limit_action = RateLimitAction::parse(value)?,
+            "command-time-budget" => self.command_time_budget = value.parse()?,
+            "tcp-keepalive" => self.tcp_keepalive = value.parse()?,
+            "maxclients" => match value.parse()? {
+                0 => return Err(anyhow::anyhow!("maxclients must be at least 1")),
+                maxclients => self.maxclients = maxclients,
+            },
+            "metrics-port" => self.metrics_port = Some(value.parse()?),
+            "http-port" => self.http_port = Some(value.parse()?),
+            "grpc-port" => self.grpc_port = Some(value.parse()?),
+            "memcached-port" => self.memcached_port = Some(value.parse()?),
+            "admin-port" => self.admin_port = Some(value.parse()?),
+            "otlp-endpoint" => self.otlp_endpoint = Some(value.to_string()),
+            "webhooks" => self.webhooks = value.split_whitespace().map(Webhook::parse).collect::<Result<_>>()?,
+            "bridges" => self.bridges = value.split_whitespace().map(Bridge::parse).collect::<Result<_>>()?,
+            "audit-log" => self.audit_log = if value.is_empty() { None } else { Some(value.to_string()) },
+            "audit-max-size" => self.audit_max_size = parse_bytes(value)?,
+            "audit-max-files" => self.audit_max_files = value.parse()?,
+            "loglevel" => self.loglevel = Level::parse(value)?,
+            "log-format" => self.log_format = Format::parse(value)?,
+            "logfile" => self.logfile = if value.is_empty() { None } else { Some(value.to_string()) },
+            "log-max-size" => self.log_max_size = parse_bytes(value)?,
+            "log-rotate-interval" => self.log_rotate_interval = value.parse()?,
+            "log-max-files" => self.log_max_files = value.parse()?,
+            "requirepass" => self.requirepass = if value.is_empty() { None } else { Some(value.to_string()) },
+            "tenants" => {
+                let tenants = value.split_whitespace().map(Tenant::parse).collect::<Result<Vec<_>>>()?;
+                if let Some(i) = (1..tenants.len()).find(|&i| tenants[..i].iter().any(|tenant| tenant.name == tenants[i].name)) {
+                    return Err(anyhow::anyhow!("Tenant '{}' is listed twice", tenants[i].name));
+                }
+                self.tenants = tenants;
+            },
+            "storage-backend" => self.storage_backend = BackendKind::parse(value)?,
+            "storage-shards" => match value.parse()? {
+                0 => return Err(anyhow::anyhow!("storage-shards must be at least 1")),
+                shards => self.storage_shards = shards,
+            },
+            "keyspace-hasher" => self.keyspace_hasher = HasherKind::parse(value)?,
+            "wal" => self.wal = parse_bool(value)?,
+            "wal-segment-size" => match parse_bytes(value)? {
+                0 => return Err(anyhow::anyhow!("wal-segment-size must be at least 1")),
+                size => self.wal_segment_size = size,
+            },
+            "encryption-key" => self.encryption_key = if value.is_empty() { None } else { Some(KeySource::parse(value)?) },
+            "encryption-key-id" => {
+                crypt::check_key_id(value)?;
+                self.encryption_key_id = value.to_string();
+            },
+            "encryption-key-command" => self.encryption_key_command = if value.is_empty() { None } else { Some(value.to_string()) },
+            "encryption-old-keys" => self.encryption_old_keys = value.split_whitespace().map(|pair| {
+                let (id, key) = pair.split_once('=').ok_or_else(|| anyhow::anyhow!("Expected id=key, got '{}'", pair))?;
+                crypt::check_key_id(id)?;
+                Ok((id.to_string(), KeySource::parse(key)?))
+            }).collect::<Result<_>>()?,
+            "snapshot-bucket" => self.snapshot_bucket = if value.is_empty() { None } else { Some(Bucket::parse(value)?) },
+            "snapshot-bucket-access-key" => self.snapshot_bucket_access_key = value.to_string(),
+            "snapshot-bucket-secret-key" => self.snapshot_bucket_secret_key = value.to_string(),
+            "snapshot-bucket-region" => self.snapshot_bucket_region = value.to_string(),
+            "snapshot-restore" => self.snapshot_restore = if value.is_empty() { None } else { Some(value.to_string()) },
+            "dir" => self.dir = PathBuf::from(value),
+            "daemonize" => self.daemonize = parse_bool(value)?,
+            "pidfile" => self.pidfile = if value.is_empty() { None } else { Some(PathBuf::from(value)) },
+            "hz" => self.hz = value.parse::<u32>()?.clamp(1, 500),
+            "proto-max-bulk-len" => match parse_bytes(value)? {
+                len if len >= 1024 * 1024 => self.proto_max_bulk_len = len as usize,
+                _ => return Err(anyhow::anyhow!("proto-max-bulk-len must be at least 1mb")),
+            },
+            "proto-max-multibulk-len" => match value.parse()? {
+                0 => return Err(anyhow::anyhow!("proto-max-multibulk-len must be at least 1")),
+                len => self.proto_max_multibulk_len = len,
+            },
+            "client-query-buffer-limit" => match parse_bytes(value)? {
+                len if len >= 1024 * 1024 => self.client_query_buffer_limit = len as usize,
+                _ => return Err(anyhow::anyhow!("client-query-buffer-limit must be at least 1mb")),
+            },
+            "runtime" => self.runtime = RuntimeKind::parse(value)?,
+            "worker-threads" => self.worker_threads = value.parse()?,
+            "command-execution" => self.command_execution = ExecutionModel::parse(value)?,
+            _ => return Err(anyhow::anyhow!("Unknown config option '{}'", name)),
+        }
+        Ok(())
+    }
+
+    pub fn init_logging(&self) -> Result<()> {
+        let file = self.logfile.as_ref().map(|path| FileOptions {
+            path: path.clone(),
+            max_size: self.log_max_size,
+            rotate_interval: Duration::from_secs(self.log_rotate_interval),
+            max_files: self.log_max_files,
+        });
+        log::init(self.loglevel, self.log_format, file)
+    }
+}
+
+// yes/no, as in redis.conf
+pub fn parse_bool(value: &str) -> Result<bool> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(anyhow::anyhow!("Invalid value '{}', expected yes or no", value)),
+    }
+}
+
+// Accepts plain byte counts or Redis-style units: 1k, 5mb, 2gb
+pub fn parse_bytes(value: &str) -> Result<u64> {
+    let lower = value.to_lowercase();
+    let split = lower.find(|c: char| !c.is_ascii_digit()).unwrap_or(lower.len());
+    let (number, unit) = lower.split_at(split);
+    let number: u64 = number.parse().map_err(|_| anyhow::anyhow!("Invalid size '{}'", value))?;
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err(anyhow::anyhow!("Invalid size unit in '{}'", value)),
+    };
+    Ok(number * multiplier)
+}
+
+// Whether the setting `name` is one of SECRET_OPTIONS
+pub fn is_secret(name: &str) -> bool {
+    SECRET_OPTIONS.iter().any(|secret| secret.eq_ignore_ascii_case(name))
+}
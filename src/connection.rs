@@ -0,0 +1,235 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use anyhow::Result;
+use bytes::Bytes;
+use crate::cdc;
+use crate::codec::Limits;
+use crate::error::{self, Error};
+use crate::listener::Accepted;
+use crate::log::{log_debug, log_error, log_warn};
+use crate::ratelimit::{RateLimiter, Verdict};
+use crate::resp::{RespHandler, Value};
+use crate::server::Server;
+use crate::session::Session;
+use crate::stats::{self, SERVER_STATS};
+use crate::trace::CommandSpan;
+
+pub(crate) const PROTECTED_MODE: &str = "DENIED zenql is running in protected mode because protected mode is enabled and no \
+password is set. In this mode connections are only accepted from the loopback interface and the unix socket. To \
+accept clients from other hosts, either: 1) disable protected mode with 'protected-mode no' in the config file, \
+then reload it with SIGHUP, 2) restart the server with '--protected-mode no', or 3) set a password with \
+requirepass, ideally before reachable interfaces are bound. Only one of these is needed.";
+
+// Queued replies are flushed mid-pipeline once they grow past this
+const MAX_PENDING_OUTPUT: usize = 64 * 1024;
+
+// Serves one client until it disconnects
+pub async fn handle(accepted: Accepted, server: Server) -> Result<()> {
+    let Accepted { stream, addr: peer, laddr: local, local: loopback } = accepted;
+    let mut handler = RespHandler::new(stream);
+    let config = server.config();
+    if config.protected() && !loopback {
+        log_warn!("Refused connection from {}: protected mode is on and no password is set", peer);
+        handler.queue_value(Value::Error(PROTECTED_MODE.to_string()));
+        let _ = handler.flush().await;
+        return Ok(());
+    }
+    handler.set_limits(Limits {
+        max_bulk_len: config.proto_max_bulk_len,
+        max_multibulk_len: config.proto_max_multibulk_len,
+        max_query_buffer: config.client_query_buffer_limit,
+    });
+    let (sender, mut messages) = mpsc::unbounded_channel();
+    let mut session = Session::new(server.pubsub.next_subscriber_id(), peer.clone(), sender, config.requirepass.is_none());
+    let registration = server.clients.register(session.id, peer, local);
+    // Counted after registering, so simultaneous connections can't all slip in under the limit
+    if server.clients.len() > config.maxclients {
+        stats::incr(&SERVER_STATS.rejected_connections, 1);
+        log_warn!("Refused connection from {}: maxclients ({}) reached", session.peer, config.maxclients);
+        handler.queue_value(Value::Error("ERR max number of clients reached".to_string()));
+        let _ = handler.flush().await;
+        return Ok(());
+    }
+
+    let mut rate_limiter = RateLimiter::new();
+    'conn: loop {
+        // Subscribers are exempt from the idle timeout, they are expected to sit waiting for messages
+        let timeout = match server.config().timeout {
+            0 => None,
+            _ if session.subscribed() => None,
+            secs => Some(Duration::from_secs(secs)),
+        };
+        let idle = async move {
+            match timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        // Published messages are forwarded while waiting for the next command
+        let mut read = tokio::select! {
+            read = handler.read_value() => read,
+            _ = idle => {
+                log_debug!("Closing client {} after {}s idle", session.peer, server.config().timeout);
+                break;
+            },
+            Some(message) = messages.recv() => {
+                if let Err(e) = handler.write_value(message).await {
+                    log_warn!("Failed to deliver message to {}: {:?}", session.peer, e);
+                    break;
+                }
+                continue;
+            },
+        };
+
+        // Run every command the client pipelined into the read buffer, queueing the replies and flushing
+        // them together once the buffer is drained
+        loop {
+            let value = match read {
+                Ok(Some(value)) => value,
+                Ok(None) => break 'conn,
+                Err(e) => {
+                    log_warn!("Error reading value from {}: {}", session.peer, e);
+                    if matches!(e, Error::Protocol(_)) {
+                        handler.queue_value(e.into_value());
+                    }
+                    let _ = flush(&mut handler, &server, &session.peer).await;
+                    break 'conn;
+                },
+            };
+
+            let mut span = CommandSpan::start(&session.peer, handler.parse_time());
+            let (command, args) = match extract_command(value) {
+                Ok(cmd) => cmd,
+                Err(e) => {
+                    log_warn!("Error extracting command from {}: {}", session.peer, e);
+                    handler.queue_value(e.into_value());
+                    let _ = flush(&mut handler, &server, &session.peer).await;
+                    break 'conn;
+                },
+            };
+            let config = server.config();
+            let rejected = match rate_limiter.check(config.client_rate_limit, config.client_rate_limit_action) {
+                Verdict::Run => false,
+                Verdict::Delay(delay) => {
+                    stats::incr(&SERVER_STATS.rate_limited_commands, 1);
+                    // The client gets the replies so far while it waits
+                    if !flush(&mut handler, &server, &session.peer).await {
+                        break 'conn;
+                    }
+                    tokio::time::sleep(delay).await;
+                    false
+                },
+                Verdict::Reject => {
+                    stats::incr(&SERVER_STATS.rate_limited_commands, 1);
+                    true
+                },
+            };
+            let responses = if rejected {
+                vec![Error::reply("ERR client rate limit exceeded, try again later").into_value()]
+            } else {
+                registration.info.command_started(&String::from_utf8_lossy(&command));
+                let responses;
+                (session, span, responses) = execute(&server, session, command, args, span).await;
+                registration.info.sync(&session);
+                handler.set_protocol(session.protocol);
+                responses
+            };
+
+            span.phase("write");
+            for response in responses {
+                handler.queue_value(response);
+            }
+            span.finish();
+
+            // CDC hands the connection over to the change feed for good, see cdc.rs
+            if let Some(follow) = session.follow.take() {
+                if flush(&mut handler, &server, &session.peer).await {
+                    if let Err(e) = cdc::stream(&mut handler, &server, follow).await {
+                        log_warn!("CDC feed to {} ended: {:?}", session.peer, e);
+                    }
+                }
+                break 'conn;
+            }
+
+            // Don't let a long pipeline build up an unbounded reply buffer
+            if handler.pending_output() >= MAX_PENDING_OUTPUT && !flush(&mut handler, &server, &session.peer).await {
+                break 'conn;
+            }
+            read = match handler.decode_buffered() {
+                Ok(None) => break,
+                other => other,
+            };
+        }
+
+        if !flush(&mut handler, &server, &session.peer).await {
+            break;
+        }
+    }
+
+    for channel in &session.subscriptions {
+        server.pubsub.unsubscribe(channel, session.id);
+    }
+    Ok(()) // Return Ok on successful completion
+}
+
+// Sends the queued replies, once the writes they may have seen are durable. False when the client should be
+// disconnected: the replies couldn't be written, or the writes can't be made durable and mustn't be
+// acknowledged.
+async fn flush(handler: &mut RespHandler, server: &Server, peer: &str) -> bool {
+    if !sync_writes(server, peer).await {
+        return false;
+    }
+    if let Err(e) = handler.flush().await {
+        log_warn!("Failed to write response to {}: {:?}", peer, e);
+        return false;
+    }
+    true
+}
+
+// Makes the writes so far durable before they're acknowledged. False when they can't be, and the client
+// mustn't be replied to.
+pub(crate) async fn sync_writes(server: &Server, peer: &str) -> bool {
+    if server.storage.needs_sync() {
+        let storage = Arc::clone(&server.storage);
+        let synced = tokio::task::spawn_blocking(move || storage.sync()).await.unwrap_or_else(|e| Err(io::Error::other(e)));
+        if let Err(e) = synced {
+            log_error!("Closing client {} without replying, its writes may not be durable: {}", peer, e);
+            return false;
+        }
+    }
+    true
+}
+
+// Runs a command on the executor when there is one, otherwise right here on the connection's task
+pub(crate) async fn execute(server: &Server, mut session: Session, command: Bytes, args: Vec<Value>, mut span: CommandSpan) -> (Session, CommandSpan, Vec<Value>) {
+    let Some(executor) = &server.executor else {
+        let responses = server.commands.process(server, &mut session, command, args, &mut span);
+        return (session, span, responses);
+    };
+    let server = server.clone();
+    executor.run(move || {
+        let responses = server.commands.process(&server, &mut session, command, args, &mut span);
+        (session, span, responses)
+    }).await
+}
+
+// Commands must be non-empty arrays of bulk strings; anything else is a protocol error. The array is reused
+// for the arguments, the name taken off its front.
+fn extract_command(value: Value) -> error::Result<(Bytes, Vec<Value>)> {
+    let bulk_strings_only = || Error::Protocol("expected bulk string arguments".to_string());
+    match value {
+        Value::Array(mut a) if !a.is_empty() => {
+            if a.iter().any(|item| !matches!(item, Value::BulkString(_))) {
+                return Err(bulk_strings_only());
+            }
+            match a.remove(0) {
+                Value::BulkString(name) => Ok((name, a)),
+                _ => Err(bulk_strings_only()),
+            }
+        },
+        _ => Err(Error::Protocol("expected a multibulk command".to_string())),
+    }
+}
@@ -0,0 +1,106 @@
+// Count-min sketches (Cormode and Muthukrishnan): approximate counts of how often each item was seen, in a fixed
+// `depth` rows of `width` counters however many distinct items come. An item bumps one counter in each row,
+// picked by that row's hash, and its count is the smallest of those, which may be over but never under the
+// truth. Counts are over by at most about e / width of the total, with probability 1 - e^-depth. Items are
+// hashed with hasher::stable_hash, as sketches are persisted and merged.
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use crate::hasher::stable_hash;
+use crate::record::PayloadReader;
+
+// Bounds a sketch's size, 512MiB
+const MAX_COUNTERS: u64 = 1 << 26;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountMinSketch {
+    pub width: u64,
+    pub depth: u64,
+    pub count: u64, // The total of all increments
+    counters: Vec<u64>, // Row after row
+}
+
+impl CountMinSketch {
+    pub fn new(width: u64, depth: u64) -> Result<CountMinSketch> {
+        if width == 0 || depth == 0 {
+            bail!("the width and depth must be positive");
+        }
+        if width.saturating_mul(depth) > MAX_COUNTERS {
+            bail!("a sketch of {} by {} would be too large", width, depth);
+        }
+        Ok(CountMinSketch { width, depth, count: 0, counters: vec![0; (width * depth) as usize] })
+    }
+
+    // A sketch whose counts are over by at most `error` of the total, with probability 1 - `probability`
+    pub fn with_error(error: f64, probability: f64) -> Result<CountMinSketch> {
+        if !(error > 0.0 && error < 1.0 && probability > 0.0 && probability < 1.0) {
+            bail!("the error and probability must be between 0 and 1");
+        }
+        let width = (std::f64::consts::E / error).ceil();
+        let depth = (1.0 / probability).ln().ceil().max(1.0);
+        if width * depth > MAX_COUNTERS as f64 {
+            bail!("a sketch that precise would be too large");
+        }
+        CountMinSketch::new(width as u64, depth as u64)
+    }
+
+    // Where an item's counter is in each row
+    fn cells(&self, item: &[u8]) -> impl Iterator<Item = usize> {
+        let (width, hash) = (self.width, stable_hash(item, 0));
+        // Rows take hashes h1 + row * h2 apart, as in Kirsch and Mitzenmacher's double hashing
+        let step = stable_hash(item, hash) | 1;
+        (0..self.depth).map(move |row| (row * width + hash.wrapping_add(row.wrapping_mul(step)) % width) as usize)
+    }
+
+    // Counts an item `by` more times, handing back its count after
+    pub fn increment(&mut self, item: &[u8], by: u64) -> u64 {
+        self.count = self.count.saturating_add(by);
+        let mut least = u64::MAX;
+        for cell in self.cells(item) {
+            self.counters[cell] = self.counters[cell].saturating_add(by);
+            least = least.min(self.counters[cell]);
+        }
+        least
+    }
+
+    // How many times an item may have been seen, never fewer than it was
+    pub fn query(&self, item: &[u8]) -> u64 {
+        self.cells(item).map(|cell| self.counters[cell]).min().unwrap_or(0)
+    }
+
+    // Adds `other`'s counts, `weight` times over, to this sketch's; both must have the same dimensions
+    pub fn merge(&mut self, other: &CountMinSketch, weight: u64) -> Result<()> {
+        if (self.width, self.depth) != (other.width, other.depth) {
+            bail!("the sketches' width and depth differ");
+        }
+        for (counter, &theirs) in self.counters.iter_mut().zip(&other.counters) {
+            *counter = counter.saturating_add(theirs.saturating_mul(weight));
+        }
+        self.count = self.count.saturating_add(other.count.saturating_mul(weight));
+        Ok(())
+    }
+
+    // The sketch as stored in a record: width u64 | depth u64 | count u64, then each counter as a u64, row
+    // after row, all little-endian
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(24 + 8 * self.counters.len());
+        for n in [self.width, self.depth, self.count].iter().chain(&self.counters) {
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        out
+    }
+
+    pub fn decode(payload: &Bytes) -> Option<CountMinSketch> {
+        let mut reader = PayloadReader::new(payload);
+        let (width, depth, count) = (reader.u64()?, reader.u64()?, reader.u64()?);
+        // Checked before the counters are made, as a bad header could ask for any size
+        if width.saturating_mul(depth).saturating_mul(8) != payload.len() as u64 - 24 {
+            return None;
+        }
+        let mut sketch = CountMinSketch::new(width, depth).ok()?;
+        sketch.count = count;
+        for counter in &mut sketch.counters {
+            *counter = reader.u64()?;
+        }
+        reader.is_empty().then_some(sketch)
+    }
+}
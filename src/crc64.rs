@@ -0,0 +1,36 @@
+// CRC-64/XZ (the ECMA-182 polynomial, reflected), the checksum the record format puts on each record to
+// catch corruption, see record.rs. Checksums match xz and Go's hash/crc64 with the ECMA table; the check
+// value of "123456789" is 0x995dc9bbdf1939fa.
+
+const POLY: u64 = 0xc96c_5795_d787_0f42;
+
+const TABLE: [u64; 256] = table();
+
+const fn table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+// Extends the checksum `crc` of some bytes to cover `bytes` after them; start from 0
+pub fn update(crc: u64, bytes: &[u8]) -> u64 {
+    let mut crc = !crc;
+    for &byte in bytes {
+        crc = TABLE[((crc ^ byte as u64) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+pub fn checksum(bytes: &[u8]) -> u64 {
+    update(0, bytes)
+}
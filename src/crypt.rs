@@ -0,0 +1,348 @@
+// Encryption at rest: with encryption-key or encryption-key-command set, the disk backend's data file, the
+// write-ahead log and snapshots are encrypted with ChaCha20, and so is the cold tier's scratch file, with a key
+// made up at startup as nothing in it outlives the process. ChaCha20 is used with the 64-bit counter and nonce
+// it was first published with, so a file can grow past 256GiB. Every file gets a random nonce and names the key
+// it was encrypted with in its header, see record.rs:
+//   "ZENQL" | kind u8 | version u16 with ENCRYPTED set | key id length u8 | key id | nonce u64 | check u64
+// Everything after the header is XORed with the keystream at its offset in the file, so records are still
+// appended in place and the disk backend still reads a value where it lies. The check is a bit of keystream no
+// record uses: a file opened with the wrong key is refused up front instead of failing its checksums.
+//
+// What this buys is confidentiality and nothing more: this isn't authenticated encryption. There's no MAC, so
+// files have no integrity protection. Flipping a bit of the ciphertext flips the same bit of the record under
+// it, and as a CRC is linear, whoever can write a file can change a value they know, or flip bits in one they
+// don't, and fix up its CRC to match without the key. The records' CRCs catch damage, not tampering. Files
+// that may be changed by somebody untrusted, snapshots in shared storage say, need protecting some other way.
+//
+// Keys are 256 bits written as 64 hex digits. encryption-key holds one, or names an environment variable to
+// read it from as env:NAME; otherwise encryption-key-command is run with the key's id as its argument and
+// prints it, which is where a KMS comes in. encryption-key-id names the key new files are written with. To
+// rotate keys, give the new one a new id and keep the old one around, in encryption-old-keys or behind the
+// command: on startup the data file or write-ahead log is rewritten with the current key when any of it was
+// written with another, or in the clear, after which the old key is only needed for old snapshots. The same
+// happens after a partial record was cut off the end of an encrypted file, so the keystream that covered it
+// isn't used again for other data.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process::Command;
+use std::sync::Mutex;
+use anyhow::Result;
+use crate::config::Config;
+use crate::locks;
+
+pub type Key = [u8; 32];
+
+// Longest key id a file header holds
+pub const MAX_KEY_ID: usize = 255;
+
+// A key given in the config: 64 hex digits, or env:NAME for an environment variable holding them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeySource(String);
+
+impl KeySource {
+    pub fn parse(text: &str) -> Result<KeySource> {
+        match text.strip_prefix("env:") {
+            Some("") => Err(anyhow::anyhow!("env: needs the name of an environment variable holding the key")),
+            Some(_) => Ok(KeySource(text.to_string())),
+            None if parse_key(text).is_some() => Ok(KeySource(text.to_string())),
+            None => Err(anyhow::anyhow!("An encryption key must be 64 hex digits, or env:NAME")),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn resolve(&self) -> Result<Key> {
+        let Some(name) = self.0.strip_prefix("env:") else {
+            return parse_key(&self.0).ok_or_else(|| anyhow::anyhow!("not 64 hex digits"));
+        };
+        let value = std::env::var(name).map_err(|_| anyhow::anyhow!("the environment variable {} isn't set", name))?;
+        parse_key(value.trim()).ok_or_else(|| anyhow::anyhow!("the environment variable {} doesn't hold 64 hex digits", name))
+    }
+}
+
+// Key ids go in file headers, and in encryption-old-keys as id=key
+pub fn check_key_id(id: &str) -> Result<()> {
+    if id.is_empty() || id.len() > MAX_KEY_ID || id.contains(|c: char| c == '=' || c.is_whitespace() || c.is_control()) {
+        return Err(anyhow::anyhow!("Invalid key id '{}': 1 to {} bytes without spaces or '='", id, MAX_KEY_ID));
+    }
+    Ok(())
+}
+
+// The keys files are encrypted with, by id. Without a current key nothing new is encrypted, but files
+// encrypted with a key it knows can still be read.
+#[derive(Default)]
+pub struct Keyring {
+    current: Option<(String, Key)>,
+    command: Option<String>, // Asked for the keys it doesn't know
+    keys: Mutex<HashMap<String, Key>>, // Older keys, given or fetched
+}
+
+impl Keyring {
+    // Encrypting new files with `key`, known as `id`
+    pub fn new(id: &str, key: Key) -> Self {
+        Keyring { current: Some((id.to_string(), key)), ..Keyring::default() }
+    }
+
+    // Also reading files encrypted with `key`, known as `id`
+    pub fn with_old_key(self, id: &str, key: Key) -> Self {
+        locks::lock(&self.keys).insert(id.to_string(), key);
+        self
+    }
+
+    // The keys the config gives, with the current one fetched from encryption-key-command if need be
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mut keyring = Keyring { command: config.encryption_key_command.clone(), ..Keyring::default() };
+        for (id, source) in &config.encryption_old_keys {
+            let key = source.resolve().map_err(|e| anyhow::anyhow!("Can't read the old key '{}': {}", id, e))?;
+            locks::lock(&keyring.keys).insert(id.clone(), key);
+        }
+        let id = &config.encryption_key_id;
+        let key = match (&config.encryption_key, &keyring.command) {
+            (Some(source), _) => source.resolve().map_err(|e| anyhow::anyhow!("Can't read encryption-key: {}", e))?,
+            (None, Some(command)) => fetch(command, id).map_err(|e| anyhow::anyhow!("Can't fetch the key '{}': {}", id, e))?,
+            (None, None) => return Ok(keyring),
+        };
+        keyring.current = Some((id.clone(), key));
+        Ok(keyring)
+    }
+
+    // Whether new files are encrypted
+    pub fn enabled(&self) -> bool {
+        self.current.is_some()
+    }
+
+    // Id of the key new files are encrypted with
+    pub fn current_id(&self) -> Option<&str> {
+        self.current.as_ref().map(|(id, _)| id.as_str())
+    }
+
+    // What a new file is encrypted with, None when it's written in the clear
+    pub fn cipher(&self) -> io::Result<Option<Cipher>> {
+        self.current.as_ref().map(|(id, key)| Ok(Cipher::new(id, key, u64::from_le_bytes(random()?)))).transpose()
+    }
+
+    // The key `id` names, asking encryption-key-command for one it doesn't know
+    pub fn key(&self, id: &str) -> io::Result<Key> {
+        if let Some((_, key)) = self.current.as_ref().filter(|(current, _)| current == id) {
+            return Ok(*key);
+        }
+        if let Some(key) = locks::lock(&self.keys).get(id) {
+            return Ok(*key);
+        }
+        let Some(command) = &self.command else {
+            return Err(io::Error::other(format!(
+                "it's encrypted with the key '{}', which isn't configured: list it in encryption-old-keys, or set \
+                encryption-key-command to fetch it",
+                id,
+            )));
+        };
+        let key = fetch(command, id).map_err(|e| io::Error::other(format!("fetching its key '{}' failed: {}", id, e)))?;
+        locks::lock(&self.keys).insert(id.to_string(), key);
+        Ok(key)
+    }
+}
+
+// Runs encryption-key-command with `id` as its argument, for the key it prints
+fn fetch(command: &str, id: &str) -> Result<Key> {
+    let output = Command::new("sh").arg("-c").arg(format!("{} \"$1\"", command)).arg("sh").arg(id).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow::anyhow!("encryption-key-command exited with {}: {}", output.status, stderr.trim()));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    parse_key(stdout.trim()).ok_or_else(|| anyhow::anyhow!("encryption-key-command didn't print 64 hex digits"))
+}
+
+fn parse_key(hex: &str) -> Option<Key> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut key = [0; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(key)
+}
+
+fn random<const N: usize>() -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+// The keystream a file is encrypted with
+#[derive(Clone)]
+pub struct Cipher {
+    id: String, // Of the key
+    key: [u32; 8],
+    nonce: u64,
+}
+
+impl std::fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cipher").field("id", &self.id).field("nonce", &self.nonce).finish_non_exhaustive()
+    }
+}
+
+impl Cipher {
+    pub fn new(id: &str, key: &Key, nonce: u64) -> Self {
+        let key = std::array::from_fn(|i| u32::from_le_bytes(key[i * 4..i * 4 + 4].try_into().unwrap()));
+        Cipher { id: id.to_string(), key, nonce }
+    }
+
+    // A cipher with a key of its own that nothing else knows, for files that don't outlive the process
+    pub fn ephemeral() -> io::Result<Self> {
+        Ok(Cipher::new("", &random()?, u64::from_le_bytes(random()?)))
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    // Tells whether a file was encrypted with this key: keystream from the last block, which no file reaches
+    pub fn check(&self) -> u64 {
+        u64::from_le_bytes(self.block(u64::MAX)[..8].try_into().unwrap())
+    }
+
+    // Encrypts or decrypts `data`, which sits at `pos` in the file
+    pub fn apply(&self, pos: u64, data: &mut [u8]) {
+        let mut counter = pos / 64;
+        let mut skip = (pos % 64) as usize;
+        let mut rest = data;
+        while !rest.is_empty() {
+            let stream = self.block(counter);
+            let (chunk, tail) = rest.split_at_mut((64 - skip).min(rest.len()));
+            for (byte, key) in chunk.iter_mut().zip(&stream[skip..]) {
+                *byte ^= key;
+            }
+            rest = tail;
+            counter += 1;
+            skip = 0;
+        }
+    }
+
+    // A copy of `data` encrypted at `pos`
+    pub fn encrypt(&self, pos: u64, data: &[u8]) -> Vec<u8> {
+        let mut data = data.to_vec();
+        self.apply(pos, &mut data);
+        data
+    }
+
+    fn block(&self, counter: u64) -> [u8; 64] {
+        let mut state = [0u32; 16];
+        state[..4].copy_from_slice(&[0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]);
+        state[4..12].copy_from_slice(&self.key);
+        state[12..].copy_from_slice(&[counter as u32, (counter >> 32) as u32, self.nonce as u32, (self.nonce >> 32) as u32]);
+        let mut working = state;
+        for _ in 0..10 {
+            quarter_round(&mut working, 0, 4, 8, 12);
+            quarter_round(&mut working, 1, 5, 9, 13);
+            quarter_round(&mut working, 2, 6, 10, 14);
+            quarter_round(&mut working, 3, 7, 11, 15);
+            quarter_round(&mut working, 0, 5, 10, 15);
+            quarter_round(&mut working, 1, 6, 11, 12);
+            quarter_round(&mut working, 2, 7, 8, 13);
+            quarter_round(&mut working, 3, 4, 9, 14);
+        }
+        let mut block = [0; 64];
+        for (i, word) in working.iter().zip(&state).map(|(working, state)| working.wrapping_add(*state)).enumerate() {
+            block[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        block
+    }
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+// A copy of `data` encrypted at `pos`, or `data` itself for a file in the clear
+pub fn encrypt<'a>(cipher: Option<&Cipher>, pos: u64, data: &'a [u8]) -> std::borrow::Cow<'a, [u8]> {
+    match cipher {
+        Some(cipher) => cipher.encrypt(pos, data).into(),
+        None => data.into(),
+    }
+}
+
+// Writes to a file from `pos` on, encrypting what's written when there's a cipher
+pub struct Writer<W> {
+    inner: W,
+    cipher: Option<Cipher>,
+    pos: u64,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(inner: W, cipher: Option<Cipher>, pos: u64) -> Self {
+        Writer { inner, cipher, pos }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    // For writing what isn't encrypted, such as the header, before anything else
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = match &self.cipher {
+            // Whole, so the position stays in step with the keystream
+            Some(cipher) => {
+                self.inner.write_all(&cipher.encrypt(self.pos, buf))?;
+                buf.len()
+            },
+            None => self.inner.write(buf)?,
+        };
+        self.pos += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Reads a file from `pos` on, decrypting what's read when there's a cipher
+pub struct Reader<R> {
+    inner: R,
+    cipher: Option<Cipher>,
+    pos: u64,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(inner: R, cipher: Option<Cipher>, pos: u64) -> Self {
+        Reader { inner, cipher, pos }
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(cipher) = &self.cipher {
+            cipher.apply(self.pos, &mut buf[..read]);
+        }
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
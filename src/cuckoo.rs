@@ -0,0 +1,257 @@
+// Cuckoo filters (Fan et al.): like a Bloom filter they answer whether an item may have been added, but they
+// keep a one-byte fingerprint of each item rather than setting bits, so items can be deleted again and counted.
+// Each item has two buckets of `bucket_size` slots, the second found from the first and the fingerprint alone,
+// so a fingerprint can be moved to its other bucket without the item. An item goes in whichever has room;
+// when neither has, fingerprints are kicked to their other buckets, up to `max_iterations` times. When that
+// fails a new filter `expansion` times bigger is stacked on, or with no expansion the filter is full.
+// Items may be added more than once, and each copy takes a slot. Items are hashed with hasher::stable_hash,
+// as filters are persisted.
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use crate::hasher::stable_hash;
+use crate::record::PayloadReader;
+
+pub const DEFAULT_CAPACITY: u64 = 1024;
+pub const DEFAULT_BUCKET_SIZE: u64 = 2;
+pub const DEFAULT_MAX_ITERATIONS: u64 = 20;
+pub const DEFAULT_EXPANSION: u64 = 1;
+// Bounds a single filter's size, 512MiB, how many can stack up and the options
+const MAX_SLOTS: u64 = 1 << 29;
+const MAX_FILTERS: usize = 64;
+pub const MAX_BUCKET_SIZE: u64 = 255;
+pub const MAX_ITERATIONS: u64 = 65535;
+pub const MAX_EXPANSION: u64 = 32768;
+const SEEDS: (u64, u64) = (0xa409_3822_299f_31d0, 0x082e_fa98_ec4e_6c89);
+// An empty slot
+const EMPTY: u8 = 0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Filter {
+    buckets: u64, // A power of two
+    count: u64,
+    slots: Vec<u8>,
+}
+
+impl Filter {
+    fn new(capacity: u64, bucket_size: u64) -> Result<Filter> {
+        let buckets = capacity.div_ceil(bucket_size).max(1).next_power_of_two();
+        if buckets.saturating_mul(bucket_size) > MAX_SLOTS {
+            bail!("a filter for {} items would be too large", capacity);
+        }
+        Ok(Filter { buckets, count: 0, slots: vec![EMPTY; (buckets * bucket_size) as usize] })
+    }
+
+    fn capacity(&self, bucket_size: u64) -> u64 {
+        self.buckets * bucket_size
+    }
+
+    // An item's two buckets
+    fn buckets(&self, (hash, fingerprint): (u64, u8)) -> (u64, u64) {
+        let first = hash & (self.buckets - 1);
+        (first, self.other(first, fingerprint))
+    }
+
+    // The bucket a fingerprint in `bucket` could move to, and back
+    fn other(&self, bucket: u64, fingerprint: u8) -> u64 {
+        (bucket ^ stable_hash(&[fingerprint], SEEDS.1)) & (self.buckets - 1)
+    }
+
+    fn bucket(&mut self, bucket: u64, bucket_size: u64) -> &mut [u8] {
+        let start = (bucket * bucket_size) as usize;
+        &mut self.slots[start..start + bucket_size as usize]
+    }
+
+    fn count(&self, item: (u64, u8), bucket_size: u64) -> u64 {
+        let (first, second) = self.buckets(item);
+        let in_bucket = |bucket: u64| {
+            let start = (bucket * bucket_size) as usize;
+            self.slots[start..start + bucket_size as usize].iter().filter(|&&slot| slot == item.1).count() as u64
+        };
+        in_bucket(first) + if second == first { 0 } else { in_bucket(second) }
+    }
+
+    // Puts a fingerprint in the first empty slot of `bucket`, false if there's none
+    fn place(&mut self, bucket: u64, bucket_size: u64, fingerprint: u8) -> bool {
+        match self.bucket(bucket, bucket_size).iter_mut().find(|slot| **slot == EMPTY) {
+            Some(slot) => {
+                *slot = fingerprint;
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Adds an item, kicking others to their other buckets to make room, false if that fails
+    fn insert(&mut self, item: (u64, u8), bucket_size: u64, max_iterations: u64) -> bool {
+        let (first, second) = self.buckets(item);
+        if self.place(first, bucket_size, item.1) || self.place(second, bucket_size, item.1) {
+            self.count += 1;
+            return true;
+        }
+        // Which slots were swapped, so a failed insert can put everything back as it was
+        let mut kicked = vec![];
+        let (mut bucket, mut fingerprint) = (first, item.1);
+        for i in 0..max_iterations {
+            let slot = (bucket * bucket_size + i % bucket_size) as usize;
+            fingerprint = std::mem::replace(&mut self.slots[slot], fingerprint);
+            kicked.push(slot);
+            bucket = self.other(bucket, fingerprint);
+            if self.place(bucket, bucket_size, fingerprint) {
+                self.count += 1;
+                return true;
+            }
+        }
+        for slot in kicked.into_iter().rev() {
+            fingerprint = std::mem::replace(&mut self.slots[slot], fingerprint);
+        }
+        false
+    }
+
+    // Removes one copy of an item, false if it isn't there
+    fn delete(&mut self, item: (u64, u8), bucket_size: u64) -> bool {
+        let (first, second) = self.buckets(item);
+        for bucket in [first, second] {
+            if let Some(slot) = self.bucket(bucket, bucket_size).iter_mut().find(|slot| **slot == item.1) {
+                *slot = EMPTY;
+                self.count -= 1;
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CuckooFilter {
+    pub bucket_size: u64,
+    pub max_iterations: u64,
+    pub expansion: u64, // 0 for a filter that doesn't grow
+    pub deleted: u64,
+    filters: Vec<Filter>,
+}
+
+// The hash picking an item's first bucket, and its fingerprint, never an empty slot's
+fn hash(item: &[u8]) -> (u64, u8) {
+    let hash = stable_hash(item, SEEDS.0);
+    (hash, (hash >> 56) as u8 % 255 + 1)
+}
+
+impl CuckooFilter {
+    pub fn new(capacity: u64, bucket_size: u64, max_iterations: u64, expansion: u64) -> Result<CuckooFilter> {
+        if capacity == 0 {
+            bail!("the capacity must be positive");
+        }
+        if !(1..=MAX_BUCKET_SIZE).contains(&bucket_size) {
+            bail!("the bucket size must be between 1 and {}", MAX_BUCKET_SIZE);
+        }
+        if !(1..=MAX_ITERATIONS).contains(&max_iterations) {
+            bail!("the max iterations must be between 1 and {}", MAX_ITERATIONS);
+        }
+        if expansion > MAX_EXPANSION {
+            bail!("the expansion must be at most {}", MAX_EXPANSION);
+        }
+        let filter = Filter::new(capacity, bucket_size)?;
+        Ok(CuckooFilter { bucket_size, max_iterations, expansion, deleted: 0, filters: vec![filter] })
+    }
+
+    // Adds an item, another copy if it's there already
+    pub fn insert(&mut self, item: &[u8]) -> Result<()> {
+        let item = hash(item);
+        let (bucket_size, max_iterations) = (self.bucket_size, self.max_iterations);
+        if self.filters.iter_mut().rev().any(|filter| filter.insert(item, bucket_size, max_iterations)) {
+            return Ok(());
+        }
+        if self.expansion == 0 {
+            bail!("filter is full");
+        }
+        if self.filters.len() >= MAX_FILTERS {
+            bail!("the filter can't grow any further");
+        }
+        let last = self.filters.last().expect("there's always a filter");
+        let mut filter = Filter::new(last.capacity(bucket_size).saturating_mul(self.expansion), bucket_size)?;
+        filter.insert(item, bucket_size, max_iterations);
+        self.filters.push(filter);
+        Ok(())
+    }
+
+    // Whether an item may have been added and not deleted since
+    pub fn contains(&self, item: &[u8]) -> bool {
+        let item = hash(item);
+        self.filters.iter().any(|filter| filter.count(item, self.bucket_size) > 0)
+    }
+
+    // How many copies of an item there may be
+    pub fn count(&self, item: &[u8]) -> u64 {
+        let item = hash(item);
+        self.filters.iter().map(|filter| filter.count(item, self.bucket_size)).sum()
+    }
+
+    // Removes one copy of an item, newest filter first, false if there's none. Deleting an item never added
+    // may remove another's fingerprint.
+    pub fn delete(&mut self, item: &[u8]) -> bool {
+        let item = hash(item);
+        let bucket_size = self.bucket_size;
+        let deleted = self.filters.iter_mut().rev().any(|filter| filter.delete(item, bucket_size));
+        self.deleted += deleted as u64;
+        deleted
+    }
+
+    // How many items are in
+    pub fn len(&self) -> u64 {
+        self.filters.iter().map(|filter| filter.count).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn buckets(&self) -> u64 {
+        self.filters.iter().map(|filter| filter.buckets).sum()
+    }
+
+    pub fn filters(&self) -> usize {
+        self.filters.len()
+    }
+
+    // Bytes of slots
+    pub fn size(&self) -> usize {
+        self.filters.iter().map(|filter| filter.slots.len()).sum()
+    }
+
+    // The filter as stored in a record: bucket size u64 | max iterations u64 | expansion u64 | deleted u64 |
+    // filter count u64, then each filter's buckets u64 | count u64 | its slots, a byte each, all little-endian
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(40 + self.size() + 16 * self.filters.len());
+        for n in [self.bucket_size, self.max_iterations, self.expansion, self.deleted, self.filters.len() as u64] {
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        for filter in &self.filters {
+            out.extend_from_slice(&filter.buckets.to_le_bytes());
+            out.extend_from_slice(&filter.count.to_le_bytes());
+            out.extend_from_slice(&filter.slots);
+        }
+        out
+    }
+
+    pub fn decode(payload: &Bytes) -> Option<CuckooFilter> {
+        let mut reader = PayloadReader::new(payload);
+        let (bucket_size, max_iterations, expansion, deleted) = (reader.u64()?, reader.u64()?, reader.u64()?, reader.u64()?);
+        let count = reader.u64()?;
+        if !(1..=MAX_BUCKET_SIZE).contains(&bucket_size) || count == 0 || count > MAX_FILTERS as u64 {
+            return None;
+        }
+        let mut filters = vec![];
+        for _ in 0..count {
+            let (buckets, count) = (reader.u64()?, reader.u64()?);
+            if !buckets.is_power_of_two() || buckets.saturating_mul(bucket_size) > MAX_SLOTS {
+                return None;
+            }
+            let slots = reader.take((buckets * bucket_size) as usize)?.to_vec();
+            filters.push(Filter { buckets, count, slots });
+        }
+        if !reader.is_empty() {
+            return None;
+        }
+        Some(CuckooFilter { bucket_size, max_iterations, expansion, deleted, filters })
+    }
+}
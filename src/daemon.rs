@@ -0,0 +1,89 @@
+use std::ffi::OsStr;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use anyhow::Result;
+use crate::config::Config;
+use crate::log::log_warn;
+
+// Set in the environment of the background process daemonize starts
+const DAEMONIZED_ENV: &str = "ZENQL_DAEMONIZED";
+
+// Tells the service manager about a state change, e.g. READY=1, when started as a Type=notify unit; does
+// nothing otherwise
+pub fn notify(state: &str) {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = send_notification(&path, state) {
+        log_warn!("Failed to notify the service manager of {}: {}", state, e);
+    }
+}
+
+// The socket path is either a file or, prefixed with '@', a name in the abstract namespace
+fn send_notification(path: &OsStr, state: &str) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    let addr = match path.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(path)?,
+    };
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+// How often to ping the watchdog when the unit sets WatchdogSec=, at half the timeout as systemd suggests
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    // WATCHDOG_PID names the process meant to ping, absent means us
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return None;
+        }
+    }
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+// Background task keeping the watchdog from restarting us while the runtime is responsive
+pub async fn ping_watchdog(interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        notify("WATCHDOG=1");
+    }
+}
+
+pub fn write_pidfile(path: &Path) -> Result<()> {
+    std::fs::write(path, format!("{}\n", std::process::id()))
+        .map_err(|e| anyhow::anyhow!("Can't write pidfile '{}': {}", path.display(), e))
+}
+
+// Moves the server into the background: the same command line runs again as a child in a process group of
+// its own, with stdin from /dev/null and output appended to the logfile (discarded without one), and this
+// process exits. Without fork and setsid, which need libc, the child stays in the terminal's session, but
+// it's out of its job control and holds no reference to the terminal. Returns in the child.
+pub fn daemonize(config: &Config) -> Result<()> {
+    if std::env::var_os(DAEMONIZED_ENV).is_some() {
+        return Ok(());
+    }
+    let output = match &config.logfile {
+        Some(path) => OpenOptions::new().create(true).append(true).open(path),
+        None => OpenOptions::new().write(true).open("/dev/null"),
+    }
+    .map_err(|e| anyhow::anyhow!("Can't open the output of the daemon: {}", e))?;
+    Command::new(std::env::current_exe()?)
+        .args(std::env::args_os().skip(1))
+        .env(DAEMONIZED_ENV, "1")
+        .stdin(Stdio::null())
+        .stdout(output.try_clone()?)
+        .stderr(output)
+        .process_group(0)
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Can't start the daemon: {}", e))?;
+    std::process::exit(0);
+}
@@ -0,0 +1,169 @@
+// The disk backend: an append-only log of every change to the keyspace, replayed on startup. The keyspace
+// keeps each value's position in the log rather than the value, so the data set may be larger than RAM as
+// long as the keys fit. Integers and the empty value are kept in memory as well, they're no bigger than a
+// position. Writes aren't fsynced, they survive the process dying but not the machine.
+// The records are described in record.rs, and with encryption on they're encrypted, see crypt.rs.
+//
+// Overwritten values pile up in the log, so once it has doubled in size since it was last compacted (and is
+// past COMPACT_MIN_SIZE), it is rewritten with only the live keys. That happens with every shard locked. A log
+// written with a key other than the current one is rewritten the same way, straight after startup.
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use bytes::Bytes;
+use crate::backend::StorageBackend;
+use crate::crypt::{self, Cipher, Keyring};
+use crate::locks;
+use crate::log::log_error;
+use crate::record::{self, in_memory, FileKind, Record, Values};
+use crate::storage::{Item, StoredValue};
+
+// In the data directory
+pub const DATA_FILE: &str = "zenql.db";
+
+const COMPACT_MIN_SIZE: u64 = 64 * 1024 * 1024;
+
+pub struct Disk {
+    path: PathBuf,
+    keys: Arc<Keyring>,
+    log: RwLock<Log>,
+}
+
+struct Log {
+    file: File,
+    end: u64,
+    compacted_size: u64, // Size after the last compaction, or at startup
+    cipher: Option<Cipher>, // The file is encrypted with
+    stale: bool, // Not encrypted with the current key, to be rewritten
+}
+
+impl Disk {
+    // Opens the log at `path`, creating it if needed, and passes every record in it to `apply` in order. A
+    // partial record at the end, left by a crash mid-write, is cut off.
+    pub fn open(path: &Path, keys: Arc<Keyring>, apply: impl FnMut(Record)) -> io::Result<Disk> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let replayed = record::replay(&file, path, FileKind::Data, Values::Positions, true, &keys, apply)?;
+        let stale = replayed.cipher.as_ref().map(Cipher::id) != keys.current_id() || (replayed.cut && replayed.cipher.is_some());
+        let log = Log { file, end: replayed.end, compacted_size: replayed.end, cipher: replayed.cipher, stale };
+        Ok(Disk { path: path.to_path_buf(), keys, log: RwLock::new(log) })
+    }
+
+    // Appends a record, returning the offset it starts at
+    fn append(&self, record: &[u8]) -> io::Result<u64> {
+        let mut log = locks::write(&self.log);
+        let offset = log.end;
+        log.file.write_all_at(&crypt::encrypt(log.cipher.as_ref(), offset, record), offset)?;
+        log.end += record.len() as u64;
+        Ok(offset)
+    }
+
+    fn append_or_warn(&self, record: &[u8]) {
+        if let Err(e) = self.append(record) {
+            log_error!("Writing to {} failed, the change is lost on restart: {}", self.path.display(), e);
+        }
+    }
+}
+
+impl StorageBackend for Disk {
+    fn write(&self, key: &Bytes, value: StoredValue, expires_at: Option<u64>) -> StoredValue {
+        let mut record = record::set_header(key, expires_at);
+        let start = record.len();
+        let section = match record::section(&value) {
+            Some(section) => section,
+            None => return value,
+        };
+        record.extend_from_slice(&section);
+        match self.append(&record::seal(record)) {
+            Ok(_) if in_memory(&value) => value,
+            Ok(offset) => StoredValue::OnDisk { offset: offset + start as u64, len: section.len() },
+            Err(e) => {
+                log_error!("Writing to {} failed, keeping the value in memory until restart: {}", self.path.display(), e);
+                value
+            },
+        }
+    }
+
+    fn set_expiry(&self, key: &Bytes, expires_at: Option<u64>) {
+        self.append_or_warn(&record::expire(key, expires_at));
+    }
+
+    fn remove(&self, key: &Bytes) {
+        self.append_or_warn(&record::del(key));
+    }
+
+    fn read(&self, value: &StoredValue) -> io::Result<Bytes> {
+        let StoredValue::OnDisk { offset, len } = value else {
+            return Ok(value.to_bytes());
+        };
+        let mut section = vec![0; *len];
+        let log = locks::read(&self.log);
+        log.file.read_exact_at(&mut section, *offset).map_err(|e| io::Error::new(e.kind(), format!("reading {} at {}: {}", self.path.display(), offset, e)))?;
+        if let Some(cipher) = &log.cipher {
+            cipher.apply(*offset, &mut section);
+        }
+        drop(log);
+        record::decode_section(section).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("the value in {} at {} failed to decode", self.path.display(), offset)))
+    }
+
+    fn needs_compaction(&self) -> bool {
+        let log = locks::read(&self.log);
+        log.stale || (log.end >= COMPACT_MIN_SIZE && log.end >= log.compacted_size * 2)
+    }
+
+    // Writes the live keys to a new file, encrypted with the current key, and swaps it in. Values only move
+    // once the new file is in place.
+    fn compact<'a>(&self, items: &mut dyn Iterator<Item = (&'a Bytes, &'a mut Item)>) -> io::Result<()> {
+        let mut log = locks::write(&self.log);
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&temp)?;
+        let cipher = self.keys.cipher()?;
+        let header = record::file_header(FileKind::Data, cipher.as_ref());
+        let mut out = BufWriter::new(file);
+        let mut end = header.len() as u64;
+        let mut moved = vec![];
+        let written: io::Result<()> = (|| {
+            out.write_all(&header)?;
+            let mut out = crypt::Writer::new(&mut out, cipher.clone(), end);
+            for (key, item) in items {
+                let section = match &item.value {
+                    StoredValue::OnDisk { offset, len } => {
+                        let mut section = vec![0; *len];
+                        log.file.read_exact_at(&mut section, *offset)?;
+                        if let Some(cipher) = &log.cipher {
+                            cipher.apply(*offset, &mut section);
+                        }
+                        section
+                    },
+                    other => record::section(other).unwrap_or_default(),
+                };
+                let mut record = record::set_header(key, item.expires_at);
+                let start = record.len();
+                record.extend_from_slice(&section);
+                let record = record::seal(record);
+                out.write_all(&record)?;
+                if matches!(item.value, StoredValue::OnDisk { .. }) {
+                    moved.push((item, end + start as u64));
+                }
+                end += record.len() as u64;
+            }
+            out.flush()?;
+            out.get_ref().get_ref().sync_all()?;
+            fs::rename(&temp, &self.path)
+        })();
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temp);
+            return Err(e);
+        }
+        for (item, new_offset) in moved {
+            if let StoredValue::OnDisk { offset, .. } = &mut item.value {
+                *offset = new_offset;
+            }
+        }
+        let file = out.into_inner().map_err(|e| e.into_error())?;
+        *log = Log { file, end, compacted_size: end, cipher, stale: false };
+        Ok(())
+    }
+}
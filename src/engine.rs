@@ -0,0 +1,152 @@
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use bytes::Bytes;
+use crate::clock::{Clock, SystemClock};
+use crate::hasher::HasherKind;
+use crate::storage::{BackendKind, ExpireFlags, Storage, DEFAULT_SHARDS};
+
+// The keyspace as a plain in-process cache: no networking, no async, no RESP. Every method takes &self and
+// is safe to call from many threads; wrap the engine in a SharedEngine to hand it out.
+//
+// Nothing deletes expired keys in the background here. They read as missing straight away, and
+// purge_expired reclaims their memory whenever the embedder sees fit. Likewise an engine opened on disk only
+// compacts its data file when told to.
+#[derive(Default)]
+pub struct Engine {
+    storage: Storage,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Engine::default()
+    }
+
+    pub fn with_backend(kind: BackendKind, shards: usize) -> Self {
+        Engine { storage: Storage::new(kind, shards) }
+    }
+
+    // An engine whose expiry deadlines follow `clock`, e.g. a ManualClock in tests
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Engine { storage: Storage::with_clock(BackendKind::Sharded, DEFAULT_SHARDS, HasherKind::default(), clock) }
+    }
+
+    // An engine keeping its values in a data file in `dir`, loading what's there
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        Engine::open_with_clock(dir, Arc::new(SystemClock))
+    }
+
+    pub fn open_with_clock(dir: impl AsRef<Path>, clock: Arc<dyn Clock>) -> io::Result<Self> {
+        Ok(Engine { storage: Storage::open(BackendKind::Disk, DEFAULT_SHARDS, HasherKind::default(), dir.as_ref(), Arc::default(), clock)? })
+    }
+
+    // An engine keeping its values in memory and logging every write to a write-ahead log in `dir`, loading
+    // what's there. Writes are durable once sync returns; dropping the engine syncs as well.
+    pub fn open_wal(dir: impl AsRef<Path>, segment_size: u64) -> io::Result<Self> {
+        Ok(Engine { storage: Storage::open_wal(BackendKind::Sharded, DEFAULT_SHARDS, HasherKind::default(), dir.as_ref(), segment_size, Arc::default(), Arc::new(SystemClock))? })
+    }
+
+    pub fn set(&self, key: impl Into<Bytes>, value: impl Into<Bytes>) {
+        self.storage.set(key.into(), value.into(), None);
+    }
+
+    // Sets a key that expires after `ttl`
+    pub fn set_ex(&self, key: impl Into<Bytes>, value: impl Into<Bytes>, ttl: Duration) {
+        self.storage.set(key.into(), value.into(), Some(self.deadline_after(ttl)));
+    }
+
+    // A key holding a JSON document, which only the server's JSON commands write, reads as missing
+    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
+        self.storage.get(key).unwrap_or(None)
+    }
+
+    pub fn exists(&self, key: &[u8]) -> bool {
+        self.storage.expires_at(key).is_some()
+    }
+
+    // True when the key existed
+    pub fn del(&self, key: &[u8]) -> bool {
+        self.storage.del(key)
+    }
+
+    // Expires an existing key after `ttl`, returning whether it existed
+    pub fn expire(&self, key: &[u8], ttl: Duration) -> bool {
+        self.storage.expire(key, Some(self.deadline_after(ttl)), ExpireFlags::default())
+    }
+
+    pub fn expire_at(&self, key: &[u8], deadline: SystemTime) -> bool {
+        self.storage.expire(key, Some(unix_ms(deadline)), ExpireFlags::default())
+    }
+
+    // Removes a key's expiry, returning whether it had one
+    pub fn persist(&self, key: &[u8]) -> bool {
+        self.storage.expire(key, None, ExpireFlags::default())
+    }
+
+    // None when the key doesn't exist, Some(None) when it never expires
+    pub fn ttl(&self, key: &[u8]) -> Option<Option<Duration>> {
+        self.storage.ttl(key)
+    }
+
+    pub fn expires_at(&self, key: &[u8]) -> Option<Option<SystemTime>> {
+        let deadline = self.storage.expires_at(key)?;
+        Some(deadline.map(|ms| UNIX_EPOCH + Duration::from_millis(ms)))
+    }
+
+    // Number of keys, including expired ones not purged yet
+    pub fn len(&self) -> usize {
+        self.storage.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.storage.is_empty()
+    }
+
+    // Deletes expired keys, returning how many
+    pub fn purge_expired(&self) -> usize {
+        self.storage.remove_expired()
+    }
+
+    // Writes everything so far to the write-ahead log and fsyncs it, for an engine opened with one
+    pub fn sync(&self) -> io::Result<()> {
+        self.storage.sync()
+    }
+
+    // Rewrites the data file of an engine opened on disk without the overwritten and deleted values, every
+    // other call waiting for it. One with a write-ahead log checkpoints it instead: the keyspace goes to a new
+    // file and the older ones are deleted, while other calls carry on.
+    pub fn compact(&self) -> io::Result<()> {
+        self.storage.compact()
+    }
+
+    // Whether the data file has grown enough since it was last compacted to make compacting worthwhile
+    pub fn needs_compaction(&self) -> bool {
+        self.storage.needs_compaction()
+    }
+
+    pub fn shared(self) -> SharedEngine {
+        SharedEngine(Arc::new(self))
+    }
+
+    fn deadline_after(&self, ttl: Duration) -> u64 {
+        self.storage.now_ms().saturating_add(ttl.as_millis().min(i64::MAX as u128) as u64)
+    }
+}
+
+// Cheaply cloneable handle to one engine, for sharing it between threads and tasks
+#[derive(Clone, Default)]
+pub struct SharedEngine(Arc<Engine>);
+
+impl Deref for SharedEngine {
+    type Target = Engine;
+
+    fn deref(&self) -> &Engine {
+        &self.0
+    }
+}
+
+fn unix_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
@@ -0,0 +1,65 @@
+use crate::resp::Value;
+use crate::storage::{ReadError, WrongType};
+
+// Errors of the protocol and command layers. Displaying one gives the RESP error reply, error code first;
+// see into_value.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    // Malformed input; the connection is closed after replying
+    #[error("ERR Protocol error: {0}")]
+    Protocol(String),
+    #[error("ERR wrong number of arguments for '{0}' command")]
+    WrongArity(String),
+    // Command name as sent and a preview of its arguments
+    #[error("ERR unknown command '{0}', with args beginning with: {1}")]
+    UnknownCommand(String, String),
+    // Command name and the subcommand as sent
+    #[error("ERR unknown subcommand '{1}'. Try {} HELP.", .0.to_uppercase())]
+    UnknownSubcommand(String, String),
+    #[error("ERR syntax error")]
+    Syntax,
+    #[error("ERR value is not an integer or out of range")]
+    NotInteger,
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+    #[error("ERR invalid expire time in '{0}' command")]
+    InvalidExpireTime(String),
+    // The command ran past command-time-budget, given in milliseconds
+    #[error("ERR command aborted after running longer than the {0}ms command-time-budget")]
+    TimeBudget(u64),
+    #[error("NOAUTH Authentication required.")]
+    NoAuth,
+    // Any other reply, given in full including its error code
+    #[error("{0}")]
+    Reply(String),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl Error {
+    pub fn reply(message: impl Into<String>) -> Error {
+        Error::Reply(message.into())
+    }
+
+    // The error reply sent to the client
+    pub fn into_value(self) -> Value {
+        Value::Error(self.to_string())
+    }
+}
+
+impl From<WrongType> for Error {
+    fn from(_: WrongType) -> Error {
+        Error::WrongType
+    }
+}
+
+impl From<ReadError> for Error {
+    fn from(e: ReadError) -> Error {
+        match e {
+            ReadError::WrongType => Error::WrongType,
+            ReadError::Unreadable => Error::reply("ERR the value couldn't be read back from disk, see the server log"),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
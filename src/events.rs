@@ -0,0 +1,125 @@
+// Keyspace events: what happened to which key, for whatever outside the keyspace follows its changes, such
+// as webhooks (see webhook.rs) and bridges (bridge.rs). Storage sends them to every subscriber while it still
+// holds the key's shard lock, so each key's events arrive in the order its changes were made. With no
+// subscribers sending costs a relaxed load.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use anyhow::Result;
+use bytes::Bytes;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use crate::glob;
+use crate::json::Json;
+use crate::locks;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyEventKind {
+    Set, // Any write, whatever the key holds
+    Expire, // A live key's deadline was set or cleared, by EXPIRE, PERSIST and the like
+    Del,
+    Expired, // Deleted by the expiry cycle, or by DEL after its deadline passed
+    Evicted, // Nothing evicts yet, which is why maxmemory only takes 0
+}
+
+pub const KEY_EVENT_KINDS: [KeyEventKind; 5] = [KeyEventKind::Set, KeyEventKind::Expire, KeyEventKind::Del, KeyEventKind::Expired, KeyEventKind::Evicted];
+
+impl KeyEventKind {
+    pub fn parse(name: &str) -> Result<KeyEventKind> {
+        match name.to_lowercase().as_str() {
+            "set" => Ok(KeyEventKind::Set),
+            "expire" => Ok(KeyEventKind::Expire),
+            "del" => Ok(KeyEventKind::Del),
+            "expired" => Ok(KeyEventKind::Expired),
+            "evicted" => Ok(KeyEventKind::Evicted),
+            _ => Err(anyhow::anyhow!("Invalid keyspace event '{}', expected set, expire, del, expired or evicted", name)),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            KeyEventKind::Set => "set",
+            KeyEventKind::Expire => "expire",
+            KeyEventKind::Del => "del",
+            KeyEventKind::Expired => "expired",
+            KeyEventKind::Evicted => "evicted",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub kind: KeyEventKind,
+    pub key: Bytes,
+    pub time_ms: u64, // UNIX milliseconds by the storage's clock
+}
+
+impl KeyEvent {
+    // As webhooks and bridges send it: {"event":"set","key":"user:1","time":1700000000000}
+    pub fn to_json(&self) -> Json {
+        Json::Object(vec![
+            ("event".to_string(), Json::String(self.kind.as_str().to_string())),
+            ("key".to_string(), Json::String(String::from_utf8_lossy(&self.key).into_owned())),
+            ("time".to_string(), Json::Int(self.time_ms as i64)),
+        ])
+    }
+}
+
+// Which events something following the keyspace wants: of the kinds listed, all by default, about keys
+// matching the glob, if any
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventFilter {
+    kinds: Vec<KeyEventKind>,
+    pattern: Option<Bytes>,
+}
+
+impl Default for EventFilter {
+    fn default() -> Self {
+        EventFilter { kinds: KEY_EVENT_KINDS.to_vec(), pattern: None }
+    }
+}
+
+impl EventFilter {
+    // Applies `events=<kinds>` or `match=<pattern>`, false for any other option
+    pub fn set(&mut self, name: &str, value: &str) -> Result<bool> {
+        match name {
+            "events" => self.kinds = value.split(',').map(KeyEventKind::parse).collect::<Result<_>>()?,
+            "match" => self.pattern = Some(Bytes::copy_from_slice(value.as_bytes())),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    pub fn wants(&self, event: &KeyEvent) -> bool {
+        self.kinds.contains(&event.kind) && self.pattern.as_ref().is_none_or(|pattern| glob::matches(pattern, &event.key))
+    }
+}
+
+#[derive(Default)]
+pub struct Events {
+    subscribers: RwLock<Vec<UnboundedSender<KeyEvent>>>,
+    any: AtomicBool,
+}
+
+impl Events {
+    // Every event from now on, until the receiver is dropped
+    pub fn subscribe(&self) -> UnboundedReceiver<KeyEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut subscribers = locks::write(&self.subscribers);
+        subscribers.retain(|subscriber| !subscriber.is_closed());
+        subscribers.push(tx);
+        self.any.store(true, Ordering::Relaxed);
+        rx
+    }
+
+    pub fn active(&self) -> bool {
+        self.any.load(Ordering::Relaxed)
+    }
+
+    pub fn send(&self, kind: KeyEventKind, key: &Bytes, time_ms: u64) {
+        if !self.active() {
+            return;
+        }
+        for subscriber in locks::read(&self.subscribers).iter() {
+            let _ = subscriber.send(KeyEvent { kind, key: key.clone(), time_ms });
+        }
+    }
+}
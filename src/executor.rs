@@ -0,0 +1,63 @@
+use std::panic::{self, AssertUnwindSafe};
+use anyhow::Result;
+use tokio::sync::{mpsc, oneshot};
+
+// Where commands run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionModel {
+    Inline, // On the connection's own task, in parallel with other connections
+    Executor, // On the one executor thread, in arrival order
+}
+
+impl ExecutionModel {
+    pub fn parse(name: &str) -> Result<ExecutionModel> {
+        match name.to_lowercase().as_str() {
+            "inline" => Ok(ExecutionModel::Inline),
+            "executor" => Ok(ExecutionModel::Executor),
+            _ => Err(anyhow::anyhow!("Invalid command execution '{}', expected inline or executor", name)),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ExecutionModel::Inline => "inline",
+            ExecutionModel::Executor => "executor",
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// A dedicated thread running jobs one at a time, in the order they were submitted. When every command goes
+// through it, the storage locks are never contended and commands from all clients have a single execution
+// order; connection tasks are left with reading, parsing and writing replies.
+//
+// The thread stops once the Executor is dropped and the queued jobs have run.
+pub struct Executor {
+    jobs: mpsc::UnboundedSender<Job>,
+}
+
+impl Executor {
+    pub fn start() -> Self {
+        let (jobs, mut queue) = mpsc::unbounded_channel::<Job>();
+        std::thread::Builder::new()
+            .name("executor".to_string())
+            .spawn(move || {
+                while let Some(job) = queue.blocking_recv() {
+                    // A panicking job only fails its own caller, like a panic on a connection task would
+                    let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                }
+            })
+            .expect("failed to spawn the executor thread");
+        Executor { jobs }
+    }
+
+    // Runs `job` on the executor thread and waits for its result
+    pub async fn run<T: Send + 'static>(&self, job: impl FnOnce() -> T + Send + 'static) -> T {
+        let (sender, result) = oneshot::channel();
+        let _ = self.jobs.send(Box::new(move || {
+            let _ = sender.send(job());
+        }));
+        result.await.expect("job panicked on the executor")
+    }
+}
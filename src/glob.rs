@@ -0,0 +1,64 @@
+// Glob-style patterns as Redis matches them in SCAN MATCH, KEYS and friends, on bytes: `*` matches any run,
+// `?` any one byte, `[abc]`, `[a-z]` and `[^abc]` a byte in or out of a set, and `\` makes the byte after it
+// literal. A `[` without its `]` runs to the end of the pattern.
+
+// Whether `pattern` matches the whole of `string`. A star that turns out too short is stretched a byte at a
+// time; only the last star needs revisiting, so this never backtracks exponentially.
+pub fn matches(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    let mut star = None; // Pattern position after the last star and the string position it stretches to
+    while s < string.len() {
+        let step = match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p + 1, s));
+                p += 1;
+                continue;
+            },
+            Some(b'?') => Some(1),
+            Some(b'[') => match class(&pattern[p..], string[s]) {
+                (true, len) => Some(len),
+                (false, _) => None,
+            },
+            Some(b'\\') if p + 1 < pattern.len() => (pattern[p + 1] == string[s]).then_some(2),
+            Some(&byte) => (byte == string[s]).then_some(1),
+            None => None,
+        };
+        match (step, star) {
+            (Some(step), _) => {
+                p += step;
+                s += 1;
+            },
+            (None, Some((after, stretched))) => {
+                star = Some((after, stretched + 1));
+                p = after;
+                s = stretched + 1;
+            },
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|&byte| byte == b'*')
+}
+
+// Whether `byte` is in the set `pattern` starts with, and how long the set is including its brackets
+fn class(pattern: &[u8], byte: u8) -> (bool, usize) {
+    let mut i = 1;
+    let negated = pattern.get(i) == Some(&b'^');
+    if negated {
+        i += 1;
+    }
+    let mut found = false;
+    while i < pattern.len() && pattern[i] != b']' {
+        if pattern[i] == b'\\' && i + 1 < pattern.len() {
+            i += 1;
+            found |= pattern[i] == byte;
+        } else if i + 2 < pattern.len() && pattern[i + 1] == b'-' {
+            let (start, end) = (pattern[i].min(pattern[i + 2]), pattern[i].max(pattern[i + 2]));
+            found |= (start..=end).contains(&byte);
+            i += 2;
+        } else {
+            found |= pattern[i] == byte;
+        }
+        i += 1;
+    }
+    (found != negated, (i + 1).min(pattern.len()))
+}
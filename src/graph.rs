@@ -0,0 +1,232 @@
+// Property graphs: nodes named by id, each with properties, and directed edges between them, each with a label
+// and properties of its own. Two nodes may be joined by any number of edges, one per direction and label.
+// Every node keeps the edges leaving it and, so traversals can follow edges backwards as cheaply, the ones
+// arriving at it. Traversals are breadth first, bounded by depth and by how many nodes they return, so a
+// query's cost follows what it asks for rather than the graph's size.
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use bytes::Bytes;
+use crate::record::PayloadReader;
+use crate::storage::WALK_CHECK_EVERY;
+
+// A node's or edge's properties, by name
+pub type Properties = BTreeMap<Bytes, Bytes>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+struct Node {
+    properties: Properties,
+    out: BTreeMap<(Bytes, Bytes), Properties>, // Edges leaving by target and label
+    incoming: BTreeSet<(Bytes, Bytes)>, // Edges arriving by source and label
+}
+
+// Which way traversals follow edges
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Direction {
+    #[default]
+    Out,
+    In,
+    Both,
+}
+
+impl Direction {
+    pub fn parse(name: &str) -> Option<Direction> {
+        match name.to_lowercase().as_str() {
+            "out" => Some(Direction::Out),
+            "in" => Some(Direction::In),
+            "both" => Some(Direction::Both),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Graph {
+    nodes: BTreeMap<Bytes, Node>,
+    edges: usize,
+}
+
+impl Graph {
+    pub fn nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn edges(&self) -> usize {
+        self.edges
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    // Adds a node, or sets properties of one there is. True when it's new.
+    pub fn add_node(&mut self, id: &Bytes, properties: impl IntoIterator<Item = (Bytes, Bytes)>) -> bool {
+        let new = !self.nodes.contains_key(id);
+        self.nodes.entry(id.clone()).or_default().properties.extend(properties);
+        new
+    }
+
+    pub fn node(&self, id: &[u8]) -> Option<&Properties> {
+        self.nodes.get(id).map(|node| &node.properties)
+    }
+
+    // Removes a node with every edge to or from it, returning how many edges went; None when there's no such
+    // node
+    pub fn remove_node(&mut self, id: &[u8]) -> Option<usize> {
+        let node = self.nodes.remove(id)?;
+        let mut removed = 0;
+        for (to, label) in node.out.keys() {
+            // A loop is in the node's own sets, gone with it
+            if let Some(target) = self.nodes.get_mut(to) {
+                target.incoming.remove(&(Bytes::copy_from_slice(id), label.clone()));
+            }
+            removed += 1;
+        }
+        for (from, label) in &node.incoming {
+            if let Some(source) = self.nodes.get_mut(from) {
+                source.out.remove(&(Bytes::copy_from_slice(id), label.clone()));
+                removed += 1;
+            }
+        }
+        self.edges -= removed;
+        Some(removed)
+    }
+
+    // Adds an edge, adding either end that isn't a node yet, or sets properties of one there is. True when it's
+    // new.
+    pub fn add_edge(&mut self, from: &Bytes, to: &Bytes, label: &Bytes, properties: impl IntoIterator<Item = (Bytes, Bytes)>) -> bool {
+        self.nodes.entry(to.clone()).or_default();
+        let source = self.nodes.entry(from.clone()).or_default();
+        let edge = (to.clone(), label.clone());
+        let new = !source.out.contains_key(&edge);
+        source.out.entry(edge).or_default().extend(properties);
+        if new {
+            self.nodes.get_mut(to).expect("added above").incoming.insert((from.clone(), label.clone()));
+            self.edges += 1;
+        }
+        new
+    }
+
+    pub fn edge(&self, from: &[u8], to: &Bytes, label: &Bytes) -> Option<&Properties> {
+        self.nodes.get(from)?.out.get(&(to.clone(), label.clone()))
+    }
+
+    // True when there was such an edge
+    pub fn remove_edge(&mut self, from: &Bytes, to: &Bytes, label: &Bytes) -> bool {
+        let Some(source) = self.nodes.get_mut(from) else {
+            return false;
+        };
+        if source.out.remove(&(to.clone(), label.clone())).is_none() {
+            return false;
+        }
+        self.nodes.get_mut(to).expect("an edge's ends are nodes").incoming.remove(&(from.clone(), label.clone()));
+        self.edges -= 1;
+        true
+    }
+
+    // The nodes an edge joins to this one, following edges `direction` and with `label` if one is given, in id
+    // order; None when there's no such node
+    pub fn neighbors(&self, id: &[u8], direction: Direction, label: Option<&[u8]>) -> Option<Vec<&Bytes>> {
+        let node = self.nodes.get(id)?;
+        let wanted = |edge_label: &Bytes| label.is_none_or(|label| edge_label[..] == *label);
+        let mut neighbors = BTreeSet::new();
+        if direction != Direction::In {
+            neighbors.extend(node.out.keys().filter(|(_, label)| wanted(label)).map(|(to, _)| to));
+        }
+        if direction != Direction::Out {
+            neighbors.extend(node.incoming.iter().filter(|(_, label)| wanted(label)).map(|(from, _)| from));
+        }
+        Some(neighbors.into_iter().collect())
+    }
+
+    // The nodes up to `max_depth` edges from `start`, as neighbors follows them, each with its depth, nearest
+    // first and in id order at each depth, up to `limit` of them. The start isn't among them. None when there's
+    // no such node. `check` is called every WALK_CHECK_EVERY nodes visited, giving up with its error.
+    pub fn traverse<E>(&self, start: &[u8], direction: Direction, label: Option<&[u8]>, max_depth: usize, limit: usize, mut check: impl FnMut() -> Result<(), E>) -> Result<Option<Vec<(Bytes, usize)>>, E> {
+        let Some((start, _)) = self.nodes.get_key_value(start) else {
+            return Ok(None);
+        };
+        let mut depths = HashMap::from([(start, 0)]);
+        let mut queue = VecDeque::from([start]);
+        let mut found = vec![];
+        let mut visited = 0;
+        while let Some(id) = queue.pop_front() {
+            visited += 1;
+            if visited % WALK_CHECK_EVERY == 0 {
+                check()?;
+            }
+            let depth = depths[id];
+            if depth == max_depth {
+                continue;
+            }
+            for neighbor in self.neighbors(id, direction, label).expect("queued nodes exist") {
+                if found.len() == limit {
+                    return Ok(Some(found));
+                }
+                if depths.contains_key(neighbor) {
+                    continue;
+                }
+                depths.insert(neighbor, depth + 1);
+                found.push((neighbor.clone(), depth + 1));
+                queue.push_back(neighbor);
+            }
+        }
+        Ok(Some(found))
+    }
+
+    // The graph as stored in a record: the node count u64, then each node's id, its properties and the edges
+    // leaving it, in id order. Properties are a count u64 then each name and value; edges a count u64 then each
+    // one's target, label and properties. Ids, names, values and labels are length u64 | bytes, all
+    // little-endian.
+    pub fn encode(&self) -> Vec<u8> {
+        fn bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+            out.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            out.extend_from_slice(bytes);
+        }
+        fn properties(out: &mut Vec<u8>, properties: &Properties) {
+            out.extend_from_slice(&(properties.len() as u64).to_le_bytes());
+            for (name, value) in properties {
+                bytes(out, name);
+                bytes(out, value);
+            }
+        }
+        let mut out = vec![];
+        out.extend_from_slice(&(self.nodes.len() as u64).to_le_bytes());
+        for (id, node) in &self.nodes {
+            bytes(&mut out, id);
+            properties(&mut out, &node.properties);
+            out.extend_from_slice(&(node.out.len() as u64).to_le_bytes());
+            for ((to, label), edge) in &node.out {
+                bytes(&mut out, to);
+                bytes(&mut out, label);
+                properties(&mut out, edge);
+            }
+        }
+        out
+    }
+
+    pub fn decode(payload: &Bytes) -> Option<Graph> {
+        fn properties(reader: &mut PayloadReader) -> Option<Properties> {
+            let mut properties = Properties::new();
+            for _ in 0..reader.u64()? {
+                properties.insert(reader.bytes()?, reader.bytes()?);
+            }
+            Some(properties)
+        }
+        let mut reader = PayloadReader::new(payload);
+        let mut graph = Graph::default();
+        let mut edges = vec![];
+        for _ in 0..reader.u64()? {
+            let id = reader.bytes()?;
+            let node = Node { properties: properties(&mut reader)?, ..Node::default() };
+            for _ in 0..reader.u64()? {
+                edges.push((id.clone(), reader.bytes()?, reader.bytes()?, properties(&mut reader)?));
+            }
+            graph.nodes.insert(id, node);
+        }
+        for (from, to, label, properties) in edges {
+            if !graph.nodes.contains_key(&to) || !graph.add_edge(&from, &to, &label, properties) {
+                return None;
+            }
+        }
+        reader.is_empty().then_some(graph)
+    }
+}
@@ -0,0 +1,819 @@
+// gRPC service: the Zenql service of proto/zenql.proto, for microservices that standardize on gRPC and
+// generated clients. It listens on grpc-port of every bind address and speaks plaintext HTTP/2 with prior
+// knowledge (h2c), the way gRPC clients connect without TLS. HTTP/2 is implemented here as far as gRPC needs
+// it, with header compression in hpack.rs and the messages' wire format in protobuf.rs.
+//
+// Get, Set, Del, Expire and Scan run as GET, SET, DEL, PEXPIRE or PERSIST, and SCAN, each in a session of
+// its own as the HTTP gateway's requests are, and are answered once their writes are durable. Watch follows
+// the keyspace events and streams one whenever a watched key changes, until the client cancels. With
+// requirepass set, calls carry the password in `authorization` metadata as they would over HTTP; without it,
+// protected mode turns other hosts away. Requests are read whole before they run, which is all unary and
+// server streaming calls, the only kinds the service has, need.
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use anyhow::Result;
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::Notify;
+use tokio::task::{AbortHandle, JoinSet};
+use crate::connection::PROTECTED_MODE;
+use crate::error::Error;
+use crate::events::KeyEventKind;
+use crate::hpack::{self, Header};
+use crate::http;
+use crate::locks;
+use crate::log::log_warn;
+use crate::protobuf::{Field, Reader, Writer};
+use crate::resp::Value;
+use crate::server::Server;
+use crate::storage::ReadError;
+
+// What a client sends before its first frame
+const PREFACE: &[u8; 24] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// Frame types
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+// Frame flags
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+// Settings
+const ENABLE_PUSH: u16 = 0x2;
+const MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const INITIAL_WINDOW_SIZE: u16 = 0x4;
+const MAX_FRAME_SIZE: u16 = 0x5;
+const MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+// HTTP/2 error codes
+const NO_ERROR: u32 = 0x0;
+const PROTOCOL_ERROR: u32 = 0x1;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const REFUSED_STREAM: u32 = 0x7;
+const COMPRESSION_ERROR: u32 = 0x9;
+
+// gRPC status codes
+const OK: u32 = 0;
+const INVALID_ARGUMENT: u32 = 3;
+const PERMISSION_DENIED: u32 = 7;
+const RESOURCE_EXHAUSTED: u32 = 8;
+const FAILED_PRECONDITION: u32 = 9;
+const UNIMPLEMENTED: u32 = 12;
+const INTERNAL: u32 = 13;
+const UNAVAILABLE: u32 = 14;
+const UNAUTHENTICATED: u32 = 16;
+
+// Largest frame accepted, HTTP/2's default; we never raise it
+const MAX_FRAME: usize = 16384;
+// Calls a client may have open at once
+const MAX_STREAMS: usize = 100;
+// Largest header block accepted, as for HTTP/1.1 requests
+const MAX_HEADER_BLOCK: usize = 64 * 1024;
+// Flow control windows start at this, and can't grow past MAX_WINDOW
+const DEFAULT_WINDOW: i64 = 65535;
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+
+const SERVICE: &str = "/zenql.v1.Zenql/";
+
+pub async fn serve(server: Server, port: u16) -> Result<()> {
+    http::listen(&server.clone(), port, "gRPC", move |stream, peer, loopback| handle(stream, peer, loopback, server.clone())).await
+}
+
+// A reason to end the connection: a GOAWAY to send with its error code, or the connection failing under us
+enum ConnectionError {
+    GoAway(u32, &'static str),
+    Io(io::Error),
+}
+
+impl From<io::Error> for ConnectionError {
+    fn from(e: io::Error) -> Self {
+        ConnectionError::Io(e)
+    }
+}
+
+fn protocol_error(message: &'static str) -> ConnectionError {
+    ConnectionError::GoAway(PROTOCOL_ERROR, message)
+}
+
+// Serves one connection until the client closes it, says GOAWAY or breaks the protocol. Its calls are tasks
+// of this one's, so they're cancelled with it.
+async fn handle(stream: TcpStream, peer: String, loopback: bool, server: Server) -> Result<()> {
+    let (read, write) = stream.into_split();
+    let mut read = BufReader::new(read);
+    let mut preface = [0; PREFACE.len()];
+    let timeout = match server.config().timeout {
+        0 => Duration::MAX,
+        secs => Duration::from_secs(secs),
+    };
+    match tokio::time::timeout(timeout, read.read_exact(&mut preface)).await {
+        Ok(Ok(_)) if preface == *PREFACE => {},
+        Ok(Ok(_)) => anyhow::bail!("not HTTP/2 with prior knowledge"),
+        Ok(Err(e)) => return Err(e.into()),
+        Err(_) => anyhow::bail!("no connection preface within {}s", timeout.as_secs()),
+    }
+    let shared = Arc::new(Shared {
+        writer: tokio::sync::Mutex::new(write),
+        flow: Mutex::new(Flow { connection: DEFAULT_WINDOW, streams: HashMap::new(), initial: DEFAULT_WINDOW, max_frame: MAX_FRAME }),
+        flow_changed: Notify::new(),
+    });
+    let settings = [(MAX_CONCURRENT_STREAMS, MAX_STREAMS as u32), (MAX_HEADER_LIST_SIZE, MAX_HEADER_BLOCK as u32)];
+    let settings: Vec<u8> = settings.iter().flat_map(|&(id, value)| [&id.to_be_bytes()[..], &value.to_be_bytes()].concat()).collect();
+    shared.send(SETTINGS, 0, 0, &settings).await?;
+
+    let mut connection = Connection {
+        shared: Arc::clone(&shared),
+        server,
+        peer,
+        loopback,
+        decoder: hpack::Decoder::default(),
+        header_block: None,
+        requests: HashMap::new(),
+        calls: HashMap::new(),
+        tasks: JoinSet::new(),
+        last_stream: 0,
+        closing: false,
+    };
+    match connection.run(&mut read).await {
+        Ok(()) => Ok(()),
+        Err(ConnectionError::GoAway(code, message)) => {
+            let mut payload = connection.last_stream.to_be_bytes().to_vec();
+            payload.extend_from_slice(&code.to_be_bytes());
+            payload.extend_from_slice(message.as_bytes());
+            shared.send(GOAWAY, 0, 0, &payload).await?;
+            anyhow::bail!("{}", message)
+        },
+        Err(ConnectionError::Io(e)) => Err(e.into()),
+    }
+}
+
+// What the connection's tasks share: the write half, each frame or run of frames going out whole under its
+// lock, and the send windows that calls wait on
+struct Shared {
+    writer: tokio::sync::Mutex<OwnedWriteHalf>,
+    flow: Mutex<Flow>,
+    flow_changed: Notify,
+}
+
+// Send flow control: how much more the client will take on the connection and on each open stream
+struct Flow {
+    connection: i64,
+    streams: HashMap<u32, i64>,
+    initial: i64, // The client's INITIAL_WINDOW_SIZE, new streams' window
+    max_frame: usize, // The client's MAX_FRAME_SIZE
+}
+
+impl Shared {
+    async fn write(&self, frames: &[u8]) -> io::Result<()> {
+        self.writer.lock().await.write_all(frames).await
+    }
+
+    async fn send(&self, kind: u8, flags: u8, stream: u32, payload: &[u8]) -> io::Result<()> {
+        self.write(&frame(kind, flags, stream, payload)).await
+    }
+
+    // Sends `data` on the stream in DATA frames as the windows allow, false if the stream was reset before
+    // it all went
+    async fn send_data(&self, stream: u32, mut data: &[u8]) -> io::Result<bool> {
+        while !data.is_empty() {
+            let Some(n) = self.reserve(stream, data.len()).await else {
+                return Ok(false);
+            };
+            self.send(DATA, 0, stream, &data[..n]).await?;
+            data = &data[n..];
+        }
+        Ok(true)
+    }
+
+    // Takes up to `wanted` bytes, and at most a frame's worth, of the stream's and the connection's windows,
+    // waiting until both are open; None once the stream is gone
+    async fn reserve(&self, stream: u32, wanted: usize) -> Option<usize> {
+        loop {
+            // Created before the check, so a change made after it isn't missed
+            let changed = self.flow_changed.notified();
+            {
+                let mut flow = locks::lock(&self.flow);
+                let max_frame = flow.max_frame;
+                let connection = flow.connection;
+                let window = flow.streams.get_mut(&stream)?;
+                let n = wanted.min(max_frame).min(connection.min(*window).max(0) as usize);
+                if n > 0 {
+                    *window -= n as i64;
+                    flow.connection -= n as i64;
+                    return Some(n);
+                }
+            }
+            changed.await;
+        }
+    }
+
+    fn open_stream(&self, stream: u32) {
+        let mut flow = locks::lock(&self.flow);
+        let initial = flow.initial;
+        flow.streams.insert(stream, initial);
+    }
+
+    // Wakes any call waiting on the stream's window, to find it gone
+    fn close_stream(&self, stream: u32) {
+        locks::lock(&self.flow).streams.remove(&stream);
+        self.flow_changed.notify_waiters();
+    }
+}
+
+fn frame(kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(9 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    frame.push(kind);
+    frame.push(flags);
+    frame.extend_from_slice(&stream.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+// A call's request as it arrives: its headers, then its body
+struct Request {
+    headers: Vec<Header>,
+    body: BytesMut,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header, _)| header == name).map(|(_, value)| value.as_str())
+    }
+}
+
+// A header block still waiting for its CONTINUATION frames
+struct HeaderBlock {
+    stream: u32,
+    end_stream: bool,
+    block: Vec<u8>,
+}
+
+// The reading side of a connection, which tracks its streams and starts a task per call
+struct Connection {
+    shared: Arc<Shared>,
+    server: Server,
+    peer: String,
+    loopback: bool,
+    decoder: hpack::Decoder,
+    header_block: Option<HeaderBlock>,
+    requests: HashMap<u32, Request>, // Calls still being received
+    calls: HashMap<u32, AbortHandle>, // Calls running, to cancel on RST_STREAM
+    tasks: JoinSet<()>,
+    last_stream: u32, // Highest stream the client opened
+    closing: bool, // The client said GOAWAY
+}
+
+impl Connection {
+    async fn run(&mut self, read: &mut BufReader<OwnedReadHalf>) -> Result<(), ConnectionError> {
+        while !self.closing {
+            let mut head = [0; 9];
+            match read.read_exact(&mut head).await {
+                Ok(_) => {},
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+            let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+            if len > MAX_FRAME {
+                return Err(ConnectionError::GoAway(FRAME_SIZE_ERROR, "frame larger than MAX_FRAME_SIZE"));
+            }
+            let mut payload = vec![0; len];
+            read.read_exact(&mut payload).await?;
+            let stream = u32::from_be_bytes([head[5], head[6], head[7], head[8]]) & 0x7fff_ffff;
+            while self.tasks.try_join_next().is_some() {}
+            self.calls.retain(|_, call| !call.is_finished());
+            self.frame(head[3], head[4], stream, Bytes::from(payload)).await?;
+        }
+        Ok(())
+    }
+
+    async fn frame(&mut self, kind: u8, flags: u8, stream: u32, mut payload: Bytes) -> Result<(), ConnectionError> {
+        // A header block's frames come one straight after the other
+        if self.header_block.as_ref().is_some_and(|block| kind != CONTINUATION || block.stream != stream) {
+            return Err(protocol_error("expected CONTINUATION"));
+        }
+        match kind {
+            DATA => self.data(flags, stream, payload).await,
+            HEADERS => {
+                if stream == 0 {
+                    return Err(protocol_error("HEADERS on stream 0"));
+                }
+                if flags & PADDED != 0 {
+                    strip_padding(&mut payload)?;
+                }
+                if flags & PRIORITY_FLAG != 0 {
+                    if payload.len() < 5 {
+                        return Err(ConnectionError::GoAway(FRAME_SIZE_ERROR, "HEADERS too short for its priority"));
+                    }
+                    payload.advance(5);
+                }
+                self.header_block = Some(HeaderBlock { stream, end_stream: flags & END_STREAM != 0, block: payload.to_vec() });
+                self.continue_headers(flags).await
+            },
+            CONTINUATION => {
+                let Some(header_block) = &mut self.header_block else {
+                    return Err(protocol_error("CONTINUATION without HEADERS"));
+                };
+                header_block.block.extend_from_slice(&payload);
+                self.continue_headers(flags).await
+            },
+            PRIORITY => match stream {
+                0 => Err(protocol_error("PRIORITY on stream 0")),
+                _ => Ok(()),
+            },
+            RST_STREAM => {
+                if stream == 0 || payload.len() != 4 {
+                    return Err(protocol_error("malformed RST_STREAM"));
+                }
+                self.reset(stream);
+                Ok(())
+            },
+            SETTINGS => self.settings(flags, stream, &payload).await,
+            PUSH_PROMISE => Err(protocol_error("clients can't push")),
+            PING => {
+                if stream != 0 || payload.len() != 8 {
+                    return Err(ConnectionError::GoAway(FRAME_SIZE_ERROR, "malformed PING"));
+                }
+                if flags & ACK == 0 {
+                    self.shared.send(PING, ACK, 0, &payload).await?;
+                }
+                Ok(())
+            },
+            GOAWAY => {
+                self.closing = true;
+                Ok(())
+            },
+            WINDOW_UPDATE => self.window_update(stream, &payload).await,
+            // Extensions' frames we don't know are ignored
+            _ => Ok(()),
+        }
+    }
+
+    async fn continue_headers(&mut self, flags: u8) -> Result<(), ConnectionError> {
+        let Some(header_block) = self.header_block.take_if(|block| flags & END_HEADERS != 0 || block.block.len() > MAX_HEADER_BLOCK) else {
+            return Ok(());
+        };
+        if header_block.block.len() > MAX_HEADER_BLOCK {
+            return Err(protocol_error("header block larger than MAX_HEADER_LIST_SIZE"));
+        }
+        // Decoded whatever becomes of the stream, as it may have changed the table
+        let headers = self.decoder.decode(&header_block.block).map_err(|_| ConnectionError::GoAway(COMPRESSION_ERROR, "invalid header block"))?;
+        let stream = header_block.stream;
+        if self.requests.contains_key(&stream) {
+            // Trailers, which end the request and say nothing a call needs
+            if !header_block.end_stream {
+                return Err(protocol_error("trailers must end the stream"));
+            }
+            self.start(stream);
+            return Ok(());
+        }
+        if stream.is_multiple_of(2) || stream <= self.last_stream {
+            return Err(protocol_error("HEADERS on a stream that isn't new"));
+        }
+        self.last_stream = stream;
+        if self.requests.len() + self.calls.len() >= MAX_STREAMS {
+            self.shared.send(RST_STREAM, 0, stream, &REFUSED_STREAM.to_be_bytes()).await?;
+            return Ok(());
+        }
+        self.requests.insert(stream, Request { headers, body: BytesMut::new() });
+        self.shared.open_stream(stream);
+        if header_block.end_stream {
+            self.start(stream);
+        }
+        Ok(())
+    }
+
+    async fn data(&mut self, flags: u8, stream: u32, mut payload: Bytes) -> Result<(), ConnectionError> {
+        if stream == 0 {
+            return Err(protocol_error("DATA on stream 0"));
+        }
+        if stream > self.last_stream {
+            return Err(protocol_error("DATA on a stream that isn't open"));
+        }
+        // The whole frame counts against the windows, padding included. They're opened again at once: what a
+        // request may send is limited by client-query-buffer-limit instead.
+        let len = payload.len() as u32;
+        if flags & PADDED != 0 {
+            strip_padding(&mut payload)?;
+        }
+        let mut updates = vec![];
+        if len > 0 {
+            updates.extend(frame(WINDOW_UPDATE, 0, 0, &len.to_be_bytes()));
+        }
+        // Otherwise a stream we reset, or already have the whole request of
+        let Some(request) = self.requests.get_mut(&stream) else {
+            return Ok(self.shared.write(&updates).await?);
+        };
+        request.body.extend_from_slice(&payload);
+        if request.body.len() > self.server.config().client_query_buffer_limit {
+            self.requests.remove(&stream);
+            self.shared.close_stream(stream);
+            let status = Status::new(RESOURCE_EXHAUSTED, "request larger than client-query-buffer-limit");
+            updates.extend(frame(HEADERS, END_STREAM | END_HEADERS, stream, &trailers(true, &status)));
+            // The client may still be sending, and needn't
+            updates.extend(frame(RST_STREAM, 0, stream, &NO_ERROR.to_be_bytes()));
+            return Ok(self.shared.write(&updates).await?);
+        }
+        if flags & END_STREAM != 0 {
+            self.start(stream);
+        } else if len > 0 {
+            updates.extend(frame(WINDOW_UPDATE, 0, stream, &len.to_be_bytes()));
+        }
+        Ok(self.shared.write(&updates).await?)
+    }
+
+    async fn settings(&mut self, flags: u8, stream: u32, payload: &[u8]) -> Result<(), ConnectionError> {
+        if stream != 0 {
+            return Err(protocol_error("SETTINGS on a stream"));
+        }
+        if flags & ACK != 0 {
+            return match payload.is_empty() {
+                true => Ok(()),
+                false => Err(ConnectionError::GoAway(FRAME_SIZE_ERROR, "SETTINGS ACK with a payload")),
+            };
+        }
+        if !payload.len().is_multiple_of(6) {
+            return Err(ConnectionError::GoAway(FRAME_SIZE_ERROR, "malformed SETTINGS"));
+        }
+        {
+            let mut flow = locks::lock(&self.shared.flow);
+            for setting in payload.chunks(6) {
+                let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+                match u16::from_be_bytes([setting[0], setting[1]]) {
+                    ENABLE_PUSH if value > 1 => return Err(protocol_error("invalid ENABLE_PUSH")),
+                    INITIAL_WINDOW_SIZE => {
+                        // Applies to the open streams' windows too, as a change to them
+                        let delta = value as i64 - flow.initial;
+                        if value as i64 > MAX_WINDOW || flow.streams.values().any(|&window| window + delta > MAX_WINDOW) {
+                            return Err(ConnectionError::GoAway(FLOW_CONTROL_ERROR, "INITIAL_WINDOW_SIZE too large"));
+                        }
+                        flow.initial = value as i64;
+                        flow.streams.values_mut().for_each(|window| *window += delta);
+                    },
+                    MAX_FRAME_SIZE => match value {
+                        16384..=16777215 => flow.max_frame = value as usize,
+                        _ => return Err(protocol_error("invalid MAX_FRAME_SIZE")),
+                    },
+                    _ => {},
+                }
+            }
+        }
+        self.shared.flow_changed.notify_waiters();
+        Ok(self.shared.send(SETTINGS, ACK, 0, &[]).await?)
+    }
+
+    async fn window_update(&mut self, stream: u32, payload: &[u8]) -> Result<(), ConnectionError> {
+        let Ok(increment) = <[u8; 4]>::try_from(payload) else {
+            return Err(ConnectionError::GoAway(FRAME_SIZE_ERROR, "malformed WINDOW_UPDATE"));
+        };
+        let increment = (u32::from_be_bytes(increment) & 0x7fff_ffff) as i64;
+        let overflowed = {
+            let mut flow = locks::lock(&self.shared.flow);
+            let window = match stream {
+                0 => Some(&mut flow.connection),
+                stream => flow.streams.get_mut(&stream),
+            };
+            window.is_some_and(|window| {
+                *window += increment;
+                *window > MAX_WINDOW
+            })
+        };
+        match (stream, increment == 0 || overflowed) {
+            (_, false) => self.shared.flow_changed.notify_waiters(),
+            (0, true) => return Err(ConnectionError::GoAway(FLOW_CONTROL_ERROR, "invalid connection WINDOW_UPDATE")),
+            (stream, true) => {
+                self.reset(stream);
+                let code = if increment == 0 { PROTOCOL_ERROR } else { FLOW_CONTROL_ERROR };
+                self.shared.send(RST_STREAM, 0, stream, &code.to_be_bytes()).await?;
+            },
+        }
+        Ok(())
+    }
+
+    // Runs a call whose request is all here
+    fn start(&mut self, stream: u32) {
+        let Some(request) = self.requests.remove(&stream) else {
+            return;
+        };
+        let mut response = Response { shared: Arc::clone(&self.shared), stream, started: false };
+        let (server, peer, loopback) = (self.server.clone(), self.peer.clone(), self.loopback);
+        let call = self.tasks.spawn(async move {
+            let status = call(&mut response, &server, &peer, loopback, request).await;
+            // A failure to write is the connection's, which its reader will see
+            let _ = response.finish(status).await;
+            response.shared.close_stream(stream);
+        });
+        self.calls.insert(stream, call);
+    }
+
+    // Cancels the stream's call, or forgets its request
+    fn reset(&mut self, stream: u32) {
+        self.requests.remove(&stream);
+        if let Some(call) = self.calls.remove(&stream) {
+            call.abort();
+        }
+        self.shared.close_stream(stream);
+    }
+}
+
+fn strip_padding(payload: &mut Bytes) -> Result<(), ConnectionError> {
+    match payload.first() {
+        Some(&padding) if (padding as usize) < payload.len() => {
+            payload.truncate(payload.len() - padding as usize);
+            payload.advance(1);
+            Ok(())
+        },
+        _ => Err(protocol_error("padding longer than the frame")),
+    }
+}
+
+// How a call ended, for its grpc-status and grpc-message trailers
+struct Status {
+    code: u32,
+    message: String,
+}
+
+impl Status {
+    fn new(code: u32, message: impl Into<String>) -> Status {
+        Status { code, message: message.into() }
+    }
+}
+
+// The status trailers, and before them the response headers when nothing else was sent
+fn trailers(trailers_only: bool, status: &Status) -> Vec<u8> {
+    let code = status.code.to_string();
+    let message = percent_encode(&status.message);
+    let mut headers = vec![];
+    if trailers_only {
+        headers.extend([(":status", "200"), ("content-type", "application/grpc")]);
+    }
+    headers.push(("grpc-status", &code));
+    if !message.is_empty() {
+        headers.push(("grpc-message", &message));
+    }
+    hpack::encode(&headers)
+}
+
+// grpc-message is percent-encoded, all but printable ASCII
+fn percent_encode(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b' '..=b'~' if byte != b'%' => out.push(byte as char),
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+// The response side of a call's stream
+struct Response {
+    shared: Arc<Shared>,
+    stream: u32,
+    started: bool, // Whether the headers went
+}
+
+impl Response {
+    async fn start(&mut self) -> Result<(), Status> {
+        if !self.started {
+            let headers = hpack::encode(&[(":status", "200"), ("content-type", "application/grpc")]);
+            self.shared.send(HEADERS, END_HEADERS, self.stream, &headers).await.map_err(|e| Status::new(UNAVAILABLE, e.to_string()))?;
+            self.started = true;
+        }
+        Ok(())
+    }
+
+    // Sends a message, length-prefixed and uncompressed
+    async fn message(&mut self, message: &[u8]) -> Result<(), Status> {
+        self.start().await?;
+        let mut data = Vec::with_capacity(5 + message.len());
+        data.push(0);
+        data.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        data.extend_from_slice(message);
+        match self.shared.send_data(self.stream, &data).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Status::new(UNAVAILABLE, "stream reset")),
+            Err(e) => Err(Status::new(UNAVAILABLE, e.to_string())),
+        }
+    }
+
+    async fn finish(&mut self, status: Result<(), Status>) -> io::Result<()> {
+        let status = status.err().unwrap_or(Status::new(OK, ""));
+        let trailers = trailers(!self.started, &status);
+        self.shared.send(HEADERS, END_STREAM | END_HEADERS, self.stream, &trailers).await
+    }
+}
+
+async fn call(response: &mut Response, server: &Server, peer: &str, loopback: bool, request: Request) -> Result<(), Status> {
+    if request.header(":method") != Some("POST") {
+        return Err(Status::new(UNIMPLEMENTED, "gRPC calls are POSTs"));
+    }
+    if !request.header("content-type").is_some_and(|content_type| content_type.starts_with("application/grpc")) {
+        return Err(Status::new(INVALID_ARGUMENT, "content-type must be application/grpc"));
+    }
+    let config = server.config();
+    if config.protected() && !loopback {
+        log_warn!("Refused gRPC call from {}: protected mode is on and no password is set", peer);
+        return Err(Status::new(PERMISSION_DENIED, PROTECTED_MODE));
+    }
+    if let Some(password) = &config.requirepass {
+        if !http::authorized(request.header("authorization"), password) {
+            return Err(Status::new(UNAUTHENTICATED, "authentication required"));
+        }
+    }
+    let message = unframe(&request.body)?;
+    let fields = Fields::parse(message)?;
+    let path = request.header(":path").unwrap_or_default();
+    let text = |text: &str| Bytes::copy_from_slice(text.as_bytes());
+    let reply = match path.strip_prefix(SERVICE).unwrap_or_default() {
+        "Get" => match command(server, peer, vec![text("GET"), fields.bytes(1)?]).await? {
+            Value::BulkString(value) => Writer::default().bool(1, true).bytes(2, &value).finish(),
+            _ => vec![],
+        },
+        "Set" => {
+            let mut args = vec![text("SET"), fields.bytes(1)?, fields.bytes(2)?];
+            match fields.varint(3)? {
+                0 => {},
+                ttl => args.extend([text("PX"), text(&ttl.to_string())]),
+            }
+            command(server, peer, args).await?;
+            vec![]
+        },
+        "Del" => {
+            let keys = fields.repeated_bytes(1)?;
+            if keys.is_empty() {
+                return Err(Status::new(INVALID_ARGUMENT, "no keys given"));
+            }
+            let deleted = command(server, peer, [text("DEL")].into_iter().chain(keys).collect()).await?;
+            Writer::default().varint(1, integer(&deleted)).finish()
+        },
+        "Expire" => {
+            let args = match fields.varint(2)? {
+                0 => vec![text("PERSIST"), fields.bytes(1)?],
+                ttl => vec![text("PEXPIRE"), fields.bytes(1)?, text(&ttl.to_string())],
+            };
+            let updated = command(server, peer, args).await?;
+            Writer::default().bool(1, integer(&updated) == 1).finish()
+        },
+        "Scan" => {
+            let mut args = vec![text("SCAN"), text(&fields.varint(1)?.to_string())];
+            let pattern = fields.string(2)?;
+            if !pattern.is_empty() {
+                args.extend([text("MATCH"), text(pattern)]);
+            }
+            match fields.varint(3)? {
+                0 => {},
+                count => args.extend([text("COUNT"), text(&count.to_string())]),
+            }
+            let Value::Array(reply) = command(server, peer, args).await? else {
+                return Err(Status::new(INTERNAL, "unexpected SCAN reply"));
+            };
+            let mut writer = Writer::default();
+            if let [Value::BulkString(cursor), Value::Array(keys)] = &reply[..] {
+                writer.varint(1, std::str::from_utf8(cursor).ok().and_then(|cursor| cursor.parse().ok()).unwrap_or(0));
+                for key in keys {
+                    if let Value::BulkString(key) = key {
+                        writer.repeated_bytes(2, key);
+                    }
+                }
+            }
+            writer.finish()
+        },
+        "Watch" => return watch(response, server, fields.repeated_bytes(1)?).await,
+        _ => return Err(Status::new(UNIMPLEMENTED, format!("unknown method {}", path))),
+    };
+    response.message(&reply).await
+}
+
+// The request's one message, from its length prefix
+fn unframe(body: &[u8]) -> Result<&[u8], Status> {
+    let Some((prefix, message)) = body.split_first_chunk::<5>() else {
+        return Err(Status::new(INTERNAL, "a request is one length-prefixed message"));
+    };
+    if prefix[0] != 0 {
+        return Err(Status::new(UNIMPLEMENTED, "compressed messages aren't supported"));
+    }
+    match u32::from_be_bytes([prefix[1], prefix[2], prefix[3], prefix[4]]) as usize == message.len() {
+        true => Ok(message),
+        false => Err(Status::new(INTERNAL, "a request is one length-prefixed message")),
+    }
+}
+
+// Runs a command as the HTTP gateway does, its error replies as statuses
+async fn command(server: &Server, peer: &str, args: Vec<Bytes>) -> Result<Value, Status> {
+    let replies = http::run(server, peer, args).await.ok_or_else(|| Status::new(UNAVAILABLE, "writes couldn't be made durable"))?;
+    match replies.into_iter().next() {
+        Some(Value::Error(e)) if e.starts_with("WRONGTYPE") => Err(Status::new(FAILED_PRECONDITION, e)),
+        Some(Value::Error(e)) => Err(Status::new(INVALID_ARGUMENT, e)),
+        Some(reply) => Ok(reply),
+        None => Err(Status::new(INTERNAL, "no reply")),
+    }
+}
+
+fn integer(reply: &Value) -> u64 {
+    match reply {
+        Value::Integer(n) => *n as u64,
+        _ => 0,
+    }
+}
+
+// Streams an event each time a key is written or deleted, until the call is cancelled. A key that holds
+// anything but a string, to start with or once written, ends the call with FAILED_PRECONDITION.
+async fn watch(response: &mut Response, server: &Server, keys: Vec<Bytes>) -> Result<(), Status> {
+    if keys.is_empty() {
+        return Err(Status::new(INVALID_ARGUMENT, "no keys given"));
+    }
+    let storage = &server.storage;
+    // Subscribed before anything is checked or sent, so no change after the headers goes unseen
+    let mut events = storage.subscribe();
+    let failed = |e: ReadError| match e {
+        ReadError::WrongType => Status::new(FAILED_PRECONDITION, Error::from(e).to_string()),
+        ReadError::Unreadable => Status::new(INTERNAL, Error::from(e).to_string()),
+    };
+    for key in &keys {
+        storage.get(key).map_err(failed)?;
+    }
+    // Headers go out at once, so the client knows the call is live before anything changes
+    response.start().await?;
+    while let Some(event) = events.recv().await {
+        if !keys.contains(&event.key) {
+            continue;
+        }
+        let value = match event.kind {
+            KeyEventKind::Set => match storage.get(&event.key).map_err(failed)? {
+                Some(value) => Some(value),
+                None => continue, // Deleted since, which has its own event
+            },
+            KeyEventKind::Expire => continue, // The value is as it was
+            KeyEventKind::Del | KeyEventKind::Expired | KeyEventKind::Evicted => None,
+        };
+        let event = Writer::default().bytes(1, &event.key).bool(2, value.is_none()).bytes(3, value.as_deref().unwrap_or_default()).finish();
+        response.message(&event).await?;
+    }
+    Ok(())
+}
+
+// A request message's fields by number. A scalar given more than once takes the last value, as in proto3.
+struct Fields<'a>(Vec<(u32, Field<'a>)>);
+
+impl<'a> Fields<'a> {
+    fn parse(message: &'a [u8]) -> Result<Fields<'a>, Status> {
+        let invalid = |_| Status::new(INVALID_ARGUMENT, "malformed request message");
+        let mut reader = Reader::new(message);
+        let mut fields = vec![];
+        while let Some(field) = reader.next_field().map_err(invalid)? {
+            fields.push(field);
+        }
+        Ok(Fields(fields))
+    }
+
+    fn last(&self, number: u32) -> Option<Field<'a>> {
+        self.0.iter().rev().find(|(field, _)| *field == number).map(|&(_, value)| value)
+    }
+
+    fn invalid(number: u32) -> Status {
+        Status::new(INVALID_ARGUMENT, format!("field {} has the wrong wire type", number))
+    }
+
+    fn bytes(&self, number: u32) -> Result<Bytes, Status> {
+        match self.last(number) {
+            Some(field) => field.bytes().map(Bytes::copy_from_slice).map_err(|_| Self::invalid(number)),
+            None => Ok(Bytes::new()),
+        }
+    }
+
+    fn string(&self, number: u32) -> Result<&'a str, Status> {
+        match self.last(number) {
+            Some(field) => field.string().map_err(|_| Self::invalid(number)),
+            None => Ok(""),
+        }
+    }
+
+    fn varint(&self, number: u32) -> Result<u64, Status> {
+        match self.last(number) {
+            Some(field) => field.varint().map_err(|_| Self::invalid(number)),
+            None => Ok(0),
+        }
+    }
+
+    fn repeated_bytes(&self, number: u32) -> Result<Vec<Bytes>, Status> {
+        let fields = self.0.iter().filter(|(field, _)| *field == number);
+        fields.map(|(_, value)| value.bytes().map(Bytes::copy_from_slice).map_err(|_| Self::invalid(number))).collect()
+    }
+}
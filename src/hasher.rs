@@ -0,0 +1,174 @@
+// The keyspace's hash functions, picked by keyspace-hasher. Fx is FxHash, the multiply-and-rotate hash rustc
+// uses: a few instructions per 8 bytes.
+// On short keys it hashes about twice as fast as SipHash, which the hashing benchmarks in hot_paths.rs see
+// as 10-15% more storage reads and writes per second. Its output goes through the murmur3 finalizer
+// with a random seed, so the shard a key lands in and its bucket within the shard's map are unrelated. What
+// the seed can't do is keep keys from colliding: FxHash keys that collide do so under any seed, and anyone
+// choosing key names can make whole shards collide, degrading lookups to a scan. SipHash (std's default,
+// randomly keyed) is the choice when untrusted clients pick key names.
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::hash::{BuildHasher, Hasher};
+use anyhow::Result;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HasherKind {
+    #[default]
+    Fx,
+    SipHash,
+}
+
+impl HasherKind {
+    pub fn parse(name: &str) -> Result<HasherKind> {
+        match name.to_lowercase().as_str() {
+            "fx" => Ok(HasherKind::Fx),
+            "siphash" => Ok(HasherKind::SipHash),
+            _ => Err(anyhow::anyhow!("Invalid keyspace hasher '{}'", name)),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HasherKind::Fx => "fx",
+            HasherKind::SipHash => "siphash",
+        }
+    }
+}
+
+// Builds hashers of one kind with one random seed; each KeyHasher::new hashes differently
+#[derive(Debug, Clone)]
+pub enum KeyHasher {
+    Fx(u64),
+    SipHash(RandomState),
+}
+
+impl KeyHasher {
+    pub fn new(kind: HasherKind) -> Self {
+        let state = RandomState::new();
+        match kind {
+            HasherKind::Fx => KeyHasher::Fx(state.hash_one(0u64)),
+            HasherKind::SipHash => KeyHasher::SipHash(state),
+        }
+    }
+}
+
+impl Default for KeyHasher {
+    fn default() -> Self {
+        KeyHasher::new(HasherKind::default())
+    }
+}
+
+impl BuildHasher for KeyHasher {
+    type Hasher = AnyHasher;
+
+    fn build_hasher(&self) -> AnyHasher {
+        match self {
+            KeyHasher::Fx(seed) => AnyHasher::Fx(FxHasher { hash: 0, seed: *seed }),
+            KeyHasher::SipHash(state) => AnyHasher::SipHash(state.build_hasher()),
+        }
+    }
+}
+
+pub enum AnyHasher {
+    Fx(FxHasher),
+    SipHash(DefaultHasher),
+}
+
+impl Hasher for AnyHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        match self {
+            AnyHasher::Fx(hasher) => hasher.write(bytes),
+            AnyHasher::SipHash(hasher) => hasher.write(bytes),
+        }
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        match self {
+            AnyHasher::Fx(hasher) => hasher.write_u64(n),
+            AnyHasher::SipHash(hasher) => hasher.write_u64(n),
+        }
+    }
+
+    // Slices hash their length first
+    fn write_usize(&mut self, n: usize) {
+        match self {
+            AnyHasher::Fx(hasher) => hasher.write_usize(n),
+            AnyHasher::SipHash(hasher) => hasher.write_usize(n),
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        match self {
+            AnyHasher::Fx(hasher) => hasher.finish(),
+            AnyHasher::SipHash(hasher) => hasher.finish(),
+        }
+    }
+}
+
+const FX_K: u64 = 0x517c_c1b7_2722_0a95;
+
+pub struct FxHasher {
+    hash: u64,
+    seed: u64,
+}
+
+impl FxHasher {
+    fn add(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(FX_K);
+    }
+}
+
+impl Hasher for FxHasher {
+    // A word at a time, then the tail in halves rather than through a padded copy
+    fn write(&mut self, mut bytes: &[u8]) {
+        while let Some((word, rest)) = bytes.split_first_chunk::<8>() {
+            self.add(u64::from_le_bytes(*word));
+            bytes = rest;
+        }
+        if let Some((word, rest)) = bytes.split_first_chunk::<4>() {
+            self.add(u32::from_le_bytes(*word) as u64);
+            bytes = rest;
+        }
+        if let Some((word, rest)) = bytes.split_first_chunk::<2>() {
+            self.add(u16::from_le_bytes(*word) as u64);
+            bytes = rest;
+        }
+        if let Some(&byte) = bytes.first() {
+            self.add(byte as u64);
+        }
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.add(n);
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.add(n as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        mix(self.hash ^ self.seed)
+    }
+}
+
+// The murmur3 finalizer, which spreads every input bit over the whole word
+fn mix(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+// A hash that comes out the same in every process, for structures that are persisted hashed, like Bloom
+// filters. Unlike FxHash, which lets differences in neighbouring words cancel out, every word is mixed in
+// fully, as similar keys must not share bits; the length goes first so a trailing zero byte still counts.
+pub fn stable_hash(bytes: &[u8], seed: u64) -> u64 {
+    let mut hash = mix(seed ^ bytes.len() as u64);
+    let mut chunks = bytes.chunks_exact(8);
+    for word in &mut chunks {
+        hash = mix(hash ^ u64::from_le_bytes(word.try_into().expect("chunks of 8"))).wrapping_add(FX_K);
+    }
+    let mut tail = [0; 8];
+    tail[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    mix(hash ^ u64::from_le_bytes(tail))
+}
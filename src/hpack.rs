@@ -0,0 +1,321 @@
+// HPACK (RFC 7541), the header compression of HTTP/2, as the gRPC listener needs it. Decoding is complete:
+// the static and dynamic tables, table size updates and Huffman coded strings. Encoding only ever refers to
+// the static table and writes everything else as literals that aren't indexed, which every decoder accepts,
+// so there's no encoder state to keep in step with the peer's.
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+
+// The header table size HTTP/2 starts with, and the most a peer may make us keep as we never raise it
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+// Bits in the Huffman code of each byte and of EOS (256), from Appendix B. The code is canonical, so the
+// lengths are all it takes to rebuild it.
+const HUFFMAN_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28,
+    28, 28, 28, 28, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+    6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6,
+    5, 5, 5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10,
+    13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6,
+    15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6, 6, 5,
+    6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28,
+    20, 22, 20, 20, 22, 22, 22, 23, 22, 23, 23, 23, 23, 23, 24, 23,
+    24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24,
+    22, 21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23,
+    21, 21, 22, 21, 23, 22, 23, 23, 20, 22, 22, 22, 23, 22, 22, 23,
+    26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25,
+    19, 21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27,
+    20, 24, 20, 21, 22, 21, 21, 23, 22, 22, 25, 25, 24, 24, 26, 23,
+    26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26,
+    30,
+];
+
+const EOS: u16 = 256;
+
+// Each entry costs its name and value plus this, for what an implementation keeps alongside
+const ENTRY_OVERHEAD: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{0}")]
+pub struct HpackError(&'static str);
+
+pub type Header = (String, String);
+
+// One connection's decoding state, fed every header block the peer sends in order
+pub struct Decoder {
+    table: VecDeque<Header>, // Newest first
+    size: usize,
+    max_size: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder { table: VecDeque::new(), size: 0, max_size: DEFAULT_TABLE_SIZE }
+    }
+}
+
+impl Decoder {
+    pub fn decode(&mut self, mut block: &[u8]) -> Result<Vec<Header>, HpackError> {
+        let mut headers = vec![];
+        while let Some(&first) = block.first() {
+            if first & 0x80 != 0 {
+                let index = integer(&mut block, 7)?;
+                headers.push(self.entry(index)?.clone());
+            } else if first & 0x40 != 0 {
+                let header = self.literal(&mut block, 6)?;
+                self.insert(header.clone());
+                headers.push(header);
+            } else if first & 0x20 != 0 {
+                // Only allowed before the block's first header
+                if !headers.is_empty() {
+                    return Err(HpackError("table size update after a header"));
+                }
+                let size = integer(&mut block, 5)?;
+                if size > DEFAULT_TABLE_SIZE {
+                    return Err(HpackError("table size update past the limit"));
+                }
+                self.max_size = size;
+                self.evict(0);
+            } else {
+                // Without indexing, or never indexed: the same to a decoder
+                headers.push(self.literal(&mut block, 4)?);
+            }
+        }
+        Ok(headers)
+    }
+
+    // Static entries come first, 1 to 61, then the dynamic ones newest first
+    fn entry(&self, index: usize) -> Result<&Header, HpackError> {
+        static STATIC: OnceLock<Vec<Header>> = OnceLock::new();
+        let statics = STATIC.get_or_init(|| STATIC_TABLE.iter().map(|&(name, value)| (name.to_string(), value.to_string())).collect());
+        match index {
+            0 => Err(HpackError("index 0")),
+            index if index <= statics.len() => Ok(&statics[index - 1]),
+            index => self.table.get(index - statics.len() - 1).ok_or(HpackError("index past the table")),
+        }
+    }
+
+    // A literal whose name is indexed when the `prefix` bit index isn't 0, and a string otherwise
+    fn literal(&self, block: &mut &[u8], prefix: u8) -> Result<Header, HpackError> {
+        let name = match integer(block, prefix)? {
+            0 => string(block)?,
+            index => self.entry(index)?.0.clone(),
+        };
+        Ok((name, string(block)?))
+    }
+
+    fn insert(&mut self, header: Header) {
+        let size = header.0.len() + header.1.len() + ENTRY_OVERHEAD;
+        self.evict(size);
+        // An entry bigger than the table empties it and isn't added
+        if size <= self.max_size {
+            self.size += size;
+            self.table.push_front(header);
+        }
+    }
+
+    // Drops the oldest entries until `room` more fits
+    fn evict(&mut self, room: usize) {
+        while self.size + room > self.max_size {
+            let Some((name, value)) = self.table.pop_back() else {
+                break;
+            };
+            self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+}
+
+// A header block for `headers`, names already lowercase
+pub fn encode(headers: &[(&str, &str)]) -> Vec<u8> {
+    let mut out = vec![];
+    for &(name, value) in headers {
+        if let Some(index) = STATIC_TABLE.iter().position(|&entry| entry == (name, value)) {
+            write_integer(&mut out, 0x80, 7, index + 1);
+            continue;
+        }
+        match STATIC_TABLE.iter().position(|&(entry, _)| entry == name) {
+            Some(index) => write_integer(&mut out, 0, 4, index + 1),
+            None => {
+                out.push(0);
+                write_string(&mut out, name);
+            },
+        }
+        write_string(&mut out, value);
+    }
+    out
+}
+
+// An integer with an N-bit prefix (section 5.1), the prefix's first byte's other bits ignored
+fn integer(block: &mut &[u8], prefix: u8) -> Result<usize, HpackError> {
+    let truncated = HpackError("truncated integer");
+    let (&first, rest) = block.split_first().ok_or(truncated.clone())?;
+    *block = rest;
+    let max = (1usize << prefix) - 1;
+    let mut value = first as usize & max;
+    if value < max {
+        return Ok(value);
+    }
+    for shift in (0..28).step_by(7) {
+        let (&byte, rest) = block.split_first().ok_or(truncated.clone())?;
+        *block = rest;
+        value += ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(HpackError("integer too large"))
+}
+
+fn write_integer(out: &mut Vec<u8>, flags: u8, prefix: u8, mut value: usize) {
+    let max = (1usize << prefix) - 1;
+    if value < max {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | max as u8);
+    value -= max;
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn string(block: &mut &[u8]) -> Result<String, HpackError> {
+    let huffman = block.first().is_some_and(|first| first & 0x80 != 0);
+    let len = integer(block, 7)?;
+    if block.len() < len {
+        return Err(HpackError("truncated string"));
+    }
+    let (data, rest) = block.split_at(len);
+    *block = rest;
+    let bytes = match huffman {
+        true => decode_huffman(data).ok_or(HpackError("invalid Huffman code"))?,
+        false => data.to_vec(),
+    };
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// Never Huffman coded: it would only save a few bytes of the headers we send
+fn write_string(out: &mut Vec<u8>, value: &str) {
+    write_integer(out, 0, 7, value.len());
+    out.extend_from_slice(value.as_bytes());
+}
+
+// The canonical code by length: the first code of each length, how many there are, and where their symbols
+// start in `symbols`, which is in code order
+struct Huffman {
+    first: [u32; 31],
+    count: [u32; 31],
+    offset: [usize; 31],
+    symbols: Vec<u16>,
+}
+
+fn huffman() -> &'static Huffman {
+    static HUFFMAN: OnceLock<Huffman> = OnceLock::new();
+    HUFFMAN.get_or_init(|| {
+        let mut symbols: Vec<u16> = (0..=EOS).collect();
+        symbols.sort_by_key(|&symbol| (HUFFMAN_LENGTHS[symbol as usize], symbol));
+        let mut huffman = Huffman { first: [0; 31], count: [0; 31], offset: [0; 31], symbols };
+        let (mut code, mut offset) = (0, 0);
+        for len in 1..=30 {
+            let count = HUFFMAN_LENGTHS.iter().filter(|&&length| length as usize == len).count();
+            huffman.first[len] = code;
+            huffman.count[len] = count as u32;
+            huffman.offset[len] = offset;
+            code = (code + count as u32) << 1;
+            offset += count;
+        }
+        huffman
+    })
+}
+
+fn decode_huffman(data: &[u8]) -> Option<Vec<u8>> {
+    let huffman = huffman();
+    let mut out = Vec::with_capacity(data.len() * 8 / 5);
+    let (mut code, mut len) = (0u32, 0);
+    for byte in data {
+        for bit in (0..8).rev() {
+            code = code << 1 | (byte >> bit & 1) as u32;
+            len += 1;
+            if len > 30 {
+                return None;
+            }
+            let index = code.wrapping_sub(huffman.first[len]);
+            if code >= huffman.first[len] && index < huffman.count[len] {
+                let symbol = huffman.symbols[huffman.offset[len] + index as usize];
+                if symbol == EOS {
+                    return None;
+                }
+                out.push(symbol as u8);
+                (code, len) = (0, 0);
+            }
+        }
+    }
+    // Padding is the start of EOS, all ones, shorter than a byte
+    (len < 8 && code == (1 << len) - 1).then_some(out)
+}
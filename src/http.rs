@@ -0,0 +1,575 @@
+// HTTP gateway: commands over HTTP/1.1 and JSON, for clients that can't open a raw TCP connection to speak
+// RESP, such as serverless functions and edge runtimes. It listens on http-port of every bind address and has
+// five endpoints:
+//
+//   GET /keys/{key}      GET, 404 when there's no such key
+//   PUT /keys/{key}      SET to the request body
+//   DELETE /keys/{key}   DEL, 404 when there was no such key
+//   POST /command        any command, the body a JSON array of its name and arguments, strings or numbers
+//   GET /ws              upgrades to a WebSocket, see websocket.rs
+//
+// Keys are percent-decoded. With requirepass set, every other request carries the password, either as
+// `Authorization: Bearer <password>` or as Basic credentials for the default user; without it, protected
+// mode turns other hosts away as it does RESP clients. Replies follow Accept: JSON unless asked otherwise,
+// {"result": ...} or {"error": "..."}, with maps as objects when their keys are strings; text/plain and
+// application/octet-stream give a string reply's bytes as they are and arrays an element per line. Command
+// errors are 400s. Each request runs in a session of its own, so MULTI or SUBSCRIBE don't outlast it, and is
+// replied to once its writes are durable, as on RESP connections. Connections are kept alive unless the
+// client asks otherwise; bodies need a Content-Length, chunked ones are refused.
+use std::fmt::Write as _;
+use std::future::Future;
+use std::time::Duration;
+use anyhow::Result;
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use crate::clients::format_addr;
+use crate::codec::format_double;
+use crate::connection::{self, PROTECTED_MODE};
+use crate::json::Json;
+use crate::log::{log_debug, log_info, log_warn};
+use crate::resp::Value;
+use crate::server::{Server, ACCEPT_RETRY_DELAY};
+use crate::session::Session;
+use crate::trace::CommandSpan;
+use crate::websocket;
+
+// Longest request line and headers accepted
+const MAX_HEAD: usize = 64 * 1024;
+
+pub async fn serve(server: Server, port: u16) -> Result<()> {
+    listen(&server.clone(), port, "HTTP", move |stream, peer, loopback| {
+        let server = server.clone();
+        async move { handle(stream, &peer, loopback, &server).await }
+    }).await
+}
+
+// Serves `protocol` on `port` of the bind addresses, running `handle` for each connection with the client's
+// address and whether it's on the loopback interface. The connections are tasks of this one's, so they end
+// with it.
+pub(crate) async fn listen<F, C>(server: &Server, port: u16, protocol: &'static str, handle: F) -> Result<()>
+where
+    F: Fn(TcpStream, String, bool) -> C + Clone + Send + 'static,
+    C: Future<Output = Result<()>> + Send + 'static,
+{
+    let mut acceptors = JoinSet::new();
+    for listener in bind(server, port, protocol).await? {
+        acceptors.spawn(accept(listener, protocol, handle.clone()));
+    }
+    while acceptors.join_next().await.is_some() {}
+    Ok(())
+}
+
+// Listeners on `port` of the bind addresses, skipping optional ones that fail as listener::bind does, or the
+// ones adopted for it
+async fn bind(server: &Server, port: u16, protocol: &str) -> Result<Vec<TcpListener>> {
+    let mut listeners = vec![];
+    for listener in server.take_adopted(port) {
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        log_info!("Serving {} on {}", protocol, listener.local_addr().map(format_addr)?);
+        listeners.push(listener);
+    }
+    if !listeners.is_empty() {
+        return Ok(listeners);
+    }
+    for address in &server.config().bind {
+        let (address, optional) = match address.strip_prefix('-') {
+            Some(address) => (address, true),
+            None => (address.as_str(), false),
+        };
+        match TcpListener::bind((address, port)).await {
+            Ok(listener) => {
+                log_info!("Serving {} on {}", protocol, listener.local_addr().map(format_addr)?);
+                listeners.push(listener);
+            },
+            Err(e) if optional => log_warn!("Skipping optional {} bind address {} port {}: {}", protocol, address, port, e),
+            Err(e) => return Err(anyhow::anyhow!("Could not listen for {} on {} port {}: {}", protocol, address, port, e)),
+        }
+    }
+    Ok(listeners)
+}
+
+async fn accept<F, C>(listener: TcpListener, protocol: &'static str, handle: F)
+where
+    F: Fn(TcpStream, String, bool) -> C,
+    C: Future<Output = Result<()>> + Send + 'static,
+{
+    let mut connections = JoinSet::new();
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            // Reap finished connections so the set doesn't grow with every client ever served
+            Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+        };
+        match accepted {
+            Ok((stream, addr)) => {
+                let peer = format_addr(addr);
+                let connection = handle(stream, peer.clone(), addr.ip().to_canonical().is_loopback());
+                connections.spawn(async move {
+                    if let Err(e) = connection.await {
+                        log_debug!("{} client {} dropped: {}", protocol, peer, e);
+                    }
+                });
+            },
+            Err(e) => {
+                log_warn!("Accepting a {} client failed: {}", protocol, e);
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+            },
+        }
+    }
+}
+
+// Serves one connection's requests until it closes, asks to or goes idle past timeout
+async fn handle(mut stream: TcpStream, peer: &str, loopback: bool, server: &Server) -> Result<()> {
+    let mut buf = BytesMut::with_capacity(4096);
+    loop {
+        let request = match read_request(&mut stream, &mut buf, server).await {
+            Ok(Some(request)) => request,
+            Ok(None) => return Ok(()),
+            Err(response) => return write(&mut stream, response, false).await,
+        };
+        if request.path == "/ws" {
+            return match websocket::handshake(&request) {
+                Ok((response, framing)) => {
+                    stream.write_all(response.as_bytes()).await?;
+                    websocket::serve(stream, buf, framing, peer, loopback, server.clone()).await
+                },
+                Err(response) => write(&mut stream, response, false).await,
+            };
+        }
+        // Writes that can't be made durable mustn't be acknowledged
+        let Some(response) = respond(server, peer, loopback, &request).await else {
+            return Ok(());
+        };
+        write(&mut stream, response, request.keep_alive).await?;
+        if !request.keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+pub(crate) struct Request {
+    pub(crate) method: String,
+    pub(crate) path: String, // Without the query, which is ignored
+    headers: Vec<(String, String)>, // Names lowercased
+    body: Bytes,
+    pub(crate) keep_alive: bool,
+}
+
+impl Request {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header, _)| header == name).map(|(_, value)| value.as_str())
+    }
+}
+
+// The next request on the connection, None once it's closed; a malformed one is answered with the error
+// response and the connection closed
+pub(crate) async fn read_request(stream: &mut TcpStream, buf: &mut BytesMut, server: &Server) -> Result<Option<Request>, Response> {
+    let timeout = server.config().timeout;
+    let head_end = loop {
+        if let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end;
+        }
+        if buf.len() > MAX_HEAD {
+            return Err(Response::error(431, "request headers too large", Format::Json));
+        }
+        if !fill(stream, buf, timeout).await {
+            return Ok(None);
+        }
+    };
+    let bad = |message: &str| Response::error(400, message, Format::Json);
+    let head = std::str::from_utf8(&buf[..head_end]).map_err(|_| bad("request headers must be UTF-8"))?;
+    let mut lines = head.split("\r\n");
+    let mut parts = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target), Some(version), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        return Err(bad("malformed request line"));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(Response::error(505, "only HTTP/1.x is supported", Format::Json));
+    }
+    let mut headers = vec![];
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(bad("malformed header"));
+        };
+        headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+    let mut request = Request {
+        method: method.to_string(),
+        path: target.split('?').next().unwrap_or_default().to_string(),
+        headers,
+        body: Bytes::new(),
+        keep_alive: version != "HTTP/1.0",
+    };
+    match request.header("connection").map(str::to_ascii_lowercase).as_deref() {
+        Some("close") => request.keep_alive = false,
+        Some("keep-alive") => request.keep_alive = true,
+        _ => {},
+    }
+    if request.header("transfer-encoding").is_some() {
+        return Err(Response::error(411, "send the body with a Content-Length", Format::Json));
+    }
+    let length = match request.header("content-length") {
+        Some(length) => length.parse::<usize>().map_err(|_| bad("invalid Content-Length"))?,
+        None => 0,
+    };
+    if length > server.config().client_query_buffer_limit {
+        return Err(Response::error(413, "request body too large", Format::Json));
+    }
+    buf.advance(head_end + 4);
+    if buf.len() < length && request.header("expect").is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue")) {
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await.map_err(|_| bad("write failed"))?;
+    }
+    while buf.len() < length {
+        if !fill(stream, buf, timeout).await {
+            return Ok(None);
+        }
+    }
+    request.body = buf.split_to(length).freeze();
+    Ok(Some(request))
+}
+
+// Reads more of a request into `buf`. False when the client closed the connection, or sent nothing for
+// `timeout` seconds (0 waits forever).
+pub(crate) async fn fill(stream: &mut TcpStream, buf: &mut BytesMut, timeout: u64) -> bool {
+    let read = match timeout {
+        0 => stream.read_buf(buf).await,
+        secs => match tokio::time::timeout(Duration::from_secs(secs), stream.read_buf(buf)).await {
+            Ok(read) => read,
+            Err(_) => return false,
+        },
+    };
+    matches!(read, Ok(n) if n > 0)
+}
+
+// Runs the request's command, None when its writes couldn't be made durable
+async fn respond(server: &Server, peer: &str, loopback: bool, request: &Request) -> Option<Response> {
+    let Some(format) = Format::negotiate(request.header("accept")) else {
+        return Some(Response::error(406, "acceptable types are application/json, text/plain and application/octet-stream", Format::Json));
+    };
+    let config = server.config();
+    if config.protected() && !loopback {
+        log_warn!("Refused HTTP request from {}: protected mode is on and no password is set", peer);
+        return Some(Response::error(403, PROTECTED_MODE, format));
+    }
+    if let Some(password) = &config.requirepass {
+        if !authorized(request.header("authorization"), password) {
+            let response = Response::error(401, "authentication required", format);
+            return Some(response.with_header("WWW-Authenticate", "Basic realm=\"zenql\""));
+        }
+    }
+    let call = match route(request, format) {
+        Ok(call) => call,
+        Err(response) => return Some(response),
+    };
+    let mut replies = run(server, peer, call.args).await?;
+    let reply = match replies.len() {
+        1 => replies.remove(0),
+        _ => Value::Array(replies),
+    };
+    Some(match reply {
+        reply if call.missing.as_ref() == Some(&reply) => Response::error(404, "no such key", format),
+        Value::Error(e) => Response::error(400, &e, format),
+        reply => Response::result(reply, format),
+    })
+}
+
+// An Authorization header giving Bearer <password>, or Basic credentials for the default user
+pub(crate) fn authorized(authorization: Option<&str>, password: &str) -> bool {
+    let Some((scheme, credentials)) = authorization.and_then(|header| header.split_once(' ')) else {
+        return false;
+    };
+    match scheme.to_ascii_lowercase().as_str() {
+        "bearer" => credentials.trim() == password,
+        "basic" => decode_base64(credentials.trim()).is_some_and(|credentials| {
+            match credentials.iter().position(|&c| c == b':') {
+                Some(colon) => matches!(&credentials[..colon], b"" | b"default") && credentials[colon + 1..] == *password.as_bytes(),
+                None => false,
+            }
+        }),
+        _ => false,
+    }
+}
+
+// A command a request runs, and the reply meaning its key wasn't there, answered with a 404
+struct Call {
+    args: Vec<Bytes>,
+    missing: Option<Value>,
+}
+
+fn route(request: &Request, format: Format) -> Result<Call, Response> {
+    let text = |text: &str| Bytes::copy_from_slice(text.as_bytes());
+    if let Some(key) = request.path.strip_prefix("/keys/") {
+        let key = percent_decode(key).ok_or_else(|| Response::error(400, "invalid percent-encoding in key", format))?;
+        return match request.method.as_str() {
+            "GET" => Ok(Call { args: vec![text("GET"), key], missing: Some(Value::Null) }),
+            "PUT" => Ok(Call { args: vec![text("SET"), key, request.body.clone()], missing: None }),
+            "DELETE" => Ok(Call { args: vec![text("DEL"), key], missing: Some(Value::Integer(0)) }),
+            _ => Err(Response::error(405, "method not allowed", format).with_header("Allow", "GET, PUT, DELETE")),
+        };
+    }
+    if request.path != "/command" {
+        return Err(Response::error(404, "no such endpoint", format));
+    }
+    if request.method != "POST" {
+        return Err(Response::error(405, "method not allowed", format).with_header("Allow", "POST"));
+    }
+    let content_type = request.header("content-type").and_then(|content_type| content_type.split(';').next());
+    if content_type.is_some_and(|content_type| !content_type.trim().eq_ignore_ascii_case("application/json")) {
+        return Err(Response::error(415, "commands are sent as application/json", format));
+    }
+    match parse_command(&request.body) {
+        Some(args) => Ok(Call { args, missing: None }),
+        None => Err(Response::error(400, "a command is a non-empty JSON array of strings and numbers", format)),
+    }
+}
+
+// A command's name and arguments from a JSON array of strings and numbers
+pub(crate) fn parse_command(json: &[u8]) -> Option<Vec<Bytes>> {
+    let Ok(Json::Array(items)) = Json::parse(json) else {
+        return None;
+    };
+    let args = items.iter().map(|item| match item {
+        Json::String(s) => Some(Bytes::copy_from_slice(s.as_bytes())),
+        Json::Int(_) | Json::Float(_) => Some(Bytes::from(item.to_text())),
+        _ => None,
+    });
+    args.collect::<Option<Vec<_>>>().filter(|args| !args.is_empty())
+}
+
+// Runs a command in a session of its own, already authenticated, that's gone with the request
+pub(crate) async fn run(server: &Server, peer: &str, mut args: Vec<Bytes>) -> Option<Vec<Value>> {
+    let (sender, _) = mpsc::unbounded_channel();
+    let session = Session::new(server.pubsub.next_subscriber_id(), peer.to_string(), sender, true);
+    let span = CommandSpan::start(peer, Duration::ZERO);
+    let command = args.remove(0);
+    let args = args.into_iter().map(Value::BulkString).collect();
+    let (session, mut span, replies) = connection::execute(server, session, command, args, span).await;
+    for channel in &session.subscriptions {
+        server.pubsub.unsubscribe(channel, session.id);
+    }
+    let synced = connection::sync_writes(server, peer).await;
+    span.phase("write");
+    span.finish();
+    synced.then_some(replies)
+}
+
+// How replies are written, from Accept
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Format {
+    Json,
+    Text,
+    Binary,
+}
+
+impl Format {
+    // The first media range in `accept` we can give that isn't refused with q=0, JSON without one
+    fn negotiate(accept: Option<&str>) -> Option<Format> {
+        let Some(accept) = accept.filter(|accept| !accept.trim().is_empty()) else {
+            return Some(Format::Json);
+        };
+        accept.split(',').find_map(|range| {
+            let mut params = range.split(';');
+            let media = params.next()?.trim().to_ascii_lowercase();
+            if params.any(|param| param.trim().strip_prefix("q=").and_then(|q| q.trim().parse::<f64>().ok()) == Some(0.0)) {
+                return None;
+            }
+            match media.as_str() {
+                "application/json" | "application/*" | "*/*" => Some(Format::Json),
+                "text/plain" | "text/*" => Some(Format::Text),
+                "application/octet-stream" => Some(Format::Binary),
+                _ => None,
+            }
+        })
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Text => "text/plain; charset=utf-8",
+            Format::Binary => "application/octet-stream",
+        }
+    }
+}
+
+pub(crate) struct Response {
+    status: u16,
+    format: Format,
+    headers: Vec<(&'static str, &'static str)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn result(reply: Value, format: Format) -> Response {
+        let body = match format {
+            Format::Json => Json::Object(vec![("result".to_string(), to_json(reply))]).to_text().into_bytes(),
+            Format::Text | Format::Binary => {
+                let mut body = vec![];
+                write_text(&mut body, reply);
+                body
+            },
+        };
+        Response { status: 200, format, headers: vec![], body }
+    }
+
+    pub(crate) fn error(status: u16, message: &str, format: Format) -> Response {
+        let body = match format {
+            Format::Json => Json::Object(vec![("error".to_string(), Json::String(message.to_string()))]).to_text().into_bytes(),
+            Format::Text | Format::Binary => message.as_bytes().to_vec(),
+        };
+        Response { status, format, headers: vec![], body }
+    }
+
+    pub(crate) fn json(status: u16, body: Json) -> Response {
+        Response { status, format: Format::Json, headers: vec![], body: body.to_text().into_bytes() }
+    }
+
+    pub(crate) fn with_header(mut self, name: &'static str, value: &'static str) -> Response {
+        self.headers.push((name, value));
+        self
+    }
+}
+
+pub(crate) async fn write(stream: &mut TcpStream, response: Response, keep_alive: bool) -> Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n",
+        response.status, reason(response.status), response.format.content_type(), response.body.len()
+    );
+    for (name, value) in &response.headers {
+        let _ = write!(head, "{}: {}\r\n", name, value);
+    }
+    if !keep_alive {
+        head.push_str("Connection: close\r\n");
+    }
+    head.push_str("\r\n");
+    // One write, so a kept-alive client isn't left waiting on a delayed ACK for the body
+    let mut out = head.into_bytes();
+    out.extend_from_slice(&response.body);
+    stream.write_all(&out).await?;
+    Ok(())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        415 => "Unsupported Media Type",
+        426 => "Upgrade Required",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    }
+}
+
+// Strings that aren't UTF-8 are converted lossily; JSON has no way to carry them
+pub(crate) fn to_json(value: Value) -> Json {
+    match value {
+        Value::SimpleString(s) | Value::BigNumber(s) => Json::String(s),
+        Value::BulkString(b) | Value::Verbatim(_, b) => Json::String(String::from_utf8_lossy(&b).into_owned()),
+        Value::Error(e) => Json::Object(vec![("error".to_string(), Json::String(e))]),
+        Value::Integer(n) => Json::Int(n),
+        Value::Double(d) if d.is_finite() => Json::Float(d),
+        Value::Double(d) => Json::String(format_double(d)),
+        Value::Boolean(b) => Json::Bool(b),
+        Value::Null | Value::NullArray => Json::Null,
+        Value::Array(items) | Value::Set(items) | Value::Push(items) => Json::Array(items.into_iter().map(to_json).collect()),
+        Value::Map(pairs) => {
+            let string_keys = pairs.iter().all(|(key, _)| matches!(key, Value::SimpleString(_) | Value::BulkString(_)));
+            match string_keys {
+                true => Json::Object(pairs.into_iter().map(|(key, value)| (text_of(key), to_json(value))).collect()),
+                false => Json::Array(pairs.into_iter().map(|(key, value)| Json::Array(vec![to_json(key), to_json(value)])).collect()),
+            }
+        },
+    }
+}
+
+fn text_of(key: Value) -> String {
+    match to_json(key) {
+        Json::String(s) => s,
+        other => other.to_text(),
+    }
+}
+
+// Strings as they are, numbers in decimal, and the elements of arrays and maps a line each, nil as nothing
+fn write_text(out: &mut Vec<u8>, value: Value) {
+    let lines = |out: &mut Vec<u8>, items: Vec<Value>| {
+        for (i, item) in items.into_iter().enumerate() {
+            if i > 0 {
+                out.push(b'\n');
+            }
+            write_text(out, item);
+        }
+    };
+    match value {
+        Value::SimpleString(s) | Value::BigNumber(s) | Value::Error(s) => out.extend_from_slice(s.as_bytes()),
+        Value::BulkString(b) | Value::Verbatim(_, b) => out.extend_from_slice(&b),
+        Value::Integer(n) => out.extend_from_slice(n.to_string().as_bytes()),
+        Value::Double(d) => out.extend_from_slice(format_double(d).as_bytes()),
+        Value::Boolean(b) => out.extend_from_slice(if b { b"true" } else { b"false" }),
+        Value::Null | Value::NullArray => {},
+        Value::Array(items) | Value::Set(items) | Value::Push(items) => lines(out, items),
+        Value::Map(pairs) => lines(out, pairs.into_iter().flat_map(|(key, value)| [key, value]).collect()),
+    }
+}
+
+fn percent_decode(text: &str) -> Option<Bytes> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let hex = bytes.get(i + 1..i + 3).filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+        out.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+        i += 3;
+    }
+    Some(Bytes::from(out))
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Standard base64, padded
+pub(crate) fn encode_base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| bits | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(BASE64[(bits >> (18 - 6 * i) & 63) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+// Standard base64, padding optional
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut out = vec![];
+    let (mut bits, mut count) = (0u32, 0);
+    for c in text.trim_end_matches('=').bytes() {
+        let sextet = BASE64.iter().position(|&digit| digit == c)? as u8;
+        // Only the bits not yet output are kept
+        bits = (bits << 6 | sextet as u32) & 0xffff;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1,163 @@
+// Secondary indexes over hash fields. An index is declared on one field of the hashes whose keys start with
+// a prefix, and maps the field's values to the keys holding them, so IDX.QUERY finds keys by exact value or
+// range without scanning the keyspace. JSON documents under the prefix are indexed too, by the member the
+// field names, dotted for a nested one: a string by its text and anything else but null or a boolean by its
+// JSON, as ZQL compares them, so ZQL can plan with the index whichever kind of row a query reads. Every shard keeps a partition of each index for its own keys, which
+// Shard::insert and remove update under the shard's write lock: a key and its index entries never disagree,
+// however the key was written, deleted or expired. Queries read the partitions a shard at a time.
+//
+// Declarations live in memory only. After a restart indexes are declared again, and built from the keys
+// there are then.
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use crate::json::Json;
+use crate::storage::StoredValue;
+
+// How an index orders values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexKind {
+    #[default]
+    Text, // Byte by byte
+    Numeric, // As numbers; values that aren't one are left out
+}
+
+impl IndexKind {
+    pub fn parse(name: &str) -> Result<IndexKind> {
+        match name.to_lowercase().as_str() {
+            "text" => Ok(IndexKind::Text),
+            "numeric" => Ok(IndexKind::Numeric),
+            _ => bail!("unknown index type '{}', expected text or numeric", name),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            IndexKind::Text => "text",
+            IndexKind::Numeric => "numeric",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexDef {
+    pub name: String,
+    pub prefix: Bytes, // Only keys starting with it are indexed, empty for all
+    pub field: Bytes,
+    pub kind: IndexKind,
+}
+
+impl IndexDef {
+    // What a key's value is indexed under, None when it isn't a hash or document of this index with the field
+    // set
+    pub fn term(&self, key: &[u8], value: &StoredValue) -> Option<Bytes> {
+        if !key.starts_with(&self.prefix) {
+            return None;
+        }
+        match value {
+            StoredValue::Hash(hash) => self.encode(hash.get(&self.field)?),
+            StoredValue::Json(doc) => {
+                let mut member = doc.as_ref();
+                for name in std::str::from_utf8(&self.field).ok()?.split('.') {
+                    let Json::Object(members) = member else {
+                        return None;
+                    };
+                    member = members.iter().find(|(member, _)| member == name).map(|(_, value)| value)?;
+                }
+                match member {
+                    Json::String(text) => self.encode(text.as_bytes()),
+                    Json::Null | Json::Bool(_) => None,
+                    member => self.encode(member.to_text().as_bytes()),
+                }
+            },
+            _ => None,
+        }
+    }
+
+    // A field value as it sorts in the index: text as it is, numbers in a binary form that sorts bytewise
+    // the way they do numerically. None for a number that isn't one.
+    pub fn encode(&self, value: &[u8]) -> Option<Bytes> {
+        match self.kind {
+            IndexKind::Text => Some(Bytes::copy_from_slice(value)),
+            IndexKind::Numeric => {
+                let n: f64 = std::str::from_utf8(value).ok()?.trim().parse().ok()?;
+                if n.is_nan() {
+                    return None;
+                }
+                // Positive numbers get the sign bit set so they sort after negative ones, whose bits are all
+                // flipped so larger magnitudes sort first. -0 is 0.
+                let bits = (n + 0.0).to_bits();
+                let ordered = if bits >> 63 == 1 { !bits } else { bits | 1 << 63 };
+                Some(Bytes::copy_from_slice(&ordered.to_be_bytes()))
+            },
+        }
+    }
+}
+
+// The terms a query asks for, each bound encoded like IndexDef::encode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TermRange {
+    pub min: Bound<Bytes>,
+    pub max: Bound<Bytes>,
+}
+
+impl TermRange {
+    pub fn exact(term: Bytes) -> TermRange {
+        TermRange { min: Bound::Included(term.clone()), max: Bound::Included(term) }
+    }
+
+    fn below_max(&self, term: &Bytes) -> bool {
+        match &self.max {
+            Bound::Included(max) => term <= max,
+            Bound::Excluded(max) => term < max,
+            Bound::Unbounded => true,
+        }
+    }
+}
+
+// One shard's part of an index
+#[derive(Debug, Default)]
+pub struct Partition {
+    entries: BTreeSet<(Bytes, Bytes)>, // Term and key, in the order queries return them
+    terms: HashMap<Bytes, Bytes>, // Each indexed key's term, to find its entry again
+}
+
+impl Partition {
+    // Indexes `key` under `term` in place of what it was indexed under, None leaving it out
+    pub fn insert(&mut self, key: &Bytes, term: Option<Bytes>) {
+        self.remove(key);
+        if let Some(term) = term {
+            self.entries.insert((term.clone(), key.clone()));
+            self.terms.insert(key.clone(), term);
+        }
+    }
+
+    pub fn remove(&mut self, key: &Bytes) {
+        if let Some(term) = self.terms.remove(key) {
+            self.entries.remove(&(term, key.clone()));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.terms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    // Entries with their term in `range`, in order
+    pub fn range<'a>(&'a self, range: &'a TermRange) -> impl Iterator<Item = (&'a Bytes, &'a Bytes)> + 'a {
+        let (start, excluded) = match &range.min {
+            Bound::Included(min) => ((min.clone(), Bytes::new()), None),
+            Bound::Excluded(min) => ((min.clone(), Bytes::new()), Some(min)),
+            Bound::Unbounded => ((Bytes::new(), Bytes::new()), None),
+        };
+        self.entries
+            .range(start..)
+            .skip_while(move |(term, _)| Some(term) == excluded)
+            .take_while(|(term, _)| range.below_max(term))
+            .map(|(term, key)| (term, key))
+    }
+}
@@ -0,0 +1,915 @@
+// JSON documents, the value type of the JSON.* commands: parsed once when set and kept as a tree, so reads
+// and updates address parts of a document without parsing it again. Objects keep their members in insertion
+// order, like RedisJSON, and look names up linearly. Numbers are 64-bit integers when written without a
+// fraction or exponent and in range, doubles otherwise.
+//
+// Paths come in RedisJSON's two flavours. JSONPath starts with `$` and may match any number of values;
+// commands reply with an array of what each match gave. The legacy syntax starts with `.` or a member name
+// and matches one value, the first if it names several; replies are that value, and a missing one is an
+// error. Both address members as `.name` or `["name"]` and array elements as `[index]`, negative indexes
+// counting from the end, and take the rest of JSONPath (RFC 9535): `*` for every child, `..` for
+// descendants at any depth, unions `[0,'a']`, slices `[start:end:step]` and filters such as
+// `[?(@.price < 10 && @.tags)]`, comparing with literals, `@` (the child tested) and `$` paths.
+use std::fmt::Write;
+
+// Deeper documents are refused, so walking one can't overflow the stack
+pub const MAX_DEPTH: usize = 128;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64), // Always finite
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+// Documents never hold NaN, so equality is total
+impl Eq for Json {}
+
+impl Json {
+    // The document `text` holds, or why it doesn't hold one
+    pub fn parse(text: &[u8]) -> Result<Json, String> {
+        let mut parser = Parser { text, pos: 0 };
+        parser.skip_whitespace();
+        let value = parser.value(0)?;
+        parser.skip_whitespace();
+        if parser.pos < text.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    // Compact JSON text
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out, &Format::default(), 0);
+        out
+    }
+
+    pub fn write(&self, out: &mut String, format: &Format, depth: usize) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Json::Int(n) => {
+                let _ = write!(out, "{}", n);
+            },
+            Json::Float(n) => write_float(out, *n),
+            Json::String(s) => write_string(out, s),
+            Json::Array(items) => {
+                format.open(out, '[', items.is_empty());
+                for (i, item) in items.iter().enumerate() {
+                    format.item(out, i, depth + 1);
+                    item.write(out, format, depth + 1);
+                }
+                format.close(out, ']', items.is_empty(), depth);
+            },
+            Json::Object(members) => {
+                format.open(out, '{', members.is_empty());
+                for (i, (name, value)) in members.iter().enumerate() {
+                    format.item(out, i, depth + 1);
+                    write_string(out, name);
+                    out.push(':');
+                    out.push_str(&format.space);
+                    value.write(out, format, depth + 1);
+                }
+                format.close(out, '}', members.is_empty(), depth);
+            },
+        }
+    }
+
+    // The value at a location found by Path::find
+    pub fn at(&self, location: &[usize]) -> &Json {
+        location.iter().fold(self, |value, &i| match value {
+            Json::Array(items) => &items[i],
+            Json::Object(members) => &members[i].1,
+            _ => panic!("locations only pass through arrays and objects"),
+        })
+    }
+
+    pub fn at_mut(&mut self, location: &[usize]) -> &mut Json {
+        location.iter().fold(self, |value, &i| match value {
+            Json::Array(items) => &mut items[i],
+            Json::Object(members) => &mut members[i].1,
+            _ => panic!("locations only pass through arrays and objects"),
+        })
+    }
+
+    // Removes the value at a location other than the root
+    pub fn remove(&mut self, location: &[usize]) {
+        let (&last, parent) = location.split_last().expect("the root can't be removed");
+        match self.at_mut(parent) {
+            Json::Array(items) => {
+                items.remove(last);
+            },
+            Json::Object(members) => {
+                members.remove(last);
+            },
+            _ => panic!("locations only pass through arrays and objects"),
+        }
+    }
+
+    // Levels of nesting, 1 for a scalar
+    pub fn depth(&self) -> usize {
+        match self {
+            Json::Array(items) => 1 + items.iter().map(Json::depth).max().unwrap_or(0),
+            Json::Object(members) => 1 + members.iter().map(|(_, value)| value.depth()).max().unwrap_or(0),
+            _ => 1,
+        }
+    }
+
+    // Values in the document, arrays and objects included
+    pub fn values(&self) -> usize {
+        match self {
+            Json::Array(items) => 1 + items.iter().map(Json::values).sum::<usize>(),
+            Json::Object(members) => 1 + members.iter().map(|(_, value)| value.values()).sum::<usize>(),
+            _ => 1,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Int(n) => Some(*n as f64),
+            Json::Float(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    // What error replies call the value's type
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Bool(_) => "boolean",
+            Json::Int(_) => "integer",
+            Json::Float(_) => "number",
+            Json::String(_) => "string",
+            Json::Array(_) => "array",
+            Json::Object(_) => "object",
+        }
+    }
+
+    fn member(&self, name: &str) -> Option<usize> {
+        match self {
+            Json::Object(members) => members.iter().position(|(member, _)| member == name),
+            _ => None,
+        }
+    }
+}
+
+// Double formatting: the shortest text reading back as the same number, keeping a fraction on whole ones
+fn write_float(out: &mut String, n: f64) {
+    let _ = write!(out, "{:?}", n);
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// JSON.GET's INDENT, NEWLINE and SPACE: what goes before each nesting level, after each item and after
+// each member's colon. All empty is compact JSON.
+#[derive(Debug, Clone, Default)]
+pub struct Format {
+    pub indent: String,
+    pub newline: String,
+    pub space: String,
+}
+
+impl Format {
+    fn open(&self, out: &mut String, bracket: char, empty: bool) {
+        out.push(bracket);
+        if !empty {
+            out.push_str(&self.newline);
+        }
+    }
+
+    fn item(&self, out: &mut String, i: usize, depth: usize) {
+        if i > 0 {
+            out.push(',');
+            out.push_str(&self.newline);
+        }
+        self.pad(out, depth);
+    }
+
+    fn close(&self, out: &mut String, bracket: char, empty: bool, depth: usize) {
+        if !empty {
+            out.push_str(&self.newline);
+            self.pad(out, depth);
+        }
+        out.push(bracket);
+    }
+
+    fn pad(&self, out: &mut String, depth: usize) {
+        for _ in 0..depth {
+            out.push_str(&self.indent);
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, reason: &str) -> String {
+        format!("{} at offset {}", reason, self.pos)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.text.get(self.pos), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, String> {
+        if depth == MAX_DEPTH {
+            return Err(self.error("nested too deeply"));
+        }
+        match self.text.get(self.pos) {
+            Some(b'{') => self.object(depth),
+            Some(b'[') => self.array(depth),
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(_) => Err(self.error("expected a value")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        if !self.text[self.pos..].starts_with(word.as_bytes()) {
+            return Err(self.error("expected a value"));
+        }
+        self.pos += word.len();
+        Ok(value)
+    }
+
+    // Moves past `byte` after any whitespace, if it's next
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.text.get(self.pos) == Some(&byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Json, String> {
+        self.pos += 1;
+        let mut items = vec![];
+        if self.eat(b']') {
+            return Ok(Json::Array(items));
+        }
+        loop {
+            self.skip_whitespace();
+            items.push(self.value(depth + 1)?);
+            if self.eat(b']') {
+                return Ok(Json::Array(items));
+            }
+            if !self.eat(b',') {
+                return Err(self.error("expected ',' or ']'"));
+            }
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Json, String> {
+        self.pos += 1;
+        let mut members: Vec<(String, Json)> = vec![];
+        if self.eat(b'}') {
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.text.get(self.pos) != Some(&b'"') {
+                return Err(self.error("expected a member name"));
+            }
+            let name = self.string()?;
+            if !self.eat(b':') {
+                return Err(self.error("expected ':'"));
+            }
+            self.skip_whitespace();
+            let value = self.value(depth + 1)?;
+            // A repeated name keeps its first place and its last value
+            match members.iter_mut().find(|(member, _)| *member == name) {
+                Some(member) => member.1 = value,
+                None => members.push((name, value)),
+            }
+            if self.eat(b'}') {
+                return Ok(Json::Object(members));
+            }
+            if !self.eat(b',') {
+                return Err(self.error("expected ',' or '}'"));
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut out = Vec::new();
+        loop {
+            let Some(&byte) = self.text.get(self.pos) else {
+                return Err(self.error("unterminated string"));
+            };
+            self.pos += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let Some(&escape) = self.text.get(self.pos) else {
+                        return Err(self.error("unterminated string"));
+                    };
+                    self.pos += 1;
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                },
+                0..=0x1f => return Err(self.error("control character in string")),
+                byte => out.push(byte),
+            }
+        }
+        String::from_utf8(out).map_err(|_| self.error("invalid UTF-8 in string"))
+    }
+
+    // After `\u`: four hex digits, or two escapes making a surrogate pair
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex4()?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.error("invalid \\u escape"));
+        }
+        if !self.text[self.pos..].starts_with(b"\\u") {
+            return Err(self.error("unpaired surrogate"));
+        }
+        self.pos += 2;
+        let low = self.hex4()?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return Err(self.error("unpaired surrogate"));
+        }
+        char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)).ok_or_else(|| self.error("invalid \\u escape"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.text.get(self.pos..self.pos + 4).ok_or_else(|| self.error("invalid \\u escape"))?;
+        let digits = std::str::from_utf8(digits).map_err(|_| self.error("invalid \\u escape"))?;
+        let n = u32::from_str_radix(digits, 16).map_err(|_| self.error("invalid \\u escape"))?;
+        self.pos += 4;
+        Ok(n)
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        let digits = |parser: &mut Parser| {
+            let from = parser.pos;
+            while parser.text.get(parser.pos).is_some_and(u8::is_ascii_digit) {
+                parser.pos += 1;
+            }
+            parser.pos - from
+        };
+        if self.text[self.pos] == b'-' {
+            self.pos += 1;
+        }
+        let int_start = self.pos;
+        match digits(self) {
+            0 => return Err(self.error("expected a digit")),
+            len if len > 1 && self.text[int_start] == b'0' => return Err(self.error("leading zero")),
+            _ => {},
+        }
+        let mut integer = true;
+        if self.text.get(self.pos) == Some(&b'.') {
+            self.pos += 1;
+            integer = false;
+            if digits(self) == 0 {
+                return Err(self.error("expected a digit"));
+            }
+        }
+        if matches!(self.text.get(self.pos), Some(b'e' | b'E')) {
+            self.pos += 1;
+            integer = false;
+            if matches!(self.text.get(self.pos), Some(b'+' | b'-')) {
+                self.pos += 1;
+            }
+            if digits(self) == 0 {
+                return Err(self.error("expected a digit"));
+            }
+        }
+        // Only digits and signs, so it's ASCII
+        let text = std::str::from_utf8(&self.text[start..self.pos]).expect("ASCII");
+        if integer {
+            if let Ok(n) = text.parse() {
+                return Ok(Json::Int(n));
+            }
+        }
+        match text.parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(Json::Float(n)),
+            _ => Err(self.error("number out of range")),
+        }
+    }
+}
+
+// Bounds how deeply filters nest in a path, so parsing one can't overflow the stack
+const MAX_FILTER_DEPTH: usize = 32;
+
+// One step of a path: the selectors pick children of each value reached so far, or with `descendants`
+// (`..`) of each value reached so far and everything below it
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Segment {
+    descendants: bool,
+    selectors: Vec<Selector>, // More than one for a union, [a,b]
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Selector {
+    Member(String),
+    Index(i64), // Negative counts from the end
+    Wildcard,
+    Slice(Option<i64>, Option<i64>, i64), // [start:end:step], Python-style
+    Filter(Box<Filter>),
+}
+
+// [?expr]: keeps the children the expression holds for, @ being the child and $ the document
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Filter {
+    Or(Box<Filter>, Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Exists(Operand),
+    Compare(Operand, Comparison, Operand),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    Current(Vec<Segment>), // The first value the path matches from @
+    Root(Vec<Segment>), // Likewise from $
+    Literal(Json),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Path {
+    pub legacy: bool, // Matches exactly one value, see the top of the file
+    segments: Vec<Segment>,
+}
+
+// Where Path::targets says a value would go
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Existing(Vec<usize>),
+    NewMember(Vec<usize>, String), // An object and the name of a member it doesn't have yet
+}
+
+impl Path {
+    pub fn parse(path: &str) -> Result<Path, String> {
+        let (legacy, rest) = match path.strip_prefix('$') {
+            Some(rest) => (false, rest),
+            None if path == "." => (true, ""),
+            None => (true, path),
+        };
+        let mut parser = PathParser { text: rest, pos: 0, in_filter: false, depth: 0 };
+        let mut segments = vec![];
+        // The legacy syntax may leave out the first dot
+        if legacy && !rest.is_empty() && !rest.starts_with(['.', '[']) {
+            segments.push(Segment { descendants: false, selectors: vec![Selector::Member(parser.name()?)] });
+        }
+        segments.extend(parser.segments()?);
+        if parser.pos < rest.len() {
+            return Err(parser.error("unexpected character"));
+        }
+        Ok(Path { legacy, segments })
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    // Every value the path matches, as locations for Json::at
+    pub fn find(&self, doc: &Json) -> Vec<Vec<usize>> {
+        let mut found = vec![];
+        walk(doc, doc, &self.segments, &mut vec![], &mut |location, _| found.push(location.to_vec()));
+        found
+    }
+
+    // Where setting the path puts values: the values it matches, and when it ends in a plain member name,
+    // that member in each object that lacks it
+    pub fn targets(&self, doc: &Json) -> Vec<Target> {
+        let (name, parents) = match self.segments.split_last() {
+            None => return vec![Target::Existing(vec![])],
+            Some((Segment { descendants: false, selectors }, parents)) => match &selectors[..] {
+                [Selector::Member(name)] => (name, parents),
+                _ => return self.find(doc).into_iter().map(Target::Existing).collect(),
+            },
+            Some(_) => return self.find(doc).into_iter().map(Target::Existing).collect(),
+        };
+        let mut targets = vec![];
+        walk(doc, doc, parents, &mut vec![], &mut |location, parent| {
+            if let Json::Object(_) = parent {
+                match parent.member(name) {
+                    Some(i) => targets.push(Target::Existing([location, &[i]].concat())),
+                    None => targets.push(Target::NewMember(location.to_vec(), name.clone())),
+                }
+            }
+        });
+        targets
+    }
+}
+
+struct PathParser<'a> {
+    text: &'a str,
+    pos: usize,
+    in_filter: bool, // Names end at operators and brackets too
+    depth: usize, // Filters entered
+}
+
+impl PathParser<'_> {
+    fn error(&self, reason: &str) -> String {
+        format!("{} at offset {}", reason, self.pos)
+    }
+
+    fn rest(&self) -> &str {
+        &self.text[self.pos..]
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        let found = self.rest().starts_with(token);
+        if found {
+            self.pos += token.len();
+        }
+        found
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.pos += self.rest().len() - self.rest().trim_start().len();
+    }
+
+    // Segments up to the first thing that doesn't continue the path
+    fn segments(&mut self) -> Result<Vec<Segment>, String> {
+        let mut segments = vec![];
+        loop {
+            let descendants = self.eat("..");
+            let selectors = if descendants || self.eat(".") {
+                if self.eat("*") {
+                    vec![Selector::Wildcard]
+                } else if descendants && self.eat("[") {
+                    self.bracket()?
+                } else {
+                    vec![Selector::Member(self.name()?)]
+                }
+            } else if self.eat("[") {
+                self.bracket()?
+            } else {
+                return Ok(segments);
+            };
+            segments.push(Segment { descendants, selectors });
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let stops: &[char] = if self.in_filter { &['.', '[', ']', ')', ' ', '=', '!', '<', '>', '&', '|', ','] } else { &['.', '['] };
+        let end = self.rest().find(stops).unwrap_or(self.rest().len());
+        if end == 0 {
+            return Err(self.error("expected a member name"));
+        }
+        let name = self.rest()[..end].to_string();
+        self.pos += end;
+        Ok(name)
+    }
+
+    // A quoted name, `\` making the next character literal
+    fn quoted(&mut self, quote: char) -> Result<String, String> {
+        let mut name = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => name.extend(chars.next().map(|(_, c)| c)),
+                c if c == quote => {
+                    self.pos += i + 1;
+                    return Ok(name);
+                },
+                c => name.push(c),
+            }
+        }
+        Err(self.error("unterminated quoted name"))
+    }
+
+    fn int(&mut self) -> Result<Option<i64>, String> {
+        let len = self.rest().find(|c: char| !(c.is_ascii_digit() || c == '-')).unwrap_or(self.rest().len());
+        if len == 0 {
+            return Ok(None);
+        }
+        let n = self.rest()[..len].parse().map_err(|_| self.error("invalid array index"))?;
+        self.pos += len;
+        Ok(Some(n))
+    }
+
+    // What follows `[` up to and including its `]`
+    fn bracket(&mut self) -> Result<Vec<Selector>, String> {
+        self.skip_whitespace();
+        if self.eat("?") {
+            let filter = self.filter()?;
+            self.skip_whitespace();
+            if !self.eat("]") {
+                return Err(self.error("expected ']'"));
+            }
+            return Ok(vec![Selector::Filter(Box::new(filter))]);
+        }
+        let mut selectors = vec![];
+        loop {
+            self.skip_whitespace();
+            let selector = if self.eat("*") {
+                Selector::Wildcard
+            } else if let Some(quote) = ['"', '\''].into_iter().find(|quote| self.rest().starts_with(*quote)) {
+                self.pos += 1;
+                Selector::Member(self.quoted(quote)?)
+            } else {
+                let start = self.int()?;
+                self.skip_whitespace();
+                if self.eat(":") {
+                    self.skip_whitespace();
+                    let end = self.int()?;
+                    self.skip_whitespace();
+                    let step = if self.eat(":") {
+                        self.skip_whitespace();
+                        self.int()?.unwrap_or(1)
+                    } else {
+                        1
+                    };
+                    if step == 0 {
+                        return Err(self.error("slice step can't be 0"));
+                    }
+                    Selector::Slice(start, end, step)
+                } else {
+                    Selector::Index(start.ok_or_else(|| self.error("expected an index, a name or a slice"))?)
+                }
+            };
+            selectors.push(selector);
+            self.skip_whitespace();
+            if self.eat("]") {
+                return Ok(selectors);
+            }
+            if !self.eat(",") {
+                return Err(self.error("expected ',' or ']'"));
+            }
+        }
+    }
+
+    fn filter(&mut self) -> Result<Filter, String> {
+        self.depth += 1;
+        if self.depth > MAX_FILTER_DEPTH {
+            return Err(self.error("filter nested too deeply"));
+        }
+        let in_filter = std::mem::replace(&mut self.in_filter, true);
+        let mut filter = self.and()?;
+        while self.eat_operator("||") {
+            filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        self.in_filter = in_filter;
+        self.depth -= 1;
+        Ok(filter)
+    }
+
+    fn eat_operator(&mut self, operator: &str) -> bool {
+        self.skip_whitespace();
+        self.eat(operator)
+    }
+
+    fn and(&mut self) -> Result<Filter, String> {
+        let mut filter = self.unary()?;
+        while self.eat_operator("&&") {
+            filter = Filter::And(Box::new(filter), Box::new(self.unary()?));
+        }
+        Ok(filter)
+    }
+
+    fn unary(&mut self) -> Result<Filter, String> {
+        if self.eat_operator("!") {
+            return Ok(Filter::Not(Box::new(self.filter_operand()?)));
+        }
+        let left = self.operand()?;
+        let comparison = [
+            ("==", Comparison::Eq),
+            ("!=", Comparison::Ne),
+            ("<=", Comparison::Le),
+            (">=", Comparison::Ge),
+            ("<", Comparison::Lt),
+            (">", Comparison::Gt),
+        ];
+        self.skip_whitespace();
+        let Some((_, comparison)) = comparison.into_iter().find(|(token, _)| self.eat(token)) else {
+            return match left {
+                Some(left @ (Operand::Current(_) | Operand::Root(_))) => Ok(Filter::Exists(left)),
+                Some(Operand::Literal(_)) => Err(self.error("expected a comparison")),
+                None => self.filter_operand(),
+            };
+        };
+        let right = self.operand()?.ok_or_else(|| self.error("expected a value to compare with"))?;
+        Ok(Filter::Compare(left.ok_or_else(|| self.error("expected a value to compare"))?, comparison, right))
+    }
+
+    // What `!` applies to: a parenthesised filter or an existence test
+    fn filter_operand(&mut self) -> Result<Filter, String> {
+        self.skip_whitespace();
+        if self.eat("(") {
+            let filter = self.filter()?;
+            if !self.eat_operator(")") {
+                return Err(self.error("expected ')'"));
+            }
+            return Ok(filter);
+        }
+        match self.operand()? {
+            Some(operand @ (Operand::Current(_) | Operand::Root(_))) => Ok(Filter::Exists(operand)),
+            _ => Err(self.error("expected a filter")),
+        }
+    }
+
+    // A path from @ or $, or a literal; None at a parenthesis, left to the caller
+    fn operand(&mut self) -> Result<Option<Operand>, String> {
+        self.skip_whitespace();
+        if self.eat("@") {
+            return Ok(Some(Operand::Current(self.segments()?)));
+        }
+        if self.eat("$") {
+            return Ok(Some(Operand::Root(self.segments()?)));
+        }
+        if let Some(quote) = ['"', '\''].into_iter().find(|quote| self.rest().starts_with(*quote)) {
+            self.pos += 1;
+            return Ok(Some(Operand::Literal(Json::String(self.quoted(quote)?))));
+        }
+        if self.rest().starts_with('(') {
+            return Ok(None);
+        }
+        let len = self.rest().find(|c: char| !(c.is_ascii_alphanumeric() || "+-.".contains(c))).unwrap_or(self.rest().len());
+        let literal = Json::parse(&self.rest().as_bytes()[..len]).map_err(|_| self.error("expected a path or a literal"))?;
+        self.pos += len;
+        Ok(Some(Operand::Literal(literal)))
+    }
+}
+
+// Calls `found` with every value `segments` lead to from `value`, and its location
+fn walk(root: &Json, value: &Json, segments: &[Segment], location: &mut Vec<usize>, found: &mut dyn FnMut(&[usize], &Json)) {
+    let Some((segment, rest)) = segments.split_first() else {
+        found(location, value);
+        return;
+    };
+    select(root, value, &segment.selectors, &mut |i, child| {
+        location.push(i);
+        walk(root, child, rest, location, found);
+        location.pop();
+    });
+    if segment.descendants {
+        for (i, child) in children(value) {
+            location.push(i);
+            walk(root, child, segments, location, found);
+            location.pop();
+        }
+    }
+}
+
+fn children(value: &Json) -> impl Iterator<Item = (usize, &Json)> {
+    let (items, members): (&[Json], &[(String, Json)]) = match value {
+        Json::Array(items) => (items, &[]),
+        Json::Object(members) => (&[], members),
+        _ => (&[], &[]),
+    };
+    items.iter().enumerate().chain(members.iter().map(|(_, value)| value).enumerate())
+}
+
+// Calls `selected` with the children of `value` each selector picks, in turn
+fn select(root: &Json, value: &Json, selectors: &[Selector], selected: &mut dyn FnMut(usize, &Json)) {
+    for selector in selectors {
+        match (selector, value) {
+            (Selector::Member(name), Json::Object(members)) => {
+                if let Some(i) = value.member(name) {
+                    selected(i, &members[i].1);
+                }
+            },
+            (Selector::Index(index), Json::Array(items)) => {
+                if let Some(i) = resolve_index(*index, items.len()) {
+                    selected(i, &items[i]);
+                }
+            },
+            (Selector::Wildcard, _) => children(value).for_each(|(i, child)| selected(i, child)),
+            (Selector::Slice(start, end, step), Json::Array(items)) => {
+                for i in slice(*start, *end, *step, items.len()) {
+                    selected(i, &items[i]);
+                }
+            },
+            (Selector::Filter(filter), _) => {
+                for (i, child) in children(value) {
+                    if holds(filter, child, root) {
+                        selected(i, child);
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
+
+// The indexes a slice picks from an array of `len`, as RFC 9535 has it
+fn slice(start: Option<i64>, end: Option<i64>, step: i64, len: usize) -> Vec<usize> {
+    let len = len as i64;
+    let normalize = |i: i64| if i < 0 { len + i } else { i };
+    let mut picked = vec![];
+    if step > 0 {
+        let (start, end) = (normalize(start.unwrap_or(0)).clamp(0, len), normalize(end.unwrap_or(len)).clamp(0, len));
+        let mut i = start;
+        while i < end {
+            picked.push(i as usize);
+            i += step;
+        }
+    } else {
+        let (start, end) = (normalize(start.unwrap_or(len - 1)).clamp(-1, len - 1), end.map_or(-1, |end| normalize(end).clamp(-1, len - 1)));
+        let mut i = start;
+        while i > end {
+            picked.push(i as usize);
+            i += step;
+        }
+    }
+    picked
+}
+
+fn holds(filter: &Filter, current: &Json, root: &Json) -> bool {
+    match filter {
+        Filter::Or(a, b) => holds(a, current, root) || holds(b, current, root),
+        Filter::And(a, b) => holds(a, current, root) && holds(b, current, root),
+        Filter::Not(filter) => !holds(filter, current, root),
+        Filter::Exists(operand) => resolve(operand, current, root).is_some(),
+        Filter::Compare(a, comparison, b) => compare(resolve(a, current, root), *comparison, resolve(b, current, root)),
+    }
+}
+
+fn resolve<'a>(operand: &'a Operand, current: &'a Json, root: &'a Json) -> Option<&'a Json> {
+    let (from, segments) = match operand {
+        Operand::Current(segments) => (current, segments),
+        Operand::Root(segments) => (root, segments),
+        Operand::Literal(value) => return Some(value),
+    };
+    let mut first = None;
+    walk(root, from, segments, &mut vec![], &mut |location, _| {
+        first.get_or_insert_with(|| location.to_vec());
+    });
+    first.map(|location| from.at(&location))
+}
+
+// Numbers compare by value whatever their representation, strings by code point; nothing is ordered
+// against a value of another type. Two missing values are equal.
+fn compare(a: Option<&Json>, comparison: Comparison, b: Option<&Json>) -> bool {
+    use std::cmp::Ordering;
+    let order = match (a, b) {
+        (None, None) => Some(Ordering::Equal),
+        (Some(Json::Int(a)), Some(Json::Int(b))) => Some(a.cmp(b)),
+        (Some(a), Some(b)) if a.as_f64().is_some() && b.as_f64().is_some() => a.as_f64().partial_cmp(&b.as_f64()),
+        (Some(Json::String(a)), Some(Json::String(b))) => Some(a.cmp(b)),
+        (Some(a), Some(b)) if a == b => Some(Ordering::Equal),
+        _ => None,
+    };
+    match comparison {
+        Comparison::Eq => order == Some(Ordering::Equal),
+        Comparison::Ne => order != Some(Ordering::Equal),
+        Comparison::Lt => order == Some(Ordering::Less),
+        Comparison::Le => matches!(order, Some(Ordering::Less | Ordering::Equal)),
+        Comparison::Gt => order == Some(Ordering::Greater),
+        Comparison::Ge => matches!(order, Some(Ordering::Greater | Ordering::Equal)),
+    }
+}
@@ -0,0 +1,318 @@
+// A Kafka producer speaking the broker protocol directly, enough for bridges (see bridge.rs) to append records
+// to one partition of a topic. It asks the broker it's given which broker leads the partition, then sends that
+// one Produce requests (version 3, the oldest brokers still accept, carrying record batches of format 2) and
+// waits for every in-sync replica to acknowledge them. Records aren't compressed.
+use anyhow::Result;
+use bytes::Bytes;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const PRODUCE: i16 = 0;
+const PRODUCE_VERSION: i16 = 3;
+const METADATA: i16 = 3;
+const METADATA_VERSION: i16 = 4;
+const CLIENT_ID: &str = "zenql";
+// Acknowledged by every in-sync replica
+const ACKS_ALL: i16 = -1;
+const PRODUCE_TIMEOUT_MS: i32 = 10_000;
+// Largest response read
+const MAX_RESPONSE: usize = 64 * 1024 * 1024;
+
+pub struct Record {
+    pub key: Option<Bytes>,
+    pub value: Bytes,
+    pub timestamp_ms: u64,
+}
+
+pub struct KafkaProducer {
+    stream: TcpStream,
+    topic: String,
+    partition: i32,
+    correlation_id: i32,
+}
+
+impl KafkaProducer {
+    // A producer for a partition, connected to its leader as `bootstrap` (host:port) knows it
+    pub async fn connect(bootstrap: &str, topic: &str, partition: i32) -> Result<KafkaProducer> {
+        let stream = TcpStream::connect(bootstrap).await?;
+        let mut producer = KafkaProducer { stream, topic: topic.to_string(), partition, correlation_id: 0 };
+        let leader = producer.leader().await?;
+        producer.stream = TcpStream::connect(leader).await?;
+        Ok(producer)
+    }
+
+    // Appends the records to the partition, in order
+    pub async fn produce(&mut self, records: &[Record]) -> Result<()> {
+        let batch = record_batch(records);
+        let mut body = Encoder::default();
+        body.i16(-1); // No transactional id
+        body.i16(ACKS_ALL);
+        body.i32(PRODUCE_TIMEOUT_MS);
+        body.i32(1);
+        body.string(&self.topic);
+        body.i32(1);
+        body.i32(self.partition);
+        body.i32(batch.len() as i32);
+        body.raw(&batch);
+        let response = self.call(PRODUCE, PRODUCE_VERSION, body).await?;
+        let mut response = Decoder { data: &response };
+        for _ in 0..response.count()? {
+            response.string()?;
+            for _ in 0..response.count()? {
+                response.i32()?;
+                let error = response.i16()?;
+                response.i64()?; // Base offset
+                response.i64()?; // Log append time
+                check(error)?;
+            }
+        }
+        Ok(())
+    }
+
+    // host:port of the broker leading the partition
+    async fn leader(&mut self) -> Result<String> {
+        let mut body = Encoder::default();
+        body.i32(1);
+        body.string(&self.topic);
+        body.bool(true); // Let the broker create the topic, if it's configured to
+        let response = self.call(METADATA, METADATA_VERSION, body).await?;
+        let mut response = Decoder { data: &response };
+        response.i32()?; // Throttle time
+        let mut brokers = vec![];
+        for _ in 0..response.count()? {
+            let id = response.i32()?;
+            let host = response.string()?;
+            let port = response.i32()?;
+            response.nullable_string()?; // Rack
+            brokers.push((id, format!("{}:{}", host, port)));
+        }
+        response.nullable_string()?; // Cluster id
+        response.i32()?; // Controller id
+        let mut leader = None;
+        for _ in 0..response.count()? {
+            let topic_error = response.i16()?;
+            let name = response.string()?;
+            response.bool()?; // Internal
+            for _ in 0..response.count()? {
+                let error = response.i16()?;
+                let index = response.i32()?;
+                let leader_id = response.i32()?;
+                for _ in 0..2 {
+                    // Replicas, then in-sync replicas
+                    for _ in 0..response.count()? {
+                        response.i32()?;
+                    }
+                }
+                if name == self.topic && index == self.partition {
+                    check(topic_error)?;
+                    check(error)?;
+                    leader = Some(leader_id);
+                }
+            }
+        }
+        let leader = leader.ok_or_else(|| anyhow::anyhow!("Kafka topic {} has no partition {}", self.topic, self.partition))?;
+        brokers.into_iter().find(|(id, _)| *id == leader).map(|(_, address)| address)
+            .ok_or_else(|| anyhow::anyhow!("Partition {} of Kafka topic {} has no leader", self.partition, self.topic))
+    }
+
+    // Sends a request and returns the body of its response
+    async fn call(&mut self, api_key: i16, api_version: i16, body: Encoder) -> Result<Vec<u8>> {
+        self.correlation_id = self.correlation_id.wrapping_add(1);
+        let mut request = Encoder::default();
+        request.i32(0); // Size, filled in below
+        request.i16(api_key);
+        request.i16(api_version);
+        request.i32(self.correlation_id);
+        request.string(CLIENT_ID);
+        request.raw(&body.0);
+        let size = (request.0.len() - 4) as i32;
+        request.0[..4].copy_from_slice(&size.to_be_bytes());
+        self.stream.write_all(&request.0).await?;
+
+        let size = self.stream.read_i32().await?;
+        let size = usize::try_from(size).ok().filter(|size| (4..=MAX_RESPONSE).contains(size))
+            .ok_or_else(|| anyhow::anyhow!("Malformed Kafka response"))?;
+        let mut response = vec![0; size];
+        self.stream.read_exact(&mut response).await?;
+        if response[..4] != self.correlation_id.to_be_bytes() {
+            return Err(anyhow::anyhow!("Kafka response to another request"));
+        }
+        response.drain(..4);
+        Ok(response)
+    }
+}
+
+fn check(error: i16) -> Result<()> {
+    let name = match error {
+        0 => return Ok(()),
+        3 => "UNKNOWN_TOPIC_OR_PARTITION",
+        5 => "LEADER_NOT_AVAILABLE",
+        6 => "NOT_LEADER_OR_FOLLOWER",
+        7 => "REQUEST_TIMED_OUT",
+        10 => "MESSAGE_TOO_LARGE",
+        19 => "NOT_ENOUGH_REPLICAS",
+        29 => "TOPIC_AUTHORIZATION_FAILED",
+        _ => "",
+    };
+    Err(anyhow::anyhow!("Kafka error {} {}", error, name))
+}
+
+// A record batch of format 2: a header, CRC-32C checked from the attributes on, then the records, whose
+// timestamps and offsets are deltas from the batch's
+pub fn record_batch(records: &[Record]) -> Vec<u8> {
+    let base_timestamp = records.first().map_or(0, |record| record.timestamp_ms) as i64;
+    let max_timestamp = records.iter().map(|record| record.timestamp_ms as i64).max().unwrap_or(base_timestamp);
+    let mut checked = Encoder::default();
+    checked.i16(0); // Attributes: no compression, create time
+    checked.i32(records.len() as i32 - 1); // Last offset delta
+    checked.i64(base_timestamp);
+    checked.i64(max_timestamp);
+    checked.i64(-1); // No producer id,
+    checked.i16(-1); // epoch
+    checked.i32(-1); // or sequence: not idempotent
+    checked.i32(records.len() as i32);
+    for (offset, record) in records.iter().enumerate() {
+        let mut body = Encoder::default();
+        body.i8(0); // Attributes
+        body.varint(record.timestamp_ms as i64 - base_timestamp);
+        body.varint(offset as i64);
+        match &record.key {
+            Some(key) => {
+                body.varint(key.len() as i64);
+                body.raw(key);
+            },
+            None => body.varint(-1),
+        }
+        body.varint(record.value.len() as i64);
+        body.raw(&record.value);
+        body.varint(0); // Headers
+        checked.varint(body.0.len() as i64);
+        checked.raw(&body.0);
+    }
+    let mut batch = Encoder::default();
+    batch.i64(0); // Base offset, assigned by the broker
+    batch.i32((4 + 1 + 4 + checked.0.len()) as i32); // Length of the rest
+    batch.i32(-1); // Partition leader epoch
+    batch.i8(2); // Magic
+    batch.raw(&crc32c(&checked.0).to_be_bytes());
+    batch.raw(&checked.0);
+    batch.0
+}
+
+#[derive(Default)]
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn i8(&mut self, value: i8) {
+        self.0.push(value as u8);
+    }
+
+    fn i16(&mut self, value: i16) {
+        self.0.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn i32(&mut self, value: i32) {
+        self.0.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn i64(&mut self, value: i64) {
+        self.0.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.0.push(value as u8);
+    }
+
+    fn string(&mut self, value: &str) {
+        self.i16(value.len() as i16);
+        self.0.extend_from_slice(value.as_bytes());
+    }
+
+    fn raw(&mut self, value: &[u8]) {
+        self.0.extend_from_slice(value);
+    }
+
+    // Zigzag encoded, so small negative numbers stay short
+    fn varint(&mut self, value: i64) {
+        let mut value = ((value << 1) ^ (value >> 63)) as u64;
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl Decoder<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        if self.data.len() < N {
+            return Err(anyhow::anyhow!("Truncated Kafka response"));
+        }
+        let (taken, rest) = self.data.split_at(N);
+        self.data = rest;
+        Ok(taken.try_into().unwrap())
+    }
+
+    fn bool(&mut self) -> Result<bool> {
+        Ok(self.take::<1>()?[0] != 0)
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        Ok(i16::from_be_bytes(self.take()?))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_be_bytes(self.take()?))
+    }
+
+    fn i64(&mut self) -> Result<i64> {
+        Ok(i64::from_be_bytes(self.take()?))
+    }
+
+    // An array's length, a null array counting as empty
+    fn count(&mut self) -> Result<usize> {
+        Ok(self.i32()?.max(0) as usize)
+    }
+
+    fn nullable_string(&mut self) -> Result<Option<String>> {
+        let len = self.i16()?;
+        if len < 0 {
+            return Ok(None);
+        }
+        let len = len as usize;
+        if self.data.len() < len {
+            return Err(anyhow::anyhow!("Truncated Kafka response"));
+        }
+        let (string, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(Some(String::from_utf8_lossy(string).into_owned()))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        Ok(self.nullable_string()?.unwrap_or_default())
+    }
+}
+
+// CRC-32C (Castagnoli), as record batches are checked with
+pub fn crc32c(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 == 1 { (crc >> 1) ^ 0x82F6_3B78 } else { crc >> 1 };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    !data.iter().fold(!0u32, |crc, &byte| TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8))
+}
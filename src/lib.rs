@@ -0,0 +1,74 @@
+// The server as a library: main.rs runs it, tests and embedders start it with a Config. The crate depends on
+// little beyond tokio, so much of what other servers take from crates is written out here, among it: the LZ4
+// and CRC-64 codecs, base64, the JSON parser, FxHash, HNSW, HTTP/2 with HPACK, protobuf, the Kafka and NATS
+// clients, ChaCha20 for encryption at rest, SHA-1 for the WebSocket handshake, SHA-256 and HMAC for signing
+// S3 requests, and the allocation counting that stands in for jemalloc's statistics.
+pub mod admin;
+pub mod allocator;
+pub mod audit;
+pub mod backend;
+pub mod bloom;
+pub mod bridge;
+pub mod bucket;
+pub mod cdc;
+pub mod client;
+pub mod clients;
+pub mod clock;
+pub mod codec;
+pub mod commands;
+pub mod config;
+pub mod crc64;
+pub mod crypt;
+pub mod cuckoo;
+pub mod connection;
+pub mod countmin;
+pub mod daemon;
+pub mod disk;
+pub mod engine;
+pub mod error;
+pub mod events;
+pub mod executor;
+pub mod glob;
+pub mod graph;
+pub mod grpc;
+pub mod hasher;
+pub mod hpack;
+pub mod http;
+pub mod index;
+pub mod json;
+pub mod kafka;
+pub mod listener;
+mod locks;
+mod log;
+pub mod lz4;
+pub mod memcached;
+pub mod metrics;
+pub mod nats;
+pub mod protobuf;
+pub mod pubsub;
+pub mod ratelimit;
+pub mod record;
+pub mod resp;
+pub mod runtime;
+pub mod search;
+pub mod server;
+pub mod tdigest;
+pub mod session;
+pub mod shared;
+pub mod snapshot;
+pub mod stats;
+pub mod storage;
+pub mod tenant;
+pub mod tier;
+pub mod timeseries;
+pub mod topk;
+pub mod trace;
+pub mod vector;
+pub mod wal;
+pub mod webhook;
+pub mod websocket;
+pub mod zql;
+
+pub use config::Config;
+pub use engine::{Engine, SharedEngine};
+pub use server::Server;
@@ -0,0 +1,139 @@
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpSocket, TcpStream, UnixListener};
+use anyhow::Result;
+use crate::clients::format_addr;
+use crate::config::Config;
+use crate::log::log_warn;
+
+// Pending connections the kernel queues per listening socket, as tokio's TcpListener::bind uses
+const LISTEN_BACKLOG: u32 = 1024;
+
+// A client connection of any transport
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+// A socket clients connect to. There's no TLS listener: the ciphers and hashes written out in this crate
+// each do one narrow job whose limits are spelled out where they're used, while TLS is a whole protocol of
+// handshakes, certificate validation and authenticated records, whose mistakes go unnoticed until they're
+// exploited. It waits for a TLS library; until then, terminate TLS in a proxy in front of the server.
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf), // Socket file, removed when the server stops
+}
+
+// A newly accepted client with its peer and local addresses in CLIENT LIST form
+pub struct Accepted {
+    pub stream: Box<dyn Stream>,
+    pub addr: String,
+    pub laddr: String,
+    pub local: bool, // Loopback or unix socket client
+}
+
+impl Listener {
+    pub async fn accept(&self, keepalive: bool) -> io::Result<Accepted> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                let stream = if keepalive { enable_keepalive(stream)? } else { stream };
+                let laddr = stream.local_addr().map(format_addr).unwrap_or_default();
+                let local = addr.ip().to_canonical().is_loopback();
+                Ok(Accepted { stream: Box::new(stream), addr: format_addr(addr), laddr, local })
+            },
+            Listener::Unix(listener, path) => {
+                let (stream, _) = listener.accept().await?;
+                // Unix clients are unnamed, Redis reports both ends as the socket path
+                let addr = format!("{}:0", path.display());
+                Ok(Accepted { stream: Box::new(stream), addr: addr.clone(), laddr: addr, local: true })
+            },
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<String> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(format_addr),
+            Listener::Unix(_, path) => Ok(path.display().to_string()),
+        }
+    }
+}
+
+// Sets SO_KEEPALIVE through a duplicate of the socket, tokio's TcpStream has no setter for it
+fn enable_keepalive(stream: TcpStream) -> io::Result<TcpStream> {
+    let stream = stream.into_std()?;
+    TcpSocket::from_std_stream(stream.try_clone()?).set_keepalive(true)?;
+    TcpStream::from_std(stream)
+}
+
+impl From<TcpListener> for Listener {
+    fn from(listener: TcpListener) -> Self {
+        Listener::Tcp(listener)
+    }
+}
+
+// Opens the configured listeners: every address in `bind` on `port` (none when port is 0) and the
+// unixsocket. Addresses prefixed with '-' are optional: failing to bind them is only logged, e.g. '-::1' on
+// a host without IPv6.
+pub async fn bind(config: &Config) -> Result<Vec<Listener>> {
+    let mut listeners = vec![];
+    for address in config.bind.iter().filter(|_| config.port != 0) {
+        let (address, optional) = match address.strip_prefix('-') {
+            Some(address) => (address, true),
+            None => (address.as_str(), false),
+        };
+        let bound = match config.reuseport_acceptors {
+            1 => TcpListener::bind((address, config.port)).await.map(|listener| vec![listener]),
+            acceptors => bind_reuseport(address, config.port, acceptors).await,
+        };
+        match bound {
+            Ok(bound) => listeners.extend(bound.into_iter().map(Listener::Tcp)),
+            Err(e) if optional => log_warn!("Skipping optional bind address {} port {}: {}", address, config.port, e),
+            Err(e) => return Err(anyhow::anyhow!("Could not listen on {} port {}: {}", address, config.port, e)),
+        }
+    }
+    if let Some(path) = &config.unixsocket {
+        listeners.push(bind_unix(path.clone(), config.unixsocketperm)?);
+    }
+    if listeners.is_empty() {
+        return Err(anyhow::anyhow!("Nothing to listen on: configure a bind address with a non-zero port, or a unixsocket"));
+    }
+    Ok(listeners)
+}
+
+// `acceptors` sockets sharing one address through SO_REUSEPORT; the kernel spreads new connections over
+// them, and each gets its own accept task
+async fn bind_reuseport(address: &str, port: u16, acceptors: usize) -> io::Result<Vec<TcpListener>> {
+    let mut last_error = None;
+    for addr in tokio::net::lookup_host((address, port)).await? {
+        let bound = (0..acceptors)
+            .map(|_| {
+                let socket = if addr.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+                socket.set_reuseaddr(true)?;
+                socket.set_reuseport(true)?;
+                socket.bind(addr)?;
+                socket.listen(LISTEN_BACKLOG)
+            })
+            .collect();
+        match bound {
+            Ok(listeners) => return Ok(listeners),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "address resolved to nothing")))
+}
+
+// A socket file left behind by a previous run is replaced
+fn bind_unix(path: PathBuf, perm: u32) -> Result<Listener> {
+    let error = |e: io::Error| anyhow::anyhow!("Could not listen on unix socket {}: {}", path.display(), e);
+    match std::fs::remove_file(&path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(error(e)),
+        _ => {},
+    }
+    let listener = UnixListener::bind(&path).map_err(error)?;
+    if perm != 0 {
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(perm)).map_err(error)?;
+    }
+    Ok(Listener::Unix(listener, path))
+}
@@ -0,0 +1,20 @@
+use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// Shared state (storage, stats, pubsub channels) sits behind std Mutexes that are only held while a command
+// runs synchronously, never across an .await: std guards are !Send, so a spawned connection task holding one
+// over IO doesn't compile. The critical sections are short and CPU-bound, which std::sync::Mutex handles
+// better than tokio's async Mutex; that one only pays off when a guard has to live across IO.
+//
+// A panic while a lock is held poisons it. State is only ever changed a whole command at a time, so the
+// guard is recovered instead of turning the panic into one for every other connection.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(|e| e.into_inner())
+}
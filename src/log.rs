@@ -0,0 +1,224 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug = 0,
+    Info = 1,
+    Warn = 2,
+    Error = 3,
+}
+
+impl Level {
+    pub fn parse(name: &str) -> Result<Level> {
+        match name.to_lowercase().as_str() {
+            "debug" => Ok(Level::Debug),
+            "info" | "notice" => Ok(Level::Info),
+            "warn" | "warning" => Ok(Level::Warn),
+            "error" => Ok(Level::Error),
+            _ => Err(anyhow::anyhow!("Invalid log level '{}'", name)),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Plain,
+    Json,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Result<Format> {
+        match name.to_lowercase().as_str() {
+            "plain" => Ok(Format::Plain),
+            "json" => Ok(Format::Json),
+            _ => Err(anyhow::anyhow!("Invalid log format '{}'", name)),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Format::Plain => "plain",
+            Format::Json => "json",
+        }
+    }
+}
+
+// Logfile settings; rotation is disabled when the corresponding limit is 0
+#[derive(Debug, Clone)]
+pub struct FileOptions {
+    pub path: String,
+    pub max_size: u64,
+    pub rotate_interval: Duration,
+    pub max_files: usize,
+}
+
+// A logfile that rotates itself, also used for the audit log
+pub(crate) struct LogFile {
+    options: FileOptions,
+    file: File,
+    size: u64,
+    opened: SystemTime,
+}
+
+struct Sink {
+    format: Format,
+    file: Option<LogFile>, // None logs to stdout
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static SINK: Mutex<Sink> = Mutex::new(Sink { format: Format::Plain, file: None });
+
+pub fn init(level: Level, format: Format, file: Option<FileOptions>) -> Result<()> {
+    set_level(level);
+    let file = match file {
+        Some(options) => Some(LogFile::open(options)?),
+        None => None,
+    };
+    let mut sink = SINK.lock().unwrap_or_else(|e| e.into_inner());
+    sink.format = format;
+    sink.file = file;
+    Ok(())
+}
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 >= LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn log(level: Level, args: std::fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    let now = SystemTime::now();
+    let mut sink = SINK.lock().unwrap_or_else(|e| e.into_inner());
+    let line = match sink.format {
+        Format::Plain => format!("{} {} {}\n", timestamp(now), level.as_str().to_uppercase(), args),
+        Format::Json => format!(
+            "{{\"ts\":\"{}\",\"level\":\"{}\",\"msg\":\"{}\"}}\n",
+            timestamp(now), level.as_str(), escape_json(&args.to_string())
+        ),
+    };
+    match sink.file.as_mut() {
+        Some(file) => {
+            if let Err(e) = file.write(&line, now) {
+                eprint!("{}", line);
+                eprintln!("Failed to write logfile: {:?}", e);
+            }
+        },
+        None => print!("{}", line),
+    }
+}
+
+impl LogFile {
+    pub(crate) fn open(options: FileOptions) -> Result<LogFile> {
+        let file = OpenOptions::new().create(true).append(true).open(&options.path)?;
+        let size = file.metadata()?.len();
+        Ok(LogFile { options, file, size, opened: SystemTime::now() })
+    }
+
+    pub(crate) fn write(&mut self, line: &str, now: SystemTime) -> Result<()> {
+        if self.should_rotate(line.len() as u64, now) {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn should_rotate(&self, incoming: u64, now: SystemTime) -> bool {
+        let too_big = self.options.max_size > 0 && self.size > 0 && self.size + incoming > self.options.max_size;
+        let too_old = !self.options.rotate_interval.is_zero()
+            && now.duration_since(self.opened).unwrap_or_default() >= self.options.rotate_interval;
+        too_big || too_old
+    }
+
+    // Shifts path.N-1 -> path.N ... path -> path.1, dropping anything beyond max_files
+    fn rotate(&mut self) -> Result<()> {
+        let path = &self.options.path;
+        let keep = self.options.max_files.max(1);
+        let _ = fs::remove_file(format!("{}.{}", path, keep));
+        for n in (1..keep).rev() {
+            let _ = fs::rename(format!("{}.{}", path, n), format!("{}.{}", path, n + 1));
+        }
+        fs::rename(path, format!("{}.1", path))?;
+        *self = LogFile::open(self.options.clone())?;
+        Ok(())
+    }
+}
+
+// RFC 3339 UTC timestamp with millisecond precision
+pub(crate) fn timestamp(now: SystemTime) -> String {
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let rem = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, rem / 3600, (rem % 3600) / 60, rem % 60, since_epoch.subsec_millis()
+    )
+}
+
+// Howard Hinnant's days-to-civil conversion
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn escape_json(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Debug, format_args!($($arg)*)) };
+}
+
+macro_rules! log_info {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Info, format_args!($($arg)*)) };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Warn, format_args!($($arg)*)) };
+}
+
+macro_rules! log_error {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Error, format_args!($($arg)*)) };
+}
+
+pub(crate) use {log_debug, log_error, log_info, log_warn};
@@ -0,0 +1,115 @@
+// The LZ4 block format, which storage keeps large values compressed in and the record format writes them out
+// in. Blocks are interchangeable with the reference implementation's LZ4_compress_default /
+// LZ4_decompress_safe; there's no frame format, the caller keeps the uncompressed length.
+//
+// A block is a series of sequences: a token (literal count in the high nibble, match length - 4 in the low
+// one, 15 meaning more length bytes follow), the literals, then a little-endian u16 offset back into the
+// output to copy the match from. The last sequence has literals only.
+
+const MIN_MATCH: usize = 4;
+// The last 5 bytes are always literals, and no match starts within the last 12
+const LAST_LITERALS: usize = 5;
+const MF_LIMIT: usize = 12;
+const MAX_OFFSET: usize = 65_535;
+const HASH_LOG: u32 = 12;
+
+// Greedy single-pass compression: each 4-byte sequence is looked up in a hash table of the last position it
+// was seen at
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2 + 16);
+    let mut table = vec![0u32; 1 << HASH_LOG]; // Position + 1, 0 for none
+    let (mut anchor, mut pos) = (0, 0);
+    while pos + MF_LIMIT < input.len() {
+        let sequence = read_u32(input, pos);
+        let slot = (sequence.wrapping_mul(2_654_435_761) >> (32 - HASH_LOG)) as usize;
+        let candidate = table[slot] as usize;
+        table[slot] = pos as u32 + 1;
+        if candidate == 0 || pos - (candidate - 1) > MAX_OFFSET || read_u32(input, candidate - 1) != sequence {
+            pos += 1;
+            continue;
+        }
+        let candidate = candidate - 1;
+        let max_len = input.len() - LAST_LITERALS - pos;
+        let mut len = MIN_MATCH;
+        while len < max_len && input[candidate + len] == input[pos + len] {
+            len += 1;
+        }
+        write_sequence(&mut out, &input[anchor..pos], Some((pos - candidate, len)));
+        pos += len;
+        anchor = pos;
+    }
+    write_sequence(&mut out, &input[anchor..], None);
+    out
+}
+
+// None unless `input` is a well-formed block decompressing to exactly `len` bytes
+pub fn decompress(input: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(len);
+    let mut pos = 0;
+    loop {
+        let token = *input.get(pos)?;
+        pos += 1;
+        let literals = read_len(input, &mut pos, (token >> 4) as usize)?;
+        out.extend_from_slice(input.get(pos..pos.checked_add(literals)?)?);
+        pos += literals;
+        if pos == input.len() {
+            break;
+        }
+
+        let offset = u16::from_le_bytes([*input.get(pos)?, *input.get(pos + 1)?]) as usize;
+        pos += 2;
+        let match_len = read_len(input, &mut pos, (token & 0xf) as usize)? + MIN_MATCH;
+        if offset == 0 || offset > out.len() || out.len() + match_len > len {
+            return None;
+        }
+        // The match may overlap what it's copying, e.g. offset 1 repeats the last byte
+        let start = out.len() - offset;
+        for i in 0..match_len {
+            out.push(out[start + i]);
+        }
+    }
+    (out.len() == len).then_some(out)
+}
+
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let match_nibble = matched.map_or(0, |(_, len)| (len - MIN_MATCH).min(15));
+    out.push(((literals.len().min(15) as u8) << 4) | match_nibble as u8);
+    if literals.len() >= 15 {
+        write_len(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+    if let Some((offset, len)) = matched {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if len - MIN_MATCH >= 15 {
+            write_len(out, len - MIN_MATCH - 15);
+        }
+    }
+}
+
+fn write_len(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+// A length whose token nibble is `nibble`, followed by extra bytes when it's 15
+fn read_len(input: &[u8], pos: &mut usize, nibble: usize) -> Option<usize> {
+    let mut len = nibble;
+    if nibble == 15 {
+        loop {
+            let byte = *input.get(*pos)?;
+            *pos += 1;
+            len = len.checked_add(byte as usize)?;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Some(len)
+}
+
+fn read_u32(input: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([input[pos], input[pos + 1], input[pos + 2], input[pos + 3]])
+}
@@ -0,0 +1,408 @@
+// Memcached listener: the memcached text protocol over the keyspace, so applications with memcached clients
+// can move to zenql without code changes. It listens on memcached-port of every bind address and knows what
+// those clients send: get, gets, gat, gats, set, add, replace, append, prepend, cas, delete, incr, decr,
+// touch, version, verbosity, stats and quit.
+//
+// Items are string keys, the same ones RESP clients see. Keys holding anything else read as missing, and
+// writing them is a CLIENT_ERROR short of delete. The flags memcached keeps with each item, which clients use
+// to record how they serialized it, are remembered beside the keyspace while the server runs; a write from
+// outside memcached resets them to 0. CAS uniques are the keys' versions, the ones WATCH uses, so any write
+// makes a cas fail. Exptimes are memcached's: seconds up to 30 days, a UNIX time past that, and already
+// expired when negative. Replies go out once the writes before them are durable, as on RESP connections.
+//
+// With requirepass set, a connection authenticates as memcached's ASCII authentication has it, with a set of
+// any key whose data is `<user> <password>`, the user being default, or just the password. Without it,
+// protected mode turns other hosts away.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use anyhow::Result;
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use crate::connection::{self, PROTECTED_MODE};
+use crate::http;
+use crate::locks;
+use crate::log::log_warn;
+use crate::server::Server;
+use crate::stats::{self, SERVER_STATS};
+use crate::storage::{ReadError, Storage, StringEntry};
+
+// Longest key memcached allows
+const MAX_KEY: usize = 250;
+// Longest command line accepted, enough for a get of a few hundred keys
+const MAX_LINE: usize = 64 * 1024;
+// Exptimes up to this many seconds are relative, longer ones UNIX times
+const MAX_RELATIVE_EXPTIME: i64 = 60 * 60 * 24 * 30;
+
+const BAD_FORMAT: &str = "CLIENT_ERROR bad command line format";
+
+pub async fn serve(server: Server, port: u16) -> Result<()> {
+    let flags = Arc::new(Mutex::new(ItemFlags::default()));
+    http::listen(&server.clone(), port, "memcached", move |stream, peer, loopback| {
+        handle(stream, peer, loopback, server.clone(), Arc::clone(&flags))
+    }).await
+}
+
+// Items' flags by key, each with the version of the key it was set for and only good while the key is still
+// at it. Entries for keys written since are pruned as the table doubles.
+#[derive(Default)]
+struct ItemFlags {
+    items: HashMap<Bytes, (u64, u32)>,
+    pruned_len: usize,
+}
+
+impl ItemFlags {
+    fn get(&self, key: &[u8], version: u64) -> u32 {
+        match self.items.get(key) {
+            Some(&(set_for, flags)) if set_for == version => flags,
+            _ => 0,
+        }
+    }
+
+    fn set(&mut self, key: Bytes, version: u64, flags: u32, storage: &Storage) {
+        if flags == 0 {
+            self.items.remove(&key);
+            return;
+        }
+        self.items.insert(key, (version, flags));
+        if self.items.len() > 2 * self.pruned_len.max(1024) {
+            self.items.retain(|key, &mut (version, _)| storage.version(key) == version);
+            self.pruned_len = self.items.len();
+        }
+    }
+}
+
+async fn handle(mut stream: TcpStream, peer: String, loopback: bool, server: Server, flags: Arc<Mutex<ItemFlags>>) -> Result<()> {
+    let config = server.config();
+    if config.protected() && !loopback {
+        log_warn!("Refused memcached connection from {}: protected mode is on and no password is set", peer);
+        stream.write_all(format!("SERVER_ERROR {}\r\n", PROTECTED_MODE).as_bytes()).await?;
+        return Ok(());
+    }
+    stats::incr(&SERVER_STATS.total_connections_received, 1);
+    stats::incr(&SERVER_STATS.connected_clients, 1);
+    let mut client = Client { server, flags, authenticated: config.requirepass.is_none(), out: vec![] };
+    let result = client.serve(&mut stream, &peer).await;
+    stats::decr(&SERVER_STATS.connected_clients, 1);
+    result
+}
+
+struct Client {
+    server: Server,
+    flags: Arc<Mutex<ItemFlags>>,
+    authenticated: bool,
+    out: Vec<u8>, // Replies waiting for the writes before them to be durable
+}
+
+// A command line split into words, and the data block that follows a storage command's
+struct Request {
+    words: Vec<Bytes>,
+    data: Option<Bytes>,
+}
+
+impl Client {
+    // Runs the commands in each read together and replies to them at once, until the client quits, closes
+    // the connection or idles past timeout
+    async fn serve(&mut self, stream: &mut TcpStream, peer: &str) -> Result<()> {
+        let mut buf = BytesMut::with_capacity(4096);
+        loop {
+            let mut open = true;
+            while open {
+                match parse(&mut buf, self.server.config().proto_max_bulk_len) {
+                    Ok(Some(request)) => open = self.run(request).await,
+                    Ok(None) => break,
+                    // The stream can't be followed past a malformed command
+                    Err(error) => {
+                        self.reply(error);
+                        open = false;
+                    },
+                }
+            }
+            if !connection::sync_writes(&self.server, peer).await {
+                return Ok(());
+            }
+            stream.write_all(&self.out).await?;
+            self.out.clear();
+            if !open || !http::fill(stream, &mut buf, self.server.config().timeout).await {
+                return Ok(());
+            }
+        }
+    }
+
+    fn reply(&mut self, line: &str) {
+        self.out.extend_from_slice(line.as_bytes());
+        self.out.extend_from_slice(b"\r\n");
+    }
+
+    // Runs a command, false when the client quit
+    async fn run(&mut self, request: Request) -> bool {
+        let Request { words, data } = request;
+        let name = String::from_utf8_lossy(&words[0]).to_ascii_lowercase();
+        if !self.authenticated {
+            match (name.as_str(), data) {
+                ("quit", _) => return false,
+                ("set", Some(data)) => self.authenticate(&data),
+                _ => self.reply("CLIENT_ERROR unauthenticated"),
+            }
+            return true;
+        }
+        stats::incr(&SERVER_STATS.total_commands_processed, 1);
+        let args = &words[1..];
+        // Storage commands, and those with a key and an argument or two, may end in noreply
+        let noreply = !matches!(name.as_str(), "get" | "gets" | "gat" | "gats") && args.last().is_some_and(|word| &word[..] == b"noreply");
+        let args = if noreply { &args[..args.len() - 1] } else { args };
+        let reply = match (name.as_str(), data) {
+            ("get" | "gets", _) if !args.is_empty() => self.get(args, name == "gets", None).await,
+            ("gat" | "gats", _) if args.len() >= 2 => match parse_number(&args[0]) {
+                Some(exptime) => self.get(&args[1..], name == "gats", Some(self.deadline(exptime))).await,
+                None => BAD_FORMAT.to_string(),
+            },
+            (command @ ("set" | "add" | "replace" | "append" | "prepend" | "cas"), Some(data)) => self.store(command, args, data).await,
+            ("delete", _) if matches!(args, [_] | [_, _]) => {
+                if args.get(1).is_some_and(|time| &time[..] != b"0") {
+                    "CLIENT_ERROR bad command line format.  Usage: delete <key> [noreply]".to_string()
+                } else {
+                    let key = args[0].clone();
+                    let deleted = self.storage(move |storage| storage.del(&key)).await;
+                    if deleted { "DELETED" } else { "NOT_FOUND" }.to_string()
+                }
+            },
+            ("incr" | "decr", _) if args.len() == 2 => match parse_number::<u64>(&args[1]) {
+                Some(delta) => self.incr(args[0].clone(), delta, name == "incr").await,
+                None => "CLIENT_ERROR invalid numeric delta argument".to_string(),
+            },
+            ("touch", _) if args.len() == 2 => match parse_number(&args[1]) {
+                Some(exptime) => {
+                    let deadline = self.deadline(exptime);
+                    match self.touch(args[0].clone(), deadline).await {
+                        Ok(Some(_)) => "TOUCHED".to_string(),
+                        Ok(None) => "NOT_FOUND".to_string(),
+                        Err(e) => read_error(e),
+                    }
+                },
+                None => BAD_FORMAT.to_string(),
+            },
+            ("version", _) => format!("VERSION {}", env!("CARGO_PKG_VERSION")),
+            ("verbosity", _) => "OK".to_string(),
+            ("stats", _) if args.is_empty() => {
+                let items = self.server.storage.len();
+                format!("STAT pid {}\r\nSTAT version {}\r\nSTAT curr_items {}\r\nEND", std::process::id(), env!("CARGO_PKG_VERSION"), items)
+            },
+            ("quit", _) => return false,
+            ("get" | "gets" | "gat" | "gats" | "set" | "add" | "replace" | "append" | "prepend" | "cas" | "delete" | "incr" | "decr" | "touch" | "stats", _) => BAD_FORMAT.to_string(),
+            _ => "ERROR".to_string(),
+        };
+        if !noreply {
+            self.reply(&reply);
+        }
+        true
+    }
+
+    fn authenticate(&mut self, data: &[u8]) {
+        let password = self.server.config().requirepass.clone().unwrap_or_default();
+        let given = match data.iter().position(|&c| c == b' ') {
+            Some(space) if &data[..space] == b"default" => &data[space + 1..],
+            _ => data,
+        };
+        self.authenticated = given == password.as_bytes();
+        self.reply(if self.authenticated { "STORED" } else { "CLIENT_ERROR authentication failure" });
+    }
+
+    // Runs a storage operation as commands are run: on the executor when there is one, and never alongside
+    // an exclusive command
+    async fn storage<T: Send + 'static>(&self, operation: impl FnOnce(&Storage) -> T + Send + 'static) -> T {
+        let storage = Arc::clone(&self.server.storage);
+        let run = move || {
+            let _shared = storage.shared();
+            operation(&storage)
+        };
+        match &self.server.executor {
+            Some(executor) => executor.run(run).await,
+            None => run(),
+        }
+    }
+
+    // An exptime as a deadline in UNIX milliseconds
+    fn deadline(&self, exptime: i64) -> Option<u64> {
+        let now = self.server.storage.now_ms();
+        match exptime {
+            0 => None,
+            // Already past, so the item is gone before anyone can read it
+            ..0 => Some(now.saturating_sub(1)),
+            1..=MAX_RELATIVE_EXPTIME => Some(now + exptime as u64 * 1000),
+            _ => Some((exptime as u64).saturating_mul(1000)),
+        }
+    }
+
+    // VALUE lines for the keys there are, with their CAS uniques for gets, touching each when `touch` gives
+    // a deadline
+    async fn get(&mut self, keys: &[Bytes], with_cas: bool, touch: Option<Option<u64>>) -> String {
+        for key in keys {
+            let found = match touch {
+                Some(deadline) => self.touch(key.clone(), deadline).await,
+                None => {
+                    let key = key.clone();
+                    self.storage(move |storage| storage.get_versioned(&key)).await
+                },
+            };
+            let Ok(Some((value, version))) = found else {
+                continue;
+            };
+            let flags = locks::lock(&self.flags).get(key, version);
+            self.out.extend_from_slice(b"VALUE ");
+            self.out.extend_from_slice(key);
+            let cas = if with_cas { format!(" {}", version) } else { String::new() };
+            self.out.extend_from_slice(format!(" {} {}{}\r\n", flags, value.len(), cas).as_bytes());
+            self.out.extend_from_slice(&value);
+            self.out.extend_from_slice(b"\r\n");
+        }
+        "END".to_string()
+    }
+
+    // set, add, replace, append, prepend or cas, `args` from the key on
+    async fn store(&mut self, command: &str, args: &[Bytes], data: Bytes) -> String {
+        let (key, flags, exptime, unique) = match args {
+            [key, flags, exptime, _] if command != "cas" => (key, flags, exptime, None),
+            [key, flags, exptime, _, unique] if command == "cas" => (key, flags, exptime, Some(unique)),
+            _ => return BAD_FORMAT.to_string(),
+        };
+        let (Some(flags), Some(exptime)) = (parse_number::<u32>(flags), parse_number(exptime)) else {
+            return BAD_FORMAT.to_string();
+        };
+        let unique = match unique.map(|unique| parse_number::<u64>(unique)) {
+            Some(None) => return BAD_FORMAT.to_string(),
+            unique => unique.flatten(),
+        };
+        if key.len() > MAX_KEY {
+            return BAD_FORMAT.to_string();
+        }
+        let deadline = self.deadline(exptime);
+        let (command, key_clone) = (command.to_string(), key.clone());
+        // What to reply, and for append and prepend the version whose flags carry over
+        let result = self.storage(move |storage| {
+            storage.update_string(&key_clone, |entry| match (command.as_str(), entry) {
+                ("set", _) | ("add", None) => (("STORED", None), Some((data, deadline))),
+                ("replace", Some(_)) => (("STORED", None), Some((data, deadline))),
+                ("cas", Some(entry)) if Some(entry.version) == unique => (("STORED", None), Some((data, deadline))),
+                ("cas", Some(_)) => (("EXISTS", None), None),
+                ("cas", None) => (("NOT_FOUND", None), None),
+                ("append", Some(StringEntry { value, version, expires_at })) => {
+                    (("STORED", Some(version)), Some(([&value[..], &data].concat().into(), expires_at)))
+                },
+                ("prepend", Some(StringEntry { value, version, expires_at })) => {
+                    (("STORED", Some(version)), Some(([&data[..], &value].concat().into(), expires_at)))
+                },
+                _ => (("NOT_STORED", None), None),
+            })
+        }).await;
+        let ((reply, carried), version) = match result {
+            Ok(result) => result,
+            Err(e) => return read_error(e),
+        };
+        if reply == "STORED" {
+            let mut item_flags = locks::lock(&self.flags);
+            let flags = carried.map_or(flags, |previous| item_flags.get(key, previous));
+            item_flags.set(key.clone(), version, flags, &self.server.storage);
+        }
+        reply.to_string()
+    }
+
+    // incr and decr, which take the item as an unsigned 64-bit number: incr wraps around and decr stops at 0
+    async fn incr(&mut self, key: Bytes, delta: u64, increment: bool) -> String {
+        let key_clone = key.clone();
+        let result = self.storage(move |storage| {
+            storage.update_string(&key_clone, |entry| {
+                let Some(entry) = entry else {
+                    return (Err("NOT_FOUND"), None);
+                };
+                // Digits, perhaps with the spaces memcached pads a shrinking number with
+                let Some(current) = std::str::from_utf8(&entry.value).ok().and_then(|value| value.trim_end_matches(' ').parse::<u64>().ok()) else {
+                    return (Err("CLIENT_ERROR cannot increment or decrement non-numeric value"), None);
+                };
+                let value = if increment { current.wrapping_add(delta) } else { current.saturating_sub(delta) };
+                (Ok((value, entry.version)), Some((value.to_string().into(), entry.expires_at)))
+            })
+        }).await;
+        match result {
+            Ok((Ok((value, previous)), version)) => {
+                let mut item_flags = locks::lock(&self.flags);
+                let flags = item_flags.get(&key, previous);
+                item_flags.set(key, version, flags, &self.server.storage);
+                value.to_string()
+            },
+            Ok((Err(reply), _)) => reply.to_string(),
+            Err(e) => read_error(e),
+        }
+    }
+
+    // Gives a live string a new deadline, returning its value and new version; its flags carry over
+    async fn touch(&mut self, key: Bytes, deadline: Option<u64>) -> Result<Option<(Bytes, u64)>, ReadError> {
+        let key_clone = key.clone();
+        let (touched, version) = self.storage(move |storage| {
+            storage.update_string(&key_clone, |entry| match entry {
+                Some(StringEntry { value, version, .. }) => (Some((value.clone(), version)), Some((value, deadline))),
+                None => (None, None),
+            })
+        }).await?;
+        let Some((value, previous)) = touched else {
+            return Ok(None);
+        };
+        let mut item_flags = locks::lock(&self.flags);
+        let flags = item_flags.get(&key, previous);
+        item_flags.set(key, version, flags, &self.server.storage);
+        Ok(Some((value, version)))
+    }
+}
+
+// A wrong-typed key is the client's mistake, a value that couldn't be read back the server's
+fn read_error(e: ReadError) -> String {
+    match e {
+        ReadError::WrongType => format!("CLIENT_ERROR {}", crate::error::Error::WrongType),
+        ReadError::Unreadable => format!("SERVER_ERROR {}", crate::error::Error::from(e)),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(word: &[u8]) -> Option<T> {
+    std::str::from_utf8(word).ok()?.parse().ok()
+}
+
+// The next whole command in `buf`, None until there is one. Errors are the reply to a command the stream
+// can't be followed past.
+fn parse(buf: &mut BytesMut, max_data: usize) -> Result<Option<Request>, &'static str> {
+    let Some(end) = buf.iter().position(|&c| c == b'\n') else {
+        return match buf.len() > MAX_LINE {
+            true => Err("CLIENT_ERROR line too long"),
+            false => Ok(None),
+        };
+    };
+    let line = buf[..end].strip_suffix(b"\r").unwrap_or(&buf[..end]);
+    let words: Vec<&[u8]> = line.split(|&c| c == b' ').filter(|word| !word.is_empty()).collect();
+    // A storage command with a well-formed line has a data block; without one, it's answered with an error
+    // and the data read as the next command, as memcached does
+    let data_len = match words.first().map(|name| name.to_ascii_lowercase()).as_deref() {
+        Some(b"set" | b"add" | b"replace" | b"append" | b"prepend" | b"cas") => words.get(4).and_then(|len| parse_number::<usize>(len)),
+        _ => None,
+    };
+    if data_len.is_some_and(|len| len > max_data) {
+        return Err("SERVER_ERROR object too large for cache");
+    }
+    if data_len.is_some_and(|len| buf.len() < end + 1 + len + 2) {
+        return Ok(None);
+    }
+    let words: Vec<Bytes> = words.into_iter().map(Bytes::copy_from_slice).collect();
+    buf.advance(end + 1);
+    let data = match data_len {
+        Some(len) => {
+            let block = buf.split_to(len + 2);
+            if !block.ends_with(b"\r\n") {
+                return Err("CLIENT_ERROR bad data chunk");
+            }
+            Some(block.freeze().slice(..len))
+        },
+        None => None,
+    };
+    if words.is_empty() {
+        return Ok(Some(Request { words: vec![Bytes::new()], data }));
+    }
+    Ok(Some(Request { words, data }))
+}
@@ -0,0 +1,125 @@
+use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use anyhow::Result;
+use crate::locks;
+use crate::log::{log_info, log_warn};
+use crate::storage::Storage;
+use crate::stats::{Stats, LATENCY_BUCKETS_USEC, SERVER_STATS};
+
+// Minimal HTTP listener serving Prometheus text exposition on GET /metrics
+pub async fn serve(port: u16, storage: Arc<Storage>, stats: Arc<Mutex<Stats>>) -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    log_info!("Serving metrics on 127.0.0.1:{}/metrics", port);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let storage_clone = Arc::clone(&storage);
+        let stats_clone = Arc::clone(&stats);
+        tokio::spawn(async move {
+            if let Err(e) = handle_scrape(stream, storage_clone, stats_clone).await {
+                log_warn!("Error serving metrics: {:?}", e);
+            }
+        });
+    }
+}
+
+async fn handle_scrape(mut stream: TcpStream, storage: Arc<Storage>, stats: Arc<Mutex<Stats>>) -> Result<()> {
+    let mut buf = vec![0u8; 4096];
+    let n = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+
+    let response = if request.starts_with("GET ") && path == "/metrics" {
+        let body = render(&storage, &stats);
+        format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+fn render(storage: &Storage, stats: &Arc<Mutex<Stats>>) -> String {
+    let mut out = String::new();
+    let counters = [
+        ("zenql_connections_received_total", "counter", &SERVER_STATS.total_connections_received),
+        ("zenql_commands_processed_total", "counter", &SERVER_STATS.total_commands_processed),
+        ("zenql_net_input_bytes_total", "counter", &SERVER_STATS.total_net_input_bytes),
+        ("zenql_net_output_bytes_total", "counter", &SERVER_STATS.total_net_output_bytes),
+        ("zenql_expired_keys_total", "counter", &SERVER_STATS.expired_keys),
+        ("zenql_evicted_keys_total", "counter", &SERVER_STATS.evicted_keys),
+        ("zenql_keyspace_hits_total", "counter", &SERVER_STATS.keyspace_hits),
+        ("zenql_keyspace_misses_total", "counter", &SERVER_STATS.keyspace_misses),
+        ("zenql_webhook_events_delivered_total", "counter", &SERVER_STATS.webhook_events_delivered),
+        ("zenql_webhook_events_failed_total", "counter", &SERVER_STATS.webhook_events_failed),
+        ("zenql_webhook_events_dropped_total", "counter", &SERVER_STATS.webhook_events_dropped),
+        ("zenql_webhook_retries_total", "counter", &SERVER_STATS.webhook_retries),
+        ("zenql_bridge_messages_sent_total", "counter", &SERVER_STATS.bridge_messages_sent),
+        ("zenql_bridge_messages_received_total", "counter", &SERVER_STATS.bridge_messages_received),
+        ("zenql_bridge_messages_dropped_total", "counter", &SERVER_STATS.bridge_messages_dropped),
+        ("zenql_bridge_connection_failures_total", "counter", &SERVER_STATS.bridge_connection_failures),
+        ("zenql_cdc_frames_sent_total", "counter", &SERVER_STATS.cdc_frames_sent),
+        ("zenql_cdc_consumers_dropped_total", "counter", &SERVER_STATS.cdc_consumers_dropped),
+        ("zenql_connected_clients", "gauge", &SERVER_STATS.connected_clients),
+    ];
+    for (name, kind, counter) in counters {
+        let _ = write!(out, "# TYPE {} {}\n{} {}\n", name, kind, name, counter.load(Ordering::Relaxed));
+    }
+
+    let keys = storage.len();
+    let _ = write!(out, "# TYPE zenql_keys gauge\nzenql_keys {}\n", keys);
+    if let Some(rss) = resident_memory_bytes() {
+        let _ = write!(out, "# TYPE process_resident_memory_bytes gauge\nprocess_resident_memory_bytes {}\n", rss);
+    }
+
+    let stats_lock = locks::lock(stats);
+    let mut names: Vec<&String> = stats_lock.commands.keys().collect();
+    names.sort();
+
+    out.push_str("# TYPE zenql_commands_total counter\n");
+    for name in &names {
+        let stat = &stats_lock.commands[*name];
+        let _ = writeln!(out, "zenql_commands_total{{cmd=\"{}\"}} {}", name, stat.calls);
+    }
+    out.push_str("# TYPE zenql_commands_failed_total counter\n");
+    for name in &names {
+        let stat = &stats_lock.commands[*name];
+        let _ = writeln!(out, "zenql_commands_failed_total{{cmd=\"{}\"}} {}", name, stat.failed_calls);
+    }
+    out.push_str("# TYPE zenql_commands_rejected_total counter\n");
+    for name in &names {
+        let stat = &stats_lock.commands[*name];
+        let _ = writeln!(out, "zenql_commands_rejected_total{{cmd=\"{}\"}} {}", name, stat.rejected_calls);
+    }
+
+    out.push_str("# TYPE zenql_command_duration_seconds histogram\n");
+    for name in &names {
+        let stat = &stats_lock.commands[*name];
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS_USEC.iter().zip(stat.latency_buckets.iter()) {
+            cumulative += count;
+            let _ = writeln!(out, "zenql_command_duration_seconds_bucket{{cmd=\"{}\",le=\"{}\"}} {}", name, *bound as f64 / 1e6, cumulative);
+        }
+        let _ = writeln!(out, "zenql_command_duration_seconds_bucket{{cmd=\"{}\",le=\"+Inf\"}} {}", name, stat.calls);
+        let _ = writeln!(out, "zenql_command_duration_seconds_sum{{cmd=\"{}\"}} {}", name, stat.usec as f64 / 1e6);
+        let _ = writeln!(out, "zenql_command_duration_seconds_count{{cmd=\"{}\"}} {}", name, stat.calls);
+    }
+
+    out.push_str("# TYPE zenql_errors_total counter\n");
+    let mut prefixes: Vec<&String> = stats_lock.errors.keys().collect();
+    prefixes.sort();
+    for prefix in prefixes {
+        let _ = writeln!(out, "zenql_errors_total{{prefix=\"{}\"}} {}", prefix, stats_lock.errors[prefix]);
+    }
+    out
+}
+
+// Linux only: second field of /proc/self/statm is the resident set size in pages
+fn resident_memory_bytes() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * 4096)
+}
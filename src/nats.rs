@@ -0,0 +1,169 @@
+// A NATS client speaking the core text protocol, enough for bridges (see bridge.rs) to publish to subjects
+// and subscribe to them. Publishes are confirmed with a PING: the server handles a connection's commands in
+// order, so its PONG means everything before was accepted.
+use std::collections::VecDeque;
+use anyhow::Result;
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use crate::json::Json;
+
+// Longest protocol line read, payloads excepted
+const MAX_LINE: usize = 64 * 1024;
+// Largest payload accepted, well past the 1mb NATS servers allow by default
+const MAX_PAYLOAD: usize = 64 * 1024 * 1024;
+
+pub struct NatsClient {
+    stream: TcpStream,
+    buf: BytesMut,
+    pending: VecDeque<(Bytes, Bytes)>, // Messages that arrived while waiting for a PONG
+}
+
+// What the server sent, protocol housekeeping aside
+enum Incoming {
+    Pong,
+    Message(Bytes, Bytes), // Subject and payload
+}
+
+impl NatsClient {
+    // Connects to `authority` (host:port), logging in with a user and password when given
+    pub async fn connect(authority: &str, credentials: Option<(&str, &str)>) -> Result<NatsClient> {
+        let stream = TcpStream::connect(authority).await?;
+        let mut client = NatsClient { stream, buf: BytesMut::with_capacity(4096), pending: VecDeque::new() };
+        let info = client.line().await?;
+        if !info.starts_with(b"INFO ") {
+            return Err(anyhow::anyhow!("Not a NATS server, it sent {:?}", String::from_utf8_lossy(&info)));
+        }
+        let mut options = vec![
+            ("verbose".to_string(), Json::Bool(false)),
+            ("pedantic".to_string(), Json::Bool(false)),
+            ("name".to_string(), Json::String("zenql".to_string())),
+            ("lang".to_string(), Json::String("rust".to_string())),
+            ("version".to_string(), Json::String(env!("CARGO_PKG_VERSION").to_string())),
+            ("protocol".to_string(), Json::Int(1)),
+        ];
+        if let Some((user, password)) = credentials {
+            options.push(("user".to_string(), Json::String(user.to_string())));
+            options.push(("pass".to_string(), Json::String(password.to_string())));
+        }
+        let connect = format!("CONNECT {}\r\n", Json::Object(options).to_text());
+        client.stream.write_all(connect.as_bytes()).await?;
+        // A refused login comes back as -ERR rather than the PONG
+        client.flush().await?;
+        Ok(client)
+    }
+
+    // Publishes each payload to `subject`, returning once the server has them
+    pub async fn publish(&mut self, subject: &str, payloads: &[Bytes]) -> Result<()> {
+        let mut out = Vec::new();
+        for payload in payloads {
+            out.extend_from_slice(format!("PUB {} {}\r\n", subject, payload.len()).as_bytes());
+            out.extend_from_slice(payload);
+            out.extend_from_slice(b"\r\n");
+        }
+        self.stream.write_all(&out).await?;
+        self.flush().await
+    }
+
+    pub async fn subscribe(&mut self, subject: &str, sid: u64) -> Result<()> {
+        self.stream.write_all(format!("SUB {} {}\r\n", subject, sid).as_bytes()).await?;
+        self.flush().await
+    }
+
+    // The next message on a subscribed subject, as its subject and payload
+    pub async fn next_message(&mut self) -> Result<(Bytes, Bytes)> {
+        if let Some(message) = self.pending.pop_front() {
+            return Ok(message);
+        }
+        loop {
+            if let Incoming::Message(subject, payload) = self.next().await? {
+                return Ok((subject, payload));
+            }
+        }
+    }
+
+    // Waits for the server to have handled everything sent so far
+    async fn flush(&mut self) -> Result<()> {
+        self.stream.write_all(b"PING\r\n").await?;
+        loop {
+            match self.next().await? {
+                Incoming::Pong => return Ok(()),
+                Incoming::Message(subject, payload) => self.pending.push_back((subject, payload)),
+            }
+        }
+    }
+
+    async fn next(&mut self) -> Result<Incoming> {
+        loop {
+            let line = self.line().await?;
+            let words: Vec<&[u8]> = line.split(|&c| c == b' ' || c == b'\t').filter(|word| !word.is_empty()).collect();
+            match words.first().map(|word| word.to_ascii_uppercase()).as_deref() {
+                Some(b"PING") => self.stream.write_all(b"PONG\r\n").await?,
+                Some(b"PONG") => return Ok(Incoming::Pong),
+                Some(b"+OK" | b"INFO") => {},
+                Some(b"-ERR") => return Err(anyhow::anyhow!("NATS server error: {}", String::from_utf8_lossy(&line[4..]).trim())),
+                // MSG <subject> <sid> [reply-to] <#bytes>
+                Some(b"MSG") if (4..=5).contains(&words.len()) => {
+                    let subject = Bytes::copy_from_slice(words[1]);
+                    let len = parse_len(words[words.len() - 1])?;
+                    let payload = self.payload(len).await?;
+                    return Ok(Incoming::Message(subject, payload));
+                },
+                // HMSG <subject> <sid> [reply-to] <#header bytes> <#total bytes>, the headers dropped
+                Some(b"HMSG") if (5..=6).contains(&words.len()) => {
+                    let subject = Bytes::copy_from_slice(words[1]);
+                    let headers = parse_len(words[words.len() - 2])?;
+                    let total = parse_len(words[words.len() - 1])?;
+                    if headers > total {
+                        return Err(anyhow::anyhow!("Malformed NATS message"));
+                    }
+                    let payload = self.payload(total).await?.slice(headers..);
+                    return Ok(Incoming::Message(subject, payload));
+                },
+                _ => return Err(anyhow::anyhow!("Unexpected NATS protocol line {:?}", String::from_utf8_lossy(&line))),
+            }
+        }
+    }
+
+    // A line without its CRLF
+    async fn line(&mut self) -> Result<Bytes> {
+        loop {
+            if let Some(end) = self.buf.windows(2).position(|window| window == b"\r\n") {
+                let line = self.buf.split_to(end).freeze();
+                self.buf.advance(2);
+                return Ok(line);
+            }
+            if self.buf.len() > MAX_LINE {
+                return Err(anyhow::anyhow!("NATS protocol line too long"));
+            }
+            self.read_more().await?;
+        }
+    }
+
+    // A payload of `len` bytes and the CRLF after it
+    async fn payload(&mut self, len: usize) -> Result<Bytes> {
+        if len > MAX_PAYLOAD {
+            return Err(anyhow::anyhow!("NATS message of {} bytes is too large", len));
+        }
+        while self.buf.len() < len + 2 {
+            self.read_more().await?;
+        }
+        let payload = self.buf.split_to(len).freeze();
+        if &self.buf[..2] != b"\r\n" {
+            return Err(anyhow::anyhow!("Malformed NATS message"));
+        }
+        self.buf.advance(2);
+        Ok(payload)
+    }
+
+    async fn read_more(&mut self) -> Result<()> {
+        if self.stream.read_buf(&mut self.buf).await? == 0 {
+            return Err(anyhow::anyhow!("NATS server closed the connection"));
+        }
+        Ok(())
+    }
+}
+
+fn parse_len(word: &[u8]) -> Result<usize> {
+    std::str::from_utf8(word).ok().and_then(|len| len.parse().ok()).ok_or_else(|| anyhow::anyhow!("Malformed NATS message"))
+}
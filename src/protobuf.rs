@@ -0,0 +1,137 @@
+// The protocol buffers wire format, enough for the gRPC service's messages, which are written by hand against
+// proto/zenql.proto rather than generated. Readers skip fields they don't know, as generated code does, so
+// clients built from a newer proto still work; writers leave out fields at their default, as proto3 does.
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("malformed protobuf message")]
+pub struct DecodeError;
+
+// A field's value by wire type. Groups, long deprecated, aren't supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+impl<'a> Field<'a> {
+    pub fn varint(self) -> Result<u64, DecodeError> {
+        match self {
+            Field::Varint(n) => Ok(n),
+            _ => Err(DecodeError),
+        }
+    }
+
+    pub fn bytes(self) -> Result<&'a [u8], DecodeError> {
+        match self {
+            Field::Bytes(bytes) => Ok(bytes),
+            _ => Err(DecodeError),
+        }
+    }
+
+    pub fn string(self) -> Result<&'a str, DecodeError> {
+        std::str::from_utf8(self.bytes()?).map_err(|_| DecodeError)
+    }
+}
+
+// The fields of a message in the order they were written
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    // The next field's number and value, None at the end of the message
+    pub fn next_field(&mut self) -> Result<Option<(u32, Field<'a>)>, DecodeError> {
+        if self.data.is_empty() {
+            return Ok(None);
+        }
+        let tag = self.varint()?;
+        let number = u32::try_from(tag >> 3).map_err(|_| DecodeError)?;
+        if number == 0 {
+            return Err(DecodeError);
+        }
+        let field = match tag & 7 {
+            0 => Field::Varint(self.varint()?),
+            1 => Field::Fixed64(u64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            2 => {
+                let len = usize::try_from(self.varint()?).map_err(|_| DecodeError)?;
+                Field::Bytes(self.take(len)?)
+            },
+            5 => Field::Fixed32(u32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+            _ => return Err(DecodeError),
+        };
+        Ok(Some((number, field)))
+    }
+
+    fn varint(&mut self) -> Result<u64, DecodeError> {
+        let mut value = 0u64;
+        for (i, &byte) in self.data.iter().enumerate().take(10) {
+            value |= ((byte & 0x7f) as u64) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.data = &self.data[i + 1..];
+                return Ok(value);
+            }
+        }
+        Err(DecodeError)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if self.data.len() < len {
+            return Err(DecodeError);
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+}
+
+#[derive(Default)]
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn varint(&mut self, number: u32, value: u64) -> &mut Self {
+        if value != 0 {
+            write_varint(&mut self.buf, (number as u64) << 3);
+            write_varint(&mut self.buf, value);
+        }
+        self
+    }
+
+    pub fn bool(&mut self, number: u32, value: bool) -> &mut Self {
+        self.varint(number, value as u64)
+    }
+
+    pub fn bytes(&mut self, number: u32, value: &[u8]) -> &mut Self {
+        if !value.is_empty() {
+            self.repeated_bytes(number, value);
+        }
+        self
+    }
+
+    // An element of a repeated field, written even when empty so it isn't lost
+    pub fn repeated_bytes(&mut self, number: u32, value: &[u8]) -> &mut Self {
+        write_varint(&mut self.buf, (number as u64) << 3 | 2);
+        write_varint(&mut self.buf, value.len() as u64);
+        self.buf.extend_from_slice(value);
+        self
+    }
+
+    pub fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buf)
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
@@ -0,0 +1,53 @@
+use tokio::sync::mpsc::UnboundedSender;
+use std::collections::HashMap;
+use bytes::Bytes;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use crate::locks;
+use crate::resp::Value;
+
+// Channel -> subscriber id -> sender feeding that subscriber's connection
+#[derive(Default)]
+pub struct PubSub {
+    channels: Mutex<HashMap<Bytes, HashMap<u64, UnboundedSender<Value>>>>,
+    next_id: AtomicU64,
+}
+
+impl PubSub {
+    pub fn new() -> Self {
+        PubSub::default()
+    }
+
+    pub fn next_subscriber_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn subscribe(&self, channel: &Bytes, id: u64, sender: UnboundedSender<Value>) {
+        let mut channels = locks::lock(&self.channels);
+        channels.entry(channel.clone()).or_default().insert(id, sender);
+    }
+
+    pub fn unsubscribe(&self, channel: &[u8], id: u64) {
+        let mut channels = locks::lock(&self.channels);
+        if let Some(subscribers) = channels.get_mut(channel) {
+            subscribers.remove(&id);
+            if subscribers.is_empty() {
+                channels.remove(channel);
+            }
+        }
+    }
+
+    // Returns the number of subscribers the message was delivered to
+    pub fn publish(&self, channel: &Bytes, message: &Bytes) -> usize {
+        let channels = locks::lock(&self.channels);
+        let Some(subscribers) = channels.get(channel) else {
+            return 0;
+        };
+        let frame = Value::Push(vec![
+            Value::BulkString("message".into()),
+            Value::BulkString(channel.clone()),
+            Value::BulkString(message.clone()),
+        ]);
+        subscribers.values().filter(|sender| sender.send(frame.clone()).is_ok()).count()
+    }
+}
@@ -0,0 +1,81 @@
+use std::time::{Duration, Instant};
+use anyhow::Result;
+
+// What happens to a command over a client's rate limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitAction {
+    Throttle, // Wait for the bucket to refill, which also stops reading from the client meanwhile
+    Reject, // Reply with an error straight away
+}
+
+impl RateLimitAction {
+    pub fn parse(name: &str) -> Result<RateLimitAction> {
+        match name.to_lowercase().as_str() {
+            "throttle" => Ok(RateLimitAction::Throttle),
+            "reject" => Ok(RateLimitAction::Reject),
+            _ => Err(anyhow::anyhow!("Invalid rate limit action '{}', expected throttle or reject", name)),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RateLimitAction::Throttle => "throttle",
+            RateLimitAction::Reject => "reject",
+        }
+    }
+}
+
+pub enum Verdict {
+    Run,
+    Delay(Duration), // Run after this long
+    Reject,
+}
+
+// A token bucket per connection: `rate` tokens a second, holding up to a second's worth so a client may
+// burst after being idle. Each command takes a token.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    rate: u64,
+    tokens: f64,
+    refilled: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        RateLimiter::default()
+    }
+
+    // Decides on the next command. `rate` is read from the config on every call so a reload applies to
+    // connected clients too; 0 disables the limit.
+    pub fn check(&mut self, rate: u64, action: RateLimitAction) -> Verdict {
+        if rate == 0 {
+            return Verdict::Run;
+        }
+        let now = Instant::now();
+        match self.refilled {
+            // A new rate starts over with a full bucket
+            Some(refilled) if self.rate == rate => {
+                let refill = now.duration_since(refilled).as_secs_f64() * rate as f64;
+                self.tokens = (self.tokens + refill).min(rate as f64);
+            },
+            _ => {
+                self.rate = rate;
+                self.tokens = rate as f64;
+            },
+        }
+        self.refilled = Some(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Verdict::Run;
+        }
+        match action {
+            RateLimitAction::Reject => Verdict::Reject,
+            // Taken on credit, the bucket is below zero until the delay has passed
+            RateLimitAction::Throttle => {
+                self.tokens -= 1.0;
+                Verdict::Delay(Duration::from_secs_f64(-self.tokens / rate as f64))
+            },
+        }
+    }
+}
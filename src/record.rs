@@ -0,0 +1,530 @@
+// The file format shared by the disk backend's log, the write-ahead log and snapshots. A file starts with a header,
+//   "ZENQL" | kind u8 | format version u16
+// the kind being D for the disk backend's data file, W for a write-ahead log segment and S for a snapshot,
+// with the version's top bit set and more to the header for an encrypted file, see crypt.rs. It's
+// followed by one record per change to the keyspace, integers little-endian:
+//   set     1 | key length u64 | key | deadline u64 | encoding u8 | payload length u64 | payload | crc u64
+//   expire  2 | key length u64 | key | deadline u64 | crc u64
+//   del     3 | key length u64 | key | crc u64
+//   snapshot 4 | id u64 | base id u64 | base path length u64 | base path | crc u64
+// The deadline is in UNIX milliseconds, u64::MAX for none. The payload is the value for encoding 0, the
+// uncompressed length u64 followed by an LZ4 block for encoding 1, a JSON document's text for encoding 2, and
+// for a hash, encoding 3, each field and its value in turn, each as length u64 | bytes; encoding 4 is a time
+// series, 5 a Bloom filter, 6 a cuckoo filter, 7 a count-min sketch, 8 a top-k, 9 a t-digest and 10 a
+// graph, laid out as their encode methods say. Everything from the encoding to the
+// payload's end is the value's section, which the disk backend points into. The crc is the CRC-64 of the
+// record's bytes before it, checked when the file is read back on startup. The snapshot record starts every
+// snapshot file, identifying it and for an incremental one the snapshot it follows; base id 0 means none.
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use bytes::Bytes;
+use crate::bloom::BloomFilter;
+use crate::countmin::CountMinSketch;
+use crate::crc64;
+use crate::crypt::{self, Cipher, Keyring};
+use crate::cuckoo::CuckooFilter;
+use crate::graph::Graph;
+use crate::log::log_warn;
+use crate::json::Json;
+use crate::lz4;
+use crate::storage::{Hash, StoredValue};
+use crate::tdigest::TDigest;
+use crate::timeseries::TimeSeries;
+use crate::topk::TopK;
+
+// Bumped whenever the layout changes; files in other versions are refused
+pub const FORMAT_VERSION: u16 = 1;
+const MAGIC: &[u8; 5] = b"ZENQL";
+// Set in the version of an encrypted file
+const ENCRYPTED: u16 = 0x8000;
+// Longest header, an encrypted one with the longest key id
+const MAX_HEADER: usize = 9 + crypt::MAX_KEY_ID + 16;
+
+const SET: u8 = 1;
+const EXPIRE: u8 = 2;
+const DEL: u8 = 3;
+const SNAPSHOT: u8 = 4;
+const RAW: u8 = 0;
+const LZ4: u8 = 1;
+const JSON: u8 = 2;
+const HASH: u8 = 3;
+const TIMESERIES: u8 = 4;
+const BLOOM: u8 = 5;
+const CUCKOO: u8 = 6;
+const COUNTMIN: u8 = 7;
+const TOPK: u8 = 8;
+const TDIGEST: u8 = 9;
+const GRAPH: u8 = 10;
+const NO_DEADLINE: u64 = u64::MAX;
+// Encoding and payload length
+pub const SECTION_HEADER: usize = 9;
+
+// A change read back from a log
+#[derive(Debug)]
+pub enum Record {
+    Set { key: Bytes, value: StoredValue, expires_at: Option<u64> },
+    Expire { key: Bytes, expires_at: Option<u64> },
+    Del { key: Bytes },
+    Snapshot { id: u64, base: Option<(u64, PathBuf)> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Data,
+    Wal,
+    Snapshot,
+}
+
+impl FileKind {
+    fn tag(self) -> u8 {
+        match self {
+            FileKind::Data => b'D',
+            FileKind::Wal => b'W',
+            FileKind::Snapshot => b'S',
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            FileKind::Data => "data file",
+            FileKind::Wal => "write-ahead log segment",
+            FileKind::Snapshot => "snapshot",
+        }
+    }
+}
+
+// The header of a new file, encrypted with `cipher` when there is one
+pub fn file_header(kind: FileKind, cipher: Option<&Cipher>) -> Vec<u8> {
+    let mut header = MAGIC.to_vec();
+    header.push(kind.tag());
+    let Some(cipher) = cipher else {
+        header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        return header;
+    };
+    header.extend_from_slice(&(FORMAT_VERSION | ENCRYPTED).to_le_bytes());
+    header.push(cipher.id().len() as u8);
+    header.extend_from_slice(cipher.id().as_bytes());
+    header.extend_from_slice(&cipher.nonce().to_le_bytes());
+    header.extend_from_slice(&cipher.check().to_le_bytes());
+    header
+}
+
+// How set records' values are read back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Values {
+    InMemory, // Whole, as Raw, Int, Compressed, Json or Hash
+    Positions, // As OnDisk where the value's section is, except what in_memory keeps
+}
+
+// What replay found
+pub struct Replayed {
+    pub end: u64, // Where the last whole record ends
+    pub cipher: Option<Cipher>, // The file is encrypted with
+    pub cut: bool, // Whether a partial record was cut off
+}
+
+// Checks the header of the `kind` file in `file`, then reads its records and passes them to `apply` in order.
+// An empty file gets a header, encrypted with the current key of `keys` if it has one; other files are read
+// with the key their header names. With `cut_partial` set, the file is the last one written to: a crash
+// mid-write may have left a partial header or record at the end, or a last record that fails its checksum,
+// and that is cut off. Anything else that doesn't check out is an error.
+pub fn replay(file: &File, path: &Path, kind: FileKind, values: Values, cut_partial: bool, keys: &Keyring, mut apply: impl FnMut(Record)) -> io::Result<Replayed> {
+    let size = file.metadata()?.len();
+    let (start, cipher) = match read_header(file, path, kind, keys) {
+        Err(e) if cut_partial && e.kind() == io::ErrorKind::UnexpectedEof => {
+            let cipher = keys.cipher()?;
+            let header = file_header(kind, cipher.as_ref());
+            file.set_len(0)?;
+            file.write_all_at(&header, 0)?;
+            return Ok(Replayed { end: header.len() as u64, cipher, cut: false });
+        },
+        header => header?,
+    };
+    let mut inner = BufReader::new(file);
+    inner.seek(SeekFrom::Start(start))?;
+    let inner = crypt::Reader::new(inner, cipher.clone(), start);
+    let mut reader = Reader { inner, pos: start, size, values, crc: 0, bad_checksum: false };
+    let mut end = start;
+    loop {
+        match reader.record() {
+            Ok(Some(record)) => apply(record),
+            Ok(None) => return Ok(Replayed { end, cipher, cut: false }),
+            Err(e) if cut_partial && (e.kind() == io::ErrorKind::UnexpectedEof || (reader.bad_checksum && reader.pos == size)) => {
+                log_warn!("Cutting {} bytes of a partial record off the end of {}", size - end, path.display());
+                file.set_len(end)?;
+                return Ok(Replayed { end, cipher, cut: true });
+            },
+            Err(e) => {
+                let reason = format!(
+                    "{} at byte {} of {}. The file is damaged: restore it from a backup, or truncate it to {} bytes to \
+                    keep the records before the damage and lose the rest",
+                    e, end, path.display(), end,
+                );
+                return Err(io::Error::new(e.kind(), reason));
+            },
+        }
+        end = reader.pos;
+    }
+}
+
+// Checks the header of the `kind` file in `file`, returning its length and the cipher of an encrypted file.
+// UnexpectedEof when the file ends partway through the header of a file of that kind.
+fn read_header(file: &File, path: &Path, kind: FileKind, keys: &Keyring) -> io::Result<(u64, Option<Cipher>)> {
+    let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidData, reason);
+    let partial = || io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} ends partway through its header", path.display()));
+    let mut header = vec![0; file.metadata()?.len().min(MAX_HEADER as u64) as usize];
+    file.read_exact_at(&mut header, 0)?;
+    let start = [&MAGIC[..], &[kind.tag()]].concat();
+    if header.len() < 8 && start.starts_with(&header[..header.len().min(start.len())]) {
+        return Err(partial());
+    }
+    if header.len() < 8 || &header[..5] != MAGIC {
+        return Err(invalid(format!(
+            "{} isn't a zenql {}: it doesn't start with a zenql header. Files written before format versioning \
+            can't be read; move it out of the way to start empty",
+            path.display(), kind.name(),
+        )));
+    }
+    if header[5] != kind.tag() {
+        return Err(invalid(format!("{} isn't a zenql {}, it holds another kind of zenql file", path.display(), kind.name())));
+    }
+    let version = u16::from_le_bytes([header[6], header[7]]);
+    if version & !ENCRYPTED != FORMAT_VERSION {
+        return Err(invalid(format!(
+            "{} is in format version {}, this build reads version {}: open it with the zenql that wrote it",
+            path.display(), version & !ENCRYPTED, FORMAT_VERSION,
+        )));
+    }
+    if version & ENCRYPTED == 0 {
+        return Ok((8, None));
+    }
+    let id_len = *header.get(8).ok_or_else(partial)? as usize;
+    let len = 9 + id_len + 16;
+    let fields = header.get(9..len).ok_or_else(partial)?;
+    let id = std::str::from_utf8(&fields[..id_len]).map_err(|_| invalid(format!("{} has a key id that isn't UTF-8 in its header", path.display())))?;
+    let nonce = u64::from_le_bytes(fields[id_len..id_len + 8].try_into().unwrap());
+    let check = u64::from_le_bytes(fields[id_len + 8..].try_into().unwrap());
+    let key = keys.key(id).map_err(|e| io::Error::new(e.kind(), format!("Can't read {}: {}", path.display(), e)))?;
+    let cipher = Cipher::new(id, &key, nonce);
+    if cipher.check() != check {
+        return Err(invalid(format!("{} is encrypted with a key other than the one configured as '{}'", path.display(), id)));
+    }
+    Ok((len as u64, Some(cipher)))
+}
+
+// Integers and the empty value are no bigger than a position, so they're kept in memory even on disk.
+// Documents, hashes, time series, filters and sketches are too, they're updated in place.
+pub fn in_memory(value: &StoredValue) -> bool {
+    match value {
+        StoredValue::Int(_) | StoredValue::Json(_) | StoredValue::Hash(_) | StoredValue::TimeSeries(_)
+            | StoredValue::Bloom(_) | StoredValue::Cuckoo(_) | StoredValue::CountMin(_)
+            | StoredValue::TopK(_) | StoredValue::TDigest(_) | StoredValue::Graph(_) => true,
+        StoredValue::Raw(value) => value.is_empty(),
+        _ => false,
+    }
+}
+
+pub fn set_header(key: &[u8], expires_at: Option<u64>) -> Vec<u8> {
+    header(SET, key, expires_at)
+}
+
+pub fn expire(key: &[u8], expires_at: Option<u64>) -> Vec<u8> {
+    seal(header(EXPIRE, key, expires_at))
+}
+
+pub fn del(key: &[u8]) -> Vec<u8> {
+    seal(header(DEL, key, None))
+}
+
+pub fn snapshot(id: u64, base: Option<(u64, &Path)>) -> Vec<u8> {
+    let (base_id, path) = base.map_or((0, &[][..]), |(id, path)| (id, path.as_os_str().as_bytes()));
+    let mut record = vec![SNAPSHOT];
+    record.extend_from_slice(&id.to_le_bytes());
+    record.extend_from_slice(&base_id.to_le_bytes());
+    record.extend_from_slice(&(path.len() as u64).to_le_bytes());
+    record.extend_from_slice(path);
+    seal(record)
+}
+
+// A whole set record for a value in memory
+pub fn set(key: &[u8], value: &StoredValue, expires_at: Option<u64>) -> Option<Vec<u8>> {
+    let mut record = set_header(key, expires_at);
+    record.extend_from_slice(&section(value)?);
+    Some(seal(record))
+}
+
+// Appends the checksum that completes a record
+pub fn seal(mut record: Vec<u8>) -> Vec<u8> {
+    let crc = crc64::checksum(&record);
+    record.extend_from_slice(&crc.to_le_bytes());
+    record
+}
+
+// Record up to the value, or all of it for expire and del
+fn header(kind: u8, key: &[u8], expires_at: Option<u64>) -> Vec<u8> {
+    let mut record = Vec::with_capacity(25 + key.len() + SECTION_HEADER);
+    record.push(kind);
+    record.extend_from_slice(&(key.len() as u64).to_le_bytes());
+    record.extend_from_slice(key);
+    if kind != DEL {
+        record.extend_from_slice(&expires_at.unwrap_or(NO_DEADLINE).to_le_bytes());
+    }
+    record
+}
+
+// Encoding, payload length and payload of a value in memory; None for one kept elsewhere
+pub fn section(value: &StoredValue) -> Option<Vec<u8>> {
+    let (encoding, payload) = match value {
+        StoredValue::Raw(value) => (RAW, value.to_vec()),
+        StoredValue::Int(n) => (RAW, n.to_string().into_bytes()),
+        StoredValue::Compressed { data, len } => {
+            let mut payload = (*len as u64).to_le_bytes().to_vec();
+            payload.extend_from_slice(data);
+            (LZ4, payload)
+        },
+        StoredValue::Json(doc) => (JSON, doc.to_text().into_bytes()),
+        StoredValue::Hash(hash) => (HASH, hash_payload(hash)),
+        StoredValue::TimeSeries(series) => (TIMESERIES, series.encode()),
+        StoredValue::Bloom(filter) => (BLOOM, filter.encode()),
+        StoredValue::Cuckoo(filter) => (CUCKOO, filter.encode()),
+        StoredValue::CountMin(sketch) => (COUNTMIN, sketch.encode()),
+        StoredValue::TopK(topk) => (TOPK, topk.encode()),
+        StoredValue::TDigest(digest) => (TDIGEST, digest.encode()),
+        StoredValue::Graph(graph) => (GRAPH, graph.encode()),
+        StoredValue::OnDisk { .. } | StoredValue::Spilled { .. } => return None,
+    };
+    let mut section = Vec::with_capacity(SECTION_HEADER + payload.len());
+    section.push(encoding);
+    section.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    section.extend_from_slice(&payload);
+    Some(section)
+}
+
+pub fn hash_payload(hash: &Hash) -> Vec<u8> {
+    let mut payload = vec![];
+    for part in hash.iter().flat_map(|(field, value)| [field, value]) {
+        payload.extend_from_slice(&(part.len() as u64).to_le_bytes());
+        payload.extend_from_slice(part);
+    }
+    payload
+}
+
+fn decode_hash(payload: Bytes) -> Option<Hash> {
+    let mut parts = vec![];
+    let mut pos = 0;
+    while pos < payload.len() {
+        let len = u64::from_le_bytes(payload.get(pos..pos + 8)?.try_into().ok()?) as usize;
+        let end = (pos + 8).checked_add(len).filter(|&end| end <= payload.len())?;
+        parts.push(payload.slice(pos + 8..end));
+        pos = end;
+    }
+    if parts.len() % 2 != 0 {
+        return None;
+    }
+    Some(parts.chunks(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect())
+}
+
+// Reads the fields of a value's payload in turn, for the types that lay theirs out themselves
+pub struct PayloadReader<'a> {
+    payload: &'a Bytes,
+    pos: usize,
+}
+
+impl<'a> PayloadReader<'a> {
+    pub fn new(payload: &'a Bytes) -> Self {
+        PayloadReader { payload, pos: 0 }
+    }
+
+    // Whether everything was read
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.payload.len()
+    }
+
+    pub fn take(&mut self, len: usize) -> Option<Bytes> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.payload.len())?;
+        let taken = self.payload.slice(self.pos..end);
+        self.pos = end;
+        Some(taken)
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?[..].try_into().ok()?))
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?[..].try_into().ok()?))
+    }
+
+    // Length u64 | bytes
+    pub fn bytes(&mut self) -> Option<Bytes> {
+        let len = usize::try_from(self.u64()?).ok()?;
+        self.take(len)
+    }
+
+    pub fn string(&mut self) -> Option<String> {
+        String::from_utf8(self.bytes()?.to_vec()).ok()
+    }
+}
+
+// The bytes of the value in a section
+pub fn decode_section(section: Vec<u8>) -> Option<Bytes> {
+    match decode_value(section)? {
+        StoredValue::Compressed { data, len } => lz4::decompress(&data, len).map(Bytes::from),
+        value => Some(value.to_bytes()),
+    }
+}
+
+fn decode_value(section: Vec<u8>) -> Option<StoredValue> {
+    let encoding = *section.first()?;
+    let payload = Bytes::from(section).slice(SECTION_HEADER..);
+    match encoding {
+        RAW => Some(StoredValue::from_bytes(payload)),
+        LZ4 => {
+            let len = u64::from_le_bytes(payload.get(..8)?.try_into().ok()?) as usize;
+            Some(StoredValue::Compressed { data: payload.slice(8..), len })
+        },
+        JSON => Json::parse(&payload).ok().map(|doc| StoredValue::Json(Arc::new(doc))),
+        HASH => decode_hash(payload).map(|hash| StoredValue::Hash(Arc::new(hash))),
+        TIMESERIES => TimeSeries::decode(&payload).map(|series| StoredValue::TimeSeries(Arc::new(series))),
+        BLOOM => BloomFilter::decode(&payload).map(|filter| StoredValue::Bloom(Arc::new(filter))),
+        CUCKOO => CuckooFilter::decode(&payload).map(|filter| StoredValue::Cuckoo(Arc::new(filter))),
+        COUNTMIN => CountMinSketch::decode(&payload).map(|sketch| StoredValue::CountMin(Arc::new(sketch))),
+        TOPK => TopK::decode(&payload).map(|topk| StoredValue::TopK(Arc::new(topk))),
+        TDIGEST => TDigest::decode(&payload).map(|digest| StoredValue::TDigest(Arc::new(digest))),
+        GRAPH => Graph::decode(&payload).map(|graph| StoredValue::Graph(Arc::new(graph))),
+        _ => None,
+    }
+}
+
+// Reads records one after another, knowing where it is so values can be found again
+struct Reader<R> {
+    inner: R,
+    pos: u64,
+    size: u64,
+    values: Values,
+    crc: u64, // Of the current record so far
+    bad_checksum: bool, // Set when the last record read was whole but failed its checksum
+}
+
+impl<R: Read> Reader<R> {
+    // None at the end of the log
+    fn record(&mut self) -> io::Result<Option<Record>> {
+        let mut kind = [0];
+        if self.inner.read(&mut kind)? == 0 {
+            return Ok(None);
+        }
+        if ![SET, EXPIRE, DEL, SNAPSHOT].contains(&kind[0]) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown record type {}", kind[0])));
+        }
+        self.pos += 1;
+        self.crc = crc64::checksum(&kind);
+        let record = match kind[0] {
+            SNAPSHOT => {
+                let id = self.u64()?;
+                let base_id = self.u64()?;
+                let path_len = self.u64()?;
+                let path = PathBuf::from(OsStr::from_bytes(&self.bytes(path_len)?));
+                Record::Snapshot { id, base: (base_id != 0).then_some((base_id, path)) }
+            },
+            _ => self.key_record(kind[0])?,
+        };
+        let crc = self.crc;
+        if self.u64()? != crc {
+            self.bad_checksum = true;
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "record failed its checksum"));
+        }
+        Ok(Some(record))
+    }
+
+    // The rest of a set, expire or del record, up to the checksum
+    fn key_record(&mut self, kind: u8) -> io::Result<Record> {
+        let key_len = self.u64()?;
+        let key = Bytes::from(self.bytes(key_len)?);
+        let record = match kind {
+            SET => {
+                let expires_at = self.deadline()?;
+                Record::Set { key, value: self.value()?, expires_at }
+            },
+            EXPIRE => Record::Expire { key, expires_at: self.deadline()? },
+            _ => Record::Del { key },
+        };
+        Ok(record)
+    }
+
+    fn value(&mut self) -> io::Result<StoredValue> {
+        let offset = self.pos;
+        let encoding = self.bytes(1)?[0];
+        let payload_len = self.u64()?;
+        if ![RAW, LZ4, JSON, HASH, TIMESERIES, BLOOM, CUCKOO, COUNTMIN, TOPK, TDIGEST, GRAPH].contains(&encoding) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown value encoding {}", encoding)));
+        }
+        let position = StoredValue::OnDisk { offset, len: SECTION_HEADER + payload_len as usize };
+        // Short values may be integers, which stay in memory like the other types
+        if self.values == Values::Positions && (encoding == LZ4 || (encoding == RAW && payload_len > 20)) {
+            self.skip(payload_len)?;
+            return Ok(position);
+        }
+        let mut section = vec![encoding];
+        section.extend_from_slice(&payload_len.to_le_bytes());
+        section.extend_from_slice(&self.bytes(payload_len)?);
+        let value = decode_value(section).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed value"))?;
+        if self.values == Values::Positions && !in_memory(&value) {
+            return Ok(position);
+        }
+        Ok(value)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.inner.read_exact(buf)?;
+        self.pos += buf.len() as u64;
+        self.crc = crc64::update(self.crc, buf);
+        Ok(())
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        self.read(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn deadline(&mut self) -> io::Result<Option<u64>> {
+        Ok(Some(self.u64()?).filter(|&deadline| deadline != NO_DEADLINE))
+    }
+
+    // A length past the end of the file means the record was cut short
+    fn check_len(&self, len: u64) -> io::Result<()> {
+        if len > self.size - self.pos {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+
+    fn bytes(&mut self, len: u64) -> io::Result<Vec<u8>> {
+        self.check_len(len)?;
+        let mut bytes = vec![0; len as usize];
+        self.read(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    // Reads past bytes that aren't kept, they still count towards the checksum
+    fn skip(&mut self, len: u64) -> io::Result<()> {
+        self.check_len(len)?;
+        let mut buf = [0; 8192];
+        let mut left = len;
+        while left > 0 {
+            let chunk = left.min(buf.len() as u64) as usize;
+            self.read(&mut buf[..chunk])?;
+            left -= chunk as u64;
+        }
+        Ok(())
+    }
+}
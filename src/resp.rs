@@ -1,110 +1,131 @@
-use tokio::{net::TcpStream, io::{AsyncReadExt, AsyncWriteExt}};
-use bytes::BytesMut;
-use anyhow::Result;
+use tokio::io::AsyncReadExt;
+use bytes::{Bytes, BytesMut};
+use std::time::{Duration, Instant};
+use crate::error::Result;
+use crate::codec::{format_double, Limits, RespCodec, WriteBuffer};
+use crate::listener::Stream;
+use crate::stats::{self, SERVER_STATS};
 
-#[derive(Clone, Debug)]
+// Minimum free space made available for each socket read
+const READ_CHUNK: usize = 16 * 1024;
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Value {
     SimpleString(String),
-    BulkString(String),
+    Error(String),
+    BulkString(Bytes),
+    Integer(i64),
     Array(Vec<Value>),
+    NullArray,
     Null,
+    // RESP3 types, downgraded to their RESP2 equivalents on protocol 2 connections
+    Map(Vec<(Value, Value)>),
+    Set(Vec<Value>),
+    Double(f64),
+    Boolean(bool),
+    BigNumber(String),
+    Verbatim(String, Bytes), // Three letter format (txt, mkd) and payload
+    Push(Vec<Value>),
 }
 
 impl Value {
-    pub fn serialize(self) -> String {
+    // Maps the RESP3-only types onto what a RESP2 client expects (flattened maps, doubles as bulk strings...)
+    pub fn downgrade(self) -> Value {
         match self {
-            Value::SimpleString(s) => format!("+{}\r\n", s),
-            Value::BulkString(s) => format!("${}\r\n{}\r\n", s.chars().count(), s),
-            Value::Null => "$-1\r\n".to_string(),
-            _ => panic!("Unsupported value for serialize"),
+            Value::Map(pairs) => Value::Array(pairs.into_iter().flat_map(|(k, v)| [k.downgrade(), v.downgrade()]).collect()),
+            Value::Set(items) | Value::Push(items) | Value::Array(items) => Value::Array(items.into_iter().map(Value::downgrade).collect()),
+            Value::Double(d) => Value::BulkString(format_double(d).into()),
+            Value::Boolean(b) => Value::Integer(b as i64),
+            Value::BigNumber(n) => Value::BulkString(n.into()),
+            Value::Verbatim(_, payload) => Value::BulkString(payload),
+            other => other,
         }
     }
 }
 
 pub struct RespHandler {
-    stream: TcpStream,
+    stream: Box<dyn Stream>,
+    codec: RespCodec,
     buffer: BytesMut,
+    write_buffer: WriteBuffer,
+    parse_time: Duration,
 }
 
 impl RespHandler {
-    pub fn new(stream: TcpStream) -> Self {
+    pub fn new(stream: Box<dyn Stream>) -> Self {
         RespHandler {
             stream,
+            codec: RespCodec::default(),
             buffer: BytesMut::with_capacity(512),
+            write_buffer: WriteBuffer::new(),
+            parse_time: Duration::ZERO,
         }
     }
 
+    // Returns the next complete request, reading more from the socket only when the buffer holds a partial frame.
+    // Malformed input yields Error::Protocol; the caller replies with it and closes the connection.
     pub async fn read_value(&mut self) -> Result<Option<Value>> {
-        let bytes_read = self.stream.read_buf(&mut self.buffer).await?;
-        if bytes_read == 0 {
-            return Ok(None);
+        loop {
+            if let Some(value) = self.decode_buffered()? {
+                return Ok(Some(value));
+            }
+
+            self.buffer.reserve(READ_CHUNK);
+            let bytes_read = self.stream.read_buf(&mut self.buffer).await?;
+            if bytes_read == 0 {
+                return Ok(None);
+            }
+            stats::incr(&SERVER_STATS.total_net_input_bytes, bytes_read as u64);
         }
-        let (v, _) = parse_message(self.buffer.split())?;
-        Ok(Some(v))
     }
 
-    pub async fn write_value(&mut self, value: Value) -> Result<()> {
-        self.stream.write(value.serialize().as_bytes()).await?;
-        Ok(())
+    // Next request already sitting in the read buffer, without touching the socket. Lets the caller drain a
+    // pipelined batch before flushing the replies.
+    pub fn decode_buffered(&mut self) -> Result<Option<Value>> {
+        loop {
+            let start = Instant::now();
+            let Some(value) = self.codec.decode(&mut self.buffer)? else {
+                return Ok(None);
+            };
+            self.parse_time = start.elapsed();
+            match value {
+                Value::Array(ref items) if items.is_empty() => continue, // Empty inline line or *0
+                _ => return Ok(Some(value)),
+            }
+        }
     }
-}
 
-fn parse_message(buffer: BytesMut) -> Result<(Value, usize)> {
-    match buffer[0] as char {
-        '+' => parse_simple_string(buffer),
-        '*' => parse_array(buffer),
-        '$' => parse_bulk_string(buffer),
-        _ => Err(anyhow::anyhow!("Not a known value type {:?}", buffer)),
+    // Time spent decoding the last value returned by read_value
+    pub fn parse_time(&self) -> Duration {
+        self.parse_time
     }
-}
 
-fn parse_simple_string(buffer: BytesMut) -> Result<(Value, usize)> {
-    if let Some((line, len)) = read_until_crlf(&buffer[1..]) {
-        let string = String::from_utf8(line.to_vec()).unwrap();
-        return Ok((Value::SimpleString(string), len + 1));
+    pub fn set_protocol(&mut self, protocol: u8) {
+        self.codec.protocol = protocol;
     }
-    Err(anyhow::anyhow!("Invalid string {:?}", buffer))
-}
-
-fn parse_array(buffer: BytesMut) -> Result<(Value, usize)> {
-    let (array_length, mut bytes_consumed) = if let Some((line, len)) = read_until_crlf(&buffer[1..]) {
-        let array_length = parse_int(line)?;
-        (array_length, len + 1)
-    } else {
-        return Err(anyhow::anyhow!("Invalid array format {:?}", buffer));
-    };
 
-    let mut items = vec![];
-    for _ in 0..array_length {
-        let (array_item, len) = parse_message(BytesMut::from(&buffer[bytes_consumed..]))?;
-        items.push(array_item);
-        bytes_consumed += len;
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.codec.limits = limits;
     }
-    Ok((Value::Array(items), bytes_consumed))
-}
 
-fn parse_bulk_string(buffer: BytesMut) -> Result<(Value, usize)> {
-    let (bulk_str_len, bytes_consumed) = if let Some((line, len)) = read_until_crlf(&buffer[1..]) {
-        let bulk_str_len = parse_int(line)?;
-        (bulk_str_len, len + 1)
-    } else {
-        return Err(anyhow::anyhow!("Invalid bulk string format {:?}", buffer));
-    };
+    // Encodes a reply without writing it; see flush
+    pub fn queue_value(&mut self, value: Value) {
+        self.codec.encode(value, &mut self.write_buffer);
+    }
 
-    let end_of_bulk_str = bytes_consumed + bulk_str_len as usize;
-    let total_parsed = end_of_bulk_str + 2;
-    Ok((Value::BulkString(String::from_utf8(buffer[bytes_consumed..end_of_bulk_str].to_vec())?), total_parsed))
-}
+    // Bytes queued but not yet flushed
+    pub fn pending_output(&self) -> usize {
+        self.write_buffer.len()
+    }
 
-fn read_until_crlf(buffer: &[u8]) -> Option<(&[u8], usize)> {
-    for i in 1..buffer.len() {
-        if buffer[i - 1] == b'\r' && buffer[i] == b'\n' {
-            return Some((&buffer[0..(i - 1)], i + 1));
-        }
+    pub async fn flush(&mut self) -> Result<()> {
+        let written = self.write_buffer.write_to(&mut self.stream).await?;
+        stats::incr(&SERVER_STATS.total_net_output_bytes, written as u64);
+        Ok(())
     }
-    None
-}
 
-fn parse_int(buffer: &[u8]) -> Result<i64> {
-    Ok(String::from_utf8(buffer.to_vec())?.parse::<i64>()?)
+    pub async fn write_value(&mut self, value: Value) -> Result<()> {
+        self.queue_value(value);
+        self.flush().await
+    }
 }
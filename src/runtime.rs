@@ -0,0 +1,41 @@
+use std::io;
+use anyhow::Result;
+use tokio::runtime::{Builder, Runtime};
+use crate::config::Config;
+
+// How the server's tasks are scheduled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeKind {
+    MultiThread, // A pool of worker threads that steal work from each other
+    CurrentThread, // Everything on the main thread: a single core, but no cross-thread wakeups
+}
+
+impl RuntimeKind {
+    pub fn parse(name: &str) -> Result<RuntimeKind> {
+        match name.to_lowercase().as_str() {
+            "multi-thread" => Ok(RuntimeKind::MultiThread),
+            "current-thread" => Ok(RuntimeKind::CurrentThread),
+            _ => Err(anyhow::anyhow!("Invalid runtime '{}', expected multi-thread or current-thread", name)),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RuntimeKind::MultiThread => "multi-thread",
+            RuntimeKind::CurrentThread => "current-thread",
+        }
+    }
+}
+
+// The runtime the server runs on. worker-threads only sizes the multi-thread pool, tokio's default of one
+// thread per core applies when it's 0.
+pub fn build(config: &Config) -> io::Result<Runtime> {
+    let mut builder = match config.runtime {
+        RuntimeKind::MultiThread => Builder::new_multi_thread(),
+        RuntimeKind::CurrentThread => Builder::new_current_thread(),
+    };
+    if config.runtime == RuntimeKind::MultiThread && config.worker_threads > 0 {
+        builder.worker_threads(config.worker_threads);
+    }
+    builder.enable_all().build()
+}
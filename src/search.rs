@@ -0,0 +1,374 @@
+// Full-text search over hashes and JSON documents. A search index covers the hashes, or the documents, whose
+// keys start with one of its prefixes, and the text in the fields of its schema: hash fields by name,
+// document values by JSONPath, strings only. Text is split into terms, runs of letters and digits lowercased,
+// and every shard keeps an inverted index of its own keys' terms, updated by Shard::insert and remove under
+// the shard's write lock like the secondary indexes of index.rs. Declarations likewise live in memory only.
+//
+// Queries are terms, all of which a match has (AND), alternatives separated by `|` (OR), grouped with
+// parentheses. `term*` matches every term starting with `term`, and `*` alone every document. Matches are
+// ranked by how often the terms they matched occur in them.
+//
+// The schema may also have vector fields, see vector.rs. A query ending in `=>[KNN k @field $param]` asks
+// instead for the k matches whose vector in the field is nearest the one given as a parameter. For `*` that's
+// the vector index's search; otherwise the matches' vectors are compared one by one.
+use std::collections::{BTreeMap, HashMap};
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use crate::json::{Json, Path};
+use crate::storage::StoredValue;
+use crate::vector::{VectorIndex, VectorParams};
+
+// Bounds how deeply parentheses nest in a query, so parsing one can't overflow the stack
+const MAX_NESTING: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DocumentKind {
+    #[default]
+    Hash,
+    Json,
+}
+
+impl DocumentKind {
+    pub fn parse(name: &str) -> Result<DocumentKind> {
+        match name.to_lowercase().as_str() {
+            "hash" => Ok(DocumentKind::Hash),
+            "json" => Ok(DocumentKind::Json),
+            _ => bail!("unknown document type '{}', expected HASH or JSON", name),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DocumentKind::Hash => "HASH",
+            DocumentKind::Json => "JSON",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchDef {
+    pub name: String,
+    pub on: DocumentKind,
+    pub prefixes: Vec<Bytes>, // Keys starting with any of them are indexed, all keys when empty
+    pub fields: Vec<String>, // Text fields: hash field names, or JSONPaths for documents
+    pub vectors: Vec<(String, VectorParams)>, // Vector fields, named likewise
+    paths: Vec<Path>, // `fields` parsed, for documents
+    vector_paths: Vec<Path>, // And the vector fields
+}
+
+// What a search index holds of a key
+#[derive(Debug, Default)]
+pub struct Document {
+    terms: HashMap<String, u32>, // How often each term occurs
+    vectors: Vec<Option<Vec<f32>>>, // One per vector field, None where it's missing or malformed
+}
+
+impl SearchDef {
+    pub fn new(name: String, on: DocumentKind, prefixes: Vec<Bytes>, fields: Vec<String>, vectors: Vec<(String, VectorParams)>) -> Result<SearchDef> {
+        let parse = |fields: &mut dyn Iterator<Item = &String>| match on {
+            DocumentKind::Hash => Ok(vec![]),
+            DocumentKind::Json => fields.map(|field| Path::parse(field).or_else(|e| bail!("invalid path '{}': {}", field, e))).collect::<Result<_>>(),
+        };
+        let paths = parse(&mut fields.iter())?;
+        let vector_paths = parse(&mut vectors.iter().map(|(field, _)| field))?;
+        Ok(SearchDef { name, on, prefixes, fields, vectors, paths, vector_paths })
+    }
+
+    // The index of a vector field by name
+    pub fn vector_field(&self, name: &str) -> Option<usize> {
+        self.vectors.iter().position(|(field, _)| field == name)
+    }
+
+    pub fn covers(&self, key: &[u8]) -> bool {
+        self.prefixes.is_empty() || self.prefixes.iter().any(|prefix| key.starts_with(prefix))
+    }
+
+    // What the index holds of a key's value, None when it doesn't cover the key
+    pub fn document(&self, key: &[u8], value: &StoredValue) -> Option<Document> {
+        if !self.covers(key) {
+            return None;
+        }
+        let mut terms = HashMap::new();
+        let mut count = |text: &str| {
+            for term in tokenize(text) {
+                *terms.entry(term).or_insert(0) += 1;
+            }
+        };
+        let vectors = match (self.on, value) {
+            (DocumentKind::Hash, StoredValue::Hash(hash)) => {
+                for field in &self.fields {
+                    if let Some(text) = hash.get(field.as_bytes()) {
+                        count(&String::from_utf8_lossy(text));
+                    }
+                }
+                self.vectors.iter().map(|(field, params)| params.from_blob(hash.get(field.as_bytes())?)).collect()
+            },
+            (DocumentKind::Json, StoredValue::Json(doc)) => {
+                for path in &self.paths {
+                    for location in path.find(doc) {
+                        if let Json::String(text) = doc.at(&location) {
+                            count(text);
+                        }
+                    }
+                }
+                let first = |path: &Path| path.find(doc).into_iter().next().map(|location| doc.at(&location));
+                self.vector_paths.iter().zip(&self.vectors).map(|(path, (_, params))| params.from_json(first(path)?)).collect()
+            },
+            _ => return None,
+        };
+        Some(Document { terms, vectors })
+    }
+}
+
+// The terms of some text, in order
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()).map(str::to_lowercase)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    All,
+    Term(String),
+    Prefix(String),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+}
+
+// The `=>[KNN k @field $param [EF_RUNTIME ef]]` a query may end in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Knn {
+    pub k: usize,
+    pub field: String,
+    pub param: String,
+    pub ef_runtime: Option<usize>,
+}
+
+impl Knn {
+    // Splits a query into what it filters on and its KNN clause
+    pub fn split(text: &str) -> Result<(&str, Option<Knn>)> {
+        let Some((filter, clause)) = text.split_once("=>") else {
+            return Ok((text, None));
+        };
+        let Some(clause) = clause.trim().strip_prefix('[').and_then(|clause| clause.strip_suffix(']')) else {
+            bail!("expected [KNN k @field $param] after =>");
+        };
+        let words: Vec<&str> = clause.split_whitespace().collect();
+        let (k, field, param, rest) = match &words[..] {
+            [knn, k, field, param, rest @ ..] if knn.eq_ignore_ascii_case("knn") => (k, field, param, rest),
+            _ => bail!("expected [KNN k @field $param] after =>"),
+        };
+        let (Some(field), Some(param)) = (field.strip_prefix('@'), param.strip_prefix('$')) else {
+            bail!("expected [KNN k @field $param] after =>");
+        };
+        let ef_runtime = match rest {
+            [] => None,
+            [name, ef] if name.eq_ignore_ascii_case("ef_runtime") => Some(ef.parse().ok().filter(|&ef| ef > 0).ok_or_else(|| anyhow::anyhow!("invalid EF_RUNTIME"))?),
+            _ => bail!("unexpected '{}' in the KNN clause", rest.join(" ")),
+        };
+        let k = k.parse().map_err(|_| anyhow::anyhow!("invalid KNN count '{}'", k))?;
+        let filter = match filter.trim() {
+            "" => "*",
+            filter => filter,
+        };
+        Ok((filter, Some(Knn { k, field: field.to_string(), param: param.to_string(), ef_runtime })))
+    }
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Result<Query> {
+        let mut parser = QueryParser { chars: text.chars().peekable(), depth: 0 };
+        let query = parser.or()?;
+        match parser.chars.next() {
+            Some(c) => bail!("unexpected '{}'", c),
+            None => Ok(query),
+        }
+    }
+}
+
+struct QueryParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    depth: usize,
+}
+
+impl QueryParser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn or(&mut self) -> Result<Query> {
+        let mut alternatives = vec![self.and()?];
+        while self.chars.next_if_eq(&'|').is_some() {
+            alternatives.push(self.and()?);
+        }
+        Ok(match alternatives.len() {
+            1 => alternatives.pop().expect("one alternative"),
+            _ => Query::Or(alternatives),
+        })
+    }
+
+    fn and(&mut self) -> Result<Query> {
+        let mut all = vec![];
+        loop {
+            self.skip_whitespace();
+            match self.chars.peek() {
+                None | Some('|' | ')') => break,
+                Some('(') => {
+                    self.chars.next();
+                    self.depth += 1;
+                    if self.depth > MAX_NESTING {
+                        bail!("query nested too deeply");
+                    }
+                    all.push(self.or()?);
+                    self.depth -= 1;
+                    if self.chars.next() != Some(')') {
+                        bail!("expected ')'");
+                    }
+                },
+                Some(_) => all.extend(self.word()?),
+            }
+        }
+        Ok(match all.len() {
+            0 => bail!("expected a term"),
+            1 => all.pop().expect("one term"),
+            _ => Query::And(all),
+        })
+    }
+
+    // A word up to whitespace or an operator, as the terms it splits into: `foo-bar` is foo AND bar. A `*` at
+    // its end makes the last term a prefix.
+    fn word(&mut self) -> Result<Vec<Query>> {
+        let mut word = String::new();
+        while let Some(c) = self.chars.next_if(|c| !c.is_whitespace() && !"|()".contains(*c)) {
+            word.push(c);
+        }
+        if word == "*" {
+            return Ok(vec![Query::All]);
+        }
+        let prefix = word.ends_with('*');
+        let mut terms: Vec<Query> = tokenize(word.trim_end_matches('*')).map(Query::Term).collect();
+        if prefix {
+            match terms.pop() {
+                Some(Query::Term(term)) => terms.push(Query::Prefix(term)),
+                _ => bail!("expected a term before '*'"),
+            }
+        }
+        if terms.is_empty() {
+            bail!("'{}' has no terms to search for", word);
+        }
+        Ok(terms)
+    }
+}
+
+// One shard's part of a search index
+#[derive(Debug)]
+pub struct SearchPartition {
+    postings: BTreeMap<String, HashMap<Bytes, u32>>, // For each term, the keys it occurs in and how often
+    docs: HashMap<Bytes, Vec<String>>, // Each indexed key's terms, to find its postings again
+    vectors: Vec<VectorIndex>, // One per vector field
+}
+
+impl SearchPartition {
+    pub fn new(def: &SearchDef) -> SearchPartition {
+        let vectors = def.vectors.iter().map(|(_, params)| VectorIndex::new(*params)).collect();
+        SearchPartition { postings: BTreeMap::new(), docs: HashMap::new(), vectors }
+    }
+
+    // Indexes `key` as `document` in place of what it had, None leaving it out
+    pub fn insert(&mut self, key: &Bytes, document: Option<Document>) {
+        self.remove(key);
+        let Some(document) = document else {
+            return;
+        };
+        let mut names = Vec::with_capacity(document.terms.len());
+        for (term, count) in document.terms {
+            self.postings.entry(term.clone()).or_default().insert(key.clone(), count);
+            names.push(term);
+        }
+        self.docs.insert(key.clone(), names);
+        for (index, vector) in self.vectors.iter_mut().zip(document.vectors) {
+            if let Some(vector) = vector {
+                index.insert(key, vector);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &Bytes) {
+        if !self.docs.contains_key(key) {
+            return;
+        }
+        for index in &mut self.vectors {
+            index.remove(key);
+        }
+        for term in self.docs.remove(key).unwrap_or_default() {
+            if let Some(keys) = self.postings.get_mut(&term) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.docs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.docs.is_empty()
+    }
+
+    // The `knn.k` keys matching `filter` whose vector in `field` is nearest `vector`, nearest first with their
+    // distances
+    pub fn nearest(&self, filter: &Query, field: usize, vector: &[f32], knn: &Knn) -> Vec<(f32, &Bytes)> {
+        let index = &self.vectors[field];
+        if *filter == Query::All {
+            return index.nearest(vector, knn.k, knn.ef_runtime);
+        }
+        let matches = self.search(filter).into_keys().filter_map(|key| Some((index.params().distance(vector, index.get(key)?), key)));
+        crate::vector::nearest(matches, knn.k)
+    }
+
+    // The keys matching `query`, each with its score
+    pub fn search(&self, query: &Query) -> HashMap<&Bytes, u32> {
+        match query {
+            Query::All => self.docs.keys().map(|key| (key, 0)).collect(),
+            Query::Term(term) => self.postings.get(term).map(|keys| keys.iter().map(|(key, count)| (key, *count)).collect()).unwrap_or_default(),
+            Query::Prefix(prefix) => {
+                let mut found = HashMap::new();
+                for (_, keys) in self.postings.range(prefix.clone()..).take_while(|(term, _)| term.starts_with(prefix.as_str())) {
+                    for (key, count) in keys {
+                        *found.entry(key).or_insert(0) += count;
+                    }
+                }
+                found
+            },
+            Query::And(all) => {
+                let mut all = all.iter();
+                let mut found = all.next().map(|query| self.search(query)).unwrap_or_default();
+                for query in all {
+                    if found.is_empty() {
+                        break;
+                    }
+                    let next = self.search(query);
+                    found.retain(|key, score| match next.get(key) {
+                        Some(more) => {
+                            *score += more;
+                            true
+                        },
+                        None => false,
+                    });
+                }
+                found
+            },
+            Query::Or(alternatives) => {
+                let mut found = HashMap::new();
+                for query in alternatives {
+                    for (key, score) in self.search(query) {
+                        *found.entry(key).or_insert(0) += score;
+                    }
+                }
+                found
+            },
+        }
+    }
+}
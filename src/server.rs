@@ -0,0 +1,335 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task::JoinSet;
+use anyhow::Result;
+use crate::admin;
+use crate::audit::AuditLog;
+use crate::clients::Clients;
+use crate::clock::{Clock, SystemClock};
+use crate::bridge;
+use crate::bucket;
+use crate::commands::Registry;
+use crate::config::Config;
+use crate::connection;
+use crate::crypt::Keyring;
+use crate::daemon;
+use crate::executor::{ExecutionModel, Executor};
+use crate::grpc;
+use crate::http;
+use crate::listener::Listener;
+use crate::locks;
+use crate::log::{log_debug, log_error, log_info, log_warn};
+use crate::memcached;
+use crate::metrics;
+use crate::pubsub::PubSub;
+use crate::snapshot::Snapshots;
+use crate::stats::{self, Stats, SERVER_STATS};
+use crate::storage::{self, BackendKind, Storage};
+use crate::trace;
+use crate::webhook;
+
+// Connections accepted but not yet picked up by the serving loop
+const ACCEPT_BACKLOG: usize = 128;
+pub(crate) const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+// State shared by every connection
+#[derive(Clone)]
+pub struct Server {
+    pub storage: Arc<Storage>,
+    pub stats: Arc<Mutex<Stats>>,
+    pub pubsub: Arc<PubSub>,
+    pub clients: Arc<Clients>,
+    pub snapshots: Arc<Snapshots>,
+    pub audit: Arc<AuditLog>,
+    config: Arc<RwLock<Arc<Config>>>, // Swapped whole on reload, read through config()
+    pub commands: Arc<Registry>,
+    pub executor: Option<Arc<Executor>>, // Runs every command when command-execution is executor
+    ready: Arc<AtomicBool>, // Accepting clients and not shutting down, see admin.rs
+    adopted: Arc<Mutex<Vec<std::net::TcpListener>>>, // Bound by the embedder for the services, see adopt_listener
+}
+
+impl Server {
+    pub fn new(config: Config) -> Result<Self> {
+        Server::with_clock(config, Arc::new(SystemClock))
+    }
+
+    // A server whose key expiry follows `clock` rather than the system time. Fails when the disk backend's
+    // data can't be loaded.
+    pub fn with_clock(config: Config, clock: Arc<dyn Clock>) -> Result<Self> {
+        config.check()?;
+        let executor = match config.command_execution {
+            ExecutionModel::Inline => None,
+            ExecutionModel::Executor => Some(Arc::new(Executor::start())),
+        };
+        let keys = Arc::new(Keyring::from_config(&config).map_err(|e| anyhow::anyhow!("Can't set up encryption: {}", e))?);
+        if let Some(id) = keys.current_id() {
+            log_info!("Encrypting data files with the key '{}'", id);
+        }
+        let storage = match config.wal {
+            true => Storage::open_wal(config.storage_backend, config.storage_shards, config.keyspace_hasher, &config.dir, config.wal_segment_size, keys, clock),
+            false => Storage::open(config.storage_backend, config.storage_shards, config.keyspace_hasher, &config.dir, keys, clock),
+        };
+        let storage = storage.map_err(|e| anyhow::anyhow!("Can't load the data in '{}': {}", config.dir.display(), e))?;
+        storage.set_compression_threshold(config.compression_threshold);
+        storage.set_tiering_idle_time(config.tiering_idle_time);
+        if config.storage_backend == BackendKind::Disk || config.wal {
+            log_info!("Loaded {} keys from disk", storage.len());
+        }
+        if let Some(name) = &config.snapshot_restore {
+            let bucket = config.snapshot_bucket.as_ref().map_or("", |bucket| bucket.as_str());
+            let loaded = bucket::restore(&config, &storage, name).map_err(|e| anyhow::anyhow!("Can't restore the snapshot '{}' from {}: {}", name, bucket, e))?;
+            log_info!("Restored {} keys from the snapshot '{}' in {}", loaded, name, bucket);
+        }
+        let audit = match &config.audit_log {
+            Some(path) => AuditLog::open(path, config.audit_max_size, config.audit_max_files)
+                .map_err(|e| anyhow::anyhow!("Can't open the audit log '{}': {}", path, e))?,
+            None => AuditLog::disabled(),
+        };
+        Ok(Server {
+            storage: Arc::new(storage),
+            stats: Arc::new(Mutex::new(Stats::new())),
+            pubsub: Arc::new(PubSub::new()),
+            clients: Arc::new(Clients::new()),
+            snapshots: Arc::new(Snapshots::new()),
+            audit: Arc::new(audit),
+            config: Arc::new(RwLock::new(Arc::new(config))),
+            commands: Arc::new(Registry::new()),
+            executor,
+            ready: Arc::new(AtomicBool::new(false)),
+            adopted: Arc::default(),
+        })
+    }
+
+    // Whether clients are being accepted, the server not yet shutting down
+    pub fn ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    // Has whichever of the HTTP gateway, gRPC service, memcached listener and admin endpoints is configured
+    // for the port `listener` is bound to serve on it, instead of binding the port on the bind addresses. For
+    // embedders, and tests, that bind ephemeral ports up front rather than pick one that's free and hope.
+    pub fn adopt_listener(&self, listener: std::net::TcpListener) {
+        locks::lock(&self.adopted).push(listener);
+    }
+
+    // The listeners adopted for `port`, taken so only the first service to ask gets them
+    pub(crate) fn take_adopted(&self, port: u16) -> Vec<std::net::TcpListener> {
+        let mut adopted = locks::lock(&self.adopted);
+        let (taken, kept) = adopted.drain(..).partition(|listener| listener.local_addr().is_ok_and(|addr| addr.port() == port));
+        *adopted = kept;
+        taken
+    }
+
+    // The configuration currently in effect
+    pub fn config(&self) -> Arc<Config> {
+        Arc::clone(&locks::read(&self.config))
+    }
+
+    // Re-reads the config file and applies what can change at runtime: logging, limits, the password,
+    // compression and tiering apply to the next log line, connection, command, write or spill cycle, the
+    // rest waits for a restart
+    pub fn reload_config(&self) -> Result<()> {
+        let running = self.config();
+        let (config, changes) = running.reload()?;
+        config.check()?;
+        if changes.is_empty() {
+            log_info!("Config reloaded, nothing changed");
+        }
+        for change in &changes {
+            let values = match change.secret {
+                true => String::new(), // Kept out of the logs
+                false => format!(" from '{}' to '{}'", change.old, change.new),
+            };
+            if change.needs_restart {
+                log_warn!("Config reload: {} changed{}, takes effect after a restart", change.name, values);
+            } else {
+                log_info!("Config reload: {} changed{}", change.name, values);
+            }
+        }
+        if changes.iter().any(|change| change.name.starts_with("log")) {
+            config.init_logging()?;
+        }
+        self.storage.set_compression_threshold(config.compression_threshold);
+        self.storage.set_tiering_idle_time(config.tiering_idle_time);
+        *locks::write(&self.config) = Arc::new(config);
+        Ok(())
+    }
+
+    // Starts the background tasks and serves clients from `listeners`
+    pub async fn run(self, listeners: Vec<Listener>) -> Result<()> {
+        self.run_until(listeners, std::future::pending()).await
+    }
+
+    // Like run, but stops once `shutdown` completes: the listeners are closed and the background tasks and
+    // client connections are aborted before returning
+    pub async fn run_until(self, listeners: Vec<Listener>, shutdown: impl Future<Output = ()>) -> Result<()> {
+        let mut background = JoinSet::new();
+        // Up first, so probes hear the server isn't ready rather than nothing at all
+        if let Some(port) = self.config().admin_port {
+            let server = self.clone();
+            background.spawn(async move {
+                if let Err(e) = admin::serve(server, port).await {
+                    log_error!("Admin endpoints failed: {:?}", e);
+                }
+            });
+        }
+        // Each listener accepts on its own task and hands the connections over
+        let (accepted_sender, mut accepted) = mpsc::channel(ACCEPT_BACKLOG);
+        let mut socket_files = vec![];
+        for listener in listeners {
+            log_info!("Ready to accept connections on {}", listener.local_addr()?);
+            if let Listener::Unix(_, path) = &listener {
+                socket_files.push(path.clone());
+            }
+            let accepted_sender = accepted_sender.clone();
+            let server = self.clone();
+            background.spawn(async move {
+                loop {
+                    match listener.accept(server.config().tcp_keepalive > 0).await {
+                        Ok(accepted) => {
+                            if accepted_sender.send(accepted).await.is_err() {
+                                return;
+                            }
+                        },
+                        // Typically out of file descriptors: back off while connections close
+                        Err(e) => {
+                            log_warn!("Accepting a client failed: {}", e);
+                            tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                        },
+                    }
+                }
+            });
+        }
+        drop(accepted_sender);
+
+        let config = self.config();
+        if let Some(path) = &config.pidfile {
+            daemon::write_pidfile(path)?;
+        }
+        warn_about_open_files_limit(config.maxclients);
+        background.spawn(storage::active_expire(Arc::clone(&self.storage), config.hz, self.executor.clone()));
+        background.spawn(reload_on_sighup(self.clone()));
+        if let Some(endpoint) = &config.otlp_endpoint {
+            trace::init_otlp(endpoint);
+        }
+        if !config.webhooks.is_empty() {
+            background.spawn(webhook::run(self.storage.subscribe(), config.webhooks.clone()));
+        }
+        for bridge in &config.bridges {
+            background.spawn(bridge::start(bridge.clone(), &self.storage, &self.pubsub));
+        }
+        if let Some(port) = config.metrics_port {
+            let storage_clone = Arc::clone(&self.storage);
+            let stats_clone = Arc::clone(&self.stats);
+            background.spawn(async move {
+                if let Err(e) = metrics::serve(port, storage_clone, stats_clone).await {
+                    log_error!("Metrics endpoint failed: {:?}", e);
+                }
+            });
+        }
+        if let Some(port) = config.http_port {
+            let server = self.clone();
+            background.spawn(async move {
+                if let Err(e) = http::serve(server, port).await {
+                    log_error!("HTTP gateway failed: {:?}", e);
+                }
+            });
+        }
+        if let Some(port) = config.grpc_port {
+            let server = self.clone();
+            background.spawn(async move {
+                if let Err(e) = grpc::serve(server, port).await {
+                    log_error!("gRPC service failed: {:?}", e);
+                }
+            });
+        }
+        if let Some(port) = config.memcached_port {
+            let server = self.clone();
+            background.spawn(async move {
+                if let Err(e) = memcached::serve(server, port).await {
+                    log_error!("memcached listener failed: {:?}", e);
+                }
+            });
+        }
+        if let Some(interval) = daemon::watchdog_interval() {
+            background.spawn(daemon::ping_watchdog(interval));
+        }
+        daemon::notify("READY=1");
+        self.ready.store(true, Ordering::Relaxed);
+
+        let mut connections = JoinSet::new();
+        tokio::pin!(shutdown);
+        loop {
+            let accepted = tokio::select! {
+                accepted = accepted.recv() => match accepted {
+                    Some(accepted) => accepted,
+                    None => break, // No listeners
+                },
+                _ = &mut shutdown => break,
+                // Reap finished connections so the set doesn't grow with every client ever served
+                Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            };
+            log_debug!("Accepted connection from {}", accepted.addr);
+            stats::incr(&SERVER_STATS.total_connections_received, 1);
+
+            let server = self.clone();
+            connections.spawn(async move {
+                stats::incr(&SERVER_STATS.connected_clients, 1);
+                let result = connection::handle(accepted, server).await;
+                stats::decr(&SERVER_STATS.connected_clients, 1);
+                result
+            });
+        }
+
+        self.ready.store(false, Ordering::Relaxed);
+        log_info!("Shutting down");
+        daemon::notify("STOPPING=1");
+        connections.shutdown().await;
+        background.shutdown().await;
+        for path in socket_files.iter().chain(&config.pidfile) {
+            let _ = std::fs::remove_file(path);
+        }
+        Ok(())
+    }
+}
+
+// Descriptors kept for listeners, logfiles and the like when comparing maxclients to the open files limit
+const RESERVED_FDS: u64 = 32;
+
+// Each client holds a file descriptor, so a maxclients above the process limit can't actually be reached
+fn warn_about_open_files_limit(maxclients: usize) {
+    let Some(limit) = open_files_limit() else {
+        return;
+    };
+    if maxclients as u64 + RESERVED_FDS > limit {
+        log_warn!(
+            "maxclients is {} but the open files limit is {}: accepting fails past about {} clients. Raise the limit \
+             (ulimit -n) or lower maxclients.",
+            maxclients, limit, limit.saturating_sub(RESERVED_FDS)
+        );
+    }
+}
+
+// The soft RLIMIT_NOFILE, read from procfs as there's no libc binding
+fn open_files_limit() -> Option<u64> {
+    let limits = std::fs::read_to_string("/proc/self/limits").ok()?;
+    let line = limits.lines().find(|line| line.starts_with("Max open files"))?;
+    line["Max open files".len()..].split_whitespace().next()?.parse().ok()
+}
+
+async fn reload_on_sighup(server: Server) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => return log_error!("Can't listen for SIGHUP: {:?}", e),
+    };
+    while hangups.recv().await.is_some() {
+        log_info!("Received SIGHUP, reloading the configuration");
+        if let Err(e) = server.reload_config() {
+            log_warn!("Config reload failed, keeping the running configuration: {}", e);
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use tokio::sync::mpsc::UnboundedSender;
+use std::collections::{HashMap, HashSet};
+use bytes::Bytes;
+use crate::cdc::Follow;
+use crate::resp::Value;
+
+// Commands queued between MULTI and EXEC
+#[derive(Debug, Default)]
+pub struct Transaction {
+    pub commands: Vec<(&'static str, Vec<Value>)>,
+    pub failed: bool, // A command was rejected while queueing, EXEC aborts
+}
+
+// Everything the server knows about one client connection
+pub struct Session {
+    pub id: u64,
+    pub peer: String,
+    pub name: Option<Bytes>, // CLIENT SETNAME
+    pub db: usize,
+    pub authenticated: bool,
+    pub tenant: Option<String>, // The tenant authenticated as, None for the default user, see tenant.rs
+    pub protocol: u8, // 2 or 3, switched by HELLO
+    pub multi: Option<Transaction>, // Some between MULTI and EXEC/DISCARD
+    pub watched: HashMap<Bytes, u64>, // WATCHed key -> version when it was watched
+    pub subscriptions: HashSet<Bytes>,
+    pub sender: UnboundedSender<Value>, // Feeds published messages back into this connection
+    pub follow: Option<Follow>, // Set by CDC, the connection becomes a change feed after replying
+}
+
+impl Session {
+    pub fn new(id: u64, peer: String, sender: UnboundedSender<Value>, authenticated: bool) -> Self {
+        Session {
+            id,
+            peer,
+            name: None,
+            db: 0,
+            authenticated,
+            tenant: None,
+            protocol: 2,
+            multi: None,
+            watched: HashMap::new(),
+            subscriptions: HashSet::new(),
+            sender,
+            follow: None,
+        }
+    }
+
+    // Marks the open transaction, if any, as failed after a command was rejected
+    pub fn flag_multi_error(&mut self) {
+        if let Some(transaction) = self.multi.as_mut() {
+            transaction.failed = true;
+        }
+    }
+
+    pub fn subscribed(&self) -> bool {
+        !self.subscriptions.is_empty()
+    }
+}
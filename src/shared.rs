@@ -0,0 +1,18 @@
+use std::sync::OnceLock;
+use bytes::Bytes;
+
+// Like Redis's shared integers: the values "0" to "9999" have one copy each, handed out whenever an integer
+// value in that range is read, so reading a small counter doesn't allocate.
+pub const SHARED_INTEGERS: usize = 10_000;
+
+static INTEGERS: OnceLock<Vec<Bytes>> = OnceLock::new();
+
+// `n` as a string, the shared copy when there is one
+pub fn integer(n: i64) -> Bytes {
+    match usize::try_from(n) {
+        Ok(n) if n < SHARED_INTEGERS => {
+            INTEGERS.get_or_init(|| (0..SHARED_INTEGERS).map(|n| Bytes::from(n.to_string())).collect())[n].clone()
+        },
+        _ => Bytes::from(n.to_string()),
+    }
+}
@@ -0,0 +1,200 @@
+// Point-in-time snapshots of the keyspace, written by SNAPSHOT EXPORT and loaded by SNAPSHOT IMPORT, for ad-hoc
+// backups and copying a data set between servers. A snapshot is a file in the format of record.rs: a snapshot
+// record identifying it, then one set record per live key, values as they're kept in memory, so compressed
+// ones stay compressed. Keys are written as Storage::snapshot reads them, writes carry on meanwhile. Files are
+// written to a temporary file next to the destination, fsynced and renamed into place, so a path only ever
+// holds a whole snapshot. With encryption on, snapshots are encrypted with the current key, and those
+// encrypted with older keys still load as long as their key is at hand, see crypt.rs.
+//
+// An incremental snapshot only holds the keys written or deleted since the server's previous export, as set
+// and del records, and names that export as its base. Loading one loads its base first, and so on down the
+// chain to a full snapshot. Bases are found by the path they were written to, relative to the incremental
+// snapshot's directory when they share it, and must carry the id the chain expects, so a base overwritten
+// since is caught. Changes are only tracked in memory: after a restart, or an export that failed, the next
+// one has to be full.
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use bytes::Bytes;
+use crate::crypt::{self, Keyring};
+use crate::locks;
+use crate::record::{self, FileKind, Record, Values};
+use crate::storage::{Storage, StoredValue};
+
+// Longest chain of incremental snapshots followed before giving up on a cycle
+const MAX_CHAIN: usize = 10_000;
+
+// What happens to keys already there when a snapshot is loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    Merge, // The snapshot's keys overwrite those of the same name, the rest stay
+    Replace, // Every other key is deleted
+}
+
+// The server's last export, which the next incremental one follows. Held for a whole export, so exports
+// happen one at a time.
+#[derive(Default)]
+pub struct Snapshots {
+    last: Mutex<Option<(u64, PathBuf)>>,
+}
+
+impl Snapshots {
+    pub fn new() -> Self {
+        Snapshots::default()
+    }
+
+    // Writes the keyspace as it is now to `path`, or with `incremental` only what changed since the last
+    // export. Returns how many keys the file holds.
+    pub fn export(&self, storage: &Storage, path: &Path, incremental: bool) -> io::Result<usize> {
+        let mut last = locks::lock(&self.last);
+        let id = new_id();
+        let written = match incremental {
+            false => {
+                write(path, storage.keyring(), record::snapshot(id, None), |out| {
+                    storage.snapshot(|key, value, expires_at| match record::set(key, value, expires_at) {
+                        Some(record) => out.write_all(&record),
+                        None => Ok(()),
+                    })
+                })
+            },
+            true => {
+                let Some((base_id, base_path)) = last.as_ref() else {
+                    return Err(io::Error::other("there's no earlier snapshot since startup to follow, export a full one first"));
+                };
+                if base_path == path {
+                    return Err(io::Error::other("that would overwrite the snapshot it follows"));
+                }
+                // Failing to read the changes loses them just like failing to write them
+                let changes = match storage.snapshot_changes() {
+                    Ok(None) => return Err(io::Error::other("changes since the last snapshot weren't tracked, export a full one first")),
+                    changes => changes.map(Option::unwrap_or_default),
+                };
+                let base = match (base_path.parent(), base_path.file_name()) {
+                    (Some(dir), Some(name)) if path.parent() == Some(dir) => Path::new(name),
+                    _ => base_path.as_path(),
+                };
+                changes.and_then(|changes| write(path, storage.keyring(), record::snapshot(id, Some((*base_id, base))), |out| {
+                    for (key, entry) in &changes {
+                        let record = match entry {
+                            Some((value, expires_at)) => record::set(key, value, *expires_at),
+                            None => Some(record::del(key)),
+                        };
+                        out.write_all(&record.unwrap_or_default())?;
+                    }
+                    Ok(changes.len())
+                }))
+            },
+        };
+        match written {
+            Ok(_) => *last = Some((id, path.to_path_buf())),
+            Err(_) => {
+                // The changes handed out are lost, an incremental snapshot can't follow on from here
+                storage.forget_changes();
+                *last = None;
+            },
+        }
+        written
+    }
+}
+
+// Loads the snapshot at `path`, with its bases for an incremental one, returning how many keys it set. Every
+// file is read and checked before the keyspace is touched, a damaged chain changes nothing. Keys whose
+// deadline passed since the export are skipped.
+pub fn import(storage: &Storage, path: &Path, mode: ImportMode) -> io::Result<usize> {
+    let mut chain = vec![];
+    let mut next = Some((path.to_path_buf(), None));
+    while let Some((path, expected_id)) = next.take() {
+        if chain.len() == MAX_CHAIN {
+            return Err(io::Error::other(format!("more than {} incremental snapshots chained, is there a cycle?", MAX_CHAIN)));
+        }
+        let SnapshotFile { id, base, records } = read(&path, storage.keyring())?;
+        if expected_id.is_some_and(|expected| expected != id) {
+            return Err(io::Error::other(format!("{} isn't the snapshot the one after it follows, it was overwritten since", path.display())));
+        }
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        next = base.map(|(base_id, base_path)| (dir.join(base_path), Some(base_id)));
+        chain.push(records);
+    }
+
+    let mut keys: HashMap<Bytes, (StoredValue, Option<u64>)> = HashMap::new();
+    for records in chain.into_iter().rev() {
+        for record in records {
+            match record {
+                Record::Set { key, value, expires_at } => {
+                    keys.insert(key, (value, expires_at));
+                },
+                Record::Del { key } => {
+                    keys.remove(&key);
+                },
+                _ => {},
+            }
+        }
+    }
+    if mode == ImportMode::Replace {
+        storage.clear();
+    }
+    let now = storage.now_ms();
+    let mut loaded = 0;
+    for (key, (value, expires_at)) in keys {
+        if expires_at.is_some_and(|deadline| deadline <= now) {
+            continue;
+        }
+        storage.restore(key, value, expires_at);
+        loaded += 1;
+    }
+    Ok(loaded)
+}
+
+// The path the incremental snapshot at `path` names as its base, relative to its directory when they share
+// it, None for a full snapshot
+pub fn base(path: &Path, keys: &Keyring) -> io::Result<Option<PathBuf>> {
+    Ok(read(path, keys)?.base.map(|(_, base)| base))
+}
+
+// Unique enough to tell snapshots apart: the time, nanoseconds since the epoch
+fn new_id() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(1, |elapsed| elapsed.as_nanos() as u64).max(1)
+}
+
+// Writes the snapshot record `header`, then whatever `records` writes, returning what it does
+fn write(path: &Path, keys: &Keyring, header: Vec<u8>, records: impl FnOnce(&mut crypt::Writer<BufWriter<File>>) -> io::Result<usize>) -> io::Result<usize> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let written: io::Result<usize> = (|| {
+        let cipher = keys.cipher()?;
+        let file_header = record::file_header(FileKind::Snapshot, cipher.as_ref());
+        let mut out = crypt::Writer::new(BufWriter::new(File::create(&temp)?), cipher, file_header.len() as u64);
+        out.get_mut().write_all(&file_header)?;
+        out.write_all(&header)?;
+        let count = records(&mut out)?;
+        out.flush()?;
+        out.get_ref().get_ref().sync_all()?;
+        fs::rename(&temp, path)?;
+        Ok(count)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+// A snapshot file read back
+struct SnapshotFile {
+    id: u64,
+    base: Option<(u64, PathBuf)>, // Id and path of the snapshot an incremental one follows
+    records: Vec<Record>, // After the snapshot record
+}
+
+fn read(path: &Path, keys: &Keyring) -> io::Result<SnapshotFile> {
+    let file = File::open(path)?;
+    let mut records = vec![];
+    record::replay(&file, path, FileKind::Snapshot, Values::InMemory, false, keys, |record| records.push(record))?;
+    let mut records = records.into_iter();
+    match records.next() {
+        Some(Record::Snapshot { id, base }) => Ok(SnapshotFile { id, base, records: records.collect() }),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} doesn't start with a snapshot record", path.display()))),
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// Upper bounds of the latency histogram buckets, in microseconds
+pub const LATENCY_BUCKETS_USEC: [u64; 9] = [10, 50, 100, 500, 1_000, 5_000, 10_000, 50_000, 100_000];
+
+#[derive(Debug, Default)]
+pub struct CommandStat {
+    pub calls: u64,
+    pub usec: u64,
+    pub usec_max: u64,
+    pub rejected_calls: u64,
+    pub failed_calls: u64,
+    pub latency_buckets: [u64; LATENCY_BUCKETS_USEC.len()], // Calls above the last bound only show up in `calls`
+}
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub commands: HashMap<String, CommandStat>,
+    pub errors: HashMap<String, u64>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Stats::default()
+    }
+
+    pub fn record_call(&mut self, command: &str, elapsed: Duration, failed: bool) {
+        let usec = elapsed.as_micros() as u64;
+        let stat = self.stat(command);
+        stat.calls += 1;
+        stat.usec += usec;
+        stat.usec_max = stat.usec_max.max(usec);
+        if let Some(bucket) = LATENCY_BUCKETS_USEC.iter().position(|bound| usec <= *bound) {
+            stat.latency_buckets[bucket] += 1;
+        }
+        if failed {
+            stat.failed_calls += 1;
+        }
+    }
+
+    // Rejected calls never ran (bad arity etc.), so they don't count towards calls/usec
+    pub fn record_rejected(&mut self, command: &str) {
+        self.stat(command).rejected_calls += 1;
+    }
+
+    // Only a command's first call allocates its name
+    fn stat(&mut self, command: &str) -> &mut CommandStat {
+        if !self.commands.contains_key(command) {
+            self.commands.insert(command.to_string(), CommandStat::default());
+        }
+        self.commands.get_mut(command).expect("inserted above")
+    }
+
+    pub fn record_error(&mut self, prefix: &str) {
+        *self.errors.entry(prefix.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn commandstats(&self) -> String {
+        let mut out = String::from("# Commandstats\r\n");
+        let mut names: Vec<&String> = self.commands.keys().collect();
+        names.sort();
+        for name in names {
+            let stat = &self.commands[name];
+            let per_call = if stat.calls > 0 { stat.usec as f64 / stat.calls as f64 } else { 0.0 };
+            let _ = write!(
+                out,
+                "cmdstat_{}:calls={},usec={},usec_per_call={:.2},usec_max={},rejected_calls={},failed_calls={}\r\n",
+                name, stat.calls, stat.usec, per_call, stat.usec_max, stat.rejected_calls, stat.failed_calls
+            );
+        }
+        out
+    }
+
+    pub fn errorstats(&self) -> String {
+        let mut out = String::from("# Errorstats\r\n");
+        let mut prefixes: Vec<&String> = self.errors.keys().collect();
+        prefixes.sort();
+        for prefix in prefixes {
+            let _ = write!(out, "errorstat_{}:count={}\r\n", prefix, self.errors[prefix]);
+        }
+        out
+    }
+}
+
+// Server-wide counters updated from the storage and network paths, surfaced via INFO stats
+#[derive(Debug)]
+pub struct ServerStats {
+    pub connected_clients: AtomicU64,
+    pub total_connections_received: AtomicU64,
+    pub rejected_connections: AtomicU64,
+    pub rate_limited_commands: AtomicU64,
+    pub aborted_commands: AtomicU64, // Stopped for running past command-time-budget
+    pub total_commands_processed: AtomicU64,
+    pub total_net_input_bytes: AtomicU64,
+    pub total_net_output_bytes: AtomicU64,
+    pub expired_keys: AtomicU64,
+    pub evicted_keys: AtomicU64,
+    pub keyspace_hits: AtomicU64,
+    pub keyspace_misses: AtomicU64,
+    pub tier_spills: AtomicU64, // Values moved to the cold tier
+    pub tier_hits: AtomicU64, // Reads with tiering on that found the value in memory
+    pub tier_misses: AtomicU64, // Reads that had to go to the cold tier
+    pub tier_promotions: AtomicU64, // Values moved back into memory
+    pub webhook_events_delivered: AtomicU64,
+    pub webhook_events_failed: AtomicU64, // Given up on after every retry failed
+    pub webhook_events_dropped: AtomicU64, // Not queued, the target being too far behind
+    pub webhook_retries: AtomicU64, // Deliveries attempted again after failing
+    pub bridge_messages_sent: AtomicU64,
+    pub bridge_messages_received: AtomicU64, // Ingested from NATS
+    pub bridge_messages_dropped: AtomicU64, // Not queued, the bridge being too far behind
+    pub bridge_connection_failures: AtomicU64,
+    pub cdc_frames_sent: AtomicU64,
+    pub cdc_consumers_dropped: AtomicU64, // Disconnected for falling too far behind
+}
+
+pub static SERVER_STATS: ServerStats = ServerStats {
+    connected_clients: AtomicU64::new(0),
+    total_connections_received: AtomicU64::new(0),
+    rejected_connections: AtomicU64::new(0),
+    rate_limited_commands: AtomicU64::new(0),
+    aborted_commands: AtomicU64::new(0),
+    total_commands_processed: AtomicU64::new(0),
+    total_net_input_bytes: AtomicU64::new(0),
+    total_net_output_bytes: AtomicU64::new(0),
+    expired_keys: AtomicU64::new(0),
+    evicted_keys: AtomicU64::new(0),
+    keyspace_hits: AtomicU64::new(0),
+    keyspace_misses: AtomicU64::new(0),
+    tier_spills: AtomicU64::new(0),
+    tier_hits: AtomicU64::new(0),
+    tier_misses: AtomicU64::new(0),
+    tier_promotions: AtomicU64::new(0),
+    webhook_events_delivered: AtomicU64::new(0),
+    webhook_events_failed: AtomicU64::new(0),
+    webhook_events_dropped: AtomicU64::new(0),
+    webhook_retries: AtomicU64::new(0),
+    bridge_messages_sent: AtomicU64::new(0),
+    bridge_messages_received: AtomicU64::new(0),
+    bridge_messages_dropped: AtomicU64::new(0),
+    bridge_connection_failures: AtomicU64::new(0),
+    cdc_frames_sent: AtomicU64::new(0),
+    cdc_consumers_dropped: AtomicU64::new(0),
+};
+
+pub fn incr(counter: &AtomicU64, by: u64) {
+    counter.fetch_add(by, Ordering::Relaxed);
+}
+
+pub fn decr(counter: &AtomicU64, by: u64) {
+    counter.fetch_sub(by, Ordering::Relaxed);
+}
+
+impl ServerStats {
+    pub fn clients_info(&self) -> String {
+        format!("# Clients\r\nconnected_clients:{}\r\n", self.connected_clients.load(Ordering::Relaxed))
+    }
+
+    // There's no replication, so this is always a master on its own, as Redis reports one
+    pub fn replication_info(&self) -> String {
+        "# Replication\r\nrole:master\r\nconnected_slaves:0\r\nmaster_repl_offset:0\r\n".to_string()
+    }
+
+    pub fn info(&self) -> String {
+        let fields = [
+            ("total_connections_received", &self.total_connections_received),
+            ("total_commands_processed", &self.total_commands_processed),
+            ("rejected_connections", &self.rejected_connections),
+            ("rate_limited_commands", &self.rate_limited_commands),
+            ("aborted_commands", &self.aborted_commands),
+            ("total_net_input_bytes", &self.total_net_input_bytes),
+            ("total_net_output_bytes", &self.total_net_output_bytes),
+            ("expired_keys", &self.expired_keys),
+            ("evicted_keys", &self.evicted_keys),
+            ("keyspace_hits", &self.keyspace_hits),
+            ("keyspace_misses", &self.keyspace_misses),
+            ("tier_spills", &self.tier_spills),
+            ("tier_hits", &self.tier_hits),
+            ("tier_misses", &self.tier_misses),
+            ("tier_promotions", &self.tier_promotions),
+            ("webhook_events_delivered", &self.webhook_events_delivered),
+            ("webhook_events_failed", &self.webhook_events_failed),
+            ("webhook_events_dropped", &self.webhook_events_dropped),
+            ("webhook_retries", &self.webhook_retries),
+            ("bridge_messages_sent", &self.bridge_messages_sent),
+            ("bridge_messages_received", &self.bridge_messages_received),
+            ("bridge_messages_dropped", &self.bridge_messages_dropped),
+            ("bridge_connection_failures", &self.bridge_connection_failures),
+            ("cdc_frames_sent", &self.cdc_frames_sent),
+            ("cdc_consumers_dropped", &self.cdc_consumers_dropped),
+        ];
+        let mut out = String::from("# Stats\r\n");
+        for (name, counter) in fields {
+            let _ = write!(out, "{}:{}\r\n", name, counter.load(Ordering::Relaxed));
+        }
+        out
+    }
+}
@@ -1,64 +1,1685 @@
-use std::collections::HashMap;
-use std::time::{Instant, Duration};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::BuildHasher;
+use bytes::Bytes;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use anyhow::Result;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedReceiver;
+use crate::backend::{Memory, Rewrite, StorageBackend};
+use crate::bloom::BloomFilter;
+use crate::clock::{Clock, SystemClock};
+use crate::countmin::CountMinSketch;
+use crate::cuckoo::CuckooFilter;
+use crate::crypt::Keyring;
+use crate::disk::{Disk, DATA_FILE};
+use crate::events::{Events, KeyEvent, KeyEventKind};
+use crate::record::{self, Record};
+use crate::search::{Knn, Query, SearchDef, SearchPartition};
+use crate::tier::{Tier, TIER_FILE};
+use crate::wal::{Wal, WAL_DIR};
+use crate::executor::Executor;
+use crate::glob;
+use crate::graph::Graph;
+use crate::hasher::{HasherKind, KeyHasher};
+use crate::index::{IndexDef, Partition, TermRange};
+use crate::json::Json;
+use crate::locks;
+use crate::log::{log_error, log_info};
+use crate::lz4;
+use crate::shared;
+use crate::stats::{self, SERVER_STATS};
+use crate::tdigest::TDigest;
+use crate::timeseries::TimeSeries;
+use crate::topk::TopK;
+
+pub const DEFAULT_SHARDS: usize = 16;
+
+// A string value. One that reads as a 64-bit integer in canonical form (no sign but '-', no leading zeros)
+// is kept as the integer: it takes no allocation, and INCR and friends update it without parsing and
+// formatting. Large values may be kept LZ4 compressed, see Storage::encode. Both are converted back to bytes
+// on reads. With the disk backend most values are only a position in its log, read back through it, and
+// values idle for long are spilled to the cold tier, see tier.rs.
+//
+// Or a JSON document, see json.rs, a hash, a time series, see timeseries.rs, a Bloom or cuckoo filter, see
+// bloom.rs and cuckoo.rs, a count-min sketch, see countmin.rs, a top-k, see topk.rs, a t-digest, see
+// tdigest.rs, or a graph, see graph.rs, all always in memory. They're shared with the copies
+// point-in-time views keep, and updates copy them only while a view still needs the old one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoredValue {
+    Raw(Bytes),
+    Int(i64),
+    Compressed { data: Bytes, len: usize }, // `len` is the uncompressed length
+    OnDisk { offset: u64, len: usize }, // Where the value's section of its record is, see disk.rs
+    Spilled { offset: u64, len: usize }, // Where the value is in the tier file
+    Json(Arc<Json>),
+    Hash(Arc<Hash>),
+    TimeSeries(Arc<TimeSeries>),
+    Bloom(Arc<BloomFilter>),
+    Cuckoo(Arc<CuckooFilter>),
+    CountMin(Arc<CountMinSketch>),
+    TopK(Arc<TopK>),
+    TDigest(Arc<TDigest>),
+    Graph(Arc<Graph>),
+}
+
+// A live string as Storage::update_string hands it over
+pub struct StringEntry {
+    pub value: Bytes,
+    pub version: u64,
+    pub expires_at: Option<u64>,
+}
+
+// A hash's fields and their values, in field order
+pub type Hash = BTreeMap<Bytes, Bytes>;
+
+// The types StoredValue holds behind an Arc, which Storage::read and Storage::update hand over as themselves
+pub trait Typed: Clone {
+    // The value as this type, None when it holds another
+    fn project(value: &StoredValue) -> Option<&Self>;
+    // The value as this type, copied only if something else shares it, or the value back when it holds another
+    fn from_value(value: StoredValue) -> Result<Self, StoredValue>;
+    fn into_value(self) -> StoredValue;
+    // Whether an updated value is kept, rather than its key deleted
+    fn keep(&self) -> bool {
+        true
+    }
+}
+
+macro_rules! typed {
+    ($($variant:ident($type:ty) $(keep $keep:expr)?),* $(,)?) => {$(
+        impl Typed for $type {
+            fn project(value: &StoredValue) -> Option<&Self> {
+                match value {
+                    StoredValue::$variant(inner) => Some(inner),
+                    _ => None,
+                }
+            }
+
+            fn from_value(value: StoredValue) -> Result<Self, StoredValue> {
+                match value {
+                    StoredValue::$variant(inner) => Ok(Arc::unwrap_or_clone(inner)),
+                    other => Err(other),
+                }
+            }
+
+            fn into_value(self) -> StoredValue {
+                StoredValue::$variant(Arc::new(self))
+            }
+
+            $(fn keep(&self) -> bool {
+                $keep(self)
+            })?
+        }
+    )*};
+}
+
+// An empty hash is no hash, unlike a series without samples, which is still there
+typed!(
+    Json(Json),
+    Hash(Hash) keep |hash: &Hash| !hash.is_empty(),
+    TimeSeries(TimeSeries),
+    Bloom(BloomFilter),
+    Cuckoo(CuckooFilter),
+    CountMin(CountMinSketch),
+    TopK(TopK),
+    TDigest(TDigest),
+    Graph(Graph),
+);
+
+impl StoredValue {
+    pub fn from_bytes(value: Bytes) -> StoredValue {
+        // The empty value needs no allocation either, and doesn't keep a read buffer alive
+        if value.is_empty() {
+            return StoredValue::Raw(Bytes::new());
+        }
+        match parse_canonical_int(&value) {
+            Some(n) => StoredValue::Int(n),
+            None => StoredValue::Raw(value),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        match self {
+            StoredValue::Raw(value) => value.clone(),
+            StoredValue::Int(n) => shared::integer(*n),
+            StoredValue::Compressed { data, len } => {
+                Bytes::from(lz4::decompress(data, *len).expect("stored value failed to decompress"))
+            },
+            StoredValue::OnDisk { .. } => panic!("values on disk are read through their backend"),
+            StoredValue::Spilled { .. } => panic!("spilled values are read through the tier"),
+            StoredValue::Json(doc) => Bytes::from(doc.to_text()),
+            StoredValue::Hash(hash) => Bytes::from(record::hash_payload(hash)),
+            StoredValue::TimeSeries(series) => Bytes::from(series.encode()),
+            StoredValue::Bloom(filter) => Bytes::from(filter.encode()),
+            StoredValue::Cuckoo(filter) => Bytes::from(filter.encode()),
+            StoredValue::CountMin(sketch) => Bytes::from(sketch.encode()),
+            StoredValue::TopK(topk) => Bytes::from(topk.encode()),
+            StoredValue::TDigest(digest) => Bytes::from(digest.encode()),
+            StoredValue::Graph(graph) => Bytes::from(graph.encode()),
+        }
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            StoredValue::Json(_) => ValueType::Json,
+            StoredValue::Hash(_) => ValueType::Hash,
+            StoredValue::TimeSeries(_) => ValueType::TimeSeries,
+            StoredValue::Bloom(_) => ValueType::Bloom,
+            StoredValue::Cuckoo(_) => ValueType::Cuckoo,
+            StoredValue::CountMin(_) => ValueType::CountMin,
+            StoredValue::TopK(_) => ValueType::TopK,
+            StoredValue::TDigest(_) => ValueType::TDigest,
+            StoredValue::Graph(_) => ValueType::Graph,
+            _ => ValueType::String,
+        }
+    }
+
+    // How big the value is in its type's unit, see ValueType::size_unit, from what's kept in memory: values
+    // on disk aren't read in, and count as the bytes stored for them, compressed if they are
+    pub fn size(&self) -> u64 {
+        let size = match self {
+            StoredValue::Raw(value) => value.len(),
+            StoredValue::Int(n) => n.unsigned_abs().checked_ilog10().map_or(1, |log| log as usize + 1) + usize::from(*n < 0),
+            StoredValue::Compressed { len, .. } | StoredValue::Spilled { len, .. } => *len,
+            StoredValue::OnDisk { len, .. } => len - record::SECTION_HEADER,
+            StoredValue::Json(doc) => doc.values(),
+            StoredValue::Hash(hash) => hash.len(),
+            StoredValue::TimeSeries(series) => series.len(),
+            StoredValue::Bloom(filter) => return filter.len(),
+            StoredValue::Cuckoo(filter) => return filter.len(),
+            StoredValue::CountMin(sketch) => return sketch.width * sketch.depth,
+            StoredValue::TopK(topk) => return topk.width * topk.depth,
+            StoredValue::TDigest(digest) => digest.centroids(),
+            StoredValue::Graph(graph) => graph.nodes(),
+        };
+        size as u64
+    }
+}
+
+// What a key holds, as TYPE names it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    String,
+    Json,
+    Hash,
+    TimeSeries,
+    Bloom,
+    Cuckoo,
+    CountMin,
+    TopK,
+    TDigest,
+    Graph,
+}
+
+impl ValueType {
+    pub const ALL: [ValueType; 10] = [
+        ValueType::String, ValueType::Json, ValueType::Hash, ValueType::TimeSeries, ValueType::Bloom,
+        ValueType::Cuckoo, ValueType::CountMin, ValueType::TopK, ValueType::TDigest, ValueType::Graph,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ValueType::String => "string",
+            ValueType::Json => "ReJSON-RL",
+            ValueType::Hash => "hash",
+            ValueType::TimeSeries => "TSDB-TYPE",
+            ValueType::Bloom => "MBbloom--",
+            ValueType::Cuckoo => "MBbloomCF",
+            ValueType::CountMin => "CMSk-TYPE",
+            ValueType::TopK => "TopK-TYPE",
+            ValueType::TDigest => "TDIS-TYPE",
+            ValueType::Graph => "graph",
+        }
+    }
+
+    // What StoredValue::size counts
+    pub fn size_unit(self) -> &'static str {
+        match self {
+            ValueType::String => "bytes",
+            ValueType::Json => "values",
+            ValueType::Hash => "fields",
+            ValueType::TimeSeries => "samples",
+            ValueType::Bloom | ValueType::Cuckoo => "items",
+            ValueType::CountMin | ValueType::TopK => "counters",
+            ValueType::TDigest => "centroids",
+            ValueType::Graph => "nodes",
+        }
+    }
+}
+
+// A key was used as a type it doesn't hold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WrongType;
+
+// Why a string couldn't be read: the key holds another type, or its value couldn't be read back from the data
+// or tier file, which is logged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadError {
+    WrongType,
+    Unreadable,
+}
+
+impl From<WrongType> for ReadError {
+    fn from(_: WrongType) -> ReadError {
+        ReadError::WrongType
+    }
+}
+
+fn unreadable(key: &[u8], e: io::Error) -> ReadError {
+    log_error!("Reading the value of '{}' failed: {}", String::from_utf8_lossy(key), e);
+    ReadError::Unreadable
+}
+
+// The integer `value` spells, if it's the one way of writing it, so converting back gives the same bytes
+fn parse_canonical_int(value: &[u8]) -> Option<i64> {
+    let digits = value.strip_prefix(b"-").unwrap_or(value);
+    let canonical = match digits {
+        [] => false,
+        [b'0'] => digits.len() == value.len(), // "0" but not "-0"
+        [first, ..] => *first != b'0' && digits.iter().all(u8::is_ascii_digit),
+    };
+    if !canonical || value.len() > 20 {
+        return None;
+    }
+    std::str::from_utf8(value).ok()?.parse().ok()
+}
+
+// Why INCR and friends couldn't update a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IncrError {
+    NotInteger,
+    Overflow,
+    WrongType,
+}
 
 #[derive(Debug)]
 pub struct Item {
-    pub value: String,
-    pub created: Instant,
-    pub expires: usize, // Expiry in milliseconds
+    pub value: StoredValue,
+    pub expires_at: Option<u64>, // Absolute deadline in UNIX milliseconds, meaningful across restarts and nodes
+    pub version: u64, // Bumped on every write, lets WATCH detect modified keys
+    pub accessed: AtomicU32, // UNIX seconds of the last read or write, updated under the shard's read lock
+}
+
+impl Item {
+    fn new(value: StoredValue, expires_at: Option<u64>, version: u64, now_ms: u64) -> Item {
+        Item { value, expires_at, version, accessed: AtomicU32::new((now_ms / 1000) as u32) }
+    }
+
+    fn idle_secs(&self, now_ms: u64) -> u64 {
+        (now_ms / 1000).saturating_sub(self.accessed.load(Ordering::Relaxed) as u64)
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|deadline| now > deadline)
+    }
+}
+
+// NX/XX/GT/LT conditions of the EXPIRE family. A key without a TTL counts as expiring at infinity for GT/LT.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExpireFlags {
+    pub nx: bool, // Only when the key has no TTL
+    pub xx: bool, // Only when the key has a TTL
+    pub gt: bool, // Only when the new deadline is later
+    pub lt: bool, // Only when the new deadline is earlier
+}
+
+impl ExpireFlags {
+    fn allows(&self, current: Option<u64>, new: u64) -> bool {
+        match current {
+            None => !self.xx && !self.gt,
+            Some(current) => !self.nx && (!self.gt || new > current) && (!self.lt || new < current),
+        }
+    }
 }
 
+// Expired keys the active expiry cycle deletes per shard lock acquisition
+const EXPIRE_BATCH: usize = 256;
+// Share of each cycle's period the active expiry cycle may spend
+const EXPIRE_CYCLE_BUDGET_PERCENT: u32 = 25;
+// Most values spilled to the cold tier per shard looked through
+const SPILL_BATCH: usize = 1024;
+// Shorter values aren't worth spilling, their place in the file and the bookkeeping take about as much memory
+const SPILL_MIN_LEN: usize = 64;
+
+// Keys a point-in-time view copies per shard lock acquisition
+const VIEW_CHUNK: usize = 256;
+// Keys walks of the keyspace for commands go through between checks of their time budget
+pub(crate) const WALK_CHECK_EVERY: usize = 4096;
+
+fn spillable(value: &StoredValue) -> bool {
+    match value {
+        StoredValue::Raw(value) => value.len() >= SPILL_MIN_LEN,
+        StoredValue::Compressed { .. } => true,
+        _ => false,
+    }
+}
+
+#[derive(Default)]
+struct Shard {
+    items: HashMap<Bytes, Item, KeyHasher>, // Seeded apart from `hasher`, so a shard's keys spread over its map
+    // Every key with a TTL ordered by deadline, so due keys are found without scanning. Kept exactly in
+    // sync with `items` by insert/remove.
+    expiries: BTreeSet<(u64, Bytes)>,
+    // Every key ordered by hash, where SCAN's cursor points. Unlike a position in `items` it doesn't move as
+    // the map grows or shrinks. Kept exactly in sync with `items` by insert/remove.
+    order: BTreeSet<(u64, Bytes)>,
+    hasher: KeyHasher, // The storage's, so a key's place in `order` matches the shard it's in
+    counts: EncodingCounts,
+    // Keys written or deleted since the last snapshot, once there was one. Writers hold the write lock and
+    // get at it for free, snapshots swap it out holding only the read lock.
+    dirty: Mutex<Option<HashSet<Bytes>>>,
+    // What the point-in-time view being read still needs of this shard, see Storage::read_view
+    frozen: Mutex<Option<Frozen>>,
+    // This shard's part of every secondary index, see index.rs. Kept exactly in sync with `items` by
+    // insert/remove.
+    indexes: Vec<(Arc<IndexDef>, Partition)>,
+    // And of every search index, see search.rs, kept in sync the same way
+    searches: Vec<(Arc<SearchDef>, SearchPartition)>,
+}
+
+#[derive(Default)]
+struct Frozen {
+    next: u64, // Keys hashing below this were read already
+    before: HashMap<Bytes, Option<(StoredValue, Option<u64>)>>, // Keys changed since the view was taken, as they were
+}
+
+// A key's value and deadline as of an incremental snapshot, None when it's gone
+pub type Change = (Bytes, Option<(StoredValue, Option<u64>)>);
+
+// A key a search found, with its score or distance and its value
+pub type Found<T> = (Bytes, T, StoredValue);
+
+// A live key of a point-in-time view, its value read in, with its deadline
+type Viewed = (Bytes, StoredValue, Option<u64>);
+
+// How many values are kept in each of the space-saving forms
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncodingCounts {
+    pub int_values: usize,
+    pub compressed_values: usize,
+    pub compression_saved_bytes: usize, // Uncompressed minus compressed length of the compressed values
+    pub disk_values: usize,
+    pub spilled_values: usize,
+    pub spilled_bytes: usize,
+}
+
+impl EncodingCounts {
+    fn add(&mut self, value: &StoredValue) {
+        match value {
+            StoredValue::Int(_) => self.int_values += 1,
+            StoredValue::Compressed { data, len } => {
+                self.compressed_values += 1;
+                self.compression_saved_bytes += len - data.len();
+            },
+            StoredValue::OnDisk { .. } => self.disk_values += 1,
+            StoredValue::Spilled { len, .. } => {
+                self.spilled_values += 1;
+                self.spilled_bytes += len;
+            },
+            StoredValue::Raw(_) | StoredValue::Json(_) | StoredValue::Hash(_) | StoredValue::TimeSeries(_)
+            | StoredValue::Bloom(_) | StoredValue::Cuckoo(_) | StoredValue::CountMin(_)
+            | StoredValue::TopK(_) | StoredValue::TDigest(_) | StoredValue::Graph(_) => {},
+        }
+    }
+
+    fn remove(&mut self, value: &StoredValue) {
+        match value {
+            StoredValue::Int(_) => self.int_values -= 1,
+            StoredValue::Compressed { data, len } => {
+                self.compressed_values -= 1;
+                self.compression_saved_bytes -= len - data.len();
+            },
+            StoredValue::OnDisk { .. } => self.disk_values -= 1,
+            StoredValue::Spilled { len, .. } => {
+                self.spilled_values -= 1;
+                self.spilled_bytes -= len;
+            },
+            StoredValue::Raw(_) | StoredValue::Json(_) | StoredValue::Hash(_) | StoredValue::TimeSeries(_)
+            | StoredValue::Bloom(_) | StoredValue::Cuckoo(_) | StoredValue::CountMin(_)
+            | StoredValue::TopK(_) | StoredValue::TDigest(_) | StoredValue::Graph(_) => {},
+        }
+    }
+}
+
+impl Shard {
+    fn insert(&mut self, key: Bytes, item: Item) {
+        self.preserve(&key);
+        self.mark_dirty(&key);
+        // The old deadline goes first, the new one may be the same
+        let deadline = item.expires_at;
+        self.counts.add(&item.value);
+        for (def, partition) in &mut self.indexes {
+            partition.insert(&key, def.term(&key, &item.value));
+        }
+        for (def, partition) in &mut self.searches {
+            partition.insert(&key, def.document(&key, &item.value));
+        }
+        match self.items.insert(key.clone(), item) {
+            Some(old) => {
+                self.counts.remove(&old.value);
+                if let Some(deadline) = old.expires_at {
+                    self.expiries.remove(&(deadline, key.clone()));
+                }
+            },
+            None => {
+                self.order.insert((self.hasher.hash_one(&key[..]), key.clone()));
+            },
+        }
+        if let Some(deadline) = deadline {
+            self.expiries.insert((deadline, key));
+        }
+    }
+
+    fn remove(&mut self, key: &Bytes) -> Option<Item> {
+        self.preserve(key);
+        let item = self.items.remove(key)?;
+        self.mark_dirty(key);
+        self.order.remove(&(self.hasher.hash_one(&key[..]), key.clone()));
+        self.counts.remove(&item.value);
+        self.unindex(key);
+        if let Some(deadline) = item.expires_at {
+            self.expiries.remove(&(deadline, key.clone()));
+        }
+        Some(item)
+    }
+
+    // Keeps a key as it was for the view being read, before the key's first change since, unless the view
+    // was read past it already
+    fn preserve(&mut self, key: &Bytes) {
+        let Some(frozen) = self.frozen.get_mut().unwrap_or_else(|e| e.into_inner()) else {
+            return;
+        };
+        if frozen.before.contains_key(key) || self.hasher.hash_one(&key[..]) < frozen.next {
+            return;
+        }
+        let entry = self.items.get(key).map(|item| (item.value.clone(), item.expires_at));
+        frozen.before.insert(key.clone(), entry);
+    }
+
+    fn unindex(&mut self, key: &Bytes) {
+        for (_, partition) in &mut self.indexes {
+            partition.remove(key);
+        }
+        for (_, partition) in &mut self.searches {
+            partition.remove(key);
+        }
+    }
+
+    fn mark_dirty(&mut self, key: &Bytes) {
+        if let Some(dirty) = self.dirty.get_mut().unwrap_or_else(|e| e.into_inner()) {
+            dirty.insert(key.clone());
+        }
+    }
+
+    // Swaps the value of a key still at `version`, keeping everything else about it
+    fn replace_value(&mut self, key: &[u8], version: u64, value: StoredValue) -> bool {
+        let Some(item) = self.items.get_mut(key).filter(|item| item.version == version) else {
+            return false;
+        };
+        self.counts.remove(&item.value);
+        self.counts.add(&value);
+        item.value = value;
+        true
+    }
+
+    // Deletes up to `limit` keys whose deadline has passed, earliest first
+    fn remove_due(&mut self, now: u64, limit: usize, backend: &dyn StorageBackend, events: &Events) -> usize {
+        let mut removed = 0;
+        while removed < limit {
+            match self.expiries.first() {
+                Some((deadline, _)) if *deadline < now => {},
+                _ => break,
+            }
+            if let Some((_, key)) = self.expiries.pop_first() {
+                self.preserve(&key);
+                if let Some(item) = self.items.remove(&key) {
+                    self.order.remove(&(self.hasher.hash_one(&key[..]), key.clone()));
+                    self.counts.remove(&item.value);
+                    self.unindex(&key);
+                    backend.remove(&key);
+                    self.mark_dirty(&key);
+                    events.send(KeyEventKind::Expired, &key, now);
+                }
+                removed += 1;
+            }
+        }
+        removed
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    Sharded,
+    Concurrent,
+    Disk,
+}
+
+impl BackendKind {
+    pub fn parse(name: &str) -> Result<BackendKind> {
+        match name.to_lowercase().as_str() {
+            "sharded" => Ok(BackendKind::Sharded),
+            "concurrent" => Ok(BackendKind::Concurrent),
+            "disk" => Ok(BackendKind::Disk),
+            _ => Err(anyhow::anyhow!("Invalid storage backend '{}'", name)),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BackendKind::Sharded => "sharded",
+            BackendKind::Concurrent => "concurrent",
+            BackendKind::Disk => "disk",
+        }
+    }
+
+    // How many shards the keyspace is split into given storage-shards. This is all Concurrent changes.
+    pub fn shard_count(self, configured: usize) -> usize {
+        match self {
+            BackendKind::Sharded | BackendKind::Disk => configured.max(1),
+            BackendKind::Concurrent => {
+                let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+                (cores * 4).next_power_of_two().max(configured)
+            },
+        }
+    }
+}
+
+// The keyspace split into shards by key hash, each behind its own RwLock, so clients working on unrelated
+// keys don't contend and lookups on the same shard run in parallel. Single-key operations only lock their
+// key's shard, read-only ones only shared. Reads never modify a shard: a key found expired is reported
+// missing and left for the active expiry cycle to delete.
+//
+// There is one layout in memory, and the backend kind only decides the shard count and where values live.
+// Concurrent isn't a separate backend: it started as RwLock shards next to Sharded's Mutex ones, and once
+// every shard became an RwLock all that was left of it is a shard count scaled with the number of cores,
+// trading memory for less contention on many-core machines. The name stays so configs naming it keep
+// working. Sharded and Concurrent keep values in memory. Disk is laid out like Sharded but writes values
+// through to a log in the data directory, see disk.rs. The layouts in memory may log changes to a
+// write-ahead log instead, see wal.rs.
+//
+// Commands additionally hold the gate: shared for ordinary commands, exclusive for EXEC, which must not
+// interleave with anything else. Callers take the gate once per command; the methods here never touch it.
 pub struct Storage {
-    pub storage: HashMap<String, Item>,
+    shards: Vec<RwLock<Shard>>,
+    hasher: KeyHasher,
+    gate: RwLock<()>,
+    next_version: AtomicU64,
+    clock: Arc<dyn Clock>,
+    compression_threshold: AtomicUsize, // Values this long or longer are compressed, 0 disables
+    backend: Box<dyn StorageBackend>,
+    tier: Option<Tier>, // Only with a data directory and values in memory
+    keys: Arc<Keyring>, // Files in the data directory and snapshots are encrypted with, see crypt.rs
+    tiering_idle_time: AtomicU64, // Seconds unread before a value is spilled, 0 disables
+    next_tier_shard: AtomicUsize,
+    // Held while a point-in-time view is read, so there's one at a time, and values' places in files stay put
+    view: Mutex<()>,
+    // The secondary indexes declared, whose partitions the shards keep. Held while one is created or dropped.
+    indexes: RwLock<Vec<Arc<IndexDef>>>,
+    searches: RwLock<Vec<Arc<SearchDef>>>, // Likewise the search indexes
+    events: Events, // Keyspace events, sent under the shard lock of the key they're about
 }
 
 impl Storage {
-    pub fn new() -> Self {
+    pub fn new(kind: BackendKind, shards: usize) -> Self {
+        Storage::with_clock(kind, shards, HasherKind::default(), Arc::new(SystemClock))
+    }
+
+    // Keeps everything in memory whatever the kind, open loads the disk backend
+    pub fn with_clock(kind: BackendKind, shards: usize, hasher: HasherKind, clock: Arc<dyn Clock>) -> Self {
+        let shards = kind.shard_count(shards);
+        let placement = KeyHasher::new(hasher);
+        let shard = || Shard { items: HashMap::with_hasher(KeyHasher::new(hasher)), hasher: placement.clone(), ..Shard::default() };
         Storage {
-            storage: HashMap::new(),
+            shards: (0..shards).map(|_| RwLock::new(shard())).collect(),
+            hasher: placement,
+            gate: RwLock::new(()),
+            next_version: AtomicU64::new(0),
+            clock,
+            compression_threshold: AtomicUsize::new(0),
+            backend: Box::new(Memory),
+            tier: None,
+            keys: Arc::default(),
+            tiering_idle_time: AtomicU64::new(0),
+            next_tier_shard: AtomicUsize::new(0),
+            view: Mutex::new(()),
+            indexes: RwLock::new(vec![]),
+            searches: RwLock::new(vec![]),
+            events: Events::default(),
+        }
+    }
+
+    // Storage of the given kind, with the disk backend's log in `dir` read back in. Storage in memory may
+    // spill cold values to a tier file there. With a current key in `keys`, the files written are encrypted.
+    pub fn open(kind: BackendKind, shards: usize, hasher: HasherKind, dir: &Path, keys: Arc<Keyring>, clock: Arc<dyn Clock>) -> io::Result<Self> {
+        let mut storage = Storage::with_clock(kind, shards, hasher, clock);
+        if kind == BackendKind::Disk {
+            let disk = Disk::open(&dir.join(DATA_FILE), Arc::clone(&keys), |record| storage.apply(record))?;
+            storage.backend = Box::new(disk);
+        } else {
+            storage.tier = Some(Tier::new(dir.join(TIER_FILE), keys.enabled()));
+        }
+        storage.keys = keys;
+        storage.compact_stale()?;
+        Ok(storage)
+    }
+
+    // Storage in memory logging every change to the write-ahead log in `dir`, with what's there read back in
+    pub fn open_wal(kind: BackendKind, shards: usize, hasher: HasherKind, dir: &Path, segment_size: u64, keys: Arc<Keyring>, clock: Arc<dyn Clock>) -> io::Result<Self> {
+        if kind == BackendKind::Disk {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the disk backend keeps its own log, it can't be combined with the write-ahead log"));
+        }
+        let mut storage = Storage::open(kind, shards, hasher, dir, Arc::clone(&keys), clock)?;
+        let wal = Wal::open(&dir.join(WAL_DIR), segment_size, keys, |record| storage.apply(record))?;
+        storage.backend = Box::new(wal);
+        storage.compact_stale()?;
+        Ok(storage)
+    }
+
+    // Rewrites what was loaded when the backend asks for it, which it does for files written with a key other
+    // than the current one, before anything else is written to them
+    fn compact_stale(&self) -> io::Result<()> {
+        if self.needs_compaction() {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    // The keys files are encrypted with
+    pub fn keyring(&self) -> &Keyring {
+        &self.keys
+    }
+
+    // Replays a change read back from the disk backend or the write-ahead log
+    fn apply(&self, record: Record) {
+        match record {
+            Record::Set { key, value, expires_at } => {
+                let version = self.next_version.fetch_add(1, Ordering::Relaxed) + 1;
+                locks::write(self.shard(&key)).insert(key, Item::new(value, expires_at, version, self.now_ms()));
+            },
+            Record::Expire { key, expires_at } => {
+                let mut shard = locks::write(self.shard(&key));
+                if let Some(mut item) = shard.remove(&key) {
+                    item.expires_at = expires_at;
+                    shard.insert(key, item);
+                }
+            },
+            Record::Del { key } => {
+                locks::write(self.shard(&key)).remove(&key);
+            },
+            Record::Snapshot { .. } => {},
+        }
+    }
+
+    // Current time in UNIX milliseconds according to the storage's clock
+    pub fn now_ms(&self) -> u64 {
+        self.clock.now_ms()
+    }
+
+    pub fn shared(&self) -> RwLockReadGuard<'_, ()> {
+        locks::read(&self.gate)
+    }
+
+    pub fn exclusive(&self) -> RwLockWriteGuard<'_, ()> {
+        locks::write(&self.gate)
+    }
+
+    pub fn set_compression_threshold(&self, threshold: usize) {
+        self.compression_threshold.store(threshold, Ordering::Relaxed);
+    }
+
+    pub fn set_tiering_idle_time(&self, secs: u64) {
+        self.tiering_idle_time.store(secs, Ordering::Relaxed);
+    }
+
+    // How a value is stored: compressed when it's at least the compression threshold long and shrinks by an
+    // eighth or more, otherwise see StoredValue::from_bytes. Runs before the shard is locked.
+    fn encode(&self, value: Bytes) -> StoredValue {
+        let threshold = self.compression_threshold.load(Ordering::Relaxed);
+        if threshold > 0 && value.len() >= threshold {
+            let compressed = lz4::compress(&value);
+            if compressed.len() <= value.len() - value.len() / 8 {
+                return StoredValue::Compressed { data: compressed.into(), len: value.len() };
+            }
+        }
+        StoredValue::from_bytes(value)
+    }
+
+    fn shard(&self, key: &[u8]) -> &RwLock<Shard> {
+        &self.shards[self.hasher.hash_one(key) as usize % self.shards.len()]
+    }
+
+    pub fn set(&self, key: Bytes, value: Bytes, expires_at: Option<u64>) {
+        self.store(key, self.encode(value), expires_at);
+    }
+
+    fn store(&self, key: Bytes, value: StoredValue, expires_at: Option<u64>) {
+        let mut shard = locks::write(self.shard(&key));
+        let version = self.next_version.fetch_add(1, Ordering::Relaxed) + 1;
+        let now = self.now_ms();
+        let item = Item::new(self.backend.write(&key, value, expires_at), expires_at, version, now);
+        self.events.send(KeyEventKind::Set, &key, now);
+        shard.insert(key, item);
+    }
+
+    // Expired keys read as missing; deleting them is left to the expiry cycle. A spilled value is read back
+    // into memory.
+    pub fn get(&self, key: &[u8]) -> std::result::Result<Option<Bytes>, ReadError> {
+        let now = self.now_ms();
+        let (value, spilled) = match locks::read(self.shard(key)).items.get(key) {
+            Some(item) if !item.is_expired(now) => {
+                if item.value.value_type() != ValueType::String {
+                    return Err(ReadError::WrongType);
+                }
+                item.accessed.store((now / 1000) as u32, Ordering::Relaxed);
+                let (value, spilled) = match (&item.value, &self.tier) {
+                    (StoredValue::Spilled { offset, len }, Some(tier)) => (tier.read(*offset, *len), Some(item.version)),
+                    (value, _) => (self.backend.read(value), None),
+                };
+                (Some(value.map_err(|e| unreadable(key, e))?), spilled)
+            },
+            _ => (None, None),
+        };
+        let counter = if value.is_some() { &SERVER_STATS.keyspace_hits } else { &SERVER_STATS.keyspace_misses };
+        stats::incr(counter, 1);
+        if spilled.is_some() {
+            stats::incr(&SERVER_STATS.tier_misses, 1);
+        } else if value.is_some() && self.tiering_idle_time.load(Ordering::Relaxed) > 0 {
+            stats::incr(&SERVER_STATS.tier_hits, 1);
+        }
+        if let (Some(value), Some(version)) = (&value, spilled) {
+            self.promote(key, version, value.clone());
+        }
+        Ok(value)
+    }
+
+    // A live string's value and version, for reads that a check-and-set write may follow. A spilled value is
+    // read back but left where it is.
+    pub fn get_versioned(&self, key: &[u8]) -> std::result::Result<Option<(Bytes, u64)>, ReadError> {
+        let now = self.now_ms();
+        let shard = locks::read(self.shard(key));
+        let value = match shard.items.get(key).filter(|item| !item.is_expired(now)) {
+            Some(item) if item.value.value_type() != ValueType::String => return Err(ReadError::WrongType),
+            Some(item) => {
+                item.accessed.store((now / 1000) as u32, Ordering::Relaxed);
+                Some((self.read_value(&item.value).map_err(|e| unreadable(key, e))?, item.version))
+            },
+            None => None,
+        };
+        let counter = if value.is_some() { &SERVER_STATS.keyspace_hits } else { &SERVER_STATS.keyspace_misses };
+        stats::incr(counter, 1);
+        Ok(value)
+    }
+
+    // Lets `update` decide what becomes of the string at `key`, holding the shard's write lock, for writes that
+    // depend on what's there such as memcached's: it's given the live string, None for a missing key, and
+    // returns what to hand back and the value and deadline to write, if any. The key's version afterwards, 0
+    // when it's missing, comes back too.
+    pub fn update_string<T>(&self, key: &Bytes, update: impl FnOnce(Option<StringEntry>) -> (T, Option<(Bytes, Option<u64>)>)) -> std::result::Result<(T, u64), ReadError> {
+        let now = self.now_ms();
+        let mut shard = locks::write(self.shard(key));
+        let entry = match shard.items.get(key).filter(|item| !item.is_expired(now)) {
+            Some(item) if item.value.value_type() != ValueType::String => return Err(ReadError::WrongType),
+            Some(item) => {
+                let value = self.read_value(&item.value).map_err(|e| unreadable(key, e))?;
+                Some(StringEntry { value, version: item.version, expires_at: item.expires_at })
+            },
+            None => None,
+        };
+        let current = entry.as_ref().map_or(0, |entry| entry.version);
+        let (result, write) = update(entry);
+        let Some((value, expires_at)) = write else {
+            return Ok((result, current));
+        };
+        let version = self.next_version.fetch_add(1, Ordering::Relaxed) + 1;
+        let value = self.backend.write(key, self.encode(value), expires_at);
+        shard.insert(key.clone(), Item::new(value, expires_at, version, now));
+        self.events.send(KeyEventKind::Set, key, now);
+        Ok((result, version))
+    }
+
+    // What a live key holds
+    pub fn value_type(&self, key: &[u8]) -> Option<ValueType> {
+        let shard = locks::read(self.shard(key));
+        shard.items.get(key).filter(|item| !item.is_expired(self.now_ms())).map(|item| item.value.value_type())
+    }
+
+    // Passes the value at a live key to `read` as a V, holding the shard's read lock
+    pub fn read<V: Typed, T>(&self, key: &[u8], read: impl FnOnce(&V) -> T) -> std::result::Result<Option<T>, WrongType> {
+        let now = self.now_ms();
+        let shard = locks::read(self.shard(key));
+        let value = match shard.items.get(key).filter(|item| !item.is_expired(now)) {
+            Some(item) => {
+                let value = V::project(&item.value).ok_or(WrongType)?;
+                item.accessed.store((now / 1000) as u32, Ordering::Relaxed);
+                Some(read(value))
+            },
+            None => None,
+        };
+        let counter = if value.is_some() { &SERVER_STATS.keyspace_hits } else { &SERVER_STATS.keyspace_misses };
+        stats::incr(counter, 1);
+        Ok(value)
+    }
+
+    // Lets `update` change the V at `key` in place, holding the shard's write lock: None stands for a missing
+    // key, and is what `update` leaves to delete the key, as is a value V::keep turns down. `update` returns
+    // what to hand back and whether it changed anything; only then is the key written, keeping its TTL.
+    pub fn update<V: Typed, T>(&self, key: &Bytes, update: impl FnOnce(&mut Option<V>) -> (T, bool)) -> std::result::Result<T, WrongType> {
+        let now = self.now_ms();
+        let mut shard = locks::write(self.shard(key));
+        let (mut value, expires_at) = match shard.items.get(key).filter(|item| !item.is_expired(now)) {
+            Some(item) => {
+                if V::project(&item.value).is_none() {
+                    return Err(WrongType);
+                }
+                let expires_at = item.expires_at;
+                // Taken out rather than cloned, so changing it copies nothing unless a view holds on to it
+                shard.preserve(key);
+                let item = shard.items.get_mut(key).expect("found above");
+                match V::from_value(std::mem::replace(&mut item.value, StoredValue::Raw(Bytes::new()))) {
+                    Ok(value) => (Some(value), expires_at),
+                    Err(value) => {
+                        item.value = value;
+                        return Err(WrongType);
+                    },
+                }
+            },
+            None => (None, None),
+        };
+        let existed = value.is_some();
+        let (result, changed) = update(&mut value);
+        match (value.filter(V::keep), changed) {
+            (Some(value), false) => {
+                let item = shard.items.get_mut(key).expect("only absent when the update changed nothing");
+                item.value = value.into_value();
+            },
+            (None, false) => {},
+            (Some(value), true) => {
+                let version = self.next_version.fetch_add(1, Ordering::Relaxed) + 1;
+                let value = self.backend.write(key, value.into_value(), expires_at);
+                shard.insert(key.clone(), Item::new(value, expires_at, version, now));
+                self.events.send(KeyEventKind::Set, key, now);
+            },
+            (None, true) => {
+                if existed {
+                    shard.remove(key);
+                    self.backend.remove(key);
+                    self.events.send(KeyEventKind::Del, key, now);
+                }
+            },
+        }
+        Ok(result)
+    }
+
+    // The live values `select` picks, with their keys, in key order. Every key is looked at, with `check` called
+    // before every shard and every WALK_CHECK_EVERY keys, giving up with its error.
+    pub fn find_values<E>(&self, mut check: impl FnMut() -> Result<(), E>, select: impl Fn(&Bytes, &StoredValue) -> bool) -> Result<Vec<(Bytes, StoredValue)>, E> {
+        let now = self.now_ms();
+        let mut found = vec![];
+        for shard in &self.shards {
+            check()?;
+            let shard = locks::read(shard);
+            for (i, (key, item)) in shard.items.iter().enumerate() {
+                if i % WALK_CHECK_EVERY == WALK_CHECK_EVERY - 1 {
+                    check()?;
+                }
+                if !item.is_expired(now) && select(key, &item.value) {
+                    found.push((key.clone(), item.value.clone()));
+                }
+            }
+        }
+        found.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        Ok(found)
+    }
+
+    // The live time series `select` picks, with their keys, in key order, checking like find_values
+    pub fn find_series<E>(&self, check: impl FnMut() -> Result<(), E>, select: impl Fn(&TimeSeries) -> bool) -> Result<Vec<(Bytes, Arc<TimeSeries>)>, E> {
+        let found = self.find_values(check, |_, value| matches!(value, StoredValue::TimeSeries(series) if select(series)))?;
+        Ok(found.into_iter().map(|(key, value)| match value {
+            StoredValue::TimeSeries(series) => (key, series),
+            _ => unreachable!("only time series are picked"),
+        }).collect())
+    }
+
+    // Stores a value read back from a snapshot: strings as they'd be set now, the other types as they are
+    pub fn restore(&self, key: Bytes, value: StoredValue, expires_at: Option<u64>) {
+        let value = match value {
+            value @ (StoredValue::Json(_) | StoredValue::Hash(_) | StoredValue::TimeSeries(_)
+            | StoredValue::Bloom(_) | StoredValue::Cuckoo(_) | StoredValue::CountMin(_)
+            | StoredValue::TopK(_) | StoredValue::TDigest(_) | StoredValue::Graph(_)) => value,
+            value => self.encode(value.to_bytes()),
+        };
+        self.store(key, value, expires_at);
+    }
+
+    // Moves a spilled value back into memory, unless the key was written since it was read
+    fn promote(&self, key: &[u8], version: u64, value: Bytes) {
+        let value = self.encode(value);
+        let mut shard = locks::write(self.shard(key));
+        if shard.replace_value(key, version, value) {
+            stats::incr(&SERVER_STATS.tier_promotions, 1);
+        }
+    }
+
+    // The bytes of a value, wherever it's kept
+    fn read_value(&self, value: &StoredValue) -> io::Result<Bytes> {
+        match (value, &self.tier) {
+            (StoredValue::Spilled { offset, len }, Some(tier)) => tier.read(*offset, *len),
+            (value, _) => self.backend.read(value),
+        }
+    }
+
+    // A copy of a value for a snapshot: values in memory are reference counted, the rest are read in
+    fn snapshot_value(&self, value: &StoredValue) -> io::Result<StoredValue> {
+        match value {
+            StoredValue::OnDisk { .. } | StoredValue::Spilled { .. } => self.read_value(value).map(StoredValue::Raw),
+            value => Ok(value.clone()),
         }
     }
 
-    pub fn set(&mut self, key: &str, value: &str, expires: usize) {
-        let item = Item {
-            value: value.to_string(),
-            created: Instant::now(),
-            expires,
+    // Passes every live key to `each` as of one point in time, returning how many there were, and starts
+    // tracking changes for snapshot_changes. Writers carry on meanwhile, see read_view.
+    pub fn snapshot(&self, mut each: impl FnMut(&Bytes, &StoredValue, Option<u64>) -> io::Result<()>) -> io::Result<usize> {
+        let start = |shards: &[RwLockReadGuard<'_, Shard>]| {
+            for shard in shards {
+                *locks::lock(&shard.dirty) = Some(HashSet::new());
+            }
+            Ok(())
+        };
+        self.read_view(start, |_, key, value, expires_at| each(key, value, expires_at)).map(|(_, read)| read)
+    }
+
+    // Like snapshot, but subscribes to keyspace events at its point in time instead of tracking changes:
+    // every change before it is in what `each` is passed, every one after comes as an event. Returns the
+    // events and how many keys there were.
+    pub fn snapshot_and_subscribe(&self, mut each: impl FnMut(&Bytes, &StoredValue, Option<u64>) -> io::Result<()>) -> io::Result<(UnboundedReceiver<KeyEvent>, usize)> {
+        // Events are sent holding the key's shard lock, which every shard's read lock here holds off
+        let start = |_: &[RwLockReadGuard<'_, Shard>]| Ok(self.events.subscribe());
+        self.read_view(start, |_, key, value, expires_at| each(key, value, expires_at))
+    }
+
+    // A live key's type and size, see StoredValue::size, without reading its value in
+    pub fn value_size(&self, key: &[u8]) -> Option<(ValueType, u64)> {
+        let now = self.now_ms();
+        let shard = locks::read(self.shard(key));
+        let item = shard.items.get(key).filter(|item| !item.is_expired(now))?;
+        Some((item.value.value_type(), item.value.size()))
+    }
+
+    // A live key's value, read in wherever it's kept, and its deadline
+    pub fn entry(&self, key: &[u8]) -> io::Result<Option<(StoredValue, Option<u64>)>> {
+        let now = self.now_ms();
+        let shard = locks::read(self.shard(key));
+        let Some(item) = shard.items.get(key).filter(|item| !item.is_expired(now)) else {
+            return Ok(None);
         };
-        self.storage.insert(key.to_string(), item);
+        Ok(Some((self.snapshot_value(&item.value)?, item.expires_at)))
     }
 
-    pub fn get(&mut self, key: &str) -> Option<&Item> {
-        self.remove_expired();  // Clean expired items before fetching
+    // Reads the keyspace as it is at one point in time, passing every live key to `each` with no lock held,
+    // while writers carry on: shards are copied a chunk at a time in hash order, and a shard keeps a key as it
+    // was when it's changed before the copying got to it. `start` runs at that point in time, with every
+    // shard locked, and what it returns is handed to `each`. Returns that and how many keys were read.
+    fn read_view<S>(
+        &self,
+        start: impl FnOnce(&[RwLockReadGuard<'_, Shard>]) -> io::Result<S>,
+        mut each: impl FnMut(&mut S, &Bytes, &StoredValue, Option<u64>) -> io::Result<()>,
+    ) -> io::Result<(S, usize)> {
+        let _view = locks::lock(&self.view);
+        let (mut state, now) = {
+            let shards: Vec<_> = self.shards.iter().map(locks::read).collect();
+            let state = start(&shards)?;
+            for shard in &shards {
+                *locks::lock(&shard.frozen) = Some(Frozen::default());
+            }
+            (state, self.now_ms())
+        };
+        let mut read = 0;
+        let result: io::Result<()> = (|| {
+            for shard in &self.shards {
+                loop {
+                    let (chunk, done) = self.view_chunk(shard, now)?;
+                    for (key, value, expires_at) in &chunk {
+                        each(&mut state, key, value, *expires_at)?;
+                    }
+                    read += chunk.len();
+                    if done {
+                        break;
+                    }
+                }
+            }
+            Ok(())
+        })();
+        if result.is_err() {
+            for shard in &self.shards {
+                *locks::lock(&locks::read(shard).frozen) = None;
+            }
+        }
+        result.map(|_| (state, read))
+    }
 
-        if let Some(item) = self.storage.get(key) {
-            let is_expired = item.expires > 0 && item.created.elapsed().as_millis() > item.expires as u128;
-            if !is_expired {
-                return Some(item);
+    // The next chunk of a shard's keys for read_view, live at `now`, and whether that was the last
+    fn view_chunk(&self, shard: &RwLock<Shard>, now: u64) -> io::Result<(Vec<Viewed>, bool)> {
+        let shard = locks::read(shard);
+        let mut frozen = locks::lock(&shard.frozen);
+        let Some(view) = frozen.as_mut() else {
+            return Ok((vec![], true));
+        };
+        let live = |expires_at: &Option<u64>| !expires_at.is_some_and(|deadline| now > deadline);
+        let mut chunk = vec![];
+        let mut entries = shard.order.range((view.next, Bytes::new())..).peekable();
+        let (mut taken, mut last) = (0, None);
+        while let Some((hash, key)) = entries.next_if(|(hash, _)| taken < VIEW_CHUNK || last == Some(*hash)) {
+            let entry = match view.before.remove(key) {
+                Some(before) => before,
+                None => shard.items.get(key).map(|item| (item.value.clone(), item.expires_at)),
+            };
+            if let Some((value, expires_at)) = entry.filter(|(_, expires_at)| live(expires_at)) {
+                chunk.push((key.clone(), self.snapshot_value(&value)?, expires_at));
+            }
+            (taken, last) = (taken + 1, Some(*hash));
+        }
+        let next = match entries.peek() {
+            Some(_) => last.and_then(|last: u64| last.checked_add(1)),
+            None => None,
+        };
+        if let Some(next) = next {
+            view.next = next;
+            return Ok((chunk, false));
+        }
+        // What's left are keys deleted before the copying got to them
+        for (key, entry) in view.before.drain() {
+            if let Some((value, expires_at)) = entry.filter(|(_, expires_at)| live(expires_at)) {
+                chunk.push((key, self.snapshot_value(&value)?, expires_at));
             }
         }
-        None
+        *frozen = None;
+        Ok((chunk, true))
     }
 
-    pub fn remove_expired(&mut self) {
-        let keys_to_remove: Vec<String> = self.storage.iter()
-            .filter_map(|(key, item)| {
-                if item.expires > 0 && item.created.elapsed().as_millis() > item.expires as u128 {
-                    Some(key.clone())
-                } else {
-                    None
+    // The keys written or deleted since the last snapshot or call to this, as they are now, at one point in
+    // time like snapshot. None when no snapshot was taken since startup or forget_changes.
+    pub fn snapshot_changes(&self) -> io::Result<Option<Vec<Change>>> {
+        let shards: Vec<_> = self.shards.iter().map(locks::read).collect();
+        let now = self.now_ms();
+        let mut changes = vec![];
+        for shard in &shards {
+            let Some(dirty) = locks::lock(&shard.dirty).replace(HashSet::new()) else {
+                return Ok(None);
+            };
+            for key in dirty {
+                let item = shard.items.get(&key).filter(|item| !item.is_expired(now));
+                let entry = item.map(|item| self.snapshot_value(&item.value).map(|value| (value, item.expires_at))).transpose()?;
+                changes.push((key, entry));
+            }
+        }
+        Ok(Some(changes))
+    }
+
+    // Stops tracking changes, after the snapshot they were meant to follow failed to be written
+    pub fn forget_changes(&self) {
+        for shard in &self.shards {
+            *locks::lock(&locks::read(shard).dirty) = None;
+        }
+    }
+
+    // Deletes every key, a shard at a time, returning how many there were
+    pub fn clear(&self) -> usize {
+        self.clear_prefix(b"")
+    }
+
+    // Deletes every key starting with `prefix`, such as a tenant's, a shard at a time, returning how many
+    // there were
+    pub fn clear_prefix(&self, prefix: &[u8]) -> usize {
+        let mut removed = 0;
+        for shard in &self.shards {
+            let mut shard = locks::write(shard);
+            let keys: Vec<Bytes> = shard.items.keys().filter(|key| key.starts_with(prefix)).cloned().collect();
+            for key in keys {
+                shard.remove(&key);
+                self.backend.remove(&key);
+                self.events.send(KeyEventKind::Del, &key, self.now_ms());
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    // Every keyspace event from now on, see events.rs
+    pub fn subscribe(&self) -> UnboundedReceiver<KeyEvent> {
+        self.events.subscribe()
+    }
+
+    // Version of a live key, 0 when it doesn't exist
+    pub fn version(&self, key: &[u8]) -> u64 {
+        match locks::read(self.shard(key)).items.get(key) {
+            Some(item) if !item.is_expired(self.now_ms()) => item.version,
+            _ => 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| locks::read(shard).items.len()).sum()
+    }
+
+    // Like len, counting only keys starting with `prefix`
+    pub fn len_prefix(&self, prefix: &[u8]) -> usize {
+        self.shards.iter().map(|shard| locks::read(shard).items.keys().filter(|key| key.starts_with(prefix)).count()).sum()
+    }
+
+    pub fn encoding_counts(&self) -> EncodingCounts {
+        let mut total = EncodingCounts::default();
+        for shard in &self.shards {
+            let counts = locks::read(shard).counts;
+            total.int_values += counts.int_values;
+            total.compressed_values += counts.compressed_values;
+            total.compression_saved_bytes += counts.compression_saved_bytes;
+            total.disk_values += counts.disk_values;
+            total.spilled_values += counts.spilled_values;
+            total.spilled_bytes += counts.spilled_bytes;
+        }
+        total
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| locks::read(shard).items.is_empty())
+    }
+
+    // Declares a secondary index and builds it from the keys there are, a shard at a time. False when there's
+    // one by that name already.
+    pub fn create_index(&self, def: IndexDef) -> bool {
+        let mut indexes = locks::write(&self.indexes);
+        if indexes.iter().any(|index| index.name == def.name) {
+            return false;
+        }
+        let def = Arc::new(def);
+        for shard in &self.shards {
+            let mut shard = locks::write(shard);
+            let mut partition = Partition::default();
+            for (key, item) in &shard.items {
+                partition.insert(key, def.term(key, &item.value));
+            }
+            shard.indexes.push((def.clone(), partition));
+        }
+        indexes.push(def);
+        true
+    }
+
+    // False when there's no index by that name
+    pub fn drop_index(&self, name: &str) -> bool {
+        let mut indexes = locks::write(&self.indexes);
+        let Some(i) = indexes.iter().position(|index| index.name == name) else {
+            return false;
+        };
+        indexes.remove(i);
+        for shard in &self.shards {
+            locks::write(shard).indexes.retain(|(def, _)| def.name != name);
+        }
+        true
+    }
+
+    // Every index declared, with how many keys it holds
+    pub fn indexes(&self) -> Vec<(Arc<IndexDef>, usize)> {
+        let indexes = locks::read(&self.indexes);
+        let mut counts = vec![0; indexes.len()];
+        for shard in &self.shards {
+            for (count, (_, partition)) in counts.iter_mut().zip(&locks::read(shard).indexes) {
+                *count += partition.len();
+            }
+        }
+        indexes.iter().cloned().zip(counts).collect()
+    }
+
+    // Live keys whose term in the index falls in `range`, in term order then key order, skipping `offset`
+    // and returning up to `count`. None when there's no index by that name.
+    pub fn query_index(&self, name: &str, range: &TermRange, offset: usize, count: usize) -> Option<Vec<Bytes>> {
+        let indexes = locks::read(&self.indexes);
+        let i = indexes.iter().position(|index| index.name == name)?;
+        let now = self.now_ms();
+        let wanted = offset.saturating_add(count);
+        let mut found = vec![];
+        for shard in &self.shards {
+            let shard = locks::read(shard);
+            let live = shard.indexes[i].1.range(range).filter(|(_, key)| shard.items.get(*key).is_some_and(|item| !item.is_expired(now)));
+            found.extend(live.take(wanted).map(|(term, key)| (term.clone(), key.clone())));
+        }
+        found.sort_unstable();
+        Some(found.into_iter().skip(offset).take(count).map(|(_, key)| key).collect())
+    }
+
+    // How many keys the index holds with their term in `range`, counting those expired but not yet removed.
+    // None when there's no index by that name.
+    pub fn count_index(&self, name: &str, range: &TermRange) -> Option<usize> {
+        let indexes = locks::read(&self.indexes);
+        let i = indexes.iter().position(|index| index.name == name)?;
+        Some(self.shards.iter().map(|shard| locks::read(shard).indexes[i].1.range(range).count()).sum())
+    }
+
+    // The live values `select` picks among the keys whose term in the index falls in `range`, with their keys,
+    // in key order, like find_values without looking at any other key. None when there's no index by that name.
+    pub fn find_indexed<E>(&self, name: &str, range: &TermRange, mut check: impl FnMut() -> Result<(), E>, select: impl Fn(&Bytes, &StoredValue) -> bool) -> Result<Option<Vec<(Bytes, StoredValue)>>, E> {
+        let indexes = locks::read(&self.indexes);
+        let Some(i) = indexes.iter().position(|index| index.name == name) else {
+            return Ok(None);
+        };
+        let now = self.now_ms();
+        let mut found = vec![];
+        for shard in &self.shards {
+            check()?;
+            let shard = locks::read(shard);
+            for (j, (_, key)) in shard.indexes[i].1.range(range).enumerate() {
+                if j % WALK_CHECK_EVERY == WALK_CHECK_EVERY - 1 {
+                    check()?;
+                }
+                if let Some(item) = shard.items.get(key).filter(|item| !item.is_expired(now) && select(key, &item.value)) {
+                    found.push((key.clone(), item.value.clone()));
                 }
-            })
-            .collect();
+            }
+        }
+        found.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        Ok(Some(found))
+    }
+
+    // Declares a search index and builds it from the keys there are, like create_index
+    pub fn create_search(&self, def: SearchDef) -> bool {
+        let mut searches = locks::write(&self.searches);
+        if searches.iter().any(|search| search.name == def.name) {
+            return false;
+        }
+        let def = Arc::new(def);
+        for shard in &self.shards {
+            let mut shard = locks::write(shard);
+            let mut partition = SearchPartition::new(&def);
+            for (key, item) in &shard.items {
+                partition.insert(key, def.document(key, &item.value));
+            }
+            shard.searches.push((def.clone(), partition));
+        }
+        searches.push(def);
+        true
+    }
+
+    pub fn drop_search(&self, name: &str) -> bool {
+        let mut searches = locks::write(&self.searches);
+        let Some(i) = searches.iter().position(|search| search.name == name) else {
+            return false;
+        };
+        searches.remove(i);
+        for shard in &self.shards {
+            locks::write(shard).searches.retain(|(def, _)| def.name != name);
+        }
+        true
+    }
+
+    // Every search index declared, with how many keys it holds
+    pub fn searches(&self) -> Vec<(Arc<SearchDef>, usize)> {
+        let searches = locks::read(&self.searches);
+        let mut counts = vec![0; searches.len()];
+        for shard in &self.shards {
+            for (count, (_, partition)) in counts.iter_mut().zip(&locks::read(shard).searches) {
+                *count += partition.len();
+            }
+        }
+        searches.iter().cloned().zip(counts).collect()
+    }
+
+    // Every live key matching `query` with its score and value, best first, then in key order. None when
+    // there's no search index by that name. `check` is called like find_values does.
+    pub fn search<E>(&self, name: &str, query: &Query, mut check: impl FnMut() -> Result<(), E>) -> Result<Option<Vec<Found<u32>>>, E> {
+        let searches = locks::read(&self.searches);
+        let Some(i) = searches.iter().position(|search| search.name == name) else {
+            return Ok(None);
+        };
+        let now = self.now_ms();
+        let mut found = vec![];
+        for shard in &self.shards {
+            check()?;
+            let shard = locks::read(shard);
+            for (j, (key, score)) in shard.searches[i].1.search(query).into_iter().enumerate() {
+                if j % WALK_CHECK_EVERY == WALK_CHECK_EVERY - 1 {
+                    check()?;
+                }
+                if let Some(item) = shard.items.get(key).filter(|item| !item.is_expired(now)) {
+                    found.push((key.clone(), score, item.value.clone()));
+                }
+            }
+        }
+        found.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Ok(Some(found))
+    }
+
+    // The live keys matching `filter` whose vector in the field is nearest `vector`, with their distances and
+    // values, nearest first. None when there's no search index by that name, or no such vector field in it.
+    // `check` is called before every shard.
+    pub fn search_nearest<E>(&self, name: &str, filter: &Query, field: &str, vector: &[f32], knn: &Knn, mut check: impl FnMut() -> Result<(), E>) -> Result<Option<Vec<Found<f32>>>, E> {
+        let searches = locks::read(&self.searches);
+        let Some(i) = searches.iter().position(|search| search.name == name) else {
+            return Ok(None);
+        };
+        let Some(field) = searches[i].vector_field(field) else {
+            return Ok(None);
+        };
+        let now = self.now_ms();
+        let mut found = vec![];
+        for shard in &self.shards {
+            check()?;
+            let shard = locks::read(shard);
+            // Keys expired but not yet removed are skipped, which may leave a shard short of k
+            let nearest = shard.searches[i].1.nearest(filter, field, vector, knn);
+            for (distance, key) in nearest {
+                if let Some(item) = shard.items.get(key).filter(|item| !item.is_expired(now)) {
+                    found.push((key.clone(), distance, item.value.clone()));
+                }
+            }
+        }
+        found.sort_unstable_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        found.truncate(knn.k);
+        Ok(Some(found))
+    }
+
+    // Adds `delta` to a key's integer value, a missing key counting as 0, and returns the result. A live key
+    // keeps its TTL.
+    pub fn incr_by(&self, key: Bytes, delta: i64) -> std::result::Result<i64, IncrError> {
+        let mut shard = locks::write(self.shard(&key));
+        let now = self.now_ms();
+        let (current, expires_at) = match shard.items.get(&key).filter(|item| !item.is_expired(now)) {
+            Some(Item { value: StoredValue::Int(n), expires_at, .. }) => (*n, *expires_at),
+            Some(item) if item.value.value_type() != ValueType::String => return Err(IncrError::WrongType),
+            Some(_) => return Err(IncrError::NotInteger),
+            None => (0, None),
+        };
+        let value = current.checked_add(delta).ok_or(IncrError::Overflow)?;
+        let version = self.next_version.fetch_add(1, Ordering::Relaxed) + 1;
+        let item = Item::new(self.backend.write(&key, StoredValue::Int(value), expires_at), expires_at, version, now);
+        self.events.send(KeyEventKind::Set, &key, now);
+        shard.insert(key, item);
+        Ok(value)
+    }
+
+    // True when a live key was deleted
+    pub fn del(&self, key: &[u8]) -> bool {
+        let mut shard = locks::write(self.shard(key));
+        match shard.items.get_key_value(key) {
+            Some((key, item)) => {
+                let now = self.now_ms();
+                let (key, expired) = (key.clone(), item.is_expired(now));
+                shard.remove(&key);
+                self.backend.remove(&key);
+                self.events.send(if expired { KeyEventKind::Expired } else { KeyEventKind::Del }, &key, now);
+                !expired
+            },
+            None => false,
+        }
+    }
+
+    // Sets (Some) or clears (None) a live key's deadline when `flags` allow it. A deadline already in the past
+    // deletes the key. Returns whether anything changed.
+    pub fn expire(&self, key: &[u8], deadline: Option<u64>, flags: ExpireFlags) -> bool {
+        let mut shard = locks::write(self.shard(key));
+        let now = self.now_ms();
+        let Some((key, item)) = shard.items.get_key_value(key).filter(|(_, item)| !item.is_expired(now)) else {
+            return false;
+        };
+        let key = key.clone();
+        let allowed = match deadline {
+            Some(deadline) => flags.allows(item.expires_at, deadline),
+            None => item.expires_at.is_some(),
+        };
+        if !allowed {
+            return false;
+        }
+        let Some(mut item) = shard.remove(&key) else {
+            return false;
+        };
+        if deadline.is_some_and(|deadline| deadline <= now) {
+            self.backend.remove(&key);
+            self.events.send(KeyEventKind::Del, &key, now);
+            return true;
+        }
+        self.backend.set_expiry(&key, deadline);
+        item.expires_at = deadline;
+        item.version = self.next_version.fetch_add(1, Ordering::Relaxed) + 1;
+        self.events.send(KeyEventKind::Expire, &key, now);
+        shard.insert(key, item);
+        true
+    }
+
+    // Absolute deadline in UNIX milliseconds: None when the key doesn't exist, Some(None) when it has no expiry
+    pub fn expires_at(&self, key: &[u8]) -> Option<Option<u64>> {
+        let shard = locks::read(self.shard(key));
+        shard.items.get(key).filter(|item| !item.is_expired(self.now_ms())).map(|item| item.expires_at)
+    }
+
+    // Remaining time to live: None when the key doesn't exist, Some(None) when it has no expiry
+    pub fn ttl(&self, key: &[u8]) -> Option<Option<Duration>> {
+        let shard = locks::read(self.shard(key));
+        let now = self.now_ms();
+        let item = shard.items.get(key).filter(|item| !item.is_expired(now))?;
+        Some(item.expires_at.map(|deadline| Duration::from_millis(deadline.saturating_sub(now))))
+    }
 
-        for key in keys_to_remove {
-            self.storage.remove(&key);
+    // A batch of about `count` live keys for SCAN, the first in hash order from `cursor` on, and the cursor to
+    // continue from, 0 once every key was seen. Hash order doesn't change as shards grow or shrink, so a key
+    // there for the whole iteration is returned at least once. Keys sharing a hash always share a batch.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<Bytes>) {
+        let count = count.max(1);
+        let now = self.now_ms();
+        let mut found = vec![];
+        let mut more = false;
+        for shard in &self.shards {
+            let shard = locks::read(shard);
+            let mut entries = shard.order.range((cursor, Bytes::new())..).peekable();
+            let (mut taken, mut last) = (0, None);
+            while let Some((hash, key)) = entries.next_if(|(hash, _)| taken < count || last == Some(*hash)) {
+                let live = shard.items.get(key).is_some_and(|item| !item.is_expired(now));
+                found.push((*hash, key.clone(), live));
+                (taken, last) = (taken + 1, Some(*hash));
+            }
+            more |= entries.peek().is_some();
+        }
+        found.sort_unstable_by_key(|(hash, _, _)| *hash);
+        if found.len() > count {
+            let end = found[count - 1].0;
+            found.retain(|(hash, _, _)| *hash <= end);
+            more = true;
+        }
+        let next = match found.last() {
+            Some((hash, _, _)) if more => hash.checked_add(1).unwrap_or(0),
+            _ => 0,
+        };
+        (next, found.into_iter().filter(|(_, _, live)| *live).map(|(_, key, _)| key).collect())
+    }
+
+    // Passes the time left, in ms, of every live key with a TTL to `each`, or of every one matching `pattern`,
+    // a shard at a time, and returns how many live keys there are with none. Without a pattern only the keys
+    // with a TTL are looked at, in deadline order. `check` is called before every shard and every
+    // WALK_CHECK_EVERY keys, giving up with its error.
+    pub fn ttls<E>(&self, pattern: Option<&[u8]>, mut check: impl FnMut() -> Result<(), E>, mut each: impl FnMut(u64)) -> Result<usize, E> {
+        let now = self.now_ms();
+        let mut persistent = 0;
+        for shard in &self.shards {
+            check()?;
+            let shard = locks::read(shard);
+            let Some(pattern) = pattern else {
+                persistent += shard.items.len() - shard.expiries.len();
+                for (i, (deadline, _)) in shard.expiries.range((now, Bytes::new())..).enumerate() {
+                    if i % WALK_CHECK_EVERY == WALK_CHECK_EVERY - 1 {
+                        check()?;
+                    }
+                    each(deadline - now);
+                }
+                continue;
+            };
+            for (i, (key, item)) in shard.items.iter().enumerate() {
+                if i % WALK_CHECK_EVERY == WALK_CHECK_EVERY - 1 {
+                    check()?;
+                }
+                if item.is_expired(now) || !glob::matches(pattern, key) {
+                    continue;
+                }
+                match item.expires_at {
+                    Some(deadline) => each(deadline - now),
+                    None => persistent += 1,
+                }
+            }
+        }
+        Ok(persistent)
+    }
+
+    // Deletes every key whose deadline has passed, for embedders running without the expiry cycle
+    pub fn remove_expired(&self) -> usize {
+        let mut removed = 0;
+        for shard in &self.shards {
+            loop {
+                let expired = locks::write(shard).remove_due(self.now_ms(), EXPIRE_BATCH, self.backend.as_ref(), &self.events);
+                removed += expired;
+                if expired < EXPIRE_BATCH {
+                    break;
+                }
+            }
+        }
+        stats::incr(&SERVER_STATS.expired_keys, removed as u64);
+        removed
+    }
+
+    // Whether there are writes the backend hasn't made durable yet; replies to clients wait for sync
+    pub fn needs_sync(&self) -> bool {
+        self.backend.needs_sync()
+    }
+
+    pub fn sync(&self) -> io::Result<()> {
+        self.backend.sync()
+    }
+
+    pub fn needs_compaction(&self) -> bool {
+        self.backend.needs_compaction()
+    }
+
+    // Lets the backend reclaim space taken by overwritten and deleted values. A backend that rewrites from a
+    // point-in-time view does so while writes carry on, otherwise every shard is locked throughout.
+    pub fn compact(&self) -> io::Result<()> {
+        if self.backend.rewrites_from_view() {
+            let start = |_: &[RwLockReadGuard<'_, Shard>]| self.backend.start_rewrite();
+            let each = |rewrite: &mut Box<dyn Rewrite + '_>, key: &Bytes, value: &StoredValue, expires_at| rewrite.write(key, value, expires_at);
+            let (rewrite, _) = self.read_view(start, each)?;
+            return rewrite.finish();
+        }
+        let _view = locks::lock(&self.view);
+        let mut shards: Vec<_> = self.shards.iter().map(locks::write).collect();
+        let mut items = shards.iter_mut().flat_map(|shard| shard.items.iter_mut());
+        self.backend.compact(&mut items)
+    }
+
+    // Spills up to SPILL_BATCH values idle for tiering-idle-time from the next shard in turn to the tier file,
+    // returning how many. The shard is scanned with its read lock, writers only wait while the values are
+    // swapped for their place in the file.
+    fn spill_cycle(&self) -> io::Result<usize> {
+        let idle_time = self.tiering_idle_time.load(Ordering::Relaxed);
+        let Some(tier) = self.tier.as_ref() else {
+            return Ok(0);
+        };
+        let shard = &self.shards[self.next_tier_shard.fetch_add(1, Ordering::Relaxed) % self.shards.len()];
+        let now = self.now_ms();
+        let cold: Vec<_> = if idle_time == 0 {
+            vec![]
+        } else {
+            locks::read(shard)
+                .items
+                .iter()
+                .filter(|(_, item)| spillable(&item.value) && !item.is_expired(now) && item.idle_secs(now) >= idle_time)
+                .take(SPILL_BATCH)
+                .map(|(key, item)| (key.clone(), item.version, item.value.to_bytes()))
+                .collect()
+        };
+        let mut spilled = Vec::with_capacity(cold.len());
+        for (key, version, value) in cold {
+            spilled.push((key, version, tier.spill(&value)?));
+        }
+        // Values written meanwhile stay in memory, their copy in the file is garbage straight away
+        let mut shard = locks::write(shard);
+        let count = spilled.into_iter().filter(|(key, version, value)| shard.replace_value(key, *version, value.clone())).count();
+        drop(shard);
+        stats::incr(&SERVER_STATS.tier_spills, count as u64);
+
+        // Not while a view is read, it may still hold places in the file
+        let view = self.view.try_lock();
+        if view.is_ok() && tier.needs_compaction(self.encoding_counts().spilled_bytes) {
+            let mut shards: Vec<_> = self.shards.iter().map(locks::write).collect();
+            tier.compact(shards.iter_mut().flat_map(|shard| shard.items.values_mut()))?;
+        }
+        Ok(count)
+    }
+
+    // One run of the active expiry cycle: walks the shards deleting due keys in batches, releasing the shard
+    // lock between batches, until everything due is gone or `budget` is used up. The next run resumes at the
+    // shard this one stopped at.
+    fn expire_cycle(&self, start_shard: usize, budget: Duration) -> usize {
+        let started = Instant::now();
+        for offset in 0..self.shards.len() {
+            let index = (start_shard + offset) % self.shards.len();
+            loop {
+                let expired = locks::write(&self.shards[index]).remove_due(self.now_ms(), EXPIRE_BATCH, self.backend.as_ref(), &self.events);
+                stats::incr(&SERVER_STATS.expired_keys, expired as u64);
+                if started.elapsed() >= budget {
+                    return index;
+                }
+                if expired < EXPIRE_BATCH {
+                    break;
+                }
+            }
+        }
+        start_shard
+    }
+}
+
+// Background task deleting expired keys that nobody reads, `hz` times a second. With an executor the cycles
+// run on it, in line with the commands. Compacting the backend's storage is checked for after each cycle, and
+// once a second a shard is looked through for values to spill to the cold tier. Writes no client is waiting
+// on, such as expiries, are synced every cycle.
+pub async fn active_expire(storage: Arc<Storage>, hz: u32, executor: Option<Arc<Executor>>) {
+    let period = Duration::from_secs(1) / hz.max(1);
+    let budget = period * EXPIRE_CYCLE_BUDGET_PERCENT / 100;
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut next_shard = 0;
+    let mut ticks = 0;
+    loop {
+        interval.tick().await;
+        next_shard = match &executor {
+            Some(executor) => {
+                let storage = Arc::clone(&storage);
+                executor.run(move || storage.expire_cycle(next_shard, budget)).await
+            },
+            None => storage.expire_cycle(next_shard, budget),
+        };
+        if ticks % hz.max(1) == 0 {
+            let storage = Arc::clone(&storage);
+            if let Ok(Err(e)) = tokio::task::spawn_blocking(move || storage.spill_cycle()).await {
+                log_error!("Spilling cold values failed: {}", e);
+            }
+        }
+        ticks += 1;
+        if storage.needs_sync() {
+            // A failure is logged by the backend, and every client waiting on it disconnected
+            let storage = Arc::clone(&storage);
+            let _ = tokio::task::spawn_blocking(move || storage.sync()).await;
+        }
+        if storage.needs_compaction() {
+            let storage = Arc::clone(&storage);
+            match tokio::task::spawn_blocking(move || storage.compact()).await {
+                Ok(Err(e)) => log_error!("Compacting the log failed: {}", e),
+                Ok(Ok(())) => log_info!("Compacted the log"),
+                Err(_) => {},
+            }
         }
     }
 }
 
 impl Default for Storage {
     fn default() -> Self {
-        Storage::new()
+        Storage::new(BackendKind::Sharded, DEFAULT_SHARDS)
     }
 }
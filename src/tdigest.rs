@@ -0,0 +1,225 @@
+// T-digests (Dunning and Ertl): streaming quantile estimates in bounded memory. Values are summarised as
+// centroids, a mean and how many values it stands for, sorted by mean. Each add merges the new values in and
+// compresses the lot, letting neighbouring centroids combine only as far as the k1 scale function allows, so
+// centroids stay small near the tails, where quantiles need to be precise, and grow large in the middle.
+// `compression` bounds how many centroids there are, about that many, and so how precise estimates are.
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use crate::record::PayloadReader;
+
+pub const DEFAULT_COMPRESSION: f64 = 100.0;
+// Bounds how many centroids a digest keeps
+pub const MAX_COMPRESSION: f64 = 100_000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Centroid {
+    mean: f64,
+    weight: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TDigest {
+    pub compression: f64,
+    pub min: f64, // Of every value added; infinite while empty
+    pub max: f64,
+    pub compressions: u64,
+    centroids: Vec<Centroid>, // By mean
+}
+
+// Values are checked to be finite as they're added, so equality is total
+impl Eq for TDigest {}
+
+impl TDigest {
+    pub fn new(compression: f64) -> Result<TDigest> {
+        if !(1.0..=MAX_COMPRESSION).contains(&compression) {
+            bail!("the compression must be between 1 and {}", MAX_COMPRESSION);
+        }
+        Ok(TDigest { compression, min: f64::INFINITY, max: f64::NEG_INFINITY, compressions: 0, centroids: vec![] })
+    }
+
+    // The k1 scale function, and its inverse: a centroid may span at most 1 of k
+    fn scale(&self, q: f64) -> f64 {
+        self.compression / (2.0 * std::f64::consts::PI) * (2.0 * q - 1.0).clamp(-1.0, 1.0).asin()
+    }
+
+    fn unscale(&self, k: f64) -> f64 {
+        let angle = (k * 2.0 * std::f64::consts::PI / self.compression).clamp(-std::f64::consts::FRAC_PI_2, std::f64::consts::FRAC_PI_2);
+        (angle.sin() + 1.0) / 2.0
+    }
+
+    // Merges `added` centroids in with this digest's, combining neighbours as far as the scale allows
+    fn compress(&mut self, added: Vec<Centroid>) {
+        let mut all = std::mem::take(&mut self.centroids);
+        all.extend(added);
+        if all.is_empty() {
+            return;
+        }
+        all.sort_by(|a, b| a.mean.total_cmp(&b.mean));
+        let total: f64 = all.iter().map(|centroid| centroid.weight).sum();
+        let mut merged = Vec::with_capacity(self.compression as usize);
+        let (mut before, mut limit) = (0.0, total * self.unscale(self.scale(0.0) + 1.0));
+        let mut current = all[0];
+        for next in all.into_iter().skip(1) {
+            if before + current.weight + next.weight <= limit {
+                let weight = current.weight + next.weight;
+                current = Centroid { mean: current.mean + (next.mean - current.mean) * next.weight / weight, weight };
+            } else {
+                before += current.weight;
+                merged.push(current);
+                limit = total * self.unscale(self.scale(before / total) + 1.0);
+                current = next;
+            }
+        }
+        merged.push(current);
+        self.centroids = merged;
+        self.compressions += 1;
+    }
+
+    pub fn add(&mut self, values: &[f64]) -> Result<()> {
+        if values.iter().any(|value| !value.is_finite()) {
+            bail!("values must be finite");
+        }
+        for &value in values {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.compress(values.iter().map(|&mean| Centroid { mean, weight: 1.0 }).collect());
+        Ok(())
+    }
+
+    // Adds every value `other` summarises
+    pub fn merge(&mut self, other: &TDigest) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.compress(other.centroids.clone());
+    }
+
+    // How many values were added
+    pub fn weight(&self) -> f64 {
+        self.centroids.iter().map(|centroid| centroid.weight).sum()
+    }
+
+    pub fn centroids(&self) -> usize {
+        self.centroids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.centroids.is_empty()
+    }
+
+    // The value `q` of the way through those added, interpolating between centroids; NaN while empty
+    pub fn quantile(&self, q: f64) -> f64 {
+        let (Some(first), Some(last)) = (self.centroids.first(), self.centroids.last()) else {
+            return f64::NAN;
+        };
+        let total = self.weight();
+        let index = q.clamp(0.0, 1.0) * total;
+        // The extremes are known exactly; between them and the outer centroids' middles values are spread
+        // evenly, a single value standing where it is
+        if index < 1.0 {
+            return self.min;
+        }
+        if index > total - 1.0 {
+            return self.max;
+        }
+        if first.weight > 1.0 && index < first.weight / 2.0 {
+            return self.min + (index - 1.0) / (first.weight / 2.0 - 1.0) * (first.mean - self.min);
+        }
+        if last.weight > 1.0 && total - index <= last.weight / 2.0 {
+            return self.max - (total - index - 1.0) / (last.weight / 2.0 - 1.0) * (self.max - last.mean);
+        }
+        let mut before = first.weight / 2.0;
+        for pair in self.centroids.windows(2) {
+            let (left, right) = (pair[0], pair[1]);
+            let gap = (left.weight + right.weight) / 2.0;
+            if before + gap > index {
+                // Single values take up half a unit either side of themselves, a rank midway going to the higher
+                let mut left_unit = 0.0;
+                if left.weight == 1.0 {
+                    if index - before < 0.5 {
+                        return left.mean;
+                    }
+                    left_unit = 0.5;
+                }
+                let mut right_unit = 0.0;
+                if right.weight == 1.0 {
+                    if before + gap - index <= 0.5 {
+                        return right.mean;
+                    }
+                    right_unit = 0.5;
+                }
+                let (into, left_of_end) = (index - before - left_unit, before + gap - index - right_unit);
+                return (left.mean * left_of_end + right.mean * into) / (into + left_of_end);
+            }
+            before += gap;
+        }
+        last.mean
+    }
+
+    // The fraction of the values added that are below `value`, counting half of those equal to it; NaN while
+    // empty
+    pub fn cdf(&self, value: f64) -> f64 {
+        if self.is_empty() {
+            return f64::NAN;
+        }
+        if value < self.min {
+            return 0.0;
+        }
+        if value > self.max {
+            return 1.0;
+        }
+        let total = self.weight();
+        // Cumulative weight rises linearly from the minimum through each centroid's middle to the maximum
+        let mut points = vec![(self.min, 0.0)];
+        let mut before = 0.0;
+        for centroid in &self.centroids {
+            points.push((centroid.mean, before + centroid.weight / 2.0));
+            before += centroid.weight;
+        }
+        points.push((self.max, total));
+        let equal: Vec<f64> = points.iter().filter(|&&(at, _)| at == value).map(|&(_, weight)| weight).collect();
+        if let (Some(low), Some(high)) = (equal.first(), equal.last()) {
+            return (low + high) / 2.0 / total;
+        }
+        let right = points.partition_point(|&(at, _)| at < value);
+        let ((x0, y0), (x1, y1)) = (points[right - 1], points[right]);
+        (y0 + (value - x0) / (x1 - x0) * (y1 - y0)) / total
+    }
+
+    // Forgets every value, keeping the compression
+    pub fn reset(&mut self) {
+        *self = TDigest::new(self.compression).expect("the compression is valid");
+    }
+
+    // Bytes the centroids take
+    pub fn size(&self) -> usize {
+        self.centroids.len() * std::mem::size_of::<Centroid>()
+    }
+
+    // The digest as stored in a record: compression f64 | min f64 | max f64 | compressions u64 | centroid count
+    // u64, then each centroid's mean f64 | weight f64, by mean, all little-endian
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(40 + 16 * self.centroids.len());
+        let header = [self.compression.to_bits(), self.min.to_bits(), self.max.to_bits(), self.compressions, self.centroids.len() as u64];
+        let centroids = self.centroids.iter().flat_map(|centroid| [centroid.mean.to_bits(), centroid.weight.to_bits()]);
+        for n in header.into_iter().chain(centroids) {
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        out
+    }
+
+    pub fn decode(payload: &Bytes) -> Option<TDigest> {
+        let mut reader = PayloadReader::new(payload);
+        let mut digest = TDigest::new(f64::from_bits(reader.u64()?)).ok()?;
+        (digest.min, digest.max) = (f64::from_bits(reader.u64()?), f64::from_bits(reader.u64()?));
+        digest.compressions = reader.u64()?;
+        for _ in 0..reader.u64()? {
+            let (mean, weight) = (f64::from_bits(reader.u64()?), f64::from_bits(reader.u64()?));
+            if !mean.is_finite() || !weight.is_finite() || weight <= 0.0 {
+                return None;
+            }
+            digest.centroids.push(Centroid { mean, weight });
+        }
+        reader.is_empty().then_some(digest)
+    }
+}
@@ -0,0 +1,54 @@
+// Tenants: named namespaces, so one server can serve several applications without them seeing each other's
+// keys. Each one in `tenants` is a name and a password:
+//
+//   tenants "billing=s3cret search=hunter2"
+//
+// A client that authenticates as one, with AUTH <tenant> <password>, is confined to it. Every key it names
+// is kept as `<tenant>:<key>`, SCAN, DBSIZE and FLUSHDB/FLUSHALL only see its keys, and commands that reach
+// past the keys they name are refused: admin commands, pub/sub, indexes, queries and the like, see
+// commands/mod.rs. The default user, with requirepass, sees every tenant's keys under their prefixes, so
+// requirepass has to be set along with tenants; clients that don't authenticate would be the default user.
+//
+// Channels, indexes and keyspace events aren't split by tenant, which is why tenants can't get at them.
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tenant {
+    pub name: String,
+    pub password: String,
+}
+
+impl Tenant {
+    // name=password
+    pub fn parse(pair: &str) -> Result<Tenant> {
+        let (name, password) = pair.split_once('=').ok_or_else(|| anyhow::anyhow!("Expected name=password, got '{}'", pair))?;
+        if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-') {
+            return Err(anyhow::anyhow!("Invalid tenant name '{}', use letters, digits, _ and -", name));
+        }
+        if name == "default" {
+            return Err(anyhow::anyhow!("The default user can't be a tenant"));
+        }
+        if password.is_empty() {
+            return Err(anyhow::anyhow!("Tenant '{}' needs a password", name));
+        }
+        Ok(Tenant { name: name.to_string(), password: password.to_string() })
+    }
+
+    pub fn as_string(&self) -> String {
+        format!("{}={}", self.name, self.password)
+    }
+}
+
+// What the keys of the tenant `name` are kept under
+pub fn prefix(name: &str) -> Bytes {
+    Bytes::from(format!("{}:", name))
+}
+
+// `key` as it's kept for the tenant with `prefix`
+pub fn scoped(prefix: &[u8], key: &[u8]) -> Bytes {
+    let mut scoped = BytesMut::with_capacity(prefix.len() + key.len());
+    scoped.put_slice(prefix);
+    scoped.put_slice(key);
+    scoped.freeze()
+}
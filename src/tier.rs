@@ -0,0 +1,120 @@
+// The cold tier: values nobody has touched for tiering-idle-time are moved out of memory into a scratch file
+// in the data directory, and moved back the next time they're read. The file holds nothing across restarts,
+// it is emptied when first used. Values are appended; the space of ones read back in, overwritten or deleted
+// is reclaimed when no spilled value is left, or by rewriting the file once it's mostly garbage. With
+// encryption on, every file is encrypted with a key of its own that's never written down, see crypt.rs.
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use bytes::Bytes;
+use crate::crypt::{self, Cipher};
+use crate::locks;
+use crate::storage::{Item, StoredValue};
+
+// In the data directory
+pub const TIER_FILE: &str = "zenql.tier";
+
+// The file is rewritten once it's at least this big and more than half garbage
+const COMPACT_MIN_SIZE: u64 = 64 * 1024 * 1024;
+
+pub struct Tier {
+    path: PathBuf,
+    encrypted: bool,
+    file: RwLock<Option<TierFile>>, // Opened on the first spill
+}
+
+struct TierFile {
+    file: File,
+    end: u64,
+    cipher: Option<Cipher>,
+}
+
+impl Tier {
+    pub fn new(path: PathBuf, encrypted: bool) -> Self {
+        Tier { path, encrypted, file: RwLock::new(None) }
+    }
+
+    fn new_cipher(&self) -> io::Result<Option<Cipher>> {
+        self.encrypted.then(Cipher::ephemeral).transpose()
+    }
+
+    // Appends a value, returning what the keyspace holds for it instead
+    pub fn spill(&self, value: &[u8]) -> io::Result<StoredValue> {
+        let mut file = locks::write(&self.file);
+        let tier = match &mut *file {
+            Some(tier) => tier,
+            unopened => {
+                let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&self.path)?;
+                unopened.insert(TierFile { file, end: 0, cipher: self.new_cipher()? })
+            },
+        };
+        let offset = tier.end;
+        tier.file.write_all_at(&crypt::encrypt(tier.cipher.as_ref(), offset, value), offset)?;
+        tier.end += value.len() as u64;
+        Ok(StoredValue::Spilled { offset, len: value.len() })
+    }
+
+    pub fn read(&self, offset: u64, len: usize) -> io::Result<Bytes> {
+        let file = locks::read(&self.file);
+        let Some(tier) = file.as_ref() else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} isn't open, nothing was spilled to it", self.path.display())));
+        };
+        let mut value = vec![0; len];
+        tier.file.read_exact_at(&mut value, offset).map_err(|e| io::Error::new(e.kind(), format!("reading {} at {}: {}", self.path.display(), offset, e)))?;
+        if let Some(cipher) = &tier.cipher {
+            cipher.apply(offset, &mut value);
+        }
+        Ok(value.into())
+    }
+
+    // Whether compact is due, given how many bytes spilled values still take
+    pub fn needs_compaction(&self, live: usize) -> bool {
+        let file = locks::read(&self.file);
+        file.as_ref().is_some_and(|tier| tier.end > 0 && (live == 0 || (tier.end >= COMPACT_MIN_SIZE && tier.end / 2 > live as u64)))
+    }
+
+    // Copies the spilled values to a new file and swaps it in, with every shard locked
+    pub fn compact<'a>(&self, items: impl Iterator<Item = &'a mut Item>) -> io::Result<()> {
+        let mut file = locks::write(&self.file);
+        let Some(tier) = file.as_mut() else {
+            return Ok(());
+        };
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        let cipher = self.new_cipher()?;
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&temp)?;
+        let mut out = crypt::Writer::new(BufWriter::new(file), cipher.clone(), 0);
+        let mut end = 0;
+        let mut moved = vec![];
+        let written: io::Result<()> = (|| {
+            for item in items {
+                let StoredValue::Spilled { offset, len } = item.value else {
+                    continue;
+                };
+                let mut value = vec![0; len];
+                tier.file.read_exact_at(&mut value, offset)?;
+                if let Some(cipher) = &tier.cipher {
+                    cipher.apply(offset, &mut value);
+                }
+                out.write_all(&value)?;
+                moved.push((item, end));
+                end += len as u64;
+            }
+            out.flush()?;
+            fs::rename(&temp, &self.path)
+        })();
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temp);
+            return Err(e);
+        }
+        for (item, new_offset) in moved {
+            if let StoredValue::Spilled { offset, .. } = &mut item.value {
+                *offset = new_offset;
+            }
+        }
+        *tier = TierFile { file: out.into_inner().into_inner().map_err(|e| e.into_error())?, end, cipher };
+        Ok(())
+    }
+}
@@ -0,0 +1,408 @@
+// Time series: samples, a millisecond timestamp and a float each, kept in timestamp order. Samples nearly
+// always come newest last, so they're appended to the back of a deque, and a retention period trims them off
+// the front; one that arrives out of order is inserted where it belongs. A sample for a timestamp already
+// there is resolved by the duplicate policy. A series is always in memory, like a document or a hash.
+//
+// A series may have labels, which TS.MRANGE selects series by, and compaction rules: each sends a
+// downsampled copy of it to another series, the aggregate of every bucket of `bucket` milliseconds once a
+// later sample closes the bucket. The aggregate is worked out from the source's samples, so an out of order
+// sample landing in a closed bucket updates its aggregate too. Compactions don't cascade: samples they write
+// aren't compacted further.
+use std::collections::VecDeque;
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use crate::record::PayloadReader;
+
+// One timestamp and its value; values are always finite
+pub type Sample = (i64, f64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    #[default]
+    Block,
+    First,
+    Last,
+    Min,
+    Max,
+    Sum,
+}
+
+impl DuplicatePolicy {
+    pub const ALL: [DuplicatePolicy; 6] = [
+        DuplicatePolicy::Block, DuplicatePolicy::First, DuplicatePolicy::Last, DuplicatePolicy::Min, DuplicatePolicy::Max, DuplicatePolicy::Sum,
+    ];
+
+    pub fn parse(name: &str) -> Result<DuplicatePolicy> {
+        match DuplicatePolicy::ALL.into_iter().find(|policy| policy.as_str().eq_ignore_ascii_case(name)) {
+            Some(policy) => Ok(policy),
+            None => bail!("unknown duplicate policy '{}'", name),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DuplicatePolicy::Block => "block",
+            DuplicatePolicy::First => "first",
+            DuplicatePolicy::Last => "last",
+            DuplicatePolicy::Min => "min",
+            DuplicatePolicy::Max => "max",
+            DuplicatePolicy::Sum => "sum",
+        }
+    }
+
+    // The value kept when `new` arrives for a timestamp holding `old`, None when it's refused
+    fn resolve(self, old: f64, new: f64) -> Option<f64> {
+        match self {
+            DuplicatePolicy::Block => None,
+            DuplicatePolicy::First => Some(old),
+            DuplicatePolicy::Last => Some(new),
+            DuplicatePolicy::Min => Some(old.min(new)),
+            DuplicatePolicy::Max => Some(old.max(new)),
+            DuplicatePolicy::Sum => Some(old + new).filter(|sum| sum.is_finite()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    Avg,
+    Sum,
+    Min,
+    Max,
+    Range, // Max minus min
+    Count,
+    First,
+    Last,
+    StdP, // Population standard deviation
+    StdS, // Sample standard deviation
+    VarP,
+    VarS,
+}
+
+impl Aggregation {
+    pub const ALL: [Aggregation; 12] = [
+        Aggregation::Avg, Aggregation::Sum, Aggregation::Min, Aggregation::Max, Aggregation::Range, Aggregation::Count,
+        Aggregation::First, Aggregation::Last, Aggregation::StdP, Aggregation::StdS, Aggregation::VarP, Aggregation::VarS,
+    ];
+
+    pub fn parse(name: &str) -> Result<Aggregation> {
+        match Aggregation::ALL.into_iter().find(|aggregation| aggregation.as_str().eq_ignore_ascii_case(name)) {
+            Some(aggregation) => Ok(aggregation),
+            None => bail!("unknown aggregation '{}'", name),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Aggregation::Avg => "avg",
+            Aggregation::Sum => "sum",
+            Aggregation::Min => "min",
+            Aggregation::Max => "max",
+            Aggregation::Range => "range",
+            Aggregation::Count => "count",
+            Aggregation::First => "first",
+            Aggregation::Last => "last",
+            Aggregation::StdP => "std.p",
+            Aggregation::StdS => "std.s",
+            Aggregation::VarP => "var.p",
+            Aggregation::VarS => "var.s",
+        }
+    }
+
+    // The aggregate of some values, in timestamp order; there's at least one
+    pub fn apply(self, values: &[f64]) -> f64 {
+        let n = values.len() as f64;
+        let sum: f64 = values.iter().sum();
+        let variance = |dof: f64| {
+            let mean = sum / n;
+            if n <= dof {
+                return 0.0;
+            }
+            values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / (n - dof)
+        };
+        let min = || values.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = || values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        match self {
+            Aggregation::Avg => sum / n,
+            Aggregation::Sum => sum,
+            Aggregation::Min => min(),
+            Aggregation::Max => max(),
+            Aggregation::Range => max() - min(),
+            Aggregation::Count => n,
+            Aggregation::First => values[0],
+            Aggregation::Last => values[values.len() - 1],
+            Aggregation::StdP => variance(0.0).sqrt(),
+            Aggregation::StdS => variance(1.0).sqrt(),
+            Aggregation::VarP => variance(0.0),
+            Aggregation::VarS => variance(1.0),
+        }
+    }
+}
+
+// Where the bucket of `bucket` milliseconds holding `timestamp` starts, buckets being aligned to `align`
+pub fn bucket_start(timestamp: i64, bucket: u64, align: i64) -> i64 {
+    let bucket = bucket.min(i64::MAX as u64) as i64;
+    timestamp - (timestamp as i128 - align as i128).rem_euclid(bucket as i128) as i64
+}
+
+// Samples downsampled: one per bucket with any, timestamped with the bucket's start
+pub fn aggregate(samples: impl Iterator<Item = Sample>, aggregation: Aggregation, bucket: u64, align: i64) -> Vec<Sample> {
+    let mut found = vec![];
+    let mut current: Option<(i64, Vec<f64>)> = None;
+    for (timestamp, value) in samples {
+        let start = bucket_start(timestamp, bucket, align);
+        match &mut current {
+            Some((at, values)) if *at == start => values.push(value),
+            _ => {
+                if let Some((at, values)) = current.replace((start, vec![value])) {
+                    found.push((at, aggregation.apply(&values)));
+                }
+            },
+        }
+    }
+    if let Some((at, values)) = current {
+        found.push((at, aggregation.apply(&values)));
+    }
+    found
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub dest: Bytes,
+    pub aggregation: Aggregation,
+    pub bucket: u64, // Milliseconds, at least 1
+    pub align: i64,
+}
+
+impl Rule {
+    // The aggregate of the bucket holding `timestamp` in `series`, which the rule's destination should have
+    pub fn compact(&self, series: &TimeSeries, timestamp: i64) -> Option<Sample> {
+        let start = bucket_start(timestamp, self.bucket, self.align);
+        let end = start.saturating_add(self.bucket.min(i64::MAX as u64) as i64 - 1);
+        let values: Vec<f64> = series.range(start, end).map(|(_, value)| value).collect();
+        (!values.is_empty()).then(|| (start, self.aggregation.apply(&values)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TimeSeries {
+    pub retention: u64, // Milliseconds before the newest sample that older ones are kept for, 0 for ever
+    pub duplicate_policy: DuplicatePolicy,
+    pub labels: Vec<(String, String)>,
+    pub rules: Vec<Rule>,
+    pub source: Option<Bytes>, // The series whose rule compacts into this one
+    samples: VecDeque<Sample>,
+}
+
+// Values are never NaN, so equality is total
+impl Eq for TimeSeries {}
+
+impl TimeSeries {
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn first(&self) -> Option<Sample> {
+        self.samples.front().copied()
+    }
+
+    pub fn last(&self) -> Option<Sample> {
+        self.samples.back().copied()
+    }
+
+    pub fn label(&self, name: &str) -> Option<&str> {
+        self.labels.iter().find(|(label, _)| label == name).map(|(_, value)| value.as_str())
+    }
+
+    // Adds a sample, `policy` overriding the series' own for a timestamp that's already there. Gives the
+    // timestamp of the newest sample before, to tell which of the rules' buckets it closed.
+    pub fn add(&mut self, (timestamp, value): Sample, policy: Option<DuplicatePolicy>) -> Result<Option<i64>> {
+        if !value.is_finite() {
+            bail!("invalid value");
+        }
+        let newest = self.last().map(|(newest, _)| newest);
+        if let Some(newest) = newest.filter(|_| self.retention > 0) {
+            if (timestamp as i128) < newest as i128 - self.retention as i128 {
+                bail!("timestamp is older than the retention period");
+            }
+        }
+        if newest.is_none_or(|newest| timestamp > newest) {
+            self.samples.push_back((timestamp, value));
+            self.trim();
+            return Ok(newest);
+        }
+        match self.samples.binary_search_by_key(&timestamp, |&(at, _)| at) {
+            Ok(i) => {
+                let policy = policy.unwrap_or(self.duplicate_policy);
+                let Some(value) = policy.resolve(self.samples[i].1, value) else {
+                    bail!("the {} duplicate policy refused another sample for timestamp {}", policy.as_str().to_uppercase(), timestamp);
+                };
+                self.samples[i].1 = value;
+            },
+            Err(i) => self.samples.insert(i, (timestamp, value)),
+        }
+        Ok(newest)
+    }
+
+    // Drops the samples older than the retention period allows
+    pub fn trim(&mut self) {
+        let Some((newest, _)) = self.last() else {
+            return;
+        };
+        if self.retention == 0 {
+            return;
+        }
+        let oldest = (newest as i128 - self.retention as i128).max(i64::MIN as i128) as i64;
+        while self.samples.front().is_some_and(|&(at, _)| at < oldest) {
+            self.samples.pop_front();
+        }
+    }
+
+    // The samples from `from` to `to`, both included
+    pub fn range(&self, from: i64, to: i64) -> impl DoubleEndedIterator<Item = Sample> + '_ {
+        let start = self.samples.partition_point(|&(at, _)| at < from);
+        let end = self.samples.partition_point(|&(at, _)| at <= to).max(start);
+        self.samples.range(start..end).copied()
+    }
+
+    // Deletes the samples from `from` to `to`, both included, giving how many there were
+    pub fn delete(&mut self, from: i64, to: i64) -> usize {
+        let start = self.samples.partition_point(|&(at, _)| at < from);
+        let end = self.samples.partition_point(|&(at, _)| at <= to).max(start);
+        self.samples.drain(start..end).count()
+    }
+
+    // The buckets of a rule that the sample just added at `timestamp`, when the newest before was at
+    // `newest`, closed or changed after closing, with their aggregates
+    pub fn compactions(&self, rule: &Rule, timestamp: i64, newest: Option<i64>) -> Vec<Sample> {
+        let bucket = |at| bucket_start(at, rule.bucket, rule.align);
+        let Some((last, _)) = self.last() else {
+            return vec![];
+        };
+        let mut buckets = vec![];
+        if let Some(newest) = newest.filter(|&newest| bucket(newest) < bucket(last)) {
+            buckets.push(bucket(newest));
+        }
+        if bucket(timestamp) < bucket(last) && !buckets.contains(&bucket(timestamp)) {
+            buckets.push(bucket(timestamp));
+        }
+        buckets.into_iter().filter_map(|start| rule.compact(self, start)).collect()
+    }
+
+    // The series as stored in a record: retention u64 | duplicate policy u8 | source | labels | rules |
+    // samples, where the source is a flag u8 and then a string, the rest counts u64 followed by their items,
+    // strings are length u64 | bytes, rules dest | aggregation u8 | bucket u64 | align i64, and samples
+    // timestamp i64 | value f64. All little-endian.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        let string = |out: &mut Vec<u8>, s: &[u8]| {
+            out.extend_from_slice(&(s.len() as u64).to_le_bytes());
+            out.extend_from_slice(s);
+        };
+        out.extend_from_slice(&self.retention.to_le_bytes());
+        out.push(DuplicatePolicy::ALL.iter().position(|&policy| policy == self.duplicate_policy).expect("listed") as u8);
+        match &self.source {
+            Some(source) => {
+                out.push(1);
+                string(&mut out, source);
+            },
+            None => out.push(0),
+        }
+        out.extend_from_slice(&(self.labels.len() as u64).to_le_bytes());
+        for (name, value) in &self.labels {
+            string(&mut out, name.as_bytes());
+            string(&mut out, value.as_bytes());
+        }
+        out.extend_from_slice(&(self.rules.len() as u64).to_le_bytes());
+        for rule in &self.rules {
+            string(&mut out, &rule.dest);
+            out.push(Aggregation::ALL.iter().position(|&aggregation| aggregation == rule.aggregation).expect("listed") as u8);
+            out.extend_from_slice(&rule.bucket.to_le_bytes());
+            out.extend_from_slice(&rule.align.to_le_bytes());
+        }
+        out.extend_from_slice(&(self.samples.len() as u64).to_le_bytes());
+        for (timestamp, value) in &self.samples {
+            out.extend_from_slice(&timestamp.to_le_bytes());
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out
+    }
+
+    pub fn decode(payload: &Bytes) -> Option<TimeSeries> {
+        let mut reader = PayloadReader::new(payload);
+        let retention = reader.u64()?;
+        let duplicate_policy = *DuplicatePolicy::ALL.get(reader.u8()? as usize)?;
+        let source = match reader.u8()? {
+            0 => None,
+            1 => Some(reader.bytes()?),
+            _ => return None,
+        };
+        let mut labels = vec![];
+        for _ in 0..reader.u64()? {
+            labels.push((reader.string()?, reader.string()?));
+        }
+        let mut rules = vec![];
+        for _ in 0..reader.u64()? {
+            let dest = reader.bytes()?;
+            let aggregation = *Aggregation::ALL.get(reader.u8()? as usize)?;
+            let bucket = reader.u64()?.max(1);
+            rules.push(Rule { dest, aggregation, bucket, align: reader.u64()? as i64 });
+        }
+        let mut samples = VecDeque::new();
+        for _ in 0..reader.u64()? {
+            let sample = (reader.u64()? as i64, f64::from_bits(reader.u64()?));
+            if !sample.1.is_finite() || samples.back().is_some_and(|&(at, _)| at >= sample.0) {
+                return None;
+            }
+            samples.push_back(sample);
+        }
+        if !reader.is_empty() {
+            return None;
+        }
+        Some(TimeSeries { retention, duplicate_policy, labels, rules, source, samples })
+    }
+}
+
+// One condition of a TS.MRANGE filter: `label=value`, `label!=value`, or either with `(value,value...)`.
+// A missing label counts as the empty value, so `label=` selects the series without it and `label!=` those
+// with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Matcher {
+    pub label: String,
+    pub values: Vec<String>,
+    pub equal: bool,
+}
+
+impl Matcher {
+    pub fn parse(text: &str) -> Result<Matcher> {
+        let (label, values, equal) = match text.split_once("!=") {
+            Some((label, values)) => (label, values, false),
+            None => match text.split_once('=') {
+                Some((label, values)) => (label, values, true),
+                None => bail!("invalid filter '{}', expected label=value or label!=value", text),
+            },
+        };
+        if label.is_empty() {
+            bail!("invalid filter '{}', the label is missing", text);
+        }
+        let values = match values.strip_prefix('(').and_then(|values| values.strip_suffix(')')) {
+            Some(values) => values.split(',').map(|value| value.trim().to_string()).collect(),
+            None => vec![values.to_string()],
+        };
+        Ok(Matcher { label: label.to_string(), values, equal })
+    }
+
+    // Whether the matcher picks series out, rather than only ruling some out; a filter needs one
+    pub fn selects(&self) -> bool {
+        self.equal && self.values.iter().any(|value| !value.is_empty())
+    }
+
+    pub fn matches(&self, series: &TimeSeries) -> bool {
+        let value = series.label(&self.label).unwrap_or("");
+        self.values.iter().any(|wanted| wanted == value) == self.equal
+    }
+}
@@ -0,0 +1,196 @@
+// Top-K heavy hitters by HeavyKeeper (Gong et al.): the `k` items seen most often, in bounded memory however
+// many distinct items come. Counts are kept in `depth` rows of `width` buckets, each holding one item's
+// fingerprint and count; an item bumps the bucket its hash picks in each row if that's empty or its own, and
+// otherwise wears the other item's count down, by one with probability `decay` to the power of the count, so
+// frequent items hold their buckets and rare ones lose them. An item's count is the most any of its buckets
+// says, and the `k` with the highest counts are kept with their names. Counts may be under but never over the
+// truth. Items are hashed with hasher::stable_hash, as the structure is persisted.
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use crate::hasher::stable_hash;
+use crate::record::PayloadReader;
+
+pub const DEFAULT_WIDTH: u64 = 8;
+pub const DEFAULT_DEPTH: u64 = 7;
+pub const DEFAULT_DECAY: f64 = 0.9;
+// Bounds the buckets, 768MiB, and the items kept
+const MAX_BUCKETS: u64 = 1 << 26;
+const MAX_K: u64 = 100_000;
+// Past this a bucket's count is all but certain never to decay, so increments stop trying
+const NEGLIGIBLE: f64 = 1e-12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct Bucket {
+    fingerprint: u32,
+    count: u64, // 0 for an empty bucket
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopK {
+    pub k: u64,
+    pub width: u64,
+    pub depth: u64,
+    pub decay: f64,
+    buckets: Vec<Bucket>, // Row after row
+    top: Vec<(Bytes, u64)>, // Most counted first
+    rng: u64, // Decides decays, xorshift64; persisted so a replayed structure carries on the same way
+}
+
+// Decay is never NaN, so equality is total
+impl Eq for TopK {}
+
+// The hash picking an item's buckets, and its fingerprint
+fn hash(item: &[u8]) -> (u64, u64, u32) {
+    let hash = stable_hash(item, 0);
+    (hash, stable_hash(item, hash) | 1, (hash >> 32) as u32)
+}
+
+impl TopK {
+    pub fn new(k: u64, width: u64, depth: u64, decay: f64) -> Result<TopK> {
+        if !(1..=MAX_K).contains(&k) {
+            bail!("k must be between 1 and {}", MAX_K);
+        }
+        if width == 0 || depth == 0 {
+            bail!("the width and depth must be positive");
+        }
+        if width.saturating_mul(depth) > MAX_BUCKETS {
+            bail!("{} by {} buckets would be too many", width, depth);
+        }
+        if !(decay > 0.0 && decay <= 1.0) {
+            bail!("the decay must be above 0 and at most 1");
+        }
+        let buckets = vec![Bucket::default(); (width * depth) as usize];
+        Ok(TopK { k, width, depth, decay, buckets, top: vec![], rng: 0x9e37_79b9_7f4a_7c15 })
+    }
+
+    // Uniform in [0, 1)
+    fn random(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Where an item's bucket is in each row
+    fn cells(&self, (hash, step, _): (u64, u64, u32)) -> impl Iterator<Item = usize> {
+        let width = self.width;
+        (0..self.depth).map(move |row| (row * width + hash.wrapping_add(row.wrapping_mul(step)) % width) as usize)
+    }
+
+    // Counts an item `by` more times, handing back the item it pushed out of the top k, if any
+    pub fn add(&mut self, item: &[u8], by: u64) -> Option<Bytes> {
+        let hash = hash(item);
+        let fingerprint = hash.2;
+        let mut count = 0;
+        for cell in self.cells(hash).collect::<Vec<_>>() {
+            let mut bucket = self.buckets[cell];
+            if bucket.count == 0 || bucket.fingerprint == fingerprint {
+                bucket = Bucket { fingerprint, count: bucket.count.saturating_add(by) };
+            } else {
+                // Each increment may wear the holder down by one; the one that empties the bucket takes it
+                let mut left = by;
+                while left > 0 {
+                    let chance = self.decay.powf(bucket.count as f64);
+                    if chance < NEGLIGIBLE {
+                        break;
+                    }
+                    left -= 1;
+                    if self.random() < chance {
+                        bucket.count -= 1;
+                        if bucket.count == 0 {
+                            bucket = Bucket { fingerprint, count: left + 1 };
+                            break;
+                        }
+                    }
+                }
+            }
+            self.buckets[cell] = bucket;
+            if bucket.fingerprint == fingerprint {
+                count = count.max(bucket.count);
+            }
+        }
+        self.offer(item, count)
+    }
+
+    // Puts an item with its new count in the top k if it belongs there, handing back the one it pushed out
+    fn offer(&mut self, item: &[u8], count: u64) -> Option<Bytes> {
+        let expelled = match self.top.iter().position(|(kept, _)| kept[..] == *item) {
+            Some(i) => {
+                let (kept, old) = self.top.remove(i);
+                self.top_insert(kept, old.max(count));
+                return None;
+            },
+            None if count == 0 => return None,
+            None if (self.top.len() as u64) < self.k => None,
+            None if count > self.top.last().expect("k is at least 1").1 => self.top.pop().map(|(item, _)| item),
+            None => return None,
+        };
+        self.top_insert(Bytes::copy_from_slice(item), count);
+        expelled
+    }
+
+    fn top_insert(&mut self, item: Bytes, count: u64) {
+        let at = self.top.partition_point(|&(_, kept)| kept >= count);
+        self.top.insert(at, (item, count));
+    }
+
+    // Whether an item is in the top k
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.top.iter().any(|(kept, _)| kept[..] == *item)
+    }
+
+    // How many times an item may have been seen, never more than it was
+    pub fn count(&self, item: &[u8]) -> u64 {
+        let hash = hash(item);
+        self.cells(hash).map(|cell| self.buckets[cell]).filter(|bucket| bucket.fingerprint == hash.2).map(|bucket| bucket.count).max().unwrap_or(0)
+    }
+
+    // The top items and their counts, most counted first
+    pub fn list(&self) -> &[(Bytes, u64)] {
+        &self.top
+    }
+
+    // The structure as stored in a record: k u64 | width u64 | depth u64 | decay f64 | rng u64, each bucket's
+    // fingerprint u32 | count u64, row after row, then how many top items there are u64 and each one's count u64
+    // | length u64 | bytes, most counted first, all little-endian
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(48 + 12 * self.buckets.len() + self.top.iter().map(|(item, _)| 16 + item.len()).sum::<usize>());
+        for n in [self.k, self.width, self.depth, self.decay.to_bits(), self.rng] {
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        for bucket in &self.buckets {
+            out.extend_from_slice(&bucket.fingerprint.to_le_bytes());
+            out.extend_from_slice(&bucket.count.to_le_bytes());
+        }
+        out.extend_from_slice(&(self.top.len() as u64).to_le_bytes());
+        for (item, count) in &self.top {
+            out.extend_from_slice(&count.to_le_bytes());
+            out.extend_from_slice(&(item.len() as u64).to_le_bytes());
+            out.extend_from_slice(item);
+        }
+        out
+    }
+
+    pub fn decode(payload: &Bytes) -> Option<TopK> {
+        let mut reader = PayloadReader::new(payload);
+        let (k, width, depth, decay, rng) = (reader.u64()?, reader.u64()?, reader.u64()?, f64::from_bits(reader.u64()?), reader.u64()?);
+        // Checked before the buckets are made, as a bad header could ask for any number
+        if width.saturating_mul(depth).saturating_mul(12) > payload.len() as u64 {
+            return None;
+        }
+        let mut topk = TopK::new(k, width, depth, decay).ok()?;
+        topk.rng = rng;
+        for bucket in &mut topk.buckets {
+            *bucket = Bucket { fingerprint: reader.u32()?, count: reader.u64()? };
+        }
+        let kept = reader.u64()?;
+        if kept > k {
+            return None;
+        }
+        for _ in 0..kept {
+            let count = reader.u64()?;
+            topk.top.push((reader.bytes()?, count));
+        }
+        reader.is_empty().then_some(topk)
+    }
+}
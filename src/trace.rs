@@ -0,0 +1,193 @@
+use tokio::net::TcpStream;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use anyhow::Result;
+use crate::log::log_warn;
+
+const EXPORT_BATCH_SIZE: usize = 512;
+const EXPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+static EXPORTER: OnceLock<UnboundedSender<SpanData>> = OnceLock::new();
+
+// A finished phase of a command, exported as a child span of the command span
+#[derive(Debug)]
+struct Phase {
+    name: &'static str,
+    start: Duration, // Offset from the command span start
+    duration: Duration,
+}
+
+#[derive(Debug)]
+pub struct SpanData {
+    name: Cow<'static, str>,
+    peer: String,
+    start_unix: Duration,
+    duration: Duration,
+    phases: Vec<Phase>,
+    error: bool,
+}
+
+// Times one command through its phases (parse, lock_wait, execute, write).
+// Spans are only shipped anywhere when an OTLP endpoint is configured.
+pub struct CommandSpan {
+    name: Cow<'static, str>, // Borrowed for known commands
+    peer: String,
+    start_unix: Duration,
+    start: Instant,
+    phases: Vec<Phase>,
+    current: Option<(&'static str, Instant)>,
+    error: bool,
+}
+
+impl CommandSpan {
+    // The span is backdated by `parse_time` so that the parse phase, measured by the reader, comes first
+    pub fn start(peer: &str, parse_time: Duration) -> Self {
+        let now_unix = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        CommandSpan {
+            name: Cow::Borrowed(""),
+            peer: peer.to_string(),
+            start_unix: now_unix.saturating_sub(parse_time),
+            start: Instant::now() - parse_time,
+            phases: vec![Phase { name: "parse", start: Duration::ZERO, duration: parse_time }],
+            current: None,
+            error: false,
+        }
+    }
+
+    pub fn set_name(&mut self, name: impl Into<Cow<'static, str>>) {
+        self.name = name.into();
+    }
+
+    pub fn set_error(&mut self, error: bool) {
+        self.error = error;
+    }
+
+    // Closes the running phase (if any) and starts timing the next one
+    pub fn phase(&mut self, name: &'static str) {
+        self.close_phase();
+        self.current = Some((name, Instant::now()));
+    }
+
+    fn close_phase(&mut self) {
+        if let Some((name, started)) = self.current.take() {
+            self.phases.push(Phase { name, start: started - self.start, duration: started.elapsed() });
+        }
+    }
+
+    pub fn finish(mut self) {
+        self.close_phase();
+        if let Some(exporter) = EXPORTER.get() {
+            let _ = exporter.send(SpanData {
+                name: self.name,
+                peer: self.peer,
+                start_unix: self.start_unix,
+                duration: self.start.elapsed(),
+                phases: self.phases,
+                error: self.error,
+            });
+        }
+    }
+}
+
+// Starts the background exporter shipping spans as OTLP/HTTP JSON to `endpoint` (host:port)
+pub fn init_otlp(endpoint: &str) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    if EXPORTER.set(tx).is_ok() {
+        let endpoint = endpoint.trim_start_matches("http://").trim_end_matches('/').to_string();
+        tokio::spawn(export_loop(endpoint, rx));
+    }
+}
+
+async fn export_loop(endpoint: String, mut rx: UnboundedReceiver<SpanData>) {
+    let mut batch = Vec::with_capacity(EXPORT_BATCH_SIZE);
+    let mut ticker = tokio::time::interval(EXPORT_INTERVAL);
+    loop {
+        tokio::select! {
+            span = rx.recv() => match span {
+                Some(span) => {
+                    batch.push(span);
+                    if batch.len() < EXPORT_BATCH_SIZE {
+                        continue;
+                    }
+                },
+                None => break,
+            },
+            _ = ticker.tick() => {},
+        }
+        if batch.is_empty() {
+            continue;
+        }
+        let body = encode_otlp(&batch);
+        batch.clear();
+        if let Err(e) = post(&endpoint, &body).await {
+            log_warn!("Failed to export spans to {}: {:?}", endpoint, e);
+        }
+    }
+}
+
+async fn post(endpoint: &str, body: &str) -> Result<()> {
+    let mut stream = TcpStream::connect(endpoint).await?;
+    let request = format!(
+        "POST /v1/traces HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        endpoint, body.len(), body
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = vec![0u8; 256];
+    let n = stream.read(&mut response).await?;
+    let status_line = String::from_utf8_lossy(&response[..n]);
+    if !status_line.starts_with("HTTP/1.1 2") && !status_line.starts_with("HTTP/1.0 2") {
+        return Err(anyhow::anyhow!("Collector replied {:?}", status_line.lines().next().unwrap_or("")));
+    }
+    Ok(())
+}
+
+fn encode_otlp(spans: &[SpanData]) -> String {
+    let mut out = String::from(
+        r#"{"resourceSpans":[{"resource":{"attributes":[{"key":"service.name","value":{"stringValue":"zenql"}}]},"scopeSpans":[{"scope":{"name":"zenql"},"spans":["#,
+    );
+    let mut first = true;
+    for span in spans {
+        let trace_id = format!("{:016x}{:016x}", next_id(), next_id());
+        let span_id = format!("{:016x}", next_id());
+        let start = span.start_unix.as_nanos();
+        let status = if span.error { 2 } else { 1 };
+        if !first {
+            out.push(',');
+        }
+        first = false;
+        let _ = write!(
+            out,
+            r#"{{"traceId":"{}","spanId":"{}","name":"{}","kind":2,"startTimeUnixNano":"{}","endTimeUnixNano":"{}","status":{{"code":{}}},"attributes":[{{"key":"db.system","value":{{"stringValue":"zenql"}}}},{{"key":"net.peer.name","value":{{"stringValue":"{}"}}}}]}}"#,
+            trace_id, span_id, escape(&span.name), start, start + span.duration.as_nanos(), status, escape(&span.peer)
+        );
+        for phase in &span.phases {
+            let phase_start = start + phase.start.as_nanos();
+            let _ = write!(
+                out,
+                r#",{{"traceId":"{}","spanId":"{:016x}","parentSpanId":"{}","name":"{}","kind":1,"startTimeUnixNano":"{}","endTimeUnixNano":"{}"}}"#,
+                trace_id, next_id(), span_id, phase.name, phase_start, phase_start + phase.duration.as_nanos()
+            );
+        }
+    }
+    out.push_str("]}]}]}");
+    out
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// splitmix64 over a global counter seeded from the clock; ids only need to be unique, not secret
+fn next_id() -> u64 {
+    static STATE: AtomicU64 = AtomicU64::new(0);
+    let _ = STATE.compare_exchange(0, SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64, Ordering::Relaxed, Ordering::Relaxed);
+    let mut z = STATE.fetch_add(0x9E3779B97F4A7C15, Ordering::Relaxed).wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
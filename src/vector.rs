@@ -0,0 +1,361 @@
+// Vector fields of search indexes, see search.rs, and the nearest-neighbour indexes behind KNN queries. A
+// vector is a hash field of `dim` little-endian FLOAT32s, or a JSON array of `dim` numbers. FLAT compares the
+// query with every vector, exact but linear; HNSW searches a hierarchical navigable small world graph (Malkov
+// and Yashunin), approximate but logarithmic. Deleted vectors
+// stay in the graph to route searches through, unreturned, until they outnumber the live ones and the graph
+// is rebuilt.
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use crate::json::Json;
+
+const DEFAULT_M: usize = 16;
+const DEFAULT_EF_CONSTRUCTION: usize = 200;
+const DEFAULT_EF_RUNTIME: usize = 10;
+// Bounds the vectors' size, and the graph's parameters, against typos
+const MAX_DIM: usize = 32_768;
+const MAX_M: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    L2, // Squared Euclidean distance
+    Cosine, // 1 minus the cosine similarity
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Flat,
+    Hnsw { m: usize, ef_construction: usize, ef_runtime: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VectorParams {
+    pub dim: usize,
+    pub metric: Metric,
+    pub algorithm: Algorithm,
+}
+
+impl VectorParams {
+    // From FT.CREATE's `VECTOR algorithm nargs name value ...`, given the algorithm and the pairs
+    pub fn parse(algorithm: &str, attributes: &[(String, String)]) -> Result<VectorParams> {
+        let (mut dim, mut metric) = (None, None);
+        let (mut m, mut ef_construction, mut ef_runtime) = (DEFAULT_M, DEFAULT_EF_CONSTRUCTION, DEFAULT_EF_RUNTIME);
+        let number = |name: &str, value: &str, max: usize| match value.parse::<usize>() {
+            Ok(n) if (1..=max).contains(&n) => Ok(n),
+            _ => bail!("{} must be between 1 and {}", name, max),
+        };
+        for (name, value) in attributes {
+            match name.to_uppercase().as_str() {
+                "TYPE" if value.eq_ignore_ascii_case("float32") => {},
+                "TYPE" => bail!("only FLOAT32 vectors are supported"),
+                "DIM" => dim = Some(number(name, value, MAX_DIM)?),
+                "DISTANCE_METRIC" => {
+                    metric = Some(match value.to_uppercase().as_str() {
+                        "L2" => Metric::L2,
+                        "COSINE" => Metric::Cosine,
+                        _ => bail!("unknown distance metric '{}', expected L2 or COSINE", value),
+                    })
+                },
+                "M" => m = number(name, value, MAX_M)?.max(2),
+                "EF_CONSTRUCTION" => ef_construction = number(name, value, usize::MAX)?,
+                "EF_RUNTIME" => ef_runtime = number(name, value, usize::MAX)?,
+                "INITIAL_CAP" | "BLOCK_SIZE" => {},
+                _ => bail!("unknown vector attribute '{}'", name),
+            }
+        }
+        let (Some(dim), Some(metric)) = (dim, metric) else {
+            bail!("vector fields need DIM and DISTANCE_METRIC");
+        };
+        let algorithm = match algorithm.to_uppercase().as_str() {
+            "FLAT" => Algorithm::Flat,
+            "HNSW" => Algorithm::Hnsw { m, ef_construction, ef_runtime },
+            _ => bail!("unknown vector algorithm '{}', expected FLAT or HNSW", algorithm),
+        };
+        Ok(VectorParams { dim, metric, algorithm })
+    }
+
+    // The vector in a hash field
+    pub fn from_blob(&self, blob: &[u8]) -> Option<Vec<f32>> {
+        if blob.len() != self.dim * 4 {
+            return None;
+        }
+        let vector: Vec<f32> = blob.chunks_exact(4).map(|n| f32::from_le_bytes(n.try_into().expect("chunks of 4"))).collect();
+        vector.iter().all(|n| n.is_finite()).then_some(vector)
+    }
+
+    // The vector in a JSON document
+    pub fn from_json(&self, value: &Json) -> Option<Vec<f32>> {
+        let Json::Array(items) = value else {
+            return None;
+        };
+        if items.len() != self.dim {
+            return None;
+        }
+        items.iter().map(|item| item.as_f64().map(|n| n as f32).filter(|n| n.is_finite())).collect()
+    }
+
+    pub fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self.metric {
+            Metric::L2 => a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum(),
+            Metric::Cosine => {
+                let (mut dot, mut norm_a, mut norm_b) = (0.0, 0.0, 0.0);
+                for (a, b) in a.iter().zip(b) {
+                    dot += a * b;
+                    norm_a += a * a;
+                    norm_b += b * b;
+                }
+                if norm_a == 0.0 || norm_b == 0.0 {
+                    return 1.0;
+                }
+                1.0 - dot / (norm_a.sqrt() * norm_b.sqrt())
+            },
+        }
+    }
+}
+
+// One shard's vectors of one field
+#[derive(Debug)]
+pub enum VectorIndex {
+    Flat(VectorParams, HashMap<Bytes, Vec<f32>>),
+    Hnsw(Hnsw),
+}
+
+impl VectorIndex {
+    pub fn new(params: VectorParams) -> VectorIndex {
+        match params.algorithm {
+            Algorithm::Flat => VectorIndex::Flat(params, HashMap::new()),
+            Algorithm::Hnsw { .. } => VectorIndex::Hnsw(Hnsw::new(params)),
+        }
+    }
+
+    pub fn params(&self) -> &VectorParams {
+        match self {
+            VectorIndex::Flat(params, _) => params,
+            VectorIndex::Hnsw(hnsw) => &hnsw.params,
+        }
+    }
+
+    pub fn insert(&mut self, key: &Bytes, vector: Vec<f32>) {
+        match self {
+            VectorIndex::Flat(_, vectors) => {
+                vectors.insert(key.clone(), vector);
+            },
+            VectorIndex::Hnsw(hnsw) => hnsw.insert(key, vector),
+        }
+    }
+
+    pub fn remove(&mut self, key: &Bytes) {
+        match self {
+            VectorIndex::Flat(_, vectors) => {
+                vectors.remove(key);
+            },
+            VectorIndex::Hnsw(hnsw) => hnsw.remove(key),
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[f32]> {
+        match self {
+            VectorIndex::Flat(_, vectors) => vectors.get(key).map(Vec::as_slice),
+            VectorIndex::Hnsw(hnsw) => hnsw.ids.get(key).map(|&id| hnsw.nodes[id as usize].vector.as_slice()),
+        }
+    }
+
+    // The `k` vectors nearest `query` and their distances, nearest first. `ef` overrides HNSW's EF_RUNTIME.
+    pub fn nearest(&self, query: &[f32], k: usize, ef: Option<usize>) -> Vec<(f32, &Bytes)> {
+        match self {
+            VectorIndex::Flat(params, vectors) => {
+                let found = vectors.iter().map(|(key, vector)| (params.distance(query, vector), key));
+                nearest(found, k)
+            },
+            VectorIndex::Hnsw(hnsw) => hnsw.search(query, k, ef),
+        }
+    }
+}
+
+// The `k` nearest of some (distance, key) pairs, nearest first, ties in key order
+pub fn nearest<'a>(found: impl Iterator<Item = (f32, &'a Bytes)>, k: usize) -> Vec<(f32, &'a Bytes)> {
+    let mut heap = BinaryHeap::new();
+    for (distance, key) in found {
+        heap.push(Scored(distance, key));
+        if heap.len() > k {
+            heap.pop();
+        }
+    }
+    let mut nearest: Vec<_> = heap.into_iter().map(|Scored(distance, key)| (distance, key)).collect();
+    nearest.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(b.1)));
+    nearest
+}
+
+// Orders by distance, then by what's attached
+#[derive(Debug, Clone, Copy)]
+struct Scored<T>(f32, T);
+
+impl<T: Ord> PartialEq for Scored<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T: Ord> Eq for Scored<T> {}
+
+impl<T: Ord> PartialOrd for Scored<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: Ord> Ord for Scored<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then_with(|| self.1.cmp(&other.1))
+    }
+}
+
+#[derive(Debug)]
+struct Node {
+    key: Bytes,
+    vector: Vec<f32>,
+    layers: Vec<Vec<u32>>, // Neighbours on each layer the node is on, the bottom one first
+    deleted: bool,
+}
+
+#[derive(Debug)]
+pub struct Hnsw {
+    params: VectorParams,
+    nodes: Vec<Node>,
+    ids: HashMap<Bytes, u32>, // Live nodes by key
+    entry: Option<u32>, // A node on the top layer
+    deleted: usize,
+    rng: u64, // Picks nodes' layers; seeded the same every time so indexes build the same way
+}
+
+impl Hnsw {
+    fn new(params: VectorParams) -> Hnsw {
+        Hnsw { params, nodes: vec![], ids: HashMap::new(), entry: None, deleted: 0, rng: 0x9e3779b97f4a7c15 }
+    }
+
+    fn settings(&self) -> (usize, usize, usize) {
+        match self.params.algorithm {
+            Algorithm::Hnsw { m, ef_construction, ef_runtime } => (m, ef_construction, ef_runtime),
+            Algorithm::Flat => (DEFAULT_M, DEFAULT_EF_CONSTRUCTION, DEFAULT_EF_RUNTIME),
+        }
+    }
+
+    // A layer drawn from the exponential distribution the paper gives, 1/ln(M) its mean
+    fn random_layer(&mut self) -> usize {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        let uniform = (self.rng >> 11) as f64 / (1u64 << 53) as f64;
+        let (m, _, _) = self.settings();
+        ((-(1.0 - uniform).ln()) / (m as f64).ln()).floor().min(32.0) as usize
+    }
+
+    fn distance_to(&self, query: &[f32], id: u32) -> f32 {
+        self.params.distance(query, &self.nodes[id as usize].vector)
+    }
+
+    fn insert(&mut self, key: &Bytes, vector: Vec<f32>) {
+        self.remove(key);
+        let (m, ef_construction, _) = self.settings();
+        let layer = self.random_layer();
+        let id = self.nodes.len() as u32;
+        self.nodes.push(Node { key: key.clone(), vector, layers: vec![vec![]; layer + 1], deleted: false });
+        self.ids.insert(key.clone(), id);
+        let Some(mut entry) = self.entry else {
+            self.entry = Some(id);
+            return;
+        };
+        let query = self.nodes[id as usize].vector.clone();
+        let top = self.nodes[entry as usize].layers.len() - 1;
+        for level in (layer + 1..=top).rev() {
+            entry = self.search_layer(&query, entry, 1, level)[0].1;
+        }
+        for level in (0..=layer.min(top)).rev() {
+            let candidates = self.search_layer(&query, entry, ef_construction, level);
+            entry = candidates[0].1;
+            let most = if level == 0 { 2 * m } else { m };
+            let neighbours: Vec<u32> = candidates.iter().map(|Scored(_, id)| *id).take(most).collect();
+            for &neighbour in &neighbours {
+                let links = &mut self.nodes[neighbour as usize].layers[level];
+                links.push(id);
+                if links.len() > most {
+                    self.prune(neighbour, level, most);
+                }
+            }
+            self.nodes[id as usize].layers[level] = neighbours;
+        }
+        if layer > top {
+            self.entry = Some(id);
+        }
+    }
+
+    // Keeps the `most` links of a node nearest to it
+    fn prune(&mut self, id: u32, level: usize, most: usize) {
+        let node = &self.nodes[id as usize];
+        let mut links: Vec<_> = node.layers[level].iter().map(|&link| Scored(self.distance_to(&node.vector, link), link)).collect();
+        links.sort();
+        links.truncate(most);
+        self.nodes[id as usize].layers[level] = links.into_iter().map(|Scored(_, link)| link).collect();
+    }
+
+    fn remove(&mut self, key: &Bytes) {
+        let Some(id) = self.ids.remove(key) else {
+            return;
+        };
+        self.nodes[id as usize].deleted = true;
+        self.deleted += 1;
+        if self.deleted > self.ids.len() {
+            self.rebuild();
+        }
+    }
+
+    fn rebuild(&mut self) {
+        let live: Vec<Node> = std::mem::take(&mut self.nodes).into_iter().filter(|node| !node.deleted).collect();
+        (self.ids, self.entry, self.deleted) = (HashMap::new(), None, 0);
+        for node in live {
+            self.insert(&node.key, node.vector);
+        }
+    }
+
+    // The `ef` nodes nearest `query` the greedy search from `entry` finds on `level`, nearest first
+    fn search_layer(&self, query: &[f32], entry: u32, ef: usize, level: usize) -> Vec<Scored<u32>> {
+        let mut visited = HashSet::from([entry]);
+        let start = Scored(self.distance_to(query, entry), entry);
+        let mut candidates = BinaryHeap::from([Reverse(start)]);
+        let mut found = BinaryHeap::from([start]);
+        while let Some(Reverse(Scored(distance, id))) = candidates.pop() {
+            if found.len() >= ef && found.peek().is_some_and(|furthest| distance > furthest.0) {
+                break;
+            }
+            for &neighbour in self.nodes[id as usize].layers.get(level).into_iter().flatten() {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+                let next = Scored(self.distance_to(query, neighbour), neighbour);
+                if found.len() < ef || found.peek().is_some_and(|furthest| next.0 < furthest.0) {
+                    candidates.push(Reverse(next));
+                    found.push(next);
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+        found.into_sorted_vec()
+    }
+
+    fn search(&self, query: &[f32], k: usize, ef: Option<usize>) -> Vec<(f32, &Bytes)> {
+        let Some(mut entry) = self.entry else {
+            return vec![];
+        };
+        let (_, _, ef_runtime) = self.settings();
+        for level in (1..self.nodes[entry as usize].layers.len()).rev() {
+            entry = self.search_layer(query, entry, 1, level)[0].1;
+        }
+        // Deleted nodes are found like any other, so the search looks further to make up for them
+        let ef = ef.unwrap_or(ef_runtime).max(k) + self.deleted.min(k);
+        let found = self.search_layer(query, entry, ef, 0).into_iter().filter(|Scored(_, id)| !self.nodes[*id as usize].deleted);
+        nearest(found.map(|Scored(distance, id)| (distance, &self.nodes[id as usize].key)), k)
+    }
+}
@@ -0,0 +1,343 @@
+// The write-ahead log: with `wal yes` every change to the keyspace is also appended to a log in the data
+// directory, and a client's replies are only sent once the log is fsynced past its writes, so acknowledged
+// writes survive the machine going down, not just the process. Values stay in memory as usual.
+//
+// Changes are collected in memory as commands run, with the key's shard locked so they're logged in the order
+// they happened. Connections call Storage::sync before sending replies: whoever gets there first writes and
+// fsyncs everyone's pending changes in one go, the rest find theirs durable already, so concurrent clients
+// share fsyncs. Reads wait too, they may have seen a write that isn't durable yet. Should a write or fsync
+// fail, the log can't be trusted to hold what was acknowledged before: every later sync fails as well and
+// clients are disconnected rather than answered, until a restart.
+//
+// The log is a series of segment files, wal/wal-00000001.log and up, each started once the previous one
+// reaches wal-segment-size, replayed in order on startup. Only the last may end in a partial record, which is
+// cut off. Once the segments add up to twice the last checkpoint (and past CHECKPOINT_MIN_SIZE) the whole
+// keyspace is written to a checkpoint segment and the older ones deleted. Records are in the format of
+// record.rs.
+//
+// A checkpoint is written from a point-in-time view of the keyspace (see Storage::read_view), so writes carry
+// on meanwhile. At that point in time the log moves on to a new segment, leaving the number before it for
+// the checkpoint: older segments replay to the keyspace the checkpoint holds, newer ones hold what changed
+// since. The checkpoint is written to a temporary file and renamed into place once it's durable, so until
+// then the log replays as if it was never started.
+//
+// With encryption on each segment is encrypted with its own nonce, see crypt.rs. When any segment was written
+// with a key other than the current one, the log moves on to a new segment on startup and is checkpointed
+// straight away, leaving nothing written with the old key.
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use bytes::Bytes;
+use crate::backend::{Rewrite, StorageBackend};
+use crate::crypt::{self, Cipher, Keyring};
+use crate::locks;
+use crate::log::{log_error, log_warn};
+use crate::record::{self, FileKind, Record, Values};
+use crate::storage::StoredValue;
+
+// In the data directory
+pub const WAL_DIR: &str = "wal";
+
+const CHECKPOINT_MIN_SIZE: u64 = 64 * 1024 * 1024;
+
+pub struct Wal {
+    dir: PathBuf,
+    segment_size: u64,
+    keys: Arc<Keyring>,
+    pending: Mutex<Vec<u8>>, // Records not written to the log yet
+    appended: AtomicU64, // Bytes of records ever added to pending
+    synced: AtomicU64, // How many of those are durable
+    log: Mutex<Log>,
+}
+
+struct Log {
+    file: File,
+    segment: u64, // Number of the segment being appended to
+    first: u64, // Number of the oldest segment
+    size: u64, // Of the current segment
+    total: u64, // Of every segment
+    checkpoint_size: u64, // Size of the last checkpoint, or of the log at startup
+    cipher: Option<Cipher>, // The current segment is encrypted with
+    stale: bool, // Segments not encrypted with the current key are left, to be checkpointed
+    failed: bool,
+}
+
+impl Wal {
+    // Opens the log in `dir`, creating it if needed, and passes every record in it to `apply` in order
+    pub fn open(dir: &Path, segment_size: u64, keys: Arc<Keyring>, mut apply: impl FnMut(Record)) -> io::Result<Wal> {
+        fs::create_dir_all(dir)?;
+        // What a crash left of a checkpoint being written
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "tmp") {
+                fs::remove_file(&path)?;
+            }
+        }
+        let segments = segments(dir)?;
+        let mut total = 0;
+        let mut stale = false;
+        let mut cipher = None;
+        for (index, &number) in segments.iter().enumerate() {
+            let path = segment_path(dir, number);
+            let file = OpenOptions::new().read(true).write(true).open(&path)?;
+            let replayed = record::replay(&file, &path, FileKind::Wal, Values::InMemory, index == segments.len() - 1, &keys, &mut apply)?;
+            total += replayed.end;
+            stale |= replayed.cipher.as_ref().map(Cipher::id) != keys.current_id() || (replayed.cut && replayed.cipher.is_some());
+            cipher = replayed.cipher;
+        }
+        let first = segments.first().copied().unwrap_or(1);
+        // Nothing more is appended to a segment written with another key
+        let (file, segment) = match segments.last() {
+            Some(&last) if !stale => (OpenOptions::new().append(true).open(segment_path(dir, last))?, last),
+            last => {
+                let number = last.map_or(1, |last| last + 1);
+                cipher = keys.cipher()?;
+                let file = create_segment(dir, number, cipher.as_ref())?;
+                total += file.metadata()?.len();
+                (file, number)
+            },
+        };
+        let size = file.metadata()?.len();
+        let log = Log { file, segment, first, size, total, checkpoint_size: total, cipher, stale, failed: false };
+        Ok(Wal {
+            dir: dir.to_path_buf(),
+            segment_size,
+            keys,
+            pending: Mutex::new(vec![]),
+            appended: AtomicU64::new(0),
+            synced: AtomicU64::new(0),
+            log: Mutex::new(log),
+        })
+    }
+
+    fn append(&self, record: &[u8]) {
+        let mut pending = locks::lock(&self.pending);
+        pending.extend_from_slice(record);
+        self.appended.fetch_add(record.len() as u64, Ordering::Relaxed);
+    }
+
+    // Writes and fsyncs the pending records, with the log locked. Returns how many bytes were appended up to
+    // then, all of them durable now.
+    fn write_pending(&self, log: &mut Log) -> io::Result<u64> {
+        let (records, appended) = {
+            let mut pending = locks::lock(&self.pending);
+            (std::mem::take(&mut *pending), self.appended.load(Ordering::Relaxed))
+        };
+        if records.is_empty() {
+            return Ok(appended);
+        }
+        log.file.write_all(&crypt::encrypt(log.cipher.as_ref(), log.size, &records))?;
+        log.file.sync_data()?;
+        log.size += records.len() as u64;
+        log.total += records.len() as u64;
+        if log.size >= self.segment_size {
+            self.start_segment(log, log.segment + 1)?;
+        }
+        Ok(appended)
+    }
+
+    fn start_segment(&self, log: &mut Log, number: u64) -> io::Result<()> {
+        let cipher = self.keys.cipher()?;
+        log.file = create_segment(&self.dir, number, cipher.as_ref())?;
+        let size = log.file.metadata()?.len();
+        log.segment = number;
+        log.cipher = cipher;
+        log.size = size;
+        log.total += size;
+        Ok(())
+    }
+
+    // Marks the log unusable after a failed write, logging why the first time
+    fn fail(&self, log: &mut Log, e: io::Error) -> io::Error {
+        if !log.failed {
+            log_error!("Writing the write-ahead log in {} failed, writes can't be acknowledged until a restart: {}", self.dir.display(), e);
+            log.failed = true;
+        }
+        e
+    }
+}
+
+impl StorageBackend for Wal {
+    fn write(&self, key: &Bytes, value: StoredValue, expires_at: Option<u64>) -> StoredValue {
+        if let Some(record) = record::set(key, &value, expires_at) {
+            self.append(&record);
+        }
+        value
+    }
+
+    fn set_expiry(&self, key: &Bytes, expires_at: Option<u64>) {
+        self.append(&record::expire(key, expires_at));
+    }
+
+    fn remove(&self, key: &Bytes) {
+        self.append(&record::del(key));
+    }
+
+    fn read(&self, value: &StoredValue) -> io::Result<Bytes> {
+        Ok(value.to_bytes())
+    }
+
+    fn needs_sync(&self) -> bool {
+        self.synced.load(Ordering::Acquire) < self.appended.load(Ordering::Relaxed)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        let mut log = locks::lock(&self.log);
+        if log.failed {
+            return Err(io::Error::other("the write-ahead log failed earlier"));
+        }
+        match self.write_pending(&mut log) {
+            Ok(appended) => {
+                self.synced.store(appended, Ordering::Release);
+                Ok(())
+            },
+            Err(e) => Err(self.fail(&mut log, e)),
+        }
+    }
+
+    fn needs_compaction(&self) -> bool {
+        let log = locks::lock(&self.log);
+        !log.failed && (log.stale || (log.total >= CHECKPOINT_MIN_SIZE && log.total >= log.checkpoint_size * 2))
+    }
+
+    fn rewrites_from_view(&self) -> bool {
+        true
+    }
+
+    // The pending records go to the current segment first, so the segments before the checkpoint add up to
+    // the keyspace it's going to hold
+    fn start_rewrite(&self) -> io::Result<Box<dyn Rewrite + '_>> {
+        let mut log = locks::lock(&self.log);
+        if log.failed {
+            return Err(io::Error::other("the write-ahead log failed earlier"));
+        }
+        let number = (|| {
+            let appended = self.write_pending(&mut log)?;
+            self.synced.store(appended, Ordering::Release);
+            let number = log.segment + 1;
+            self.start_segment(&mut log, number + 1)?;
+            Ok(number)
+        })();
+        let number = number.map_err(|e| self.fail(&mut log, e))?;
+        drop(log);
+
+        let mut temp = segment_path(&self.dir, number).into_os_string();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let cipher = self.keys.cipher()?;
+        let header = record::file_header(FileKind::Wal, cipher.as_ref());
+        let out = crypt::Writer::new(BufWriter::new(File::create(&temp)?), cipher, header.len() as u64);
+        let mut checkpoint = Checkpoint { wal: self, number, temp, out: Some(out) };
+        if let Some(out) = &mut checkpoint.out {
+            out.get_mut().write_all(&header)?;
+        }
+        Ok(Box::new(checkpoint))
+    }
+}
+
+// A checkpoint being written, see start_rewrite
+struct Checkpoint<'a> {
+    wal: &'a Wal,
+    number: u64, // Of the segment it becomes
+    temp: PathBuf,
+    out: Option<crypt::Writer<BufWriter<File>>>, // Taken by finish
+}
+
+impl Rewrite for Checkpoint<'_> {
+    fn write(&mut self, key: &Bytes, value: &StoredValue, expires_at: Option<u64>) -> io::Result<()> {
+        match (&mut self.out, record::set(key, value, expires_at)) {
+            (Some(out), Some(record)) => out.write_all(&record),
+            _ => Ok(()),
+        }
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        let Some(mut out) = self.out.take() else {
+            return Ok(());
+        };
+        let path = segment_path(&self.wal.dir, self.number);
+        let written: io::Result<u64> = (|| {
+            out.flush()?;
+            out.get_ref().get_ref().sync_data()?;
+            fs::rename(&self.temp, &path)?;
+            sync_dir(&self.wal.dir)?;
+            out.get_ref().get_ref().metadata().map(|metadata| metadata.len())
+        })();
+        let size = match written {
+            Ok(size) => size,
+            Err(e) => {
+                let _ = fs::remove_file(&self.temp);
+                return Err(e);
+            },
+        };
+
+        // Oldest first: what's left after a crash or a failed delete is the tail of the log, which still
+        // replays correctly before the checkpoint. The next checkpoint has another go at the rest.
+        let mut log = locks::lock(&self.wal.log);
+        log.total += size;
+        log.checkpoint_size = size;
+        log.stale = false;
+        while log.first < self.number {
+            let path = segment_path(&self.wal.dir, log.first);
+            let len = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+            match fs::remove_file(&path) {
+                Ok(()) => log.first += 1,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => log.first += 1,
+                Err(e) => {
+                    log_warn!("Can't delete {} after a checkpoint: {}", path.display(), e);
+                    break;
+                },
+            }
+            log.total -= len.min(log.total);
+        }
+        Ok(())
+    }
+}
+
+// A checkpoint given up on leaves no trace
+impl Drop for Checkpoint<'_> {
+    fn drop(&mut self) {
+        if self.out.take().is_some() {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+// Whatever a client was told, pending records still go to the log on a clean shutdown
+impl Drop for Wal {
+    fn drop(&mut self) {
+        let _ = self.sync();
+    }
+}
+
+fn segment_path(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("wal-{:08}.log", number))
+}
+
+// Numbers of the segments in `dir`, oldest first
+fn segments(dir: &Path) -> io::Result<Vec<u64>> {
+    let mut numbers = vec![];
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let number: Option<u64> = name.to_str().and_then(|name| name.strip_prefix("wal-")?.strip_suffix(".log")?.parse().ok());
+        numbers.extend(number);
+    }
+    numbers.sort_unstable();
+    Ok(numbers)
+}
+
+// A new segment holding only the file header, its directory entry durable. The header itself is fsynced
+// along with the first records; a crash before leaves a partial header, which is rewritten on startup.
+fn create_segment(dir: &Path, number: u64, cipher: Option<&Cipher>) -> io::Result<File> {
+    let mut file = OpenOptions::new().append(true).create_new(true).open(segment_path(dir, number))?;
+    file.write_all(&record::file_header(FileKind::Wal, cipher))?;
+    sync_dir(dir)?;
+    Ok(file)
+}
+
+// Makes a file created in `dir` durable
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
@@ -0,0 +1,157 @@
+// Webhooks: keyspace events (see events.rs) POSTed as JSON to HTTP endpoints, so other systems hear about
+// changes without polling. Each target in `webhooks` is a URL, optionally followed by filters:
+//
+//   http://host:port/path;events=set,del;match=user:*
+//
+// events lists the kinds it wants, all by default, and match is a glob its keys must match. Only plain http
+// is spoken; put a TLS-terminating proxy in front of endpoints that need https.
+//
+// Every target has a queue of its own, so a slow one doesn't hold up the rest. Its events go out in order,
+// in batches of up to WEBHOOK_BATCH as `{"events":[{"event":"set","key":"user:1","time":1700000000000}]}`.
+// A batch the endpoint doesn't answer with a 2xx status is retried with exponential backoff, and given up
+// on after WEBHOOK_ATTEMPTS tries. Events for a target whose queue is full are dropped; the stats count
+// both, see INFO and the metrics endpoint.
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::Result;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, Receiver, UnboundedReceiver};
+use crate::events::{EventFilter, KeyEvent};
+use crate::json::Json;
+use crate::log::log_warn;
+use crate::stats::{self, SERVER_STATS};
+
+// Most events sent in one request
+const WEBHOOK_BATCH: usize = 100;
+// Events waiting for a target before more are dropped
+const WEBHOOK_QUEUE: usize = 10_000;
+// Tries at delivering a batch
+const WEBHOOK_ATTEMPTS: u32 = 5;
+// Wait before the first retry, doubling after every one up to MAX_BACKOFF
+const INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+// Longest a delivery may take, connecting included
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    spec: String, // As configured
+    authority: String, // host:port
+    path: String,
+    filter: EventFilter,
+}
+
+impl Webhook {
+    pub fn parse(spec: &str) -> Result<Webhook> {
+        let mut parts = spec.split(';');
+        let url = parts.next().unwrap_or_default();
+        let rest = match url.split_once("://") {
+            Some(("http", rest)) => rest,
+            Some(("https", _)) => return Err(anyhow::anyhow!("Webhook '{}' is https, only http is supported", url)),
+            _ => return Err(anyhow::anyhow!("Invalid webhook URL '{}', expected http://host[:port][/path]", url)),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err(anyhow::anyhow!("Webhook URL '{}' has no host", url));
+        }
+        // A port is whatever follows the last colon, unless that's inside an IPv6 address's brackets
+        let authority = match authority.rsplit_once(':') {
+            Some((_, port)) if !port.contains(']') => authority.to_string(),
+            _ => format!("{}:80", authority),
+        };
+        let mut filter = EventFilter::default();
+        for option in parts {
+            let recognized = match option.split_once('=') {
+                Some((name, value)) => filter.set(name, value)?,
+                None => false,
+            };
+            if !recognized {
+                return Err(anyhow::anyhow!("Invalid webhook filter '{}', expected events=<kinds> or match=<pattern>", option));
+            }
+        }
+        Ok(Webhook { spec: spec.to_string(), authority, path: path.to_string(), filter })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.spec
+    }
+}
+
+// Hands the events to the targets that want them, until the storage goes away
+pub async fn run(mut events: UnboundedReceiver<KeyEvent>, webhooks: Vec<Webhook>) {
+    let targets: Vec<_> = webhooks.into_iter().map(|webhook| {
+        let webhook = Arc::new(webhook);
+        let (tx, rx) = mpsc::channel(WEBHOOK_QUEUE);
+        tokio::spawn(deliver(Arc::clone(&webhook), rx));
+        (webhook, tx)
+    }).collect();
+    while let Some(event) = events.recv().await {
+        for (webhook, queue) in &targets {
+            if webhook.filter.wants(&event) && queue.try_send(event.clone()).is_err() {
+                stats::incr(&SERVER_STATS.webhook_events_dropped, 1);
+            }
+        }
+    }
+}
+
+async fn deliver(webhook: Arc<Webhook>, mut queue: Receiver<KeyEvent>) {
+    let mut batch = Vec::with_capacity(WEBHOOK_BATCH);
+    while let Some(event) = queue.recv().await {
+        batch.push(event);
+        while batch.len() < WEBHOOK_BATCH {
+            match queue.try_recv() {
+                Ok(event) => batch.push(event),
+                Err(_) => break,
+            }
+        }
+        let body = payload(&batch);
+        let mut backoff = INITIAL_BACKOFF;
+        for attempt in 1..=WEBHOOK_ATTEMPTS {
+            let result = match tokio::time::timeout(DELIVERY_TIMEOUT, post(&webhook, &body)).await {
+                Ok(result) => result,
+                Err(_) => Err(anyhow::anyhow!("Timed out")),
+            };
+            match result {
+                Ok(()) => {
+                    stats::incr(&SERVER_STATS.webhook_events_delivered, batch.len() as u64);
+                    break;
+                },
+                Err(e) if attempt == WEBHOOK_ATTEMPTS => {
+                    log_warn!("Gave up delivering {} keyspace events to webhook {}: {:?}", batch.len(), webhook.spec, e);
+                    stats::incr(&SERVER_STATS.webhook_events_failed, batch.len() as u64);
+                },
+                Err(_) => {
+                    stats::incr(&SERVER_STATS.webhook_retries, 1);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                },
+            }
+        }
+        batch.clear();
+    }
+}
+
+fn payload(batch: &[KeyEvent]) -> String {
+    let events = batch.iter().map(KeyEvent::to_json).collect();
+    Json::Object(vec![("events".to_string(), Json::Array(events))]).to_text()
+}
+
+async fn post(webhook: &Webhook, body: &str) -> Result<()> {
+    let mut stream = TcpStream::connect(&webhook.authority).await?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: zenql/{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        webhook.path, webhook.authority, env!("CARGO_PKG_VERSION"), body.len(), body
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = vec![0u8; 256];
+    let n = stream.read(&mut response).await?;
+    let status_line = String::from_utf8_lossy(&response[..n]);
+    if !status_line.starts_with("HTTP/1.1 2") && !status_line.starts_with("HTTP/1.0 2") {
+        return Err(anyhow::anyhow!("Endpoint replied {:?}", status_line.lines().next().unwrap_or("")));
+    }
+    Ok(())
+}
@@ -0,0 +1,375 @@
+// WebSockets on the HTTP gateway, for browsers: GET /ws upgrades the connection, which is then served as a
+// RESP client's is, bridged to connection::handle through an in-memory pipe, so pub/sub, transactions and
+// AUTH work as they do there. Two framings are offered, picked with Sec-WebSocket-Protocol:
+//
+//   json   the default: each text message is a command as POST /command takes it, a JSON array, and each
+//          reply a text message, {"result": ...} or {"error": "..."}. The connection speaks RESP3, so
+//          subscription confirmations and published messages arrive as {"push": [...]} whenever they come.
+//   resp   binary messages carry the RESP protocol both ways, split into messages any way the sender likes
+//
+// Browsers can't set headers on a WebSocket, so clients authenticate with AUTH rather than Authorization.
+// Malformed frames and JSON messages that aren't commands close the connection, as do messages longer than
+// client-query-buffer-limit.
+use anyhow::Result;
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use crate::clients::format_addr;
+use crate::codec::RespCodec;
+use crate::connection;
+use crate::http::{self, encode_base64, Format, Request, Response};
+use crate::json::Json;
+use crate::listener::Accepted;
+use crate::resp::Value;
+use crate::server::Server;
+use crate::stats::{self, SERVER_STATS};
+
+// Appended to the client's key and hashed for Sec-WebSocket-Accept (RFC 6455)
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// Bytes buffered each way between the WebSocket and its connection
+const PIPE_SIZE: usize = 64 * 1024;
+
+// Opcodes
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+// Close codes
+const NORMAL: u16 = 1000;
+const PROTOCOL_ERROR: u16 = 1002;
+const INVALID_DATA: u16 = 1007;
+const TOO_BIG: u16 = 1009;
+
+// Sent through the pipe first under the json framing, its reply dropped
+const HELLO: &[u8] = b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Framing {
+    Json,
+    Resp,
+}
+
+impl Framing {
+    fn name(self) -> &'static str {
+        match self {
+            Framing::Json => "json",
+            Framing::Resp => "resp",
+        }
+    }
+}
+
+// The 101 response to an upgrade request and the framing settled on, the first of the client's we offer, or
+// the response refusing it
+pub(crate) fn handshake(request: &Request) -> Result<(String, Framing), Response> {
+    let upgrade = request.header("upgrade").is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+    if request.method != "GET" || !upgrade {
+        return Err(Response::error(426, "GET /ws upgrades to a WebSocket", Format::Json).with_header("Upgrade", "websocket"));
+    }
+    if request.header("sec-websocket-version") != Some("13") {
+        return Err(Response::error(400, "only WebSocket version 13 is supported", Format::Json).with_header("Sec-WebSocket-Version", "13"));
+    }
+    let Some(key) = request.header("sec-websocket-key") else {
+        return Err(Response::error(400, "missing Sec-WebSocket-Key", Format::Json));
+    };
+    let framing = request.header("sec-websocket-protocol").and_then(|offered| {
+        offered.split(',').find_map(|name| match name.trim() {
+            "json" => Some(Framing::Json),
+            "resp" => Some(Framing::Resp),
+            _ => None,
+        })
+    });
+    let mut response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n",
+        accept_key(key)
+    );
+    if let Some(framing) = framing {
+        response.push_str(&format!("Sec-WebSocket-Protocol: {}\r\n", framing.name()));
+    }
+    response.push_str("\r\n");
+    Ok((response, framing.unwrap_or(Framing::Json)))
+}
+
+fn accept_key(key: &str) -> String {
+    encode_base64(&sha1(format!("{}{}", key.trim(), GUID).as_bytes()))
+}
+
+// Serves an upgraded connection until either side closes it. `buffered` is what the client sent past the
+// upgrade request.
+pub(crate) async fn serve(stream: TcpStream, buffered: BytesMut, framing: Framing, peer: &str, loopback: bool, server: Server) -> Result<()> {
+    let laddr = stream.local_addr().map(format_addr).unwrap_or_default();
+    let (client, pipe) = tokio::io::duplex(PIPE_SIZE);
+    let accepted = Accepted { stream: Box::new(client), addr: peer.to_string(), laddr, local: loopback };
+    let max_message = server.config().client_query_buffer_limit;
+    let (reader, writer) = stream.into_split();
+    let (from_server, to_server) = tokio::io::split(pipe);
+    let (control, controls) = mpsc::unbounded_channel();
+    let inbound = inbound(reader, buffered, to_server, control, framing, max_message);
+    let outbound = outbound(writer, from_server, controls, framing);
+    // Done once the client is sent a close frame; the client ending its side first closes the pipe, so the
+    // connection ends and then outbound
+    let bridge = async {
+        tokio::pin!(inbound, outbound);
+        let mut inbound_done = false;
+        loop {
+            tokio::select! {
+                result = &mut inbound, if !inbound_done => {
+                    inbound_done = true;
+                    result?;
+                },
+                result = &mut outbound => return result,
+            }
+        }
+    };
+    stats::incr(&SERVER_STATS.total_connections_received, 1);
+    stats::incr(&SERVER_STATS.connected_clients, 1);
+    let (handled, bridged) = tokio::join!(connection::handle(accepted, server), bridge);
+    stats::decr(&SERVER_STATS.connected_clients, 1);
+    handled.and(bridged)
+}
+
+// Client to server: feeds the payloads of the client's messages to the connection, queueing the answers to
+// pings and closes on `control`. The pipe is closed behind them.
+async fn inbound(
+    mut reader: OwnedReadHalf,
+    buf: BytesMut,
+    mut to_server: WriteHalf<DuplexStream>,
+    control: UnboundedSender<Vec<u8>>,
+    framing: Framing,
+    max_message: usize,
+) -> Result<()> {
+    let result = forward(&mut reader, buf, &mut to_server, &control, framing, max_message).await;
+    let _ = to_server.shutdown().await;
+    result
+}
+
+async fn forward(
+    reader: &mut OwnedReadHalf,
+    mut buf: BytesMut,
+    to_server: &mut WriteHalf<DuplexStream>,
+    control: &UnboundedSender<Vec<u8>>,
+    framing: Framing,
+    max_message: usize,
+) -> Result<()> {
+    if framing == Framing::Json {
+        to_server.write_all(HELLO).await?;
+    }
+    let mut message = BytesMut::new(); // A json message so far
+    let mut fragmented = false; // Between a message's first frame and its last
+    loop {
+        let close = |code: u16, reason: &str| {
+            let _ = control.send(close_frame(code, reason));
+            Ok(())
+        };
+        let frame = match decode_frame(&mut buf, max_message) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                if reader.read_buf(&mut buf).await? == 0 {
+                    return Ok(());
+                }
+                continue;
+            },
+            Err((code, reason)) => return close(code, reason),
+        };
+        match frame.opcode {
+            PING => {
+                let _ = control.send(encode_frame(PONG, &frame.payload));
+            },
+            PONG => {},
+            CLOSE => {
+                let code = frame.payload.get(..2).map_or(NORMAL, |code| u16::from_be_bytes([code[0], code[1]]));
+                return close(code, "");
+            },
+            TEXT | BINARY | CONTINUATION => {
+                if (frame.opcode == CONTINUATION) != fragmented {
+                    return close(PROTOCOL_ERROR, "unexpected continuation frame");
+                }
+                fragmented = !frame.fin;
+                if framing == Framing::Resp {
+                    to_server.write_all(&frame.payload).await?;
+                    continue;
+                }
+                message.extend_from_slice(&frame.payload);
+                if message.len() > max_message {
+                    return close(TOO_BIG, "message too big");
+                }
+                if !frame.fin {
+                    continue;
+                }
+                let Some(args) = http::parse_command(&message) else {
+                    return close(INVALID_DATA, "a command is a non-empty JSON array of strings and numbers");
+                };
+                to_server.write_all(&encode_command(&args)).await?;
+                message.clear();
+            },
+            _ => return close(PROTOCOL_ERROR, "unknown opcode"),
+        }
+    }
+}
+
+// Server to client: frames the connection's output as messages, and sends the frames inbound queues. Done
+// once a close frame is sent, which it sends itself when the connection ends first.
+async fn outbound(mut writer: OwnedWriteHalf, mut from_server: ReadHalf<DuplexStream>, mut controls: UnboundedReceiver<Vec<u8>>, framing: Framing) -> Result<()> {
+    let mut buf = BytesMut::with_capacity(PIPE_SIZE);
+    let mut codec = RespCodec::default();
+    let mut hello = framing == Framing::Json; // HELLO's reply is still to come
+    loop {
+        tokio::select! {
+            read = from_server.read_buf(&mut buf) => {
+                if read? == 0 {
+                    writer.write_all(&close_frame(NORMAL, "")).await?;
+                    return Ok(());
+                }
+                if framing == Framing::Resp {
+                    writer.write_all(&encode_frame(BINARY, &buf.split())).await?;
+                    continue;
+                }
+                while let Some(reply) = codec.decode_reply(&mut buf)? {
+                    // Unless the connection was refused before HELLO could run
+                    if std::mem::take(&mut hello) && !matches!(reply, Value::Error(_)) {
+                        continue;
+                    }
+                    let (name, json) = match reply {
+                        Value::Push(items) => ("push", http::to_json(Value::Array(items))),
+                        Value::Error(e) => ("error", Json::String(e)),
+                        reply => ("result", http::to_json(reply)),
+                    };
+                    let message = Json::Object(vec![(name.to_string(), json)]).to_text();
+                    writer.write_all(&encode_frame(TEXT, message.as_bytes())).await?;
+                }
+            },
+            Some(frame) = controls.recv() => {
+                writer.write_all(&frame).await?;
+                if frame[0] == 0x80 | CLOSE {
+                    return Ok(());
+                }
+            },
+        }
+    }
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Bytes, // Unmasked
+}
+
+// The next frame in `buf`, None while it's incomplete, or the close code and reason it's refused with.
+// Client frames are always masked.
+fn decode_frame(buf: &mut BytesMut, max_payload: usize) -> Result<Option<Frame>, (u16, &'static str)> {
+    let [first, second, ..] = buf[..] else {
+        return Ok(None);
+    };
+    let (fin, opcode) = (first & 0x80 != 0, first & 0x0f);
+    if first & 0x70 != 0 {
+        return Err((PROTOCOL_ERROR, "reserved bits set"));
+    }
+    if second & 0x80 == 0 {
+        return Err((PROTOCOL_ERROR, "client frames must be masked"));
+    }
+    let (len, header) = match second & 0x7f {
+        126 if buf.len() < 4 => return Ok(None),
+        126 => (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4),
+        127 if buf.len() < 10 => return Ok(None),
+        127 => (u64::from_be_bytes(buf[2..10].try_into().expect("8 bytes")), 10),
+        len => (len as u64, 2),
+    };
+    if opcode >= CLOSE && (len > 125 || !fin) {
+        return Err((PROTOCOL_ERROR, "invalid control frame"));
+    }
+    if len > max_payload as u64 {
+        return Err((TOO_BIG, "message too big"));
+    }
+    let total = header + 4 + len as usize;
+    if buf.len() < total {
+        buf.reserve(total - buf.len());
+        return Ok(None);
+    }
+    let mask: [u8; 4] = buf[header..header + 4].try_into().expect("4 bytes");
+    buf.advance(header + 4);
+    let mut payload = buf.split_to(len as usize);
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok(Some(Frame { fin, opcode, payload: payload.freeze() }))
+}
+
+// A whole, unmasked frame, as servers send them
+fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        },
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        },
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn close_frame(code: u16, reason: &str) -> Vec<u8> {
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(reason.as_bytes());
+    encode_frame(CLOSE, &payload)
+}
+
+// A multibulk request, as a RESP client sends one
+fn encode_command(args: &[Bytes]) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        out.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        out.extend_from_slice(arg);
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
+
+// SHA-1, which the handshake needs and nothing else does
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().expect("4 bytes"));
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in words.into_iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let next = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, next);
+        }
+        for (state, value) in state.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+    let mut digest = [0; 20];
+    for (bytes, word) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
@@ -0,0 +1,776 @@
+// ZenQL, SQL-like queries over the keyspace: `SELECT name, price FROM product:* WHERE price > 10 AND name LIKE
+// 'a%' ORDER BY price DESC LIMIT 20 OFFSET 40`. FROM takes a glob pattern, see glob.rs, and the rows are the
+// hashes and JSON documents whose keys it matches. Fields are hash fields by name, or document members, dotted
+// for nested ones (`address.city`), with backquotes around names that aren't plain words; `__key` is the key.
+// SELECT takes `*` or a list of fields.
+//
+// WHERE compares fields and literals, numbers and 'strings', with = != <> < <= > >=, and takes IS [NOT] NULL,
+// [NOT] LIKE with SQL's % and _, [NOT] IN (...) and BETWEEN, combined with AND, OR, NOT and parentheses. Hash
+// values that read as numbers compare as numbers, and a number compares with a string by reading the string as
+// one. A comparison with a missing field, or of values that don't compare, is false. ORDER BY sorts by one or
+// more fields, ascending unless DESC, rows missing a field last and ties in key order.
+//
+// A query is planned before it runs. Without a better way it scans every key, but where the WHERE clause is
+// a conjunction with comparisons of a field against literals, and a secondary index on that field covers
+// every key the pattern can match, see index.rs, it reads only the keys in the range the comparisons bound,
+// then applies the whole clause to them. Of the indexes that fit, the one with the fewest keys in its range
+// is used, if that's fewer than a scan would look at. A numeric index serves comparisons with numbers and a
+// text index those with strings that don't read as numbers, as only then does the index order the values the
+// way the comparisons do. `EXPLAIN SELECT ...` gives the plan instead of the rows.
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::Bound;
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use crate::glob;
+use crate::index::{IndexDef, IndexKind, TermRange};
+use crate::json::{Json, Path};
+use crate::storage::{Storage, StoredValue};
+
+// Bounds how deeply parentheses and NOTs nest in a query, so parsing one can't overflow the stack
+const MAX_NESTING: usize = 32;
+const KEY_FIELD: &str = "__key";
+const KEYWORDS: &[&str] = &[
+    "explain", "select", "from", "where", "and", "or", "not", "order", "by", "asc", "desc", "limit", "offset", "is", "null", "like", "in", "between",
+    "true", "false",
+];
+
+// A field, with the path it is in a document
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    path: Path,
+}
+
+impl Field {
+    fn new(name: String) -> Result<Field> {
+        // Members by name, whatever characters they have
+        let path = name.split('.').map(|member| format!("[{}]", Json::String(member.to_string()).to_text())).collect::<String>();
+        let path = Path::parse(&format!("${}", path)).or_else(|e| bail!("invalid field '{}': {}", name, e))?;
+        Ok(Field { name, path })
+    }
+
+    pub fn is_key(&self) -> bool {
+        self.name == KEY_FIELD
+    }
+
+    // The field's value in a row, Null where it's missing
+    pub fn value(&self, key: &[u8], value: &StoredValue) -> Datum {
+        if self.is_key() {
+            return Datum::from_text(key);
+        }
+        match value {
+            StoredValue::Hash(hash) => hash.get(self.name.as_bytes()).map_or(Datum::Null, |value| Datum::from_text(value)),
+            StoredValue::Json(doc) => match self.path.find(doc).first() {
+                Some(location) => Datum::from_json(doc.at(location)),
+                None => Datum::Null,
+            },
+            _ => Datum::Null,
+        }
+    }
+
+    // The field's value as a reply gives it: a hash value as it is, a document's strings without quotes and
+    // other values as JSON
+    pub fn output(&self, key: &Bytes, value: &StoredValue) -> Option<Bytes> {
+        if self.is_key() {
+            return Some(key.clone());
+        }
+        match value {
+            StoredValue::Hash(hash) => hash.get(self.name.as_bytes()).cloned(),
+            StoredValue::Json(doc) => self.path.find(doc).first().map(|location| match doc.at(location) {
+                Json::String(text) => Bytes::from(text.clone()),
+                value => Bytes::from(value.to_text()),
+            }),
+            _ => None,
+        }
+    }
+}
+
+// A value a query works with
+#[derive(Debug, Clone, PartialEq)]
+pub enum Datum {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Text(Bytes),
+}
+
+impl Datum {
+    // Text that reads as a number is one
+    fn from_text(text: &[u8]) -> Datum {
+        match std::str::from_utf8(text).ok().and_then(|text| text.parse::<f64>().ok()).filter(|n| n.is_finite()) {
+            Some(n) => Datum::Number(n),
+            None => Datum::Text(Bytes::copy_from_slice(text)),
+        }
+    }
+
+    fn from_json(value: &Json) -> Datum {
+        match value {
+            Json::Null => Datum::Null,
+            Json::Bool(b) => Datum::Bool(*b),
+            Json::Int(_) | Json::Float(_) => Datum::Number(value.as_f64().expect("a number")),
+            Json::String(text) => Datum::Text(Bytes::from(text.clone())),
+            value => Datum::Text(Bytes::from(value.to_text())),
+        }
+    }
+
+    fn as_number(&self) -> Option<f64> {
+        match self {
+            Datum::Number(n) => Some(*n),
+            Datum::Text(text) => std::str::from_utf8(text).ok()?.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    // How two values compare in a condition, None when they don't
+    pub fn compare(&self, other: &Datum) -> Option<Ordering> {
+        match (self, other) {
+            (Datum::Text(a), Datum::Text(b)) => Some(a.cmp(b)),
+            (Datum::Bool(a), Datum::Bool(b)) => Some(a.cmp(b)),
+            (Datum::Number(_), Datum::Text(_)) | (Datum::Text(_), Datum::Number(_)) | (Datum::Number(_), Datum::Number(_)) => {
+                self.as_number()?.partial_cmp(&other.as_number()?)
+            },
+            _ => None,
+        }
+    }
+
+    // How two values sort: booleans, then numbers, then text, then missing values
+    fn sort(&self, other: &Datum) -> Ordering {
+        let rank = |datum: &Datum| match datum {
+            Datum::Bool(_) => 0,
+            Datum::Number(_) => 1,
+            Datum::Text(_) => 2,
+            Datum::Null => 3,
+        };
+        match (self, other) {
+            (Datum::Number(a), Datum::Number(b)) => a.total_cmp(b),
+            (a, b) => a.compare(b).filter(|_| rank(a) == rank(b)).unwrap_or_else(|| rank(a).cmp(&rank(b))),
+        }
+    }
+}
+
+// A value as a query would write it
+impl fmt::Display for Datum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Datum::Null => write!(f, "NULL"),
+            Datum::Bool(b) => write!(f, "{}", if *b { "TRUE" } else { "FALSE" }),
+            Datum::Number(n) => write!(f, "{}", n),
+            Datum::Text(text) => write!(f, "'{}'", String::from_utf8_lossy(text).replace('\'', "''")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Op::Eq => ordering.is_eq(),
+            Op::Ne => ordering.is_ne(),
+            Op::Lt => ordering.is_lt(),
+            Op::Le => ordering.is_le(),
+            Op::Gt => ordering.is_gt(),
+            Op::Ge => ordering.is_ge(),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Op::Eq => "=",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+        }
+    }
+
+    // The operator with its sides swapped: a < b is b > a
+    pub fn flip(self) -> Op {
+        match self {
+            Op::Lt => Op::Gt,
+            Op::Le => Op::Ge,
+            Op::Gt => Op::Lt,
+            Op::Ge => Op::Le,
+            op => op,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Field(Field),
+    Literal(Datum),
+}
+
+impl Operand {
+    fn value(&self, key: &[u8], value: &StoredValue) -> Datum {
+        match self {
+            Operand::Field(field) => field.value(key, value),
+            Operand::Literal(datum) => datum.clone(),
+        }
+    }
+}
+
+// A WHERE condition. BETWEEN and IN are read as the comparisons they stand for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, Op, Operand),
+    IsNull(Operand),
+    Like(Operand, Bytes), // The LIKE pattern as a glob
+}
+
+impl Expr {
+    // The conditions that must all hold for this one to, flattening nested ANDs
+    pub fn conjuncts(&self) -> Vec<&Expr> {
+        match self {
+            Expr::And(all) => all.iter().flat_map(Expr::conjuncts).collect(),
+            expr => vec![expr],
+        }
+    }
+
+    pub fn matches(&self, key: &[u8], value: &StoredValue) -> bool {
+        match self {
+            Expr::And(all) => all.iter().all(|expr| expr.matches(key, value)),
+            Expr::Or(any) => any.iter().any(|expr| expr.matches(key, value)),
+            Expr::Not(expr) => !expr.matches(key, value),
+            Expr::Compare(left, op, right) => left.value(key, value).compare(&right.value(key, value)).is_some_and(|ordering| op.holds(ordering)),
+            Expr::IsNull(operand) => operand.value(key, value) == Datum::Null,
+            Expr::Like(operand, pattern) => match operand.value(key, value) {
+                Datum::Text(text) => glob::matches(pattern, &text),
+                Datum::Number(_) => match operand {
+                    // Numbers are matched as written
+                    Operand::Field(field) => field.output(&Bytes::copy_from_slice(key), value).is_some_and(|text| glob::matches(pattern, &text)),
+                    Operand::Literal(_) => false,
+                },
+                _ => false,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Projection {
+    All,
+    Fields(Vec<Field>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Select {
+    pub projection: Projection,
+    pub pattern: Bytes,
+    pub filter: Option<Expr>,
+    pub order: Vec<(Field, bool)>, // Each field and whether it's descending
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+impl Select {
+    pub fn parse(text: &str) -> Result<Select> {
+        let mut parser = Parser { text, pos: 0, depth: 0 };
+        let select = parser.select()?;
+        parser.end()?;
+        Ok(select)
+    }
+
+    // Whether a key and its value make a row of the query
+    pub fn matches(&self, key: &[u8], value: &StoredValue) -> bool {
+        matches!(value, StoredValue::Hash(_) | StoredValue::Json(_))
+            && glob::matches(&self.pattern, key)
+            && self.filter.as_ref().is_none_or(|filter| filter.matches(key, value))
+    }
+
+    // Puts rows in the order the query asks for, key order when it asks for none
+    pub fn sort(&self, rows: &mut [(Bytes, StoredValue)]) {
+        rows.sort_by(|(a, a_value), (b, b_value)| {
+            self.order.iter().map(|(field, descending)| {
+                let (x, y) = (field.value(a, a_value), field.value(b, b_value));
+                match (&x, &y, descending) {
+                    // Missing values go last either way
+                    (Datum::Null, _, _) | (_, Datum::Null, _) => x.sort(&y),
+                    (_, _, true) => y.sort(&x),
+                    (_, _, false) => x.sort(&y),
+                }
+            }).find(|ordering| ordering.is_ne()).unwrap_or_else(|| a.cmp(b))
+        });
+    }
+
+    // A row's fields and values as the query selects them
+    pub fn project(&self, key: &Bytes, value: &StoredValue) -> Vec<(Bytes, Bytes)> {
+        match (&self.projection, value) {
+            (Projection::All, StoredValue::Hash(hash)) => hash.iter().map(|(field, value)| (field.clone(), value.clone())).collect(),
+            (Projection::All, value) => vec![(Bytes::from_static(b"$"), value.to_bytes())],
+            (Projection::Fields(fields), value) => {
+                fields.iter().filter_map(|field| Some((Bytes::from(field.name.clone()), field.output(key, value)?))).collect()
+            },
+        }
+    }
+
+    // The cheapest way to find the rows, see the top of the file
+    pub fn plan(&self, storage: &Storage) -> Plan {
+        let scan_cost = storage.len();
+        let mut plan = Plan { index: None, cost: scan_cost, scan_cost };
+        for (def, _) in storage.indexes() {
+            let Some((min, max)) = self.index_bounds(&def) else {
+                continue;
+            };
+            let term = |bound: &IndexBound| match bound {
+                Bound::Included((term, _)) => Bound::Included(term.clone()),
+                Bound::Excluded((term, _)) => Bound::Excluded(term.clone()),
+                Bound::Unbounded => Bound::Unbounded,
+            };
+            let range = TermRange { min: term(&min), max: term(&max) };
+            // Dropped since it was listed, if this is None
+            let Some(cost) = storage.count_index(&def.name, &range) else {
+                continue;
+            };
+            if cost < plan.cost {
+                let datum = |bound: IndexBound| bound.map(|(_, datum)| datum);
+                let field = String::from_utf8_lossy(&def.field).into_owned();
+                plan.index = Some(IndexScan { name: def.name.clone(), field, range, min: datum(min), max: datum(max) });
+                plan.cost = cost;
+            }
+        }
+        plan
+    }
+
+    // The range of an index's terms the filter bounds the rows to, with the values bounding it, None when it
+    // doesn't or the index doesn't cover every key the pattern matches
+    fn index_bounds(&self, def: &IndexDef) -> Option<(IndexBound, IndexBound)> {
+        let literal = self.pattern.iter().position(|c| b"*?[\\".contains(c)).unwrap_or(self.pattern.len());
+        if !self.pattern[..literal].starts_with(&def.prefix) {
+            return None;
+        }
+        let (mut min, mut max) = (Bound::Unbounded, Bound::Unbounded);
+        for conjunct in self.filter.as_ref()?.conjuncts() {
+            let (field, op, literal) = match conjunct {
+                Expr::Compare(Operand::Field(field), op, Operand::Literal(literal)) => (field, *op, literal),
+                Expr::Compare(Operand::Literal(literal), op, Operand::Field(field)) => (field, op.flip(), literal),
+                _ => continue,
+            };
+            if field.is_key() || field.name.as_bytes() != def.field {
+                continue;
+            }
+            let term = match (def.kind, literal) {
+                (IndexKind::Numeric, Datum::Number(n)) => def.encode(n.to_string().as_bytes()),
+                (IndexKind::Text, Datum::Text(text)) if literal.as_number().is_none() => def.encode(text),
+                _ => None,
+            };
+            let Some(term) = term else {
+                continue;
+            };
+            let bound = |inclusive: bool| match inclusive {
+                true => Bound::Included((term.clone(), literal.clone())),
+                false => Bound::Excluded((term.clone(), literal.clone())),
+            };
+            if matches!(op, Op::Eq | Op::Gt | Op::Ge) {
+                min = tighter(min, bound(op != Op::Gt), Ordering::Greater);
+            }
+            if matches!(op, Op::Eq | Op::Lt | Op::Le) {
+                max = tighter(max, bound(op != Op::Lt), Ordering::Less);
+            }
+        }
+        match (&min, &max) {
+            (Bound::Unbounded, Bound::Unbounded) => None,
+            _ => Some((min, max)),
+        }
+    }
+
+    // The rows the plan finds, in key order, giving up with the error `check` gives, called as the walk goes
+    pub fn run<E>(&self, storage: &Storage, plan: &Plan, mut check: impl FnMut() -> Result<(), E>) -> Result<Vec<(Bytes, StoredValue)>, E> {
+        if let Some(index) = &plan.index {
+            if let Some(found) = storage.find_indexed(&index.name, &index.range, &mut check, |key, value| self.matches(key, value))? {
+                return Ok(found);
+            }
+        }
+        // A scan too when the index was dropped since
+        storage.find_values(check, |key, value| self.matches(key, value))
+    }
+}
+
+// Of two bounds on the same end of a range, the one leaving less in: the further `direction`, and exclusive
+// over inclusive at the same term
+fn tighter(a: IndexBound, b: IndexBound, direction: Ordering) -> IndexBound {
+    let (Bound::Included((a_term, _)) | Bound::Excluded((a_term, _))) = &a else {
+        return b;
+    };
+    let (Bound::Included((b_term, _)) | Bound::Excluded((b_term, _))) = &b else {
+        return a;
+    };
+    match b_term.cmp(a_term) {
+        Ordering::Equal if matches!(b, Bound::Excluded(_)) => b,
+        Ordering::Equal => a,
+        ordering if ordering == direction => b,
+        _ => a,
+    }
+}
+
+// A bound on an index's terms, with the value it was encoded from
+type IndexBound = Bound<(Bytes, Datum)>;
+
+// How a query finds its rows: a range of an index, or every key without one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    pub index: Option<IndexScan>,
+    pub cost: usize, // Keys looked at, as the index or keyspace holds them now
+    pub scan_cost: usize, // What scanning every key would look at instead
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexScan {
+    pub name: String,
+    pub field: String,
+    pub range: TermRange,
+    pub min: Bound<Datum>, // The values bounding the range
+    pub max: Bound<Datum>,
+}
+
+impl IndexScan {
+    // The range, as the interval between the values bounding it
+    pub fn describe(&self) -> String {
+        let min = match &self.min {
+            Bound::Included(value) => format!("[{}", value),
+            Bound::Excluded(value) => format!("({}", value),
+            Bound::Unbounded => "(-inf".to_string(),
+        };
+        let max = match &self.max {
+            Bound::Included(value) => format!("{}]", value),
+            Bound::Excluded(value) => format!("{})", value),
+            Bound::Unbounded => "+inf)".to_string(),
+        };
+        format!("{} in {}, {}", self.field, min, max)
+    }
+}
+
+// A query, or EXPLAIN and one
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Select(Select),
+    Explain(Select),
+}
+
+impl Statement {
+    pub fn parse(text: &str) -> Result<Statement> {
+        let mut parser = Parser { text, pos: 0, depth: 0 };
+        let explain = parser.keyword("explain");
+        let select = parser.select()?;
+        parser.end()?;
+        Ok(if explain { Statement::Explain(select) } else { Statement::Select(select) })
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    depth: usize,
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.text[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.pos = self.text.len() - self.rest().trim_start().len();
+    }
+
+    // Fails unless only whitespace is left
+    fn end(&mut self) -> Result<()> {
+        self.skip_whitespace();
+        match self.rest().chars().next() {
+            Some(c) => bail!("unexpected '{}' at offset {}", c, self.pos),
+            None => Ok(()),
+        }
+    }
+
+    // Takes `keyword`, in any case, when it comes next as a whole word
+    fn keyword(&mut self, keyword: &str) -> bool {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let matched = rest.len() >= keyword.len()
+            && rest.is_char_boundary(keyword.len())
+            && rest[..keyword.len()].eq_ignore_ascii_case(keyword)
+            && !rest[keyword.len()..].starts_with(is_word);
+        if matched {
+            self.pos += keyword.len();
+        }
+        matched
+    }
+
+    fn expect(&mut self, keyword: &str) -> Result<()> {
+        match self.keyword(keyword) {
+            true => Ok(()),
+            false => bail!("expected {} at offset {}", keyword.to_uppercase(), self.pos),
+        }
+    }
+
+    // Takes `symbol` when it comes next
+    fn symbol(&mut self, symbol: &str) -> bool {
+        self.skip_whitespace();
+        let matched = self.rest().starts_with(symbol);
+        if matched {
+            self.pos += symbol.len();
+        }
+        matched
+    }
+
+    fn select(&mut self) -> Result<Select> {
+        self.expect("select")?;
+        let projection = match self.symbol("*") {
+            true => Projection::All,
+            false => Projection::Fields(self.list(Parser::field)?),
+        };
+        self.expect("from")?;
+        let pattern = self.pattern()?;
+        let filter = match self.keyword("where") {
+            true => Some(self.or()?),
+            false => None,
+        };
+        let mut order = vec![];
+        if self.keyword("order") {
+            self.expect("by")?;
+            order = self.list(|parser| {
+                let field = parser.field()?;
+                let descending = parser.keyword("desc");
+                if !descending {
+                    parser.keyword("asc");
+                }
+                Ok((field, descending))
+            })?;
+        }
+        let (mut offset, mut limit) = (0, None);
+        if self.keyword("limit") {
+            limit = Some(self.count()?);
+            if self.keyword("offset") {
+                offset = self.count()?;
+            }
+        }
+        Ok(Select { projection, pattern, filter, order, offset, limit })
+    }
+
+    // One or more of what `item` parses, separated by commas
+    fn list<T>(&mut self, item: impl Fn(&mut Self) -> Result<T>) -> Result<Vec<T>> {
+        let mut items = vec![item(self)?];
+        while self.symbol(",") {
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+
+    fn count(&mut self) -> Result<usize> {
+        self.skip_whitespace();
+        let digits = self.rest().len() - self.rest().trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let Ok(n) = self.rest()[..digits].parse() else {
+            bail!("expected a count at offset {}", self.pos);
+        };
+        self.pos += digits;
+        Ok(n)
+    }
+
+    // A glob pattern, quoted or up to whitespace
+    fn pattern(&mut self) -> Result<Bytes> {
+        self.skip_whitespace();
+        if self.rest().starts_with('\'') {
+            return Ok(Bytes::from(self.string()?));
+        }
+        let len = self.rest().find(char::is_whitespace).unwrap_or(self.rest().len());
+        if len == 0 {
+            bail!("expected a key pattern at offset {}", self.pos);
+        }
+        let pattern = Bytes::copy_from_slice(&self.rest().as_bytes()[..len]);
+        self.pos += len;
+        Ok(pattern)
+    }
+
+    // A 'string', with '' for a quote inside
+    fn string(&mut self) -> Result<String> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut string = String::new();
+        loop {
+            let Some(c) = self.rest().chars().next() else {
+                bail!("unterminated string at offset {}", start);
+            };
+            self.pos += c.len_utf8();
+            match c {
+                '\'' if self.rest().starts_with('\'') => {
+                    string.push('\'');
+                    self.pos += 1;
+                },
+                '\'' => return Ok(string),
+                c => string.push(c),
+            }
+        }
+    }
+
+    // A field name: words joined by dots, or anything between backquotes
+    fn field(&mut self) -> Result<Field> {
+        self.skip_whitespace();
+        if let Some(quoted) = self.rest().strip_prefix('`') {
+            let Some(len) = quoted.find('`') else {
+                bail!("unterminated field name at offset {}", self.pos);
+            };
+            let name = quoted[..len].to_string();
+            self.pos += len + 2;
+            return Field::new(name);
+        }
+        let len = self.rest().len() - self.rest().trim_start_matches(|c: char| is_word(c) || c == '.').len();
+        let name = self.rest()[..len].to_string();
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit() || c == '.') || KEYWORDS.contains(&name.to_lowercase().as_str()) {
+            bail!("expected a field at offset {}", self.pos);
+        }
+        self.pos += len;
+        Field::new(name)
+    }
+
+    fn literal(&mut self) -> Result<Option<Datum>> {
+        self.skip_whitespace();
+        if self.rest().starts_with('\'') {
+            return Ok(Some(Datum::Text(Bytes::from(self.string()?))));
+        }
+        if self.keyword("true") {
+            return Ok(Some(Datum::Bool(true)));
+        }
+        if self.keyword("false") {
+            return Ok(Some(Datum::Bool(false)));
+        }
+        let len = self.rest().len() - self.rest().trim_start_matches(|c: char| c.is_ascii_digit() || "+-.eE".contains(c)).len();
+        if len == 0 || !self.rest().starts_with(|c: char| c.is_ascii_digit() || "+-.".contains(c)) {
+            return Ok(None);
+        }
+        match self.rest()[..len].parse::<f64>() {
+            Ok(n) if n.is_finite() => {
+                self.pos += len;
+                Ok(Some(Datum::Number(n)))
+            },
+            _ => bail!("invalid number at offset {}", self.pos),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand> {
+        match self.literal()? {
+            Some(datum) => Ok(Operand::Literal(datum)),
+            None => Ok(Operand::Field(self.field()?)),
+        }
+    }
+
+    fn nest(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            bail!("query nested too deeply");
+        }
+        Ok(())
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut any = vec![self.and()?];
+        while self.keyword("or") {
+            any.push(self.and()?);
+        }
+        Ok(if any.len() == 1 { any.pop().expect("one condition") } else { Expr::Or(any) })
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut all = vec![self.not()?];
+        while self.keyword("and") {
+            all.push(self.not()?);
+        }
+        Ok(if all.len() == 1 { all.pop().expect("one condition") } else { Expr::And(all) })
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        if self.keyword("not") {
+            self.nest()?;
+            let expr = self.not()?;
+            self.depth -= 1;
+            return Ok(Expr::Not(Box::new(expr)));
+        }
+        if self.symbol("(") {
+            self.nest()?;
+            let expr = self.or()?;
+            self.depth -= 1;
+            if !self.symbol(")") {
+                bail!("expected ')' at offset {}", self.pos);
+            }
+            return Ok(expr);
+        }
+        self.predicate()
+    }
+
+    fn predicate(&mut self) -> Result<Expr> {
+        let left = self.operand()?;
+        if self.keyword("is") {
+            let negated = self.keyword("not");
+            self.expect("null")?;
+            let expr = Expr::IsNull(left);
+            return Ok(if negated { Expr::Not(Box::new(expr)) } else { expr });
+        }
+        let negated = self.keyword("not");
+        let expr = if self.keyword("like") {
+            self.skip_whitespace();
+            if !self.rest().starts_with('\'') {
+                bail!("expected a pattern at offset {}", self.pos);
+            }
+            Expr::Like(left, like_to_glob(&self.string()?))
+        } else if self.keyword("in") {
+            if !self.symbol("(") {
+                bail!("expected '(' at offset {}", self.pos);
+            }
+            let values = self.list(|parser| parser.literal()?.ok_or_else(|| anyhow::anyhow!("expected a value at offset {}", parser.pos)))?;
+            if !self.symbol(")") {
+                bail!("expected ')' at offset {}", self.pos);
+            }
+            Expr::Or(values.into_iter().map(|value| Expr::Compare(left.clone(), Op::Eq, Operand::Literal(value))).collect())
+        } else if self.keyword("between") {
+            let low = self.operand()?;
+            self.expect("and")?;
+            let high = self.operand()?;
+            Expr::And(vec![Expr::Compare(left.clone(), Op::Ge, low), Expr::Compare(left, Op::Le, high)])
+        } else if negated {
+            bail!("expected LIKE, IN or BETWEEN at offset {}", self.pos);
+        } else {
+            let op = self.op()?;
+            Expr::Compare(left, op, self.operand()?)
+        };
+        Ok(if negated { Expr::Not(Box::new(expr)) } else { expr })
+    }
+
+    fn op(&mut self) -> Result<Op> {
+        // Longest first, so <= isn't read as <
+        let ops = [("<=", Op::Le), (">=", Op::Ge), ("!=", Op::Ne), ("<>", Op::Ne), ("==", Op::Eq), ("=", Op::Eq), ("<", Op::Lt), (">", Op::Gt)];
+        match ops.iter().find(|(symbol, _)| self.symbol(symbol)) {
+            Some(&(_, op)) => Ok(op),
+            None => bail!("expected a comparison at offset {}", self.pos),
+        }
+    }
+}
+
+// A LIKE pattern as a glob: % is any run and _ any one character, everything else literal
+fn like_to_glob(pattern: &str) -> Bytes {
+    let mut glob = Vec::with_capacity(pattern.len());
+    for c in pattern.chars() {
+        match c {
+            '%' => glob.push(b'*'),
+            '_' => glob.push(b'?'),
+            '*' | '?' | '[' | ']' | '\\' => glob.extend_from_slice(&[b'\\', c as u8]),
+            c => glob.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    Bytes::from(glob)
+}
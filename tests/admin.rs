@@ -0,0 +1,71 @@
+use std::time::Duration;
+use redis_starter_rust::Config;
+use support::TestServer;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+mod support;
+
+async fn start(config: Config) -> (TestServer, u16) {
+    TestServer::with_port(config, |config| &mut config.admin_port).await
+}
+
+// Sends a request and returns the response's status and body
+async fn request(port: u16, method: &str, path: &str, authorization: Option<&str>) -> (u16, String) {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let authorization = authorization.map(|value| format!("Authorization: {}\r\n", value)).unwrap_or_default();
+    let request = format!("{} {} HTTP/1.1\r\nHost: localhost\r\n{}Connection: close\r\n\r\n", method, path, authorization);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let status = response.split(' ').nth(1).unwrap().parse().unwrap();
+    let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+    (status, body)
+}
+
+#[tokio::test]
+async fn health_endpoints() {
+    let (server, port) = start(Config::default()).await;
+    let mut client = server.client().await;
+    client.set("a", "1").await.unwrap();
+
+    assert_eq!(request(port, "GET", "/healthz", None).await, (200, r#"{"status":"ok"}"#.to_string()));
+    assert_eq!(request(port, "GET", "/readyz", None).await, (200, r#"{"status":"ready"}"#.to_string()));
+    let (status, body) = request(port, "GET", "/status", None).await;
+    assert_eq!(status, 200);
+    assert!(body.starts_with(r#"{"ready":true,"version":""#), "{}", body);
+    assert!(body.contains(r#""keys":1,"clients":{"connected_clients":1"#), "{}", body);
+    assert!(body.contains(r#""stats":{"#), "{}", body);
+
+    assert_eq!(request(port, "GET", "/metrics", None).await.0, 404);
+    assert_eq!(request(port, "POST", "/readyz", None).await.0, 405);
+}
+
+#[tokio::test]
+async fn not_ready_at_maxclients() {
+    let (server, port) = start(Config { maxclients: 1, ..Config::default() }).await;
+    assert_eq!(request(port, "GET", "/readyz", None).await.0, 200);
+    let mut client = server.client().await;
+    client.ping().await.unwrap();
+    assert_eq!(request(port, "GET", "/readyz", None).await, (503, r#"{"status":"not ready","reason":"maxclients reached"}"#.to_string()));
+    // Still alive, just full
+    assert_eq!(request(port, "GET", "/healthz", None).await.0, 200);
+    drop(client);
+    for _ in 0..100 {
+        if request(port, "GET", "/readyz", None).await.0 == 200 {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("never ready again after the client left");
+}
+
+#[tokio::test]
+async fn status_needs_password() {
+    let (_server, port) = start(Config { requirepass: Some("secret".to_string()), ..Config::default() }).await;
+    assert_eq!(request(port, "GET", "/healthz", None).await.0, 200);
+    assert_eq!(request(port, "GET", "/readyz", None).await.0, 200);
+    assert_eq!(request(port, "GET", "/status", None).await.0, 401);
+    assert_eq!(request(port, "GET", "/status", Some("Bearer wrong")).await.0, 401);
+    assert_eq!(request(port, "GET", "/status", Some("Bearer secret")).await.0, 200);
+}
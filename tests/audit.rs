@@ -0,0 +1,107 @@
+use std::path::PathBuf;
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::Config;
+use support::{bulk, TestServer};
+
+mod support;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("zenql-audit-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn entries(reply: Value) -> Vec<Vec<Value>> {
+    let Value::Array(entries) = reply else {
+        panic!("expected an array, got {:?}", reply);
+    };
+    entries.into_iter().map(|entry| match entry {
+        Value::Array(fields) => fields,
+        other => panic!("expected an entry, got {:?}", other),
+    }).collect()
+}
+
+#[tokio::test]
+async fn audit_records_writes() {
+    let path = temp_dir("writes").join("audit.log");
+    let server = TestServer::with_config(Config { audit_log: Some(path.display().to_string()), ..Config::default() }).await;
+    let mut client = server.client().await;
+    client.call(["CLIENT", "SETNAME", "worker"]).await.unwrap();
+    client.set("a", "1").await.unwrap();
+    client.call(["GET", "a"]).await.unwrap();
+    client.del(["a"]).await.unwrap();
+    assert!(client.call(["INCR", "a", "b"]).await.is_err());
+    client.call(["HSET", "h", "f", "x"]).await.unwrap();
+    assert!(client.call(["INCR", "h"]).await.is_err());
+    client.call(["MULTI"]).await.unwrap();
+    client.call(["SET", "b", "2"]).await.unwrap();
+    client.call(["EXEC"]).await.unwrap();
+
+    // Reads aren't recorded, nor are commands refused before they ran, and transactions as they execute.
+    // Reading the audit log is an admin command, so it's recorded too.
+    assert_eq!(client.call(["AUDIT", "LEN"]).await.unwrap(), Value::Integer(5));
+    let recent = entries(client.call(["AUDIT", "GET"]).await.unwrap());
+    let commands: Vec<_> = recent.iter().map(|entry| entry[6].clone()).collect();
+    assert_eq!(commands, vec![
+        Value::Array(vec![bulk("audit"), bulk("LEN")]),
+        Value::Array(vec![bulk("set"), bulk("b"), bulk("2")]),
+        Value::Array(vec![bulk("incr"), bulk("h")]),
+        Value::Array(vec![bulk("hset"), bulk("h"), bulk("f"), bulk("x")]),
+        Value::Array(vec![bulk("del"), bulk("a")]),
+        Value::Array(vec![bulk("set"), bulk("a"), bulk("1")]),
+    ]);
+    let latest = &recent[1];
+    assert_eq!(latest[0], Value::Integer(5));
+    assert_eq!(latest[3], bulk("worker"));
+    assert_eq!(latest[4], bulk("default"));
+    assert_eq!(latest[5], Value::Integer(0));
+    assert_eq!(latest[7], bulk("ok"));
+    assert!(matches!(&recent[2][7], Value::BulkString(result) if result.starts_with(b"WRONGTYPE")), "{:?}", recent[2]);
+    assert_eq!(entries(client.call(["AUDIT", "GET", "2"]).await.unwrap()).len(), 2);
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<_> = log.lines().collect();
+    assert_eq!(lines.len(), 8, "{}", log);
+    assert!(lines[0].starts_with(r#"{"ts":""#), "{}", lines[0]);
+    assert!(lines[0].contains(r#""id":1,"client":"127.0.0.1:"#), "{}", lines[0]);
+    assert!(lines[0].ends_with(r#""name":"worker","user":"default","db":0,"command":["set","a","1"],"result":"ok"}"#), "{}", lines[0]);
+
+    assert!(client.call(["AUDIT", "GET", "-1"]).await.is_err());
+    assert!(client.call(["AUDIT", "LEN", "1"]).await.is_err());
+    assert!(client.call(["AUDIT", "CLEAR"]).await.is_err());
+}
+
+#[tokio::test]
+async fn audit_truncates_and_rotates() {
+    let dir = temp_dir("rotate");
+    let path = dir.join("audit.log").display().to_string();
+    let config = Config { audit_log: Some(path.clone()), audit_max_size: 4096, audit_max_files: 2, ..Config::default() };
+    let server = TestServer::with_config(config).await;
+    let mut client = server.client().await;
+
+    let value = "x".repeat(1000);
+    client.set("big", &value).await.unwrap();
+    let recent = entries(client.call(["AUDIT", "GET", "1"]).await.unwrap());
+    let Value::Array(command) = &recent[0][6] else {
+        panic!("expected the command");
+    };
+    assert_eq!(command[2], bulk(&format!("{}... (1000 bytes)", "x".repeat(256))));
+
+    for i in 0..100 {
+        client.set(format!("k{}", i), "v").await.unwrap();
+    }
+    assert!(std::fs::metadata(format!("{}.1", path)).is_ok());
+    assert!(std::fs::metadata(format!("{}.2", path)).is_ok());
+    assert!(std::fs::metadata(format!("{}.3", path)).is_err());
+    assert!(std::fs::metadata(&path).unwrap().len() <= 4096);
+}
+
+#[tokio::test]
+async fn audit_disabled() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.set("a", "1").await.unwrap();
+    let err = client.call(["AUDIT", "GET"]).await.unwrap_err();
+    assert!(err.to_string().contains("audit log is disabled"), "{}", err);
+}
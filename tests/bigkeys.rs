@@ -0,0 +1,72 @@
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::storage::BackendKind;
+use redis_starter_rust::Config;
+use support::{bulk, field, fields, TestServer};
+
+mod support;
+
+#[tokio::test]
+async fn bigkeys_reports_each_type() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.set("small", "ab").await.unwrap();
+    client.set("large", "x".repeat(100)).await.unwrap();
+    client.set("number", "12345").await.unwrap();
+    client.call(["HSET", "h1", "a", "1"]).await.unwrap();
+    client.call(["HSET", "h2", "a", "1", "b", "2", "c", "3"]).await.unwrap();
+    client.call(["BF.ADD", "seen", "a"]).await.unwrap();
+    client.call(["JSON.SET", "doc", "$", r#"{"a":[1,2],"b":"x"}"#]).await.unwrap();
+
+    let reply = fields(client.call(["ZENQL", "BIGKEYS"]).await.unwrap());
+    assert_eq!(field(&reply, "scanned"), Value::Integer(7));
+    let Value::Array(types) = field(&reply, "types") else {
+        panic!("expected the types");
+    };
+    let types: Vec<_> = types.into_iter().map(fields).collect();
+    let summary: Vec<_> = types.iter().map(|sizes| {
+        ["type", "keys", "unit", "total", "biggest", "biggest.size"].map(|name| field(sizes, name))
+    }).collect();
+    assert_eq!(summary, vec![
+        [bulk("MBbloom--"), Value::Integer(1), bulk("items"), Value::Integer(1), bulk("seen"), Value::Integer(1)],
+        [bulk("ReJSON-RL"), Value::Integer(1), bulk("values"), Value::Integer(5), bulk("doc"), Value::Integer(5)],
+        [bulk("hash"), Value::Integer(2), bulk("fields"), Value::Integer(4), bulk("h2"), Value::Integer(3)],
+        [bulk("string"), Value::Integer(3), bulk("bytes"), Value::Integer(107), bulk("large"), Value::Integer(100)],
+    ]);
+}
+
+#[tokio::test]
+async fn bigkeys_samples() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    for i in 0..50 {
+        client.set(format!("k{}", i), "v").await.unwrap();
+    }
+    let reply = fields(client.call(["ZENQL", "BIGKEYS", "SAMPLES", "10"]).await.unwrap());
+    assert_eq!(field(&reply, "scanned"), Value::Integer(10));
+
+    // An empty keyspace has no types to report
+    let empty = TestServer::start().await;
+    let reply = fields(empty.client().await.call(["ZENQL", "BIGKEYS"]).await.unwrap());
+    assert_eq!(field(&reply, "scanned"), Value::Integer(0));
+    assert_eq!(field(&reply, "types"), Value::Array(vec![]));
+
+    assert!(client.call(["ZENQL", "BIGKEYS", "SAMPLES", "0"]).await.is_err());
+    assert!(client.call(["ZENQL", "BIGKEYS", "SAMPLES"]).await.is_err());
+    assert!(client.call(["ZENQL", "BIGKEYS", "COUNT", "1"]).await.is_err());
+    assert!(client.call(["ZENQL", "SMALLKEYS"]).await.is_err());
+}
+
+#[tokio::test]
+async fn bigkeys_leaves_values_on_disk() {
+    let server = TestServer::with_config(Config { storage_backend: BackendKind::Disk, ..Config::default() }).await;
+    let mut client = server.client().await;
+    client.set("small", "ab").await.unwrap();
+    client.set("large", "x".repeat(1000)).await.unwrap();
+    let reply = fields(client.call(["ZENQL", "BIGKEYS"]).await.unwrap());
+    let Value::Array(types) = field(&reply, "types") else {
+        panic!("expected the types");
+    };
+    let sizes = fields(types[0].clone());
+    assert_eq!(field(&sizes, "total"), Value::Integer(1002));
+    assert_eq!(field(&sizes, "biggest"), bulk("large"));
+}
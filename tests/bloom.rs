@@ -0,0 +1,80 @@
+use bytes::Bytes;
+use redis_starter_rust::bloom::BloomFilter;
+use redis_starter_rust::resp::Value;
+use support::{ints, scan_type, wal_storage, TestServer};
+
+mod support;
+
+#[tokio::test]
+async fn filter_commands() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    assert_eq!(client.call(["BF.ADD", "seen", "a"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(client.call(["BF.ADD", "seen", "a"]).await.unwrap(), Value::Integer(0));
+    assert_eq!(client.call(["BF.MADD", "seen", "b", "a", "c"]).await.unwrap(), ints(&[1, 0, 1]));
+    assert_eq!(client.call(["BF.EXISTS", "seen", "b"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(client.call(["BF.EXISTS", "seen", "zzz"]).await.unwrap(), Value::Integer(0));
+    assert_eq!(client.call(["BF.MEXISTS", "seen", "c", "d"]).await.unwrap(), ints(&[1, 0]));
+    assert_eq!(client.call(["BF.EXISTS", "missing", "a"]).await.unwrap(), Value::Integer(0));
+    assert_eq!(client.call(["BF.CARD", "seen"]).await.unwrap(), Value::Integer(3));
+    assert_eq!(client.call(["BF.CARD", "missing"]).await.unwrap(), Value::Integer(0));
+
+    client.call(["BF.RESERVE", "small", "0.001", "2", "NONSCALING"]).await.unwrap();
+    assert!(client.call(["BF.RESERVE", "small", "0.01", "10"]).await.is_err());
+    let Value::Array(replies) = client.call(["BF.MADD", "small", "x", "y", "z"]).await.unwrap() else { panic!("BF.MADD should reply with an array") };
+    assert_eq!(replies[..2], [Value::Integer(1), Value::Integer(1)]);
+    assert!(matches!(&replies[2], Value::Error(e) if e.contains("full")));
+    assert!(client.call(["BF.ADD", "small", "w"]).await.is_err());
+    let info = client.call(["BF.INFO", "small"]).await.unwrap();
+    let Value::Array(info) = info else { panic!("BF.INFO should reply with a map") };
+    assert_eq!(info[1], Value::Integer(2));
+    assert_eq!(info[4..], [Value::SimpleString("Number of filters".into()), Value::Integer(1), Value::SimpleString("Number of items inserted".into()), Value::Integer(2), Value::SimpleString("Expansion rate".into()), Value::Null]);
+
+    assert!(client.call(["BF.INSERT", "new", "NOCREATE", "ITEMS", "a"]).await.is_err());
+    assert_eq!(client.call(["BF.INSERT", "new", "CAPACITY", "1", "ERROR", "0.1", "EXPANSION", "4", "ITEMS", "a", "b", "c"]).await.unwrap(), ints(&[1, 1, 1]));
+    let Value::Array(info) = client.call(["BF.INFO", "new"]).await.unwrap() else { panic!("BF.INFO should reply with a map") };
+    // 1, then 4, stacked
+    assert_eq!((&info[1], &info[5], &info[9]), (&Value::Integer(5), &Value::Integer(2), &Value::Integer(4)));
+
+    for args in [&["BF.RESERVE", "bad", "1.5", "10"][..], &["BF.RESERVE", "bad", "0.1", "0"], &["BF.RESERVE", "bad", "0.1", "10", "EXPANSION"], &["BF.INSERT", "bad", "ITEMS"], &["BF.INFO", "missing"]] {
+        assert!(client.call(args).await.is_err(), "{:?}", args);
+    }
+    client.set("string", "value").await.unwrap();
+    assert!(client.call(["BF.ADD", "string", "a"]).await.unwrap_err().to_string().contains("WRONGTYPE"));
+    assert_eq!(scan_type(&mut client, "MBbloom--").await, ["new", "seen", "small"]);
+}
+
+// However many items a scaling filter takes, it finds them all and wrongly finds others about as often as asked
+#[test]
+fn error_rate_holds_as_filters_scale() {
+    let mut filter = BloomFilter::new(0.01, 100, 2).unwrap();
+    // An item wrongly found when added isn't counted
+    let added = (0..5000).filter(|i| filter.insert(format!("item:{}", i).as_bytes()).unwrap()).count();
+    assert!(added > 4850, "{} of 5000 added", added);
+    assert_eq!(filter.len(), added as u64);
+    assert!(filter.filters() > 1);
+    assert!((0..5000).all(|i| filter.contains(format!("item:{}", i).as_bytes())));
+    let wrong = (0..20_000).filter(|i| filter.contains(format!("other:{}", i).as_bytes())).count();
+    assert!(wrong < 400, "{} false positives in 20000", wrong);
+    assert_eq!(BloomFilter::decode(&Bytes::from(filter.encode())), Some(filter.clone()));
+    assert!(BloomFilter::decode(&Bytes::from(filter.encode()[1..].to_vec())).is_none());
+}
+
+#[test]
+fn filters_are_replayed() {
+    let wal = wal_storage("bloom");
+    let storage = wal.open();
+    let key = Bytes::from("f");
+    for item in ["a", "b", "c"] {
+        storage.update::<BloomFilter, _>(&key, |filter| {
+            let added = filter.get_or_insert_with(|| BloomFilter::new(0.01, 2, 2).unwrap()).insert(item.as_bytes()).unwrap();
+            ((), added)
+        }).unwrap();
+    }
+    storage.sync().unwrap();
+    drop(storage);
+
+    let storage = wal.open();
+    let found = storage.read::<BloomFilter, _>(&key, |filter| (filter.len(), filter.filters(), filter.contains(b"c"), filter.contains(b"d"))).unwrap();
+    assert_eq!(found, Some((3, 2, true, false)));
+}
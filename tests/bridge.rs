@@ -0,0 +1,299 @@
+use std::time::Duration;
+use redis_starter_rust::bridge::Bridge;
+use redis_starter_rust::kafka::crc32c;
+use redis_starter_rust::Config;
+use support::TestServer;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+mod support;
+
+// A NATS server for one client at a time: answers PINGs, reports every PUB as (subject, payload) on
+// `published`, and sends subscribers each payload from `deliver`
+async fn fake_nats(listener: TcpListener, published: UnboundedSender<(String, String)>, mut deliver: UnboundedReceiver<String>) {
+    loop {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        writer.write_all(b"INFO {\"server_id\":\"fake\",\"max_payload\":1048576}\r\n").await.unwrap();
+        let mut subscription = None;
+        loop {
+            tokio::select! {
+                line = lines.next_line() => {
+                    let Ok(Some(line)) = line else {
+                        break;
+                    };
+                    let words: Vec<&str> = line.split_whitespace().collect();
+                    match words[0] {
+                        "PING" => writer.write_all(b"PONG\r\n").await.unwrap(),
+                        "PUB" => {
+                            let payload = lines.next_line().await.unwrap().unwrap();
+                            assert_eq!(payload.len(), words[2].parse::<usize>().unwrap());
+                            published.send((words[1].to_string(), payload)).unwrap();
+                        },
+                        "SUB" => subscription = Some((words[1].to_string(), words[2].to_string())),
+                        _ => {},
+                    }
+                },
+                Some(payload) = deliver.recv(), if subscription.is_some() => {
+                    let (subject, sid) = subscription.as_ref().unwrap();
+                    let message = format!("MSG {} {} {}\r\n{}\r\n", subject, sid, payload.len(), payload);
+                    writer.write_all(message.as_bytes()).await.unwrap();
+                },
+            }
+        }
+    }
+}
+
+async fn next(published: &mut UnboundedReceiver<(String, String)>) -> (String, String) {
+    tokio::time::timeout(Duration::from_secs(10), published.recv()).await.expect("nothing was published").unwrap()
+}
+
+#[tokio::test]
+async fn nats_bridge_exports() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (published_tx, mut published) = mpsc::unbounded_channel();
+    let (_deliver, deliver_rx) = mpsc::unbounded_channel();
+    tokio::spawn(fake_nats(listener, published_tx, deliver_rx));
+
+    let bridges = [
+        format!("nats://{};subject=zenql.keys;events=set;match=user:*", address),
+    ];
+    let config = Config { bridges: bridges.iter().map(|spec| Bridge::parse(spec).unwrap()).collect(), ..Config::default() };
+    let server = TestServer::with_config(config).await;
+    let mut client = server.client().await;
+    client.set("order:1", "skipped").await.unwrap();
+    client.set("user:1", "ada").await.unwrap();
+    client.del(["user:1"]).await.unwrap();
+    client.set("user:2", "grace").await.unwrap();
+
+    let (subject, payload) = next(&mut published).await;
+    assert_eq!(subject, "zenql.keys");
+    assert!(payload.starts_with(r#"{"event":"set","key":"user:1","time":"#), "{}", payload);
+    let (_, payload) = next(&mut published).await;
+    assert!(payload.starts_with(r#"{"event":"set","key":"user:2","time":"#), "{}", payload);
+}
+
+#[tokio::test]
+async fn nats_bridge_channels() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (published_tx, mut published) = mpsc::unbounded_channel();
+    let (_deliver, deliver_rx) = mpsc::unbounded_channel();
+    tokio::spawn(fake_nats(listener, published_tx, deliver_rx));
+    let inbound = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let inbound_address = inbound.local_addr().unwrap();
+    let (inbound_tx, _inbound_published) = mpsc::unbounded_channel();
+    let (deliver, deliver_rx) = mpsc::unbounded_channel();
+    tokio::spawn(fake_nats(inbound, inbound_tx, deliver_rx));
+
+    let bridges = [
+        format!("nats://{};from=channel:orders;subject=orders", address),
+        format!("nats://user:secret@{};subject=inbound.*;to=channel:inbox", inbound_address),
+    ];
+    let config = Config { bridges: bridges.iter().map(|spec| Bridge::parse(spec).unwrap()).collect(), ..Config::default() };
+    let server = TestServer::with_config(config).await;
+
+    let mut client = server.client().await;
+    client.publish("orders", "order 1 placed").await.unwrap();
+    assert_eq!(next(&mut published).await, ("orders".to_string(), "order 1 placed".to_string()));
+
+    let mut inbox = server.client().await.subscribe(["inbox"]).await.unwrap();
+    // The subscription is confirmed once a PUBLISH on another connection reaches it
+    while client.publish("inbox", "ready").await.unwrap() == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    deliver.send("from NATS".to_string()).unwrap();
+    let mut payloads = vec![];
+    while payloads.last().map(|payload: &String| payload.as_str()) != Some("from NATS") {
+        let message = tokio::time::timeout(Duration::from_secs(10), inbox.next_message()).await.unwrap().unwrap();
+        assert_eq!(&message.channel[..], b"inbox");
+        payloads.push(String::from_utf8(message.payload.to_vec()).unwrap());
+    }
+}
+
+// A Kafka broker leading every partition it's asked about, reporting each record produced as (key, value)
+async fn fake_kafka(listener: TcpListener, produced: UnboundedSender<(Option<String>, String)>) {
+    let port = listener.local_addr().unwrap().port();
+    loop {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let produced = produced.clone();
+        tokio::spawn(async move {
+            while let Ok(size) = stream.read_i32().await {
+                let mut request = vec![0; size as usize];
+                stream.read_exact(&mut request).await.unwrap();
+                let mut request = Reader(&request);
+                let (api_key, version, correlation_id) = (request.i16(), request.i16(), request.i32());
+                request.string(); // Client id
+                let body = match (api_key, version) {
+                    (3, 4) => metadata(&mut request, port),
+                    (0, 3) => produce(&mut request, &produced),
+                    _ => panic!("unexpected request {} v{}", api_key, version),
+                };
+                let mut response = (body.len() as i32 + 4).to_be_bytes().to_vec();
+                response.extend_from_slice(&correlation_id.to_be_bytes());
+                response.extend_from_slice(&body);
+                stream.write_all(&response).await.unwrap();
+            }
+        });
+    }
+}
+
+fn metadata(request: &mut Reader, port: u16) -> Vec<u8> {
+    assert_eq!(request.i32(), 1);
+    let topic = request.string();
+    let mut body = vec![];
+    body.extend_from_slice(&0i32.to_be_bytes()); // Throttle time
+    body.extend_from_slice(&1i32.to_be_bytes());
+    body.extend_from_slice(&7i32.to_be_bytes()); // Node id
+    put_string(&mut body, "127.0.0.1");
+    body.extend_from_slice(&(port as i32).to_be_bytes());
+    body.extend_from_slice(&(-1i16).to_be_bytes()); // Rack
+    body.extend_from_slice(&(-1i16).to_be_bytes()); // Cluster id
+    body.extend_from_slice(&7i32.to_be_bytes()); // Controller
+    body.extend_from_slice(&1i32.to_be_bytes());
+    body.extend_from_slice(&0i16.to_be_bytes());
+    put_string(&mut body, &topic);
+    body.push(0); // Not internal
+    body.extend_from_slice(&2i32.to_be_bytes());
+    for partition in 0..2i32 {
+        body.extend_from_slice(&0i16.to_be_bytes());
+        body.extend_from_slice(&partition.to_be_bytes());
+        body.extend_from_slice(&7i32.to_be_bytes()); // Leader
+        for _ in 0..2 {
+            body.extend_from_slice(&1i32.to_be_bytes());
+            body.extend_from_slice(&7i32.to_be_bytes());
+        }
+    }
+    body
+}
+
+fn produce(request: &mut Reader, produced: &UnboundedSender<(Option<String>, String)>) -> Vec<u8> {
+    assert_eq!(request.i16(), -1); // Transactional id
+    assert_eq!(request.i16(), -1); // acks=all
+    request.i32();
+    assert_eq!(request.i32(), 1);
+    let topic = request.string();
+    assert_eq!(request.i32(), 1);
+    let partition = request.i32();
+    let size = request.i32() as usize;
+    let mut batch = Reader(request.take(size));
+    batch.take(8 + 4 + 4); // Base offset, length, leader epoch
+    assert_eq!(batch.take(1), [2]);
+    let crc = u32::from_be_bytes(batch.take(4).try_into().unwrap());
+    assert_eq!(crc, crc32c(batch.0));
+    batch.take(2 + 4 + 8 + 8 + 8 + 2 + 4);
+    for _ in 0..batch.i32() {
+        let len = batch.varint() as usize;
+        let mut record = Reader(batch.take(len));
+        record.take(1);
+        record.varint();
+        record.varint();
+        let key = match record.varint() {
+            -1 => None,
+            len => Some(String::from_utf8(record.take(len as usize).to_vec()).unwrap()),
+        };
+        let len = record.varint() as usize;
+        let value = String::from_utf8(record.take(len).to_vec()).unwrap();
+        assert_eq!(record.varint(), 0);
+        produced.send((key, value)).unwrap();
+    }
+    let mut body = vec![];
+    body.extend_from_slice(&1i32.to_be_bytes());
+    put_string(&mut body, &topic);
+    body.extend_from_slice(&1i32.to_be_bytes());
+    body.extend_from_slice(&partition.to_be_bytes());
+    body.extend_from_slice(&0i16.to_be_bytes());
+    body.extend_from_slice(&0i64.to_be_bytes());
+    body.extend_from_slice(&(-1i64).to_be_bytes());
+    body.extend_from_slice(&0i32.to_be_bytes()); // Throttle time
+    body
+}
+
+fn put_string(out: &mut Vec<u8>, value: &str) {
+    out.extend_from_slice(&(value.len() as i16).to_be_bytes());
+    out.extend_from_slice(value.as_bytes());
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> &'a [u8] {
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        taken
+    }
+
+    fn i16(&mut self) -> i16 {
+        i16::from_be_bytes(self.take(2).try_into().unwrap())
+    }
+
+    fn i32(&mut self) -> i32 {
+        i32::from_be_bytes(self.take(4).try_into().unwrap())
+    }
+
+    fn string(&mut self) -> String {
+        let len = self.i16() as usize;
+        String::from_utf8(self.take(len).to_vec()).unwrap()
+    }
+
+    fn varint(&mut self) -> i64 {
+        let (mut value, mut shift) = (0u64, 0);
+        loop {
+            let byte = self.take(1)[0];
+            value |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return (value >> 1) as i64 ^ -((value & 1) as i64);
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn kafka_bridge() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (produced_tx, mut produced) = mpsc::unbounded_channel();
+    tokio::spawn(fake_kafka(listener, produced_tx));
+
+    let bridges = [
+        format!("kafka://{};topic=keys;partition=1;events=set,del", address),
+        format!("kafka://{};from=channel:orders;topic=orders", address),
+    ];
+    let config = Config { bridges: bridges.iter().map(|spec| Bridge::parse(spec).unwrap()).collect(), ..Config::default() };
+    let server = TestServer::with_config(config).await;
+    let mut client = server.client().await;
+    client.set("a", "1").await.unwrap();
+    client.del(["a"]).await.unwrap();
+    client.publish("orders", "order 2 shipped").await.unwrap();
+
+    let mut records = vec![];
+    while records.len() < 3 {
+        records.push(tokio::time::timeout(Duration::from_secs(10), produced.recv()).await.expect("nothing was produced").unwrap());
+    }
+    records.sort_by_key(|(key, _)| key.is_some());
+    assert_eq!(records[0], (None, "order 2 shipped".to_string()));
+    assert_eq!(records[1].0.as_deref(), Some("a"));
+    assert!(records[1].1.starts_with(r#"{"event":"set","key":"a","#), "{}", records[1].1);
+    assert!(records[2].1.starts_with(r#"{"event":"del","key":"a","#), "{}", records[2].1);
+}
+
+#[test]
+fn bridge_specs() {
+    assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+    assert!(Bridge::parse("nats://localhost;subject=keys").is_ok());
+    assert!(Bridge::parse("kafka://localhost:9092;topic=keys;partition=3;match=user:*").is_ok());
+    assert!(Bridge::parse("nats://localhost").is_err());
+    assert!(Bridge::parse("kafka://localhost;subject=keys").is_err());
+    assert!(Bridge::parse("kafka://localhost;topic=in;to=channel:inbox").is_err());
+    assert!(Bridge::parse("nats://localhost;subject=in;to=channel:inbox;events=set").is_err());
+    assert!(Bridge::parse("nats://localhost;subject=in;from=channel:a;to=channel:b").is_err());
+    assert!(Bridge::parse("amqp://localhost;subject=keys").is_err());
+
+    let mut config = Config::default();
+    config.set("bridges", "nats://a:1;subject=x kafka://b;topic=y").unwrap();
+    assert_eq!(config.get("bridges").unwrap(), "nats://a:1;subject=x kafka://b;topic=y");
+}
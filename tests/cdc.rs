@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use bytes::Bytes;
+use redis_starter_rust::client::{Change, ChangeFeed};
+use redis_starter_rust::resp::Value;
+use support::{bulk, TestServer};
+
+mod support;
+
+async fn next(feed: &mut ChangeFeed) -> Change {
+    tokio::time::timeout(Duration::from_secs(10), feed.next_change()).await.expect("no change arrived").unwrap()
+}
+
+#[tokio::test]
+async fn cdc_snapshot_then_changes() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.set("a", "1").await.unwrap();
+    client.set_ex("b", "2", Duration::from_secs(100)).await.unwrap();
+    client.call(["HSET", "h", "f1", "v1", "f2", "v2"]).await.unwrap();
+
+    let mut feed = server.client().await.cdc(None).await.unwrap();
+    assert!(matches!(next(&mut feed).await, Change::Snapshot { .. }));
+    let mut snapshot = HashMap::new();
+    for _ in 0..3 {
+        let Change::Set { key, kind, value, expires_at, .. } = next(&mut feed).await else {
+            panic!("expected a set");
+        };
+        snapshot.insert(key, (kind, value, expires_at.is_some()));
+    }
+    assert!(matches!(next(&mut feed).await, Change::Synced { keys: 3, .. }));
+    assert_eq!(snapshot[&Bytes::from("a")], ("string".to_string(), bulk("1"), false));
+    assert_eq!(snapshot[&Bytes::from("b")], ("string".to_string(), bulk("2"), true));
+    assert_eq!(snapshot[&Bytes::from("h")], ("hash".to_string(), Value::Array(vec![bulk("f1"), bulk("v1"), bulk("f2"), bulk("v2")]), false));
+
+    client.set("c", "3").await.unwrap();
+    client.del(["a"]).await.unwrap();
+    let Change::Set { key, value, .. } = next(&mut feed).await else {
+        panic!("expected a set");
+    };
+    assert_eq!((key, value), (Bytes::from("c"), bulk("3")));
+    let Change::Removed { key, reason, .. } = next(&mut feed).await else {
+        panic!("expected a removal");
+    };
+    assert_eq!((key, reason.as_str()), (Bytes::from("a"), "del"));
+
+    // A deadline changed on its own comes as an expire, with no deadline once it's cleared
+    client.expire("c", Duration::from_secs(100)).await.unwrap();
+    let Change::Expire { key, expires_at, .. } = next(&mut feed).await else {
+        panic!("expected an expire");
+    };
+    assert_eq!(key, Bytes::from("c"));
+    assert!(expires_at.is_some());
+    client.call(["PERSIST", "c"]).await.unwrap();
+    assert!(matches!(next(&mut feed).await, Change::Expire { key, expires_at: None, .. } if key == "c"));
+
+    client.set_ex("t", "4", Duration::from_millis(500)).await.unwrap();
+    let Change::Set { key, expires_at, .. } = next(&mut feed).await else {
+        panic!("expected a set");
+    };
+    assert_eq!(key, Bytes::from("t"));
+    assert!(expires_at.is_some());
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    client.del(["t"]).await.unwrap();
+    let Change::Removed { key, reason, .. } = next(&mut feed).await else {
+        panic!("expected a removal");
+    };
+    assert_eq!((key, reason.as_str()), (Bytes::from("t"), "expired"));
+}
+
+#[tokio::test]
+async fn cdc_match_and_errors() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.set("user:1", "ada").await.unwrap();
+    client.set("order:1", "book").await.unwrap();
+
+    let mut feed = server.client().await.cdc(Some(b"user:*")).await.unwrap();
+    assert!(matches!(next(&mut feed).await, Change::Snapshot { .. }));
+    assert!(matches!(next(&mut feed).await, Change::Set { key, .. } if key == "user:1"));
+    assert!(matches!(next(&mut feed).await, Change::Synced { keys: 1, .. }));
+    client.set("order:2", "pen").await.unwrap();
+    client.set("user:2", "grace").await.unwrap();
+    assert!(matches!(next(&mut feed).await, Change::Set { key, .. } if key == "user:2"));
+
+    assert!(client.call(["CDC", "MATCH"]).await.is_err());
+    assert!(client.call(["CDC", "COUNT", "1"]).await.is_err());
+    client.call(["MULTI"]).await.unwrap();
+    let err = client.call(["CDC"]).await.unwrap_err();
+    assert!(err.to_string().contains("not allowed in transactions"), "{}", err);
+    client.call(["DISCARD"]).await.unwrap();
+    client.ping().await.unwrap();
+}
+
+#[tokio::test]
+async fn cdc_converges_under_writes() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    for i in 0..1000 {
+        client.set(format!("k{}", i), "old").await.unwrap();
+    }
+
+    // Writes carry on while the snapshot is taken and sent; applying everything the feed sends, in order,
+    // has to end up where the keyspace did
+    let writer = tokio::spawn(async move {
+        for i in 0..1000 {
+            client.set(format!("k{}", i), format!("new{}", i)).await.unwrap();
+            if i % 3 == 0 {
+                client.del([format!("k{}", i)]).await.unwrap();
+            }
+        }
+        client.set("done", "1").await.unwrap();
+    });
+    let mut feed = server.client().await.cdc(None).await.unwrap();
+    let mut mirror = HashMap::new();
+    loop {
+        match next(&mut feed).await {
+            Change::Set { key, value, .. } => {
+                mirror.insert(key, value);
+            },
+            Change::Removed { key, .. } => {
+                mirror.remove(&key);
+            },
+            Change::Snapshot { .. } | Change::Synced { .. } | Change::Expire { .. } => {},
+        }
+        if mirror.contains_key(&Bytes::from("done")) {
+            break;
+        }
+    }
+    writer.await.unwrap();
+    assert_eq!(mirror.len(), 1000 - 334 + 1);
+    for i in (0..1000).filter(|i| i % 3 != 0) {
+        assert_eq!(mirror[&Bytes::from(format!("k{}", i))], bulk(&format!("new{}", i)));
+    }
+}
@@ -0,0 +1,80 @@
+use std::time::Duration;
+use bytes::Bytes;
+use futures::StreamExt;
+use redis_starter_rust::client::{Message, Pipeline};
+use redis_starter_rust::error::Error;
+use redis_starter_rust::resp::Value;
+use support::TestServer;
+
+mod support;
+
+#[tokio::test]
+async fn typed_commands() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.ping().await.unwrap();
+    assert_eq!(client.get("missing").await.unwrap(), None);
+
+    client.set("key", "value").await.unwrap();
+    assert_eq!(client.get("key").await.unwrap(), Some(Bytes::from("value")));
+    assert_eq!(client.ttl("key").await.unwrap(), Some(None));
+    assert!(client.expire("key", Duration::from_secs(100)).await.unwrap());
+    assert!(client.ttl("key").await.unwrap().unwrap().is_some());
+    assert!(client.persist("key").await.unwrap());
+
+    client.set_ex("other", "value", Duration::from_secs(100)).await.unwrap();
+    assert_eq!(client.del(["key", "other", "missing"]).await.unwrap(), 2);
+    assert_eq!(client.ttl("key").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn error_replies() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    match client.call(["GET"]).await {
+        Err(Error::Reply(message)) => assert_eq!(message, "ERR wrong number of arguments for 'get' command"),
+        other => panic!("unexpected {:?}", other),
+    }
+    // The connection stays usable
+    client.ping().await.unwrap();
+}
+
+#[tokio::test]
+async fn pipelining() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    let pipeline = (0..100).fold(Pipeline::new(), |pipeline, i| pipeline.cmd(["SET".to_string(), format!("key{}", i), i.to_string()]));
+    let pipeline = pipeline.cmd(["GET", "key42"]).cmd(["NOSUCHCOMMAND"]);
+    assert_eq!(pipeline.len(), 102);
+
+    let replies = client.execute(pipeline).await.unwrap();
+    assert_eq!(replies.len(), 102);
+    assert!(replies[..100].iter().all(|reply| matches!(reply, Value::SimpleString(s) if s == "OK")));
+    assert!(matches!(&replies[100], Value::BulkString(b) if b == "42"));
+    assert!(matches!(&replies[101], Value::Error(_)));
+}
+
+#[tokio::test]
+async fn subscriptions() {
+    let server = TestServer::start().await;
+    let mut publisher = server.client().await;
+    let mut subscription = server.client().await.subscribe(["news"]).await.unwrap();
+    subscription.subscribe(["sports"]).await.unwrap();
+
+    // Wait until both subscriptions are registered
+    while publisher.publish("sports", "goal").await.unwrap() == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(publisher.publish("news", "hello").await.unwrap(), 1);
+    assert_eq!(subscription.next_message().await.unwrap(), Message { channel: "sports".into(), payload: "goal".into() });
+
+    let mut client = subscription.into_client().await.unwrap();
+    client.ping().await.unwrap();
+    assert_eq!(publisher.publish("news", "again").await.unwrap(), 0);
+
+    let mut stream = Box::pin(client.subscribe(["stream"]).await.unwrap().into_stream());
+    while publisher.publish("stream", "first").await.unwrap() == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(stream.next().await.unwrap().unwrap().payload, "first");
+}
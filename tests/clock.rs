@@ -0,0 +1,121 @@
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use redis_starter_rust::clock::ManualClock;
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::{Config, Engine};
+use support::TestServer;
+
+mod support;
+
+const START: u64 = 1_700_000_000_000;
+
+#[test]
+fn engine_expiry_follows_the_clock() {
+    let clock = Arc::new(ManualClock::new(START));
+    let engine = Engine::with_clock(clock.clone());
+    engine.set_ex("key", "value", Duration::from_secs(10));
+    assert_eq!(engine.ttl(b"key"), Some(Some(Duration::from_secs(10))));
+    assert_eq!(engine.expires_at(b"key"), Some(Some(UNIX_EPOCH + Duration::from_millis(START + 10_000))));
+
+    clock.advance(Duration::from_millis(9_999));
+    assert_eq!(engine.ttl(b"key"), Some(Some(Duration::from_millis(1))));
+    assert!(engine.get(b"key").is_some());
+
+    // The deadline itself is still live, one millisecond past it isn't
+    clock.advance(Duration::from_millis(1));
+    assert!(engine.exists(b"key"));
+    clock.advance(Duration::from_millis(1));
+    assert_eq!(engine.get(b"key"), None);
+    assert_eq!(engine.ttl(b"key"), None);
+
+    assert_eq!(engine.len(), 1);
+    assert_eq!(engine.purge_expired(), 1);
+    assert!(engine.is_empty());
+}
+
+#[test]
+fn expire_in_the_past_deletes() {
+    let clock = Arc::new(ManualClock::new(START));
+    let engine = Engine::with_clock(clock.clone());
+    engine.set("key", "value");
+    assert!(engine.expire_at(b"key", UNIX_EPOCH + Duration::from_millis(START - 1)));
+    assert!(!engine.exists(b"key"));
+    assert!(engine.is_empty());
+}
+
+#[tokio::test]
+async fn server_ttls_follow_the_clock() {
+    let clock = Arc::new(ManualClock::new(START));
+    let server = TestServer::with_clock(Config::default(), clock.clone()).await;
+    let mut client = server.client().await;
+
+    client.call(["SET", "key", "value", "EX", "100"]).await.unwrap();
+    assert_eq!(client.call(["TTL", "key"]).await.unwrap(), Value::Integer(100));
+    assert_eq!(client.call(["PEXPIRETIME", "key"]).await.unwrap(), Value::Integer(START as i64 + 100_000));
+
+    clock.advance(Duration::from_secs(40));
+    assert_eq!(client.call(["PTTL", "key"]).await.unwrap(), Value::Integer(60_000));
+    client.call(["EXPIRE", "key", "30", "GT"]).await.unwrap();
+    assert_eq!(client.call(["TTL", "key"]).await.unwrap(), Value::Integer(60));
+
+    clock.advance(Duration::from_secs(61));
+    assert_eq!(client.call(["GET", "key"]).await.unwrap(), Value::Null);
+    assert_eq!(client.call(["TTL", "key"]).await.unwrap(), Value::Integer(-2));
+}
+
+#[tokio::test]
+async fn idle_values_spill_to_the_cold_tier() {
+    let clock = Arc::new(ManualClock::new(START));
+    let config = Config { tiering_idle_time: 60, storage_shards: 1, ..Config::default() };
+    let server = TestServer::with_clock(config, clock.clone()).await;
+    let mut client = server.client().await;
+    let value = "x".repeat(1000);
+    client.set("cold", &value).await.unwrap();
+    client.set("warm", &value).await.unwrap();
+    client.set("small", "value").await.unwrap();
+
+    clock.advance(Duration::from_secs(30));
+    client.get("warm").await.unwrap();
+    clock.advance(Duration::from_secs(31));
+    assert_eq!(spilled(&mut client).await, 1);
+    assert!(server.dir().join("zenql.tier").is_file());
+
+    // Reading it brings it back
+    assert_eq!(client.get("cold").await.unwrap().as_deref(), Some(value.as_bytes()));
+    assert_eq!(memory_stat(&mut client, "keys.spilled").await, 0);
+    let Value::BulkString(info) = client.call(["INFO", "stats"]).await.unwrap() else {
+        panic!("INFO should reply with a bulk string");
+    };
+    let info = String::from_utf8_lossy(&info).into_owned();
+    assert!(!info.contains("tier_promotions:0\r\n"), "{}", info);
+
+    // A spilled value gone from the tier file fails the read, not the server
+    clock.advance(Duration::from_secs(61));
+    assert_eq!(spilled(&mut client).await, 2);
+    std::fs::OpenOptions::new().write(true).open(server.dir().join("zenql.tier")).unwrap().set_len(0).unwrap();
+    let error = client.get("cold").await.unwrap_err();
+    assert!(error.to_string().contains("couldn't be read back"), "{}", error);
+    assert_eq!(client.get("small").await.unwrap().as_deref(), Some(&b"value"[..]));
+}
+
+// Waits for values to be spilled, the shard being looked through about once a second, and returns how many
+async fn spilled(client: &mut redis_starter_rust::client::Client) -> i64 {
+    for _ in 0..50 {
+        let spilled = memory_stat(client, "keys.spilled").await;
+        if spilled > 0 {
+            return spilled;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    0
+}
+
+async fn memory_stat(client: &mut redis_starter_rust::client::Client, name: &str) -> i64 {
+    let Value::Array(stats) = client.call(["MEMORY", "STATS"]).await.unwrap() else {
+        panic!("MEMORY STATS should reply with a map");
+    };
+    match stats.chunks(2).find(|pair| pair[0] == Value::BulkString(name.as_bytes().to_vec().into())) {
+        Some([_, Value::Integer(n)]) => *n,
+        _ => panic!("no {} in MEMORY STATS", name),
+    }
+}
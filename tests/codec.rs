@@ -0,0 +1,73 @@
+use std::io::{self, IoSlice};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use bytes::Bytes;
+use redis_starter_rust::codec::{RespCodec, WriteBuffer};
+use redis_starter_rust::resp::Value;
+use tokio::io::AsyncWrite;
+
+// Collects what's written, taking at most `limit` bytes per write, and counts the writes
+struct Recorder {
+    written: Vec<u8>,
+    writes: usize,
+    limit: usize,
+}
+
+impl AsyncWrite for Recorder {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.poll_write_vectored(cx, &[IoSlice::new(buf)])
+    }
+
+    fn poll_write_vectored(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, bufs: &[IoSlice<'_>]) -> Poll<io::Result<usize>> {
+        self.writes += 1;
+        let mut taken = 0;
+        for buf in bufs {
+            let len = buf.len().min(self.limit - taken);
+            self.written.extend_from_slice(&buf[..len]);
+            taken += len;
+        }
+        Poll::Ready(Ok(taken))
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn large_array() -> Value {
+    let items = (0..20).map(|i| Value::BulkString(Bytes::from(vec![b'a' + i as u8; 20_000]))).collect();
+    Value::Array(vec![Value::Integer(1), Value::Array(items), Value::SimpleString("OK".to_string())])
+}
+
+#[tokio::test]
+async fn replies_go_out_in_vectored_writes() {
+    let mut codec = RespCodec::default();
+    let mut expected = WriteBuffer::new();
+    codec.encode(large_array(), &mut expected);
+    let expected: Vec<u8> = expected.take_chunks().concat();
+
+    // Every large value is its own chunk, all of them written at once
+    let mut buffer = WriteBuffer::new();
+    codec.encode(large_array(), &mut buffer);
+    let mut out = Recorder { written: vec![], writes: 0, limit: usize::MAX };
+    assert_eq!(buffer.write_to(&mut out).await.unwrap(), expected.len());
+    assert_eq!(out.writes, 1);
+    assert_eq!(out.written, expected);
+    assert!(buffer.is_empty());
+
+    // Short writes pick up mid-chunk
+    codec.encode(large_array(), &mut buffer);
+    let mut out = Recorder { written: vec![], writes: 0, limit: 7_000 };
+    assert_eq!(buffer.write_to(&mut out).await.unwrap(), expected.len());
+    assert_eq!(out.writes, expected.len().div_ceil(7_000));
+    assert_eq!(out.written, expected);
+    assert_eq!(buffer.len(), 0);
+}
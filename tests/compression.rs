@@ -0,0 +1,76 @@
+use bytes::Bytes;
+use redis_starter_rust::lz4;
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::Config;
+use support::TestServer;
+
+mod support;
+
+// Inputs with every kind of match: none, overlapping runs, long literals and matches, far offsets
+fn inputs() -> Vec<Vec<u8>> {
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    let mut random = |len: usize| -> Vec<u8> {
+        (0..len).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        }).collect()
+    };
+    let text: Vec<u8> = (0..5_000).flat_map(|i| format!("{{\"id\":{},\"name\":\"user{}\"}},", i, i % 37).into_bytes()).collect();
+    let mut far = random(70_000);
+    far.extend_from_within(..1_000);
+    vec![
+        vec![],
+        b"a".to_vec(),
+        b"abcdabcdabcdabcd".to_vec(),
+        vec![b'x'; 100_000],
+        random(300),
+        random(5_000).repeat(3),
+        text,
+        far,
+    ]
+}
+
+#[test]
+fn lz4_round_trips() {
+    for input in inputs() {
+        let compressed = lz4::compress(&input);
+        assert_eq!(lz4::decompress(&compressed, input.len()).as_deref(), Some(input.as_slice()), "length {}", input.len());
+    }
+    // Repetitive input actually shrinks
+    assert!(lz4::compress(&[b'x'; 100_000]).len() < 1_000);
+}
+
+#[test]
+fn lz4_rejects_bad_blocks() {
+    for input in inputs().into_iter().filter(|input| input.len() > 1) {
+        let compressed = lz4::compress(&input);
+        // Wrong length, truncation and corruption are caught rather than read out of bounds
+        assert_eq!(lz4::decompress(&compressed, input.len() - 1), None);
+        assert_eq!(lz4::decompress(&compressed[..compressed.len() - 1], input.len()), None);
+        for i in (0..compressed.len()).step_by(compressed.len() / 50 + 1) {
+            let mut corrupt = compressed.clone();
+            corrupt[i] ^= 0x5a;
+            let _ = lz4::decompress(&corrupt, input.len());
+        }
+    }
+}
+
+#[tokio::test]
+async fn compressed_values() {
+    let server = TestServer::with_config(Config { compression_threshold: 1024, ..Config::default() }).await;
+    let mut client = server.client().await;
+    let long = "abc".repeat(1_000);
+    client.set("long", &long).await.unwrap();
+    client.set("short", "abc").await.unwrap();
+    assert_eq!(client.get("long").await.unwrap(), Some(Bytes::from(long)));
+    assert_eq!(client.get("short").await.unwrap(), Some(Bytes::from("abc")));
+
+    let Value::Array(stats) = client.call(["MEMORY", "STATS"]).await.unwrap() else {
+        panic!("MEMORY STATS should reply with a map");
+    };
+    let field = |name: &str| stats.chunks(2).find(|pair| pair[0] == Value::BulkString(Bytes::copy_from_slice(name.as_bytes()))).map(|pair| pair[1].clone());
+    assert_eq!(field("keys.compressed"), Some(Value::Integer(1)));
+    assert!(matches!(field("compression.bytes-saved"), Some(Value::Integer(saved)) if saved > 2_000));
+}
@@ -0,0 +1,92 @@
+use bytes::Bytes;
+use redis_starter_rust::countmin::CountMinSketch;
+use redis_starter_rust::resp::Value;
+use support::{ints, scan_type, wal_storage, TestServer};
+
+mod support;
+
+#[tokio::test]
+async fn sketch_commands() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.call(["CMS.INITBYDIM", "a", "2000", "5"]).await.unwrap();
+    client.call(["CMS.INITBYPROB", "b", "0.001", "0.01"]).await.unwrap();
+    assert!(client.call(["CMS.INITBYDIM", "a", "10", "10"]).await.is_err());
+    assert_eq!(client.call(["CMS.INCRBY", "a", "x", "3", "y", "1"]).await.unwrap(), ints(&[3, 1]));
+    assert_eq!(client.call(["CMS.INCRBY", "a", "x", "2"]).await.unwrap(), ints(&[5]));
+    assert_eq!(client.call(["CMS.QUERY", "a", "x", "y", "z"]).await.unwrap(), ints(&[5, 1, 0]));
+    assert!(client.call(["CMS.INCRBY", "missing", "x", "1"]).await.is_err());
+    assert!(client.call(["CMS.QUERY", "missing", "x"]).await.is_err());
+    let info = client.call(["CMS.INFO", "b"]).await.unwrap();
+    // e / 0.001 wide, ln(100) deep
+    assert_eq!(info, Value::Array(vec![
+        Value::SimpleString("width".into()), Value::Integer(2719),
+        Value::SimpleString("depth".into()), Value::Integer(5),
+        Value::SimpleString("count".into()), Value::Integer(0),
+    ]));
+
+    client.call(["CMS.INITBYDIM", "c", "2000", "5"]).await.unwrap();
+    client.call(["CMS.INCRBY", "c", "x", "1", "z", "4"]).await.unwrap();
+    client.call(["CMS.INITBYDIM", "sum", "2000", "5"]).await.unwrap();
+    client.call(["CMS.MERGE", "sum", "2", "a", "c", "WEIGHTS", "1", "10"]).await.unwrap();
+    assert_eq!(client.call(["CMS.QUERY", "sum", "x", "y", "z"]).await.unwrap(), ints(&[15, 1, 40]));
+    // Merging replaces what the destination had
+    client.call(["CMS.MERGE", "sum", "1", "c"]).await.unwrap();
+    assert_eq!(client.call(["CMS.QUERY", "sum", "x", "y", "z"]).await.unwrap(), ints(&[1, 0, 4]));
+    let Value::Array(info) = client.call(["CMS.INFO", "sum"]).await.unwrap() else { panic!("CMS.INFO should reply with a map") };
+    assert_eq!(info[5], Value::Integer(5));
+
+    for args in [
+        &["CMS.MERGE", "sum", "2", "a", "b"][..], &["CMS.MERGE", "missing", "1", "a"], &["CMS.MERGE", "sum", "3", "a", "c"],
+        &["CMS.MERGE", "sum", "2", "a", "c", "WEIGHTS", "1"], &["CMS.INCRBY", "a", "x", "-1"], &["CMS.INCRBY", "a", "x", "1", "y"],
+        &["CMS.INITBYDIM", "bad", "0", "5"], &["CMS.INITBYPROB", "bad", "1.5", "0.1"], &["CMS.INFO", "missing"],
+    ] {
+        assert!(client.call(args).await.is_err(), "{:?}", args);
+    }
+    client.set("string", "value").await.unwrap();
+    assert!(client.call(["CMS.QUERY", "string", "a"]).await.unwrap_err().to_string().contains("WRONGTYPE"));
+    assert_eq!(scan_type(&mut client, "CMSk-TYPE").await, ["a", "b", "c", "sum"]);
+}
+
+// Counts never come in under the truth, and over it by no more than the sketch's bound, however skewed the stream
+#[test]
+fn counts_stay_within_bounds() {
+    let mut sketch = CountMinSketch::with_error(0.001, 0.01).unwrap();
+    let mut truth = vec![0u64; 5000];
+    for i in 0..100_000u64 {
+        // A few items are far more common than the rest
+        let item = if i % 3 == 0 { i % 10 } else { i.wrapping_mul(2_654_435_761) % 5000 };
+        truth[item as usize] += 1;
+        sketch.increment(format!("item:{}", item).as_bytes(), 1);
+    }
+    assert_eq!(sketch.count, 100_000);
+    let bound = (0.001 * 100_000f64) as u64;
+    let mut over = 0;
+    for (item, &count) in truth.iter().enumerate() {
+        let estimate = sketch.query(format!("item:{}", item).as_bytes());
+        assert!(estimate >= count, "item {}: {} under {}", item, estimate, count);
+        over += (estimate - count > bound) as usize;
+    }
+    assert!(over <= 50, "{} of 5000 counts over the bound", over);
+    assert_eq!(CountMinSketch::decode(&Bytes::from(sketch.encode())), Some(sketch.clone()));
+    assert!(CountMinSketch::decode(&Bytes::from(sketch.encode()[8..].to_vec())).is_none());
+}
+
+#[test]
+fn sketches_are_replayed() {
+    let wal = wal_storage("countmin");
+    let storage = wal.open();
+    let key = Bytes::from("s");
+    for (item, by) in [("a", 2), ("b", 1), ("a", 5)] {
+        storage.update::<CountMinSketch, _>(&key, |sketch| {
+            sketch.get_or_insert_with(|| CountMinSketch::new(100, 4).unwrap()).increment(item.as_bytes(), by);
+            ((), true)
+        }).unwrap();
+    }
+    storage.sync().unwrap();
+    drop(storage);
+
+    let storage = wal.open();
+    let found = storage.read::<CountMinSketch, _>(&key, |sketch| (sketch.count, sketch.query(b"a"), sketch.query(b"b"))).unwrap();
+    assert_eq!(found, Some((8, 7, 1)));
+}
@@ -0,0 +1,94 @@
+use bytes::Bytes;
+use redis_starter_rust::cuckoo::CuckooFilter;
+use redis_starter_rust::resp::Value;
+use support::{ints, scan_type, wal_storage, TestServer};
+
+mod support;
+
+#[tokio::test]
+async fn filter_commands() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    assert_eq!(client.call(["CF.ADD", "seen", "a"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(client.call(["CF.ADD", "seen", "a"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(client.call(["CF.ADDNX", "seen", "a"]).await.unwrap(), Value::Integer(0));
+    assert_eq!(client.call(["CF.ADDNX", "seen", "b"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(client.call(["CF.COUNT", "seen", "a"]).await.unwrap(), Value::Integer(2));
+    assert_eq!(client.call(["CF.MEXISTS", "seen", "a", "b", "c"]).await.unwrap(), ints(&[1, 1, 0]));
+
+    // Copies go one at a time
+    assert_eq!(client.call(["CF.DEL", "seen", "a"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(client.call(["CF.EXISTS", "seen", "a"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(client.call(["CF.DEL", "seen", "a"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(client.call(["CF.EXISTS", "seen", "a"]).await.unwrap(), Value::Integer(0));
+    assert_eq!(client.call(["CF.DEL", "seen", "a"]).await.unwrap(), Value::Integer(0));
+    assert_eq!(client.call(["CF.COUNT", "seen", "a"]).await.unwrap(), Value::Integer(0));
+    assert!(client.call(["CF.DEL", "missing", "a"]).await.is_err());
+    assert_eq!(client.call(["CF.EXISTS", "missing", "a"]).await.unwrap(), Value::Integer(0));
+    let Value::Array(info) = client.call(["CF.INFO", "seen"]).await.unwrap() else { panic!("CF.INFO should reply with a map") };
+    assert_eq!(info[6..10], [Value::SimpleString("Number of items inserted".into()), Value::Integer(1), Value::SimpleString("Number of items deleted".into()), Value::Integer(2)]);
+
+    client.call(["CF.RESERVE", "small", "4", "BUCKETSIZE", "1", "MAXITERATIONS", "5", "EXPANSION", "0"]).await.unwrap();
+    assert!(client.call(["CF.RESERVE", "small", "8"]).await.is_err());
+    let items: Vec<String> = (0..8).map(|i| format!("item:{}", i)).collect();
+    let Value::Array(replies) = client.call(["CF.INSERT", "small", "ITEMS"].into_iter().map(String::from).chain(items)).await.unwrap() else { panic!("CF.INSERT should reply with an array") };
+    assert!(replies.iter().any(|reply| matches!(reply, Value::Error(e) if e.contains("full"))), "{:?}", replies);
+    let added = replies.iter().filter(|&reply| reply == &Value::Integer(1)).count();
+    let Value::Array(info) = client.call(["CF.INFO", "small"]).await.unwrap() else { panic!("CF.INFO should reply with a map") };
+    assert_eq!((&info[1], &info[3], &info[7]), (&Value::Integer(4), &Value::Integer(4), &Value::Integer(added as i64)));
+
+    assert!(client.call(["CF.INSERT", "new", "NOCREATE", "ITEMS", "a"]).await.is_err());
+    assert_eq!(client.call(["CF.INSERTNX", "new", "CAPACITY", "100", "ITEMS", "a", "a", "b"]).await.unwrap(), ints(&[1, 0, 1]));
+
+    for args in [&["CF.RESERVE", "bad", "0"][..], &["CF.RESERVE", "bad", "10", "BUCKETSIZE", "0"], &["CF.RESERVE", "bad", "10", "EXPANSION"], &["CF.INSERT", "bad", "ITEMS"], &["CF.INFO", "missing"]] {
+        assert!(client.call(args).await.is_err(), "{:?}", args);
+    }
+    client.set("string", "value").await.unwrap();
+    assert!(client.call(["CF.ADD", "string", "a"]).await.unwrap_err().to_string().contains("WRONGTYPE"));
+    assert_eq!(scan_type(&mut client, "MBbloomCF").await, ["new", "seen", "small"]);
+}
+
+// A growing filter takes however many items come, finds them all and forgets each once deleted
+#[test]
+fn filters_grow_and_forget() {
+    let mut filter = CuckooFilter::new(64, 2, 20, 2).unwrap();
+    for i in 0..2000 {
+        filter.insert(format!("item:{}", i).as_bytes()).unwrap();
+    }
+    assert_eq!(filter.len(), 2000);
+    assert!(filter.filters() > 1);
+    assert!((0..2000).all(|i| filter.contains(format!("item:{}", i).as_bytes())));
+    let wrong = (0..20_000).filter(|i| filter.contains(format!("other:{}", i).as_bytes())).count();
+    assert!(wrong < 20_000 / 10, "{} false positives in 20000", wrong);
+    assert_eq!(CuckooFilter::decode(&Bytes::from(filter.encode())), Some(filter.clone()));
+    assert!(CuckooFilter::decode(&Bytes::from(filter.encode()[1..].to_vec())).is_none());
+
+    assert!((0..1000).all(|i| filter.delete(format!("item:{}", i).as_bytes())));
+    assert_eq!((filter.len(), filter.deleted), (1000, 1000));
+    assert!((1000..2000).all(|i| filter.contains(format!("item:{}", i).as_bytes())));
+    let remembered = (0..1000).filter(|i| filter.contains(format!("item:{}", i).as_bytes())).count();
+    assert!(remembered < 100, "{} of 1000 deleted items still found", remembered);
+}
+
+#[test]
+fn filters_are_replayed() {
+    let wal = wal_storage("cuckoo");
+    let storage = wal.open();
+    let key = Bytes::from("f");
+    for item in ["a", "b", "b"] {
+        storage.update::<CuckooFilter, _>(&key, |filter| {
+            filter.get_or_insert_with(|| CuckooFilter::new(8, 2, 20, 1).unwrap()).insert(item.as_bytes()).unwrap();
+            ((), true)
+        }).unwrap();
+    }
+    storage.update::<CuckooFilter, _>(&key, |filter| {
+        let deleted = filter.as_mut().unwrap().delete(b"a");
+        ((), deleted)
+    }).unwrap();
+    storage.sync().unwrap();
+    drop(storage);
+
+    let storage = wal.open();
+    let found = storage.read::<CuckooFilter, _>(&key, |filter| (filter.len(), filter.deleted, filter.count(b"b"), filter.contains(b"a"))).unwrap();
+    assert_eq!(found, Some((2, 1, 2, false)));
+}
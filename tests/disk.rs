@@ -0,0 +1,181 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+use std::time::Duration;
+use bytes::Bytes;
+use redis_starter_rust::crc64;
+use redis_starter_rust::disk::DATA_FILE;
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::storage::BackendKind;
+use redis_starter_rust::{Config, Engine};
+use support::TestServer;
+
+mod support;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("zenql-disk-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn reopen_keeps_data() {
+    let dir = temp_dir("reopen");
+    let engine = Engine::open(&dir).unwrap();
+    engine.set("string", "value");
+    engine.set("number", "42");
+    engine.set("empty", "");
+    engine.set_ex("expiring", "value", Duration::from_secs(100));
+    engine.set("deleted", "value");
+    engine.del(b"deleted");
+    drop(engine);
+
+    let engine = Engine::open(&dir).unwrap();
+    assert_eq!(engine.get(b"string"), Some(Bytes::from("value")));
+    assert_eq!(engine.get(b"number"), Some(Bytes::from("42")));
+    assert_eq!(engine.get(b"empty"), Some(Bytes::new()));
+    assert!(engine.ttl(b"expiring").unwrap().is_some());
+    assert_eq!(engine.get(b"deleted"), None);
+    assert_eq!(engine.len(), 4);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn partial_record_is_cut_off() {
+    let dir = temp_dir("partial");
+    let engine = Engine::open(&dir).unwrap();
+    engine.set("key", "value");
+    drop(engine);
+    let path = dir.join(DATA_FILE);
+    let size = std::fs::metadata(&path).unwrap().len();
+
+    // A set record that stops halfway through the key, as a crash mid-write leaves it
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[1, 10, 0, 0, 0, 0, 0, 0, 0, b'o', b't']).unwrap();
+    drop(file);
+    let engine = Engine::open(&dir).unwrap();
+    assert_eq!(engine.get(b"key"), Some(Bytes::from("value")));
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), size);
+
+    // Writes carry on after the last whole record
+    engine.set("other", "value");
+    drop(engine);
+    let engine = Engine::open(&dir).unwrap();
+    assert_eq!(engine.get(b"other"), Some(Bytes::from("value")));
+
+    // Anything else that doesn't parse is an error rather than data loss
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[9; 32]).unwrap();
+    drop(file);
+    drop(engine);
+    assert!(Engine::open(&dir).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn crc64_check_value() {
+    assert_eq!(crc64::checksum(b"123456789"), 0x995d_c9bb_df19_39fa);
+    assert_eq!(crc64::update(crc64::checksum(b"1234"), b"56789"), crc64::checksum(b"123456789"));
+}
+
+#[test]
+fn damaged_files_are_refused() {
+    let dir = temp_dir("damaged");
+    let engine = Engine::open(&dir).unwrap();
+    engine.set("first", "value");
+    engine.set("last", "value");
+    drop(engine);
+    let path = dir.join(DATA_FILE);
+    let size = std::fs::metadata(&path).unwrap().len();
+    let flip = |offset: u64| {
+        let file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        let mut byte = [0];
+        file.read_exact_at(&mut byte, offset).unwrap();
+        file.write_all_at(&[byte[0] ^ 0xff], offset).unwrap();
+    };
+
+    // A torn write to the last record is cut off like a partial one
+    flip(size - 2);
+    let engine = Engine::open(&dir).unwrap();
+    assert_eq!(engine.get(b"first"), Some(Bytes::from("value")));
+    assert_eq!(engine.get(b"last"), None);
+    engine.set("last", "value");
+    drop(engine);
+
+    // Damage before the end is an error that says where
+    flip(20);
+    let error = Engine::open(&dir).err().unwrap().to_string();
+    assert!(error.contains("checksum") && error.contains("at byte 8"), "{}", error);
+    flip(20);
+
+    // As is a file that isn't ours or is in another format version
+    flip(0);
+    assert!(Engine::open(&dir).err().unwrap().to_string().contains("header"));
+    flip(0);
+    flip(6);
+    assert!(Engine::open(&dir).err().unwrap().to_string().contains("format version"));
+    flip(6);
+    assert_eq!(Engine::open(&dir).unwrap().len(), 2);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn compaction_drops_overwritten_values() {
+    let dir = temp_dir("compact");
+    let engine = Engine::open(&dir).unwrap();
+    for round in 0..20 {
+        for i in 0..100 {
+            engine.set(format!("key{}", i), format!("value{}-{}", i, round));
+        }
+    }
+    engine.del(b"key0");
+    let path = dir.join(DATA_FILE);
+    let before = std::fs::metadata(&path).unwrap().len();
+    engine.compact().unwrap();
+    let after = std::fs::metadata(&path).unwrap().len();
+    assert!(after < before / 10, "{} bytes before, {} after", before, after);
+
+    // Values moved within the file read the same, before and after a reopen
+    assert_eq!(engine.get(b"key7"), Some(Bytes::from("value7-19")));
+    drop(engine);
+    let engine = Engine::open(&dir).unwrap();
+    assert_eq!(engine.get(b"key7"), Some(Bytes::from("value7-19")));
+    assert_eq!(engine.get(b"key0"), None);
+    assert_eq!(engine.len(), 99);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn server_on_disk() {
+    let config = Config { storage_backend: BackendKind::Disk, compression_threshold: 1024, ..Config::default() };
+    let server = TestServer::with_config(config).await;
+    let mut client = server.client().await;
+    client.set("small", "value").await.unwrap();
+    client.set("large", "x".repeat(10_000)).await.unwrap();
+    assert_eq!(client.call(["INCR", "counter"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(client.get("large").await.unwrap(), Some(Bytes::from("x".repeat(10_000))));
+    assert!(server.dir().join(DATA_FILE).is_file());
+
+    // The counter stays in memory
+    let Value::Array(stats) = client.call(["MEMORY", "STATS"]).await.unwrap() else {
+        panic!("MEMORY STATS should reply with a map");
+    };
+    let on_disk = stats.chunks(2).find(|pair| pair[0] == Value::BulkString(Bytes::from("keys.on-disk"))).map(|pair| pair[1].clone());
+    assert_eq!(on_disk, Some(Value::Integer(2)));
+}
+
+#[tokio::test]
+async fn unreadable_values_are_errors() {
+    let server = TestServer::with_config(Config { storage_backend: BackendKind::Disk, ..Config::default() }).await;
+    let mut client = server.client().await;
+    client.set("large", "x".repeat(10_000)).await.unwrap();
+    assert_eq!(client.call(["INCR", "counter"]).await.unwrap(), Value::Integer(1));
+
+    // A value gone from under the keyspace fails the read, not the server
+    OpenOptions::new().write(true).open(server.dir().join(DATA_FILE)).unwrap().set_len(16).unwrap();
+    let error = client.get("large").await.unwrap_err();
+    assert!(error.to_string().contains("couldn't be read back"), "{}", error);
+    assert_eq!(client.call(["GET", "counter"]).await.unwrap(), Value::BulkString(Bytes::from("1")));
+}
@@ -0,0 +1,202 @@
+use std::time::Duration;
+use bytes::Bytes;
+use redis_starter_rust::client::Pipeline;
+use redis_starter_rust::executor::ExecutionModel;
+use redis_starter_rust::ratelimit::RateLimitAction;
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::Config;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use support::TestServer;
+
+mod support;
+
+#[tokio::test]
+async fn set_and_get() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    assert_eq!(client.get("key").await.unwrap(), None);
+    client.set("key", "value").await.unwrap();
+    assert_eq!(client.get("key").await.unwrap(), Some(Bytes::from("value")));
+    client.set("key", "").await.unwrap();
+    assert_eq!(client.get("key").await.unwrap(), Some(Bytes::new()));
+
+    // Other connections see the same keyspace
+    let mut other = server.client().await;
+    assert_eq!(other.get("key").await.unwrap(), Some(Bytes::new()));
+}
+
+#[tokio::test]
+async fn expiry() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.set_ex("short", "value", Duration::from_millis(50)).await.unwrap();
+    client.set_ex("long", "value", Duration::from_secs(100)).await.unwrap();
+    assert!(client.get("short").await.unwrap().is_some());
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(client.get("short").await.unwrap(), None);
+    assert_eq!(client.ttl("short").await.unwrap(), None);
+    assert!(client.get("long").await.unwrap().is_some());
+
+    assert!(client.persist("long").await.unwrap());
+    assert_eq!(client.ttl("long").await.unwrap(), Some(None));
+}
+
+#[tokio::test]
+async fn counters() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    assert_eq!(client.call(["INCR", "counter"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(client.call(["INCRBY", "counter", "41"]).await.unwrap(), Value::Integer(42));
+    assert_eq!(client.call(["DECRBY", "counter", "50"]).await.unwrap(), Value::Integer(-8));
+    assert_eq!(client.get("counter").await.unwrap(), Some(Bytes::from("-8")));
+
+    // Values set as strings count as integers only when written canonically
+    client.set("counter", "12").await.unwrap();
+    assert_eq!(client.call(["DECR", "counter"]).await.unwrap(), Value::Integer(11));
+    client.set("counter", "012").await.unwrap();
+    assert!(client.call(["INCR", "counter"]).await.is_err());
+    assert_eq!(client.get("counter").await.unwrap(), Some(Bytes::from("012")));
+    client.set("counter", i64::MAX.to_string()).await.unwrap();
+    assert!(client.call(["INCR", "counter"]).await.is_err());
+}
+
+#[tokio::test]
+async fn pipelining() {
+    let server = TestServer::start().await;
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+
+    // Many commands in a single write, replies must come back complete and in order
+    let mut request = Vec::new();
+    let mut expected = Vec::new();
+    for i in 0..1000 {
+        let value = format!("value{}", i);
+        request.extend(format!("*3\r\n$3\r\nSET\r\n${}\r\nkey{}\r\n${}\r\n{}\r\n", format!("key{}", i).len(), i, value.len(), value).bytes());
+        request.extend(format!("*2\r\n$3\r\nGET\r\n${}\r\nkey{}\r\n", format!("key{}", i).len(), i).bytes());
+        expected.extend(b"+OK\r\n");
+        expected.extend(format!("${}\r\n{}\r\n", value.len(), value).bytes());
+    }
+    stream.write_all(&request).await.unwrap();
+
+    let mut response = vec![0; expected.len()];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(String::from_utf8_lossy(&response), String::from_utf8_lossy(&expected));
+}
+
+#[tokio::test]
+async fn request_limits() {
+    let config = Config { proto_max_multibulk_len: 3, client_query_buffer_limit: 1024 * 1024, proto_max_bulk_len: 4 * 1024 * 1024, ..Config::default() };
+    let server = TestServer::with_config(config).await;
+
+    // Too many arguments, or a bulk string announcing more than the query buffer may hold, get a protocol
+    // error and the connection closed
+    for request in [&b"*4\r\n$3\r\nDEL\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n"[..], b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$2000000\r\n"] {
+        let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+        stream.write_all(request).await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        assert!(response.starts_with(b"-ERR Protocol error"), "{}", String::from_utf8_lossy(&response));
+    }
+
+    let mut client = server.client().await;
+    client.set("key", "x".repeat(900_000)).await.unwrap();
+}
+
+#[tokio::test]
+async fn rate_limit() {
+    let config = Config { client_rate_limit: 5, client_rate_limit_action: RateLimitAction::Reject, ..Config::default() };
+    let server = TestServer::with_config(config).await;
+    let mut client = server.client().await;
+    // A second's worth goes through at once, the rest is refused until the bucket refills
+    for _ in 0..5 {
+        assert_eq!(client.call(["PING"]).await.unwrap(), Value::SimpleString("PONG".to_string()));
+    }
+    assert!(client.call(["PING"]).await.is_err());
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert_eq!(client.call(["PING"]).await.unwrap(), Value::SimpleString("PONG".to_string()));
+
+    // The limit is per client
+    let mut other = server.client().await;
+    assert_eq!(other.call(["PING"]).await.unwrap(), Value::SimpleString("PONG".to_string()));
+}
+
+#[tokio::test]
+async fn time_budget() {
+    let config = Config { command_time_budget: 5, ..Config::default() };
+    let server = TestServer::with_config(config).await;
+    let mut client = server.client().await;
+    // A 100M cell LCS table takes far longer than the budget
+    client.set("a", "ab".repeat(5000)).await.unwrap();
+    client.set("b", "ba".repeat(5000)).await.unwrap();
+    let error = client.call(["LCS", "a", "b", "LEN"]).await.unwrap_err();
+    assert!(error.to_string().contains("command-time-budget"), "{}", error);
+
+    // Small ones finish well within it
+    client.set("a", "ohmytext").await.unwrap();
+    client.set("b", "mynewtext").await.unwrap();
+    assert_eq!(client.call(["LCS", "a", "b"]).await.unwrap(), Value::BulkString(Bytes::from("mytext")));
+}
+
+#[tokio::test]
+async fn time_budget_for_scans() {
+    let config = Config { command_time_budget: 1, ..Config::default() };
+    let server = TestServer::with_config(config).await;
+    let mut client = server.client().await;
+    // Queries that look at every key take far longer than the budget over this many
+    for batch in 0..200 {
+        let pipeline = (0..1000).fold(Pipeline::new(), |pipeline, i| pipeline.cmd(["SET".to_string(), format!("k:{}:{}", batch, i), "v".to_string()]));
+        client.execute(pipeline).await.unwrap();
+    }
+    for command in [vec!["ZQL", "SELECT * FROM k:* WHERE v = 1"], vec!["TS.MRANGE", "-", "+", "FILTER", "a=b"]] {
+        let error = client.call(command.clone()).await.unwrap_err();
+        assert!(error.to_string().contains("command-time-budget"), "{:?}: {}", command, error);
+    }
+}
+
+#[tokio::test]
+async fn executor() {
+    let config = Config { command_execution: ExecutionModel::Executor, ..Config::default() };
+    let server = TestServer::with_config(config).await;
+
+    // Clients writing concurrently all go through the one executor thread
+    let mut writers = Vec::new();
+    for i in 0..8 {
+        let mut client = server.client().await;
+        writers.push(tokio::spawn(async move {
+            for j in 0..100 {
+                client.set(format!("key{}-{}", i, j), format!("value{}", j)).await.unwrap();
+            }
+        }));
+    }
+    for writer in writers {
+        writer.await.unwrap();
+    }
+    let mut client = server.client().await;
+    for i in 0..8 {
+        assert_eq!(client.get(format!("key{}-99", i)).await.unwrap(), Some(Bytes::from("value99")));
+    }
+
+    // Transactions and expiry run there too
+    assert_eq!(client.call(["MULTI"]).await.unwrap(), Value::SimpleString("OK".to_string()));
+    assert_eq!(client.call(["SET", "a", "1"]).await.unwrap(), Value::SimpleString("QUEUED".to_string()));
+    assert_eq!(client.call(["EXEC"]).await.unwrap(), Value::Array(vec![Value::SimpleString("OK".to_string())]));
+    client.set_ex("short", "value", Duration::from_millis(50)).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(client.get("short").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn shutdown() {
+    let server = TestServer::start().await;
+    let addr = server.addr();
+    let dir = server.dir().to_path_buf();
+    let mut client = server.client().await;
+    assert_eq!(client.call(["PING"]).await.unwrap(), Value::SimpleString("PONG".to_string()));
+    assert!(dir.is_dir());
+
+    server.shutdown().await;
+    assert!(client.call(["PING"]).await.is_err());
+    assert!(TcpStream::connect(addr).await.is_err());
+    assert!(!dir.exists());
+}
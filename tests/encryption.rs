@@ -0,0 +1,170 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use bytes::Bytes;
+use redis_starter_rust::clock::SystemClock;
+use redis_starter_rust::crypt::{Cipher, Key, KeySource, Keyring};
+use redis_starter_rust::disk::DATA_FILE;
+use redis_starter_rust::hasher::HasherKind;
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::storage::{BackendKind, Storage, DEFAULT_SHARDS};
+use redis_starter_rust::wal::WAL_DIR;
+use redis_starter_rust::Config;
+use support::TestServer;
+
+mod support;
+
+const KEY1: Key = [1; 32];
+const KEY2: Key = [2; 32];
+const SECRET: &str = "the secret value";
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("zenql-encryption-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn open_disk(dir: &Path, keys: Keyring) -> std::io::Result<Storage> {
+    Storage::open(BackendKind::Disk, DEFAULT_SHARDS, HasherKind::default(), dir, Arc::new(keys), Arc::new(SystemClock))
+}
+
+fn open_wal(dir: &Path, keys: Keyring) -> std::io::Result<Storage> {
+    Storage::open_wal(BackendKind::Sharded, DEFAULT_SHARDS, HasherKind::default(), dir, 1 << 20, Arc::new(keys), Arc::new(SystemClock))
+}
+
+// Whether any file under `dir` holds `text` in the clear
+fn contains(dir: &Path, text: &str) -> bool {
+    std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).any(|path| match path.is_dir() {
+        true => contains(&path, text),
+        false => std::fs::read(&path).unwrap().windows(text.len()).any(|window| window == text.as_bytes()),
+    })
+}
+
+#[test]
+fn chacha20_test_vector() {
+    // RFC 7539 section 2.4.2, its 96-bit nonce and 32-bit counter laid out as a 64-bit counter and nonce
+    let key: Key = std::array::from_fn(|i| i as u8);
+    let cipher = Cipher::new("test", &key, 0x4a000000);
+    let plain = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+    let mut text = plain.to_vec();
+    cipher.apply(64, &mut text);
+    assert_eq!(&text[..16], &[0x6e, 0x2e, 0x35, 0x9a, 0x25, 0x68, 0xf9, 0x80, 0x41, 0xba, 0x07, 0x28, 0xdd, 0x0d, 0x69, 0x81]);
+
+    // Decrypting part way through, across block boundaries, gives the same bytes
+    let mut tail = text[70..].to_vec();
+    cipher.apply(64 + 70, &mut tail);
+    assert_eq!(&tail[..], &plain[70..]);
+}
+
+#[test]
+fn disk_backend_is_encrypted() {
+    let dir = temp_dir("disk");
+    let storage = open_disk(&dir, Keyring::new("k1", KEY1)).unwrap();
+    storage.set(Bytes::from("key"), Bytes::from(SECRET), None);
+    storage.set(Bytes::from("other"), Bytes::from("x".repeat(1000)), None);
+    drop(storage);
+    assert!(!contains(&dir, SECRET));
+
+    let storage = open_disk(&dir, Keyring::new("k1", KEY1)).unwrap();
+    assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from(SECRET)));
+    assert_eq!(storage.get(b"other").unwrap(), Some(Bytes::from("x".repeat(1000))));
+    drop(storage);
+
+    let error = open_disk(&dir, Keyring::default()).err().unwrap().to_string();
+    assert!(error.contains("encrypted with the key 'k1', which isn't configured"), "{}", error);
+    let error = open_disk(&dir, Keyring::new("k1", KEY2)).err().unwrap().to_string();
+    assert!(error.contains("encrypted with a key other than the one configured as 'k1'"), "{}", error);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn keys_rotate_on_startup() {
+    let dir = temp_dir("rotate");
+    let storage = open_wal(&dir, Keyring::default()).unwrap();
+    storage.set(Bytes::from("key"), Bytes::from(SECRET), None);
+    storage.sync().unwrap();
+    drop(storage);
+    assert!(contains(&dir.join(WAL_DIR), SECRET));
+
+    // Turning encryption on rewrites what was in the clear
+    let storage = open_wal(&dir, Keyring::new("k1", KEY1)).unwrap();
+    storage.set(Bytes::from("later"), Bytes::from("value"), None);
+    storage.sync().unwrap();
+    drop(storage);
+    assert!(!contains(&dir, SECRET));
+
+    // So does a new key, reading with the old one, after which the old one isn't needed
+    let storage = open_wal(&dir, Keyring::new("k2", KEY2).with_old_key("k1", KEY1)).unwrap();
+    assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from(SECRET)));
+    drop(storage);
+    let storage = open_wal(&dir, Keyring::new("k2", KEY2)).unwrap();
+    assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from(SECRET)));
+    assert_eq!(storage.get(b"later").unwrap(), Some(Bytes::from("value")));
+    drop(storage);
+
+    // And the disk backend's log is rewritten in the clear once encryption is off
+    let storage = open_disk(&dir, Keyring::new("k2", KEY2)).unwrap();
+    storage.set(Bytes::from("key"), Bytes::from(SECRET), None);
+    drop(storage);
+    let storage = open_disk(&dir, Keyring::default().with_old_key("k2", KEY2)).unwrap();
+    assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from(SECRET)));
+    drop(storage);
+    assert!(std::fs::read(dir.join(DATA_FILE)).unwrap().windows(SECRET.len()).any(|window| window == SECRET.as_bytes()));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn snapshots_are_encrypted() {
+    let hex: String = KEY1.iter().map(|byte| format!("{:02x}", byte)).collect();
+    // The key comes from a command given the key's id, as a KMS would be asked
+    let command = format!("f() {{ test \"$1\" = k1 && echo {}; }}; f", hex);
+    let config = Config { encryption_key_id: "k1".to_string(), encryption_key_command: Some(command), ..Config::default() };
+    let server = TestServer::with_config(config).await;
+    let mut client = server.client().await;
+    client.set("key", SECRET).await.unwrap();
+    assert_eq!(client.call(["SNAPSHOT", "EXPORT", "backup.snap"]).await.unwrap(), Value::Integer(1));
+    assert!(!contains(server.dir(), SECRET));
+
+    client.del(["key"]).await.unwrap();
+    assert_eq!(client.call(["SNAPSHOT", "IMPORT", "backup.snap"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(client.get("key").await.unwrap(), Some(Bytes::from(SECRET)));
+
+    // Without the key it's refused
+    let path = server.dir().join("backup.snap");
+    let other = TestServer::start().await;
+    std::fs::copy(&path, other.dir().join("backup.snap")).unwrap();
+    let error = other.client().await.call(["SNAPSHOT", "IMPORT", "backup.snap"]).await.unwrap_err().to_string();
+    assert!(error.contains("isn't configured"), "{}", error);
+}
+
+#[test]
+fn key_options() {
+    let mut config = Config::default();
+    assert!(config.set("encryption-key", "abc").is_err());
+    assert!(config.set("encryption-key", "env:").is_err());
+    config.set("encryption-key", &"ab".repeat(32)).unwrap();
+    assert!(config.set("encryption-key-id", "has space").is_err());
+    assert!(config.set("encryption-old-keys", "k1").is_err());
+    config.set("encryption-old-keys", &format!("k1={} k2=env:OLD_KEY", "cd".repeat(32))).unwrap();
+    assert_eq!(config.get("encryption-old-keys").unwrap(), format!("k1={} k2=env:OLD_KEY", "cd".repeat(32)));
+
+    // Nor is a key shown once set, not even in what a reload changed
+    assert_eq!(config.get_redacted("encryption-key").unwrap(), "(redacted)");
+    assert_eq!(config.get_redacted("encryption-old-keys").unwrap(), "(redacted)");
+    assert_eq!(config.get_redacted("encryption-key-id").unwrap(), "default");
+    let path = temp_dir("reload").join("zenql.conf");
+    std::fs::write(&path, format!("encryption-key {}\nrequirepass secret\n", "ef".repeat(32))).unwrap();
+    config.config_file = Some(path);
+    let (_, changes) = config.reload().unwrap();
+    let changed: Vec<_> = changes.iter().map(|change| (change.name, change.old.as_str(), change.new.as_str(), change.secret)).collect();
+    assert_eq!(changed, vec![
+        ("requirepass", "(redacted)", "(redacted)", true),
+        ("encryption-key", "(redacted)", "(redacted)", true),
+        ("encryption-old-keys", "(redacted)", "(redacted)", true),
+    ]);
+
+    // The environment variable is only read on startup
+    let config = Config { encryption_key: Some(KeySource::parse("env:ZENQL_TEST_MISSING_KEY").unwrap()), ..Config::default() };
+    let error = Keyring::from_config(&config).err().unwrap().to_string();
+    assert!(error.contains("ZENQL_TEST_MISSING_KEY isn't set"), "{}", error);
+}
@@ -0,0 +1,119 @@
+use bytes::Bytes;
+use redis_starter_rust::graph::{Direction, Graph};
+use redis_starter_rust::resp::Value;
+use support::{bulk, scan_type, wal_storage, TestServer};
+
+mod support;
+
+fn ids(ids: &[&str]) -> Value {
+    Value::Array(ids.iter().map(|id| bulk(id)).collect())
+}
+
+fn walk(found: &[(&str, i64)]) -> Value {
+    Value::Array(found.iter().map(|&(id, depth)| Value::Array(vec![bulk(id), Value::Integer(depth)])).collect())
+}
+
+#[tokio::test]
+async fn graph_commands() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    assert_eq!(client.call(["GRAPH.ADDNODE", "g", "alice", "age", "30"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(client.call(["GRAPH.ADDNODE", "g", "alice", "city", "Paris"]).await.unwrap(), Value::Integer(0));
+    assert_eq!(client.call(["GRAPH.NODE", "g", "alice"]).await.unwrap(), ids(&["age", "30", "city", "Paris"]));
+    assert!(client.call(["GRAPH.ADDNODE", "g", "bob", "odd"]).await.is_err());
+
+    // Edges add the nodes they join
+    assert_eq!(client.call(["GRAPH.ADDEDGE", "g", "alice", "bob", "follows", "since", "2020"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(client.call(["GRAPH.ADDEDGE", "g", "alice", "bob", "follows"]).await.unwrap(), Value::Integer(0));
+    assert_eq!(client.call(["GRAPH.ADDEDGE", "g", "alice", "bob", "blocks"]).await.unwrap(), Value::Integer(1));
+    for (from, to) in [("bob", "carol"), ("carol", "dave"), ("dave", "erin"), ("carol", "alice")] {
+        client.call(["GRAPH.ADDEDGE", "g", from, to, "follows"]).await.unwrap();
+    }
+    assert_eq!(client.call(["GRAPH.EDGE", "g", "alice", "bob", "follows"]).await.unwrap(), ids(&["since", "2020"]));
+    assert_eq!(client.call(["GRAPH.EDGE", "g", "bob", "alice", "follows"]).await.unwrap(), Value::Null);
+    assert_eq!(client.call(["GRAPH.NODE", "g", "bob"]).await.unwrap(), ids(&[]));
+    assert_eq!(client.call(["GRAPH.NODE", "g", "zed"]).await.unwrap(), Value::Null);
+    assert_eq!(client.call(["GRAPH.INFO", "g"]).await.unwrap(), Value::Array(vec![
+        Value::SimpleString("nodes".into()), Value::Integer(5), Value::SimpleString("edges".into()), Value::Integer(6),
+    ]));
+
+    assert_eq!(client.call(["GRAPH.NEIGHBORS", "g", "alice"]).await.unwrap(), ids(&["bob"]));
+    assert_eq!(client.call(["GRAPH.NEIGHBORS", "g", "alice", "DIRECTION", "in"]).await.unwrap(), ids(&["carol"]));
+    assert_eq!(client.call(["GRAPH.NEIGHBORS", "g", "carol", "DIRECTION", "BOTH"]).await.unwrap(), ids(&["alice", "bob", "dave"]));
+    assert_eq!(client.call(["GRAPH.NEIGHBORS", "g", "alice", "LABEL", "blocks"]).await.unwrap(), ids(&["bob"]));
+    assert_eq!(client.call(["GRAPH.NEIGHBORS", "g", "bob", "LABEL", "blocks"]).await.unwrap(), ids(&[]));
+    assert_eq!(client.call(["GRAPH.NEIGHBORS", "g", "zed"]).await.unwrap(), ids(&[]));
+    assert_eq!(client.call(["GRAPH.NEIGHBORS", "missing", "alice"]).await.unwrap(), ids(&[]));
+    assert!(client.call(["GRAPH.NEIGHBORS", "g", "alice", "DIRECTION", "up"]).await.is_err());
+    assert!(client.call(["GRAPH.NEIGHBORS", "g", "alice", "DEPTH", "2"]).await.is_err());
+
+    // Breadth first, each node once, at the depth it's first reached
+    assert_eq!(client.call(["GRAPH.TRAVERSE", "g", "alice"]).await.unwrap(), walk(&[("bob", 1), ("carol", 2), ("dave", 3)]));
+    assert_eq!(client.call(["GRAPH.TRAVERSE", "g", "alice", "DEPTH", "10"]).await.unwrap(), walk(&[("bob", 1), ("carol", 2), ("dave", 3), ("erin", 4)]));
+    assert_eq!(client.call(["GRAPH.TRAVERSE", "g", "alice", "DEPTH", "10", "LIMIT", "2"]).await.unwrap(), walk(&[("bob", 1), ("carol", 2)]));
+    assert_eq!(client.call(["GRAPH.TRAVERSE", "g", "alice", "DEPTH", "0"]).await.unwrap(), walk(&[]));
+    assert_eq!(client.call(["GRAPH.TRAVERSE", "g", "erin", "DIRECTION", "in", "DEPTH", "2"]).await.unwrap(), walk(&[("dave", 1), ("carol", 2)]));
+    assert_eq!(client.call(["GRAPH.TRAVERSE", "g", "dave", "DIRECTION", "both", "DEPTH", "1"]).await.unwrap(), walk(&[("carol", 1), ("erin", 1)]));
+    assert_eq!(client.call(["GRAPH.TRAVERSE", "g", "alice", "LABEL", "blocks"]).await.unwrap(), walk(&[("bob", 1)]));
+    assert!(client.call(["GRAPH.TRAVERSE", "g", "alice", "DEPTH", "-1"]).await.is_err());
+    assert!(client.call(["GRAPH.TRAVERSE", "g", "alice", "DEPTH"]).await.is_err());
+
+    // Removing a node removes its edges, and the last node the key
+    assert_eq!(client.call(["GRAPH.DELEDGE", "g", "alice", "bob", "blocks"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(client.call(["GRAPH.DELEDGE", "g", "alice", "bob", "blocks"]).await.unwrap(), Value::Integer(0));
+    assert_eq!(client.call(["GRAPH.DELNODE", "g", "carol"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(client.call(["GRAPH.DELNODE", "g", "carol"]).await.unwrap(), Value::Integer(0));
+    assert_eq!(client.call(["GRAPH.NEIGHBORS", "g", "bob", "DIRECTION", "both"]).await.unwrap(), ids(&["alice"]));
+    assert_eq!(client.call(["GRAPH.INFO", "g"]).await.unwrap(), Value::Array(vec![
+        Value::SimpleString("nodes".into()), Value::Integer(4), Value::SimpleString("edges".into()), Value::Integer(2),
+    ]));
+    for node in ["alice", "bob", "dave", "erin"] {
+        client.call(["GRAPH.DELNODE", "g", node]).await.unwrap();
+    }
+    assert_eq!(client.call(["GET", "g"]).await.unwrap(), Value::Null);
+    assert!(client.call(["GRAPH.INFO", "g"]).await.is_err());
+
+    client.set("s", "v").await.unwrap();
+    assert!(client.call(["GRAPH.ADDNODE", "s", "n"]).await.is_err());
+    client.call(["GRAPH.ADDNODE", "g", "n"]).await.unwrap();
+    assert!(client.call(["GET", "g"]).await.is_err());
+    assert_eq!(scan_type(&mut client, "graph").await, ["g"]);
+}
+
+#[test]
+fn graphs_encode_and_loops() {
+    let (a, b, label) = (Bytes::from("a"), Bytes::from("b"), Bytes::from("to"));
+    let mut graph = Graph::default();
+    graph.add_node(&a, [(Bytes::from("k"), Bytes::from("v"))]);
+    graph.add_edge(&a, &a, &label, []);
+    graph.add_edge(&a, &b, &label, [(Bytes::from("w"), Bytes::from("2"))]);
+    graph.add_edge(&b, &a, &label, []);
+    assert_eq!((graph.nodes(), graph.edges()), (2, 3));
+    assert_eq!(graph.neighbors(b"a", Direction::Both, None).unwrap(), [&a, &b]);
+    assert_eq!(Graph::decode(&Bytes::from(graph.encode())), Some(graph.clone()));
+    assert!(Graph::decode(&Bytes::from(graph.encode()[..20].to_vec())).is_none());
+
+    assert_eq!(graph.remove_node(b"a"), Some(3));
+    assert_eq!((graph.nodes(), graph.edges()), (1, 0));
+    assert_eq!(graph.neighbors(b"b", Direction::Both, None).unwrap(), Vec::<&Bytes>::new());
+    assert_eq!(graph.remove_node(b"a"), None);
+}
+
+#[test]
+fn graphs_are_replayed() {
+    let wal = wal_storage("graph");
+    let storage = wal.open();
+    let key = Bytes::from("g");
+    for (from, to) in [("a", "b"), ("b", "c")] {
+        storage.update::<Graph, _>(&key, |graph| {
+            graph.get_or_insert_default().add_edge(&Bytes::from(from), &Bytes::from(to), &Bytes::from("e"), []);
+            ((), true)
+        }).unwrap();
+    }
+    storage.sync().unwrap();
+    drop(storage);
+
+    let storage = wal.open();
+    let found = storage.read::<Graph, _>(&key, |graph| graph.traverse(b"a", Direction::Out, None, 5, usize::MAX, || Ok::<_, ()>(())).unwrap()).unwrap();
+    assert_eq!(found, Some(Some(vec![(Bytes::from("b"), 1), (Bytes::from("c"), 2)])));
+}
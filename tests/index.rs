@@ -0,0 +1,135 @@
+use std::sync::Arc;
+use std::time::Duration;
+use bytes::Bytes;
+use redis_starter_rust::client::Client;
+use redis_starter_rust::clock::ManualClock;
+use redis_starter_rust::index::{IndexDef, IndexKind, TermRange};
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::storage::Hash;
+use redis_starter_rust::Config;
+use support::{bulk, wal_storage, TestServer};
+
+mod support;
+
+fn keys(names: &[&str]) -> Value {
+    Value::Array(names.iter().map(|name| bulk(name)).collect())
+}
+
+async fn query(client: &mut Client, args: &[&str]) -> Value {
+    client.call(["IDX.QUERY"].iter().chain(args)).await.unwrap()
+}
+
+#[tokio::test]
+async fn hash_commands() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    assert_eq!(client.call(["HSET", "h", "b", "2", "a", "1"]).await.unwrap(), Value::Integer(2));
+    assert_eq!(client.call(["HSET", "h", "a", "one", "c", "3"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(client.call(["HGET", "h", "a"]).await.unwrap(), bulk("one"));
+    assert_eq!(client.call(["HGET", "h", "z"]).await.unwrap(), Value::Null);
+    assert_eq!(client.call(["HMGET", "h", "c", "z"]).await.unwrap(), Value::Array(vec![bulk("3"), Value::Null]));
+    assert_eq!(client.call(["HGETALL", "h"]).await.unwrap(), keys(&["a", "one", "b", "2", "c", "3"]));
+    assert_eq!(client.call(["HLEN", "h"]).await.unwrap(), Value::Integer(3));
+    assert_eq!(client.call(["HEXISTS", "h", "b"]).await.unwrap(), Value::Integer(1));
+    assert!(client.call(["HSET", "h", "odd"]).await.is_err());
+    assert!(client.call(["HSET", "h", "a", "1", "odd"]).await.is_err());
+
+    assert_eq!(client.call(["HDEL", "h", "a", "b", "z"]).await.unwrap(), Value::Integer(2));
+    assert_eq!(client.call(["HDEL", "h", "c"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(client.call(["DEL", "h"]).await.unwrap(), Value::Integer(0));
+    assert_eq!(client.call(["HGETALL", "h"]).await.unwrap(), Value::Array(vec![]));
+
+    client.set("string", "value").await.unwrap();
+    client.call(["HSET", "hash", "f", "v"]).await.unwrap();
+    for args in [&["HSET", "string", "f", "v"][..], &["HGET", "string", "f"], &["GET", "hash"], &["INCR", "hash"], &["JSON.GET", "hash"]] {
+        let error = client.call(args).await.unwrap_err().to_string();
+        assert!(error.contains("WRONGTYPE"), "{:?}: {}", args, error);
+    }
+}
+
+#[tokio::test]
+async fn indexes_follow_writes() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.call(["HSET", "user:1", "email", "a@x", "age", "30"]).await.unwrap();
+    client.call(["HSET", "user:2", "email", "b@x", "age", "25"]).await.unwrap();
+    client.call(["HSET", "other:1", "email", "a@x"]).await.unwrap();
+    client.call(["IDX.CREATE", "users", "PREFIX", "user:", "ON", "field:email"]).await.unwrap();
+    client.call(["IDX.CREATE", "ages", "PREFIX", "user:", "ON", "field:age", "NUMERIC"]).await.unwrap();
+    assert!(client.call(["IDX.CREATE", "users", "ON", "field:email"]).await.is_err());
+    assert!(client.call(["IDX.CREATE", "bad", "ON", "email"]).await.is_err());
+
+    // Built from the hashes already there, then kept up to date by every kind of write
+    assert_eq!(query(&mut client, &["users", "EQ", "a@x"]).await, keys(&["user:1"]));
+    client.call(["HSET", "user:3", "email", "a@x", "age", "100"]).await.unwrap();
+    assert_eq!(query(&mut client, &["users", "EQ", "a@x"]).await, keys(&["user:1", "user:3"]));
+    client.call(["HSET", "user:1", "email", "c@x"]).await.unwrap();
+    assert_eq!(query(&mut client, &["users", "EQ", "a@x"]).await, keys(&["user:3"]));
+    assert_eq!(query(&mut client, &["users", "EQ", "c@x"]).await, keys(&["user:1"]));
+    client.call(["HDEL", "user:3", "email"]).await.unwrap();
+    assert_eq!(query(&mut client, &["users", "EQ", "a@x"]).await, keys(&[]));
+
+    // Numbers compare as numbers, not as text
+    assert_eq!(query(&mut client, &["ages", "RANGE", "-", "+"]).await, keys(&["user:2", "user:1", "user:3"]));
+    assert_eq!(query(&mut client, &["ages", "RANGE", "25", "(100"]).await, keys(&["user:2", "user:1"]));
+    assert_eq!(query(&mut client, &["ages", "RANGE", "(25", "+", "LIMIT", "1", "5"]).await, keys(&["user:3"]));
+    assert_eq!(query(&mut client, &["ages", "EQ", "30.0"]).await, keys(&["user:1"]));
+    assert!(client.call(["IDX.QUERY", "ages", "EQ", "thirty"]).await.is_err());
+    assert_eq!(query(&mut client, &["users", "RANGE", "b", "[c@x"]).await, keys(&["user:2", "user:1"]));
+
+    client.call(["DEL", "user:2"]).await.unwrap();
+    client.set("user:1", "now a string").await.unwrap();
+    assert_eq!(query(&mut client, &["ages", "RANGE", "-", "+"]).await, keys(&["user:3"]));
+    let info = client.call(["IDX.INFO", "users"]).await.unwrap();
+    assert_eq!(info, Value::Array(vec![
+        bulk("name"), bulk("users"), bulk("prefix"), bulk("user:"), bulk("field"), bulk("email"), bulk("type"), bulk("text"), bulk("keys"), Value::Integer(0),
+    ]));
+
+    assert_eq!(client.call(["IDX.LIST"]).await.unwrap(), keys(&["users", "ages"]));
+    client.call(["IDX.DROP", "users"]).await.unwrap();
+    assert!(client.call(["IDX.QUERY", "users", "EQ", "a@x"]).await.is_err());
+    assert!(client.call(["IDX.DROP", "users"]).await.is_err());
+    assert_eq!(client.call(["IDX.LIST"]).await.unwrap(), keys(&["ages"]));
+}
+
+#[tokio::test]
+async fn expired_keys_leave_indexes() {
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let server = TestServer::with_clock(Config::default(), clock.clone()).await;
+    let mut client = server.client().await;
+    client.call(["IDX.CREATE", "colors", "ON", "field:color"]).await.unwrap();
+    client.call(["HSET", "a", "color", "red"]).await.unwrap();
+    client.call(["HSET", "b", "color", "red"]).await.unwrap();
+    client.expire("a", Duration::from_secs(10)).await.unwrap();
+    assert_eq!(query(&mut client, &["colors", "EQ", "red"]).await, keys(&["a", "b"]));
+    clock.advance(Duration::from_secs(11));
+    assert_eq!(query(&mut client, &["colors", "EQ", "red"]).await, keys(&["b"]));
+}
+
+#[test]
+fn hashes_are_replayed_and_indexed() {
+    let wal = wal_storage("hash");
+    let storage = wal.open();
+    for (key, score) in [("p:1", "-1.5"), ("p:2", "7"), ("p:3", "-20"), ("p:4", "not a number")] {
+        storage.update::<Hash, _>(&Bytes::from(key), |hash| {
+            let hash = hash.get_or_insert_with(Default::default);
+            hash.insert(Bytes::from("score"), Bytes::from(score));
+            hash.insert(Bytes::from("empty"), Bytes::new());
+            ((), true)
+        }).unwrap();
+    }
+    storage.sync().unwrap();
+    drop(storage);
+
+    let storage = wal.open();
+    let fields = storage.read::<Hash, _>(b"p:1", |hash| hash.len()).unwrap();
+    assert_eq!(fields, Some(2));
+    let def = IndexDef { name: "scores".into(), prefix: Bytes::from("p:"), field: Bytes::from("score"), kind: IndexKind::Numeric };
+    assert!(storage.create_index(def.clone()));
+    let all = TermRange { min: std::ops::Bound::Unbounded, max: std::ops::Bound::Unbounded };
+    let found = storage.query_index("scores", &all, 0, usize::MAX).unwrap();
+    assert_eq!(found, vec![Bytes::from("p:3"), Bytes::from("p:1"), Bytes::from("p:2")]);
+    assert_eq!(storage.indexes()[0].1, 3);
+    let exact = TermRange::exact(def.encode(b"-1.5").unwrap());
+    assert_eq!(storage.query_index("scores", &exact, 0, 10).unwrap(), vec![Bytes::from("p:1")]);
+}
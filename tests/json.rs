@@ -0,0 +1,201 @@
+use bytes::Bytes;
+use redis_starter_rust::client::Client;
+use redis_starter_rust::json::{Format, Json, Path};
+use redis_starter_rust::resp::Value;
+use support::{wal_storage, TestServer};
+
+mod support;
+
+async fn json_get(client: &mut Client, args: &[&str]) -> Option<String> {
+    match client.call(["JSON.GET"].iter().chain(args)).await.unwrap() {
+        Value::BulkString(text) => Some(String::from_utf8(text.to_vec()).unwrap()),
+        Value::Null => None,
+        other => panic!("unexpected reply {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn set_get_and_delete_by_path() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    let doc = r#"{"name":"zen","tags":["a","b","c"],"nested":{"n":1,"f":2.5,"ok":true,"none":null}}"#;
+    assert_eq!(client.call(["JSON.SET", "doc", "$", doc]).await.unwrap(), Value::SimpleString("OK".into()));
+    assert_eq!(json_get(&mut client, &["doc"]).await.as_deref(), Some(doc));
+
+    // JSONPath replies with every match, legacy paths with the one value
+    assert_eq!(json_get(&mut client, &["doc", "$.tags[-1]"]).await.as_deref(), Some(r#"["c"]"#));
+    assert_eq!(json_get(&mut client, &["doc", "$.missing"]).await.as_deref(), Some("[]"));
+    assert_eq!(json_get(&mut client, &["doc", ".nested.f"]).await.as_deref(), Some("2.5"));
+    assert_eq!(json_get(&mut client, &["doc", "nested[\"ok\"]"]).await.as_deref(), Some("true"));
+    assert!(client.call(["JSON.GET", "doc", ".missing"]).await.is_err());
+    assert_eq!(json_get(&mut client, &["doc", "$.name", ".nested.n"]).await.as_deref(), Some(r#"{"$.name":["zen"],".nested.n":1}"#));
+    assert_eq!(json_get(&mut client, &["doc", "INDENT", "  ", "NEWLINE", "\n", "SPACE", " ", "$.tags"]).await.as_deref(), Some("[\n  [\n    \"a\",\n    \"b\",\n    \"c\"\n  ]\n]"));
+    assert_eq!(json_get(&mut client, &["missing"]).await, None);
+
+    // Replacing, adding a member, NX and XX
+    assert_eq!(client.call(["JSON.SET", "doc", "$.nested.n", "[1,2]"]).await.unwrap(), Value::SimpleString("OK".into()));
+    assert_eq!(client.call(["JSON.SET", "doc", "$.added", "{}", "XX"]).await.unwrap(), Value::Null);
+    assert_eq!(client.call(["JSON.SET", "doc", "$.added", "{}", "NX"]).await.unwrap(), Value::SimpleString("OK".into()));
+    assert_eq!(client.call(["JSON.SET", "doc", "$.added", "1", "NX"]).await.unwrap(), Value::Null);
+    assert_eq!(json_get(&mut client, &["doc", "$.nested.n", "$.added"]).await.as_deref(), Some(r#"{"$.nested.n":[[1,2]],"$.added":[{}]}"#));
+    assert!(client.call(["JSON.SET", "doc", ".no.such.parent", "1"]).await.is_err());
+    assert!(client.call(["JSON.SET", "new", "$.a", "1"]).await.is_err());
+    assert!(client.call(["JSON.SET", "doc", "$", "{bad json}"]).await.is_err());
+    assert!(client.call(["JSON.SET", "doc", "$[", "1"]).await.is_err());
+
+    assert_eq!(client.call(["JSON.DEL", "doc", "$.tags[0]"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(client.call(["JSON.DEL", "doc", "$.nothing"]).await.unwrap(), Value::Integer(0));
+    assert_eq!(json_get(&mut client, &["doc", "$.tags"]).await.as_deref(), Some(r#"[["b","c"]]"#));
+    assert_eq!(client.call(["JSON.FORGET", "doc"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(json_get(&mut client, &["doc"]).await, None);
+}
+
+#[tokio::test]
+async fn mutating_in_place() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    let doc = r#"{"n":1,"f":1.5,"s":"ab","a":[1,2],"o":{"n":10,"a":[],"s":"x"}}"#;
+    client.call(["JSON.SET", "doc", "$", doc]).await.unwrap();
+
+    // A JSONPath replies per match, null where the value is of the wrong type; a legacy path fails instead
+    assert_eq!(client.call(["JSON.NUMINCRBY", "doc", "$..n", "2"]).await.unwrap(), Value::BulkString(Bytes::from("[3,12]")));
+    assert_eq!(client.call(["JSON.NUMINCRBY", "doc", "$.*", "1"]).await.unwrap(), Value::BulkString(Bytes::from("[4,2.5,null,null,null]")));
+    assert_eq!(client.call(["JSON.NUMINCRBY", "doc", ".f", "0.5"]).await.unwrap(), Value::BulkString(Bytes::from("3.0")));
+    assert!(client.call(["JSON.NUMINCRBY", "doc", ".s", "1"]).await.unwrap_err().to_string().contains("expected number but found string"));
+    assert!(client.call(["JSON.NUMINCRBY", "doc", ".n", "\"1\""]).await.is_err());
+    client.call(["JSON.SET", "doc", "$.big", "9223372036854775807"]).await.unwrap();
+    assert_eq!(client.call(["JSON.NUMINCRBY", "doc", "$.big", "1"]).await.unwrap(), Value::BulkString(Bytes::from("[9.223372036854776e18]")));
+    assert!(client.call(["JSON.NUMINCRBY", "doc", "$.big", "1e308"]).await.is_ok());
+    assert!(client.call(["JSON.NUMINCRBY", "doc", "$.big", "1e308"]).await.is_err());
+
+    assert_eq!(client.call(["JSON.ARRAPPEND", "doc", "$..a", "3", "\"four\""]).await.unwrap(), Value::Array(vec![Value::Integer(4), Value::Integer(2)]));
+    assert_eq!(client.call(["JSON.ARRINSERT", "doc", ".a", "-1", "null"]).await.unwrap(), Value::Integer(5));
+    assert_eq!(client.call(["JSON.ARRINSERT", "doc", "$.a", "5", "true"]).await.unwrap(), Value::Array(vec![Value::Integer(6)]));
+    assert!(client.call(["JSON.ARRINSERT", "doc", "$.a", "7", "1"]).await.is_err());
+    assert_eq!(client.call(["JSON.ARRAPPEND", "doc", "$.s", "1"]).await.unwrap(), Value::Array(vec![Value::Null]));
+    assert_eq!(json_get(&mut client, &["doc", "$.a", "$.o.a"]).await.as_deref(), Some(r#"{"$.a":[[1,2,3,null,"four",true]],"$.o.a":[[3,"four"]]}"#));
+
+    assert_eq!(client.call(["JSON.STRAPPEND", "doc", "$..s", "\"é\""]).await.unwrap(), Value::Array(vec![Value::Integer(4), Value::Integer(3)]));
+    assert_eq!(client.call(["JSON.STRAPPEND", "doc", ".o.s", "\"!\""]).await.unwrap(), Value::Integer(4));
+    assert!(client.call(["JSON.STRAPPEND", "doc", ".o.s", "plain"]).await.is_err());
+    assert_eq!(json_get(&mut client, &["doc", "$..s"]).await.as_deref(), Some(r#"["abé","xé!"]"#));
+
+    let names = |names: &[&str]| Value::Array(names.iter().map(|name| Value::BulkString(Bytes::from(name.to_string()))).collect());
+    assert_eq!(client.call(["JSON.OBJKEYS", "doc", "$.o"]).await.unwrap(), Value::Array(vec![names(&["n", "a", "s"])]));
+    assert_eq!(client.call(["JSON.OBJKEYS", "doc", "$[?(@.n > 10)]"]).await.unwrap(), Value::Array(vec![names(&["n", "a", "s"])]));
+    assert_eq!(client.call(["JSON.OBJKEYS", "doc", "$.a"]).await.unwrap(), Value::Array(vec![Value::Null]));
+    assert_eq!(client.call(["JSON.OBJKEYS", "doc"]).await.unwrap(), names(&["n", "f", "s", "a", "o", "big"]));
+    assert_eq!(client.call(["JSON.OBJKEYS", "missing"]).await.unwrap(), Value::Null);
+    for args in [["JSON.NUMINCRBY", "missing", "$", "1"], ["JSON.ARRAPPEND", "doc", ".none", "1"], ["JSON.ARRAPPEND", "doc", ".n", "1"]] {
+        assert!(client.call(args).await.is_err(), "{:?}", args);
+    }
+}
+
+#[tokio::test]
+async fn concurrent_increments_are_not_lost() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.call(["JSON.SET", "counter", "$", r#"{"hits":0,"log":[]}"#]).await.unwrap();
+    let mut tasks = vec![];
+    for _ in 0..4 {
+        let mut client = server.client().await;
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..50 {
+                client.call(["JSON.NUMINCRBY", "counter", "$.hits", "1"]).await.unwrap();
+                client.call(["JSON.ARRAPPEND", "counter", "$.log", "0"]).await.unwrap();
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+    assert_eq!(json_get(&mut client, &["counter", ".hits"]).await.as_deref(), Some("200"));
+    assert_eq!(client.call(["JSON.ARRINSERT", "counter", ".log", "0", "0"]).await.unwrap(), Value::Integer(201));
+}
+
+#[tokio::test]
+async fn documents_and_strings_keep_apart() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.set("string", "value").await.unwrap();
+    client.call(["JSON.SET", "doc", ".", "[1]"]).await.unwrap();
+    for args in [&["GET", "doc"][..], &["INCR", "doc"], &["JSON.GET", "string"], &["JSON.SET", "string", "$", "1"], &["JSON.DEL", "string"]] {
+        let error = client.call(args).await.unwrap_err().to_string();
+        assert!(error.contains("WRONGTYPE"), "{:?}: {}", args, error);
+    }
+    let Value::Array(reply) = client.call(["SCAN", "0", "TYPE", "ReJSON-RL"]).await.unwrap() else {
+        panic!("SCAN should reply with an array");
+    };
+    assert_eq!(reply[1], Value::Array(vec![Value::BulkString(Bytes::from("doc"))]));
+
+    // SET replaces a document like any other value, and documents survive a snapshot
+    client.set("doc", "plain").await.unwrap();
+    assert_eq!(client.get("doc").await.unwrap(), Some(Bytes::from("plain")));
+    client.call(["DEL", "doc"]).await.unwrap();
+    client.call(["JSON.SET", "doc", "$", r#"{"kept":["in","a","snapshot"]}"#]).await.unwrap();
+    client.call(["SNAPSHOT", "EXPORT", "json.snap"]).await.unwrap();
+    client.call(["JSON.DEL", "doc"]).await.unwrap();
+    client.call(["SNAPSHOT", "IMPORT", "json.snap"]).await.unwrap();
+    assert_eq!(json_get(&mut client, &["doc", "$.kept[2]"]).await.as_deref(), Some(r#"["snapshot"]"#));
+}
+
+#[test]
+fn documents_are_replayed_from_the_log() {
+    let wal = wal_storage("json");
+    let key = Bytes::from("doc");
+    let storage = wal.open();
+    storage.update::<Json, _>(&key, |doc| {
+        *doc = Some(Json::parse(br#"{"a":[1,2.5,"three"]}"#).unwrap());
+        ((), true)
+    }).unwrap();
+    storage.sync().unwrap();
+    drop(storage);
+
+    let storage = wal.open();
+    let text = storage.read::<Json, _>(&key, |doc| doc.to_text()).unwrap();
+    assert_eq!(text.as_deref(), Some(r#"{"a":[1,2.5,"three"]}"#));
+}
+
+#[test]
+fn parsing_and_writing() {
+    for text in [r#"{"a":[1,-2,3.5,1e300,-0.0]}"#, r#""\"\\\n\u0001é""#, "[]", "{}", "null", "-9223372036854775808"] {
+        let doc = Json::parse(text.as_bytes()).unwrap();
+        assert_eq!(Json::parse(doc.to_text().as_bytes()).unwrap(), doc, "{}", text);
+    }
+    assert_eq!(Json::parse(br#""\ud83d\ude00""#).unwrap(), Json::String("😀".to_string()));
+    assert_eq!(Json::parse(b"18446744073709551616").unwrap(), Json::Float(18446744073709551616.0));
+    assert_eq!(Json::parse(br#"{"a":1,"a":2}"#).unwrap().to_text(), r#"{"a":2}"#);
+    for bad in ["", "01", "1.", "[1,]", "{\"a\"}", "\"\\ud83d\"", "nul", "[1] 2", "1e999", &"[".repeat(200)] {
+        assert!(Json::parse(bad.as_bytes()).is_err(), "{}", bad);
+    }
+
+    let doc = Json::parse(br#"{"a":{"b":[10,20]}}"#).unwrap();
+    let format = Format { indent: "\t".into(), newline: "\n".into(), space: " ".into() };
+    let mut out = String::new();
+    doc.write(&mut out, &format, 0);
+    assert_eq!(out, "{\n\t\"a\": {\n\t\t\"b\": [\n\t\t\t10,\n\t\t\t20\n\t\t]\n\t}\n}");
+    for (path, found) in [("$", 1), ("$.a.b[1]", 1), ("a['b'][-3]", 0), (".a.b", 1), ("$.b", 0)] {
+        assert_eq!(Path::parse(path).unwrap().find(&doc).len(), found, "{}", path);
+    }
+
+    let doc = Json::parse(br#"{"items":[{"id":1,"price":5,"tags":["x"]},{"id":2,"price":15},{"id":3,"price":8.5,"name":"c"}],"max":10}"#).unwrap();
+    let ids = |path: &str| {
+        let path = Path::parse(path).unwrap();
+        path.find(&doc).into_iter().map(|location| doc.at(&location).clone()).collect::<Vec<_>>()
+    };
+    let ints = |ns: &[i64]| ns.iter().map(|&n| Json::Int(n)).collect::<Vec<_>>();
+    assert_eq!(ids("$.items[*].id"), ints(&[1, 2, 3]));
+    assert_eq!(ids("$..id"), ints(&[1, 2, 3]));
+    assert_eq!(ids("$.items[0,2].id"), ints(&[1, 3]));
+    assert_eq!(ids("$.items[1:].id"), ints(&[2, 3]));
+    assert_eq!(ids("$.items[::-2].id"), ints(&[3, 1]));
+    assert_eq!(ids("$.items[?(@.price < 10)].id"), ints(&[1, 3]));
+    assert_eq!(ids("$.items[?@.price > $.max && !@.tags].id"), ints(&[2]));
+    assert_eq!(ids("$.items[?(@.tags || @.name == 'c')].id"), ints(&[1, 3]));
+    assert_eq!(ids("$.items[?(@.price == 8.5)]..name"), vec![Json::String("c".into())]);
+    assert_eq!(ids("$..[?(@ == 'x')]"), vec![Json::String("x".into())]);
+    assert_eq!(ids("$.items[?(@.missing == 1)]"), vec![]);
+    for bad in ["$.", "$[1", "$['a'", "$x", ".a[one]", "$[::0]", "$[?(@.a]", "$[?@.a ==]", "$[?1]"] {
+        assert!(Path::parse(bad).is_err(), "{}", bad);
+    }
+}
@@ -0,0 +1,147 @@
+use std::time::Duration;
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::Config;
+use support::TestServer;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+mod support;
+
+async fn start(requirepass: Option<&str>) -> (TestServer, u16) {
+    let config = Config { requirepass: requirepass.map(str::to_string), ..Config::default() };
+    TestServer::with_port(config, |config| &mut config.memcached_port).await
+}
+
+struct Client {
+    stream: TcpStream,
+    buf: Vec<u8>,
+}
+
+impl Client {
+    async fn connect(port: u16) -> Client {
+        Client { stream: TcpStream::connect(("127.0.0.1", port)).await.unwrap(), buf: vec![] }
+    }
+
+    // Sends `request` and reads the reply up to and including a line `last` starts with
+    async fn call(&mut self, request: &str, last: &[&str]) -> String {
+        self.stream.write_all(request.as_bytes()).await.unwrap();
+        loop {
+            let text = String::from_utf8_lossy(&self.buf).to_string();
+            let mut end = 0;
+            for line in text.split_inclusive("\r\n") {
+                end += line.len();
+                if line.ends_with("\r\n") && last.iter().any(|prefix| line.starts_with(prefix)) {
+                    self.buf.drain(..end);
+                    return text[..end].to_string();
+                }
+            }
+            let mut chunk = [0; 4096];
+            let n = tokio::time::timeout(Duration::from_secs(5), self.stream.read(&mut chunk)).await.unwrap().unwrap();
+            assert!(n > 0, "connection closed after {:?}", text);
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    async fn line(&mut self, request: &str) -> String {
+        self.call(request, &[""]).await
+    }
+}
+
+#[tokio::test]
+async fn memcached_commands() {
+    let (server, port) = start(None).await;
+    let mut client = Client::connect(port).await;
+
+    assert_eq!(client.line("set greeting 0 0 5\r\nhello\r\n").await, "STORED\r\n");
+    assert_eq!(client.call("get greeting missing\r\n", &["END"]).await, "VALUE greeting 0 5\r\nhello\r\nEND\r\n");
+    assert_eq!(client.line("add greeting 0 0 3\r\nbye\r\n").await, "NOT_STORED\r\n");
+    assert_eq!(client.line("replace missing 0 0 3\r\nbye\r\n").await, "NOT_STORED\r\n");
+    assert_eq!(client.line("append greeting 0 0 6\r\n world\r\n").await, "STORED\r\n");
+    assert_eq!(client.line("prepend greeting 0 0 1\r\n>\r\n").await, "STORED\r\n");
+    assert_eq!(client.call("get greeting\r\n", &["END"]).await, "VALUE greeting 0 12\r\n>hello world\r\nEND\r\n");
+
+    // The keys are the ones RESP clients see
+    let mut resp = server.client().await;
+    assert_eq!(resp.call(["GET", "greeting"]).await.unwrap(), Value::BulkString(">hello world".into()));
+    resp.set("counter", "41").await.unwrap();
+    assert_eq!(client.line("incr counter 1\r\n").await, "42\r\n");
+    assert_eq!(client.line("decr counter 100\r\n").await, "0\r\n");
+    assert_eq!(client.line("incr greeting 1\r\n").await, "CLIENT_ERROR cannot increment or decrement non-numeric value\r\n");
+    assert_eq!(client.line("incr counter x\r\n").await, "CLIENT_ERROR invalid numeric delta argument\r\n");
+    assert_eq!(client.line("incr missing 1\r\n").await, "NOT_FOUND\r\n");
+    assert_eq!(resp.call(["HSET", "hash", "field", "value"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(client.call("get hash\r\n", &["END"]).await, "END\r\n");
+    assert!(client.line("set hash 0 0 1\r\nx\r\n").await.starts_with("CLIENT_ERROR WRONGTYPE"));
+
+    // Exptimes are relative seconds, and negative ones expire the item at once
+    assert_eq!(client.line("touch counter 100\r\n").await, "TOUCHED\r\n");
+    let ttl = resp.call(["TTL", "counter"]).await.unwrap();
+    assert!(matches!(ttl, Value::Integer(99..=100)), "{:?}", ttl);
+    assert_eq!(client.line("set gone 0 -1 1\r\nx\r\n").await, "STORED\r\n");
+    assert_eq!(client.call("get gone\r\n", &["END"]).await, "END\r\n");
+
+    assert_eq!(client.line("delete greeting\r\n").await, "DELETED\r\n");
+    assert_eq!(client.line("delete greeting\r\n").await, "NOT_FOUND\r\n");
+    // noreply commands answer nothing, so the version is the next line back
+    client.stream.write_all(b"set quiet 0 0 1 noreply\r\nq\r\ndelete missing noreply\r\n").await.unwrap();
+    assert!(client.line("version\r\n").await.starts_with("VERSION "));
+    assert_eq!(resp.call(["GET", "quiet"]).await.unwrap(), Value::BulkString("q".into()));
+    assert_eq!(client.line("bogus\r\n").await, "ERROR\r\n");
+    assert_eq!(client.line("set key 0 0\r\n").await, "CLIENT_ERROR bad command line format\r\n");
+
+    // A data block not ending where its length says ends the connection
+    assert_eq!(client.line("set key 0 0 1\r\nxyz\r\n").await, "CLIENT_ERROR bad data chunk\r\n");
+    let mut rest = vec![];
+    client.stream.read_to_end(&mut rest).await.unwrap();
+    assert!(rest.is_empty());
+}
+
+#[tokio::test]
+async fn memcached_flags_and_cas() {
+    let (server, port) = start(None).await;
+    let mut client = Client::connect(port).await;
+
+    assert_eq!(client.line("set item 42 0 3\r\none\r\n").await, "STORED\r\n");
+    let reply = client.call("gets item\r\n", &["END"]).await;
+    let unique: u64 = reply.split("\r\n").next().unwrap().rsplit(' ').next().unwrap().parse().unwrap();
+    assert_eq!(reply, format!("VALUE item 42 3 {}\r\none\r\nEND\r\n", unique));
+
+    // Flags survive append, but a write from RESP resets them
+    assert_eq!(client.line("append item 0 0 1\r\n!\r\n").await, "STORED\r\n");
+    assert_eq!(client.call("get item\r\n", &["END"]).await, "VALUE item 42 4\r\none!\r\nEND\r\n");
+
+    // A cas against a stale unique fails, and against the current one succeeds
+    assert_eq!(client.line(&format!("cas item 7 0 3 {}\r\ntwo\r\n", unique)).await, "EXISTS\r\n");
+    let reply = client.call("gets item\r\n", &["END"]).await;
+    let unique: u64 = reply.split("\r\n").next().unwrap().rsplit(' ').next().unwrap().parse().unwrap();
+    assert_eq!(client.line(&format!("cas item 7 0 3 {}\r\ntwo\r\n", unique)).await, "STORED\r\n");
+    assert_eq!(client.call("get item\r\n", &["END"]).await, "VALUE item 7 3\r\ntwo\r\nEND\r\n");
+    assert_eq!(client.line("cas missing 0 0 1 1\r\nx\r\n").await, "NOT_FOUND\r\n");
+
+    // Any write moves the unique on
+    let reply = client.call("gets item\r\n", &["END"]).await;
+    let unique: u64 = reply.split("\r\n").next().unwrap().rsplit(' ').next().unwrap().parse().unwrap();
+    server.client().await.set("item", "three").await.unwrap();
+    assert_eq!(client.line(&format!("cas item 0 0 1 {}\r\nx\r\n", unique)).await, "EXISTS\r\n");
+    assert_eq!(client.call("get item\r\n", &["END"]).await, "VALUE item 0 5\r\nthree\r\nEND\r\n");
+
+    // gat touches as it reads
+    assert_eq!(client.call("gat 100 item\r\n", &["END"]).await, "VALUE item 0 5\r\nthree\r\nEND\r\n");
+    let ttl = server.client().await.call(["TTL", "item"]).await.unwrap();
+    assert!(matches!(ttl, Value::Integer(99..=100)), "{:?}", ttl);
+}
+
+#[tokio::test]
+async fn memcached_authentication() {
+    let (_server, port) = start(Some("secret")).await;
+    let mut client = Client::connect(port).await;
+    assert_eq!(client.call("get key\r\n", &["END", "CLIENT_ERROR"]).await, "CLIENT_ERROR unauthenticated\r\n");
+    assert_eq!(client.line("set auth 0 0 5\r\nwrong\r\n").await, "CLIENT_ERROR authentication failure\r\n");
+    assert_eq!(client.line("set auth 0 0 14\r\ndefault secret\r\n").await, "STORED\r\n");
+    assert_eq!(client.line("set key 0 0 1\r\nx\r\n").await, "STORED\r\n");
+    assert_eq!(client.call("get key auth\r\n", &["END"]).await, "VALUE key 0 1\r\nx\r\nEND\r\n");
+
+    let mut client = Client::connect(port).await;
+    assert_eq!(client.line("set auth 0 0 6\r\nsecret\r\n").await, "STORED\r\n");
+    assert_eq!(client.call("get key\r\n", &["END"]).await, "VALUE key 0 1\r\nx\r\nEND\r\n");
+}
@@ -0,0 +1,59 @@
+use bytes::Bytes;
+use redis_starter_rust::allocator::CountingAllocator;
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::Config;
+use support::TestServer;
+
+mod support;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// The INFO field `name`, as a number
+fn info_field(info: &str, name: &str) -> f64 {
+    let line = info.lines().find_map(|line| line.strip_prefix(&format!("{}:", name)));
+    line.unwrap_or_else(|| panic!("no {} in INFO", name)).parse().unwrap()
+}
+
+#[tokio::test]
+async fn memory_usage_and_fragmentation() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    let Value::BulkString(info) = client.call(["INFO", "memory"]).await.unwrap() else {
+        panic!("INFO should reply with a bulk string");
+    };
+    let info = String::from_utf8(info.to_vec()).unwrap();
+    let before = info_field(&info, "used_memory");
+    assert!(info.contains("mem_allocator:libc\r\n"));
+
+    // Megabytes of values show up as allocated, and the ratio follows from the two sizes
+    for i in 0..100 {
+        client.set(format!("key{}", i), "x".repeat(100_000)).await.unwrap();
+    }
+    let Value::BulkString(info) = client.call(["INFO"]).await.unwrap() else {
+        panic!("INFO should reply with a bulk string");
+    };
+    let info = String::from_utf8(info.to_vec()).unwrap();
+    let (used, rss) = (info_field(&info, "used_memory"), info_field(&info, "used_memory_rss"));
+    assert!(used - before >= 10_000_000.0, "{} then {}", before, used);
+    assert!(info_field(&info, "used_memory_peak") >= used);
+    assert!((info_field(&info, "mem_fragmentation_ratio") - rss / used).abs() < 0.01);
+
+    let Value::Array(stats) = client.call(["MEMORY", "STATS"]).await.unwrap() else {
+        panic!("MEMORY STATS should reply with a map");
+    };
+    let field = |name: &str| stats.chunks(2).find(|pair| pair[0] == Value::BulkString(Bytes::copy_from_slice(name.as_bytes()))).map(|pair| pair[1].clone());
+    assert!(matches!(field("total.allocated"), Some(Value::Integer(allocated)) if allocated as f64 >= used));
+    assert!(matches!(field("allocator.resident"), Some(Value::Integer(_))));
+    assert!(field("fragmentation").is_some());
+}
+
+#[test]
+fn maxmemory_is_refused() {
+    // Without eviction a limit would be silently ignored
+    let mut config = Config::default();
+    let error = config.set("maxmemory", "100mb").unwrap_err().to_string();
+    assert!(error.contains("maxmemory isn't enforced"), "{}", error);
+    config.set("maxmemory", "0").unwrap();
+    assert_eq!(config.get("maxmemory").unwrap(), "0");
+}
@@ -0,0 +1,19 @@
+use redis_starter_rust::resp::Value;
+use support::TestServer;
+
+mod support;
+
+#[tokio::test]
+async fn info_replication() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    let Value::BulkString(info) = client.call(["INFO", "replication"]).await.unwrap() else {
+        panic!("INFO should reply with a bulk string");
+    };
+    assert_eq!(&info[..], b"# Replication\r\nrole:master\r\nconnected_slaves:0\r\nmaster_repl_offset:0\r\n");
+
+    let Value::BulkString(info) = client.call(["INFO"]).await.unwrap() else {
+        panic!("INFO should reply with a bulk string");
+    };
+    assert!(String::from_utf8_lossy(&info).contains("\r\nrole:master\r\n"));
+}
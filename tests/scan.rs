@@ -0,0 +1,158 @@
+use std::collections::HashSet;
+use bytes::Bytes;
+use redis_starter_rust::client::Client;
+use redis_starter_rust::config::Config;
+use redis_starter_rust::glob;
+use redis_starter_rust::resp::Value;
+use support::TestServer;
+
+mod support;
+
+// One SCAN call with any options after the cursor: the next cursor and the keys returned
+async fn scan(client: &mut Client, cursor: &str, options: &[&str]) -> (String, Vec<Bytes>) {
+    let args = ["SCAN", cursor].into_iter().chain(options.iter().copied());
+    match client.call(args).await.unwrap() {
+        Value::Array(reply) => match &reply[..] {
+            [Value::BulkString(next), Value::Array(keys)] => {
+                let keys = keys.iter().map(|key| match key {
+                    Value::BulkString(key) => key.clone(),
+                    other => panic!("unexpected key {:?}", other),
+                });
+                (String::from_utf8(next.to_vec()).unwrap(), keys.collect())
+            },
+            other => panic!("unexpected reply {:?}", other),
+        },
+        other => panic!("unexpected reply {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn scan_survives_growing_and_shrinking() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    assert_eq!(scan(&mut client, "0", &[]).await, ("0".to_string(), vec![]));
+    for i in 0..500 {
+        client.set(format!("stable{}", i), "value").await.unwrap();
+    }
+
+    // The keyspace grows many times over during the first half of the iteration and shrinks back after
+    let mut seen = HashSet::new();
+    let (mut cursor, mut calls, mut added) = ("0".to_string(), 0, 0);
+    loop {
+        let (next, keys) = scan(&mut client, &cursor, &[]).await;
+        seen.extend(keys);
+        calls += 1;
+        if calls < 25 {
+            for _ in 0..200 {
+                client.set(format!("grow{}", added), "value").await.unwrap();
+                added += 1;
+            }
+        } else if added > 0 {
+            for i in (added.max(400) - 400)..added {
+                client.call(["DEL".to_string(), format!("grow{}", i)]).await.unwrap();
+            }
+            added = added.max(400) - 400;
+        }
+        if next == "0" {
+            break;
+        }
+        cursor = next;
+    }
+    for i in 0..500 {
+        assert!(seen.contains(format!("stable{}", i).as_bytes()), "stable{} wasn't returned", i);
+    }
+
+    assert!(client.call(["SCAN", "nope"]).await.is_err());
+    assert!(client.call(["SCAN", "-1"]).await.is_err());
+}
+
+#[tokio::test]
+async fn scan_options() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    for i in 0..100 {
+        client.set(format!("user:{}", i), "value").await.unwrap();
+        client.set(format!("session:{}", i), "value").await.unwrap();
+    }
+
+    // A large COUNT takes every key in one go, MATCH keeps those wanted
+    let (next, keys) = scan(&mut client, "0", &["MATCH", "user:*", "COUNT", "1000"]).await;
+    assert_eq!(next, "0");
+    assert_eq!(keys.len(), 100);
+    assert!(keys.iter().all(|key| key.starts_with(b"user:")));
+
+    // COUNT 1 goes a key or so at a time
+    let (mut cursor, mut calls, mut matched) = ("0".to_string(), 0, 0);
+    loop {
+        let (next, keys) = scan(&mut client, &cursor, &["COUNT", "1", "MATCH", "session:[1-2]?"]).await;
+        (calls, matched) = (calls + 1, matched + keys.len());
+        if next == "0" {
+            break;
+        }
+        cursor = next;
+    }
+    assert!(calls >= 200, "{} calls", calls);
+    assert_eq!(matched, 20);
+
+    // TYPE takes any name TYPE replies with, in any case, and Redis's other types, which match nothing
+    client.call(["HSET", "profile", "name", "ada"]).await.unwrap();
+    client.call(["TS.ADD", "temperature", "1", "20"]).await.unwrap();
+    assert_eq!(scan(&mut client, "0", &["TYPE", "string", "COUNT", "1000"]).await.1.len(), 200);
+    assert_eq!(scan(&mut client, "0", &["TYPE", "hash", "COUNT", "1000"]).await.1, vec![Bytes::from("profile")]);
+    assert_eq!(scan(&mut client, "0", &["TYPE", "TSDB-TYPE", "COUNT", "1000"]).await.1, vec![Bytes::from("temperature")]);
+    assert_eq!(scan(&mut client, "0", &["TYPE", "tsdb-type", "COUNT", "1000"]).await.1, vec![Bytes::from("temperature")]);
+    assert_eq!(scan(&mut client, "0", &["TYPE", "list", "COUNT", "1000"]).await, ("0".to_string(), vec![]));
+
+    for options in [&["COUNT", "0"][..], &["COUNT", "many"], &["TYPE", "thing"], &["MATCH"], &["LIMIT", "1"]] {
+        let args = ["SCAN", "0"].into_iter().chain(options.iter().copied());
+        assert!(client.call(args).await.is_err(), "{:?}", options);
+    }
+}
+
+#[tokio::test]
+async fn either_keyspace_hasher() {
+    for name in ["fx", "siphash"] {
+        let config = Config::parse_args(["--keyspace-hasher".to_string(), name.to_string()]).unwrap();
+        assert_eq!(config.get("keyspace-hasher").as_deref(), Some(name));
+        let server = TestServer::with_config(config).await;
+        let mut client = server.client().await;
+        for i in 0..300 {
+            client.set(format!("key{}", i), i.to_string()).await.unwrap();
+        }
+        for i in 0..300 {
+            assert_eq!(client.get(format!("key{}", i)).await.unwrap(), Some(Bytes::from(i.to_string())));
+        }
+        assert_eq!(scan(&mut client, "0", &["COUNT", "1000"]).await.1.len(), 300);
+    }
+    assert!(Config::parse_args(["--keyspace-hasher".to_string(), "md5".to_string()]).is_err());
+}
+
+#[test]
+fn glob_patterns() {
+    let cases: &[(&str, &str, bool)] = &[
+        ("*", "", true),
+        ("*", "anything", true),
+        ("h?llo", "hello", true),
+        ("h?llo", "hllo", false),
+        ("h*llo", "heeeello", true),
+        ("h*llo", "hello world", false),
+        ("h[ae]llo", "hallo", true),
+        ("h[ae]llo", "hillo", false),
+        ("h[^e]llo", "hallo", true),
+        ("h[^e]llo", "hello", false),
+        ("h[a-b]llo", "hbllo", true),
+        ("h[b-a]llo", "hallo", true),
+        ("h[a-b]llo", "hcllo", false),
+        ("h\\*llo", "h*llo", true),
+        ("h\\*llo", "hello", false),
+        ("*a*b*c*", "xxaxxbxxcxx", true),
+        ("*a*b*c*", "xxcxxbxxaxx", false),
+        ("a[", "a", false),
+        ("[abc", "b", true),
+    ];
+    for &(pattern, string, expected) in cases {
+        assert_eq!(glob::matches(pattern.as_bytes(), string.as_bytes()), expected, "{} against {}", pattern, string);
+    }
+    // Many stars against a long mismatch still finish promptly
+    assert!(!glob::matches("*a*a*a*a*a*a*a*a*a*b".as_bytes(), "a".repeat(10_000).as_bytes()));
+}
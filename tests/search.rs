@@ -0,0 +1,94 @@
+use redis_starter_rust::client::Client;
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::search::{tokenize, Query};
+use support::{bulk, TestServer};
+
+mod support;
+
+// The keys FT.SEARCH ... NOCONTENT found, after checking the total it gives
+async fn search(client: &mut Client, index: &str, query: &str, extra: &[&str]) -> (i64, Vec<String>) {
+    let args = ["FT.SEARCH", index, query, "NOCONTENT"].into_iter().chain(extra.iter().copied());
+    let Value::Array(reply) = client.call(args).await.unwrap() else {
+        panic!("FT.SEARCH should reply with an array");
+    };
+    let Value::Integer(total) = reply[0] else {
+        panic!("FT.SEARCH should start with the total");
+    };
+    let keys = reply[1..].iter().map(|key| match key {
+        Value::BulkString(key) => String::from_utf8(key.to_vec()).unwrap(),
+        other => panic!("unexpected {:?}", other),
+    });
+    (total, keys.collect())
+}
+
+#[tokio::test]
+async fn searching_hashes() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.call(["HSET", "post:1", "title", "Rust in production", "body", "Fearless concurrency, fearless refactoring"]).await.unwrap();
+    client.call(["HSET", "post:2", "title", "Production databases", "body", "Replication and durability"]).await.unwrap();
+    client.call(["HSET", "draft:1", "title", "Rust drafts"]).await.unwrap();
+    client.call(["FT.CREATE", "posts", "ON", "HASH", "PREFIX", "1", "post:", "SCHEMA", "title", "TEXT", "body"]).await.unwrap();
+    assert!(client.call(["FT.CREATE", "posts", "SCHEMA", "title"]).await.is_err());
+    assert!(client.call(["FT.CREATE", "bad", "SCHEMA", "n", "NUMERIC"]).await.is_err());
+
+    assert_eq!(search(&mut client, "posts", "rust", &[]).await, (1, vec!["post:1".into()]));
+    assert_eq!(search(&mut client, "posts", "PRODUCTION", &[]).await.0, 2);
+    assert_eq!(search(&mut client, "posts", "production rust", &[]).await, (1, vec!["post:1".into()]));
+    assert_eq!(search(&mut client, "posts", "rust | replication", &[]).await, (2, vec!["post:1".into(), "post:2".into()]));
+    assert_eq!(search(&mut client, "posts", "dura* (rust | production)", &[]).await, (1, vec!["post:2".into()]));
+    // Scored by how often the terms occur: fearless twice in post:1
+    assert_eq!(search(&mut client, "posts", "fearless | databases", &[]).await, (2, vec!["post:1".into(), "post:2".into()]));
+    assert_eq!(search(&mut client, "posts", "*", &["LIMIT", "1", "1"]).await, (2, vec!["post:2".into()]));
+    assert_eq!(search(&mut client, "posts", "nothing", &[]).await, (0, vec![]));
+    assert!(client.call(["FT.SEARCH", "posts", "(rust"]).await.is_err());
+    assert!(client.call(["FT.SEARCH", "missing", "rust"]).await.is_err());
+
+    // Kept up to date as hashes change, including through FT.ADD
+    client.call(["HSET", "post:2", "title", "Rust databases"]).await.unwrap();
+    assert_eq!(search(&mut client, "posts", "rust", &[]).await.0, 2);
+    client.call(["DEL", "post:1"]).await.unwrap();
+    assert_eq!(search(&mut client, "posts", "fearless", &[]).await.0, 0);
+    client.call(["FT.ADD", "posts", "post:3", "FIELDS", "title", "Rusty hinges"]).await.unwrap();
+    assert!(client.call(["FT.ADD", "posts", "post:3", "FIELDS", "title", "again"]).await.is_err());
+    assert!(client.call(["FT.ADD", "posts", "draft:2", "FIELDS", "title", "x"]).await.is_err());
+    assert_eq!(search(&mut client, "posts", "rust*", &[]).await, (2, vec!["post:2".into(), "post:3".into()]));
+    client.call(["FT.ADD", "posts", "post:3", "REPLACE", "FIELDS", "body", "hinges"]).await.unwrap();
+    assert_eq!(client.call(["FT.SEARCH", "posts", "hinges", "WITHSCORES"]).await.unwrap(), Value::Array(vec![
+        Value::Integer(1), bulk("post:3"), bulk("1"), Value::Array(vec![bulk("body"), bulk("hinges")]),
+    ]));
+
+    assert_eq!(client.call(["FT._LIST"]).await.unwrap(), Value::Array(vec![bulk("posts")]));
+    client.call(["FT.DROPINDEX", "posts"]).await.unwrap();
+    assert!(client.call(["FT.SEARCH", "posts", "rust"]).await.is_err());
+}
+
+#[tokio::test]
+async fn searching_documents() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.call(["FT.CREATE", "books", "ON", "JSON", "SCHEMA", "$.title", "$.tags[*]"]).await.unwrap();
+    client.call(["JSON.SET", "book:1", "$", r#"{"title":"The Rust Book","tags":["programming","systems"],"pages":500}"#]).await.unwrap();
+    client.call(["JSON.SET", "book:2", "$", r#"{"title":"Gardening","tags":["outdoors"]}"#]).await.unwrap();
+    client.call(["HSET", "book:3", "title", "A hash, not a document"]).await.unwrap();
+    assert_eq!(search(&mut client, "books", "systems", &[]).await, (1, vec!["book:1".into()]));
+    assert_eq!(search(&mut client, "books", "500", &[]).await.0, 0);
+    client.call(["JSON.ARRAPPEND", "book:2", "$.tags", r#""systems""#]).await.unwrap();
+    assert_eq!(search(&mut client, "books", "systems", &[]).await.0, 2);
+    assert_eq!(client.call(["FT.SEARCH", "books", "gardening"]).await.unwrap(), Value::Array(vec![
+        Value::Integer(1), bulk("book:2"), Value::Array(vec![bulk("$"), bulk(r#"{"title":"Gardening","tags":["outdoors","systems"]}"#)]),
+    ]));
+    assert!(client.call(["FT.ADD", "books", "book:4", "FIELDS", "title", "x"]).await.is_err());
+}
+
+#[test]
+fn queries() {
+    assert_eq!(tokenize("Hello, wörld! it's 2024").collect::<Vec<_>>(), ["hello", "wörld", "it", "s", "2024"]);
+    let term = |t: &str| Query::Term(t.into());
+    assert_eq!(Query::parse("a b").unwrap(), Query::And(vec![term("a"), term("b")]));
+    assert_eq!(Query::parse("a|b c").unwrap(), Query::Or(vec![term("a"), Query::And(vec![term("b"), term("c")])]));
+    assert_eq!(Query::parse("(a | b) foo-bar*").unwrap(), Query::And(vec![Query::Or(vec![term("a"), term("b")]), term("foo"), Query::Prefix("bar".into())]));
+    for bad in ["", "a |", "(a", "a)", "!!", "**", &"(".repeat(100)] {
+        assert!(Query::parse(bad).is_err(), "{}", bad);
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::os::unix::fs::FileExt;
+use bytes::Bytes;
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::storage::{BackendKind, Storage};
+use redis_starter_rust::Config;
+use support::TestServer;
+
+mod support;
+
+#[tokio::test]
+async fn export_and_import() {
+    let config = Config { compression_threshold: 1024, ..Config::default() };
+    let server = TestServer::with_config(config).await;
+    let mut client = server.client().await;
+    client.set("string", "value").await.unwrap();
+    client.set("large", "x".repeat(10_000)).await.unwrap();
+    client.call(["SET", "expiring", "value", "EX", "100"]).await.unwrap();
+    client.call(["INCR", "counter"]).await.unwrap();
+    assert_eq!(client.call(["SNAPSHOT", "EXPORT", "backup.snap"]).await.unwrap(), Value::Integer(4));
+    assert!(server.dir().join("backup.snap").is_file());
+
+    // MERGE overwrites the snapshot's keys and keeps the rest
+    client.set("string", "changed").await.unwrap();
+    client.set("other", "value").await.unwrap();
+    assert_eq!(client.call(["SNAPSHOT", "IMPORT", "backup.snap"]).await.unwrap(), Value::Integer(4));
+    assert_eq!(client.get("string").await.unwrap(), Some(Bytes::from("value")));
+    assert_eq!(client.get("other").await.unwrap(), Some(Bytes::from("value")));
+    assert_eq!(client.get("large").await.unwrap(), Some(Bytes::from("x".repeat(10_000))));
+    assert!(matches!(client.call(["TTL", "expiring"]).await.unwrap(), Value::Integer(ttl) if ttl > 0));
+
+    // REPLACE leaves only the snapshot's keys
+    assert_eq!(client.call(["SNAPSHOT", "IMPORT", "backup.snap", "REPLACE"]).await.unwrap(), Value::Integer(4));
+    assert_eq!(client.get("other").await.unwrap(), None);
+    assert_eq!(client.call(["INCR", "counter"]).await.unwrap(), Value::Integer(2));
+}
+
+#[tokio::test]
+async fn damaged_snapshots_change_nothing() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.set("key", "value").await.unwrap();
+    client.call(["SNAPSHOT", "EXPORT", "backup.snap"]).await.unwrap();
+    let path = server.dir().join("backup.snap");
+    let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.write_all_at(b"X", 20).unwrap();
+    drop(file);
+
+    client.set("key", "changed").await.unwrap();
+    let error = client.call(["SNAPSHOT", "IMPORT", "backup.snap", "REPLACE"]).await.unwrap_err().to_string();
+    assert!(error.contains("checksum"), "{}", error);
+    assert_eq!(client.get("key").await.unwrap(), Some(Bytes::from("changed")));
+
+    assert!(client.call(["SNAPSHOT", "IMPORT", "missing.snap"]).await.is_err());
+    assert!(client.call(["SNAPSHOT", "IMPORT", "backup.snap", "SOMETIMES"]).await.is_err());
+    assert!(client.call(["SNAPSHOT", "EXPORT", "no/such/dir/backup.snap"]).await.is_err());
+}
+
+#[tokio::test]
+async fn incremental_snapshots_chain() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    assert!(client.call(["SNAPSHOT", "EXPORT", "first.snap", "INCREMENTAL"]).await.is_err());
+    for i in 0..100 {
+        client.set(format!("key{}", i), "value").await.unwrap();
+    }
+    assert_eq!(client.call(["SNAPSHOT", "EXPORT", "full.snap"]).await.unwrap(), Value::Integer(100));
+
+    // Only the changed keys go into each increment, deletions included
+    client.set("key1", "changed").await.unwrap();
+    client.call(["DEL", "key2"]).await.unwrap();
+    assert_eq!(client.call(["SNAPSHOT", "EXPORT", "delta1.snap", "INCREMENTAL"]).await.unwrap(), Value::Integer(2));
+    client.set("key2", "back").await.unwrap();
+    client.call(["DEL", "key3"]).await.unwrap();
+    client.set("new", "value").await.unwrap();
+    assert_eq!(client.call(["SNAPSHOT", "EXPORT", "delta2.snap", "INCREMENTAL"]).await.unwrap(), Value::Integer(3));
+    let full = std::fs::metadata(server.dir().join("full.snap")).unwrap().len();
+    let delta = std::fs::metadata(server.dir().join("delta2.snap")).unwrap().len();
+    assert!(delta * 10 < full, "{} bytes full, {} incremental", full, delta);
+
+    // Loading the last increment loads the chain
+    assert_eq!(client.call(["SNAPSHOT", "IMPORT", "delta2.snap", "REPLACE"]).await.unwrap(), Value::Integer(100));
+    assert_eq!(client.get("key1").await.unwrap(), Some(Bytes::from("changed")));
+    assert_eq!(client.get("key2").await.unwrap(), Some(Bytes::from("back")));
+    assert_eq!(client.get("key3").await.unwrap(), None);
+    assert_eq!(client.get("new").await.unwrap(), Some(Bytes::from("value")));
+    assert_eq!(client.call(["SNAPSHOT", "IMPORT", "delta1.snap", "REPLACE"]).await.unwrap(), Value::Integer(99));
+    assert_eq!(client.get("key2").await.unwrap(), None);
+
+    // A base overwritten since breaks the chain
+    assert_eq!(client.call(["SNAPSHOT", "EXPORT", "full.snap"]).await.unwrap(), Value::Integer(99));
+    let error = client.call(["SNAPSHOT", "IMPORT", "delta2.snap"]).await.unwrap_err().to_string();
+    assert!(error.contains("overwritten"), "{}", error);
+}
+
+#[test]
+fn writes_carry_on_during_a_snapshot() {
+    let storage = Storage::new(BackendKind::Sharded, 4);
+    for i in 0..2000 {
+        storage.set(format!("key{}", i).into(), "before".into(), None);
+    }
+
+    // Writing from inside the snapshot would deadlock if it held a lock, and mustn't show in it
+    let mut seen = HashMap::new();
+    let mut changed = false;
+    let count = storage.snapshot(|key, value, _| {
+        if !changed {
+            for i in 0..2000 {
+                storage.set(format!("key{}", i).into(), "after".into(), None);
+                storage.set(format!("new{}", i).into(), "after".into(), None);
+            }
+            for i in 0..1000 {
+                storage.del(format!("key{}", i).as_bytes());
+            }
+            changed = true;
+        }
+        seen.insert(key.clone(), value.to_bytes());
+        Ok(())
+    }).unwrap();
+    assert_eq!(count, 2000);
+    assert_eq!(seen.len(), 2000);
+    assert!(seen.iter().all(|(key, value)| key.starts_with(b"key") && value == "before"));
+    assert_eq!(storage.len(), 3000);
+    assert_eq!(storage.get(b"key1500"), Ok(Some(Bytes::from("after"))));
+
+    // The next one sees the writes
+    let count = storage.snapshot(|key, value, _| {
+        assert_eq!(value.to_bytes(), "after", "{:?}", key);
+        Ok(())
+    }).unwrap();
+    assert_eq!(count, 3000);
+}
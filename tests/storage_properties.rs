@@ -0,0 +1,255 @@
+// Model-based property tests: random operation sequences run against the Engine and against a plain HashMap
+// model, checking after every step that both agree. Failures print the seed; set ZENQL_PROPTEST_SEED to
+// replay one and ZENQL_PROPTEST_CASES to run more cases.
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use bytes::Bytes;
+use redis_starter_rust::clock::ManualClock;
+use redis_starter_rust::storage::BackendKind;
+use redis_starter_rust::Engine;
+
+const START: u64 = 1_700_000_000_000;
+const KEYS: u64 = 8; // Few keys, so operations keep colliding
+const STEPS: usize = 200;
+
+// xorshift64*
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) % bound
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Op {
+    Set(Bytes, Bytes),
+    SetEx(Bytes, Bytes, u64),
+    Get(Bytes),
+    Del(Bytes),
+    Expire(Bytes, u64),
+    ExpireAt(Bytes, u64), // Absolute, possibly in the past
+    Persist(Bytes),
+    Advance(u64),
+    Purge,
+    Reopen, // Disk only: drops the engine and loads it again from its data file
+    Compact, // Disk only
+}
+
+impl Op {
+    fn random(rng: &mut Rng, disk: bool) -> Op {
+        let key = Bytes::from(format!("key{}", rng.below(KEYS)));
+        // Integers are kept differently, in memory even on disk
+        let value = match rng.below(4) {
+            0 => Bytes::from(rng.below(1000).to_string()),
+            _ => Bytes::from(format!("value{}", rng.below(1000))),
+        };
+        match rng.below(9) {
+            0 => Op::Set(key, value),
+            1 => Op::SetEx(key, value, 1 + rng.below(5_000)),
+            2 | 3 => Op::Get(key),
+            4 => Op::Del(key),
+            5 => Op::Expire(key, 1 + rng.below(5_000)),
+            6 => Op::ExpireAt(key, START + rng.below(20_000)),
+            7 => Op::Persist(key),
+            _ => match rng.below(6) {
+                0 => Op::Purge,
+                1 if disk => Op::Reopen,
+                2 if disk => Op::Compact,
+                _ => Op::Advance(rng.below(2_000)),
+            },
+        }
+    }
+}
+
+// What the engine should behave like: values with optional absolute deadlines, expired entries kept around
+// until purged
+#[derive(Default)]
+struct Model {
+    items: HashMap<Bytes, (Bytes, Option<u64>)>,
+    now: u64,
+}
+
+impl Model {
+    fn live(&self, key: &Bytes) -> Option<&(Bytes, Option<u64>)> {
+        self.items.get(key).filter(|(_, deadline)| deadline.is_none_or(|deadline| self.now <= deadline))
+    }
+
+    fn ttl(&self, key: &Bytes) -> Option<Option<Duration>> {
+        let (_, deadline) = self.live(key)?;
+        Some(deadline.map(|deadline| Duration::from_millis(deadline - self.now)))
+    }
+
+    // Sets the deadline of a live key; a deadline already due deletes it
+    fn expire(&mut self, key: &Bytes, deadline: u64) -> bool {
+        if self.live(key).is_none() {
+            return false;
+        }
+        if deadline <= self.now {
+            self.items.remove(key);
+        } else if let Some(item) = self.items.get_mut(key) {
+            item.1 = Some(deadline);
+        }
+        true
+    }
+}
+
+// With `dir`, the engine keeps its values on disk there
+fn run_case(seed: u64, dir: Option<&Path>) {
+    let mut rng = Rng(seed | 1);
+    let clock = Arc::new(ManualClock::new(START));
+    let open = || match dir {
+        Some(dir) => Engine::open_with_clock(dir, clock.clone()).unwrap(),
+        None => Engine::with_clock(clock.clone()),
+    };
+    let mut engine = open();
+    let mut model = Model { now: START, ..Model::default() };
+    let mut history = vec![];
+
+    for step in 0..STEPS {
+        let op = Op::random(&mut rng, dir.is_some());
+        history.push(op.clone());
+        let context = || format!("seed {} step {} after {:?}", seed, step, history);
+        let ttls_before: Vec<_> = (0..KEYS).map(|i| engine.ttl(format!("key{}", i).as_bytes())).collect();
+
+        match &op {
+            Op::Set(key, value) => {
+                engine.set(key.clone(), value.clone());
+                model.items.insert(key.clone(), (value.clone(), None));
+            },
+            Op::SetEx(key, value, ttl) => {
+                engine.set_ex(key.clone(), value.clone(), Duration::from_millis(*ttl));
+                model.items.insert(key.clone(), (value.clone(), Some(model.now + ttl)));
+            },
+            Op::Get(key) => {
+                assert_eq!(engine.get(key), model.live(key).map(|(value, _)| value.clone()), "{}", context());
+            },
+            Op::Del(key) => {
+                let existed = model.live(key).is_some();
+                model.items.remove(key);
+                assert_eq!(engine.del(key), existed, "{}", context());
+            },
+            Op::Expire(key, ttl) => {
+                let expected = model.expire(key, model.now + ttl);
+                assert_eq!(engine.expire(key, Duration::from_millis(*ttl)), expected, "{}", context());
+            },
+            Op::ExpireAt(key, deadline) => {
+                let expected = model.expire(key, *deadline);
+                assert_eq!(engine.expire_at(key, UNIX_EPOCH + Duration::from_millis(*deadline)), expected, "{}", context());
+            },
+            Op::Persist(key) => {
+                let expected = model.live(key).is_some_and(|(_, deadline)| deadline.is_some());
+                if let Some(item) = model.items.get_mut(key).filter(|_| expected) {
+                    item.1 = None;
+                }
+                assert_eq!(engine.persist(key), expected, "{}", context());
+            },
+            Op::Advance(ms) => {
+                clock.advance(Duration::from_millis(*ms));
+                model.now += ms;
+            },
+            Op::Purge => {
+                let now = model.now;
+                let before = model.items.len();
+                model.items.retain(|_, (_, deadline)| deadline.is_none_or(|deadline| now <= deadline));
+                assert_eq!(engine.purge_expired(), before - model.items.len(), "{}", context());
+            },
+            Op::Reopen => {
+                drop(engine);
+                engine = open();
+            },
+            Op::Compact => engine.compact().unwrap(),
+        }
+
+        // Every key reads the same as in the model
+        for i in 0..KEYS {
+            let key = Bytes::from(format!("key{}", i));
+            assert_eq!(engine.get(&key), model.live(&key).map(|(value, _)| value.clone()), "{} key {:?}", context(), key);
+            assert_eq!(engine.ttl(&key), model.ttl(&key), "{} key {:?}", context(), key);
+        }
+        // Expired keys may linger until purged, but never more than the model holds
+        assert_eq!(engine.len(), model.items.len(), "{}", context());
+
+        // Without a write to the key, time only ever shortens a TTL
+        if matches!(op, Op::Advance(_) | Op::Get(_) | Op::Purge | Op::Reopen | Op::Compact) {
+            for (i, before) in ttls_before.into_iter().enumerate() {
+                let after = engine.ttl(format!("key{}", i).as_bytes());
+                match (before, after) {
+                    (Some(Some(before)), Some(Some(after))) => assert!(after <= before, "{} key{}", context(), i),
+                    (None, Some(_)) | (Some(None), Some(Some(_))) | (Some(Some(_)), Some(None)) => {
+                        panic!("{} key{} went from {:?} to {:?}", context(), i, before, after)
+                    },
+                    _ => {},
+                }
+            }
+        }
+    }
+}
+
+fn cases() -> Vec<u64> {
+    if let Some(seed) = std::env::var("ZENQL_PROPTEST_SEED").ok().and_then(|seed| seed.parse().ok()) {
+        return vec![seed];
+    }
+    let count = std::env::var("ZENQL_PROPTEST_CASES").ok().and_then(|n| n.parse().ok()).unwrap_or(256u64);
+    let base = std::time::SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+    (0..count).map(|i| base.wrapping_add(i.wrapping_mul(0x9e37_79b9_7f4a_7c15))).collect()
+}
+
+#[test]
+fn engine_matches_model() {
+    for seed in cases() {
+        run_case(seed, None);
+    }
+}
+
+// The same on disk, where the engine also has to read back what it wrote before being reopened
+#[test]
+fn disk_engine_matches_model() {
+    for seed in cases().into_iter().take(32) {
+        let dir = std::env::temp_dir().join(format!("zenql-proptest-{}-{}", std::process::id(), seed));
+        std::fs::create_dir_all(&dir).unwrap();
+        run_case(seed, Some(&dir));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+// Values written by concurrent writers to disjoint keys are all readable afterwards
+#[test]
+fn concurrent_writers_dont_lose_updates() {
+    let engine = Engine::with_backend(BackendKind::Concurrent, 0).shared();
+    let writers: Vec<_> = (0..8)
+        .map(|writer| {
+            let engine = engine.clone();
+            std::thread::spawn(move || {
+                for i in 0..1_000 {
+                    engine.set(format!("writer{}:{}", writer, i), format!("{}", i));
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    assert_eq!(engine.len(), 8_000);
+    for writer in 0..8 {
+        for i in 0..1_000 {
+            let value = engine.get(format!("writer{}:{}", writer, i).as_bytes());
+            assert_eq!(value, Some(Bytes::from(i.to_string())));
+        }
+    }
+}
+
+// Concurrent is the sharded layout with more shards, never fewer than configured
+#[test]
+fn concurrent_only_scales_the_shard_count() {
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    assert_eq!(BackendKind::Sharded.shard_count(16), 16);
+    assert_eq!(BackendKind::Sharded.shard_count(0), 1);
+    assert_eq!(BackendKind::Concurrent.shard_count(1), (cores * 4).next_power_of_two());
+    assert_eq!(BackendKind::Concurrent.shard_count(100_000), 100_000);
+}
@@ -0,0 +1,165 @@
+// Shared by every integration test crate; each uses only part of it
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use bytes::Bytes;
+use redis_starter_rust::client::Client;
+use redis_starter_rust::clock::{Clock, SystemClock};
+use redis_starter_rust::hasher::HasherKind;
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::storage::{BackendKind, Storage, DEFAULT_SHARDS};
+use redis_starter_rust::{Config, Server};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+static NEXT_DIR: AtomicU64 = AtomicU64::new(0);
+
+// A server on an ephemeral port with its own data directory, stopped and cleaned up when dropped
+pub struct TestServer {
+    addr: SocketAddr,
+    dir: PathBuf,
+    shutdown: Option<oneshot::Sender<()>>,
+    task: Option<JoinHandle<anyhow::Result<()>>>,
+}
+
+impl TestServer {
+    pub async fn start() -> TestServer {
+        TestServer::with_config(Config::default()).await
+    }
+
+    // `config.dir` is replaced by a fresh temp directory
+    pub async fn with_config(config: Config) -> TestServer {
+        TestServer::with_clock(config, Arc::new(SystemClock)).await
+    }
+
+    // Expiry follows `clock`, typically a ManualClock the test advances
+    pub async fn with_clock(config: Config, clock: Arc<dyn Clock>) -> TestServer {
+        TestServer::launch(config, clock, None).await
+    }
+
+    // With the service whose port `port` picks, e.g. |config| &mut config.http_port, on an ephemeral port bound
+    // up front and handed to the server, so it's taken from the start and accepts as soon as this returns.
+    // Returns the port too.
+    pub async fn with_port(mut config: Config, port: fn(&mut Config) -> &mut Option<u16>) -> (TestServer, u16) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind ephemeral port");
+        let bound = listener.local_addr().expect("local addr").port();
+        *port(&mut config) = Some(bound);
+        (TestServer::launch(config, Arc::new(SystemClock), Some(listener)).await, bound)
+    }
+
+    async fn launch(mut config: Config, clock: Arc<dyn Clock>, adopted: Option<std::net::TcpListener>) -> TestServer {
+        let dir = std::env::temp_dir().join(format!("zenql-test-{}-{}", std::process::id(), NEXT_DIR.fetch_add(1, Ordering::Relaxed)));
+        std::fs::create_dir_all(&dir).expect("create test data dir");
+        config.dir = dir.clone();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind ephemeral port");
+        let addr = listener.local_addr().expect("local addr");
+        let (shutdown, stop) = oneshot::channel();
+        let server = Server::with_clock(config, clock).expect("start test server");
+        if let Some(adopted) = adopted {
+            server.adopt_listener(adopted);
+        }
+        let task = tokio::spawn(server.run_until(vec![listener.into()], async {
+            let _ = stop.await;
+        }));
+        TestServer { addr, dir, shutdown: Some(shutdown), task: Some(task) }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub async fn client(&self) -> Client {
+        Client::connect(self.addr).await.expect("connect to test server")
+    }
+
+    // Stops the server and waits until it has closed the listener and every connection
+    pub async fn shutdown(mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(task) = self.task.take() {
+            task.await.expect("server task").expect("server result");
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+// Storage on a write-ahead log in a temp directory of its own, removed when dropped whether the test passed
+// or not. Each open replays what the ones before wrote and synced.
+pub struct WalStorage {
+    dir: PathBuf,
+}
+
+pub fn wal_storage(name: &str) -> WalStorage {
+    let dir = std::env::temp_dir().join(format!("zenql-{}-wal-{}-{}", name, std::process::id(), NEXT_DIR.fetch_add(1, Ordering::Relaxed)));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("create test data dir");
+    WalStorage { dir }
+}
+
+impl WalStorage {
+    pub fn open(&self) -> Storage {
+        Storage::open_wal(BackendKind::Sharded, DEFAULT_SHARDS, HasherKind::default(), &self.dir, 1 << 20, Arc::default(), Arc::new(SystemClock)).expect("open the write-ahead log")
+    }
+}
+
+impl Drop for WalStorage {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub fn bulk(s: &str) -> Value {
+    Value::BulkString(Bytes::copy_from_slice(s.as_bytes()))
+}
+
+pub fn ints(values: &[i64]) -> Value {
+    Value::Array(values.iter().map(|&n| Value::Integer(n)).collect())
+}
+
+// The keys a whole-keyspace SCAN finds holding the type named `kind`, sorted
+pub async fn scan_type(client: &mut Client, kind: &str) -> Vec<Bytes> {
+    let reply = client.call(["SCAN", "0", "TYPE", kind, "COUNT", "1000"]).await.unwrap();
+    let Value::Array(reply) = reply else {
+        panic!("expected a SCAN reply, got {:?}", reply);
+    };
+    let [cursor, Value::Array(keys)] = &reply[..] else {
+        panic!("expected a SCAN reply, got {:?}", reply);
+    };
+    assert_eq!(*cursor, bulk("0"));
+    let mut keys: Vec<Bytes> = keys.iter().map(|key| match key {
+        Value::BulkString(key) => key.clone(),
+        other => panic!("expected a key, got {:?}", other),
+    }).collect();
+    keys.sort();
+    keys
+}
+
+// A map reply, flattened to an array over RESP2, as field and value pairs
+pub fn fields(reply: Value) -> Vec<(Value, Value)> {
+    let Value::Array(items) = reply else {
+        panic!("expected a map, got {:?}", reply);
+    };
+    items.chunks(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect()
+}
+
+pub fn field(fields: &[(Value, Value)], name: &str) -> Value {
+    fields.iter().find(|(field, _)| *field == bulk(name)).unwrap_or_else(|| panic!("no {} in {:?}", name, fields)).1.clone()
+}
@@ -0,0 +1,105 @@
+use bytes::Bytes;
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::tdigest::TDigest;
+use support::{scan_type, wal_storage, TestServer};
+
+mod support;
+
+fn doubles(reply: Value) -> Vec<f64> {
+    let Value::Array(values) = reply else { panic!("expected an array, got {:?}", reply) };
+    values.iter().map(|value| match value {
+        Value::BulkString(s) => std::str::from_utf8(s).unwrap().parse().unwrap(),
+        _ => panic!("expected a double, got {:?}", value),
+    }).collect()
+}
+
+#[tokio::test]
+async fn digest_commands() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.call(["TDIGEST.CREATE", "t"]).await.unwrap();
+    assert!(client.call(["TDIGEST.CREATE", "t"]).await.is_err());
+    assert_eq!(client.call(["TDIGEST.MIN", "t"]).await.unwrap(), Value::BulkString(Bytes::from("nan")));
+    assert_eq!(client.call(["TDIGEST.QUANTILE", "t", "0.5"]).await.unwrap(), Value::Array(vec![Value::BulkString(Bytes::from("nan"))]));
+    client.call(["TDIGEST.ADD", "t", "1", "2", "3", "4", "5"]).await.unwrap();
+    client.call(["TDIGEST.ADD", "t", "6", "7", "8", "9", "10"]).await.unwrap();
+    assert_eq!(doubles(client.call(["TDIGEST.QUANTILE", "t", "0", "0.5", "0.75", "1"]).await.unwrap()), [1.0, 6.0, 8.0, 10.0]);
+    assert_eq!(doubles(client.call(["TDIGEST.CDF", "t", "0", "3", "11"]).await.unwrap()), [0.0, 0.25, 1.0]);
+    assert_eq!(client.call(["TDIGEST.MIN", "t"]).await.unwrap(), Value::BulkString(Bytes::from("1")));
+    assert_eq!(client.call(["TDIGEST.MAX", "t"]).await.unwrap(), Value::BulkString(Bytes::from("10")));
+
+    client.call(["TDIGEST.CREATE", "u", "COMPRESSION", "50"]).await.unwrap();
+    client.call(["TDIGEST.ADD", "u", "100", "200"]).await.unwrap();
+    client.call(["TDIGEST.MERGE", "both", "2", "t", "u"]).await.unwrap();
+    let Value::Array(info) = client.call(["TDIGEST.INFO", "both"]).await.unwrap() else { panic!("TDIGEST.INFO should reply with a map") };
+    assert_eq!((&info[1], &info[5]), (&Value::Integer(100), &Value::Integer(12)));
+    assert_eq!(client.call(["TDIGEST.MAX", "both"]).await.unwrap(), Value::BulkString(Bytes::from("200")));
+    // Merging adds to what the destination had, unless told to override it
+    client.call(["TDIGEST.MERGE", "both", "1", "u"]).await.unwrap();
+    let Value::Array(info) = client.call(["TDIGEST.INFO", "both"]).await.unwrap() else { panic!("TDIGEST.INFO should reply with a map") };
+    assert_eq!(info[5], Value::Integer(14));
+    client.call(["TDIGEST.MERGE", "both", "1", "u", "COMPRESSION", "20", "OVERRIDE"]).await.unwrap();
+    let Value::Array(info) = client.call(["TDIGEST.INFO", "both"]).await.unwrap() else { panic!("TDIGEST.INFO should reply with a map") };
+    assert_eq!((&info[1], &info[5]), (&Value::Integer(20), &Value::Integer(2)));
+
+    client.call(["TDIGEST.RESET", "t"]).await.unwrap();
+    assert_eq!(client.call(["TDIGEST.MAX", "t"]).await.unwrap(), Value::BulkString(Bytes::from("nan")));
+
+    for args in [
+        &["TDIGEST.ADD", "missing", "1"][..], &["TDIGEST.ADD", "t", "x"], &["TDIGEST.ADD", "t", "inf"], &["TDIGEST.QUANTILE", "t", "1.5"],
+        &["TDIGEST.CREATE", "bad", "COMPRESSION", "0"], &["TDIGEST.MERGE", "both", "2", "t"], &["TDIGEST.MERGE", "both", "1", "missing"],
+        &["TDIGEST.MERGE", "both", "1", "t", "COMPRESSION"], &["TDIGEST.INFO", "missing"],
+    ] {
+        assert!(client.call(args).await.is_err(), "{:?}", args);
+    }
+    client.set("string", "value").await.unwrap();
+    assert!(client.call(["TDIGEST.MIN", "string"]).await.unwrap_err().to_string().contains("WRONGTYPE"));
+    assert_eq!(scan_type(&mut client, "TDIS-TYPE").await, ["both", "t", "u"]);
+}
+
+// Quantiles of a large shuffled stream, added in batches, come out within a tenth of a percent of the truth,
+// with far fewer centroids than values
+#[test]
+fn quantiles_are_close() {
+    let mut digest = TDigest::new(100.0).unwrap();
+    let values: Vec<f64> = (0..100_000u64).map(|i| (i.wrapping_mul(48_271) % 100_000) as f64).collect();
+    for batch in values.chunks(500) {
+        digest.add(batch).unwrap();
+    }
+    assert_eq!(digest.weight(), 100_000.0);
+    assert!(digest.centroids() < 200, "{} centroids", digest.centroids());
+    for q in [0.0001, 0.001, 0.01, 0.25, 0.5, 0.75, 0.99, 0.999, 0.9999] {
+        let estimate = digest.quantile(q);
+        assert!((estimate - q * 100_000.0).abs() <= 100.0, "quantile {}: {}", q, estimate);
+        assert!((digest.cdf(q * 100_000.0) - q).abs() <= 0.001, "cdf at {}", q);
+    }
+    assert_eq!((digest.quantile(0.0), digest.quantile(1.0)), (0.0, 99_999.0));
+
+    // Two halves merged give what the whole does
+    let (mut low, mut high) = (TDigest::new(100.0).unwrap(), TDigest::new(100.0).unwrap());
+    low.add(&values[..50_000]).unwrap();
+    high.add(&values[50_000..]).unwrap();
+    low.merge(&high);
+    assert!((low.quantile(0.5) - 50_000.0).abs() <= 100.0);
+    assert_eq!(TDigest::decode(&Bytes::from(digest.encode())), Some(digest.clone()));
+    assert!(TDigest::decode(&Bytes::from(digest.encode()[..20].to_vec())).is_none());
+}
+
+#[test]
+fn digests_are_replayed() {
+    let wal = wal_storage("tdigest");
+    let storage = wal.open();
+    let key = Bytes::from("d");
+    for values in [[1.0, 2.0], [3.0, 4.0]] {
+        storage.update::<TDigest, _>(&key, |digest| {
+            digest.get_or_insert_with(|| TDigest::new(100.0).unwrap()).add(&values).unwrap();
+            ((), true)
+        }).unwrap();
+    }
+    storage.sync().unwrap();
+    drop(storage);
+
+    let storage = wal.open();
+    let found = storage.read::<TDigest, _>(&key, |digest| (digest.weight(), digest.min, digest.max, digest.quantile(0.5))).unwrap();
+    assert_eq!(found, Some((4.0, 1.0, 4.0, 3.0)));
+}
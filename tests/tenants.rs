@@ -0,0 +1,118 @@
+use bytes::Bytes;
+use redis_starter_rust::client::Client;
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::server::Server;
+use redis_starter_rust::tenant::Tenant;
+use redis_starter_rust::Config;
+use support::{bulk, TestServer};
+
+mod support;
+
+async fn start() -> TestServer {
+    let tenants = vec![Tenant::parse("app1=one").unwrap(), Tenant::parse("app2=two").unwrap()];
+    TestServer::with_config(Config { requirepass: Some("admin".to_string()), tenants, ..Config::default() }).await
+}
+
+async fn login(server: &TestServer, user: &str, password: &str) -> Client {
+    let mut client = server.client().await;
+    assert_eq!(client.call(["AUTH", user, password]).await.unwrap(), Value::SimpleString("OK".to_string()));
+    client
+}
+
+fn scanned(reply: Value) -> Vec<Value> {
+    match reply {
+        Value::Array(mut parts) => match parts.pop() {
+            Some(Value::Array(keys)) => keys,
+            other => panic!("expected the keys, got {:?}", other),
+        },
+        other => panic!("expected a SCAN reply, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn tenants_are_isolated() {
+    let server = start().await;
+    let mut one = login(&server, "app1", "one").await;
+    let mut two = login(&server, "app2", "two").await;
+    one.set("user:1", "ada").await.unwrap();
+    one.call(["HSET", "profile", "name", "ada"]).await.unwrap();
+    assert_eq!(two.get("user:1").await.unwrap(), None);
+    two.set("user:1", "grace").await.unwrap();
+    assert_eq!(one.get("user:1").await.unwrap(), Some(Bytes::from("ada")));
+    assert_eq!(two.get("user:1").await.unwrap(), Some(Bytes::from("grace")));
+
+    // Every command's keys are scoped, however many it names
+    assert_eq!(two.call(["DEL", "user:1", "profile"]).await.unwrap(), Value::Integer(1));
+    two.set("user:1", "grace").await.unwrap();
+    two.call(["WATCH", "user:1"]).await.unwrap();
+    two.call(["MULTI"]).await.unwrap();
+    two.call(["SET", "user:2", "linus"]).await.unwrap();
+    two.call(["EXEC"]).await.unwrap();
+
+    // The default user sees them all under their prefixes
+    let mut admin = login(&server, "default", "admin").await;
+    assert_eq!(admin.get("app1:user:1").await.unwrap(), Some(Bytes::from("ada")));
+    assert_eq!(admin.get("app2:user:2").await.unwrap(), Some(Bytes::from("linus")));
+    assert_eq!(admin.call(["DBSIZE"]).await.unwrap(), Value::Integer(4));
+
+    assert_eq!(one.call(["DBSIZE"]).await.unwrap(), Value::Integer(2));
+    let mut keys = scanned(one.call(["SCAN", "0", "COUNT", "100"]).await.unwrap());
+    keys.sort_by_key(|key| format!("{:?}", key));
+    assert_eq!(keys, vec![bulk("profile"), bulk("user:1")]);
+    assert_eq!(scanned(one.call(["SCAN", "0", "COUNT", "100", "MATCH", "user:*"]).await.unwrap()), vec![bulk("user:1")]);
+
+    // Flushing only deletes the tenant's own keys
+    assert_eq!(two.call(["FLUSHDB"]).await.unwrap(), Value::SimpleString("OK".to_string()));
+    assert_eq!(two.call(["DBSIZE"]).await.unwrap(), Value::Integer(0));
+    assert_eq!(one.call(["DBSIZE"]).await.unwrap(), Value::Integer(2));
+    admin.call(["FLUSHALL"]).await.unwrap();
+    assert_eq!(one.call(["DBSIZE"]).await.unwrap(), Value::Integer(0));
+}
+
+#[tokio::test]
+async fn tenants_are_confined() {
+    let server = start().await;
+    let mut one = login(&server, "app1", "one").await;
+    for command in [
+        vec!["INFO"],
+        vec!["SUBSCRIBE", "news"],
+        vec!["PUBLISH", "news", "hi"],
+        vec!["CLIENT", "LIST"],
+        vec!["SNAPSHOT", "EXPORT", "backup.snap"],
+        vec!["ZENQL", "BIGKEYS"],
+        vec!["CMS.MERGE", "dest", "1", "src"],
+        vec!["ZQL", "SELECT * FROM keys"],
+    ] {
+        let err = one.call(command.clone()).await.unwrap_err().to_string();
+        assert!(err.contains("NOPERM User app1 has no permissions"), "{:?}: {}", command, err);
+    }
+    assert!(matches!(one.call(["CLIENT", "ID"]).await.unwrap(), Value::Integer(_)));
+    assert_eq!(one.call(["PING"]).await.unwrap(), Value::SimpleString("PONG".to_string()));
+
+    // Logging in as someone else leaves the tenant
+    assert!(one.call(["AUTH", "app1", "two"]).await.is_err());
+    assert!(one.call(["AUTH", "default", "one"]).await.is_err());
+    assert!(one.call(["AUTH", "app3", "one"]).await.is_err());
+    one.call(["AUTH", "admin"]).await.unwrap();
+    one.call(["INFO"]).await.unwrap();
+}
+
+#[test]
+fn tenant_options() {
+    assert!(Tenant::parse("app1").is_err());
+    assert!(Tenant::parse("app1=").is_err());
+    assert!(Tenant::parse("a:b=pass").is_err());
+    assert!(Tenant::parse("default=pass").is_err());
+
+    let mut config = Config::default();
+    assert!(config.set("tenants", "app1=a app1=b").is_err());
+    config.set("tenants", "app1=a app2=b").unwrap();
+    assert_eq!(config.get("tenants").unwrap(), "app1=a app2=b");
+
+    // Without requirepass, clients that never AUTH would see every tenant's keys
+    let error = Server::new(config.clone()).err().unwrap().to_string();
+    assert!(error.contains("tenants needs requirepass"), "{}", error);
+    config.set("requirepass", "admin").unwrap();
+    config.dir = std::env::temp_dir().join(format!("zenql-tenants-{}", std::process::id()));
+    assert!(Server::new(config).is_ok());
+}
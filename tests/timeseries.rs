@@ -0,0 +1,192 @@
+use bytes::Bytes;
+use redis_starter_rust::client::Client;
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::timeseries::{Aggregation, DuplicatePolicy, Matcher, Rule, TimeSeries};
+use support::{bulk, wal_storage, TestServer};
+
+mod support;
+
+// Samples as a RESP2 client sees them
+fn samples(samples: &[(i64, &str)]) -> Value {
+    Value::Array(samples.iter().map(|&(at, value)| Value::Array(vec![Value::Integer(at), bulk(value)])).collect())
+}
+
+async fn call(client: &mut Client, args: &[&str]) -> Value {
+    client.call(args).await.unwrap()
+}
+
+#[tokio::test]
+async fn adding_and_ranges() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    assert_eq!(call(&mut client, &["TS.CREATE", "temp", "LABELS", "room", "kitchen"]).await, Value::SimpleString("OK".into()));
+    assert!(client.call(["TS.CREATE", "temp"]).await.is_err());
+    for (at, value) in [("1000", "20"), ("2000", "21.5"), ("4000", "19"), ("3000", "22")] {
+        assert_eq!(call(&mut client, &["TS.ADD", "temp", at, value]).await, Value::Integer(at.parse().unwrap()));
+    }
+    assert_eq!(call(&mut client, &["TS.GET", "temp"]).await, Value::Array(vec![Value::Integer(4000), bulk("19")]));
+    assert_eq!(call(&mut client, &["TS.RANGE", "temp", "-", "+"]).await, samples(&[(1000, "20"), (2000, "21.5"), (3000, "22"), (4000, "19")]));
+    assert_eq!(call(&mut client, &["TS.RANGE", "temp", "1500", "3000"]).await, samples(&[(2000, "21.5"), (3000, "22")]));
+    assert_eq!(call(&mut client, &["TS.REVRANGE", "temp", "-", "+", "COUNT", "2"]).await, samples(&[(4000, "19"), (3000, "22")]));
+    assert_eq!(call(&mut client, &["TS.RANGE", "temp", "-", "+", "AGGREGATION", "avg", "2000"]).await, samples(&[(0, "20"), (2000, "21.75"), (4000, "19")]));
+    assert_eq!(call(&mut client, &["TS.RANGE", "temp", "-", "+", "AGGREGATION", "max", "10000"]).await, samples(&[(0, "22")]));
+    assert_eq!(call(&mut client, &["TS.REVRANGE", "temp", "-", "+", "AGGREGATION", "count", "2000", "COUNT", "1"]).await, samples(&[(4000, "1")]));
+
+    // Duplicates are refused unless a policy says what to keep
+    assert!(client.call(["TS.ADD", "temp", "1000", "30"]).await.is_err());
+    call(&mut client, &["TS.ADD", "temp", "1000", "5", "ON_DUPLICATE", "sum"]).await;
+    call(&mut client, &["TS.ALTER", "temp", "DUPLICATE_POLICY", "max"]).await;
+    call(&mut client, &["TS.ADD", "temp", "2000", "1"]).await;
+    assert_eq!(call(&mut client, &["TS.RANGE", "temp", "0", "2000"]).await, samples(&[(1000, "25"), (2000, "21.5")]));
+
+    assert_eq!(call(&mut client, &["TS.DEL", "temp", "2000", "3000"]).await, Value::Integer(2));
+    assert_eq!(call(&mut client, &["TS.RANGE", "temp", "-", "+"]).await, samples(&[(1000, "25"), (4000, "19")]));
+    let reply = call(&mut client, &["TS.MADD", "temp", "5000", "1", "missing", "5000", "1", "temp", "6000", "nan"]).await;
+    let Value::Array(replies) = reply else { panic!("TS.MADD should reply with an array") };
+    assert_eq!(replies[0], Value::Integer(5000));
+    assert!(matches!(&replies[1], Value::Error(_)) && matches!(&replies[2], Value::Error(_)));
+
+    // TS.ADD creates the series it's missing, with the options given
+    call(&mut client, &["TS.ADD", "new", "*", "1.5", "RETENTION", "100", "LABELS", "a", "b"]).await;
+    let info = call(&mut client, &["TS.INFO", "new"]).await;
+    let Value::Array(info) = info else { panic!("TS.INFO should reply with a map") };
+    assert_eq!(info[0..2], [Value::SimpleString("totalSamples".into()), Value::Integer(1)]);
+    assert_eq!(info[6..8], [Value::SimpleString("retentionTime".into()), Value::Integer(100)]);
+    assert_eq!(info[10..12], [Value::SimpleString("labels".into()), Value::Array(vec![Value::Array(vec![bulk("a"), bulk("b")])])]);
+
+    for args in [&["TS.ADD", "temp", "-1", "1"][..], &["TS.ADD", "temp", "1", "x"], &["TS.RANGE", "missing", "-", "+"], &["TS.CREATE", "x", "RETENTION"], &["TS.RANGE", "temp", "-", "+", "AGGREGATION", "median", "10"], &["TS.GET", "missing"]] {
+        assert!(client.call(args).await.is_err(), "{:?}", args);
+    }
+    client.set("string", "value").await.unwrap();
+    assert!(client.call(["TS.ADD", "string", "1", "1"]).await.unwrap_err().to_string().contains("WRONGTYPE"));
+}
+
+#[tokio::test]
+async fn retention() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    call(&mut client, &["TS.CREATE", "s", "RETENTION", "1000"]).await;
+    for at in ["100", "600", "1200"] {
+        call(&mut client, &["TS.ADD", "s", at, "1"]).await;
+    }
+    // 1200 - 1000 = 200: 100 is gone, and nothing older than 200 can be added
+    assert_eq!(call(&mut client, &["TS.RANGE", "s", "-", "+"]).await, samples(&[(600, "1"), (1200, "1")]));
+    assert!(client.call(["TS.ADD", "s", "150", "1"]).await.is_err());
+    call(&mut client, &["TS.ADD", "s", "300", "2"]).await;
+    call(&mut client, &["TS.ALTER", "s", "RETENTION", "100"]).await;
+    assert_eq!(call(&mut client, &["TS.RANGE", "s", "-", "+"]).await, samples(&[(1200, "1")]));
+}
+
+#[tokio::test]
+async fn multiple_series() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    call(&mut client, &["TS.CREATE", "cpu:a", "LABELS", "metric", "cpu", "host", "a"]).await;
+    call(&mut client, &["TS.CREATE", "cpu:b", "LABELS", "metric", "cpu", "host", "b", "dc", "east"]).await;
+    call(&mut client, &["TS.CREATE", "mem:a", "LABELS", "metric", "mem", "host", "a"]).await;
+    for key in ["cpu:a", "cpu:b", "mem:a"] {
+        call(&mut client, &["TS.ADD", key, "10", "1"]).await;
+        call(&mut client, &["TS.ADD", key, "20", "3"]).await;
+    }
+    let query = |filter: &[&str]| ["TS.QUERYINDEX"].into_iter().chain(filter.iter().copied()).map(str::to_string).collect::<Vec<_>>();
+    let keys = |keys: &[&str]| Value::Array(keys.iter().map(|key| bulk(key)).collect());
+    assert_eq!(client.call(query(&["metric=cpu"])).await.unwrap(), keys(&["cpu:a", "cpu:b"]));
+    assert_eq!(client.call(query(&["host=a", "metric!=mem"])).await.unwrap(), keys(&["cpu:a"]));
+    assert_eq!(client.call(query(&["metric=(cpu,mem)", "dc="])).await.unwrap(), keys(&["cpu:a", "mem:a"]));
+    assert_eq!(client.call(query(&["host=a", "dc!="])).await.unwrap(), keys(&[]));
+    assert!(client.call(query(&["dc!=east"])).await.is_err());
+
+    assert_eq!(call(&mut client, &["TS.MRANGE", "-", "+", "AGGREGATION", "sum", "100", "FILTER", "host=a"]).await, Value::Array(vec![
+        Value::Array(vec![bulk("cpu:a"), Value::Array(vec![]), samples(&[(0, "4")])]),
+        Value::Array(vec![bulk("mem:a"), Value::Array(vec![]), samples(&[(0, "4")])]),
+    ]));
+    assert_eq!(call(&mut client, &["TS.MREVRANGE", "-", "+", "WITHLABELS", "COUNT", "1", "FILTER", "dc=east"]).await, Value::Array(vec![
+        Value::Array(vec![bulk("cpu:b"), Value::Array(vec![
+            Value::Array(vec![bulk("metric"), bulk("cpu")]), Value::Array(vec![bulk("host"), bulk("b")]), Value::Array(vec![bulk("dc"), bulk("east")]),
+        ]), samples(&[(20, "3")])]),
+    ]));
+    assert!(client.call(["TS.MRANGE", "-", "+"]).await.is_err());
+}
+
+#[tokio::test]
+async fn compaction_rules() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    call(&mut client, &["TS.CREATE", "raw"]).await;
+    call(&mut client, &["TS.CREATE", "avg"]).await;
+    call(&mut client, &["TS.CREATE", "max"]).await;
+    call(&mut client, &["TS.CREATERULE", "raw", "avg", "AGGREGATION", "avg", "60"]).await;
+    call(&mut client, &["TS.CREATERULE", "raw", "max", "AGGREGATION", "max", "60", "30"]).await;
+    assert!(client.call(["TS.CREATERULE", "max", "avg", "AGGREGATION", "avg", "60"]).await.is_err());
+    assert!(client.call(["TS.CREATERULE", "raw", "raw", "AGGREGATION", "avg", "60"]).await.is_err());
+    assert!(client.call(["TS.CREATERULE", "raw", "missing", "AGGREGATION", "avg", "60"]).await.is_err());
+
+    for (at, value) in [("0", "1"), ("20", "3"), ("59", "5"), ("60", "10"), ("100", "20")] {
+        call(&mut client, &["TS.ADD", "raw", at, value]).await;
+    }
+    // Only closed buckets are compacted: [0, 60) so far, and [-30, 30) and [30, 90) aligned to 30
+    assert_eq!(call(&mut client, &["TS.RANGE", "avg", "-", "+"]).await, samples(&[(0, "3")]));
+    assert_eq!(call(&mut client, &["TS.RANGE", "max", "-", "+"]).await, samples(&[(-30, "3"), (30, "10")]));
+    // A late sample in a closed bucket updates its aggregate
+    call(&mut client, &["TS.ADD", "raw", "30", "11"]).await;
+    assert_eq!(call(&mut client, &["TS.RANGE", "avg", "-", "+"]).await, samples(&[(0, "5")]));
+    call(&mut client, &["TS.ADD", "raw", "125", "0"]).await;
+    assert_eq!(call(&mut client, &["TS.RANGE", "avg", "-", "+"]).await, samples(&[(0, "5"), (60, "15")]));
+
+    let Value::Array(info) = call(&mut client, &["TS.INFO", "raw"]).await else { panic!("TS.INFO should reply with a map") };
+    assert_eq!(info[15], Value::Array(vec![
+        Value::Array(vec![bulk("avg"), Value::Integer(60), Value::SimpleString("avg".into()), Value::Integer(0)]),
+        Value::Array(vec![bulk("max"), Value::Integer(60), Value::SimpleString("max".into()), Value::Integer(30)]),
+    ]));
+    let Value::Array(info) = call(&mut client, &["TS.INFO", "avg"]).await else { panic!("TS.INFO should reply with a map") };
+    assert_eq!(info[13], bulk("raw"));
+
+    call(&mut client, &["TS.DELETERULE", "raw", "avg"]).await;
+    assert!(client.call(["TS.DELETERULE", "raw", "avg"]).await.is_err());
+    call(&mut client, &["TS.ADD", "raw", "200", "0"]).await;
+    assert_eq!(call(&mut client, &["TS.RANGE", "avg", "-", "+"]).await, samples(&[(0, "5"), (60, "15")]));
+    call(&mut client, &["TS.CREATERULE", "max", "avg", "AGGREGATION", "last", "1000"]).await;
+}
+
+#[test]
+fn series_are_replayed() {
+    let wal = wal_storage("ts");
+    let storage = wal.open();
+    let key = Bytes::from("s");
+    let mut expected = TimeSeries::default();
+    (expected.retention, expected.duplicate_policy) = (5000, DuplicatePolicy::Sum);
+    expected.labels.push(("k".into(), "v".into()));
+    expected.rules.push(Rule { dest: Bytes::from("d"), aggregation: Aggregation::StdS, bucket: 10, align: -3 });
+    expected.source = Some(Bytes::from("src"));
+    for at in 0..100 {
+        expected.add((at * 7, at as f64 / 3.0), None).unwrap();
+    }
+    storage.update::<TimeSeries, _>(&key, |series| {
+        *series = Some(expected.clone());
+        ((), true)
+    }).unwrap();
+    storage.sync().unwrap();
+    drop(storage);
+
+    let storage = wal.open();
+    assert_eq!(storage.read::<TimeSeries, _>(&key, Clone::clone).unwrap(), Some(expected.clone()));
+    assert_eq!(TimeSeries::decode(&Bytes::from(expected.encode())), Some(expected.clone()));
+    let encoded = expected.encode();
+    assert_eq!(TimeSeries::decode(&Bytes::copy_from_slice(&encoded[..encoded.len() - 1])), None);
+}
+
+#[test]
+fn matchers_and_aggregations() {
+    let mut series = TimeSeries::default();
+    series.labels.push(("a".into(), "1".into()));
+    for (filter, matches) in [("a=1", true), ("a!=1", false), ("a=(2, 1)", true), ("a!=(2,3)", true), ("b=", true), ("b!=", false), ("a=", false)] {
+        assert_eq!(Matcher::parse(filter).unwrap().matches(&series), matches, "{}", filter);
+    }
+    assert!(Matcher::parse("nolabel").is_err() && Matcher::parse("=1").is_err());
+    let values = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+    assert_eq!(Aggregation::StdP.apply(&values), 2.0);
+    assert_eq!(Aggregation::VarS.apply(&values), 32.0 / 7.0);
+    assert_eq!(Aggregation::Range.apply(&values), 7.0);
+    assert_eq!(Aggregation::StdS.apply(&[1.0]), 0.0);
+    assert_eq!(Aggregation::parse("STD.P").unwrap(), Aggregation::StdP);
+}
@@ -0,0 +1,85 @@
+use bytes::Bytes;
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::topk::TopK;
+use support::{bulk, scan_type, wal_storage, TestServer};
+
+mod support;
+
+#[tokio::test]
+async fn topk_commands() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.call(["TOPK.RESERVE", "top", "2", "50", "4", "0.9"]).await.unwrap();
+    assert!(client.call(["TOPK.RESERVE", "top", "3"]).await.is_err());
+    assert_eq!(client.call(["TOPK.ADD", "top", "a", "b", "a"]).await.unwrap(), Value::Array(vec![Value::Null; 3]));
+    assert_eq!(client.call(["TOPK.INCRBY", "top", "c", "5"]).await.unwrap(), Value::Array(vec![bulk("b")]));
+    assert_eq!(client.call(["TOPK.LIST", "top"]).await.unwrap(), Value::Array(vec![bulk("c"), bulk("a")]));
+    assert_eq!(client.call(["TOPK.LIST", "top", "WITHCOUNT"]).await.unwrap(), Value::Array(vec![bulk("c"), Value::Integer(5), bulk("a"), Value::Integer(2)]));
+    assert_eq!(client.call(["TOPK.QUERY", "top", "a", "b", "c"]).await.unwrap(), Value::Array(vec![Value::Integer(1), Value::Integer(0), Value::Integer(1)]));
+    assert_eq!(client.call(["TOPK.COUNT", "top", "a", "b", "zzz"]).await.unwrap(), Value::Array(vec![Value::Integer(2), Value::Integer(1), Value::Integer(0)]));
+    assert_eq!(client.call(["TOPK.INFO", "top"]).await.unwrap(), Value::Array(vec![
+        Value::SimpleString("k".into()), Value::Integer(2),
+        Value::SimpleString("width".into()), Value::Integer(50),
+        Value::SimpleString("depth".into()), Value::Integer(4),
+        Value::SimpleString("decay".into()), bulk("0.9"),
+    ]));
+
+    for args in [
+        &["TOPK.ADD", "missing", "a"][..], &["TOPK.LIST", "missing"], &["TOPK.RESERVE", "bad", "0"], &["TOPK.RESERVE", "bad", "5", "8"],
+        &["TOPK.RESERVE", "bad", "5", "8", "7", "1.5"], &["TOPK.INCRBY", "top", "a"], &["TOPK.INCRBY", "top", "a", "-1"], &["TOPK.LIST", "top", "COUNTS"],
+    ] {
+        assert!(client.call(args).await.is_err(), "{:?}", args);
+    }
+    client.set("string", "value").await.unwrap();
+    assert!(client.call(["TOPK.LIST", "string"]).await.unwrap_err().to_string().contains("WRONGTYPE"));
+    assert_eq!(scan_type(&mut client, "TopK-TYPE").await, ["top"]);
+}
+
+// In a skewed stream over many more items than there are buckets, the heaviest are the ones kept, with counts
+// close to and never over the truth
+#[test]
+fn heavy_hitters_are_found() {
+    let mut topk = TopK::new(10, 200, 5, 0.9).unwrap();
+    let mut truth = vec![0u64; 10_000];
+    for i in 0..200_000u64 {
+        // Half the stream is the first 10 items, item n weighted n + 1; the rest is noise over many others
+        let roll = (i.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 40) % 110;
+        let item = match (0..10).find(|&n| roll < (n + 1) * (n + 2) / 2) {
+            Some(n) => n,
+            None => 10 + i.wrapping_mul(2_654_435_761) % 9990,
+        };
+        truth[item as usize] += 1;
+        topk.add(format!("item:{}", item).as_bytes(), 1);
+    }
+    let mut heaviest: Vec<usize> = (0..truth.len()).collect();
+    heaviest.sort_by_key(|&item| std::cmp::Reverse(truth[item]));
+    let found: Vec<String> = topk.list().iter().map(|(item, _)| String::from_utf8(item.to_vec()).unwrap()).collect();
+    let expected: Vec<String> = heaviest[..10].iter().map(|item| format!("item:{}", item)).collect();
+    assert_eq!(found, expected);
+    for (item, count) in topk.list() {
+        let n: usize = std::str::from_utf8(&item[5..]).unwrap().parse().unwrap();
+        assert!(*count <= truth[n] && *count * 10 >= truth[n] * 9, "item {}: {} against {}", n, count, truth[n]);
+    }
+    assert!(topk.list().windows(2).all(|pair| pair[0].1 >= pair[1].1));
+    assert_eq!(TopK::decode(&Bytes::from(topk.encode())), Some(topk.clone()));
+    assert!(TopK::decode(&Bytes::from(topk.encode()[..100].to_vec())).is_none());
+}
+
+#[test]
+fn topks_are_replayed() {
+    let wal = wal_storage("topk");
+    let storage = wal.open();
+    let key = Bytes::from("t");
+    for (item, by) in [("a", 3), ("b", 1), ("c", 2)] {
+        storage.update::<TopK, _>(&key, |topk| {
+            topk.get_or_insert_with(|| TopK::new(2, 8, 7, 0.9).unwrap()).add(item.as_bytes(), by);
+            ((), true)
+        }).unwrap();
+    }
+    storage.sync().unwrap();
+    drop(storage);
+
+    let storage = wal.open();
+    let found = storage.read::<TopK, _>(&key, |topk| topk.list().to_vec()).unwrap();
+    assert_eq!(found, Some(vec![(Bytes::from("a"), 3), (Bytes::from("c"), 2)]));
+}
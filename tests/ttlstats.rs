@@ -0,0 +1,56 @@
+use std::sync::Arc;
+use std::time::Duration;
+use redis_starter_rust::clock::ManualClock;
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::Config;
+use support::{bulk, field, fields, TestServer};
+
+mod support;
+
+// The buckets holding any keys, by name
+fn histogram(stats: &[(Value, Value)]) -> Vec<(Value, Value)> {
+    fields(field(stats, "histogram")).into_iter().filter(|(_, count)| *count != Value::Integer(0)).collect()
+}
+
+#[tokio::test]
+async fn ttl_histogram() {
+    let clock = Arc::new(ManualClock::new(1_700_000_000_000));
+    let server = TestServer::with_clock(Config::default(), clock.clone()).await;
+    let mut client = server.client().await;
+    client.set("plain", "v").await.unwrap();
+    client.call(["SET", "session:1", "v", "EX", "30"]).await.unwrap();
+    client.call(["SET", "session:2", "v", "EX", "40"]).await.unwrap();
+    client.call(["SET", "session:3", "v", "EX", "7200"]).await.unwrap();
+    client.call(["SET", "cache:1", "v", "EX", "86400"]).await.unwrap();
+    client.call(["SET", "cache:2", "v", "EX", "100000000"]).await.unwrap();
+    // Expired but not yet removed, which isn't counted
+    client.call(["SET", "gone", "v", "PX", "10"]).await.unwrap();
+    clock.advance(Duration::from_millis(20));
+
+    let stats = fields(client.call(["TTLSTATS"]).await.unwrap());
+    assert_eq!(field(&stats, "keys"), Value::Integer(6));
+    assert_eq!(field(&stats, "persistent"), Value::Integer(1));
+    assert_eq!(field(&stats, "volatile"), Value::Integer(5));
+    assert_eq!(field(&stats, "ttl.min"), Value::Integer(29_980));
+    assert_eq!(field(&stats, "ttl.max"), Value::Integer(99_999_999_980));
+    assert_eq!(histogram(&stats), vec![
+        (bulk("1m"), Value::Integer(2)),
+        (bulk("6h"), Value::Integer(1)),
+        (bulk("1d"), Value::Integer(1)),
+        (bulk("inf"), Value::Integer(1)),
+    ]);
+
+    let stats = fields(client.call(["TTLSTATS", "MATCH", "session:*"]).await.unwrap());
+    assert_eq!(field(&stats, "keys"), Value::Integer(3));
+    assert_eq!(field(&stats, "persistent"), Value::Integer(0));
+    assert_eq!(histogram(&stats), vec![(bulk("1m"), Value::Integer(2)), (bulk("6h"), Value::Integer(1))]);
+
+    let stats = fields(client.call(["TTLSTATS", "MATCH", "pl*"]).await.unwrap());
+    assert_eq!(field(&stats, "keys"), Value::Integer(1));
+    assert_eq!(field(&stats, "persistent"), Value::Integer(1));
+    assert_eq!(field(&stats, "ttl.avg"), Value::Null);
+    assert_eq!(histogram(&stats), vec![]);
+
+    assert!(client.call(["TTLSTATS", "MATCH"]).await.is_err());
+    assert!(client.call(["TTLSTATS", "COUNT", "1"]).await.is_err());
+}
@@ -0,0 +1,150 @@
+use bytes::Bytes;
+use redis_starter_rust::client::Client;
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::search::Knn;
+use redis_starter_rust::vector::{Algorithm, Metric, VectorIndex, VectorParams};
+use support::{bulk, TestServer};
+
+mod support;
+
+fn arg(s: &str) -> Bytes {
+    Bytes::from(s.to_string())
+}
+
+fn blob(vector: &[f32]) -> Bytes {
+    vector.iter().flat_map(|n| n.to_le_bytes()).collect::<Vec<u8>>().into()
+}
+
+// The keys a KNN query found, nearest first
+async fn knn(client: &mut Client, index: &str, query: &str, vector: &[f32]) -> Vec<String> {
+    let args = [arg("FT.SEARCH"), arg(index), arg(query), arg("NOCONTENT"), arg("PARAMS"), arg("2"), arg("v"), blob(vector)];
+    let Value::Array(reply) = client.call(args).await.unwrap() else {
+        panic!("FT.SEARCH should reply with an array");
+    };
+    reply[1..].iter().map(|key| match key {
+        Value::BulkString(key) => String::from_utf8(key.to_vec()).unwrap(),
+        other => panic!("unexpected {:?}", other),
+    }).collect()
+}
+
+async fn hset(client: &mut Client, key: &str, color: &str, vector: &[f32]) {
+    client.call([arg("HSET"), arg(key), arg("color"), arg(color), arg("embedding"), blob(vector)]).await.unwrap();
+}
+
+#[tokio::test]
+async fn knn_over_hashes() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    for algorithm in ["FLAT", "HNSW"] {
+        let index = format!("items-{}", algorithm);
+        let prefix = format!("{}:", algorithm);
+        client.call([
+            "FT.CREATE", &index, "PREFIX", "1", &prefix, "SCHEMA", "color", "TEXT",
+            "embedding", "VECTOR", algorithm, "6", "TYPE", "FLOAT32", "DIM", "2", "DISTANCE_METRIC", "L2",
+        ]).await.unwrap();
+        let key = |n: u32| format!("{}{}", prefix, n);
+        hset(&mut client, &key(1), "red", &[0.0, 0.0]).await;
+        hset(&mut client, &key(2), "blue", &[1.0, 0.0]).await;
+        hset(&mut client, &key(3), "red", &[5.0, 5.0]).await;
+        hset(&mut client, &key(4), "red", &[0.9, 0.1]).await;
+        // A vector of the wrong size leaves the key out of the vector index, not the text one
+        client.call(["HSET", &key(5), "color", "red", "embedding", "short"]).await.unwrap();
+
+        assert_eq!(knn(&mut client, &index, "*=>[KNN 2 @embedding $v]", &[1.0, 0.0]).await, [key(2), key(4)]);
+        assert_eq!(knn(&mut client, &index, "=>[KNN 10 @embedding $v]", &[4.0, 4.0]).await, [key(3), key(4), key(2), key(1)]);
+        // Filtered: only red ones
+        assert_eq!(knn(&mut client, &index, "red=>[KNN 2 @embedding $v EF_RUNTIME 50]", &[1.0, 0.0]).await, [key(4), key(1)]);
+
+        // Kept up to date as the hashes change
+        hset(&mut client, &key(1), "red", &[10.0, 10.0]).await;
+        client.call(["DEL", &key(2)]).await.unwrap();
+        assert_eq!(knn(&mut client, &index, "*=>[KNN 2 @embedding $v]", &[9.0, 9.0]).await, [key(1), key(3)]);
+        assert_eq!(knn(&mut client, &index, "*=>[KNN 5 @embedding $v]", &[1.0, 0.0]).await, [key(4), key(3), key(1)]);
+    }
+
+    // Distances are the scores, and part of the content
+    let args = [arg("FT.SEARCH"), arg("items-FLAT"), arg("*=>[KNN 1 @embedding $v]"), arg("WITHSCORES"), arg("PARAMS"), arg("2"), arg("v"), blob(&[0.9, 1.1])];
+    assert_eq!(client.call(args).await.unwrap(), Value::Array(vec![
+        Value::Integer(1), bulk("FLAT:4"), bulk("1"), Value::Array(vec![
+            bulk("color"), bulk("red"), bulk("embedding"), Value::BulkString(blob(&[0.9, 0.1])), bulk("__embedding_score"), bulk("1"),
+        ]),
+    ]));
+
+    for (query, vector) in [("*=>[KNN 1 @color $v]", blob(&[0.0, 0.0])), ("*=>[KNN 1 @embedding $v]", blob(&[0.0])), ("*=>[KNN 1 @embedding $w]", blob(&[0.0, 0.0])), ("*=>KNN 1", blob(&[0.0, 0.0]))] {
+        let args = [arg("FT.SEARCH"), arg("items-FLAT"), arg(query), arg("PARAMS"), arg("2"), arg("v"), vector];
+        assert!(client.call(args).await.is_err(), "{}", query);
+    }
+    for attributes in [&["DIM", "2"][..], &["DIM", "0", "DISTANCE_METRIC", "L2"], &["DIM", "2", "DISTANCE_METRIC", "DOT"], &["TYPE", "FLOAT64", "DIM", "2", "DISTANCE_METRIC", "L2"]] {
+        let nargs = attributes.len().to_string();
+        let args = ["FT.CREATE", "bad", "SCHEMA", "v", "VECTOR", "FLAT", &nargs].into_iter().chain(attributes.iter().copied());
+        assert!(client.call(args).await.is_err(), "{:?}", attributes);
+    }
+    assert!(client.call(["FT.CREATE", "bad", "SCHEMA", "v", "VECTOR", "FLAT", "4", "DIM", "2"]).await.is_err());
+}
+
+#[tokio::test]
+async fn knn_over_documents() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.call([
+        "FT.CREATE", "docs", "ON", "JSON", "SCHEMA", "$.title", "TEXT",
+        "$.embedding", "VECTOR", "HNSW", "8", "DIM", "3", "DISTANCE_METRIC", "COSINE", "M", "4", "EF_CONSTRUCTION", "20",
+    ]).await.unwrap();
+    client.call(["JSON.SET", "doc:1", "$", r#"{"title":"cats","embedding":[1,0,0]}"#]).await.unwrap();
+    client.call(["JSON.SET", "doc:2", "$", r#"{"title":"dogs","embedding":[10,10,0]}"#]).await.unwrap();
+    client.call(["JSON.SET", "doc:3", "$", r#"{"title":"birds","embedding":[0,0,2]}"#]).await.unwrap();
+    client.call(["JSON.SET", "doc:4", "$", r#"{"title":"fish","embedding":[1,"x",0]}"#]).await.unwrap();
+    // Cosine ignores length: [3, 1, 0] is nearer [1, 1, 0] in direction than [1, 0, 0]
+    assert_eq!(knn(&mut client, "docs", "*=>[KNN 3 @$.embedding $v]", &[3.0, 1.0, 0.0]).await, ["doc:1", "doc:2", "doc:3"]);
+    assert_eq!(knn(&mut client, "docs", "*=>[KNN 1 @$.embedding $v]", &[1.0, 1.0, 0.0]).await, ["doc:2"]);
+    client.call(["JSON.SET", "doc:3", "$.embedding", "[1,1,0.1]"]).await.unwrap();
+    assert_eq!(knn(&mut client, "docs", "*=>[KNN 2 @$.embedding $v]", &[1.0, 1.0, 0.0]).await, ["doc:2", "doc:3"]);
+    assert_eq!(knn(&mut client, "docs", "birds | cats=>[KNN 1 @$.embedding $v]", &[1.0, 1.0, 0.0]).await, ["doc:3"]);
+}
+
+// HNSW is approximate; on random vectors it should still find nearly all of the exact nearest neighbours
+#[test]
+fn hnsw_recall() {
+    let params = |algorithm| VectorParams { dim: 16, metric: Metric::L2, algorithm };
+    let mut flat = VectorIndex::new(params(Algorithm::Flat));
+    let mut hnsw = VectorIndex::new(params(Algorithm::Hnsw { m: 16, ef_construction: 100, ef_runtime: 50 }));
+    let mut state = 12345u64;
+    let mut random = move || {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (state >> 40) as f32 / (1u64 << 24) as f32
+    };
+    let keys: Vec<Bytes> = (0..600).map(|i| Bytes::from(format!("v{}", i))).collect();
+    for key in &keys {
+        let vector: Vec<f32> = (0..16).map(|_| random()).collect();
+        flat.insert(key, vector.clone());
+        hnsw.insert(key, vector);
+    }
+    // Deleting a good share exercises the tombstones and, past half, the rebuild
+    for key in keys.iter().step_by(3).chain(keys.iter().skip(1).step_by(3).take(120)) {
+        flat.remove(key);
+        hnsw.remove(key);
+    }
+    assert!(hnsw.get(&keys[0]).is_none() && hnsw.get(&keys[2]).is_some());
+    let (mut hits, mut total) = (0, 0);
+    for _ in 0..50 {
+        let query: Vec<f32> = (0..16).map(|_| random()).collect();
+        let exact: Vec<_> = flat.nearest(&query, 10, None).into_iter().map(|(_, key)| key.clone()).collect();
+        let approximate: Vec<_> = hnsw.nearest(&query, 10, None).into_iter().map(|(_, key)| key.clone()).collect();
+        assert_eq!(approximate.len(), 10);
+        hits += exact.iter().filter(|key| approximate.contains(key)).count();
+        total += exact.len();
+    }
+    assert!(hits * 100 >= total * 90, "recall {}/{}", hits, total);
+}
+
+#[test]
+fn knn_clauses() {
+    let (filter, knn) = Knn::split("@title:(a | b) => [KNN 5 @vec $blob EF_RUNTIME 20]").unwrap();
+    assert_eq!(filter, "@title:(a | b)");
+    assert_eq!(knn, Some(Knn { k: 5, field: "vec".into(), param: "blob".into(), ef_runtime: Some(20) }));
+    assert_eq!(Knn::split("plain").unwrap(), ("plain", None));
+    assert_eq!(Knn::split("=>[knn 1 @v $p]").unwrap().0, "*");
+    for bad in ["*=>KNN 1 @v $p", "*=>[KNN x @v $p]", "*=>[KNN 1 v $p]", "*=>[KNN 1 @v p]", "*=>[KNN 1 @v $p EF 2]", "*=>[KNN 1 @v $p EF_RUNTIME 0]"] {
+        assert!(Knn::split(bad).is_err(), "{}", bad);
+    }
+}
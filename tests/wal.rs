@@ -0,0 +1,155 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use bytes::Bytes;
+use redis_starter_rust::wal::WAL_DIR;
+use redis_starter_rust::{Config, Engine};
+use support::TestServer;
+
+mod support;
+
+const SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("zenql-wal-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Segment files, oldest first
+fn segments(dir: &Path) -> Vec<PathBuf> {
+    let mut segments: Vec<_> = std::fs::read_dir(dir.join(WAL_DIR)).unwrap().map(|entry| entry.unwrap().path()).collect();
+    segments.sort();
+    segments
+}
+
+#[test]
+fn restart_replays_the_log() {
+    let dir = temp_dir("replay");
+    let engine = Engine::open_wal(&dir, SEGMENT_SIZE).unwrap();
+    engine.set("string", "value");
+    engine.set("number", "42");
+    engine.set_ex("expiring", "value", Duration::from_secs(100));
+    engine.set("persisted", "value");
+    engine.expire(b"persisted", Duration::from_secs(100));
+    engine.persist(b"persisted");
+    engine.set("deleted", "value");
+    engine.del(b"deleted");
+    engine.sync().unwrap();
+    drop(engine);
+
+    let engine = Engine::open_wal(&dir, SEGMENT_SIZE).unwrap();
+    assert_eq!(engine.get(b"string"), Some(Bytes::from("value")));
+    assert_eq!(engine.get(b"number"), Some(Bytes::from("42")));
+    assert!(engine.ttl(b"expiring").unwrap().is_some());
+    assert_eq!(engine.ttl(b"persisted"), Some(None));
+    assert_eq!(engine.get(b"deleted"), None);
+    assert_eq!(engine.len(), 4);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn partial_record_is_cut_off() {
+    let dir = temp_dir("partial");
+    let engine = Engine::open_wal(&dir, SEGMENT_SIZE).unwrap();
+    engine.set("key", "value");
+    drop(engine);
+    let path = segments(&dir).pop().unwrap();
+    let size = std::fs::metadata(&path).unwrap().len();
+
+    // A set record that stops halfway through the key, as a crash mid-write leaves it
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[1, 10, 0, 0, 0, 0, 0, 0, 0, b'o', b't']).unwrap();
+    drop(file);
+    let engine = Engine::open_wal(&dir, SEGMENT_SIZE).unwrap();
+    assert_eq!(engine.get(b"key"), Some(Bytes::from("value")));
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), size);
+
+    // Anything else that doesn't parse is an error rather than data loss
+    drop(engine);
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[9; 32]).unwrap();
+    drop(file);
+    assert!(Engine::open_wal(&dir, SEGMENT_SIZE).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn segments_rotate_and_checkpoint() {
+    let dir = temp_dir("rotate");
+    let engine = Engine::open_wal(&dir, 512).unwrap();
+    for round in 0..10 {
+        for i in 0..20 {
+            engine.set(format!("key{}", i), format!("value{}-{}", i, round));
+        }
+        engine.sync().unwrap();
+    }
+    engine.del(b"key0");
+    assert!(segments(&dir).len() >= 10, "{:?}", segments(&dir));
+
+    // A partial record only counts as a crash in the last segment
+    let middle = segments(&dir)[1].clone();
+    let mut file = OpenOptions::new().append(true).open(&middle).unwrap();
+    file.write_all(&[1, 10, 0, 0, 0, 0, 0, 0, 0, b'o', b't']).unwrap();
+    drop(file);
+    engine.sync().unwrap();
+    assert!(Engine::open_wal(&dir, 512).is_err());
+
+    // A checkpoint leaves a segment holding the keyspace, and the one logging went on in
+    engine.compact().unwrap();
+    assert_eq!(segments(&dir).len(), 2);
+    engine.set("after", "checkpoint");
+    drop(engine);
+    let engine = Engine::open_wal(&dir, 512).unwrap();
+    assert_eq!(engine.get(b"key7"), Some(Bytes::from("value7-9")));
+    assert_eq!(engine.get(b"key0"), None);
+    assert_eq!(engine.get(b"after"), Some(Bytes::from("checkpoint")));
+    assert_eq!(engine.len(), 20);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn checkpoints_run_alongside_writes() {
+    let dir = temp_dir("alongside");
+    let engine = Engine::open_wal(&dir, 4096).unwrap();
+    for i in 0..1000 {
+        engine.set(format!("key{}", i), "first");
+    }
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            for round in 0..20 {
+                for i in 0..1000 {
+                    engine.set(format!("key{}", i), format!("{}", round));
+                }
+                engine.del(format!("key{}", round).as_bytes());
+                engine.sync().unwrap();
+            }
+        });
+        for _ in 0..10 {
+            engine.compact().unwrap();
+        }
+    });
+    drop(engine);
+
+    // Whatever the checkpoints caught, the log replays to where the writes ended
+    let engine = Engine::open_wal(&dir, 4096).unwrap();
+    for i in 0..1000 {
+        let expected = if i == 19 { None } else { Some(Bytes::from("19")) };
+        assert_eq!(engine.get(format!("key{}", i).as_bytes()), expected, "key{}", i);
+    }
+    assert!(!std::fs::read_dir(dir.join(WAL_DIR)).unwrap().any(|entry| entry.unwrap().path().extension().is_some_and(|e| e == "tmp")));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn replies_wait_for_the_log() {
+    let config = Config { wal: true, ..Config::default() };
+    let server = TestServer::with_config(config).await;
+    let mut client = server.client().await;
+    let before: u64 = segments(server.dir()).iter().map(|path| std::fs::metadata(path).unwrap().len()).sum();
+    client.set("key", "x".repeat(1000)).await.unwrap();
+    let after: u64 = segments(server.dir()).iter().map(|path| std::fs::metadata(path).unwrap().len()).sum();
+    assert!(after >= before + 1000, "{} bytes logged before the reply, {} after", before, after);
+}
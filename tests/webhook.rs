@@ -0,0 +1,118 @@
+use std::time::Duration;
+use redis_starter_rust::json::Json;
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::webhook::Webhook;
+use redis_starter_rust::Config;
+use support::TestServer;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+mod support;
+
+// Reads one request off the connection, answers it with `status` and returns its path and events as
+// (event, key) pairs
+async fn receive(stream: &mut TcpStream, status: &str) -> (String, Vec<(String, String)>) {
+    let mut request = vec![];
+    let mut chunk = [0; 4096];
+    let body_start = loop {
+        let n = stream.read(&mut chunk).await.unwrap();
+        assert!(n > 0, "connection closed mid-request");
+        request.extend_from_slice(&chunk[..n]);
+        if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+    };
+    let head = String::from_utf8_lossy(&request[..body_start]).to_string();
+    let length: usize = head.lines()
+        .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|value| value.trim().parse().unwrap()))
+        .unwrap();
+    while request.len() < body_start + length {
+        let n = stream.read(&mut chunk).await.unwrap();
+        request.extend_from_slice(&chunk[..n]);
+    }
+    stream.write_all(format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).as_bytes()).await.unwrap();
+    let path = head.split_whitespace().nth(1).unwrap().to_string();
+    let Ok(Json::Object(fields)) = Json::parse(&request[body_start..]) else {
+        panic!("not a JSON object: {:?}", String::from_utf8_lossy(&request[body_start..]));
+    };
+    let Json::Array(events) = &fields[0].1 else {
+        panic!("no events in {:?}", fields);
+    };
+    let events = events.iter().map(|event| match event {
+        Json::Object(fields) => match (&fields[0].1, &fields[1].1) {
+            (Json::String(event), Json::String(key)) => (event.clone(), key.clone()),
+            _ => panic!("malformed event {:?}", event),
+        },
+        _ => panic!("malformed event {:?}", event),
+    }).collect();
+    (path, events)
+}
+
+// Events delivered until `count` have arrived, answering each request with the next of `statuses`, then 200
+async fn collect(listener: &TcpListener, count: usize, statuses: &[&str]) -> Vec<(String, String)> {
+    let mut events = vec![];
+    let mut statuses = statuses.iter();
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while events.len() < count {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let status = statuses.next().copied().unwrap_or("200 OK");
+            let (path, batch) = receive(&mut stream, status).await;
+            assert_eq!(path, "/hook");
+            if status.starts_with('2') {
+                events.extend(batch);
+            }
+        }
+    }).await.expect("events never arrived");
+    events
+}
+
+#[tokio::test]
+async fn webhook_filters_and_retries() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let spec = format!("http://{}/hook;events=set,del;match=user:*", listener.local_addr().unwrap());
+    let config = Config { webhooks: vec![Webhook::parse(&spec).unwrap()], ..Config::default() };
+    let server = TestServer::with_config(config).await;
+    let mut client = server.client().await;
+
+    client.set("user:1", "ada").await.unwrap();
+    client.set("order:1", "skipped, the pattern doesn't match").await.unwrap();
+    client.call(["HSET", "user:2", "name", "grace"]).await.unwrap();
+    client.call(["DEL", "user:1"]).await.unwrap();
+    client.call(["INCR", "user:3"]).await.unwrap();
+
+    // The first delivery fails, and the same events come again
+    let events = collect(&listener, 4, &["503 Service Unavailable"]).await;
+    let expected = [("set", "user:1"), ("set", "user:2"), ("del", "user:1"), ("set", "user:3")];
+    assert_eq!(events, expected.map(|(event, key)| (event.to_string(), key.to_string())));
+    let Value::BulkString(info) = client.call(["INFO", "stats"]).await.unwrap() else {
+        panic!("INFO didn't return a bulk string");
+    };
+    let info = String::from_utf8_lossy(&info);
+    assert!(info.contains("webhook_retries:1\r\n"), "{}", info);
+}
+
+#[tokio::test]
+async fn webhook_expired_events() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let spec = format!("http://{}/hook;events=expired", listener.local_addr().unwrap());
+    let config = Config { webhooks: vec![Webhook::parse(&spec).unwrap()], hz: 50, ..Config::default() };
+    let server = TestServer::with_config(config).await;
+    let mut client = server.client().await;
+
+    client.call(["SET", "session", "x", "PX", "20"]).await.unwrap();
+    let events = collect(&listener, 1, &[]).await;
+    assert_eq!(events, [("expired".to_string(), "session".to_string())]);
+}
+
+#[test]
+fn webhook_specs() {
+    assert!(Webhook::parse("http://localhost").is_ok());
+    assert!(Webhook::parse("http://[::1]:8080/hook;events=expired,evicted").is_ok());
+    assert!(Webhook::parse("https://example.com/hook").is_err());
+    assert!(Webhook::parse("http://example.com;events=flushed").is_err());
+    assert!(Webhook::parse("http://example.com;when=now").is_err());
+
+    let mut config = Config::default();
+    config.set("webhooks", "http://a:1/x;match=user:* http://b:2").unwrap();
+    assert_eq!(config.get("webhooks").unwrap(), "http://a:1/x;match=user:* http://b:2");
+}
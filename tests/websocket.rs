@@ -0,0 +1,180 @@
+use std::time::Duration;
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::Config;
+use support::TestServer;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+mod support;
+
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+async fn start(config: Config) -> (TestServer, u16) {
+    TestServer::with_port(config, |config| &mut config.http_port).await
+}
+
+async fn connect(port: u16) -> TcpStream {
+    TcpStream::connect(("127.0.0.1", port)).await.unwrap()
+}
+
+// Sends the upgrade request with `headers` and returns the response head
+async fn upgrade(stream: &mut TcpStream, headers: &str) -> String {
+    let request = format!("GET /ws HTTP/1.1\r\nHost: zenql\r\n{}\r\n", headers);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut head = vec![];
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        assert_eq!(stream.read(&mut byte).await.unwrap(), 1, "connection closed during the handshake");
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+async fn open(port: u16, protocol: &str) -> TcpStream {
+    let mut stream = connect(port).await;
+    let headers = format!(
+        "Upgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Protocol: {}\r\n",
+        protocol
+    );
+    let head = upgrade(&mut stream, &headers).await;
+    assert!(head.starts_with("HTTP/1.1 101 "), "{}", head);
+    stream
+}
+
+// Masked, as client frames must be
+async fn send_frame(stream: &mut TcpStream, fin: bool, opcode: u8, payload: &[u8]) {
+    let mask = [0x12, 0x34, 0x56, 0x78];
+    let mut frame = vec![if fin { 0x80 } else { 0 } | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(0x80 | len as u8),
+        len => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        },
+    }
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+    stream.write_all(&frame).await.unwrap();
+}
+
+async fn read_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut head = [0; 2];
+    stream.read_exact(&mut head).await.unwrap();
+    assert_eq!(head[0] & 0x80, 0x80, "server frames are whole");
+    assert_eq!(head[1] & 0x80, 0, "server frames aren't masked");
+    let len = match head[1] & 0x7f {
+        126 => stream.read_u16().await.unwrap() as usize,
+        127 => stream.read_u64().await.unwrap() as usize,
+        len => len as usize,
+    };
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload).await.unwrap();
+    (head[0] & 0x0f, payload)
+}
+
+async fn command(stream: &mut TcpStream, json: &str) -> String {
+    send_frame(stream, true, TEXT, json.as_bytes()).await;
+    read_text(stream).await
+}
+
+async fn read_text(stream: &mut TcpStream) -> String {
+    let (opcode, payload) = read_frame(stream).await;
+    assert_eq!(opcode, TEXT);
+    String::from_utf8(payload).unwrap()
+}
+
+#[tokio::test]
+async fn handshake() {
+    let (_server, port) = start(Config::default()).await;
+
+    // The example in RFC 6455
+    let mut stream = connect(port).await;
+    let head = upgrade(&mut stream, "Upgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Protocol: chat, resp\r\n").await;
+    assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"), "{}", head);
+    assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"), "{}", head);
+    assert!(head.contains("Sec-WebSocket-Protocol: resp\r\n"), "{}", head);
+
+    // Without a protocol we know, none is named and the framing is json
+    let mut stream = connect(port).await;
+    let head = upgrade(&mut stream, "Upgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: x3JJHMbDL1EzLkh9GBhXDw==\r\n").await;
+    assert!(head.contains("Sec-WebSocket-Accept: HSmrc0sMlYUkAGmm5OPpG2HaGWk=\r\n"), "{}", head);
+    assert!(!head.contains("Sec-WebSocket-Protocol"), "{}", head);
+    assert_eq!(command(&mut stream, r#"["PING"]"#).await, r#"{"result":"PONG"}"#);
+
+    let mut stream = connect(port).await;
+    let head = upgrade(&mut stream, "").await;
+    assert!(head.starts_with("HTTP/1.1 426 ") && head.contains("Upgrade: websocket\r\n"), "{}", head);
+    let mut stream = connect(port).await;
+    let head = upgrade(&mut stream, "Upgrade: websocket\r\nSec-WebSocket-Version: 8\r\nSec-WebSocket-Key: x3JJHMbDL1EzLkh9GBhXDw==\r\n").await;
+    assert!(head.starts_with("HTTP/1.1 400 ") && head.contains("Sec-WebSocket-Version: 13\r\n"), "{}", head);
+}
+
+#[tokio::test]
+async fn json_framing() {
+    let (server, port) = start(Config::default()).await;
+    let mut stream = open(port, "json").await;
+
+    assert_eq!(command(&mut stream, r#"["SET", "greeting", "hello"]"#).await, r#"{"result":"OK"}"#);
+    assert_eq!(command(&mut stream, r#"["GET", "greeting"]"#).await, r#"{"result":"hello"}"#);
+    assert!(command(&mut stream, r#"["GET"]"#).await.starts_with(r#"{"error":"ERR wrong number of arguments"#));
+    // The connection speaks RESP3
+    assert_eq!(command(&mut stream, r#"["HSET", "user", "name", "ada"]"#).await, r#"{"result":1}"#);
+    assert_eq!(command(&mut stream, r#"["HGETALL", "user"]"#).await, r#"{"result":{"name":"ada"}}"#);
+
+    // A message split into fragments, with a ping between them
+    send_frame(&mut stream, false, TEXT, br#"["GET", "#).await;
+    send_frame(&mut stream, true, PING, b"are you there").await;
+    send_frame(&mut stream, true, 0, br#""greeting"]"#).await;
+    assert_eq!(read_frame(&mut stream).await, (PONG, b"are you there".to_vec()));
+    assert_eq!(read_text(&mut stream).await, r#"{"result":"hello"}"#);
+
+    // Published messages are pushed
+    assert_eq!(command(&mut stream, r#"["SUBSCRIBE", "news"]"#).await, r#"{"push":["subscribe","news",1]}"#);
+    let mut client = server.client().await;
+    assert_eq!(client.call(["PUBLISH", "news", "extra extra"]).await.unwrap(), Value::Integer(1));
+    assert_eq!(read_text(&mut stream).await, r#"{"push":["message","news","extra extra"]}"#);
+
+    send_frame(&mut stream, true, CLOSE, &1000u16.to_be_bytes()).await;
+    assert_eq!(read_frame(&mut stream).await, (CLOSE, 1000u16.to_be_bytes().to_vec()));
+    assert_eq!(stream.read(&mut [0; 16]).await.unwrap(), 0);
+    // The subscription went with the connection
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(client.call(["PUBLISH", "news", "anyone?"]).await.unwrap(), Value::Integer(0));
+
+    // A message that isn't a command closes the connection
+    let mut stream = open(port, "json").await;
+    send_frame(&mut stream, true, TEXT, b"GET greeting").await;
+    let (opcode, payload) = read_frame(&mut stream).await;
+    assert_eq!((opcode, &payload[..2]), (CLOSE, &1007u16.to_be_bytes()[..]));
+    // As does an unmasked frame
+    let mut stream = open(port, "json").await;
+    stream.write_all(&[0x81, 0x02, b'[', b']']).await.unwrap();
+    let (opcode, payload) = read_frame(&mut stream).await;
+    assert_eq!((opcode, &payload[..2]), (CLOSE, &1002u16.to_be_bytes()[..]));
+}
+
+#[tokio::test]
+async fn resp_framing() {
+    let (_server, port) = start(Config { requirepass: Some("s3cret".to_string()), ..Config::default() }).await;
+    let mut stream = open(port, "resp").await;
+
+    // Commands may be split across messages any way at all
+    send_frame(&mut stream, true, BINARY, b"*2\r\n$3\r\nGET\r\n").await;
+    send_frame(&mut stream, true, BINARY, b"$1\r\nk\r\n").await;
+    let (opcode, payload) = read_frame(&mut stream).await;
+    assert_eq!(opcode, BINARY);
+    assert!(payload.starts_with(b"-NOAUTH"), "{}", String::from_utf8_lossy(&payload));
+
+    send_frame(&mut stream, true, BINARY, b"*2\r\n$4\r\nAUTH\r\n$6\r\ns3cret\r\n*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n").await;
+    let mut replies = vec![];
+    while replies.len() < b"+OK\r\n+OK\r\n".len() {
+        replies.extend(read_frame(&mut stream).await.1);
+    }
+    assert_eq!(replies, b"+OK\r\n+OK\r\n");
+    send_frame(&mut stream, true, BINARY, b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n").await;
+    assert_eq!(read_frame(&mut stream).await, (BINARY, b"$1\r\nv\r\n".to_vec()));
+}
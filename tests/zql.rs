@@ -0,0 +1,191 @@
+use redis_starter_rust::client::Client;
+use redis_starter_rust::resp::Value;
+use redis_starter_rust::zql::{Expr, Op, Operand, Select};
+use support::TestServer;
+
+mod support;
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::BulkString(text) => String::from_utf8(text.to_vec()).unwrap(),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+// The total ZQL gives, and each row's key with its fields and values
+async fn zql(client: &mut Client, query: &str) -> (i64, Vec<(String, Vec<String>)>) {
+    let Value::Array(reply) = client.call(["ZQL", query]).await.unwrap() else {
+        panic!("ZQL should reply with an array");
+    };
+    let Value::Integer(total) = reply[0] else {
+        panic!("ZQL should start with the total");
+    };
+    let rows = reply[1..].chunks(2).map(|row| match &row[1] {
+        Value::Array(fields) => (text(&row[0]), fields.iter().map(text).collect()),
+        other => panic!("unexpected {:?}", other),
+    });
+    (total, rows.collect())
+}
+
+async fn keys(client: &mut Client, query: &str) -> Vec<String> {
+    zql(client, query).await.1.into_iter().map(|(key, _)| key).collect()
+}
+
+#[tokio::test]
+async fn selecting_hashes() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.call(["HSET", "product:1", "name", "anvil", "price", "40", "stock", "3"]).await.unwrap();
+    client.call(["HSET", "product:2", "name", "bolt", "price", "2.5"]).await.unwrap();
+    client.call(["HSET", "product:3", "name", "axe", "price", "15", "stock", "0"]).await.unwrap();
+    client.call(["HSET", "product:4", "name", "chisel", "price", "9"]).await.unwrap();
+    client.call(["HSET", "order:1", "name", "anvil", "price", "40"]).await.unwrap();
+    client.set("product:5", "not a hash").await.unwrap();
+
+    assert_eq!(zql(&mut client, "SELECT name, price FROM product:* WHERE price > 10 ORDER BY price").await, (2, vec![
+        ("product:3".into(), vec!["name".into(), "axe".into(), "price".into(), "15".into()]),
+        ("product:1".into(), vec!["name".into(), "anvil".into(), "price".into(), "40".into()]),
+    ]));
+    // Numbers compare as numbers, not text
+    assert_eq!(keys(&mut client, "select * from product:* where price < 10 order by price desc").await, ["product:4", "product:2"]);
+    assert_eq!(zql(&mut client, "SELECT * FROM product:2").await.1, [("product:2".into(), vec!["name".into(), "bolt".into(), "price".into(), "2.5".into()])]);
+    assert_eq!(zql(&mut client, "SELECT __key, stock FROM product:4").await.1, [("product:4".into(), vec!["__key".into(), "product:4".into()])]);
+
+    assert_eq!(keys(&mut client, "SELECT name FROM product:* WHERE name LIKE 'a%'").await, ["product:1", "product:3"]);
+    assert_eq!(keys(&mut client, "SELECT name FROM product:* WHERE name NOT LIKE 'a%' AND price BETWEEN 2 AND 9").await, ["product:2", "product:4"]);
+    assert_eq!(keys(&mut client, "SELECT name FROM product:* WHERE name IN ('bolt', 'axe')").await, ["product:2", "product:3"]);
+    assert_eq!(keys(&mut client, "SELECT name FROM product:* WHERE stock IS NULL").await, ["product:2", "product:4"]);
+    assert_eq!(keys(&mut client, "SELECT name FROM product:* WHERE NOT (stock = 0 OR stock IS NULL)").await, ["product:1"]);
+    // A missing field compares as neither equal nor unequal
+    assert_eq!(keys(&mut client, "SELECT name FROM product:* WHERE stock != 0").await, ["product:1"]);
+    assert_eq!(keys(&mut client, "SELECT name FROM '*' WHERE name = 'anvil'").await, ["order:1", "product:1"]);
+
+    // Missing values sort last both ways, ties in key order
+    assert_eq!(keys(&mut client, "SELECT name FROM product:* ORDER BY stock").await, ["product:3", "product:1", "product:2", "product:4"]);
+    assert_eq!(keys(&mut client, "SELECT name FROM product:* ORDER BY stock DESC, name").await, ["product:1", "product:3", "product:2", "product:4"]);
+    assert_eq!(zql(&mut client, "SELECT name FROM product:* ORDER BY price LIMIT 2 OFFSET 1").await.0, 4);
+    assert_eq!(keys(&mut client, "SELECT name FROM product:* ORDER BY price LIMIT 2 OFFSET 1").await, ["product:4", "product:3"]);
+    assert_eq!(keys(&mut client, "SELECT name FROM product:* LIMIT 0").await, Vec::<String>::new());
+    assert_eq!(zql(&mut client, "SELECT name FROM nothing:*").await, (0, vec![]));
+}
+
+#[tokio::test]
+async fn selecting_documents() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.call(["JSON.SET", "user:1", "$", r#"{"name":"Ada","age":36,"address":{"city":"London"},"admin":true}"#]).await.unwrap();
+    client.call(["JSON.SET", "user:2", "$", r#"{"name":"Grace","age":85,"address":{"city":"New York"},"admin":false}"#]).await.unwrap();
+    client.call(["JSON.SET", "user:3", "$", r#"{"name":"Linus","tags":["kernel","git"]}"#]).await.unwrap();
+
+    assert_eq!(zql(&mut client, "SELECT name, address.city FROM user:* WHERE age >= 36 ORDER BY age DESC").await, (2, vec![
+        ("user:2".into(), vec!["name".into(), "Grace".into(), "address.city".into(), "New York".into()]),
+        ("user:1".into(), vec!["name".into(), "Ada".into(), "address.city".into(), "London".into()]),
+    ]));
+    assert_eq!(keys(&mut client, "SELECT name FROM user:* WHERE address.city = 'London'").await, ["user:1"]);
+    assert_eq!(keys(&mut client, "SELECT name FROM user:* WHERE admin = true").await, ["user:1"]);
+    assert_eq!(keys(&mut client, "SELECT name FROM user:* WHERE age IS NULL").await, ["user:3"]);
+    assert_eq!(zql(&mut client, "SELECT tags FROM user:3").await.1, [("user:3".into(), vec!["tags".into(), r#"["kernel","git"]"#.into()])]);
+    assert_eq!(zql(&mut client, "SELECT * FROM user:3").await.1, [("user:3".into(), vec!["$".into(), r#"{"name":"Linus","tags":["kernel","git"]}"#.into()])]);
+    assert_eq!(keys(&mut client, "SELECT `name` FROM user:* WHERE `name` LIKE '_ra%'").await, ["user:2"]);
+}
+
+#[tokio::test]
+async fn rejecting_bad_queries() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    for query in [
+        "",
+        "SELECT FROM x",
+        "SELECT a x",
+        "SELECT a FROM",
+        "SELECT a FROM x WHERE",
+        "SELECT a FROM x WHERE a >",
+        "SELECT a FROM x WHERE (a = 1",
+        "SELECT a FROM x WHERE a = 'open",
+        "SELECT a FROM x ORDER a",
+        "SELECT a FROM x LIMIT -1",
+        "SELECT a FROM x LIMIT 1 extra",
+        "SELECT from FROM x",
+    ] {
+        assert!(client.call(["ZQL", query]).await.is_err(), "{:?} should be rejected", query);
+    }
+    let nested = format!("SELECT a FROM x WHERE {}a = 1{}", "(".repeat(100), ")".repeat(100));
+    assert!(client.call(["ZQL", nested.as_str()]).await.is_err());
+    assert!(client.call(["ZQL"]).await.is_err());
+}
+
+#[test]
+fn parsing() {
+    let select = Select::parse("select a from k:* where a > 1 and 2 <= b limit 5 offset 10").unwrap();
+    assert_eq!((select.offset, select.limit), (10, Some(5)));
+    assert_eq!(&select.pattern[..], b"k:*");
+    let Some(Expr::And(conditions)) = select.filter else {
+        panic!("expected a conjunction");
+    };
+    assert!(matches!(&conditions[0], Expr::Compare(Operand::Field(field), Op::Gt, Operand::Literal(_)) if field.name == "a"));
+    assert!(matches!(&conditions[1], Expr::Compare(Operand::Literal(_), Op::Le, Operand::Field(field)) if field.name == "b"));
+}
+
+// EXPLAIN's fields and values, as text
+async fn explain(client: &mut Client, query: &str) -> Vec<String> {
+    let Value::Array(reply) = client.call(["ZQL", &format!("EXPLAIN {}", query)]).await.unwrap() else {
+        panic!("EXPLAIN should reply with a map");
+    };
+    reply.iter().map(|value| match value {
+        Value::SimpleString(text) => text.clone(),
+        Value::Integer(n) => n.to_string(),
+        value => text(value),
+    }).collect()
+}
+
+#[tokio::test]
+async fn planning_with_indexes() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    for i in 0..100 {
+        let (key, price, name) = (format!("product:{:03}", i), i.to_string(), format!("item{:03}", i));
+        client.call(["HSET", key.as_str(), "price", price.as_str(), "name", name.as_str()]).await.unwrap();
+    }
+    client.call(["JSON.SET", "product:doc", "$", r#"{"price":42.5,"name":"document"}"#]).await.unwrap();
+    client.call(["HSET", "order:1", "price", "50"]).await.unwrap();
+    let query = "SELECT name FROM product:* WHERE price >= 40 AND price < 45 AND name != 'item041' ORDER BY price";
+    let expected = ["product:040", "product:042", "product:doc", "product:043", "product:044"];
+    assert_eq!(keys(&mut client, query).await, expected);
+    assert_eq!(explain(&mut client, query).await, ["access", "scan", "estimated cost", "102", "scan cost", "102"]);
+
+    client.call(["IDX.CREATE", "by_price", "PREFIX", "product:", "ON", "field:price", "NUMERIC"]).await.unwrap();
+    client.call(["IDX.CREATE", "by_name", "PREFIX", "product:", "ON", "field:name"]).await.unwrap();
+    client.call(["IDX.CREATE", "orders", "PREFIX", "order:", "ON", "field:price", "NUMERIC"]).await.unwrap();
+    // Documents are indexed by their members
+    assert_eq!(client.call(["IDX.QUERY", "by_price", "EQ", "42.5"]).await.unwrap(), Value::Array(vec![Value::BulkString("product:doc".into())]));
+    assert_eq!(explain(&mut client, query).await, [
+        "access", "index", "index", "by_price", "range", "price in [40, 45)", "estimated cost", "6", "scan cost", "102",
+    ]);
+    assert_eq!(keys(&mut client, query).await, expected);
+    // The narrowest range wins, bounds on either side of the comparison
+    let query = "SELECT name FROM product:* WHERE 90 < price AND name BETWEEN 'item010' AND 'item012'";
+    assert_eq!(explain(&mut client, query).await[..6], ["access", "index", "index", "by_name", "range", "name in ['item010', 'item012']"]);
+    assert_eq!(keys(&mut client, query).await, Vec::<String>::new());
+    assert_eq!(keys(&mut client, "SELECT name FROM product:* WHERE price = 7 AND price = 7.0").await, ["product:007"]);
+    assert_eq!(explain(&mut client, "SELECT name FROM product:* WHERE price > 5 AND price > 95 AND price <= 99").await[5], "price in (95, 99]");
+
+    // Not when the index might miss rows, or doesn't order values as the comparison does
+    for query in [
+        "SELECT name FROM * WHERE price > 90",
+        "SELECT name FROM product:* WHERE price > 90 OR name = 'x'",
+        "SELECT name FROM product:* WHERE price > '90'",
+        "SELECT name FROM product:* WHERE name = '5'",
+        "SELECT name FROM product:* WHERE price != 5",
+        "SELECT name FROM product:*",
+    ] {
+        assert_eq!(explain(&mut client, query).await[..2], ["access", "scan"], "{}", query);
+    }
+    assert_eq!(explain(&mut client, "SELECT price FROM order:* WHERE price = 50").await[3], "orders");
+    assert_eq!(keys(&mut client, "SELECT price FROM order:* WHERE price = 50").await, ["order:1"]);
+
+    // A dropped index is no longer used
+    client.call(["IDX.DROP", "by_price"]).await.unwrap();
+    assert_eq!(explain(&mut client, "SELECT name FROM product:* WHERE price = 1").await[..2], ["access", "scan"]);
+    assert!(client.call(["ZQL", "EXPLAIN"]).await.is_err());
+    assert!(client.call(["ZQL", "EXPLAIN EXPLAIN SELECT a FROM x"]).await.is_err());
+}